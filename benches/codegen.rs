@@ -0,0 +1,77 @@
+//! Times sb3 generation for a large generated project.
+//!
+//! Compare the serial and threaded emitters with:
+//!
+//! ```text
+//! cargo bench --bench codegen
+//! cargo bench --bench codegen --features parallel
+//! ```
+
+use sbtext_rs_core::codegen::{build_sb3_bytes, CodegenOptions};
+use std::fs;
+use std::time::{Duration, Instant};
+
+const SPRITES: usize = 48;
+const STATEMENTS_PER_SCRIPT: usize = 400;
+const ITERATIONS: u32 = 5;
+
+fn generated_source() -> String {
+    let mut source = String::from("stage\n  var score\nend\n\n");
+    for sprite in 0..SPRITES {
+        source.push_str(&format!(
+            "sprite Sprite{}\n  costume \"art{}.svg\"\n  var speed\n",
+            sprite,
+            sprite % 8
+        ));
+        source.push_str("  when flag clicked\n");
+        for statement in 0..STATEMENTS_PER_SCRIPT {
+            source.push_str(&format!(
+                "    if <(speed) > ({})> then\n      change [score] by ((speed) * (2))\n    else\n      set [speed] to (pick random (1) to (10))\n    end\n",
+                statement
+            ));
+        }
+        source.push_str("  end\nend\n\n");
+    }
+    source
+}
+
+fn main() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    for art in 0..8 {
+        let mut svg =
+            String::from("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 480 360\">");
+        for shape in 0..2000 {
+            svg.push_str(&format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"4\" height=\"4\" fill=\"#{:06x}\"/>",
+                (shape * 7 + art) % 480,
+                (shape * 13) % 360,
+                shape * 2654435 % 0xFFFFFF
+            ));
+        }
+        svg.push_str("</svg>");
+        fs::write(dir.path().join(format!("art{}.svg", art)), svg).expect("svg should write");
+    }
+    let project = sbtext_rs_core::parse_and_validate_source(&generated_source())
+        .expect("generated source should validate");
+
+    let mut total = Duration::ZERO;
+    for _ in 0..ITERATIONS {
+        let started = Instant::now();
+        let bytes = build_sb3_bytes(&project, dir.path(), CodegenOptions::default())
+            .expect("project should build");
+        total += started.elapsed();
+        assert!(!bytes.is_empty());
+    }
+    println!(
+        "codegen ({}): {} sprites x {} statements, mean {:.1?} over {} runs",
+        if cfg!(feature = "parallel") {
+            "parallel"
+        } else {
+            "serial"
+        },
+        SPRITES,
+        STATEMENTS_PER_SCRIPT,
+        total / ITERATIONS,
+        ITERATIONS
+    );
+}
@@ -0,0 +1,72 @@
+//! Generates a quiz project from a table of questions with the project
+//! builder, without writing any SBText source.
+//!
+//! ```bash
+//! cargo run --example quiz -- quiz.sb3
+//! ```
+
+use anyhow::Result;
+use sbtext_rs_core::build::{expr, MonitorMode, ProjectBuilder};
+use sbtext_rs_core::codegen::CodegenOptions;
+use std::path::{Path, PathBuf};
+
+const QUESTIONS: &[(&str, &str)] = &[
+    ("What is 7 x 8?", "56"),
+    ("Which planet is known as the red planet?", "Mars"),
+    ("How many sides does a hexagon have?", "6"),
+];
+
+fn main() -> Result<()> {
+    let output = std::env::args_os()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("quiz.sb3"));
+
+    let bytes = ProjectBuilder::new()
+        .stage(|stage| {
+            stage.var_with_value("score", 0.0);
+            stage.monitor("score", 5.0, 5.0, MonitorMode::Large);
+        })
+        .sprite("Host", |host| {
+            host.list_with_items("questions", QUESTIONS.iter().map(|(q, _)| *q));
+            host.list_with_items("answers", QUESTIONS.iter().map(|(_, a)| *a));
+            host.var("number");
+            host.define("ask question", &["index"], |b| {
+                b.ask(expr::item_of("questions", expr::var("index")));
+                b.if_else(
+                    expr::eq(expr::answer(), expr::item_of("answers", expr::var("index"))),
+                    |b| {
+                        b.change_var("score", expr::num(1.0));
+                        b.say_for_secs(expr::str("Correct!"), expr::num(1.0));
+                    },
+                    |b| {
+                        b.say_for_secs(
+                            expr::join(
+                                expr::str("The answer was "),
+                                expr::item_of("answers", expr::var("index")),
+                            ),
+                            expr::num(2.0),
+                        );
+                    },
+                );
+            });
+            host.on_flag(|b| {
+                b.set_var("score", expr::num(0.0));
+                b.for_each("number", expr::length_of_list("questions"), |b| {
+                    b.call("ask question", vec![expr::var("number")]);
+                });
+                b.say(expr::join(
+                    expr::str("Your score: "),
+                    expr::join(
+                        expr::var("score"),
+                        expr::join(expr::str(" / "), expr::length_of_list("questions")),
+                    ),
+                ));
+            });
+        })
+        .build_sb3_bytes(Path::new("."), CodegenOptions::default())?;
+
+    std::fs::write(&output, bytes)?;
+    println!("Wrote '{}'", output.display());
+    Ok(())
+}
@@ -0,0 +1,131 @@
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Supplies costume and sound bytes to codegen, keyed by the path written in
+/// source (e.g. `costume "art/player.png"`). Resolvers are shared between
+/// threads when targets are emitted in parallel.
+pub trait AssetResolver: Sync {
+    /// Reads the asset referenced by `path`.
+    fn read(&self, path: &str) -> Result<Vec<u8>>;
+
+    /// Stable identity of the asset behind `path`, used in messages and to
+    /// share one read between every reference to the same asset.
+    fn resolve(&self, path: &str) -> String {
+        path.to_string()
+    }
+
+    /// The file on disk backing `path`, if any. Streaming writers copy such
+    /// assets in chunks instead of holding them in memory.
+    fn file_path(&self, _path: &str) -> Option<PathBuf> {
+        None
+    }
+
+    /// The path to look `path` up by when it was declared in a file in
+    /// `origin_dir`. By default the origin is ignored.
+    fn locate(&self, path: &str, _origin_dir: Option<&Path>) -> String {
+        path.to_string()
+    }
+}
+
+/// Reads assets from disk, searching the declaring file's directory, the
+/// source directory, its parent, and the working directory in that order.
+#[derive(Debug, Clone)]
+pub struct FsAssetResolver {
+    source_dir: PathBuf,
+}
+
+impl FsAssetResolver {
+    pub fn new(source_dir: &Path) -> Self {
+        Self {
+            source_dir: source_dir.to_path_buf(),
+        }
+    }
+
+    fn resolve_path(&self, path: &str) -> PathBuf {
+        let file_path = Path::new(path).to_path_buf();
+        if file_path.is_absolute() {
+            return file_path;
+        }
+        let mut candidates = Vec::new();
+        candidates.push(self.source_dir.join(&file_path));
+        if let Some(parent) = self.source_dir.parent() {
+            candidates.push(parent.join(&file_path));
+        }
+        if let Ok(cwd) = std::env::current_dir() {
+            candidates.push(cwd.join(&file_path));
+        }
+        if let Some(found) = candidates.iter().find(|p| p.exists()) {
+            return found.clone();
+        }
+        candidates.swap_remove(0)
+    }
+}
+
+impl AssetResolver for FsAssetResolver {
+    fn read(&self, path: &str) -> Result<Vec<u8>> {
+        let file_path = self.resolve_path(path);
+        if !file_path.is_file() {
+            bail!(
+                "File not found: '{}' resolved to '{}'.",
+                path,
+                file_path.display()
+            );
+        }
+        fs::read(&file_path).with_context(|| format!("Failed to read '{}'", file_path.display()))
+    }
+
+    fn resolve(&self, path: &str) -> String {
+        let file_path = self.resolve_path(path);
+        fs::canonicalize(&file_path)
+            .unwrap_or(file_path)
+            .display()
+            .to_string()
+    }
+
+    fn file_path(&self, path: &str) -> Option<PathBuf> {
+        Some(self.resolve_path(path)).filter(|p| p.is_file())
+    }
+
+    fn locate(&self, path: &str, origin_dir: Option<&Path>) -> String {
+        let Some(origin_dir) = origin_dir else {
+            return path.to_string();
+        };
+        let candidate = origin_dir.join(path);
+        if Path::new(path).is_relative() && candidate.is_file() {
+            return candidate.to_string_lossy().into_owned();
+        }
+        path.to_string()
+    }
+}
+
+/// In-memory assets keyed by file name, for callers without a filesystem
+/// (wasm, editors). `./` prefixes and backslashes are ignored when matching.
+impl AssetResolver for HashMap<String, Vec<u8>> {
+    fn read(&self, path: &str) -> Result<Vec<u8>> {
+        if let Some(data) = self.get(path) {
+            return Ok(data.clone());
+        }
+        let wanted = normalize_asset_name(path);
+        match self
+            .iter()
+            .find(|(name, _)| normalize_asset_name(name) == wanted)
+        {
+            Some((_, data)) => Ok(data.clone()),
+            None => bail!("'{}' is not among the provided assets.", path),
+        }
+    }
+
+    fn resolve(&self, path: &str) -> String {
+        normalize_asset_name(path)
+    }
+}
+
+fn normalize_asset_name(path: &str) -> String {
+    let mut name = path.replace('\\', "/");
+    while let Some(rest) = name.strip_prefix("./") {
+        name = rest.to_string();
+    }
+    name
+}
@@ -1,6 +1,11 @@
 #![allow(dead_code)]
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Position {
     pub line: usize,
     pub column: usize,
@@ -12,7 +17,8 @@ impl Position {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Expr {
     Number {
         pos: Position,
@@ -22,6 +28,10 @@ pub enum Expr {
         pos: Position,
         value: String,
     },
+    Color {
+        pos: Position,
+        value: String,
+    },
     Var {
         pos: Position,
         name: String,
@@ -49,6 +59,11 @@ pub enum Expr {
         pos: Position,
         list_name: String,
     },
+    ItemNumOfList {
+        pos: Position,
+        list_name: String,
+        item: Box<Expr>,
+    },
     KeyPressed {
         pos: Position,
         key: Box<Expr>,
@@ -61,11 +76,30 @@ pub enum Expr {
         pos: Position,
         color: Box<Expr>,
     },
+    ColorTouchingColor {
+        pos: Position,
+        color: Box<Expr>,
+        color2: Box<Expr>,
+    },
     StringJoin {
         pos: Position,
         text1: Box<Expr>,
         text2: Box<Expr>,
     },
+    StringLength {
+        pos: Position,
+        text: Box<Expr>,
+    },
+    LetterOf {
+        pos: Position,
+        index: Box<Expr>,
+        text: Box<Expr>,
+    },
+    StringContains {
+        pos: Position,
+        text: Box<Expr>,
+        substring: Box<Expr>,
+    },
     StringSplit {
         pos: Position,
         text: Box<Expr>,
@@ -81,6 +115,11 @@ pub enum Expr {
         pos: Position,
         kind: String,
     },
+    LooksNumberName {
+        pos: Position,
+        backdrop: bool,
+        number_name: String,
+    },
     MathFunc {
         pos: Position,
         op: String,
@@ -104,35 +143,194 @@ impl Expr {
         match self {
             Expr::Number { pos, .. }
             | Expr::String { pos, .. }
+            | Expr::Color { pos, .. }
             | Expr::Var { pos, .. }
             | Expr::PickRandom { pos, .. }
             | Expr::ListItem { pos, .. }
             | Expr::ListLength { pos, .. }
+            | Expr::StringLength { pos, .. }
+            | Expr::LetterOf { pos, .. }
+            | Expr::StringContains { pos, .. }
             | Expr::ListContains { pos, .. }
             | Expr::ListContents { pos, .. }
+            | Expr::ItemNumOfList { pos, .. }
             | Expr::KeyPressed { pos, .. }
             | Expr::TouchingObject { pos, .. }
             | Expr::TouchingColor { pos, .. }
+            | Expr::ColorTouchingColor { pos, .. }
             | Expr::StringJoin { pos, .. }
             | Expr::StringSplit { pos, .. }
             | Expr::Substring { pos, .. }
             | Expr::BuiltinReporter { pos, .. }
+            | Expr::LooksNumberName { pos, .. }
             | Expr::MathFunc { pos, .. }
             | Expr::Unary { pos, .. }
             | Expr::Binary { pos, .. } => *pos,
         }
     }
+
+    pub fn pos_mut(&mut self) -> &mut Position {
+        match self {
+            Expr::Number { pos, .. }
+            | Expr::String { pos, .. }
+            | Expr::Color { pos, .. }
+            | Expr::Var { pos, .. }
+            | Expr::PickRandom { pos, .. }
+            | Expr::ListItem { pos, .. }
+            | Expr::ListLength { pos, .. }
+            | Expr::StringLength { pos, .. }
+            | Expr::LetterOf { pos, .. }
+            | Expr::StringContains { pos, .. }
+            | Expr::ListContains { pos, .. }
+            | Expr::ListContents { pos, .. }
+            | Expr::ItemNumOfList { pos, .. }
+            | Expr::KeyPressed { pos, .. }
+            | Expr::TouchingObject { pos, .. }
+            | Expr::TouchingColor { pos, .. }
+            | Expr::ColorTouchingColor { pos, .. }
+            | Expr::StringJoin { pos, .. }
+            | Expr::StringSplit { pos, .. }
+            | Expr::Substring { pos, .. }
+            | Expr::BuiltinReporter { pos, .. }
+            | Expr::LooksNumberName { pos, .. }
+            | Expr::MathFunc { pos, .. }
+            | Expr::Unary { pos, .. }
+            | Expr::Binary { pos, .. } => pos,
+        }
+    }
+
+    /// The expressions nested directly in this one, in source order.
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::ListItem { index: expr, .. }
+            | Expr::ListContains { item: expr, .. }
+            | Expr::ItemNumOfList { item: expr, .. }
+            | Expr::KeyPressed { key: expr, .. }
+            | Expr::TouchingObject { target: expr, .. }
+            | Expr::TouchingColor { color: expr, .. }
+            | Expr::StringLength { text: expr, .. }
+            | Expr::MathFunc { value: expr, .. }
+            | Expr::Unary { operand: expr, .. } => vec![expr.as_ref()],
+            Expr::PickRandom {
+                start: first,
+                end: second,
+                ..
+            }
+            | Expr::ColorTouchingColor {
+                color: first,
+                color2: second,
+                ..
+            }
+            | Expr::StringJoin {
+                text1: first,
+                text2: second,
+                ..
+            }
+            | Expr::LetterOf {
+                index: first,
+                text: second,
+                ..
+            }
+            | Expr::StringContains {
+                text: first,
+                substring: second,
+                ..
+            }
+            | Expr::StringSplit {
+                text: first,
+                sep: second,
+                ..
+            }
+            | Expr::Binary {
+                left: first,
+                right: second,
+                ..
+            } => vec![first.as_ref(), second.as_ref()],
+            Expr::Substring {
+                text, start, end, ..
+            } => vec![text.as_ref(), start.as_ref(), end.as_ref()],
+            Expr::Number { .. }
+            | Expr::String { .. }
+            | Expr::Color { .. }
+            | Expr::Var { .. }
+            | Expr::ListLength { .. }
+            | Expr::ListContents { .. }
+            | Expr::BuiltinReporter { .. }
+            | Expr::LooksNumberName { .. } => Vec::new(),
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Expr::ListItem { index: expr, .. }
+            | Expr::ListContains { item: expr, .. }
+            | Expr::ItemNumOfList { item: expr, .. }
+            | Expr::KeyPressed { key: expr, .. }
+            | Expr::TouchingObject { target: expr, .. }
+            | Expr::TouchingColor { color: expr, .. }
+            | Expr::StringLength { text: expr, .. }
+            | Expr::MathFunc { value: expr, .. }
+            | Expr::Unary { operand: expr, .. } => vec![expr.as_mut()],
+            Expr::PickRandom {
+                start: first,
+                end: second,
+                ..
+            }
+            | Expr::ColorTouchingColor {
+                color: first,
+                color2: second,
+                ..
+            }
+            | Expr::StringJoin {
+                text1: first,
+                text2: second,
+                ..
+            }
+            | Expr::LetterOf {
+                index: first,
+                text: second,
+                ..
+            }
+            | Expr::StringContains {
+                text: first,
+                substring: second,
+                ..
+            }
+            | Expr::StringSplit {
+                text: first,
+                sep: second,
+                ..
+            }
+            | Expr::Binary {
+                left: first,
+                right: second,
+                ..
+            } => vec![first.as_mut(), second.as_mut()],
+            Expr::Substring {
+                text, start, end, ..
+            } => vec![text.as_mut(), start.as_mut(), end.as_mut()],
+            Expr::Number { .. }
+            | Expr::String { .. }
+            | Expr::Color { .. }
+            | Expr::Var { .. }
+            | Expr::ListLength { .. }
+            | Expr::ListContents { .. }
+            | Expr::BuiltinReporter { .. }
+            | Expr::LooksNumberName { .. } => Vec::new(),
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Statement {
     Broadcast {
         pos: Position,
-        message: String,
+        message: Expr,
     },
     BroadcastAndWait {
         pos: Position,
-        message: String,
+        message: Expr,
     },
     SetVar {
         pos: Position,
@@ -161,6 +359,11 @@ pub enum Statement {
         pos: Position,
         message: Expr,
     },
+    ThinkForSeconds {
+        pos: Position,
+        message: Expr,
+        duration: Expr,
+    },
     Wait {
         pos: Position,
         duration: Expr,
@@ -324,6 +527,10 @@ pub enum Statement {
         param: String,
         value: Expr,
     },
+    SetPenColorTo {
+        pos: Position,
+        color: Expr,
+    },
     Show {
         pos: Position,
     },
@@ -344,6 +551,10 @@ pub enum Statement {
         pos: Position,
         backdrop: Expr,
     },
+    SwitchBackdropToAndWait {
+        pos: Position,
+        backdrop: Expr,
+    },
     Stop {
         pos: Position,
         option: Expr,
@@ -368,10 +579,48 @@ pub enum Statement {
         effect: String,
         value: Expr,
     },
+    ChangeSoundEffectBy {
+        pos: Position,
+        effect: String,
+        value: Expr,
+    },
     SetVolumeTo {
         pos: Position,
         value: Expr,
     },
+    ChangeVolumeBy {
+        pos: Position,
+        value: Expr,
+    },
+    PlayNoteForBeats {
+        pos: Position,
+        note: Expr,
+        beats: Expr,
+    },
+    PlayDrumForBeats {
+        pos: Position,
+        drum: String,
+        beats: Expr,
+    },
+    RestForBeats {
+        pos: Position,
+        beats: Expr,
+    },
+    SetInstrumentTo {
+        pos: Position,
+        instrument: String,
+    },
+    SetTempoTo {
+        pos: Position,
+        value: Expr,
+    },
+    ChangeTempoBy {
+        pos: Position,
+        value: Expr,
+    },
+    ClearSoundEffects {
+        pos: Position,
+    },
     CreateCloneOf {
         pos: Position,
         target: Expr,
@@ -387,6 +636,14 @@ pub enum Statement {
         pos: Position,
         var_name: String,
     },
+    ShowList {
+        pos: Position,
+        list_name: String,
+    },
+    HideList {
+        pos: Position,
+        list_name: String,
+    },
     ResetTimer {
         pos: Position,
     },
@@ -416,6 +673,13 @@ pub enum Statement {
         index: Expr,
         item: Expr,
     },
+    /// A block this language has no syntax for, kept as Scratch block JSON:
+    /// `raw {"opcode": "...", "inputs": {...}, "fields": {...}}`. Input slots and
+    /// `next` hold nested block objects instead of ids.
+    RawBlock {
+        pos: Position,
+        block: serde_json::Value,
+    },
 }
 
 impl Statement {
@@ -429,6 +693,7 @@ impl Statement {
             | Statement::Say { pos, .. }
             | Statement::SayForSeconds { pos, .. }
             | Statement::Think { pos, .. }
+            | Statement::ThinkForSeconds { pos, .. }
             | Statement::Wait { pos, .. }
             | Statement::WaitUntil { pos, .. }
             | Statement::Repeat { pos, .. }
@@ -467,84 +732,490 @@ impl Statement {
             | Statement::SetPenSizeTo { pos, .. }
             | Statement::ChangePenColorParamBy { pos, .. }
             | Statement::SetPenColorParamTo { pos, .. }
+            | Statement::SetPenColorTo { pos, .. }
             | Statement::Show { pos, .. }
             | Statement::Hide { pos, .. }
             | Statement::NextCostume { pos, .. }
             | Statement::NextBackdrop { pos, .. }
             | Statement::SwitchCostumeTo { pos, .. }
             | Statement::SwitchBackdropTo { pos, .. }
+            | Statement::SwitchBackdropToAndWait { pos, .. }
             | Statement::Stop { pos, .. }
             | Statement::Ask { pos, .. }
             | Statement::StartSound { pos, .. }
             | Statement::PlaySoundUntilDone { pos, .. }
             | Statement::StopAllSounds { pos, .. }
             | Statement::SetSoundEffectTo { pos, .. }
+            | Statement::ChangeSoundEffectBy { pos, .. }
             | Statement::SetVolumeTo { pos, .. }
+            | Statement::ChangeVolumeBy { pos, .. }
+            | Statement::PlayNoteForBeats { pos, .. }
+            | Statement::PlayDrumForBeats { pos, .. }
+            | Statement::RestForBeats { pos, .. }
+            | Statement::SetInstrumentTo { pos, .. }
+            | Statement::SetTempoTo { pos, .. }
+            | Statement::ChangeTempoBy { pos, .. }
+            | Statement::ClearSoundEffects { pos, .. }
             | Statement::CreateCloneOf { pos, .. }
             | Statement::DeleteThisClone { pos, .. }
             | Statement::ShowVariable { pos, .. }
+            | Statement::ShowList { pos, .. }
+            | Statement::HideList { pos, .. }
             | Statement::HideVariable { pos, .. }
             | Statement::ResetTimer { pos, .. }
             | Statement::AddToList { pos, .. }
             | Statement::DeleteOfList { pos, .. }
             | Statement::DeleteAllOfList { pos, .. }
             | Statement::InsertAtList { pos, .. }
-            | Statement::ReplaceItemOfList { pos, .. } => *pos,
+            | Statement::ReplaceItemOfList { pos, .. }
+            | Statement::RawBlock { pos, .. } => *pos,
+        }
+    }
+
+    /// The expressions this statement takes, in source order, and the statement
+    /// lists nested in it: a loop body, or the `then` and `else` branches of an
+    /// `if`. Expressions inside those lists are not included.
+    pub fn children(&self) -> (Vec<&Expr>, Vec<&Vec<Statement>>) {
+        match self {
+            Statement::Repeat {
+                times: expr, body, ..
+            }
+            | Statement::ForEach {
+                value: expr, body, ..
+            }
+            | Statement::While {
+                condition: expr,
+                body,
+                ..
+            }
+            | Statement::RepeatUntil {
+                condition: expr,
+                body,
+                ..
+            } => (vec![expr], vec![body]),
+            Statement::Forever { body, .. } => (Vec::new(), vec![body]),
+            Statement::If {
+                condition,
+                then_body,
+                else_body,
+                ..
+            } => (vec![condition], vec![then_body, else_body]),
+            Statement::Broadcast { message: expr, .. }
+            | Statement::BroadcastAndWait { message: expr, .. }
+            | Statement::SetVar { value: expr, .. }
+            | Statement::ChangeVar { delta: expr, .. }
+            | Statement::Move { steps: expr, .. }
+            | Statement::Say { message: expr, .. }
+            | Statement::Think { message: expr, .. }
+            | Statement::Wait { duration: expr, .. }
+            | Statement::WaitUntil {
+                condition: expr, ..
+            }
+            | Statement::TurnRight { degrees: expr, .. }
+            | Statement::TurnLeft { degrees: expr, .. }
+            | Statement::GoToTarget { target: expr, .. }
+            | Statement::ChangeXBy { value: expr, .. }
+            | Statement::SetX { value: expr, .. }
+            | Statement::ChangeYBy { value: expr, .. }
+            | Statement::SetY { value: expr, .. }
+            | Statement::PointInDirection {
+                direction: expr, ..
+            }
+            | Statement::PointTowards { target: expr, .. }
+            | Statement::ChangeSizeBy { value: expr, .. }
+            | Statement::SetSizeTo { value: expr, .. }
+            | Statement::SetGraphicEffectTo { value: expr, .. }
+            | Statement::ChangeGraphicEffectBy { value: expr, .. }
+            | Statement::GoLayers { layers: expr, .. }
+            | Statement::ChangePenSizeBy { value: expr, .. }
+            | Statement::SetPenSizeTo { value: expr, .. }
+            | Statement::ChangePenColorParamBy { value: expr, .. }
+            | Statement::SetPenColorParamTo { value: expr, .. }
+            | Statement::SetPenColorTo { color: expr, .. }
+            | Statement::SwitchCostumeTo { costume: expr, .. }
+            | Statement::SwitchBackdropTo { backdrop: expr, .. }
+            | Statement::SwitchBackdropToAndWait { backdrop: expr, .. }
+            | Statement::Stop { option: expr, .. }
+            | Statement::Ask { question: expr, .. }
+            | Statement::StartSound { sound: expr, .. }
+            | Statement::PlaySoundUntilDone { sound: expr, .. }
+            | Statement::SetSoundEffectTo { value: expr, .. }
+            | Statement::ChangeSoundEffectBy { value: expr, .. }
+            | Statement::SetVolumeTo { value: expr, .. }
+            | Statement::ChangeVolumeBy { value: expr, .. }
+            | Statement::PlayDrumForBeats { beats: expr, .. }
+            | Statement::RestForBeats { beats: expr, .. }
+            | Statement::SetTempoTo { value: expr, .. }
+            | Statement::ChangeTempoBy { value: expr, .. }
+            | Statement::CreateCloneOf { target: expr, .. }
+            | Statement::AddToList { item: expr, .. }
+            | Statement::DeleteOfList { index: expr, .. } => (vec![expr], Vec::new()),
+            Statement::SayForSeconds {
+                message: first,
+                duration: second,
+                ..
+            }
+            | Statement::ThinkForSeconds {
+                message: first,
+                duration: second,
+                ..
+            }
+            | Statement::GoToXY {
+                x: first,
+                y: second,
+                ..
+            }
+            | Statement::GlideToTarget {
+                duration: first,
+                target: second,
+                ..
+            }
+            | Statement::PlayNoteForBeats {
+                note: first,
+                beats: second,
+                ..
+            }
+            | Statement::InsertAtList {
+                item: first,
+                index: second,
+                ..
+            }
+            | Statement::ReplaceItemOfList {
+                index: first,
+                item: second,
+                ..
+            } => (vec![first, second], Vec::new()),
+            Statement::GlideToXY { duration, x, y, .. } => (vec![duration, x, y], Vec::new()),
+            Statement::ProcedureCall { args, .. } => (args.iter().collect(), Vec::new()),
+            Statement::SetRotationStyle { .. }
+            | Statement::IfOnEdgeBounce { .. }
+            | Statement::ClearGraphicEffects { .. }
+            | Statement::GoToLayer { .. }
+            | Statement::PenDown { .. }
+            | Statement::PenUp { .. }
+            | Statement::PenClear { .. }
+            | Statement::PenStamp { .. }
+            | Statement::Show { .. }
+            | Statement::Hide { .. }
+            | Statement::NextCostume { .. }
+            | Statement::NextBackdrop { .. }
+            | Statement::StopAllSounds { .. }
+            | Statement::SetInstrumentTo { .. }
+            | Statement::ClearSoundEffects { .. }
+            | Statement::DeleteThisClone { .. }
+            | Statement::ShowVariable { .. }
+            | Statement::HideVariable { .. }
+            | Statement::ShowList { .. }
+            | Statement::HideList { .. }
+            | Statement::ResetTimer { .. }
+            | Statement::DeleteAllOfList { .. }
+            | Statement::RawBlock { .. } => (Vec::new(), Vec::new()),
+        }
+    }
+
+    pub fn children_mut(&mut self) -> (Vec<&mut Expr>, Vec<&mut Vec<Statement>>) {
+        match self {
+            Statement::Repeat {
+                times: expr, body, ..
+            }
+            | Statement::ForEach {
+                value: expr, body, ..
+            }
+            | Statement::While {
+                condition: expr,
+                body,
+                ..
+            }
+            | Statement::RepeatUntil {
+                condition: expr,
+                body,
+                ..
+            } => (vec![expr], vec![body]),
+            Statement::Forever { body, .. } => (Vec::new(), vec![body]),
+            Statement::If {
+                condition,
+                then_body,
+                else_body,
+                ..
+            } => (vec![condition], vec![then_body, else_body]),
+            Statement::Broadcast { message: expr, .. }
+            | Statement::BroadcastAndWait { message: expr, .. }
+            | Statement::SetVar { value: expr, .. }
+            | Statement::ChangeVar { delta: expr, .. }
+            | Statement::Move { steps: expr, .. }
+            | Statement::Say { message: expr, .. }
+            | Statement::Think { message: expr, .. }
+            | Statement::Wait { duration: expr, .. }
+            | Statement::WaitUntil {
+                condition: expr, ..
+            }
+            | Statement::TurnRight { degrees: expr, .. }
+            | Statement::TurnLeft { degrees: expr, .. }
+            | Statement::GoToTarget { target: expr, .. }
+            | Statement::ChangeXBy { value: expr, .. }
+            | Statement::SetX { value: expr, .. }
+            | Statement::ChangeYBy { value: expr, .. }
+            | Statement::SetY { value: expr, .. }
+            | Statement::PointInDirection {
+                direction: expr, ..
+            }
+            | Statement::PointTowards { target: expr, .. }
+            | Statement::ChangeSizeBy { value: expr, .. }
+            | Statement::SetSizeTo { value: expr, .. }
+            | Statement::SetGraphicEffectTo { value: expr, .. }
+            | Statement::ChangeGraphicEffectBy { value: expr, .. }
+            | Statement::GoLayers { layers: expr, .. }
+            | Statement::ChangePenSizeBy { value: expr, .. }
+            | Statement::SetPenSizeTo { value: expr, .. }
+            | Statement::ChangePenColorParamBy { value: expr, .. }
+            | Statement::SetPenColorParamTo { value: expr, .. }
+            | Statement::SetPenColorTo { color: expr, .. }
+            | Statement::SwitchCostumeTo { costume: expr, .. }
+            | Statement::SwitchBackdropTo { backdrop: expr, .. }
+            | Statement::SwitchBackdropToAndWait { backdrop: expr, .. }
+            | Statement::Stop { option: expr, .. }
+            | Statement::Ask { question: expr, .. }
+            | Statement::StartSound { sound: expr, .. }
+            | Statement::PlaySoundUntilDone { sound: expr, .. }
+            | Statement::SetSoundEffectTo { value: expr, .. }
+            | Statement::ChangeSoundEffectBy { value: expr, .. }
+            | Statement::SetVolumeTo { value: expr, .. }
+            | Statement::ChangeVolumeBy { value: expr, .. }
+            | Statement::PlayDrumForBeats { beats: expr, .. }
+            | Statement::RestForBeats { beats: expr, .. }
+            | Statement::SetTempoTo { value: expr, .. }
+            | Statement::ChangeTempoBy { value: expr, .. }
+            | Statement::CreateCloneOf { target: expr, .. }
+            | Statement::AddToList { item: expr, .. }
+            | Statement::DeleteOfList { index: expr, .. } => (vec![expr], Vec::new()),
+            Statement::SayForSeconds {
+                message: first,
+                duration: second,
+                ..
+            }
+            | Statement::ThinkForSeconds {
+                message: first,
+                duration: second,
+                ..
+            }
+            | Statement::GoToXY {
+                x: first,
+                y: second,
+                ..
+            }
+            | Statement::GlideToTarget {
+                duration: first,
+                target: second,
+                ..
+            }
+            | Statement::PlayNoteForBeats {
+                note: first,
+                beats: second,
+                ..
+            }
+            | Statement::InsertAtList {
+                item: first,
+                index: second,
+                ..
+            }
+            | Statement::ReplaceItemOfList {
+                index: first,
+                item: second,
+                ..
+            } => (vec![first, second], Vec::new()),
+            Statement::GlideToXY { duration, x, y, .. } => (vec![duration, x, y], Vec::new()),
+            Statement::ProcedureCall { args, .. } => (args.iter_mut().collect(), Vec::new()),
+            Statement::SetRotationStyle { .. }
+            | Statement::IfOnEdgeBounce { .. }
+            | Statement::ClearGraphicEffects { .. }
+            | Statement::GoToLayer { .. }
+            | Statement::PenDown { .. }
+            | Statement::PenUp { .. }
+            | Statement::PenClear { .. }
+            | Statement::PenStamp { .. }
+            | Statement::Show { .. }
+            | Statement::Hide { .. }
+            | Statement::NextCostume { .. }
+            | Statement::NextBackdrop { .. }
+            | Statement::StopAllSounds { .. }
+            | Statement::SetInstrumentTo { .. }
+            | Statement::ClearSoundEffects { .. }
+            | Statement::DeleteThisClone { .. }
+            | Statement::ShowVariable { .. }
+            | Statement::HideVariable { .. }
+            | Statement::ShowList { .. }
+            | Statement::HideList { .. }
+            | Statement::ResetTimer { .. }
+            | Statement::DeleteAllOfList { .. }
+            | Statement::RawBlock { .. } => (Vec::new(), Vec::new()),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// Calls `visit` on every statement in `statements` and in the bodies nested in
+/// them, each statement before its bodies.
+pub fn walk_statements<'a>(statements: &'a [Statement], visit: &mut impl FnMut(&'a Statement)) {
+    for statement in statements {
+        visit(statement);
+        for body in statement.children().1 {
+            walk_statements(body, visit);
+        }
+    }
+}
+
+/// Like [`walk_statements`]. The bodies are walked after `visit` returns, so
+/// statements it adds to them are visited too.
+pub fn walk_statements_mut(statements: &mut [Statement], visit: &mut impl FnMut(&mut Statement)) {
+    for statement in statements {
+        visit(statement);
+        for body in statement.children_mut().1 {
+            walk_statements_mut(body, visit);
+        }
+    }
+}
+
+/// Calls `visit` on `expr` and every expression nested in it, outermost first.
+pub fn walk_exprs<'a>(expr: &'a Expr, visit: &mut impl FnMut(&'a Expr)) {
+    visit(expr);
+    for child in expr.children() {
+        walk_exprs(child, visit);
+    }
+}
+
+/// Like [`walk_exprs`]. Children are walked after `visit` returns, so an
+/// expression it replaces is walked in its new form.
+pub fn walk_exprs_mut(expr: &mut Expr, visit: &mut impl FnMut(&mut Expr)) {
+    visit(expr);
+    for child in expr.children_mut() {
+        walk_exprs_mut(child, visit);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum EventType {
     WhenFlagClicked,
     WhenThisSpriteClicked,
     WhenIReceive(String),
     WhenKeyPressed(String),
+    WhenStartAsClone,
+    WhenBackdropSwitches(String),
+    WhenGreaterThan { sensor: String, value: Expr },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct EventScript {
     pub pos: Position,
     pub event_type: EventType,
     pub body: Vec<Statement>,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ParamKind {
+    StringNumber,
+    Boolean,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Procedure {
     pub pos: Position,
     pub name: String,
     pub params: Vec<String>,
+    pub param_kinds: Vec<ParamKind>,
     pub run_without_screen_refresh: bool,
     pub body: Vec<Statement>,
+    pub comment: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CostumeDecl {
     pub pos: Position,
+    pub name: Option<String>,
     pub path: String,
+    /// Directory of the imported file that declared the costume, searched
+    /// before the usual asset directories.
+    pub origin_dir: Option<PathBuf>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TargetProperty {
+    X(f64),
+    Y(f64),
+    Size(f64),
+    Direction(f64),
+    Visible(bool),
+    RotationStyle(String),
+    Volume(f64),
+    /// Pinned stacking position among sprites; `1` is the bottom layer.
+    Layer(f64),
+    /// Name of the costume (or backdrop) shown when the project starts.
+    StartCostume(String),
+    Tempo(f64),
+    VideoTransparency(f64),
+    VideoState(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PropertyDecl {
+    pub pos: Position,
+    pub property: TargetProperty,
+}
+
+/// A stage monitor for a variable or list: `monitor [score] at (10) (10) mode [large]`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MonitorDecl {
+    pub pos: Position,
+    pub var_name: String,
+    pub x: f64,
+    pub y: f64,
+    pub mode: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SoundDecl {
+    pub pos: Position,
+    pub name: Option<String>,
+    pub path: String,
+    /// Directory of the imported file that declared the sound, searched
+    /// before the usual asset directories.
+    pub origin_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum InitialValue {
     Number(f64),
     String(String),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct VariableDecl {
     pub pos: Position,
     pub name: String,
     pub initial_value: Option<InitialValue>,
+    pub is_cloud: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ListDecl {
     pub pos: Position,
     pub name: String,
     pub initial_items: Option<Vec<InitialValue>>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Target {
     pub pos: Position,
     pub name: String,
@@ -552,18 +1223,110 @@ pub struct Target {
     pub variables: Vec<VariableDecl>,
     pub lists: Vec<ListDecl>,
     pub costumes: Vec<CostumeDecl>,
+    pub sounds: Vec<SoundDecl>,
+    pub properties: Vec<PropertyDecl>,
+    pub monitors: Vec<MonitorDecl>,
     pub procedures: Vec<Procedure>,
     pub scripts: Vec<EventScript>,
     pub reporters: Vec<ReporterDecl>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Project {
     pub pos: Position,
     pub targets: Vec<Target>,
 }
 
-#[derive(Debug, Clone)]
+/// The project as JSON, in the shape `serde` gives the AST: structs become
+/// objects and enum variants objects keyed by the variant name, such as
+/// `{"Number": {"pos": {"line": 3, "column": 9}, "value": 10.0}}`.
+///
+/// Fails on an infinite or NaN number, such as one built with
+/// `f64::INFINITY`, since JSON cannot hold it and serde would write `null`.
+#[cfg(feature = "serde")]
+pub fn project_to_json(project: &Project) -> anyhow::Result<serde_json::Value> {
+    if let Some((value, pos)) = first_non_finite_number(project) {
+        anyhow::bail!(
+            "Number {} at line {}, column {} cannot be written as JSON.",
+            value,
+            pos.line,
+            pos.column
+        );
+    }
+    Ok(serde_json::to_value(project)?)
+}
+
+/// The first infinite or NaN number in `project`, with its position.
+#[cfg(feature = "serde")]
+fn first_non_finite_number(project: &Project) -> Option<(f64, Position)> {
+    let mut found = None;
+    let mut check = |value: f64, pos: Position| {
+        if !value.is_finite() && found.is_none() {
+            found = Some((value, pos));
+        }
+    };
+    for target in &project.targets {
+        for decl in &target.variables {
+            if let Some(InitialValue::Number(value)) = decl.initial_value {
+                check(value, decl.pos);
+            }
+        }
+        for decl in &target.lists {
+            for item in decl.initial_items.iter().flatten() {
+                if let InitialValue::Number(value) = item {
+                    check(*value, decl.pos);
+                }
+            }
+        }
+        for decl in &target.properties {
+            match decl.property {
+                TargetProperty::X(value)
+                | TargetProperty::Y(value)
+                | TargetProperty::Size(value)
+                | TargetProperty::Direction(value)
+                | TargetProperty::Volume(value)
+                | TargetProperty::Layer(value)
+                | TargetProperty::Tempo(value)
+                | TargetProperty::VideoTransparency(value) => check(value, decl.pos),
+                _ => {}
+            }
+        }
+        for decl in &target.monitors {
+            check(decl.x, decl.pos);
+            check(decl.y, decl.pos);
+        }
+
+        let mut exprs = target
+            .scripts
+            .iter()
+            .filter_map(|script| match &script.event_type {
+                EventType::WhenGreaterThan { value, .. } => Some(value),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let bodies = target
+            .procedures
+            .iter()
+            .map(|procedure| &procedure.body)
+            .chain(target.scripts.iter().map(|script| &script.body))
+            .chain(target.reporters.iter().map(|reporter| &reporter.body));
+        for body in bodies {
+            walk_statements(body, &mut |statement| exprs.extend(statement.children().0));
+        }
+        for expr in exprs {
+            walk_exprs(expr, &mut |expr| {
+                if let Expr::Number { pos, value } = expr {
+                    check(*value, *pos);
+                }
+            });
+        }
+    }
+    found
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ReporterDecl {
     pub pos: Position,
     pub name: String,
@@ -571,3 +1334,124 @@ pub struct ReporterDecl {
     pub return_name: Option<String>,
     pub body: Vec<Statement>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first_script_body(source: &str) -> Vec<Statement> {
+        let project = crate::parse_and_validate_source(source).expect("source should check");
+        project.targets[0].scripts[0].body.clone()
+    }
+
+    #[test]
+    fn walks_nested_bodies_before_later_statements() {
+        let body = first_script_body(
+            "sprite Cat\n  when flag clicked\n    repeat (2)\n      if <(1) = (1)> then\n        show\n      else\n        forever\n          hide\n        end\n      end\n    end\n    stop [all]\n  end\nend\n",
+        );
+        let mut lines = Vec::new();
+        walk_statements(&body, &mut |statement| lines.push(statement.pos().line));
+        assert_eq!(lines, vec![3, 4, 5, 7, 8, 12]);
+    }
+
+    #[test]
+    fn walks_sub_expressions_outermost_first() {
+        let body = first_script_body(
+            "sprite Cat\n  when flag clicked\n    say (join (letter (1) of (\"ab\")) ((2) * (3)))\n  end\nend\n",
+        );
+        let Statement::Say { message, .. } = &body[0] else {
+            panic!("expected say, got {:?}", body[0]);
+        };
+        let mut numbers = Vec::new();
+        let mut joins = 0;
+        walk_exprs(message, &mut |expr| match expr {
+            Expr::Number { value, .. } => numbers.push(*value),
+            Expr::StringJoin { .. } => joins += 1,
+            _ => {}
+        });
+        assert_eq!(numbers, vec![1.0, 2.0, 3.0]);
+        assert_eq!(joins, 1);
+    }
+
+    #[test]
+    fn mutable_walks_reach_every_expression() {
+        let mut body = first_script_body(
+            "sprite Cat\n  when flag clicked\n    repeat (2)\n      move ((1) + (2)) steps\n    end\n  end\nend\n",
+        );
+        walk_statements_mut(&mut body, &mut |statement| {
+            for expr in statement.children_mut().0 {
+                walk_exprs_mut(expr, &mut |expr| {
+                    if let Expr::Number { value, .. } = expr {
+                        *value *= 10.0;
+                    }
+                });
+            }
+        });
+        let mut numbers = Vec::new();
+        walk_statements(&body, &mut |statement| {
+            for expr in statement.children().0 {
+                walk_exprs(expr, &mut |expr| {
+                    if let Expr::Number { value, .. } = expr {
+                        numbers.push(*value);
+                    }
+                });
+            }
+        });
+        assert_eq!(numbers, vec![20.0, 10.0, 20.0]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn projects_round_trip_through_json() {
+        let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/adv");
+        for entry in std::fs::read_dir(&fixtures).expect("fixtures directory") {
+            let path = entry.expect("fixture entry").path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("sbtext") {
+                continue;
+            }
+            let source = std::fs::read_to_string(&path).expect("fixture should read");
+            let project = crate::parse_and_validate_source(&source)
+                .unwrap_or_else(|err| panic!("{}: {:#}", path.display(), err));
+            let json = project_to_json(&project).expect("project should convert");
+            let back: Project = serde_json::from_value(json.clone())
+                .unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+            assert_eq!(back, project, "{}", path.display());
+            assert_eq!(
+                project_to_json(&back).expect("project should convert"),
+                json
+            );
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_names_variants_and_fields() {
+        let project = crate::parse_and_validate_source(
+            "sprite Cat\n  when flag clicked\n    move (10) steps\n  end\nend\n",
+        )
+        .expect("source should check");
+        let json = project_to_json(&project).expect("project should convert");
+        let script = &json["targets"][0]["scripts"][0];
+        assert_eq!(script["event_type"], "WhenFlagClicked");
+        assert_eq!(script["body"][0]["Move"]["steps"]["Number"]["value"], 10.0);
+        assert_eq!(script["pos"]["line"], 2);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_rejects_numbers_it_cannot_hold() {
+        let project = crate::build::ProjectBuilder::new()
+            .sprite("Cat", |sp| {
+                sp.on_flag(|b| {
+                    b.move_steps(crate::build::expr::num(f64::INFINITY));
+                });
+            })
+            .build()
+            .expect("project should check");
+        let err = project_to_json(&project).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Number inf at line 3, column 1 cannot be written as JSON."
+        );
+    }
+}
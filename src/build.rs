@@ -0,0 +1,1659 @@
+use crate::ast::{
+    walk_exprs_mut, CostumeDecl, EventScript, EventType, Expr, InitialValue, ListDecl, MonitorDecl,
+    ParamKind, Position, Procedure, Project, PropertyDecl, SoundDecl, Statement, Target,
+    TargetProperty, VariableDecl,
+};
+use crate::codegen::{self, CodegenOptions};
+use crate::music::{MUSIC_DRUMS, MUSIC_INSTRUMENTS};
+use crate::semantic::{self, SemanticOptions, SemanticReport};
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::path::Path;
+
+/// Builds a project in Rust instead of writing SBText source:
+///
+/// ```
+/// use sbtext_rs_core::build::{expr, ProjectBuilder};
+///
+/// let project = ProjectBuilder::new()
+///     .sprite("Cat", |sp| {
+///         sp.var("score");
+///         sp.on_flag(|b| {
+///             b.set_var("score", expr::num(0.0));
+///             b.repeat(expr::num(10.0), |b| {
+///                 b.change_var("score", expr::num(1.0));
+///             });
+///             b.say(expr::join(expr::str("Score: "), expr::var("score")));
+///         });
+///     })
+///     .build()
+///     .expect("project should check");
+/// assert_eq!(project.targets.len(), 1);
+/// ```
+///
+/// Nothing has a source position, so declarations, scripts, and blocks are
+/// numbered as lines in the order they were added, each expression taking the
+/// line of its block. Semantic errors refer to those numbers.
+#[derive(Debug, Clone)]
+pub struct ProjectBuilder {
+    stage: Option<Target>,
+    sprites: Vec<Target>,
+    next_line: usize,
+}
+
+impl Default for ProjectBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProjectBuilder {
+    pub fn new() -> Self {
+        Self {
+            stage: None,
+            sprites: Vec::new(),
+            next_line: 1,
+        }
+    }
+
+    /// Adds to the stage. Without a call the project gets an empty stage.
+    pub fn stage(mut self, build: impl FnOnce(&mut TargetBuilder<'_>)) -> Self {
+        let target = match self.stage.take() {
+            Some(target) => target,
+            None => new_target(next_pos(&mut self.next_line), "Stage", true),
+        };
+        self.stage = Some(TargetBuilder::run(target, &mut self.next_line, build));
+        self
+    }
+
+    pub fn sprite(
+        mut self,
+        name: impl Into<String>,
+        build: impl FnOnce(&mut TargetBuilder<'_>),
+    ) -> Self {
+        let target = new_target(next_pos(&mut self.next_line), name.into(), false);
+        self.sprites
+            .push(TargetBuilder::run(target, &mut self.next_line, build));
+        self
+    }
+
+    /// The project, after the same semantic checks as compiled source.
+    /// Warnings are dropped; [`ProjectBuilder::build_with_report`] returns them.
+    pub fn build(self) -> Result<Project> {
+        self.build_with_report().map(|(project, _)| project)
+    }
+
+    /// Like [`ProjectBuilder::build`], but also returns the semantic report
+    /// with its warnings.
+    pub fn build_with_report(self) -> Result<(Project, SemanticReport)> {
+        let project = Project {
+            pos: Position::new(1, 1),
+            targets: self.stage.into_iter().chain(self.sprites).collect(),
+        };
+        let report = semantic::analyze_with_options(&project, SemanticOptions::default())
+            .map_err(|err| anyhow!(err.message))?;
+        Ok((project, report))
+    }
+
+    /// Checks the project and packages it as `.sb3` bytes, loading costumes and
+    /// sounds relative to `source_dir`. Warnings are dropped, as in
+    /// [`ProjectBuilder::build`].
+    pub fn build_sb3_bytes(self, source_dir: &Path, options: CodegenOptions) -> Result<Vec<u8>> {
+        codegen::build_sb3_bytes(&self.build()?, source_dir, options)
+    }
+}
+
+fn next_pos(next_line: &mut usize) -> Position {
+    let pos = Position::new(*next_line, 1);
+    *next_line += 1;
+    pos
+}
+
+fn new_target(pos: Position, name: impl Into<String>, is_stage: bool) -> Target {
+    Target {
+        pos,
+        name: name.into(),
+        is_stage,
+        variables: Vec::new(),
+        lists: Vec::new(),
+        costumes: Vec::new(),
+        sounds: Vec::new(),
+        properties: Vec::new(),
+        monitors: Vec::new(),
+        procedures: Vec::new(),
+        scripts: Vec::new(),
+        reporters: Vec::new(),
+    }
+}
+
+/// Declarations, procedures, and scripts of the stage or one sprite.
+#[derive(Debug)]
+pub struct TargetBuilder<'a> {
+    target: Target,
+    next_line: &'a mut usize,
+}
+
+impl<'a> TargetBuilder<'a> {
+    fn run(target: Target, next_line: &'a mut usize, build: impl FnOnce(&mut Self)) -> Target {
+        let mut builder = Self { target, next_line };
+        build(&mut builder);
+        builder.target
+    }
+
+    fn next_pos(&mut self) -> Position {
+        next_pos(self.next_line)
+    }
+
+    pub fn var(&mut self, name: impl Into<String>) -> &mut Self {
+        self.add_var(name.into(), None, false)
+    }
+
+    pub fn var_with_value(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Into<InitialValue>,
+    ) -> &mut Self {
+        self.add_var(name.into(), Some(value.into()), false)
+    }
+
+    /// A cloud variable, which only the stage can declare.
+    pub fn cloud_var(&mut self, name: impl Into<String>) -> &mut Self {
+        self.add_var(name.into(), None, true)
+    }
+
+    fn add_var(
+        &mut self,
+        name: String,
+        initial_value: Option<InitialValue>,
+        is_cloud: bool,
+    ) -> &mut Self {
+        let pos = self.next_pos();
+        self.target.variables.push(VariableDecl {
+            pos,
+            name,
+            initial_value,
+            is_cloud,
+        });
+        self
+    }
+
+    pub fn list(&mut self, name: impl Into<String>) -> &mut Self {
+        let pos = self.next_pos();
+        self.target.lists.push(ListDecl {
+            pos,
+            name: name.into(),
+            initial_items: None,
+        });
+        self
+    }
+
+    pub fn list_with_items<T: Into<InitialValue>>(
+        &mut self,
+        name: impl Into<String>,
+        items: impl IntoIterator<Item = T>,
+    ) -> &mut Self {
+        let pos = self.next_pos();
+        self.target.lists.push(ListDecl {
+            pos,
+            name: name.into(),
+            initial_items: Some(items.into_iter().map(Into::into).collect()),
+        });
+        self
+    }
+
+    /// A costume (a backdrop on the stage) named after its file.
+    pub fn costume(&mut self, path: impl Into<String>) -> &mut Self {
+        self.add_costume(None, path.into())
+    }
+
+    pub fn costume_named(&mut self, name: impl Into<String>, path: impl Into<String>) -> &mut Self {
+        self.add_costume(Some(name.into()), path.into())
+    }
+
+    fn add_costume(&mut self, name: Option<String>, path: String) -> &mut Self {
+        let pos = self.next_pos();
+        self.target.costumes.push(CostumeDecl {
+            pos,
+            name,
+            path,
+            origin_dir: None,
+        });
+        self
+    }
+
+    pub fn sound(&mut self, path: impl Into<String>) -> &mut Self {
+        self.add_sound(None, path.into())
+    }
+
+    pub fn sound_named(&mut self, name: impl Into<String>, path: impl Into<String>) -> &mut Self {
+        self.add_sound(Some(name.into()), path.into())
+    }
+
+    fn add_sound(&mut self, name: Option<String>, path: String) -> &mut Self {
+        let pos = self.next_pos();
+        self.target.sounds.push(SoundDecl {
+            pos,
+            name,
+            path,
+            origin_dir: None,
+        });
+        self
+    }
+
+    pub fn property(&mut self, property: TargetProperty) -> &mut Self {
+        let pos = self.next_pos();
+        self.target.properties.push(PropertyDecl { pos, property });
+        self
+    }
+
+    /// A stage monitor for a variable or list.
+    pub fn monitor(
+        &mut self,
+        var_name: impl Into<String>,
+        x: f64,
+        y: f64,
+        mode: MonitorMode,
+    ) -> &mut Self {
+        let pos = self.next_pos();
+        self.target.monitors.push(MonitorDecl {
+            pos,
+            var_name: var_name.into(),
+            x,
+            y,
+            mode: mode.as_str().to_string(),
+        });
+        self
+    }
+
+    /// A custom block whose parameters all take numbers or text.
+    pub fn define(
+        &mut self,
+        name: impl Into<String>,
+        params: &[&str],
+        body: impl FnOnce(&mut BlockBuilder<'_>),
+    ) -> &mut Self {
+        let params = params
+            .iter()
+            .map(|param| (*param, ParamKind::StringNumber))
+            .collect::<Vec<_>>();
+        self.define_with(name, &params, false, body)
+    }
+
+    /// A custom block with boolean parameters or that runs without screen
+    /// refresh.
+    pub fn define_with(
+        &mut self,
+        name: impl Into<String>,
+        params: &[(&str, ParamKind)],
+        run_without_screen_refresh: bool,
+        body: impl FnOnce(&mut BlockBuilder<'_>),
+    ) -> &mut Self {
+        let pos = self.next_pos();
+        let body = BlockBuilder::run(self.next_line, body);
+        self.target.procedures.push(Procedure {
+            pos,
+            name: name.into(),
+            params: params.iter().map(|(name, _)| name.to_string()).collect(),
+            param_kinds: params.iter().map(|(_, kind)| *kind).collect(),
+            run_without_screen_refresh,
+            body,
+            comment: None,
+        });
+        self
+    }
+
+    pub fn on_flag(&mut self, body: impl FnOnce(&mut BlockBuilder<'_>)) -> &mut Self {
+        self.script(EventType::WhenFlagClicked, body)
+    }
+
+    pub fn on_click(&mut self, body: impl FnOnce(&mut BlockBuilder<'_>)) -> &mut Self {
+        self.script(EventType::WhenThisSpriteClicked, body)
+    }
+
+    pub fn on_receive(
+        &mut self,
+        message: impl Into<String>,
+        body: impl FnOnce(&mut BlockBuilder<'_>),
+    ) -> &mut Self {
+        self.script(EventType::WhenIReceive(message.into()), body)
+    }
+
+    pub fn on_key(
+        &mut self,
+        key: impl Into<String>,
+        body: impl FnOnce(&mut BlockBuilder<'_>),
+    ) -> &mut Self {
+        self.script(EventType::WhenKeyPressed(key.into()), body)
+    }
+
+    pub fn on_clone_start(&mut self, body: impl FnOnce(&mut BlockBuilder<'_>)) -> &mut Self {
+        self.script(EventType::WhenStartAsClone, body)
+    }
+
+    pub fn on_backdrop_switch(
+        &mut self,
+        backdrop: impl Into<String>,
+        body: impl FnOnce(&mut BlockBuilder<'_>),
+    ) -> &mut Self {
+        self.script(EventType::WhenBackdropSwitches(backdrop.into()), body)
+    }
+
+    /// `when [loudness] > (value)`.
+    pub fn on_greater_than(
+        &mut self,
+        sensor: Sensor,
+        value: Expr,
+        body: impl FnOnce(&mut BlockBuilder<'_>),
+    ) -> &mut Self {
+        let event_type = EventType::WhenGreaterThan {
+            sensor: sensor.as_str().to_string(),
+            value,
+        };
+        self.script(event_type, body)
+    }
+
+    fn script(
+        &mut self,
+        mut event_type: EventType,
+        body: impl FnOnce(&mut BlockBuilder<'_>),
+    ) -> &mut Self {
+        let pos = self.next_pos();
+        if let EventType::WhenGreaterThan { value, .. } = &mut event_type {
+            place(value, pos);
+        }
+        let body = BlockBuilder::run(self.next_line, body);
+        self.target.scripts.push(EventScript {
+            pos,
+            event_type,
+            body,
+            comment: None,
+        });
+        self
+    }
+}
+
+/// Moves `expr` and everything nested in it to `pos`.
+fn place(expr: &mut Expr, pos: Position) {
+    walk_exprs_mut(expr, &mut |expr| *expr.pos_mut() = pos);
+}
+
+/// The blocks of a script, procedure, or loop or `if` body, in order. Every
+/// block takes its inputs as arguments; nested bodies are built by closures.
+#[derive(Debug)]
+pub struct BlockBuilder<'a> {
+    statements: Vec<Statement>,
+    next_line: &'a mut usize,
+}
+
+impl<'a> BlockBuilder<'a> {
+    fn run(next_line: &'a mut usize, build: impl FnOnce(&mut Self)) -> Vec<Statement> {
+        let mut builder = Self {
+            statements: Vec::new(),
+            next_line,
+        };
+        build(&mut builder);
+        builder.statements
+    }
+
+    fn next_pos(&mut self) -> Position {
+        next_pos(self.next_line)
+    }
+
+    fn body(&mut self, build: impl FnOnce(&mut BlockBuilder<'_>)) -> Vec<Statement> {
+        BlockBuilder::run(self.next_line, build)
+    }
+
+    fn add(&mut self, mut statement: Statement) -> &mut Self {
+        let pos = statement.pos();
+        for expr in statement.children_mut().0 {
+            place(expr, pos);
+        }
+        self.statements.push(statement);
+        self
+    }
+
+    fn push(&mut self, statement: impl FnOnce(Position) -> Statement) -> &mut Self {
+        let pos = self.next_pos();
+        self.add(statement(pos))
+    }
+
+    pub fn broadcast(&mut self, message: Expr) -> &mut Self {
+        self.push(|pos| Statement::Broadcast { pos, message })
+    }
+
+    pub fn broadcast_and_wait(&mut self, message: Expr) -> &mut Self {
+        self.push(|pos| Statement::BroadcastAndWait { pos, message })
+    }
+
+    pub fn set_var(&mut self, var_name: impl Into<String>, value: Expr) -> &mut Self {
+        let var_name = var_name.into();
+        self.push(|pos| Statement::SetVar {
+            pos,
+            var_name,
+            value,
+        })
+    }
+
+    pub fn change_var(&mut self, var_name: impl Into<String>, delta: Expr) -> &mut Self {
+        let var_name = var_name.into();
+        self.push(|pos| Statement::ChangeVar {
+            pos,
+            var_name,
+            delta,
+        })
+    }
+
+    /// `move (steps) steps`.
+    pub fn move_steps(&mut self, steps: Expr) -> &mut Self {
+        self.push(|pos| Statement::Move { pos, steps })
+    }
+
+    pub fn say(&mut self, message: Expr) -> &mut Self {
+        self.push(|pos| Statement::Say { pos, message })
+    }
+
+    pub fn say_for_secs(&mut self, message: Expr, duration: Expr) -> &mut Self {
+        self.push(|pos| Statement::SayForSeconds {
+            pos,
+            message,
+            duration,
+        })
+    }
+
+    pub fn think(&mut self, message: Expr) -> &mut Self {
+        self.push(|pos| Statement::Think { pos, message })
+    }
+
+    pub fn think_for_secs(&mut self, message: Expr, duration: Expr) -> &mut Self {
+        self.push(|pos| Statement::ThinkForSeconds {
+            pos,
+            message,
+            duration,
+        })
+    }
+
+    pub fn wait(&mut self, duration: Expr) -> &mut Self {
+        self.push(|pos| Statement::Wait { pos, duration })
+    }
+
+    pub fn wait_until(&mut self, condition: Expr) -> &mut Self {
+        self.push(|pos| Statement::WaitUntil { pos, condition })
+    }
+
+    pub fn repeat(&mut self, times: Expr, body: impl FnOnce(&mut BlockBuilder<'_>)) -> &mut Self {
+        let pos = self.next_pos();
+        let body = self.body(body);
+        self.add(Statement::Repeat { pos, times, body })
+    }
+
+    /// `for each [var_name] in (value)`: counts `var_name` from 1 to `value`.
+    pub fn for_each(
+        &mut self,
+        var_name: impl Into<String>,
+        value: Expr,
+        body: impl FnOnce(&mut BlockBuilder<'_>),
+    ) -> &mut Self {
+        let pos = self.next_pos();
+        let body = self.body(body);
+        self.add(Statement::ForEach {
+            pos,
+            var_name: var_name.into(),
+            value,
+            body,
+        })
+    }
+
+    /// `while <condition>`.
+    pub fn repeat_while(
+        &mut self,
+        condition: Expr,
+        body: impl FnOnce(&mut BlockBuilder<'_>),
+    ) -> &mut Self {
+        let pos = self.next_pos();
+        let body = self.body(body);
+        self.add(Statement::While {
+            pos,
+            condition,
+            body,
+        })
+    }
+
+    pub fn repeat_until(
+        &mut self,
+        condition: Expr,
+        body: impl FnOnce(&mut BlockBuilder<'_>),
+    ) -> &mut Self {
+        let pos = self.next_pos();
+        let body = self.body(body);
+        self.add(Statement::RepeatUntil {
+            pos,
+            condition,
+            body,
+        })
+    }
+
+    pub fn forever(&mut self, body: impl FnOnce(&mut BlockBuilder<'_>)) -> &mut Self {
+        let pos = self.next_pos();
+        let body = self.body(body);
+        self.add(Statement::Forever { pos, body })
+    }
+
+    pub fn if_then(
+        &mut self,
+        condition: Expr,
+        then_body: impl FnOnce(&mut BlockBuilder<'_>),
+    ) -> &mut Self {
+        self.if_else(condition, then_body, |_| {})
+    }
+
+    pub fn if_else(
+        &mut self,
+        condition: Expr,
+        then_body: impl FnOnce(&mut BlockBuilder<'_>),
+        else_body: impl FnOnce(&mut BlockBuilder<'_>),
+    ) -> &mut Self {
+        let pos = self.next_pos();
+        let then_body = self.body(then_body);
+        let else_body = self.body(else_body);
+        self.add(Statement::If {
+            pos,
+            condition,
+            then_body,
+            else_body,
+        })
+    }
+
+    /// Calls a custom block, or `Target.name` to run one in another sprite.
+    pub fn call(&mut self, name: impl Into<String>, args: Vec<Expr>) -> &mut Self {
+        let name = name.into();
+        self.push(|pos| Statement::ProcedureCall { pos, name, args })
+    }
+
+    pub fn turn_right(&mut self, degrees: Expr) -> &mut Self {
+        self.push(|pos| Statement::TurnRight { pos, degrees })
+    }
+
+    pub fn turn_left(&mut self, degrees: Expr) -> &mut Self {
+        self.push(|pos| Statement::TurnLeft { pos, degrees })
+    }
+
+    pub fn go_to_xy(&mut self, x: Expr, y: Expr) -> &mut Self {
+        self.push(|pos| Statement::GoToXY { pos, x, y })
+    }
+
+    /// `go to (target)`: a sprite name, `random position`, or `mouse-pointer`.
+    pub fn go_to(&mut self, target: Expr) -> &mut Self {
+        self.push(|pos| Statement::GoToTarget { pos, target })
+    }
+
+    pub fn glide_to_xy(&mut self, duration: Expr, x: Expr, y: Expr) -> &mut Self {
+        self.push(|pos| Statement::GlideToXY {
+            pos,
+            duration,
+            x,
+            y,
+        })
+    }
+
+    pub fn glide_to(&mut self, duration: Expr, target: Expr) -> &mut Self {
+        self.push(|pos| Statement::GlideToTarget {
+            pos,
+            duration,
+            target,
+        })
+    }
+
+    pub fn change_x_by(&mut self, value: Expr) -> &mut Self {
+        self.push(|pos| Statement::ChangeXBy { pos, value })
+    }
+
+    pub fn set_x(&mut self, value: Expr) -> &mut Self {
+        self.push(|pos| Statement::SetX { pos, value })
+    }
+
+    pub fn change_y_by(&mut self, value: Expr) -> &mut Self {
+        self.push(|pos| Statement::ChangeYBy { pos, value })
+    }
+
+    pub fn set_y(&mut self, value: Expr) -> &mut Self {
+        self.push(|pos| Statement::SetY { pos, value })
+    }
+
+    pub fn point_in_direction(&mut self, direction: Expr) -> &mut Self {
+        self.push(|pos| Statement::PointInDirection { pos, direction })
+    }
+
+    pub fn point_towards(&mut self, target: Expr) -> &mut Self {
+        self.push(|pos| Statement::PointTowards { pos, target })
+    }
+
+    pub fn set_rotation_style(&mut self, style: RotationStyle) -> &mut Self {
+        let style = style.as_str().to_string();
+        self.push(|pos| Statement::SetRotationStyle { pos, style })
+    }
+
+    pub fn if_on_edge_bounce(&mut self) -> &mut Self {
+        self.push(|pos| Statement::IfOnEdgeBounce { pos })
+    }
+
+    pub fn change_size_by(&mut self, value: Expr) -> &mut Self {
+        self.push(|pos| Statement::ChangeSizeBy { pos, value })
+    }
+
+    pub fn set_size_to(&mut self, value: Expr) -> &mut Self {
+        self.push(|pos| Statement::SetSizeTo { pos, value })
+    }
+
+    pub fn clear_graphic_effects(&mut self) -> &mut Self {
+        self.push(|pos| Statement::ClearGraphicEffects { pos })
+    }
+
+    pub fn set_graphic_effect_to(&mut self, effect: GraphicEffect, value: Expr) -> &mut Self {
+        let effect = effect.as_str().to_string();
+        self.push(|pos| Statement::SetGraphicEffectTo { pos, effect, value })
+    }
+
+    pub fn change_graphic_effect_by(&mut self, effect: GraphicEffect, value: Expr) -> &mut Self {
+        let effect = effect.as_str().to_string();
+        self.push(|pos| Statement::ChangeGraphicEffectBy { pos, effect, value })
+    }
+
+    pub fn go_to_layer(&mut self, layer: Layer) -> &mut Self {
+        let layer = layer.as_str().to_string();
+        self.push(|pos| Statement::GoToLayer { pos, layer })
+    }
+
+    pub fn go_layers(&mut self, direction: LayerDirection, layers: Expr) -> &mut Self {
+        let direction = direction.as_str().to_string();
+        self.push(|pos| Statement::GoLayers {
+            pos,
+            direction,
+            layers,
+        })
+    }
+
+    pub fn pen_down(&mut self) -> &mut Self {
+        self.push(|pos| Statement::PenDown { pos })
+    }
+
+    pub fn pen_up(&mut self) -> &mut Self {
+        self.push(|pos| Statement::PenUp { pos })
+    }
+
+    pub fn pen_clear(&mut self) -> &mut Self {
+        self.push(|pos| Statement::PenClear { pos })
+    }
+
+    pub fn pen_stamp(&mut self) -> &mut Self {
+        self.push(|pos| Statement::PenStamp { pos })
+    }
+
+    pub fn change_pen_size_by(&mut self, value: Expr) -> &mut Self {
+        self.push(|pos| Statement::ChangePenSizeBy { pos, value })
+    }
+
+    pub fn set_pen_size_to(&mut self, value: Expr) -> &mut Self {
+        self.push(|pos| Statement::SetPenSizeTo { pos, value })
+    }
+
+    pub fn change_pen_color_param_by(&mut self, param: PenColorParam, value: Expr) -> &mut Self {
+        let param = param.as_str().to_string();
+        self.push(|pos| Statement::ChangePenColorParamBy { pos, param, value })
+    }
+
+    pub fn set_pen_color_param_to(&mut self, param: PenColorParam, value: Expr) -> &mut Self {
+        let param = param.as_str().to_string();
+        self.push(|pos| Statement::SetPenColorParamTo { pos, param, value })
+    }
+
+    pub fn set_pen_color_to(&mut self, color: Expr) -> &mut Self {
+        self.push(|pos| Statement::SetPenColorTo { pos, color })
+    }
+
+    pub fn show(&mut self) -> &mut Self {
+        self.push(|pos| Statement::Show { pos })
+    }
+
+    pub fn hide(&mut self) -> &mut Self {
+        self.push(|pos| Statement::Hide { pos })
+    }
+
+    pub fn next_costume(&mut self) -> &mut Self {
+        self.push(|pos| Statement::NextCostume { pos })
+    }
+
+    pub fn next_backdrop(&mut self) -> &mut Self {
+        self.push(|pos| Statement::NextBackdrop { pos })
+    }
+
+    pub fn switch_costume_to(&mut self, costume: Expr) -> &mut Self {
+        self.push(|pos| Statement::SwitchCostumeTo { pos, costume })
+    }
+
+    pub fn switch_backdrop_to(&mut self, backdrop: Expr) -> &mut Self {
+        self.push(|pos| Statement::SwitchBackdropTo { pos, backdrop })
+    }
+
+    pub fn switch_backdrop_to_and_wait(&mut self, backdrop: Expr) -> &mut Self {
+        self.push(|pos| Statement::SwitchBackdropToAndWait { pos, backdrop })
+    }
+
+    pub fn stop(&mut self, option: StopOption) -> &mut Self {
+        let option = expr::str(option.as_str());
+        self.push(|pos| Statement::Stop { pos, option })
+    }
+
+    pub fn ask(&mut self, question: Expr) -> &mut Self {
+        self.push(|pos| Statement::Ask { pos, question })
+    }
+
+    pub fn start_sound(&mut self, sound: Expr) -> &mut Self {
+        self.push(|pos| Statement::StartSound { pos, sound })
+    }
+
+    pub fn play_sound_until_done(&mut self, sound: Expr) -> &mut Self {
+        self.push(|pos| Statement::PlaySoundUntilDone { pos, sound })
+    }
+
+    pub fn stop_all_sounds(&mut self) -> &mut Self {
+        self.push(|pos| Statement::StopAllSounds { pos })
+    }
+
+    pub fn set_sound_effect_to(&mut self, effect: SoundEffect, value: Expr) -> &mut Self {
+        let effect = effect.as_str().to_string();
+        self.push(|pos| Statement::SetSoundEffectTo { pos, effect, value })
+    }
+
+    pub fn change_sound_effect_by(&mut self, effect: SoundEffect, value: Expr) -> &mut Self {
+        let effect = effect.as_str().to_string();
+        self.push(|pos| Statement::ChangeSoundEffectBy { pos, effect, value })
+    }
+
+    pub fn set_volume_to(&mut self, value: Expr) -> &mut Self {
+        self.push(|pos| Statement::SetVolumeTo { pos, value })
+    }
+
+    pub fn change_volume_by(&mut self, value: Expr) -> &mut Self {
+        self.push(|pos| Statement::ChangeVolumeBy { pos, value })
+    }
+
+    pub fn play_note_for_beats(&mut self, note: Expr, beats: Expr) -> &mut Self {
+        self.push(|pos| Statement::PlayNoteForBeats { pos, note, beats })
+    }
+
+    pub fn play_drum_for_beats(&mut self, drum: Drum, beats: Expr) -> &mut Self {
+        let drum = drum.as_str().to_string();
+        self.push(|pos| Statement::PlayDrumForBeats { pos, drum, beats })
+    }
+
+    pub fn rest_for_beats(&mut self, beats: Expr) -> &mut Self {
+        self.push(|pos| Statement::RestForBeats { pos, beats })
+    }
+
+    pub fn set_instrument_to(&mut self, instrument: Instrument) -> &mut Self {
+        let instrument = instrument.as_str().to_string();
+        self.push(|pos| Statement::SetInstrumentTo { pos, instrument })
+    }
+
+    pub fn set_tempo_to(&mut self, value: Expr) -> &mut Self {
+        self.push(|pos| Statement::SetTempoTo { pos, value })
+    }
+
+    pub fn change_tempo_by(&mut self, value: Expr) -> &mut Self {
+        self.push(|pos| Statement::ChangeTempoBy { pos, value })
+    }
+
+    pub fn clear_sound_effects(&mut self) -> &mut Self {
+        self.push(|pos| Statement::ClearSoundEffects { pos })
+    }
+
+    /// `target` is a sprite name or `myself`.
+    pub fn create_clone_of(&mut self, target: Expr) -> &mut Self {
+        self.push(|pos| Statement::CreateCloneOf { pos, target })
+    }
+
+    pub fn delete_this_clone(&mut self) -> &mut Self {
+        self.push(|pos| Statement::DeleteThisClone { pos })
+    }
+
+    pub fn show_variable(&mut self, var_name: impl Into<String>) -> &mut Self {
+        let var_name = var_name.into();
+        self.push(|pos| Statement::ShowVariable { pos, var_name })
+    }
+
+    pub fn hide_variable(&mut self, var_name: impl Into<String>) -> &mut Self {
+        let var_name = var_name.into();
+        self.push(|pos| Statement::HideVariable { pos, var_name })
+    }
+
+    pub fn show_list(&mut self, list_name: impl Into<String>) -> &mut Self {
+        let list_name = list_name.into();
+        self.push(|pos| Statement::ShowList { pos, list_name })
+    }
+
+    pub fn hide_list(&mut self, list_name: impl Into<String>) -> &mut Self {
+        let list_name = list_name.into();
+        self.push(|pos| Statement::HideList { pos, list_name })
+    }
+
+    pub fn reset_timer(&mut self) -> &mut Self {
+        self.push(|pos| Statement::ResetTimer { pos })
+    }
+
+    pub fn add_to_list(&mut self, list_name: impl Into<String>, item: Expr) -> &mut Self {
+        let list_name = list_name.into();
+        self.push(|pos| Statement::AddToList {
+            pos,
+            list_name,
+            item,
+        })
+    }
+
+    pub fn delete_of_list(&mut self, list_name: impl Into<String>, index: Expr) -> &mut Self {
+        let list_name = list_name.into();
+        self.push(|pos| Statement::DeleteOfList {
+            pos,
+            list_name,
+            index,
+        })
+    }
+
+    pub fn delete_all_of_list(&mut self, list_name: impl Into<String>) -> &mut Self {
+        let list_name = list_name.into();
+        self.push(|pos| Statement::DeleteAllOfList { pos, list_name })
+    }
+
+    pub fn insert_at_list(
+        &mut self,
+        list_name: impl Into<String>,
+        item: Expr,
+        index: Expr,
+    ) -> &mut Self {
+        let list_name = list_name.into();
+        self.push(|pos| Statement::InsertAtList {
+            pos,
+            list_name,
+            item,
+            index,
+        })
+    }
+
+    pub fn replace_item_of_list(
+        &mut self,
+        list_name: impl Into<String>,
+        index: Expr,
+        item: Expr,
+    ) -> &mut Self {
+        let list_name = list_name.into();
+        self.push(|pos| Statement::ReplaceItemOfList {
+            pos,
+            list_name,
+            index,
+            item,
+        })
+    }
+
+    /// A Scratch block with no SBText syntax, as block JSON with nested
+    /// blocks in place of ids (see [`Statement::RawBlock`]).
+    pub fn raw(&mut self, block: Value) -> &mut Self {
+        self.push(|pos| Statement::RawBlock { pos, block })
+    }
+}
+
+impl From<f64> for InitialValue {
+    fn from(value: f64) -> Self {
+        InitialValue::Number(value)
+    }
+}
+
+impl From<&str> for InitialValue {
+    fn from(value: &str) -> Self {
+        InitialValue::String(value.to_string())
+    }
+}
+
+impl From<String> for InitialValue {
+    fn from(value: String) -> Self {
+        InitialValue::String(value)
+    }
+}
+
+/// An option of [`BlockBuilder::stop`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopOption {
+    All,
+    ThisScript,
+    OtherScriptsInSprite,
+    /// `other scripts in stage`, for the stage's own scripts.
+    OtherScriptsInStage,
+}
+
+impl StopOption {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StopOption::All => "all",
+            StopOption::ThisScript => "this script",
+            StopOption::OtherScriptsInSprite => "other scripts in sprite",
+            StopOption::OtherScriptsInStage => "other scripts in stage",
+        }
+    }
+}
+
+/// A style for [`BlockBuilder::set_rotation_style`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationStyle {
+    AllAround,
+    LeftRight,
+    DontRotate,
+}
+
+impl RotationStyle {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RotationStyle::AllAround => "all around",
+            RotationStyle::LeftRight => "left-right",
+            RotationStyle::DontRotate => "don't rotate",
+        }
+    }
+}
+
+/// A layer for [`BlockBuilder::go_to_layer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    Front,
+    Back,
+}
+
+impl Layer {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Layer::Front => "front",
+            Layer::Back => "back",
+        }
+    }
+}
+
+/// A direction for [`BlockBuilder::go_layers`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerDirection {
+    Forward,
+    Backward,
+}
+
+impl LayerDirection {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LayerDirection::Forward => "forward",
+            LayerDirection::Backward => "backward",
+        }
+    }
+}
+
+/// A display mode for [`TargetBuilder::monitor`]. List monitors only use
+/// [`MonitorMode::Default`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MonitorMode {
+    Default,
+    Large,
+    Slider,
+}
+
+impl MonitorMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MonitorMode::Default => "default",
+            MonitorMode::Large => "large",
+            MonitorMode::Slider => "slider",
+        }
+    }
+}
+
+/// A sensor for [`TargetBuilder::on_greater_than`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sensor {
+    Loudness,
+    Timer,
+}
+
+impl Sensor {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Sensor::Loudness => "loudness",
+            Sensor::Timer => "timer",
+        }
+    }
+}
+
+/// An effect for [`BlockBuilder::set_graphic_effect_to`] and
+/// [`BlockBuilder::change_graphic_effect_by`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphicEffect {
+    Color,
+    Fisheye,
+    Whirl,
+    Pixelate,
+    Mosaic,
+    Brightness,
+    Ghost,
+}
+
+impl GraphicEffect {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GraphicEffect::Color => "color",
+            GraphicEffect::Fisheye => "fisheye",
+            GraphicEffect::Whirl => "whirl",
+            GraphicEffect::Pixelate => "pixelate",
+            GraphicEffect::Mosaic => "mosaic",
+            GraphicEffect::Brightness => "brightness",
+            GraphicEffect::Ghost => "ghost",
+        }
+    }
+}
+
+/// An effect for [`BlockBuilder::set_sound_effect_to`] and
+/// [`BlockBuilder::change_sound_effect_by`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundEffect {
+    Pitch,
+    /// `pan left/right`
+    Pan,
+}
+
+impl SoundEffect {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SoundEffect::Pitch => "pitch",
+            SoundEffect::Pan => "pan left/right",
+        }
+    }
+}
+
+/// A pen color parameter for [`BlockBuilder::set_pen_color_param_to`] and
+/// [`BlockBuilder::change_pen_color_param_by`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PenColorParam {
+    Color,
+    Saturation,
+    Brightness,
+    Transparency,
+}
+
+impl PenColorParam {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PenColorParam::Color => "color",
+            PenColorParam::Saturation => "saturation",
+            PenColorParam::Brightness => "brightness",
+            PenColorParam::Transparency => "transparency",
+        }
+    }
+}
+
+/// A drum for [`BlockBuilder::play_drum_for_beats`], in the music
+/// extension's menu order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Drum {
+    SnareDrum,
+    BassDrum,
+    SideStick,
+    CrashCymbal,
+    OpenHiHat,
+    ClosedHiHat,
+    Tambourine,
+    HandClap,
+    Claves,
+    WoodBlock,
+    Cowbell,
+    Triangle,
+    Bongo,
+    Conga,
+    Cabasa,
+    Guiro,
+    Vibraslap,
+    Cuica,
+}
+
+impl Drum {
+    pub fn as_str(self) -> &'static str {
+        MUSIC_DRUMS[self as usize]
+    }
+}
+
+/// An instrument for [`BlockBuilder::set_instrument_to`], in the music
+/// extension's menu order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instrument {
+    Piano,
+    ElectricPiano,
+    Organ,
+    Guitar,
+    ElectricGuitar,
+    Bass,
+    Pizzicato,
+    Cello,
+    Trombone,
+    Clarinet,
+    Saxophone,
+    Flute,
+    WoodenFlute,
+    Bassoon,
+    Choir,
+    Vibraphone,
+    MusicBox,
+    SteelDrum,
+    Marimba,
+    SynthLead,
+    SynthPad,
+}
+
+impl Instrument {
+    pub fn as_str(self) -> &'static str {
+        MUSIC_INSTRUMENTS[self as usize]
+    }
+}
+
+/// Expressions for [`BlockBuilder`] inputs. Positions are filled in when the
+/// expression is used in a block.
+pub mod expr {
+    use crate::ast::{Expr, Position};
+
+    fn unplaced() -> Position {
+        Position::new(0, 0)
+    }
+
+    fn boxed(expr: Expr) -> Box<Expr> {
+        Box::new(expr)
+    }
+
+    /// A Scratch math function for [`math`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum MathOp {
+        Abs,
+        Floor,
+        Ceiling,
+        Sqrt,
+        Sin,
+        Cos,
+        Tan,
+        Asin,
+        Acos,
+        Atan,
+        Ln,
+        Log,
+        /// `e ^`
+        Exp,
+        /// `10 ^`
+        Pow10,
+        Round,
+    }
+
+    impl MathOp {
+        pub fn as_str(self) -> &'static str {
+            match self {
+                MathOp::Abs => "abs",
+                MathOp::Floor => "floor",
+                MathOp::Ceiling => "ceiling",
+                MathOp::Sqrt => "sqrt",
+                MathOp::Sin => "sin",
+                MathOp::Cos => "cos",
+                MathOp::Tan => "tan",
+                MathOp::Asin => "asin",
+                MathOp::Acos => "acos",
+                MathOp::Atan => "atan",
+                MathOp::Ln => "ln",
+                MathOp::Log => "log",
+                MathOp::Exp => "e ^",
+                MathOp::Pow10 => "10 ^",
+                MathOp::Round => "round",
+            }
+        }
+    }
+
+    pub fn num(value: f64) -> Expr {
+        Expr::Number {
+            pos: unplaced(),
+            value,
+        }
+    }
+
+    pub fn str(value: impl Into<String>) -> Expr {
+        Expr::String {
+            pos: unplaced(),
+            value: value.into(),
+        }
+    }
+
+    /// A color literal such as `#ff8800`.
+    pub fn color(value: impl Into<String>) -> Expr {
+        Expr::Color {
+            pos: unplaced(),
+            value: value.into(),
+        }
+    }
+
+    /// A variable, or a parameter inside a custom block.
+    pub fn var(name: impl Into<String>) -> Expr {
+        Expr::Var {
+            pos: unplaced(),
+            name: name.into(),
+        }
+    }
+
+    pub fn pick_random(start: Expr, end: Expr) -> Expr {
+        Expr::PickRandom {
+            pos: unplaced(),
+            start: boxed(start),
+            end: boxed(end),
+        }
+    }
+
+    pub fn item_of(list_name: impl Into<String>, index: Expr) -> Expr {
+        Expr::ListItem {
+            pos: unplaced(),
+            list_name: list_name.into(),
+            index: boxed(index),
+        }
+    }
+
+    pub fn length_of_list(list_name: impl Into<String>) -> Expr {
+        Expr::ListLength {
+            pos: unplaced(),
+            list_name: list_name.into(),
+        }
+    }
+
+    pub fn list_contains(list_name: impl Into<String>, item: Expr) -> Expr {
+        Expr::ListContains {
+            pos: unplaced(),
+            list_name: list_name.into(),
+            item: boxed(item),
+        }
+    }
+
+    /// The items of a list joined into one string.
+    pub fn list_contents(list_name: impl Into<String>) -> Expr {
+        Expr::ListContents {
+            pos: unplaced(),
+            list_name: list_name.into(),
+        }
+    }
+
+    pub fn item_num_of(list_name: impl Into<String>, item: Expr) -> Expr {
+        Expr::ItemNumOfList {
+            pos: unplaced(),
+            list_name: list_name.into(),
+            item: boxed(item),
+        }
+    }
+
+    pub fn key_pressed(key: Expr) -> Expr {
+        Expr::KeyPressed {
+            pos: unplaced(),
+            key: boxed(key),
+        }
+    }
+
+    /// `touching (target)`: a sprite name, `mouse-pointer`, or `edge`.
+    pub fn touching(target: Expr) -> Expr {
+        Expr::TouchingObject {
+            pos: unplaced(),
+            target: boxed(target),
+        }
+    }
+
+    pub fn touching_color(color: Expr) -> Expr {
+        Expr::TouchingColor {
+            pos: unplaced(),
+            color: boxed(color),
+        }
+    }
+
+    pub fn color_touching_color(color: Expr, color2: Expr) -> Expr {
+        Expr::ColorTouchingColor {
+            pos: unplaced(),
+            color: boxed(color),
+            color2: boxed(color2),
+        }
+    }
+
+    pub fn join(text1: Expr, text2: Expr) -> Expr {
+        Expr::StringJoin {
+            pos: unplaced(),
+            text1: boxed(text1),
+            text2: boxed(text2),
+        }
+    }
+
+    /// The length of a string.
+    pub fn length(text: Expr) -> Expr {
+        Expr::StringLength {
+            pos: unplaced(),
+            text: boxed(text),
+        }
+    }
+
+    pub fn letter_of(index: Expr, text: Expr) -> Expr {
+        Expr::LetterOf {
+            pos: unplaced(),
+            index: boxed(index),
+            text: boxed(text),
+        }
+    }
+
+    pub fn contains(text: Expr, substring: Expr) -> Expr {
+        Expr::StringContains {
+            pos: unplaced(),
+            text: boxed(text),
+            substring: boxed(substring),
+        }
+    }
+
+    pub fn split(text: Expr, sep: Expr) -> Expr {
+        Expr::StringSplit {
+            pos: unplaced(),
+            text: boxed(text),
+            sep: boxed(sep),
+        }
+    }
+
+    pub fn substring(text: Expr, start: Expr, end: Expr) -> Expr {
+        Expr::Substring {
+            pos: unplaced(),
+            text: boxed(text),
+            start: boxed(start),
+            end: boxed(end),
+        }
+    }
+
+    fn builtin(kind: &str) -> Expr {
+        Expr::BuiltinReporter {
+            pos: unplaced(),
+            kind: kind.to_string(),
+        }
+    }
+
+    pub fn answer() -> Expr {
+        builtin("answer")
+    }
+
+    pub fn mouse_x() -> Expr {
+        builtin("mouse_x")
+    }
+
+    pub fn mouse_y() -> Expr {
+        builtin("mouse_y")
+    }
+
+    pub fn mouse_down() -> Expr {
+        builtin("mouse_down")
+    }
+
+    pub fn timer() -> Expr {
+        builtin("timer")
+    }
+
+    pub fn loudness() -> Expr {
+        builtin("loudness")
+    }
+
+    pub fn x_position() -> Expr {
+        builtin("x_position")
+    }
+
+    pub fn y_position() -> Expr {
+        builtin("y_position")
+    }
+
+    pub fn direction() -> Expr {
+        builtin("direction")
+    }
+
+    pub fn size() -> Expr {
+        builtin("size")
+    }
+
+    pub fn username() -> Expr {
+        builtin("username")
+    }
+
+    pub fn days_since_2000() -> Expr {
+        builtin("days_since_2000")
+    }
+
+    pub fn tempo() -> Expr {
+        builtin("tempo")
+    }
+
+    fn looks_number_name(backdrop: bool, number_name: &str) -> Expr {
+        Expr::LooksNumberName {
+            pos: unplaced(),
+            backdrop,
+            number_name: number_name.to_string(),
+        }
+    }
+
+    pub fn costume_number() -> Expr {
+        looks_number_name(false, "number")
+    }
+
+    pub fn costume_name() -> Expr {
+        looks_number_name(false, "name")
+    }
+
+    pub fn backdrop_number() -> Expr {
+        looks_number_name(true, "number")
+    }
+
+    pub fn backdrop_name() -> Expr {
+        looks_number_name(true, "name")
+    }
+
+    pub fn math(op: MathOp, value: Expr) -> Expr {
+        Expr::MathFunc {
+            pos: unplaced(),
+            op: op.as_str().to_string(),
+            value: boxed(value),
+        }
+    }
+
+    fn unary(op: &str, operand: Expr) -> Expr {
+        Expr::Unary {
+            pos: unplaced(),
+            op: op.to_string(),
+            operand: boxed(operand),
+        }
+    }
+
+    /// `-(operand)`.
+    pub fn neg(operand: Expr) -> Expr {
+        unary("-", operand)
+    }
+
+    pub fn not(operand: Expr) -> Expr {
+        unary("not", operand)
+    }
+
+    fn binary(op: &str, left: Expr, right: Expr) -> Expr {
+        Expr::Binary {
+            pos: unplaced(),
+            op: op.to_string(),
+            left: boxed(left),
+            right: boxed(right),
+        }
+    }
+
+    pub fn add(left: Expr, right: Expr) -> Expr {
+        binary("+", left, right)
+    }
+
+    pub fn sub(left: Expr, right: Expr) -> Expr {
+        binary("-", left, right)
+    }
+
+    pub fn mul(left: Expr, right: Expr) -> Expr {
+        binary("*", left, right)
+    }
+
+    pub fn div(left: Expr, right: Expr) -> Expr {
+        binary("/", left, right)
+    }
+
+    /// `left % right` (Scratch's `mod`).
+    pub fn modulo(left: Expr, right: Expr) -> Expr {
+        binary("%", left, right)
+    }
+
+    pub fn eq(left: Expr, right: Expr) -> Expr {
+        binary("=", left, right)
+    }
+
+    pub fn ne(left: Expr, right: Expr) -> Expr {
+        binary("!=", left, right)
+    }
+
+    pub fn lt(left: Expr, right: Expr) -> Expr {
+        binary("<", left, right)
+    }
+
+    pub fn le(left: Expr, right: Expr) -> Expr {
+        binary("<=", left, right)
+    }
+
+    pub fn gt(left: Expr, right: Expr) -> Expr {
+        binary(">", left, right)
+    }
+
+    pub fn ge(left: Expr, right: Expr) -> Expr {
+        binary(">=", left, right)
+    }
+
+    pub fn and(left: Expr, right: Expr) -> Expr {
+        binary("and", left, right)
+    }
+
+    pub fn or(left: Expr, right: Expr) -> Expr {
+        binary("or", left, right)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_projects_compile_and_match_the_same_source() {
+        let built = ProjectBuilder::new()
+            .stage(|s| {
+                s.var_with_value("score", 0.0);
+            })
+            .sprite("Cat", |sp| {
+                sp.list_with_items("words", ["a", "b"]);
+                sp.define("hop", &["n"], |b| {
+                    b.change_y_by(expr::var("n"));
+                });
+                sp.on_flag(|b| {
+                    b.repeat(expr::num(3.0), |b| {
+                        b.if_else(
+                            expr::gt(expr::var("score"), expr::num(1.0)),
+                            |b| {
+                                b.call("hop", vec![expr::num(10.0)]);
+                            },
+                            |b| {
+                                b.say(expr::item_of("words", expr::num(1.0)));
+                            },
+                        );
+                        b.change_var("score", expr::num(1.0));
+                    });
+                });
+            })
+            .build()
+            .expect("built project should check");
+        let parsed = crate::parse_and_validate_source(
+            "stage\n  var score = 0\nend\nsprite Cat\n  list words = [\"a\", \"b\"]\n  define hop (n)\n    change y by (n)\n  end\n  when flag clicked\n    repeat (3)\n      if <(score) > (1)> then\n        hop (10)\n      else\n        say (item (1) of [words])\n      end\n      change [score] by (1)\n    end\n  end\nend\n",
+        )
+        .expect("source should check");
+
+        let project_json = |project: &Project| {
+            let options = CodegenOptions {
+                deterministic_ids: true,
+                ..CodegenOptions::default()
+            };
+            codegen::build_project_json(project, Path::new("."), options)
+                .expect("project should compile")
+                .0
+        };
+        assert_eq!(project_json(&built), project_json(&parsed));
+
+        let cat = &built.targets[1];
+        let mut lines = Vec::new();
+        crate::ast::walk_statements(&cat.scripts[0].body, &mut |statement| {
+            lines.push(statement.pos().line);
+            for expr in statement.children().0 {
+                assert_eq!(expr.pos(), statement.pos());
+            }
+        });
+        assert_eq!(lines, vec![8, 9, 10, 11, 12]);
+    }
+
+    #[test]
+    fn menu_options_match_the_same_source() {
+        let built = ProjectBuilder::new()
+            .sprite("Cat", |sp| {
+                sp.on_greater_than(Sensor::Timer, expr::num(5.0), |b| {
+                    b.set_rotation_style(RotationStyle::DontRotate);
+                    b.go_to_layer(Layer::Front);
+                    b.go_layers(LayerDirection::Backward, expr::num(1.0));
+                    b.set_graphic_effect_to(GraphicEffect::Ghost, expr::num(50.0));
+                    b.set_sound_effect_to(SoundEffect::Pan, expr::num(10.0));
+                    b.change_pen_color_param_by(PenColorParam::Saturation, expr::num(5.0));
+                    b.play_drum_for_beats(Drum::OpenHiHat, expr::num(0.25));
+                    b.set_instrument_to(Instrument::ElectricGuitar);
+                    b.stop(StopOption::OtherScriptsInSprite);
+                });
+            })
+            .build()
+            .expect("built project should check");
+        let parsed = crate::parse_and_validate_source(
+            "sprite Cat\n  when timer > (5)\n    set rotation style [\"don't rotate\"]\n    go to [front] layer\n    go [backward] (1) layers\n    set graphic effect [ghost] to (50)\n    set sound effect [pan] to (10)\n    change pen saturation by (5)\n    play drum [open hi-hat] for (0.25) beats\n    set instrument to [electric guitar]\n    stop [other scripts in sprite]\n  end\nend\n",
+        )
+        .expect("source should check");
+
+        let project_json = |project: &Project| {
+            let options = CodegenOptions {
+                deterministic_ids: true,
+                ..CodegenOptions::default()
+            };
+            codegen::build_project_json(project, Path::new("."), options)
+                .expect("project should compile")
+                .0
+        };
+        assert_eq!(project_json(&built), project_json(&parsed));
+    }
+
+    #[test]
+    fn build_with_report_returns_warnings() {
+        let (_, report) = ProjectBuilder::new()
+            .stage(|s| {
+                s.on_flag(|b| {
+                    b.say(expr::str("hi"));
+                });
+            })
+            .build_with_report()
+            .expect("stage say is only a warning");
+        assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+    }
+
+    #[test]
+    fn semantic_errors_are_reported() {
+        let err = ProjectBuilder::new()
+            .sprite("Cat", |sp| {
+                sp.on_flag(|b| {
+                    b.set_var("missing", expr::num(1.0));
+                });
+            })
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().contains("'missing'"), "{}", err);
+    }
+}
@@ -1,4 +1,6 @@
 use crate::obfuscator::config::{ObfuscationLevel, ObfuscationPreset};
+use crate::semantic::UnknownProcPolicy;
+use anyhow::{anyhow, bail, Result};
 use clap::{Args as ClapArgs, Parser, Subcommand};
 use std::path::PathBuf;
 
@@ -6,6 +8,8 @@ use std::path::PathBuf;
 #[command(
     name = "sbtext-rs",
     about = "Rust SBText compiler with SB3 decompile, inspect, and obfuscation support.",
+    after_help = "Exit codes: 0 success, 1 usage error, 2 lex/parse/import error, 3 semantic error, 4 codegen or asset error, 5 I/O error, 6 differences found (`diff`, `fmt --check`).",
+    arg_required_else_help = true,
     subcommand_negates_reqs = true,
     subcommand_precedence_over_arg = true
 )]
@@ -13,33 +17,174 @@ pub struct Args {
     #[command(subcommand)]
     pub command: Option<Command>,
 
+    /// The flat flags from before subcommands existed (`sbtext-rs INPUT OUTPUT
+    /// --decompile`). Hidden, and translated by [`Args::command`].
     #[command(flatten)]
-    pub compile: CompileArgs,
+    pub legacy: LegacyArgs,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Suppress progress output; errors and warnings are still printed."
+    )]
+    pub quiet: bool,
+
+    #[arg(
+        long,
+        global = true,
+        conflicts_with = "quiet",
+        help = "Print one plain line per phase instead of a redrawn progress bar."
+    )]
+    pub no_progress: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Print wall-clock time spent in each phase when done."
+    )]
+    pub timings: bool,
+}
+
+impl Args {
+    /// The subcommand to run, translating the legacy flat flags when no
+    /// subcommand was given.
+    pub fn command(&self) -> Result<Command> {
+        match &self.command {
+            Some(_) if self.legacy != LegacyArgs::default() => {
+                bail!("Options other than --quiet, --no-progress, and --timings go after the subcommand.")
+            }
+            Some(command) => Ok(command.clone()),
+            None => self.legacy.to_command(),
+        }
+    }
+
+    /// Whether the arguments use the legacy flat flags.
+    pub fn is_legacy(&self) -> bool {
+        self.command.is_none()
+    }
 }
 
-#[derive(Subcommand, Debug)]
+#[derive(Subcommand, Debug, Clone)]
 pub enum Command {
+    /// Compile SBText (or an .sbtc bundle) into .sb3 or .sprite3.
+    Compile(CompileArgs),
+    /// Decompile an .sb3 or .sb2 project into SBText.
+    Decompile(DecompileArgs),
+    /// Parse and check SBText without writing a project.
+    Check(CheckArgs),
+    /// Re-indent SBText source files.
+    Fmt(FmtArgs),
+    /// Obfuscate an .sb3 project.
     Obfuscate(ObfuscateArgs),
+    /// List the targets, variables, broadcasts, and custom blocks of an .sb3 project.
     Inspect(InspectArgs),
+    /// Compare two .sb3 projects, ignoring block ids, positions, and ordering.
+    Diff(DiffArgs),
 }
 
-#[derive(ClapArgs, Debug, Default)]
+#[derive(ClapArgs, Debug, Clone, Default)]
 pub struct CompileArgs {
-    #[arg(value_name = "INPUT")]
-    pub input: Option<PathBuf>,
+    #[arg(
+        value_name = "INPUT",
+        help = "Source or .sbtc file; '-' reads source from stdin."
+    )]
+    pub input: PathBuf,
 
-    #[arg(value_name = "OUTPUT")]
+    #[arg(
+        value_name = "OUTPUT",
+        help = ".sb3 or .sprite3 path [default: INPUT with an .sb3 extension]."
+    )]
     pub output: Option<PathBuf>,
 
+    #[arg(
+        long,
+        conflicts_with_all = ["output", "emit_depfile", "stats"],
+        help = "Check and run the --emit-* options without writing a project."
+    )]
+    pub no_output: bool,
+
     #[arg(long, help = "Disable automatic SVG normalization to 64x64.")]
     pub no_svg_scale: bool,
 
+    #[arg(long, help = "Treat PNG costumes as 2x bitmaps (bitmapResolution 2).")]
+    pub hd_bitmaps: bool,
+
+    #[arg(
+        long,
+        help = "Derive block and variable ids from content so unchanged scripts keep their ids."
+    )]
+    pub deterministic_ids: bool,
+
+    #[arg(long, help = "Write project.json without pretty-printing.")]
+    pub compact_json: bool,
+
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        value_parser = clap::value_parser!(i64).range(0..=9),
+        help = "Deflate level (0-9) for project.json and compressible assets."
+    )]
+    pub zip_level: Option<i64>,
+
+    #[arg(
+        short = 'I',
+        long = "include",
+        value_name = "DIR",
+        help = "Search DIR for imports after the importing file's own directory (repeatable). Directories listed in SBTEXT_PATH are searched after these."
+    )]
+    pub include_dirs: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Resolve imports and assets of source read from stdin ('-') against DIR instead of the current directory."
+    )]
+    pub source_dir: Option<PathBuf>,
+
     #[arg(
         long,
         help = "Write merged source after resolving imports to this path."
     )]
     pub emit_merged: Option<PathBuf>,
 
+    #[arg(
+        long,
+        help = "Write a JSON source map from merged-source lines to the original files to this path."
+    )]
+    pub emit_source_map: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Write a Make-style depfile ('OUTPUT: deps...') listing the source files and assets the build read."
+    )]
+    pub emit_depfile: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Write the generated project.json (pretty-printed, without packaging assets) to this path. Combine with --no-output to skip the project."
+    )]
+    pub emit_project_json: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Write the parsed and checked program as JSON to this path. Positions are lines of the merged source (see --emit-source-map)."
+    )]
+    pub emit_ast: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Print counts of sprites, scripts, blocks, assets, and the archive size after compiling."
+    )]
+    pub stats: bool,
+
+    #[arg(
+        long,
+        help = "After compiling, recompile whenever a source file or asset changes (requires the 'watch' feature)."
+    )]
+    pub watch: bool,
+
     #[arg(
         long,
         help = "Write merged/compiled SBText bundle (.sbtc) to this path."
@@ -61,24 +206,313 @@ pub struct CompileArgs {
 
     #[arg(
         long,
+        conflicts_with_all = ["allow_unknown_procedures", "allow_unknown_procedure", "emit_project_json", "stats", "compile_sbtc", "sprite_name"],
         help = "Use Python backend instead of native Rust backend (parity checks only)."
     )]
     pub python_backend: bool,
 
-    #[arg(long, help = "Decompile .sb3 input into .sbtext source.")]
-    pub decompile: bool,
+    #[arg(
+        long,
+        help = "Allow unresolved procedure calls. Unknown procedure calls compile as no-op wait(0) blocks."
+    )]
+    pub allow_unknown_procedures: bool,
+
+    #[arg(
+        long = "allow-unknown-procedure",
+        value_name = "NAME",
+        help = "Allow unresolved calls to procedure NAME only, compiling them as no-op wait(0) blocks (repeatable, case-insensitive)."
+    )]
+    pub allow_unknown_procedure: Vec<String>,
 
     #[arg(
         long,
-        help = "When used with --decompile, writes output as multiple sprite files plus main.sbtext (stage)."
+        help = "Report sprite-only blocks (motion, pen, show, ...) in the stage as warnings instead of errors."
+    )]
+    pub allow_sprite_blocks_on_stage: bool,
+}
+
+impl CompileArgs {
+    /// `--allow-unknown-procedures` allows every unknown call and wins over
+    /// any `--allow-unknown-procedure NAME`.
+    pub fn unknown_proc_policy(&self) -> UnknownProcPolicy {
+        if self.allow_unknown_procedures {
+            UnknownProcPolicy::All
+        } else if self.allow_unknown_procedure.is_empty() {
+            UnknownProcPolicy::None
+        } else {
+            UnknownProcPolicy::Names(self.allow_unknown_procedure.clone())
+        }
+    }
+}
+
+#[derive(ClapArgs, Debug, Clone, Default)]
+pub struct DecompileArgs {
+    #[arg(
+        value_name = "INPUT",
+        help = ".sb3, .sb2, or project.json file; '-' reads it from stdin."
     )]
+    pub input: PathBuf,
+
+    #[arg(
+        value_name = "OUTPUT",
+        help = ".sbtext file or directory; '-' writes the source to stdout."
+    )]
+    pub output: Option<PathBuf>,
+
+    #[arg(long, help = "Write multiple sprite files plus main.sbtext (stage).")]
     pub split_sprites: bool,
 
     #[arg(
         long,
-        help = "Allow unresolved procedure calls. Unknown procedure calls compile as no-op wait(0) blocks."
+        help = "Write assets under their md5 names instead of costume/sound names (byte-exact round-trips)."
+    )]
+    pub keep_asset_hashes: bool,
+
+    #[arg(
+        long,
+        help = "Keep the broadcasts, variables, and handler scripts generated for Target.proc(...) calls instead of folding them back into calls (for debugging)."
+    )]
+    pub keep_remote_calls: bool,
+}
+
+#[derive(ClapArgs, Debug, Clone, Default)]
+pub struct CheckArgs {
+    #[arg(
+        value_name = "INPUT",
+        help = "Source or .sbtc file; '-' reads source from stdin."
+    )]
+    pub input: PathBuf,
+
+    #[arg(
+        short = 'I',
+        long = "include",
+        value_name = "DIR",
+        help = "Search DIR for imports after the importing file's own directory (repeatable)."
+    )]
+    pub include_dirs: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Resolve imports of source read from stdin ('-') against DIR instead of the current directory."
+    )]
+    pub source_dir: Option<PathBuf>,
+
+    #[arg(long, help = "Treat INPUT as an .sbtc bundle.")]
+    pub compile_sbtc: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Write the parsed and checked program as JSON to this path."
+    )]
+    pub emit_ast: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Re-check whenever a source file changes (requires the 'watch' feature)."
+    )]
+    pub watch: bool,
+
+    #[arg(long, help = "Allow unresolved procedure calls.")]
+    pub allow_unknown_procedures: bool,
+
+    #[arg(
+        long = "allow-unknown-procedure",
+        value_name = "NAME",
+        help = "Allow unresolved calls to procedure NAME only (repeatable)."
+    )]
+    pub allow_unknown_procedure: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Report sprite-only blocks in the stage as warnings instead of errors."
+    )]
+    pub allow_sprite_blocks_on_stage: bool,
+}
+
+/// Checking is compiling with `--no-output`.
+impl From<CheckArgs> for CompileArgs {
+    fn from(args: CheckArgs) -> Self {
+        CompileArgs {
+            input: args.input,
+            no_output: true,
+            include_dirs: args.include_dirs,
+            source_dir: args.source_dir,
+            compile_sbtc: args.compile_sbtc,
+            emit_ast: args.emit_ast,
+            watch: args.watch,
+            allow_unknown_procedures: args.allow_unknown_procedures,
+            allow_unknown_procedure: args.allow_unknown_procedure,
+            allow_sprite_blocks_on_stage: args.allow_sprite_blocks_on_stage,
+            ..CompileArgs::default()
+        }
+    }
+}
+
+#[derive(ClapArgs, Debug, Clone)]
+pub struct FmtArgs {
+    #[arg(
+        value_name = "FILE",
+        required = true,
+        help = "Source files to format in place; '-' formats stdin to stdout."
+    )]
+    pub inputs: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Write nothing; list files that are not formatted and exit with code 6 if there are any."
     )]
+    pub check: bool,
+}
+
+/// The pre-subcommand command line: `INPUT [OUTPUT]` plus every compile and
+/// decompile flag, with `--decompile` choosing between them. Hidden from help.
+#[derive(ClapArgs, Debug, Clone, Default, PartialEq)]
+pub struct LegacyArgs {
+    #[arg(value_name = "INPUT", hide = true)]
+    pub input: Option<PathBuf>,
+    #[arg(value_name = "OUTPUT", hide = true)]
+    pub output: Option<PathBuf>,
+    #[arg(long, hide = true)]
+    pub no_svg_scale: bool,
+    #[arg(long, hide = true)]
+    pub hd_bitmaps: bool,
+    #[arg(long, hide = true)]
+    pub deterministic_ids: bool,
+    #[arg(long, hide = true)]
+    pub compact_json: bool,
+    #[arg(long, hide = true, value_parser = clap::value_parser!(i64).range(0..=9))]
+    pub zip_level: Option<i64>,
+    #[arg(short = 'I', long = "include", hide = true)]
+    pub include_dirs: Vec<PathBuf>,
+    #[arg(long, hide = true)]
+    pub source_dir: Option<PathBuf>,
+    #[arg(long, hide = true)]
+    pub emit_merged: Option<PathBuf>,
+    #[arg(long, hide = true)]
+    pub emit_source_map: Option<PathBuf>,
+    #[arg(long, hide = true)]
+    pub emit_depfile: Option<PathBuf>,
+    #[arg(long, hide = true)]
+    pub emit_project_json: Option<PathBuf>,
+    #[arg(long, hide = true)]
+    pub stats: bool,
+    #[arg(long, hide = true)]
+    pub watch: bool,
+    #[arg(long, hide = true)]
+    pub emit_sbtc: Option<PathBuf>,
+    #[arg(long, hide = true)]
+    pub compile_sbtc: bool,
+    #[arg(long, hide = true)]
+    pub sprite_name: Option<String>,
+    #[arg(long, hide = true)]
+    pub python_backend: bool,
+    #[arg(long, hide = true)]
+    pub decompile: bool,
+    #[arg(long, hide = true)]
+    pub split_sprites: bool,
+    #[arg(long, hide = true)]
+    pub keep_asset_hashes: bool,
+    #[arg(long, hide = true)]
+    pub keep_remote_calls: bool,
+    #[arg(long, hide = true)]
     pub allow_unknown_procedures: bool,
+    #[arg(long, hide = true)]
+    pub allow_sprite_blocks_on_stage: bool,
+}
+
+impl LegacyArgs {
+    /// `compile` or `decompile`, rejecting flags the chosen one does not take.
+    pub fn to_command(&self) -> Result<Command> {
+        let input = self.input.clone().ok_or_else(|| {
+            anyhow!("Missing INPUT. Use 'sbtext-rs compile <INPUT> [OUTPUT]' or 'sbtext-rs decompile <INPUT> [OUTPUT]'; see 'sbtext-rs --help'.")
+        })?;
+        if self.decompile {
+            let compile_only = [
+                ("--python-backend", self.python_backend),
+                ("--sprite-name", self.sprite_name.is_some()),
+                ("--emit-merged", self.emit_merged.is_some()),
+                ("--emit-source-map", self.emit_source_map.is_some()),
+                ("--emit-depfile", self.emit_depfile.is_some()),
+                ("--watch", self.watch),
+                ("--emit-project-json", self.emit_project_json.is_some()),
+                ("--stats", self.stats),
+                ("--emit-sbtc", self.emit_sbtc.is_some()),
+                ("--compile-sbtc", self.compile_sbtc),
+                ("--allow-unknown-procedures", self.allow_unknown_procedures),
+                ("--include", !self.include_dirs.is_empty()),
+                ("--source-dir", self.source_dir.is_some()),
+            ];
+            if let Some((flag, _)) = compile_only.iter().find(|(_, set)| *set) {
+                bail!("{} cannot be used with --decompile.", flag);
+            }
+            return Ok(Command::Decompile(DecompileArgs {
+                input,
+                output: self.output.clone(),
+                split_sprites: self.split_sprites,
+                keep_asset_hashes: self.keep_asset_hashes,
+                keep_remote_calls: self.keep_remote_calls,
+            }));
+        }
+
+        let decompile_only = [
+            ("--split-sprites", self.split_sprites),
+            ("--keep-asset-hashes", self.keep_asset_hashes),
+            ("--keep-remote-calls", self.keep_remote_calls),
+        ];
+        if let Some((flag, _)) = decompile_only.iter().find(|(_, set)| *set) {
+            bail!("{} requires --decompile.", flag);
+        }
+        if self.python_backend && self.allow_unknown_procedures {
+            bail!("--allow-unknown-procedures is only supported by the native Rust backend (remove --python-backend).");
+        }
+        if self.python_backend && self.emit_project_json.is_some() {
+            bail!("--emit-project-json is only supported by the native Rust backend (remove --python-backend).");
+        }
+        if self.python_backend && self.stats {
+            bail!(
+                "--stats is only supported by the native Rust backend (remove --python-backend)."
+            );
+        }
+        if self.python_backend && self.compile_sbtc {
+            bail!("--python-backend is not supported with .sbtc input.");
+        }
+        if self.emit_depfile.is_some() && self.output.is_none() {
+            bail!("--emit-depfile requires OUTPUT.");
+        }
+        if self.stats && self.output.is_none() {
+            bail!("--stats requires an .sb3 OUTPUT.");
+        }
+        // The flat form never picked an OUTPUT itself; without one it only
+        // checked the input.
+        Ok(Command::Compile(CompileArgs {
+            input,
+            output: self.output.clone(),
+            no_output: self.output.is_none(),
+            no_svg_scale: self.no_svg_scale,
+            hd_bitmaps: self.hd_bitmaps,
+            deterministic_ids: self.deterministic_ids,
+            compact_json: self.compact_json,
+            zip_level: self.zip_level,
+            include_dirs: self.include_dirs.clone(),
+            source_dir: self.source_dir.clone(),
+            emit_merged: self.emit_merged.clone(),
+            emit_source_map: self.emit_source_map.clone(),
+            emit_depfile: self.emit_depfile.clone(),
+            emit_project_json: self.emit_project_json.clone(),
+            emit_ast: None,
+            stats: self.stats,
+            watch: self.watch,
+            emit_sbtc: self.emit_sbtc.clone(),
+            compile_sbtc: self.compile_sbtc,
+            sprite_name: self.sprite_name.clone(),
+            python_backend: self.python_backend,
+            allow_unknown_procedures: self.allow_unknown_procedures,
+            allow_unknown_procedure: Vec::new(),
+            allow_sprite_blocks_on_stage: self.allow_sprite_blocks_on_stage,
+        }))
+    }
 }
 
 #[derive(ClapArgs, Debug, Clone)]
@@ -135,3 +569,12 @@ pub struct InspectArgs {
     #[arg(value_name = "INPUT")]
     pub input: PathBuf,
 }
+
+#[derive(ClapArgs, Debug, Clone)]
+pub struct DiffArgs {
+    #[arg(value_name = "OLD")]
+    pub old: PathBuf,
+
+    #[arg(value_name = "NEW")]
+    pub new: PathBuf,
+}
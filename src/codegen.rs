@@ -1,14 +1,21 @@
+use crate::assets::{AssetResolver, FsAssetResolver};
 use crate::ast::{
-    EventScript, EventType, Expr, InitialValue, ListDecl, Position, Procedure, Project, ReporterDecl,
-    Statement, Target, VariableDecl,
+    walk_exprs, walk_statements, EventScript, EventType, Expr, InitialValue, ListDecl, MonitorDecl,
+    ParamKind, Position, Procedure, Project, ReporterDecl, Statement, Target, TargetProperty,
+    VariableDecl,
 };
+use crate::music::{music_menu_number, MUSIC_DRUMS, MUSIC_INSTRUMENTS};
+use crate::rpc::rpc_names;
+use crate::sb3::validate_project_json;
+use crate::semantic::UnknownProcPolicy;
 use anyhow::{anyhow, bail, Result};
 use serde_json::{json, Map, Value};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::fs;
-use std::io::Cursor;
-use std::io::Write;
-use std::path::Path;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use xmltree::{Element, XMLNode};
 use zip::write::SimpleFileOptions;
 
@@ -17,24 +24,90 @@ const DEFAULT_STAGE_SVG: &str =
 const DEFAULT_SPRITE_SVG: &str =
     r##"<svg xmlns="http://www.w3.org/2000/svg" width="1" height="1" viewBox="0 0 1 1"></svg>"##;
 const DEFAULT_SVG_TARGET_SIZE: f64 = 64.0;
+const DEFAULT_MP3_RATE: u32 = 48000;
+const COMMENT_WIDTH: usize = 200;
+// Workspace layout: approximate rendered sizes of blocks, in editor pixels.
+const PROCEDURE_COLUMN_X: i32 = 30;
+const SCRIPT_COLUMN_X: i32 = 630;
+const RPC_COLUMN_X: i32 = 1230;
+const HAT_BLOCK_HEIGHT: i32 = 88;
+const STACK_BLOCK_HEIGHT: i32 = 48;
+const C_BLOCK_ARM_HEIGHT: i32 = 32;
+const SCRIPT_GAP: i32 = 40;
+const COMMENT_CHARS_PER_LINE: usize = 28;
+// Menu value kept under a computed broadcast message; Scratch ignores it at runtime.
+const DYNAMIC_BROADCAST_SHADOW: &str = "message1";
+/// Scratch marks cloud variables by this prefix on the variable name.
+pub const CLOUD_VARIABLE_PREFIX: &str = "☁ ";
 
 type CodegenProgressCallback<'a> = dyn FnMut(usize, usize, &str) + 'a;
 
-#[derive(Debug, Clone, Copy)]
+/// How costume and sound files are stored in the generated archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AssetCompression {
+    /// Store already-compressed formats (png, jpg, mp3) and deflate the rest.
+    #[default]
+    Auto,
+    /// Store every asset without compression.
+    Stored,
+    /// Deflate every asset.
+    Deflate,
+}
+
+/// Options for building a project. Not `Copy`, since
+/// [`UnknownProcPolicy::Names`] holds a list of names; clone it to reuse it.
+#[derive(Debug, Clone)]
 pub struct CodegenOptions {
     pub scale_svgs: bool,
+    /// Which calls to undeclared procedures become no-op `wait 0` blocks.
+    pub allow_unknown: UnknownProcPolicy,
+    /// `true` allows every unknown call, whatever `allow_unknown` says.
+    #[deprecated(note = "use `allow_unknown: UnknownProcPolicy::All` instead")]
     pub allow_unknown_procedures: bool,
+    /// Emit PNG costumes with `bitmapResolution: 2`, i.e. as @2x art.
+    pub hd_bitmaps: bool,
+    /// Derive block, variable, list, and broadcast ids from stable content
+    /// (target, script, and names) instead of a global counter, so unchanged
+    /// scripts keep identical ids across compiles.
+    pub deterministic_ids: bool,
+    /// Pretty-print `project.json`/`sprite.json`. Compact output is much
+    /// smaller for large projects and loads faster.
+    pub pretty_json: bool,
+    pub asset_compression: AssetCompression,
+    /// Deflate level (0-9) for compressed zip entries; `None` uses the zip
+    /// library default.
+    pub zip_level: Option<i64>,
+    /// Check the generated `project.json` for dangling block links and
+    /// unknown variable/list/broadcast ids before writing it.
+    pub validate_output: bool,
 }
 
 impl Default for CodegenOptions {
+    #[allow(deprecated)]
     fn default() -> Self {
         Self {
             scale_svgs: true,
+            allow_unknown: UnknownProcPolicy::None,
             allow_unknown_procedures: false,
+            hd_bitmaps: false,
+            deterministic_ids: false,
+            pretty_json: true,
+            asset_compression: AssetCompression::Auto,
+            zip_level: None,
+            validate_output: true,
         }
     }
 }
 
+impl CodegenOptions {
+    /// The policy in effect, taking the deprecated `allow_unknown_procedures`
+    /// flag into account.
+    #[allow(deprecated)]
+    pub fn unknown_proc_policy(&self) -> &UnknownProcPolicy {
+        UnknownProcPolicy::or_all(&self.allow_unknown, self.allow_unknown_procedures)
+    }
+}
+
 pub fn write_sb3(
     project: &Project,
     source_dir: &Path,
@@ -87,33 +160,238 @@ pub fn build_sb3_bytes_with_progress<F>(
     options: CodegenOptions,
     progress: Option<&mut F>,
 ) -> Result<Vec<u8>>
+where
+    F: FnMut(usize, usize, &str),
+{
+    build_sb3_bytes_with_assets_and_progress(
+        project,
+        &FsAssetResolver::new(source_dir),
+        options,
+        progress,
+    )
+}
+
+pub fn build_sb3_bytes_with_assets(
+    project: &Project,
+    resolver: &dyn AssetResolver,
+    options: CodegenOptions,
+) -> Result<Vec<u8>> {
+    build_sb3_bytes_with_assets_and_progress(
+        project,
+        resolver,
+        options,
+        Option::<&mut fn(usize, usize, &str)>::None,
+    )
+}
+
+pub fn build_sb3_bytes_with_assets_and_progress<F>(
+    project: &Project,
+    resolver: &dyn AssetResolver,
+    options: CodegenOptions,
+    progress: Option<&mut F>,
+) -> Result<Vec<u8>>
 where
     F: FnMut(usize, usize, &str),
 {
     let mut progress = progress.map(|cb| cb as &mut CodegenProgressCallback<'_>);
-    let mut builder = ProjectBuilder::new(project, source_dir, options);
-    let (project_json, assets) = builder.build_with_progress(&mut progress)?;
+    let (bytes, _) = build_sb3_bytes_and_stats(project, resolver, options, &mut progress)?;
+    Ok(bytes)
+}
+
+/// Like [`build_sb3_bytes_with_assets`], also returning [`CodegenStats`] for the
+/// project, including the archive size.
+pub fn build_sb3_bytes_with_stats(
+    project: &Project,
+    resolver: &dyn AssetResolver,
+    options: CodegenOptions,
+) -> Result<(Vec<u8>, CodegenStats)> {
+    build_sb3_bytes_and_stats(project, resolver, options, &mut None)
+}
+
+fn build_sb3_bytes_and_stats(
+    project: &Project,
+    resolver: &dyn AssetResolver,
+    options: CodegenOptions,
+    progress: &mut Option<&mut CodegenProgressCallback<'_>>,
+) -> Result<(Vec<u8>, CodegenStats)> {
+    let mut builder = ProjectBuilder::new(project, resolver, options.clone());
+    let (project_json, assets, mut stats) = builder.build_with_progress(progress)?;
+    let entries = assets
+        .into_iter()
+        .map(|(name, bytes)| (name, AssetSource::Bytes(bytes)))
+        .collect();
     let mut buffer = Cursor::new(Vec::<u8>::new());
-    let mut zip = zip::ZipWriter::new(&mut buffer);
-    let opts = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
-    report_progress(&mut progress, 1, 1, "Writing project.json");
-    zip.start_file("project.json", opts)?;
-    let project_bytes = serde_json::to_vec_pretty(&project_json)?;
+    write_sb3_archive(&mut buffer, &project_json, entries, &options, progress)?;
+    let bytes = buffer.into_inner();
+    stats.archive_bytes = bytes.len() as u64;
+    Ok((bytes, stats))
+}
+
+/// Summary counts for a compiled project, as printed by `--stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CodegenStats {
+    pub sprites: usize,
+    /// Top-level scripts, not counting procedure definitions.
+    pub scripts: usize,
+    pub procedures: usize,
+    /// Entries in every target's `blocks` map, including shadows and menus.
+    pub blocks: usize,
+    pub broadcasts: usize,
+    pub variables: usize,
+    pub lists: usize,
+    /// Distinct asset files in the archive.
+    pub assets: usize,
+    pub asset_bytes: u64,
+    /// Size of the written `.sb3`; 0 when no archive was written.
+    pub archive_bytes: u64,
+}
+
+impl CodegenStats {
+    /// One `label  value` row per count, aligned for printing.
+    pub fn render_table(&self) -> String {
+        let rows = [
+            ("Sprites", self.sprites.to_string()),
+            ("Scripts", self.scripts.to_string()),
+            ("Procedures", self.procedures.to_string()),
+            ("Blocks", self.blocks.to_string()),
+            ("Broadcasts", self.broadcasts.to_string()),
+            ("Variables", self.variables.to_string()),
+            ("Lists", self.lists.to_string()),
+            (
+                "Assets",
+                format!("{} ({})", self.assets, format_size(self.asset_bytes)),
+            ),
+            ("Archive", format_size(self.archive_bytes)),
+        ];
+        rows.iter()
+            .map(|(label, value)| format!("{:<12}{}\n", label, value))
+            .collect()
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+/// An asset that `project.json` refers to, as returned by [`build_project_json`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetRef {
+    /// Archive entry name, `<md5>.<ext>`.
+    pub md5ext: String,
+    /// File the bytes are copied from, or `None` when codegen produces the
+    /// bytes itself (normalized SVGs, default costumes).
+    pub path: Option<PathBuf>,
+}
+
+/// Builds `project.json` without packaging an archive. Every asset is still
+/// resolved and hashed, so missing or invalid files fail here as they would
+/// when writing the `.sb3`; PNG costumes and sounds are hashed from disk
+/// rather than held in memory. Assets are sorted by `md5ext`.
+pub fn build_project_json(
+    project: &Project,
+    source_dir: &Path,
+    options: CodegenOptions,
+) -> Result<(Value, Vec<AssetRef>)> {
+    let resolver = FsAssetResolver::new(source_dir);
+    let mut builder = ProjectBuilder::new(project, &resolver, options);
+    builder.stream_assets = true;
+    let (project_json, assets, _) = builder.build_with_progress(&mut None)?;
+    let mut refs = assets
+        .into_keys()
+        .map(|md5ext| AssetRef { md5ext, path: None })
+        .chain(
+            std::mem::take(&mut builder.asset_files)
+                .into_iter()
+                .map(|(md5ext, path)| AssetRef {
+                    md5ext,
+                    path: Some(path),
+                }),
+        )
+        .collect::<Vec<_>>();
+    refs.sort_by(|left, right| left.md5ext.cmp(&right.md5ext));
+    Ok((project_json, refs))
+}
+
+/// Compiles straight into `output_path`, copying file-backed assets (PNG
+/// costumes, sounds) from disk in chunks. Peak memory stays close to the
+/// size of `project.json` instead of the whole archive.
+pub fn write_sb3_streaming<F>(
+    project: &Project,
+    source_dir: &Path,
+    output_path: &Path,
+    options: CodegenOptions,
+    progress: Option<&mut F>,
+) -> Result<CodegenStats>
+where
+    F: FnMut(usize, usize, &str),
+{
+    let mut progress = progress.map(|cb| cb as &mut CodegenProgressCallback<'_>);
+    let resolver = FsAssetResolver::new(source_dir);
+    let mut builder = ProjectBuilder::new(project, &resolver, options.clone());
+    builder.stream_assets = true;
+    let (project_json, assets, mut stats) = builder.build_with_progress(&mut progress)?;
+    let entries = assets
+        .into_iter()
+        .map(|(name, bytes)| (name, AssetSource::Bytes(bytes)))
+        .chain(
+            std::mem::take(&mut builder.asset_files)
+                .into_iter()
+                .map(|(name, path)| (name, AssetSource::File(path))),
+        )
+        .collect();
+
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(output_path)?;
+    let written = write_sb3_archive(&mut file, &project_json, entries, &options, &mut progress);
+    if written.is_err() {
+        drop(file);
+        let _ = fs::remove_file(output_path);
+    }
+    written?;
+    stats.archive_bytes = fs::metadata(output_path)?.len();
+    Ok(stats)
+}
+
+fn write_sb3_archive<W: Write + Seek>(
+    writer: W,
+    project_json: &Value,
+    entries: Vec<(String, AssetSource)>,
+    options: &CodegenOptions,
+    progress: &mut Option<&mut CodegenProgressCallback<'_>>,
+) -> Result<()> {
+    let mut zip = zip::ZipWriter::new(writer);
+    report_progress(progress, 1, 1, "Writing project.json");
+    zip.start_file("project.json", deflated_zip_options(options))?;
+    let project_bytes = json_bytes(project_json, options.pretty_json)?;
     zip.write_all(&project_bytes)?;
 
-    let mut assets = assets.into_iter().collect::<Vec<_>>();
-    assets.sort_by(|(left_name, _), (right_name, _)| left_name.cmp(right_name));
-    let asset_total = assets.len().max(1);
-    if assets.is_empty() {
-        report_progress(&mut progress, 1, 1, "Packaging assets");
+    let mut entries = entries;
+    entries.sort_by(|(left_name, _), (right_name, _)| left_name.cmp(right_name));
+    let asset_total = entries.len().max(1);
+    if entries.is_empty() {
+        report_progress(progress, 1, 1, "Packaging assets");
     }
-    for (index, (name, bytes)) in assets.into_iter().enumerate() {
+    for (index, (name, source)) in entries.into_iter().enumerate() {
+        let opts = asset_zip_options(options, &name);
         zip.start_file(name, opts)?;
-        zip.write_all(&bytes)?;
-        report_progress(&mut progress, index + 1, asset_total, "Packaging assets");
+        match source {
+            AssetSource::Bytes(bytes) => zip.write_all(&bytes)?,
+            AssetSource::File(path) => {
+                io::copy(&mut File::open(path)?, &mut zip)?;
+            }
+        }
+        report_progress(progress, index + 1, asset_total, "Packaging assets");
     }
     zip.finish()?;
-    Ok(buffer.into_inner())
+    Ok(())
 }
 
 pub fn write_sprite3(
@@ -179,8 +457,9 @@ where
     F: FnMut(usize, usize, &str),
 {
     let mut progress = progress.map(|cb| cb as &mut CodegenProgressCallback<'_>);
-    let mut builder = ProjectBuilder::new(project, source_dir, options);
-    let (project_json, assets) = builder.build_with_progress(&mut progress)?;
+    let resolver = FsAssetResolver::new(source_dir);
+    let mut builder = ProjectBuilder::new(project, &resolver, options.clone());
+    let (project_json, assets, _) = builder.build_with_progress(&mut progress)?;
 
     report_progress(&mut progress, 1, 1, "Selecting sprite target");
     let sprite_json = select_sprite_target_json(&project_json, sprite_name)?;
@@ -191,11 +470,10 @@ where
 
     let mut buffer = Cursor::new(Vec::<u8>::new());
     let mut zip = zip::ZipWriter::new(&mut buffer);
-    let opts = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
 
     report_progress(&mut progress, 1, 1, "Writing sprite.json");
-    zip.start_file("sprite.json", opts)?;
-    let sprite_bytes = serde_json::to_vec_pretty(&sprite_json)?;
+    zip.start_file("sprite.json", deflated_zip_options(&options))?;
+    let sprite_bytes = json_bytes(&sprite_json, options.pretty_json)?;
     zip.write_all(&sprite_bytes)?;
 
     let asset_total = asset_names.len().max(1);
@@ -209,6 +487,7 @@ where
                 asset_name
             )
         })?;
+        let opts = asset_zip_options(&options, &asset_name);
         zip.start_file(asset_name, opts)?;
         zip.write_all(bytes)?;
         report_progress(&mut progress, index + 1, asset_total, "Packaging assets");
@@ -294,14 +573,234 @@ fn report_progress(
     }
 }
 
+/// `<(busy) = value>` for a remote procedure's busy flag.
+fn rpc_busy_condition(spec: &RemoteCallSpec, pos: Position, value: f64) -> Expr {
+    Expr::Binary {
+        pos,
+        op: "=".to_string(),
+        left: Box::new(Expr::Var {
+            pos,
+            name: spec.busy_var_name.clone(),
+        }),
+        right: Box::new(Expr::Number { pos, value }),
+    }
+}
+
+/// Handler for a remote procedure: marks it busy, then pops one argument
+/// record per iteration into the argument variables and calls the procedure
+/// until the queue is empty.
+fn rpc_handler_body(spec: &RemoteCallSpec, pos: Position) -> Vec<Statement> {
+    let set_busy = |value: f64| Statement::SetVar {
+        pos,
+        var_name: spec.busy_var_name.clone(),
+        value: Expr::Number { pos, value },
+    };
+    let pop_front = |list_name: &String| Statement::DeleteOfList {
+        pos,
+        list_name: list_name.clone(),
+        index: Expr::Number { pos, value: 1.0 },
+    };
+    let mut body = Vec::new();
+    for (var_name, queue) in spec.arg_var_names.iter().zip(&spec.arg_queue_names) {
+        body.push(Statement::SetVar {
+            pos,
+            var_name: var_name.clone(),
+            value: Expr::ListItem {
+                pos,
+                list_name: queue.clone(),
+                index: Box::new(Expr::Number { pos, value: 1.0 }),
+            },
+        });
+        body.push(pop_front(queue));
+    }
+    body.push(pop_front(&spec.calls_list_name));
+    body.push(Statement::ProcedureCall {
+        pos,
+        name: spec.procedure_name.clone(),
+        args: spec
+            .arg_var_names
+            .iter()
+            .map(|name| Expr::Var {
+                pos,
+                name: name.clone(),
+            })
+            .collect(),
+    });
+    vec![
+        set_busy(1.0),
+        Statement::RepeatUntil {
+            pos,
+            condition: Expr::Binary {
+                pos,
+                op: "=".to_string(),
+                left: Box::new(Expr::ListLength {
+                    pos,
+                    list_name: spec.calls_list_name.clone(),
+                }),
+                right: Box::new(Expr::Number { pos, value: 0.0 }),
+            },
+            body,
+        },
+        set_busy(0.0),
+    ]
+}
+
+/// Assigns `layerOrder` to each target: the stage is 0, sprites with a `layer`
+/// declaration take that slot, and the rest fill the free slots in declaration
+/// order. Pins past the sprite count keep their relative order at the top.
+fn layer_orders(targets: &[Target]) -> Vec<i32> {
+    let pinned_layer = |target: &Target| {
+        target
+            .properties
+            .iter()
+            .rev()
+            .find_map(|decl| match decl.property {
+                TargetProperty::Layer(layer) => Some(layer),
+                _ => None,
+            })
+    };
+    let mut pinned = Vec::new();
+    let mut unpinned = VecDeque::new();
+    for (index, target) in targets.iter().enumerate() {
+        if target.is_stage {
+            continue;
+        }
+        match pinned_layer(target) {
+            Some(layer) => pinned.push((layer, index)),
+            None => unpinned.push_back(index),
+        }
+    }
+    pinned.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    let mut pinned = pinned.into_iter().peekable();
+
+    let mut layers = vec![0; targets.len()];
+    let mut slot = 1;
+    loop {
+        let next = match pinned.peek() {
+            Some(&(layer, index)) if layer <= slot as f64 || unpinned.is_empty() => {
+                pinned.next();
+                index
+            }
+            _ => match unpinned.pop_front() {
+                Some(index) => index,
+                None => break,
+            },
+        };
+        layers[next] = slot;
+        slot += 1;
+    }
+    layers
+}
+
+/// Approximate rendered height of the stack starting at `first`, including
+/// the mouths of C blocks and their nested substacks.
+fn stack_height(blocks: &Map<String, Value>, first: Option<&str>) -> i32 {
+    let mut height = 0;
+    let mut current = first.map(str::to_string);
+    while let Some(id) = current {
+        let Some(block) = blocks.get(&id) else {
+            break;
+        };
+        height += STACK_BLOCK_HEIGHT;
+        for input in ["SUBSTACK", "SUBSTACK2"] {
+            let Some(slot) = block.get("inputs").and_then(|inputs| inputs.get(input)) else {
+                continue;
+            };
+            let substack = slot.get(1).and_then(Value::as_str);
+            height += C_BLOCK_ARM_HEIGHT + stack_height(blocks, substack).max(C_BLOCK_ARM_HEIGHT);
+        }
+        current = block
+            .get("next")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+    }
+    height
+}
+
+fn asset_extension(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .and_then(|x| x.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+/// Resolves a `start costume` name to its index, preferring an exact match.
+fn costume_index(costumes: &[Value], name: &str) -> Result<usize> {
+    let name_of = |costume: &Value| costume["name"].as_str().unwrap_or_default().to_string();
+    costumes
+        .iter()
+        .position(|costume| name_of(costume) == name)
+        .or_else(|| {
+            costumes
+                .iter()
+                .position(|costume| name_of(costume).to_lowercase() == name.to_lowercase())
+        })
+        .ok_or_else(|| anyhow!("Unknown start costume '{}'.", name))
+}
+
+fn asset_stem(path: &str, fallback: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(fallback)
+        .to_string()
+}
+
+fn deflated_zip_options(options: &CodegenOptions) -> SimpleFileOptions {
+    SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .compression_level(options.zip_level)
+}
+
+fn asset_zip_options(options: &CodegenOptions, asset_name: &str) -> SimpleFileOptions {
+    let store = match options.asset_compression {
+        AssetCompression::Stored => true,
+        AssetCompression::Deflate => false,
+        AssetCompression::Auto => {
+            let ext = Path::new(asset_name)
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or("")
+                .to_ascii_lowercase();
+            matches!(ext.as_str(), "png" | "jpg" | "jpeg" | "mp3")
+        }
+    };
+    if store {
+        SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored)
+    } else {
+        deflated_zip_options(options)
+    }
+}
+
+fn json_bytes(value: &Value, pretty: bool) -> Result<Vec<u8>> {
+    if pretty {
+        Ok(serde_json::to_vec_pretty(value)?)
+    } else {
+        Ok(serde_json::to_vec(value)?)
+    }
+}
+
+fn hashed_id(prefix: &str, key: &str) -> String {
+    let digest = format!("{:x}", md5::compute(format!("{}\0{}", prefix, key)));
+    format!("{}_{}", prefix, &digest[..16])
+}
+
 #[derive(Clone, Debug)]
 struct ProcedureSignature {
     params: Vec<String>,
+    param_kinds: Vec<ParamKind>,
     arg_ids: Vec<String>,
     proccode: String,
     warp: bool,
 }
 
+/// Generated plumbing for one `Target.proc(...)` callee. Callers append their
+/// arguments to per-argument queue lists (plus a marker on `calls_list_name`)
+/// and the handler drains the queues, so concurrent callers never overwrite
+/// each other's arguments. `busy_var_name` is set while the handler drains so
+/// callers wait on it instead of re-broadcasting, which would restart the
+/// handler mid-call.
 #[derive(Debug, Clone)]
 struct RemoteCallSpec {
     callee_target_lower: String,
@@ -309,6 +808,9 @@ struct RemoteCallSpec {
     procedure_name: String,
     message: String,
     arg_var_names: Vec<String>,
+    arg_queue_names: Vec<String>,
+    calls_list_name: String,
+    busy_var_name: String,
 }
 
 #[derive(Debug, Clone)]
@@ -317,11 +819,96 @@ struct EmittedStatement {
     last: String,
 }
 
+/// Asset contents, either held in memory or left on disk for streaming.
+enum AssetSource {
+    Bytes(Vec<u8>),
+    File(PathBuf),
+}
+
+impl AssetSource {
+    fn md5_hex(&self) -> Result<String> {
+        match self {
+            AssetSource::Bytes(bytes) => Ok(format!("{:x}", md5::compute(bytes))),
+            AssetSource::File(path) => {
+                let mut file = File::open(path)?;
+                let mut context = md5::Context::new();
+                let mut chunk = vec![0u8; 64 * 1024];
+                loop {
+                    let read = file.read(&mut chunk)?;
+                    if read == 0 {
+                        break;
+                    }
+                    context.consume(&chunk[..read]);
+                }
+                Ok(format!("{:x}", context.compute()))
+            }
+        }
+    }
+
+    fn png_size(&self) -> Result<Option<(u32, u32)>> {
+        match self {
+            AssetSource::Bytes(bytes) => Ok(read_png_size(bytes)),
+            AssetSource::File(path) => {
+                let mut header = Vec::new();
+                File::open(path)?.take(24).read_to_end(&mut header)?;
+                Ok(read_png_size(&header))
+            }
+        }
+    }
+
+    fn wav_info(&self) -> Result<Option<WavInfo>> {
+        self.inspect(read_wav_info_with)
+    }
+
+    fn mp3_info(&self) -> Result<Option<WavInfo>> {
+        self.inspect(read_mp3_info_with)
+    }
+
+    /// Runs a header parser that reads through `read_at(offset, count)`, so
+    /// files on disk are inspected without loading them whole.
+    fn inspect<T>(
+        &self,
+        parse: impl FnOnce(usize, &mut dyn FnMut(usize, usize) -> Option<Vec<u8>>) -> Option<T>,
+    ) -> Result<Option<T>> {
+        match self {
+            AssetSource::Bytes(bytes) => Ok(parse(bytes.len(), &mut |at, count| {
+                bytes.get(at..at.checked_add(count)?).map(<[u8]>::to_vec)
+            })),
+            AssetSource::File(path) => {
+                let mut file = File::open(path)?;
+                let len = usize::try_from(file.metadata()?.len())?;
+                Ok(parse(len, &mut |at, count| {
+                    file.seek(SeekFrom::Start(at as u64)).ok()?;
+                    let mut buf = vec![0u8; count];
+                    file.read_exact(&mut buf).ok()?;
+                    Some(buf)
+                }))
+            }
+        }
+    }
+}
+
+/// A costume file after normalization and hashing, shared by every costume
+/// that references the same path.
+#[derive(Clone, Debug)]
+struct PreparedCostume {
+    digest: String,
+    md5ext: String,
+    rotation_center_x: f64,
+    rotation_center_y: f64,
+}
+
+/// One entry of the costume cache: empty until the first builder to reach the
+/// file has prepared it. Builders hold the slot's lock while preparing.
+type CostumeSlot = Arc<Mutex<Option<Option<PreparedCostume>>>>;
+
 struct ProjectBuilder<'a> {
     project: &'a Project,
-    source_dir: &'a Path,
+    resolver: &'a dyn AssetResolver,
     options: CodegenOptions,
     id_counter: usize,
+    id_scope: String,
+    scope_counter: usize,
     assets: HashMap<String, Vec<u8>>,
     broadcast_ids: HashMap<String, String>,
     remote_calls: Vec<RemoteCallSpec>,
@@ -331,15 +918,37 @@ struct ProjectBuilder<'a> {
     global_list_names: HashMap<String, String>,
     current_reporters: HashMap<String, ReporterDecl>,
     current_signatures: HashMap<String, ProcedureSignature>,
+    current_boolean_params: HashSet<String>,
+    current_comments: Map<String, Value>,
+    monitors: Vec<Value>,
+    cloud_var_names: HashMap<String, String>,
+    /// Prepared costumes keyed by resolved asset path. `scale_svgs` is fixed
+    /// for the builder, so the path alone identifies the prepared bytes.
+    /// Shared with parallel target workers; the asset bytes themselves live
+    /// in whichever builder prepared them until workers are merged.
+    costume_cache: Arc<Mutex<HashMap<String, CostumeSlot>>>,
+    /// Number of costume files read through the resolver.
+    costume_reads: usize,
+    /// Leave file-backed PNG costumes and sounds on disk (see `asset_files`).
+    stream_assets: bool,
+    /// Assets to copy from disk when writing, keyed by md5ext.
+    asset_files: HashMap<String, PathBuf>,
+    /// Prepended to sequential ids so per-target workers never collide.
+    id_prefix: String,
+    /// Emit targets on worker threads.
+    #[cfg(feature = "parallel")]
+    parallel: bool,
 }
 
 impl<'a> ProjectBuilder<'a> {
-    fn new(project: &'a Project, source_dir: &'a Path, options: CodegenOptions) -> Self {
+    fn new(project: &'a Project, resolver: &'a dyn AssetResolver, options: CodegenOptions) -> Self {
         Self {
             project,
-            source_dir,
+            resolver,
             options,
             id_counter: 0,
+            id_scope: String::new(),
+            scope_counter: 0,
             assets: HashMap::new(),
             broadcast_ids: HashMap::new(),
             remote_calls: Vec::new(),
@@ -349,13 +958,24 @@ impl<'a> ProjectBuilder<'a> {
             global_list_names: HashMap::new(),
             current_reporters: HashMap::new(),
             current_signatures: HashMap::new(),
+            current_boolean_params: HashSet::new(),
+            current_comments: Map::new(),
+            monitors: Vec::new(),
+            cloud_var_names: HashMap::new(),
+            costume_cache: Arc::new(Mutex::new(HashMap::new())),
+            costume_reads: 0,
+            stream_assets: false,
+            asset_files: HashMap::new(),
+            id_prefix: String::new(),
+            #[cfg(feature = "parallel")]
+            parallel: true,
         }
     }
 
     fn build_with_progress(
         &mut self,
         progress: &mut Option<&mut CodegenProgressCallback<'_>>,
-    ) -> Result<(Value, HashMap<String, Vec<u8>>)> {
+    ) -> Result<(Value, HashMap<String, Vec<u8>>, CodegenStats)> {
         self.broadcast_ids = self.collect_broadcast_ids();
         self.remote_calls = self.collect_remote_call_specs()?;
         self.register_remote_call_broadcasts();
@@ -368,32 +988,23 @@ impl<'a> ProjectBuilder<'a> {
         }
         self.register_declared_stage_globals(&ordered_targets);
 
-        let mut targets_json = Vec::new();
-        let mut sprite_layer = 1;
+        let layers = layer_orders(&ordered_targets);
         if ordered_targets.is_empty() {
             report_progress(progress, 1, 1, "Emitting targets");
         }
-        for (index, target) in ordered_targets.iter().enumerate() {
-            let layer = if target.is_stage {
-                0
-            } else {
-                let out = sprite_layer;
-                sprite_layer += 1;
-                out
-            };
-            targets_json.push(self.build_target_json(target, layer)?);
-            report_progress(
-                progress,
-                index + 1,
-                ordered_targets.len().max(1),
-                "Emitting targets",
-            );
-        }
+        #[cfg(feature = "parallel")]
+        let targets_json = if self.parallel {
+            self.emit_targets_parallel(&ordered_targets, &layers, progress)?
+        } else {
+            self.emit_targets_serial(&ordered_targets, &layers, progress)?
+        };
+        #[cfg(not(feature = "parallel"))]
+        let targets_json = self.emit_targets_serial(&ordered_targets, &layers, progress)?;
 
         let extensions = self.collect_extensions();
         let project_json = json!({
             "targets": targets_json,
-            "monitors": [],
+            "monitors": std::mem::take(&mut self.monitors),
             "extensions": extensions,
             "meta": {
                 "semver": "3.0.0",
@@ -401,7 +1012,268 @@ impl<'a> ProjectBuilder<'a> {
                 "agent": "SBText Rust Compiler"
             }
         });
-        Ok((project_json, std::mem::take(&mut self.assets)))
+        if self.options.validate_output {
+            let issues = validate_project_json(&project_json);
+            if !issues.is_empty() {
+                let listing = issues
+                    .iter()
+                    .map(|issue| format!("  - {}", issue))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                bail!(
+                    "Generated project.json failed validation ({} issue(s)):\n{}",
+                    issues.len(),
+                    listing
+                );
+            }
+        }
+        let stats = self.collect_stats(&project_json);
+        Ok((project_json, std::mem::take(&mut self.assets), stats))
+    }
+
+    /// Counts what `project_json` and the prepared assets contain. The archive
+    /// size is left at 0 for the writer to fill in.
+    fn collect_stats(&self, project_json: &Value) -> CodegenStats {
+        let mut stats = CodegenStats::default();
+        let targets = project_json["targets"].as_array().into_iter().flatten();
+        for target in targets {
+            if target["isStage"] != true {
+                stats.sprites += 1;
+            }
+            let object_len = |key: &str| target[key].as_object().map_or(0, Map::len);
+            stats.variables += object_len("variables");
+            stats.lists += object_len("lists");
+            stats.broadcasts += object_len("broadcasts");
+            let blocks = target["blocks"]
+                .as_object()
+                .into_iter()
+                .flat_map(Map::values);
+            for block in blocks {
+                stats.blocks += 1;
+                if block["topLevel"] != true {
+                    continue;
+                }
+                if block["opcode"] == "procedures_definition" {
+                    stats.procedures += 1;
+                } else {
+                    stats.scripts += 1;
+                }
+            }
+        }
+        stats.assets = self.assets.len() + self.asset_files.len();
+        stats.asset_bytes = self
+            .assets
+            .values()
+            .map(|bytes| bytes.len() as u64)
+            .chain(
+                self.asset_files
+                    .values()
+                    .map(|path| fs::metadata(path).map_or(0, |meta| meta.len())),
+            )
+            .sum();
+        stats
+    }
+
+    fn emit_targets_serial(
+        &mut self,
+        targets: &[Target],
+        layers: &[i32],
+        progress: &mut Option<&mut CodegenProgressCallback<'_>>,
+    ) -> Result<Vec<Value>> {
+        let mut targets_json = Vec::new();
+        for (index, target) in targets.iter().enumerate() {
+            targets_json.push(self.build_target_json(target, layers[index])?);
+            report_progress(progress, index + 1, targets.len(), "Emitting targets");
+        }
+        Ok(targets_json)
+    }
+
+    /// Emits each target on a worker thread with its own forked builder, then
+    /// merges assets, monitors, and broadcasts back in target order. Progress
+    /// is reported from this thread as workers finish.
+    #[cfg(feature = "parallel")]
+    fn emit_targets_parallel(
+        &mut self,
+        targets: &[Target],
+        layers: &[i32],
+        progress: &mut Option<&mut CodegenProgressCallback<'_>>,
+    ) -> Result<Vec<Value>> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::mpsc;
+
+        let workers = std::thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(targets.len())
+            .max(1);
+        let next = AtomicUsize::new(0);
+        let (sender, receiver) = mpsc::channel();
+        let this = &*self;
+        let results = std::thread::scope(|scope| {
+            for _ in 0..workers {
+                let sender = sender.clone();
+                let next = &next;
+                scope.spawn(move || loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= targets.len() {
+                        break;
+                    }
+                    let mut worker = this.fork_for_target(index);
+                    let built = worker
+                        .build_target_json(&targets[index], layers[index])
+                        .map(|json| (json, worker));
+                    if sender.send((index, built)).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(sender);
+            let mut results = (0..targets.len()).map(|_| None).collect::<Vec<_>>();
+            for (done, (index, built)) in receiver.iter().enumerate() {
+                results[index] = Some(built);
+                report_progress(progress, done + 1, targets.len(), "Emitting targets");
+            }
+            results
+        });
+
+        let mut targets_json = Vec::new();
+        for built in results {
+            let (json, worker) =
+                built.ok_or_else(|| anyhow!("A target worker exited without a result."))??;
+            self.absorb_worker(worker);
+            targets_json.push(json);
+        }
+        Ok(targets_json)
+    }
+
+    /// A builder for emitting one target independently: it shares the
+    /// project-wide id maps but owns its assets, monitors, and id counter.
+    #[cfg(feature = "parallel")]
+    fn fork_for_target(&self, index: usize) -> ProjectBuilder<'a> {
+        let mut worker = ProjectBuilder::new(self.project, self.resolver, self.options.clone());
+        worker.broadcast_ids = self.broadcast_ids.clone();
+        worker.remote_calls = self.remote_calls.clone();
+        worker.global_var_ids = self.global_var_ids.clone();
+        worker.global_var_names = self.global_var_names.clone();
+        worker.global_list_ids = self.global_list_ids.clone();
+        worker.global_list_names = self.global_list_names.clone();
+        worker.cloud_var_names = self.cloud_var_names.clone();
+        worker.costume_cache = Arc::clone(&self.costume_cache);
+        worker.stream_assets = self.stream_assets;
+        worker.id_prefix = format!("t{}_", index);
+        worker.parallel = false;
+        worker
+    }
+
+    #[cfg(feature = "parallel")]
+    fn absorb_worker(&mut self, worker: ProjectBuilder<'a>) {
+        self.assets.extend(worker.assets);
+        self.asset_files.extend(worker.asset_files);
+        self.monitors.extend(worker.monitors);
+        self.costume_reads += worker.costume_reads;
+        for (message, id) in worker.broadcast_ids {
+            self.broadcast_ids.entry(message).or_insert(id);
+        }
+    }
+
+    fn push_variable_monitor(
+        &mut self,
+        target: &Target,
+        monitor: &MonitorDecl,
+        local_variables_map: &HashMap<String, String>,
+        variables_json: &Map<String, Value>,
+    ) -> Result<()> {
+        let key = monitor.var_name.to_lowercase();
+        let (var_id, sprite_name) = match local_variables_map.get(&key) {
+            Some(id) if !target.is_stage => (id.clone(), json!(target.name)),
+            Some(id) => (id.clone(), Value::Null),
+            None => {
+                let id = self.global_var_ids.get(&key).ok_or_else(|| {
+                    anyhow!(
+                        "Monitor in target '{}' refers to unknown variable '{}'.",
+                        target.name,
+                        monitor.var_name
+                    )
+                })?;
+                (id.clone(), Value::Null)
+            }
+        };
+        let declared = variables_json.get(&var_id);
+        let var_name = declared
+            .and_then(|v| v.get(0))
+            .and_then(Value::as_str)
+            .or_else(|| self.global_var_names.get(&key).map(String::as_str))
+            .unwrap_or(&monitor.var_name)
+            .to_string();
+        let value = declared
+            .and_then(|v| v.get(1))
+            .cloned()
+            .unwrap_or_else(|| json!(0));
+        self.monitors.push(json!({
+            "id": var_id,
+            "mode": monitor.mode,
+            "opcode": "data_variable",
+            "params": {"VARIABLE": var_name},
+            "spriteName": sprite_name,
+            "value": value,
+            "width": 0,
+            "height": 0,
+            "x": monitor.x,
+            "y": monitor.y,
+            "visible": true,
+            "sliderMin": 0,
+            "sliderMax": 100,
+            "isDiscrete": true
+        }));
+        Ok(())
+    }
+
+    fn push_list_monitor(
+        &mut self,
+        target: &Target,
+        monitor: &MonitorDecl,
+        lists_map: &HashMap<String, String>,
+        lists_json: &Map<String, Value>,
+    ) -> Result<()> {
+        let key = monitor.var_name.to_lowercase();
+        let (list_id, sprite_name) = match lists_map.get(&key) {
+            Some(id) if !target.is_stage => (id.clone(), json!(target.name)),
+            Some(id) => (id.clone(), Value::Null),
+            None => {
+                let id = self.global_list_ids.get(&key).ok_or_else(|| {
+                    anyhow!(
+                        "Monitor in target '{}' refers to unknown variable or list '{}'.",
+                        target.name,
+                        monitor.var_name
+                    )
+                })?;
+                (id.clone(), Value::Null)
+            }
+        };
+        let declared = lists_json.get(&list_id);
+        let list_name = declared
+            .and_then(|l| l.get(0))
+            .and_then(Value::as_str)
+            .or_else(|| self.global_list_names.get(&key).map(String::as_str))
+            .unwrap_or(&monitor.var_name)
+            .to_string();
+        let value = declared
+            .and_then(|l| l.get(1))
+            .cloned()
+            .unwrap_or_else(|| json!([]));
+        self.monitors.push(json!({
+            "id": list_id,
+            "mode": "list",
+            "opcode": "data_listcontents",
+            "params": {"LIST": list_name},
+            "spriteName": sprite_name,
+            "value": value,
+            "width": 0,
+            "height": 0,
+            "x": monitor.x,
+            "y": monitor.y,
+            "visible": true
+        }));
+        Ok(())
     }
 
     fn synthesized_stage_target(&self, existing: &[Target]) -> Target {
@@ -422,6 +1294,9 @@ impl<'a> ProjectBuilder<'a> {
             variables: Vec::<VariableDecl>::new(),
             lists: Vec::<ListDecl>::new(),
             costumes: Vec::new(),
+            sounds: Vec::new(),
+            properties: Vec::new(),
+            monitors: Vec::new(),
             procedures: Vec::<Procedure>::new(),
             scripts: Vec::<EventScript>::new(),
             reporters: Vec::<crate::ast::ReporterDecl>::new(),
@@ -434,27 +1309,36 @@ impl<'a> ProjectBuilder<'a> {
         let mut variables_json: Map<String, Value> = Map::new();
         let mut lists_map: HashMap<String, String> = HashMap::new();
         let mut lists_json: Map<String, Value> = Map::new();
+        let target_scope = target.name.to_lowercase();
+        self.enter_id_scope(format!("{}/target", target_scope));
 
         for var_decl in &target.variables {
             let key = var_decl.name.to_lowercase();
             if local_variables_map.contains_key(&key) {
                 continue;
             }
+            let named_key = format!("{}/{}", target_scope, key);
             let var_id = if target.is_stage {
                 self.global_var_ids
                     .get(&key)
                     .cloned()
-                    .unwrap_or_else(|| self.new_id("var"))
+                    .unwrap_or_else(|| self.named_id("var", &named_key))
             } else {
-                self.new_id("var")
+                self.named_id("var", &named_key)
             };
             local_variables_map.insert(key, var_id.clone());
+            let name = emitted_variable_name(var_decl);
             let initial = var_decl
                 .initial_value
                 .as_ref()
                 .map(initial_value_json)
                 .unwrap_or_else(|| json!(0));
-            variables_json.insert(var_id, json!([var_decl.name, initial]));
+            if var_decl.is_cloud {
+                local_variables_map.insert(name.to_lowercase(), var_id.clone());
+                variables_json.insert(var_id, json!([name, initial, true]));
+            } else {
+                variables_json.insert(var_id, json!([name, initial]));
+            }
         }
         if target.is_stage {
             for (var_lower, var_id) in &self.global_var_ids {
@@ -472,13 +1356,14 @@ impl<'a> ProjectBuilder<'a> {
             if lists_map.contains_key(&key) {
                 continue;
             }
+            let named_key = format!("{}/{}", target_scope, key);
             let list_id = if target.is_stage {
                 self.global_list_ids
                     .get(&key)
                     .cloned()
-                    .unwrap_or_else(|| self.new_id("list"))
+                    .unwrap_or_else(|| self.named_id("list", &named_key))
             } else {
-                self.new_id("list")
+                self.named_id("list", &named_key)
             };
             lists_map.insert(key, list_id.clone());
             let initial = list_decl
@@ -488,6 +1373,17 @@ impl<'a> ProjectBuilder<'a> {
                 .unwrap_or_else(|| json!([]));
             lists_json.insert(list_id, json!([list_decl.name, initial]));
         }
+        if target.is_stage {
+            for (list_lower, list_id) in &self.global_list_ids {
+                if lists_json.contains_key(list_id) {
+                    continue;
+                }
+                let list_name = self.global_list_names.get(list_lower).ok_or_else(|| {
+                    anyhow!("Missing generated global list name for '{}'.", list_lower)
+                })?;
+                lists_json.insert(list_id.clone(), json!([list_name, []]));
+            }
+        }
 
         // Inject generated lists for reporters (output lists)
         for reporter in &target.reporters {
@@ -496,18 +1392,30 @@ impl<'a> ProjectBuilder<'a> {
                 if lists_map.contains_key(&key) {
                     continue;
                 }
+                let named_key = format!("{}/{}", target_scope, key);
                 let list_id = if target.is_stage {
                     self.global_list_ids
                         .get(&key)
                         .cloned()
-                        .unwrap_or_else(|| self.new_id("list"))
+                        .unwrap_or_else(|| self.named_id("list", &named_key))
                 } else {
-                    self.new_id("list")
+                    self.named_id("list", &named_key)
                 };
                 lists_map.insert(key, list_id.clone());
                 lists_json.insert(list_id, json!([rname, json!([])]));
             }
         }
+        for monitor in &target.monitors {
+            let key = monitor.var_name.to_lowercase();
+            let is_list = !local_variables_map.contains_key(&key)
+                && !self.global_var_ids.contains_key(&key)
+                && (lists_map.contains_key(&key) || self.global_list_ids.contains_key(&key));
+            if is_list {
+                self.push_list_monitor(target, monitor, &lists_map, &lists_json)?;
+            } else {
+                self.push_variable_monitor(target, monitor, &local_variables_map, &variables_json)?;
+            }
+        }
 
         let mut variables_map = local_variables_map.clone();
         for (k, v) in &self.global_var_ids {
@@ -525,8 +1433,15 @@ impl<'a> ProjectBuilder<'a> {
                 .insert(r.name.to_lowercase(), r.clone());
         }
         self.current_signatures = signatures.clone();
+        self.current_comments.clear();
+        // Procedures, event scripts, and RPC handlers each get their own column.
         let mut y_cursor: i32 = 30;
         for procedure in &target.procedures {
+            self.enter_id_scope(format!(
+                "{}/procedure/{}",
+                target_scope,
+                procedure.name.to_lowercase()
+            ));
             y_cursor = self.emit_procedure_definition(
                 &mut blocks,
                 procedure,
@@ -535,7 +1450,7 @@ impl<'a> ProjectBuilder<'a> {
                 &lists_map,
                 y_cursor,
             )?;
-            y_cursor += 40;
+            y_cursor += SCRIPT_GAP;
         }
         // Emit synthesized procedures for reporters
         for reporter in &target.reporters {
@@ -544,9 +1459,16 @@ impl<'a> ProjectBuilder<'a> {
                 pos: reporter.pos,
                 name: proc_name.clone(),
                 params: reporter.params.clone(),
+                param_kinds: vec![ParamKind::StringNumber; reporter.params.len()],
                 run_without_screen_refresh: false,
                 body: reporter.body.clone(),
+                comment: None,
             };
+            self.enter_id_scope(format!(
+                "{}/reporter/{}",
+                target_scope,
+                reporter.name.to_lowercase()
+            ));
             y_cursor = self.emit_procedure_definition(
                 &mut blocks,
                 &synth_proc,
@@ -555,29 +1477,40 @@ impl<'a> ProjectBuilder<'a> {
                 &lists_map,
                 y_cursor,
             )?;
-            y_cursor += 40;
+            y_cursor += SCRIPT_GAP;
         }
-        for script in &target.scripts {
+        let mut y_cursor: i32 = 30;
+        for (script_index, script) in target.scripts.iter().enumerate() {
+            self.enter_id_scope(format!("{}/script/{}", target_scope, script_index));
             y_cursor = self.emit_event_script(
                 &mut blocks,
                 script,
+                target.is_stage,
                 &signatures,
                 &variables_map,
                 &lists_map,
                 y_cursor,
             )?;
-            y_cursor += 40;
+            y_cursor += SCRIPT_GAP;
         }
+        self.enter_id_scope(format!("{}/remote", target_scope));
         let _ = self.emit_remote_call_handlers(
             &mut blocks,
             target,
             &signatures,
             &variables_map,
             &lists_map,
-            y_cursor,
+            30,
         )?;
 
         let costumes = self.build_costumes(target)?;
+        let mut current_costume = 0;
+        for decl in &target.properties {
+            if let TargetProperty::StartCostume(name) = &decl.property {
+                current_costume = costume_index(&costumes, name)?;
+            }
+        }
+        let sounds = self.build_sounds(target)?;
         let stage_broadcasts = if target.is_stage {
             let mut m = Map::new();
             for (msg, id) in &self.broadcast_ids {
@@ -595,10 +1528,10 @@ impl<'a> ProjectBuilder<'a> {
             "lists": lists_json,
             "broadcasts": stage_broadcasts,
             "blocks": blocks,
-            "comments": {},
-            "currentCostume": 0,
+            "comments": std::mem::take(&mut self.current_comments),
+            "currentCostume": current_costume,
             "costumes": costumes,
-            "sounds": [],
+            "sounds": sounds,
             "volume": 100,
             "layerOrder": layer_order
         });
@@ -626,6 +1559,22 @@ impl<'a> ProjectBuilder<'a> {
                 }),
             )?;
         }
+        for decl in &target.properties {
+            let (key, value) = match &decl.property {
+                TargetProperty::X(v) => ("x", json!(v)),
+                TargetProperty::Y(v) => ("y", json!(v)),
+                TargetProperty::Size(v) => ("size", json!(v)),
+                TargetProperty::Direction(v) => ("direction", json!(v)),
+                TargetProperty::Visible(v) => ("visible", json!(v)),
+                TargetProperty::RotationStyle(v) => ("rotationStyle", json!(v)),
+                TargetProperty::Volume(v) => ("volume", json!(v)),
+                TargetProperty::Layer(_) | TargetProperty::StartCostume(_) => continue,
+                TargetProperty::Tempo(v) => ("tempo", json!(v)),
+                TargetProperty::VideoTransparency(v) => ("videoTransparency", json!(v)),
+                TargetProperty::VideoState(v) => ("videoState", json!(v)),
+            };
+            set_value_key(&mut target_json, key, value)?;
+        }
         Ok(target_json)
     }
 
@@ -634,16 +1583,25 @@ impl<'a> ProjectBuilder<'a> {
         target: &Target,
     ) -> HashMap<String, ProcedureSignature> {
         let mut signatures = HashMap::new();
+        let target_scope = target.name.to_lowercase();
         for procedure in &target.procedures {
+            self.enter_id_scope(format!(
+                "{}/procedure/{}/args",
+                target_scope,
+                procedure.name.to_lowercase()
+            ));
             let arg_ids = procedure
                 .params
                 .iter()
                 .map(|_| self.new_id("arg"))
                 .collect::<Vec<_>>();
             let placeholders = procedure
-                .params
+                .param_kinds
                 .iter()
-                .map(|_| "%s")
+                .map(|kind| match kind {
+                    ParamKind::StringNumber => "%s",
+                    ParamKind::Boolean => "%b",
+                })
                 .collect::<Vec<_>>()
                 .join(" ");
             let proccode = if placeholders.is_empty() {
@@ -655,6 +1613,7 @@ impl<'a> ProjectBuilder<'a> {
                 procedure.name.to_lowercase(),
                 ProcedureSignature {
                     params: procedure.params.clone(),
+                    param_kinds: procedure.param_kinds.clone(),
                     arg_ids,
                     proccode,
                     warp: procedure.run_without_screen_refresh,
@@ -663,6 +1622,11 @@ impl<'a> ProjectBuilder<'a> {
         }
         // Include reporter declarations as callable procedures (synthesized)
         for reporter in &target.reporters {
+            self.enter_id_scope(format!(
+                "{}/reporter/{}/args",
+                target_scope,
+                reporter.name.to_lowercase()
+            ));
             let arg_ids = reporter
                 .params
                 .iter()
@@ -678,6 +1642,7 @@ impl<'a> ProjectBuilder<'a> {
                 format!("__reporter__{}", reporter.name).to_lowercase(),
                 ProcedureSignature {
                     params: reporter.params.clone(),
+                    param_kinds: vec![ParamKind::StringNumber; reporter.params.len()],
                     arg_ids,
                     proccode,
                     warp: false,
@@ -693,10 +1658,30 @@ impl<'a> ProjectBuilder<'a> {
             .project
             .targets
             .iter()
-            .any(|target| target_uses_pen_extension(target))
+            .any(target_uses_pen_extension)
         {
             extensions.push("pen".to_string());
         }
+        if self.project.targets.iter().any(target_uses_music_extension) {
+            extensions.push("music".to_string());
+        }
+        let mut raw_extensions = BTreeSet::new();
+        for target in &self.project.targets {
+            for script in &target.scripts {
+                collect_raw_extensions(&script.body, &mut raw_extensions);
+            }
+            for procedure in &target.procedures {
+                collect_raw_extensions(&procedure.body, &mut raw_extensions);
+            }
+            for reporter in &target.reporters {
+                collect_raw_extensions(&reporter.body, &mut raw_extensions);
+            }
+        }
+        for extension in raw_extensions {
+            if !extensions.contains(&extension) {
+                extensions.push(extension);
+            }
+        }
         extensions
     }
 
@@ -737,70 +1722,45 @@ impl<'a> ProjectBuilder<'a> {
         local_procs: &HashMap<String, (String, String, usize)>,
         out: &mut HashMap<String, RemoteCallSpec>,
     ) -> Result<()> {
-        for stmt in statements {
-            match stmt {
-                Statement::ProcedureCall { name, args, .. } => {
-                    if let Some((target_name, proc_name)) = split_qualified(name) {
-                        let key = format!(
-                            "{}.{}",
-                            target_name.to_lowercase(),
-                            proc_name.to_lowercase()
-                        );
-                        let Some((_target_display, proc_display, expected_args)) =
-                            local_procs.get(&key)
-                        else {
-                            continue;
-                        };
-                        if *expected_args != args.len() {
-                            bail!(
-                                "Remote procedure '{}' expects {} args, got {}.",
-                                name,
-                                expected_args,
-                                args.len()
-                            );
-                        }
-                        out.entry(key.clone()).or_insert_with(|| {
-                            let arg_var_names = (0..*expected_args)
-                                .map(|i| {
-                                    format!(
-                                        "__rpc__{}__{}__arg{}",
-                                        target_name.to_lowercase(),
-                                        proc_name.to_lowercase(),
-                                        i + 1
-                                    )
-                                })
-                                .collect::<Vec<_>>();
-                            RemoteCallSpec {
-                                callee_target_lower: target_name.to_lowercase(),
-                                procedure_lower: proc_name.to_lowercase(),
-                                procedure_name: proc_display.clone(),
-                                message: format!(
-                                    "__rpc__{}__{}",
-                                    target_name.to_lowercase(),
-                                    proc_name.to_lowercase()
-                                ),
-                                arg_var_names,
-                            }
-                        });
-                    }
-                }
-                Statement::Repeat { body, .. }
-                | Statement::ForEach { body, .. }
-                | Statement::While { body, .. }
-                | Statement::RepeatUntil { body, .. }
-                | Statement::Forever { body, .. } => {
-                    self.collect_remote_calls_from_statements(body, local_procs, out)?;
-                }
-                Statement::If {
-                    then_body,
-                    else_body,
-                    ..
-                } => {
-                    self.collect_remote_calls_from_statements(then_body, local_procs, out)?;
-                    self.collect_remote_calls_from_statements(else_body, local_procs, out)?;
-                }
-                _ => {}
+        let mut calls = Vec::new();
+        walk_statements(statements, &mut |stmt| {
+            if let Statement::ProcedureCall { name, args, .. } = stmt {
+                calls.push((name, args.len()));
+            }
+        });
+        for (name, arg_count) in calls {
+            let Some((target_name, proc_name)) = split_qualified(name) else {
+                continue;
+            };
+            let key = format!(
+                "{}.{}",
+                target_name.to_lowercase(),
+                proc_name.to_lowercase()
+            );
+            let Some((_target_display, proc_display, expected_args)) = local_procs.get(&key) else {
+                continue;
+            };
+            if *expected_args != arg_count {
+                bail!(
+                    "Remote procedure '{}' expects {} args, got {}.",
+                    name,
+                    expected_args,
+                    arg_count
+                );
             }
+            out.entry(key.clone()).or_insert_with(|| {
+                let names = rpc_names(target_name, proc_name, *expected_args);
+                RemoteCallSpec {
+                    callee_target_lower: target_name.to_lowercase(),
+                    procedure_lower: proc_name.to_lowercase(),
+                    procedure_name: proc_display.clone(),
+                    message: names.message,
+                    arg_var_names: names.arg_vars,
+                    arg_queue_names: names.arg_queues,
+                    calls_list_name: names.calls_list,
+                    busy_var_name: names.busy_var,
+                }
+            });
         }
         Ok(())
     }
@@ -809,7 +1769,7 @@ impl<'a> ProjectBuilder<'a> {
         let remote_calls = self.remote_calls.clone();
         for spec in &remote_calls {
             if !self.broadcast_ids.contains_key(&spec.message) {
-                let id = self.new_id("broadcast");
+                let id = self.named_id("broadcast", &spec.message);
                 self.broadcast_ids.insert(spec.message.clone(), id);
             }
         }
@@ -818,15 +1778,32 @@ impl<'a> ProjectBuilder<'a> {
     fn allocate_generated_global_vars(&mut self) {
         let remote_calls = self.remote_calls.clone();
         for spec in &remote_calls {
-            for var_name in &spec.arg_var_names {
+            for var_name in spec
+                .arg_var_names
+                .iter()
+                .chain(std::iter::once(&spec.busy_var_name))
+            {
                 let key = var_name.to_lowercase();
                 if self.global_var_ids.contains_key(&key) {
                     continue;
                 }
-                let id = self.new_id("gvar");
+                let id = self.named_id("gvar", &key);
                 self.global_var_ids.insert(key.clone(), id);
                 self.global_var_names.insert(key, var_name.clone());
             }
+            for list_name in spec
+                .arg_queue_names
+                .iter()
+                .chain(std::iter::once(&spec.calls_list_name))
+            {
+                let key = list_name.to_lowercase();
+                if self.global_list_ids.contains_key(&key) {
+                    continue;
+                }
+                let id = self.named_id("glist", &key);
+                self.global_list_ids.insert(key.clone(), id);
+                self.global_list_names.insert(key, list_name.clone());
+            }
         }
     }
 
@@ -840,16 +1817,23 @@ impl<'a> ProjectBuilder<'a> {
                 if self.global_var_ids.contains_key(&key) {
                     continue;
                 }
-                let id = self.new_id("gvar");
+                let id = self.named_id("gvar", &key);
+                let name = emitted_variable_name(var_decl);
+                if var_decl.is_cloud {
+                    self.cloud_var_names.insert(id.clone(), name.clone());
+                    self.global_var_ids.insert(name.to_lowercase(), id.clone());
+                    self.global_var_names
+                        .insert(name.to_lowercase(), name.clone());
+                }
                 self.global_var_ids.insert(key.clone(), id);
-                self.global_var_names.insert(key, var_decl.name.clone());
+                self.global_var_names.insert(key, name);
             }
             for list_decl in &target.lists {
                 let key = list_decl.name.to_lowercase();
                 if self.global_list_ids.contains_key(&key) {
                     continue;
                 }
-                let id = self.new_id("glist");
+                let id = self.named_id("glist", &key);
                 self.global_list_ids.insert(key.clone(), id);
                 self.global_list_names.insert(key, list_decl.name.clone());
             }
@@ -924,38 +1908,55 @@ impl<'a> ProjectBuilder<'a> {
                     "fields": {"BROADCAST_OPTION": [handler.message, bid]},
                     "shadow": false,
                     "topLevel": true,
-                    "x": 580,
+                    "x": RPC_COLUMN_X,
                     "y": start_y
                 }),
             );
 
-            let args = handler
-                .arg_var_names
-                .iter()
-                .map(|name| Expr::Var {
-                    pos: target.pos,
-                    name: name.clone(),
-                })
-                .collect::<Vec<_>>();
-            let emitted = self.emit_call_stmt(
+            let body = rpc_handler_body(&handler, target.pos);
+            let (first, _) = self.emit_statement_chain(
                 blocks,
+                &body,
                 &hat_id,
-                &handler.procedure_name,
-                &args,
-                signatures,
                 variables_map,
                 lists_map,
+                signatures,
                 &HashSet::new(),
             )?;
-            set_block_next(blocks, &hat_id, Value::String(emitted.first))?;
-            start_y += 140;
+            if let Some(first) = &first {
+                set_block_next(blocks, &hat_id, Value::String(first.clone()))?;
+            }
+            start_y += HAT_BLOCK_HEIGHT + stack_height(blocks, first.as_deref()) + SCRIPT_GAP;
         }
         Ok(start_y)
     }
 
     fn new_id(&mut self, prefix: &str) -> String {
+        if self.options.deterministic_ids {
+            self.scope_counter += 1;
+            let key = format!("{}/{}", self.id_scope, self.scope_counter);
+            return hashed_id(prefix, &key);
+        }
         self.id_counter += 1;
-        format!("{}_{}", prefix, self.id_counter)
+        format!("{}{}_{}", self.id_prefix, prefix, self.id_counter)
+    }
+
+    /// Allocates an id for a named entity (variable, list, broadcast). In
+    /// deterministic mode the id is a hash of `key`; otherwise it falls back
+    /// to the sequential counter.
+    fn named_id(&mut self, prefix: &str, key: &str) -> String {
+        if self.options.deterministic_ids {
+            return hashed_id(prefix, key);
+        }
+        self.new_id(prefix)
+    }
+
+    /// Starts a new id scope. Ids allocated by `new_id` in deterministic mode
+    /// depend only on the scope and their order within it, so a script's ids
+    /// are unaffected by edits to other scripts.
+    fn enter_id_scope(&mut self, scope: String) {
+        self.id_scope = scope;
+        self.scope_counter = 0;
     }
 
     fn new_block_id(&mut self) -> String {
@@ -974,12 +1975,16 @@ impl<'a> ProjectBuilder<'a> {
             for procedure in &target.procedures {
                 collect_messages_from_statements(&procedure.body, &mut messages);
             }
+            for reporter in &target.reporters {
+                collect_messages_from_statements(&reporter.body, &mut messages);
+            }
         }
         let mut map = HashMap::new();
         let mut sorted = messages.into_iter().collect::<Vec<_>>();
         sorted.sort();
         for msg in sorted {
-            map.insert(msg, self.new_id("broadcast"));
+            let id = self.named_id("broadcast", &msg);
+            map.insert(msg, id);
         }
         map
     }
@@ -988,7 +1993,7 @@ impl<'a> ProjectBuilder<'a> {
         if let Some(id) = self.broadcast_ids.get(message) {
             return id.clone();
         }
-        let id = self.new_id("broadcast");
+        let id = self.named_id("broadcast", message);
         self.broadcast_ids.insert(message.to_string(), id.clone());
         id
     }
@@ -1017,18 +2022,28 @@ impl<'a> ProjectBuilder<'a> {
                 "fields": {},
                 "shadow": false,
                 "topLevel": true,
-                "x": 30,
+                "x": PROCEDURE_COLUMN_X,
                 "y": start_y
             }),
         );
+        let mut comment_height = 0;
+        if let Some(text) = &procedure.comment {
+            comment_height =
+                self.attach_comment(blocks, &definition_id, text, PROCEDURE_COLUMN_X, start_y)?;
+        }
 
         let mut prototype_inputs = Map::new();
-        for (param_name, arg_id) in signature.params.iter().zip(signature.arg_ids.iter()) {
+        for ((param_name, kind), arg_id) in signature
+            .params
+            .iter()
+            .zip(signature.param_kinds.iter())
+            .zip(signature.arg_ids.iter())
+        {
             let reporter_id = self.new_block_id();
             blocks.insert(
                 reporter_id.clone(),
                 json!({
-                    "opcode": "argument_reporter_string_number",
+                    "opcode": argument_reporter_opcode(*kind),
                     "next": Value::Null,
                     "parent": prototype_id.clone(),
                     "inputs": {},
@@ -1055,12 +2070,28 @@ impl<'a> ProjectBuilder<'a> {
                     "proccode": signature.proccode,
                     "argumentids": serde_json::to_string(&signature.arg_ids)?,
                     "argumentnames": serde_json::to_string(&signature.params)?,
-                    "argumentdefaults": serde_json::to_string(&vec![""; signature.params.len()])?,
+                    "argumentdefaults": serde_json::to_string(
+                        &signature
+                            .param_kinds
+                            .iter()
+                            .map(|kind| match kind {
+                                ParamKind::StringNumber => "",
+                                ParamKind::Boolean => "false",
+                            })
+                            .collect::<Vec<_>>()
+                    )?,
                     "warp": if signature.warp { "true" } else { "false" }
                 }
             }),
         );
-        let (first, last) = self.emit_statement_chain(
+        self.current_boolean_params = signature
+            .params
+            .iter()
+            .zip(signature.param_kinds.iter())
+            .filter(|(_, kind)| **kind == ParamKind::Boolean)
+            .map(|(name, _)| name.to_lowercase())
+            .collect();
+        let (first, _) = self.emit_statement_chain(
             blocks,
             &procedure.body,
             &definition_id,
@@ -1073,24 +2104,67 @@ impl<'a> ProjectBuilder<'a> {
                 .map(|s| s.to_lowercase())
                 .collect::<HashSet<_>>(),
         )?;
-        if let Some(fid) = first {
-            set_block_next(blocks, &definition_id, Value::String(fid))?;
-            return Ok(start_y + 120 + if last.is_some() { 20 } else { 0 });
+        self.current_boolean_params.clear();
+        if let Some(fid) = &first {
+            set_block_next(blocks, &definition_id, Value::String(fid.clone()))?;
         }
-        Ok(start_y + 80)
+        let height = HAT_BLOCK_HEIGHT + stack_height(blocks, first.as_deref());
+        Ok(start_y + height.max(comment_height))
+    }
+
+    /// Adds a workspace comment beside a top-level block, sized to roughly fit its text.
+    /// Returns the comment height.
+    fn attach_comment(
+        &mut self,
+        blocks: &mut Map<String, Value>,
+        block_id: &str,
+        text: &str,
+        block_x: i32,
+        block_y: i32,
+    ) -> Result<i32> {
+        let wrapped_lines = text
+            .lines()
+            .map(|line| line.chars().count().div_ceil(COMMENT_CHARS_PER_LINE).max(1))
+            .sum::<usize>()
+            .max(1);
+        let height = (32 + 18 * wrapped_lines).min(400) as i32;
+        let comment_id = self.new_id("comment");
+        self.current_comments.insert(
+            comment_id.clone(),
+            json!({
+                "blockId": block_id,
+                "x": block_x + 260,
+                "y": block_y,
+                "width": COMMENT_WIDTH,
+                "height": height,
+                "minimized": false,
+                "text": text
+            }),
+        );
+        let block = blocks
+            .get_mut(block_id)
+            .ok_or_else(|| anyhow!("Missing block '{}' for comment.", block_id))?;
+        set_value_key(block, "comment", Value::String(comment_id))?;
+        Ok(height)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_event_script(
         &mut self,
         blocks: &mut Map<String, Value>,
         script: &EventScript,
+        is_stage: bool,
         signatures: &HashMap<String, ProcedureSignature>,
         variables_map: &HashMap<String, String>,
         lists_map: &HashMap<String, String>,
         start_y: i32,
     ) -> Result<i32> {
+        let hat_id = self.new_block_id();
+        let mut inputs = json!({});
         let (opcode, fields) = match &script.event_type {
             EventType::WhenFlagClicked => ("event_whenflagclicked", json!({})),
+            // The stage has its own hat; the sprite one never fires there.
+            EventType::WhenThisSpriteClicked if is_stage => ("event_whenstageclicked", json!({})),
             EventType::WhenThisSpriteClicked => ("event_whenthisspriteclicked", json!({})),
             EventType::WhenIReceive(msg) => {
                 let bid = self.broadcast_id(msg);
@@ -1101,25 +2175,50 @@ impl<'a> ProjectBuilder<'a> {
             }
             EventType::WhenKeyPressed(key_name) => (
                 "event_whenkeypressed",
-                json!({"KEY_OPTION": [key_name.clone(), Value::Null]}),
+                json!({"KEY_OPTION": [key_name.to_lowercase(), Value::Null]}),
             ),
+            EventType::WhenStartAsClone => ("control_start_as_clone", json!({})),
+            EventType::WhenBackdropSwitches(backdrop) => (
+                "event_whenbackdropswitchesto",
+                json!({"BACKDROP": [backdrop.clone(), Value::Null]}),
+            ),
+            EventType::WhenGreaterThan { sensor, value } => {
+                let value_input = self.expr_input(
+                    blocks,
+                    value,
+                    &hat_id,
+                    variables_map,
+                    lists_map,
+                    &HashSet::new(),
+                    "number",
+                )?;
+                inputs = json!({"VALUE": value_input});
+                (
+                    "event_whengreaterthan",
+                    json!({"WHENGREATERTHANMENU": [sensor.to_uppercase(), Value::Null]}),
+                )
+            }
         };
-        let hat_id = self.new_block_id();
         blocks.insert(
             hat_id.clone(),
             json!({
                 "opcode": opcode,
                 "next": Value::Null,
                 "parent": Value::Null,
-                "inputs": {},
+                "inputs": inputs,
                 "fields": fields,
                 "shadow": false,
                 "topLevel": true,
-                "x": 320,
+                "x": SCRIPT_COLUMN_X,
                 "y": start_y
             }),
         );
-        let (first, last) = self.emit_statement_chain(
+        let mut comment_height = 0;
+        if let Some(text) = &script.comment {
+            comment_height =
+                self.attach_comment(blocks, &hat_id, text, SCRIPT_COLUMN_X, start_y)?;
+        }
+        let (first, _) = self.emit_statement_chain(
             blocks,
             &script.body,
             &hat_id,
@@ -1128,13 +2227,14 @@ impl<'a> ProjectBuilder<'a> {
             signatures,
             &HashSet::new(),
         )?;
-        if let Some(fid) = first {
-            set_block_next(blocks, &hat_id, Value::String(fid))?;
-            return Ok(start_y + 120 + if last.is_some() { 20 } else { 0 });
+        if let Some(fid) = &first {
+            set_block_next(blocks, &hat_id, Value::String(fid.clone()))?;
         }
-        Ok(start_y + 80)
+        let height = HAT_BLOCK_HEIGHT + stack_height(blocks, first.as_deref());
+        Ok(start_y + height.max(comment_height))
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_statement_chain(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -1169,6 +2269,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok((first, prev_last))
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_statement(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -1184,12 +2285,32 @@ impl<'a> ProjectBuilder<'a> {
             last: id,
         };
         match stmt {
-            Statement::Broadcast { message, .. } => Ok(single(
-                self.emit_broadcast_stmt(blocks, parent_id, message)?,
-            )),
-            Statement::BroadcastAndWait { message, .. } => Ok(single(
-                self.emit_broadcast_and_wait_stmt(blocks, parent_id, message)?,
-            )),
+            Statement::Broadcast { message, .. } => Ok(single(match literal_message(message) {
+                Some(value) => self.emit_broadcast_stmt(blocks, parent_id, &value)?,
+                None => self.emit_dynamic_broadcast_stmt(
+                    blocks,
+                    parent_id,
+                    "event_broadcast",
+                    message,
+                    variables_map,
+                    lists_map,
+                    param_scope,
+                )?,
+            })),
+            Statement::BroadcastAndWait { message, .. } => {
+                Ok(single(match literal_message(message) {
+                    Some(value) => self.emit_broadcast_and_wait_stmt(blocks, parent_id, &value)?,
+                    None => self.emit_dynamic_broadcast_stmt(
+                        blocks,
+                        parent_id,
+                        "event_broadcastandwait",
+                        message,
+                        variables_map,
+                        lists_map,
+                        param_scope,
+                    )?,
+                }))
+            }
             Statement::SetVar {
                 var_name, value, ..
             } => Ok(single(self.emit_set_stmt(
@@ -1239,6 +2360,7 @@ impl<'a> ProjectBuilder<'a> {
             } => Ok(single(self.emit_say_for_seconds_stmt(
                 blocks,
                 parent_id,
+                "looks_sayforsecs",
                 message,
                 duration,
                 variables_map,
@@ -1256,6 +2378,18 @@ impl<'a> ProjectBuilder<'a> {
                 param_scope,
                 "string",
             )?)),
+            Statement::ThinkForSeconds {
+                message, duration, ..
+            } => Ok(single(self.emit_say_for_seconds_stmt(
+                blocks,
+                parent_id,
+                "looks_thinkforsecs",
+                message,
+                duration,
+                variables_map,
+                lists_map,
+                param_scope,
+            )?)),
             Statement::TurnRight { degrees, .. } => Ok(single(self.emit_single_input_stmt(
                 blocks,
                 parent_id,
@@ -1530,6 +2664,16 @@ impl<'a> ProjectBuilder<'a> {
                     param_scope,
                 )?))
             }
+            Statement::SetPenColorTo { color, .. } => {
+                Ok(single(self.emit_pen_color_to_color_stmt(
+                    blocks,
+                    parent_id,
+                    color,
+                    variables_map,
+                    lists_map,
+                    param_scope,
+                )?))
+            }
             Statement::Show { .. } => Ok(single(self.emit_no_input_stmt(
                 blocks,
                 parent_id,
@@ -1574,6 +2718,19 @@ impl<'a> ProjectBuilder<'a> {
                     "string",
                 )?))
             }
+            Statement::SwitchBackdropToAndWait { backdrop, .. } => {
+                Ok(single(self.emit_single_input_stmt(
+                    blocks,
+                    parent_id,
+                    "looks_switchbackdroptoandwait",
+                    "BACKDROP",
+                    backdrop,
+                    variables_map,
+                    lists_map,
+                    param_scope,
+                    "string",
+                )?))
+            }
             Statement::Wait { duration, .. } => Ok(single(self.emit_single_input_stmt(
                 blocks,
                 parent_id,
@@ -1710,6 +2867,19 @@ impl<'a> ProjectBuilder<'a> {
                 Ok(single(self.emit_sound_effect_stmt(
                     blocks,
                     parent_id,
+                    "sound_seteffectto",
+                    effect,
+                    value,
+                    variables_map,
+                    lists_map,
+                    param_scope,
+                )?))
+            }
+            Statement::ChangeSoundEffectBy { effect, value, .. } => {
+                Ok(single(self.emit_sound_effect_stmt(
+                    blocks,
+                    parent_id,
+                    "sound_changeeffectby",
                     effect,
                     value,
                     variables_map,
@@ -1728,6 +2898,96 @@ impl<'a> ProjectBuilder<'a> {
                 param_scope,
                 "number",
             )?)),
+            Statement::ChangeVolumeBy { value, .. } => Ok(single(self.emit_single_input_stmt(
+                blocks,
+                parent_id,
+                "sound_changevolumeby",
+                "VOLUME",
+                value,
+                variables_map,
+                lists_map,
+                param_scope,
+                "number",
+            )?)),
+            Statement::ClearSoundEffects { .. } => Ok(single(self.emit_no_input_stmt(
+                blocks,
+                parent_id,
+                "sound_cleareffects",
+            )?)),
+            Statement::PlayNoteForBeats { note, beats, .. } => {
+                Ok(single(self.emit_play_note_stmt(
+                    blocks,
+                    parent_id,
+                    note,
+                    beats,
+                    variables_map,
+                    lists_map,
+                    param_scope,
+                )?))
+            }
+            Statement::PlayDrumForBeats { drum, beats, .. } => {
+                let number = music_menu_number(&MUSIC_DRUMS, drum)
+                    .ok_or_else(|| anyhow!("Unknown drum '{}'.", drum))?;
+                Ok(single(self.emit_music_menu_stmt(
+                    blocks,
+                    parent_id,
+                    "music_playDrumForBeats",
+                    "DRUM",
+                    number,
+                    Some(beats),
+                    variables_map,
+                    lists_map,
+                    param_scope,
+                )?))
+            }
+            Statement::RestForBeats { beats, .. } => Ok(single(self.emit_single_input_stmt(
+                blocks,
+                parent_id,
+                "music_restForBeats",
+                "BEATS",
+                beats,
+                variables_map,
+                lists_map,
+                param_scope,
+                "number",
+            )?)),
+            Statement::SetInstrumentTo { instrument, .. } => {
+                let number = music_menu_number(&MUSIC_INSTRUMENTS, instrument)
+                    .ok_or_else(|| anyhow!("Unknown instrument '{}'.", instrument))?;
+                Ok(single(self.emit_music_menu_stmt(
+                    blocks,
+                    parent_id,
+                    "music_setInstrument",
+                    "INSTRUMENT",
+                    number,
+                    None,
+                    variables_map,
+                    lists_map,
+                    param_scope,
+                )?))
+            }
+            Statement::SetTempoTo { value, .. } => Ok(single(self.emit_single_input_stmt(
+                blocks,
+                parent_id,
+                "music_setTempo",
+                "TEMPO",
+                value,
+                variables_map,
+                lists_map,
+                param_scope,
+                "number",
+            )?)),
+            Statement::ChangeTempoBy { value, .. } => Ok(single(self.emit_single_input_stmt(
+                blocks,
+                parent_id,
+                "music_changeTempo",
+                "TEMPO",
+                value,
+                variables_map,
+                lists_map,
+                param_scope,
+                "number",
+            )?)),
             Statement::CreateCloneOf { target, .. } => Ok(single(
                 self.emit_clone_target_menu_stmt(blocks, parent_id, target)?,
             )),
@@ -1754,6 +3014,20 @@ impl<'a> ProjectBuilder<'a> {
                     variables_map,
                 )?))
             }
+            Statement::ShowList { list_name, .. } => Ok(single(self.emit_show_hide_list_stmt(
+                blocks,
+                parent_id,
+                "data_showlist",
+                list_name,
+                lists_map,
+            )?)),
+            Statement::HideList { list_name, .. } => Ok(single(self.emit_show_hide_list_stmt(
+                blocks,
+                parent_id,
+                "data_hidelist",
+                list_name,
+                lists_map,
+            )?)),
             Statement::ResetTimer { .. } => Ok(single(self.emit_no_input_stmt(
                 blocks,
                 parent_id,
@@ -1824,45 +3098,179 @@ impl<'a> ProjectBuilder<'a> {
                 lists_map,
                 param_scope,
             ),
+            Statement::RawBlock { block, .. } => {
+                let first =
+                    self.emit_raw_block(blocks, block, parent_id, variables_map, lists_map)?;
+                let mut last = first.clone();
+                while let Some(next) = blocks
+                    .get(&last)
+                    .and_then(|b| b.get("next"))
+                    .and_then(Value::as_str)
+                {
+                    last = next.to_string();
+                }
+                Ok(EmittedStatement { first, last })
+            }
         }
     }
 
-    fn emit_no_input_stmt(
+    /// Inserts a `raw` block and its nested blocks under fresh ids, linking
+    /// parent/next and pointing variable, list, and broadcast references at this
+    /// project's ids by name.
+    fn emit_raw_block(
         &mut self,
         blocks: &mut Map<String, Value>,
+        raw: &Value,
         parent_id: &str,
-        opcode: &str,
+        variables_map: &HashMap<String, String>,
+        lists_map: &HashMap<String, String>,
     ) -> Result<String> {
+        let mut block = raw
+            .as_object()
+            .filter(|block| block.get("opcode").is_some_and(Value::is_string))
+            .cloned()
+            .ok_or_else(|| anyhow!("Raw block must be a JSON object with an 'opcode' field."))?;
         let block_id = self.new_block_id();
-        blocks.insert(
-            block_id.clone(),
-            json!({
-                "opcode": opcode,
-                "next": Value::Null,
-                "parent": parent_id,
-                "inputs": {},
-                "fields": {},
-                "shadow": false,
-                "topLevel": false
-            }),
-        );
+        let mut fields = Map::new();
+        for (key, value) in block
+            .get("fields")
+            .and_then(Value::as_object)
+            .cloned()
+            .unwrap_or_default()
+        {
+            let value = self.remap_raw_reference(&key, &value, variables_map, lists_map)?;
+            fields.insert(key, value);
+        }
+        let mut inputs = Map::new();
+        for (key, input) in block
+            .get("inputs")
+            .and_then(Value::as_object)
+            .cloned()
+            .unwrap_or_default()
+        {
+            let Value::Array(slots) = input else {
+                inputs.insert(key, input);
+                continue;
+            };
+            let mut remapped = Vec::with_capacity(slots.len());
+            for (index, slot) in slots.iter().enumerate() {
+                remapped.push(match slot {
+                    Value::Object(_) if index > 0 => Value::String(self.emit_raw_block(
+                        blocks,
+                        slot,
+                        &block_id,
+                        variables_map,
+                        lists_map,
+                    )?),
+                    Value::Array(primitive) if index > 0 => {
+                        self.remap_raw_primitive(primitive, variables_map, lists_map)?
+                    }
+                    _ => slot.clone(),
+                });
+            }
+            inputs.insert(key, Value::Array(remapped));
+        }
+        let next = match block.get("next") {
+            Some(next @ Value::Object(_)) => Value::String(self.emit_raw_block(
+                blocks,
+                next,
+                &block_id,
+                variables_map,
+                lists_map,
+            )?),
+            _ => Value::Null,
+        };
+        for key in ["x", "y", "comment"] {
+            block.remove(key);
+        }
+        block.insert("next".to_string(), next);
+        block.insert("parent".to_string(), json!(parent_id));
+        block.insert("inputs".to_string(), Value::Object(inputs));
+        block.insert("fields".to_string(), Value::Object(fields));
+        block.entry("shadow").or_insert_with(|| Value::Bool(false));
+        block.insert("topLevel".to_string(), Value::Bool(false));
+        blocks.insert(block_id.clone(), Value::Object(block));
         Ok(block_id)
     }
 
-    fn emit_single_input_stmt(
+    fn remap_raw_reference(
         &mut self,
-        blocks: &mut Map<String, Value>,
-        parent_id: &str,
-        opcode: &str,
-        input_name: &str,
-        value: &Expr,
+        field: &str,
+        value: &Value,
         variables_map: &HashMap<String, String>,
         lists_map: &HashMap<String, String>,
-        param_scope: &HashSet<String>,
-        default_kind: &str,
-    ) -> Result<String> {
-        let block_id = self.new_block_id();
-        let input = self.expr_input(
+    ) -> Result<Value> {
+        let Some(name) = value.get(0).and_then(Value::as_str) else {
+            return Ok(value.clone());
+        };
+        Ok(match field {
+            "VARIABLE" => json!([name, self.lookup_var_id(variables_map, name)?]),
+            "LIST" => json!([name, self.lookup_list_id(lists_map, name)?]),
+            "BROADCAST_OPTION" => json!([name, self.broadcast_id(name)]),
+            _ => value.clone(),
+        })
+    }
+
+    fn remap_raw_primitive(
+        &mut self,
+        primitive: &[Value],
+        variables_map: &HashMap<String, String>,
+        lists_map: &HashMap<String, String>,
+    ) -> Result<Value> {
+        let field = match primitive.first().and_then(Value::as_i64) {
+            Some(11) => "BROADCAST_OPTION",
+            Some(12) => "VARIABLE",
+            Some(13) => "LIST",
+            _ => return Ok(Value::Array(primitive.to_vec())),
+        };
+        let reference = self.remap_raw_reference(
+            field,
+            &Value::Array(primitive[1..].to_vec()),
+            variables_map,
+            lists_map,
+        )?;
+        let mut out = vec![primitive[0].clone()];
+        out.extend(reference.as_array().cloned().unwrap_or_default());
+        Ok(Value::Array(out))
+    }
+
+    fn emit_no_input_stmt(
+        &mut self,
+        blocks: &mut Map<String, Value>,
+        parent_id: &str,
+        opcode: &str,
+    ) -> Result<String> {
+        let block_id = self.new_block_id();
+        blocks.insert(
+            block_id.clone(),
+            json!({
+                "opcode": opcode,
+                "next": Value::Null,
+                "parent": parent_id,
+                "inputs": {},
+                "fields": {},
+                "shadow": false,
+                "topLevel": false
+            }),
+        );
+        Ok(block_id)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn emit_single_input_stmt(
+        &mut self,
+        blocks: &mut Map<String, Value>,
+        parent_id: &str,
+        opcode: &str,
+        input_name: &str,
+        value: &Expr,
+        variables_map: &HashMap<String, String>,
+        lists_map: &HashMap<String, String>,
+        param_scope: &HashSet<String>,
+        default_kind: &str,
+    ) -> Result<String> {
+        let block_id = self.new_block_id();
+        let input = self.expr_input(
             blocks,
             value,
             &block_id,
@@ -1886,6 +3294,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_pen_color_param_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -1935,10 +3344,45 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    fn emit_pen_color_to_color_stmt(
+        &mut self,
+        blocks: &mut Map<String, Value>,
+        parent_id: &str,
+        color: &Expr,
+        variables_map: &HashMap<String, String>,
+        lists_map: &HashMap<String, String>,
+        param_scope: &HashSet<String>,
+    ) -> Result<String> {
+        let block_id = self.new_block_id();
+        let color_input = self.color_expr_input(
+            blocks,
+            color,
+            &block_id,
+            variables_map,
+            lists_map,
+            param_scope,
+        )?;
+        blocks.insert(
+            block_id.clone(),
+            json!({
+                "opcode": "pen_setPenColorToColor",
+                "next": Value::Null,
+                "parent": parent_id,
+                "inputs": {"COLOR": color_input},
+                "fields": {},
+                "shadow": false,
+                "topLevel": false
+            }),
+        );
+        Ok(block_id)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn emit_say_for_seconds_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
         parent_id: &str,
+        opcode: &str,
         message: &Expr,
         duration: &Expr,
         variables_map: &HashMap<String, String>,
@@ -1967,7 +3411,7 @@ impl<'a> ProjectBuilder<'a> {
         blocks.insert(
             block_id.clone(),
             json!({
-                "opcode": "looks_sayforsecs",
+                "opcode": opcode,
                 "next": Value::Null,
                 "parent": parent_id,
                 "inputs": {"MESSAGE": message_input, "SECS": secs_input},
@@ -2013,6 +3457,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_go_to_xy_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -2057,6 +3502,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_glide_to_xy_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -2111,6 +3557,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_glide_to_target_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -2132,7 +3579,8 @@ impl<'a> ProjectBuilder<'a> {
             param_scope,
             "number",
         )?;
-        let target_value = self.menu_text_from_expr(target, "_random_");
+        let target_value =
+            normalize_motion_target_menu(&self.menu_text_from_expr(target, "_random_"));
         blocks.insert(
             block_id.clone(),
             json!({
@@ -2174,7 +3622,8 @@ impl<'a> ProjectBuilder<'a> {
     ) -> Result<String> {
         let block_id = self.new_block_id();
         let menu_id = self.new_block_id();
-        let target_value = self.menu_text_from_expr(target, fallback);
+        let target_value =
+            normalize_motion_target_menu(&self.menu_text_from_expr(target, fallback));
         blocks.insert(
             block_id.clone(),
             json!({
@@ -2285,6 +3734,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_looks_go_layers_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -2358,10 +3808,12 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_sound_effect_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
         parent_id: &str,
+        opcode: &str,
         effect: &str,
         value: &Expr,
         variables_map: &HashMap<String, String>,
@@ -2381,15 +3833,138 @@ impl<'a> ProjectBuilder<'a> {
         blocks.insert(
             block_id.clone(),
             json!({
-                "opcode": "sound_seteffectto",
+                "opcode": opcode,
                 "next": Value::Null,
                 "parent": parent_id,
                 "inputs": {"VALUE": value_input},
-                "fields": {"EFFECT": [effect, Value::Null]},
+                "fields": {"EFFECT": [sound_effect_field(effect), Value::Null]},
+                "shadow": false,
+                "topLevel": false
+            }),
+        );
+        Ok(block_id)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn emit_play_note_stmt(
+        &mut self,
+        blocks: &mut Map<String, Value>,
+        parent_id: &str,
+        note: &Expr,
+        beats: &Expr,
+        variables_map: &HashMap<String, String>,
+        lists_map: &HashMap<String, String>,
+        param_scope: &HashSet<String>,
+    ) -> Result<String> {
+        let block_id = self.new_block_id();
+        // NOTE takes a piano `note` shadow; a reporter covers it like any input.
+        let note_id = self.new_block_id();
+        let (note_input, note_value) = match note {
+            Expr::Number { .. } | Expr::String { .. } => (
+                json!([1, note_id.clone()]),
+                self.menu_text_from_expr(note, "60"),
+            ),
+            _ => match self.emit_expr_reporter(
+                blocks,
+                note,
+                &block_id,
+                variables_map,
+                lists_map,
+                param_scope,
+            )? {
+                Some(reporter_id) => (json!([3, reporter_id, note_id.clone()]), "60".to_string()),
+                None => (json!([1, note_id.clone()]), "60".to_string()),
+            },
+        };
+        blocks.insert(
+            note_id,
+            json!({
+                "opcode": "note",
+                "next": Value::Null,
+                "parent": block_id.clone(),
+                "inputs": {},
+                "fields": {"NOTE": [note_value, Value::Null]},
+                "shadow": true,
+                "topLevel": false
+            }),
+        );
+        let beats_input = self.expr_input(
+            blocks,
+            beats,
+            &block_id,
+            variables_map,
+            lists_map,
+            param_scope,
+            "number",
+        )?;
+        blocks.insert(
+            block_id.clone(),
+            json!({
+                "opcode": "music_playNoteForBeats",
+                "next": Value::Null,
+                "parent": parent_id,
+                "inputs": { "NOTE": note_input, "BEATS": beats_input },
+                "fields": {},
+                "shadow": false,
+                "topLevel": false
+            }),
+        );
+        Ok(block_id)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn emit_music_menu_stmt(
+        &mut self,
+        blocks: &mut Map<String, Value>,
+        parent_id: &str,
+        opcode: &str,
+        menu_name: &str,
+        menu_number: usize,
+        beats: Option<&Expr>,
+        variables_map: &HashMap<String, String>,
+        lists_map: &HashMap<String, String>,
+        param_scope: &HashSet<String>,
+    ) -> Result<String> {
+        let block_id = self.new_block_id();
+        let menu_id = self.new_block_id();
+        let mut inputs = Map::new();
+        inputs.insert(menu_name.to_string(), json!([1, menu_id.clone()]));
+        if let Some(beats) = beats {
+            let beats_input = self.expr_input(
+                blocks,
+                beats,
+                &block_id,
+                variables_map,
+                lists_map,
+                param_scope,
+                "number",
+            )?;
+            inputs.insert("BEATS".to_string(), beats_input);
+        }
+        blocks.insert(
+            block_id.clone(),
+            json!({
+                "opcode": opcode,
+                "next": Value::Null,
+                "parent": parent_id,
+                "inputs": inputs,
+                "fields": {},
                 "shadow": false,
                 "topLevel": false
             }),
         );
+        blocks.insert(
+            menu_id,
+            json!({
+                "opcode": format!("music_menu_{}", menu_name),
+                "next": Value::Null,
+                "parent": block_id.clone(),
+                "inputs": {},
+                "fields": {menu_name: [menu_number.to_string(), Value::Null]},
+                "shadow": true,
+                "topLevel": false
+            }),
+        );
         Ok(block_id)
     }
 
@@ -2446,7 +4021,32 @@ impl<'a> ProjectBuilder<'a> {
                 "next": Value::Null,
                 "parent": parent_id,
                 "inputs": {},
-                "fields": {"VARIABLE": [var_name, var_id]},
+                "fields": {"VARIABLE": [self.variable_field_name(var_name, &var_id), var_id]},
+                "shadow": false,
+                "topLevel": false
+            }),
+        );
+        Ok(block_id)
+    }
+
+    fn emit_show_hide_list_stmt(
+        &mut self,
+        blocks: &mut Map<String, Value>,
+        parent_id: &str,
+        opcode: &str,
+        list_name: &str,
+        lists_map: &HashMap<String, String>,
+    ) -> Result<String> {
+        let list_id = self.lookup_list_id(lists_map, list_name)?;
+        let block_id = self.new_block_id();
+        blocks.insert(
+            block_id.clone(),
+            json!({
+                "opcode": opcode,
+                "next": Value::Null,
+                "parent": parent_id,
+                "inputs": {},
+                "fields": {"LIST": [list_name, list_id]},
                 "shadow": false,
                 "topLevel": false
             }),
@@ -2477,6 +4077,49 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    /// Emits a broadcast whose message is computed at runtime. The reporter
+    /// covers the broadcast menu, which only serves as the obscured shadow.
+    #[allow(clippy::too_many_arguments)]
+    fn emit_dynamic_broadcast_stmt(
+        &mut self,
+        blocks: &mut Map<String, Value>,
+        parent_id: &str,
+        opcode: &str,
+        message: &Expr,
+        variables_map: &HashMap<String, String>,
+        lists_map: &HashMap<String, String>,
+        param_scope: &HashSet<String>,
+    ) -> Result<String> {
+        let block_id = self.new_block_id();
+        let bid = self.broadcast_id(DYNAMIC_BROADCAST_SHADOW);
+        let shadow = json!([11, DYNAMIC_BROADCAST_SHADOW, bid]);
+        let input = match self.emit_expr_reporter(
+            blocks,
+            message,
+            &block_id,
+            variables_map,
+            lists_map,
+            param_scope,
+        )? {
+            Some(reporter_id) => json!([3, reporter_id, shadow]),
+            None => json!([1, shadow]),
+        };
+        blocks.insert(
+            block_id.clone(),
+            json!({
+                "opcode": opcode,
+                "next": Value::Null,
+                "parent": parent_id,
+                "inputs": {"BROADCAST_INPUT": input},
+                "fields": {},
+                "shadow": false,
+                "topLevel": false
+            }),
+        );
+        Ok(block_id)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn emit_set_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -2505,7 +4148,7 @@ impl<'a> ProjectBuilder<'a> {
                 "next": Value::Null,
                 "parent": parent_id,
                 "inputs": {"VALUE": val_input},
-                "fields": {"VARIABLE": [var_name, var_id]},
+                "fields": {"VARIABLE": [self.variable_field_name(var_name, &var_id), var_id]},
                 "shadow": false,
                 "topLevel": false
             }),
@@ -2513,6 +4156,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_change_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -2541,7 +4185,7 @@ impl<'a> ProjectBuilder<'a> {
                 "next": Value::Null,
                 "parent": parent_id,
                 "inputs": {"VALUE": val_input},
-                "fields": {"VARIABLE": [var_name, var_id]},
+                "fields": {"VARIABLE": [self.variable_field_name(var_name, &var_id), var_id]},
                 "shadow": false,
                 "topLevel": false
             }),
@@ -2549,6 +4193,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_repeat_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -2597,6 +4242,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_for_each_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -2627,7 +4273,7 @@ impl<'a> ProjectBuilder<'a> {
                 "next": Value::Null,
                 "parent": parent_id,
                 "inputs": {"VALUE": value_input},
-                "fields": {"VARIABLE": [var_name, var_id]},
+                "fields": {"VARIABLE": [self.variable_field_name(var_name, &var_id), var_id]},
                 "shadow": false,
                 "topLevel": false
             }),
@@ -2647,6 +4293,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_while_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -2695,6 +4342,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_repeat_until_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -2743,6 +4391,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_forever_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -2781,6 +4430,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_if_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -2803,10 +4453,15 @@ impl<'a> ProjectBuilder<'a> {
             param_scope,
             "boolean",
         )?;
+        let opcode = if else_body.is_empty() {
+            "control_if"
+        } else {
+            "control_if_else"
+        };
         blocks.insert(
             block_id.clone(),
             json!({
-                "opcode": "control_if_else",
+                "opcode": opcode,
                 "next": Value::Null,
                 "parent": parent_id,
                 "inputs": {"CONDITION": cond_input},
@@ -2857,6 +4512,7 @@ impl<'a> ProjectBuilder<'a> {
             .and_then(|v| v.get(1).and_then(|x| x.as_str()).map(|s| s.to_string()))
             .unwrap_or_else(|| "all".to_string());
         let _ = (variables_map, lists_map, param_scope);
+        let hasnext = stop_option_allows_next(&option_text).to_string();
         blocks.insert(
             block_id.clone(),
             json!({
@@ -2867,7 +4523,7 @@ impl<'a> ProjectBuilder<'a> {
                 "fields": { "STOP_OPTION": [option_text, Value::Null]},
                 "shadow": false,
                 "topLevel": false,
-                "mutation": {"tagName": "mutation", "children": [], "hasnext": "false"}
+                "mutation": {"tagName": "mutation", "children": [], "hasnext": hasnext}
             }),
         );
         Ok(block_id)
@@ -2897,6 +4553,7 @@ impl<'a> ProjectBuilder<'a> {
         })
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_call_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -2920,10 +4577,11 @@ impl<'a> ProjectBuilder<'a> {
                         args,
                         variables_map,
                         lists_map,
+                        signatures,
                         param_scope,
                     );
                 }
-                if self.options.allow_unknown_procedures {
+                if self.options.unknown_proc_policy().allows(name) {
                     return self.emit_noop_wait_zero_stmt(blocks, parent_id);
                 }
                 return self.emit_remote_call_stmt(
@@ -2934,22 +4592,28 @@ impl<'a> ProjectBuilder<'a> {
                     args,
                     variables_map,
                     lists_map,
+                    signatures,
                     param_scope,
                 );
             }
-            if is_ignored_noop_call(name) || self.options.allow_unknown_procedures {
+            if is_ignored_noop_call(name) || self.options.unknown_proc_policy().allows(name) {
                 return self.emit_noop_wait_zero_stmt(blocks, parent_id);
             }
         }
         let Some(sig) = signatures.get(&name_lower) else {
-            if self.options.allow_unknown_procedures {
+            if self.options.unknown_proc_policy().allows(name) {
                 return self.emit_noop_wait_zero_stmt(blocks, parent_id);
             }
             return Err(anyhow!("Unknown procedure '{}' during codegen.", name));
         };
         let block_id = self.new_block_id();
         let mut inputs = Map::new();
-        for (arg_id, expr) in sig.arg_ids.iter().zip(args.iter()) {
+        for ((arg_id, kind), expr) in sig
+            .arg_ids
+            .iter()
+            .zip(sig.param_kinds.iter())
+            .zip(args.iter())
+        {
             let val = self.expr_input(
                 blocks,
                 expr,
@@ -2957,7 +4621,10 @@ impl<'a> ProjectBuilder<'a> {
                 variables_map,
                 lists_map,
                 param_scope,
-                "string",
+                match kind {
+                    ParamKind::StringNumber => "string",
+                    ParamKind::Boolean => "boolean",
+                },
             )?;
             inputs.insert(arg_id.clone(), val);
         }
@@ -2986,6 +4653,10 @@ impl<'a> ProjectBuilder<'a> {
         })
     }
 
+    /// Queues the arguments for `callee_target.callee_proc`, then either
+    /// waits for the running handler to drain the queue or starts it with
+    /// `broadcast and wait`.
+    #[allow(clippy::too_many_arguments)]
     fn emit_remote_call_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -2995,67 +4666,63 @@ impl<'a> ProjectBuilder<'a> {
         args: &[Expr],
         variables_map: &HashMap<String, String>,
         lists_map: &HashMap<String, String>,
+        signatures: &HashMap<String, ProcedureSignature>,
         param_scope: &HashSet<String>,
     ) -> Result<EmittedStatement> {
         let spec = self
             .lookup_remote_call_spec(callee_target, callee_proc, args.len())?
             .clone();
-        let mut first: Option<String> = None;
-        let mut prev: Option<String> = None;
-
-        for (idx, expr) in args.iter().enumerate() {
-            let arg_var_name = spec.arg_var_names.get(idx).ok_or_else(|| {
-                anyhow!(
-                    "Internal error: missing RPC arg variable for index {}.",
-                    idx
-                )
-            })?;
-            let arg_var_id = self.lookup_var_id(variables_map, arg_var_name)?;
-            let block_id = self.new_block_id();
-            let val_input = self.expr_input(
-                blocks,
-                expr,
-                &block_id,
-                variables_map,
-                lists_map,
-                param_scope,
-                "string",
-            )?;
-            let parent = prev.clone().unwrap_or_else(|| parent_id.to_string());
-            blocks.insert(
-                block_id.clone(),
-                json!({
-                    "opcode": "data_setvariableto",
-                    "next": Value::Null,
-                    "parent": parent,
-                    "inputs": {"VALUE": val_input},
-                    "fields": {"VARIABLE": [arg_var_name, arg_var_id]},
-                    "shadow": false,
-                    "topLevel": false
-                }),
-            );
-            if let Some(prev_id) = &prev {
-                set_block_next(blocks, prev_id, Value::String(block_id.clone()))?;
-            }
-            if first.is_none() {
-                first = Some(block_id.clone());
-            }
-            prev = Some(block_id);
-        }
-
-        let parent_for_broadcast = prev.clone().unwrap_or_else(|| parent_id.to_string());
-        let broadcast_id =
-            self.emit_broadcast_and_wait_stmt(blocks, &parent_for_broadcast, &spec.message)?;
-        if let Some(prev_id) = &prev {
-            set_block_next(blocks, prev_id, Value::String(broadcast_id.clone()))?;
-        } else {
-            first = Some(broadcast_id.clone());
+        let pos = args.first().map_or_else(|| Position::new(0, 0), Expr::pos);
+        let mut statements = spec
+            .arg_queue_names
+            .iter()
+            .zip(args)
+            .map(|(queue, expr)| Statement::AddToList {
+                pos,
+                list_name: queue.clone(),
+                item: expr.clone(),
+            })
+            .collect::<Vec<_>>();
+        statements.push(Statement::AddToList {
+            pos,
+            list_name: spec.calls_list_name.clone(),
+            item: Expr::String {
+                pos,
+                value: spec.message.clone(),
+            },
+        });
+        statements.push(Statement::If {
+            pos,
+            condition: rpc_busy_condition(&spec, pos, 1.0),
+            then_body: vec![Statement::WaitUntil {
+                pos,
+                condition: rpc_busy_condition(&spec, pos, 0.0),
+            }],
+            else_body: vec![Statement::BroadcastAndWait {
+                pos,
+                message: Expr::String {
+                    pos,
+                    value: spec.message.clone(),
+                },
+            }],
+        });
+        let (first, last) = self.emit_statement_chain(
+            blocks,
+            &statements,
+            parent_id,
+            variables_map,
+            lists_map,
+            signatures,
+            param_scope,
+        )?;
+        match (first, last) {
+            (Some(first), Some(last)) => Ok(EmittedStatement { first, last }),
+            _ => bail!(
+                "Internal error: empty RPC call for '{}.{}'.",
+                callee_target,
+                callee_proc
+            ),
         }
-
-        Ok(EmittedStatement {
-            first: first.unwrap_or_else(|| broadcast_id.clone()),
-            last: broadcast_id,
-        })
     }
 
     fn emit_broadcast_and_wait_stmt(
@@ -3081,6 +4748,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_add_to_list_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -3117,6 +4785,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_delete_of_list_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -3177,6 +4846,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_insert_at_list_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -3223,6 +4893,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn emit_replace_item_of_list_stmt(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -3269,6 +4940,7 @@ impl<'a> ProjectBuilder<'a> {
         Ok(block_id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn expr_input(
         &mut self,
         blocks: &mut Map<String, Value>,
@@ -3330,13 +5002,22 @@ impl<'a> ProjectBuilder<'a> {
         param_scope: &HashSet<String>,
     ) -> Result<Option<String>> {
         match expr {
-            Expr::Number { .. } | Expr::String { .. } => Ok(None),
+            Expr::Number { .. } | Expr::String { .. } | Expr::Color { .. } => Ok(None),
             Expr::BuiltinReporter { kind, .. } => {
                 let opcode = match kind.as_str() {
                     "answer" => "sensing_answer",
                     "mouse_x" => "sensing_mousex",
                     "mouse_y" => "sensing_mousey",
+                    "mouse_down" => "sensing_mousedown",
                     "timer" => "sensing_timer",
+                    "loudness" => "sensing_loudness",
+                    "x_position" => "motion_xposition",
+                    "y_position" => "motion_yposition",
+                    "direction" => "motion_direction",
+                    "size" => "looks_size",
+                    "username" => "sensing_username",
+                    "days_since_2000" => "sensing_dayssince2000",
+                    "tempo" => "music_getTempo",
                     _ => bail!("Unsupported built-in reporter '{}'.", kind),
                 };
                 let block_id = self.new_block_id();
@@ -3354,6 +5035,31 @@ impl<'a> ProjectBuilder<'a> {
                 );
                 Ok(Some(block_id))
             }
+            Expr::LooksNumberName {
+                backdrop,
+                number_name,
+                ..
+            } => {
+                let opcode = if *backdrop {
+                    "looks_backdropnumbername"
+                } else {
+                    "looks_costumenumbername"
+                };
+                let block_id = self.new_block_id();
+                blocks.insert(
+                    block_id.clone(),
+                    json!({
+                        "opcode": opcode,
+                        "next": Value::Null,
+                        "parent": parent_id,
+                        "inputs": {},
+                        "fields": {"NUMBER_NAME": [number_name, Value::Null]},
+                        "shadow": false,
+                        "topLevel": false
+                    }),
+                );
+                Ok(Some(block_id))
+            }
             Expr::MathFunc { op, value, .. } => {
                 let block_id = self.new_block_id();
                 let opcode = if op == "round" {
@@ -3395,11 +5101,16 @@ impl<'a> ProjectBuilder<'a> {
             Expr::Var { name, .. } => {
                 let lowered = name.to_lowercase();
                 if param_scope.contains(&lowered) {
+                    let kind = if self.current_boolean_params.contains(&lowered) {
+                        ParamKind::Boolean
+                    } else {
+                        ParamKind::StringNumber
+                    };
                     let block_id = self.new_block_id();
                     blocks.insert(
                         block_id.clone(),
                         json!({
-                            "opcode": "argument_reporter_string_number",
+                            "opcode": argument_reporter_opcode(kind),
                             "next": Value::Null,
                             "parent": parent_id,
                             "inputs": {},
@@ -3419,7 +5130,7 @@ impl<'a> ProjectBuilder<'a> {
                             "next": Value::Null,
                             "parent": parent_id,
                             "inputs": {},
-                            "fields": {"VARIABLE": [name, var_id]},
+                            "fields": {"VARIABLE": [self.variable_field_name(name, &var_id), var_id]},
                             "shadow": false,
                             "topLevel": false
                         }),
@@ -3464,7 +5175,7 @@ impl<'a> ProjectBuilder<'a> {
                         "next": Value::Null,
                         "parent": parent_id,
                         "inputs": {},
-                        "fields": {"VARIABLE": [name, var_id]},
+                        "fields": {"VARIABLE": [self.variable_field_name(name, &var_id), var_id]},
                         "shadow": false,
                         "topLevel": false
                     }),
@@ -3599,6 +5310,35 @@ impl<'a> ProjectBuilder<'a> {
                 set_block_input(blocks, &block_id, "ITEM", item_input)?;
                 Ok(Some(block_id))
             }
+            Expr::ItemNumOfList {
+                list_name, item, ..
+            } => {
+                let list_id = self.lookup_list_id(lists_map, list_name)?;
+                let block_id = self.new_block_id();
+                blocks.insert(
+                    block_id.clone(),
+                    json!({
+                        "opcode": "data_itemnumoflist",
+                        "next": Value::Null,
+                        "parent": parent_id,
+                        "inputs": {},
+                        "fields": {"LIST": [list_name, list_id]},
+                        "shadow": false,
+                        "topLevel": false
+                    }),
+                );
+                let item_input = self.expr_input(
+                    blocks,
+                    item,
+                    &block_id,
+                    variables_map,
+                    lists_map,
+                    param_scope,
+                    "string",
+                )?;
+                set_block_input(blocks, &block_id, "ITEM", item_input)?;
+                Ok(Some(block_id))
+            }
             Expr::KeyPressed { key, .. } => {
                 let block_id = self.new_block_id();
                 let menu_id = self.new_block_id();
@@ -3694,6 +5434,38 @@ impl<'a> ProjectBuilder<'a> {
                 );
                 Ok(Some(block_id))
             }
+            Expr::ColorTouchingColor { color, color2, .. } => {
+                let block_id = self.new_block_id();
+                let color_input = self.color_expr_input(
+                    blocks,
+                    color,
+                    &block_id,
+                    variables_map,
+                    lists_map,
+                    param_scope,
+                )?;
+                let color2_input = self.color_expr_input(
+                    blocks,
+                    color2,
+                    &block_id,
+                    variables_map,
+                    lists_map,
+                    param_scope,
+                )?;
+                blocks.insert(
+                    block_id.clone(),
+                    json!({
+                        "opcode": "sensing_coloristouchingcolor",
+                        "next": Value::Null,
+                        "parent": parent_id,
+                        "inputs": {"COLOR": color_input, "COLOR2": color2_input},
+                        "fields": {},
+                        "shadow": false,
+                        "topLevel": false
+                    }),
+                );
+                Ok(Some(block_id))
+            }
             Expr::StringJoin { text1, text2, .. } => {
                 let block_id = self.new_block_id();
                 blocks.insert(
@@ -3730,6 +5502,106 @@ impl<'a> ProjectBuilder<'a> {
                 set_block_input(blocks, &block_id, "STRING2", text2_input)?;
                 Ok(Some(block_id))
             }
+            Expr::StringLength { text, .. } => {
+                let block_id = self.new_block_id();
+                blocks.insert(
+                    block_id.clone(),
+                    json!({
+                        "opcode": "operator_length",
+                        "next": Value::Null,
+                        "parent": parent_id,
+                        "inputs": {},
+                        "fields": {},
+                        "shadow": false,
+                        "topLevel": false
+                    }),
+                );
+                let text_input = self.expr_input(
+                    blocks,
+                    text,
+                    &block_id,
+                    variables_map,
+                    lists_map,
+                    param_scope,
+                    "string",
+                )?;
+                set_block_input(blocks, &block_id, "STRING", text_input)?;
+                Ok(Some(block_id))
+            }
+            Expr::LetterOf { index, text, .. } => {
+                let block_id = self.new_block_id();
+                blocks.insert(
+                    block_id.clone(),
+                    json!({
+                        "opcode": "operator_letter_of",
+                        "next": Value::Null,
+                        "parent": parent_id,
+                        "inputs": {},
+                        "fields": {},
+                        "shadow": false,
+                        "topLevel": false
+                    }),
+                );
+                let letter_input = self.expr_input(
+                    blocks,
+                    index,
+                    &block_id,
+                    variables_map,
+                    lists_map,
+                    param_scope,
+                    "number",
+                )?;
+                let text_input = self.expr_input(
+                    blocks,
+                    text,
+                    &block_id,
+                    variables_map,
+                    lists_map,
+                    param_scope,
+                    "string",
+                )?;
+                set_block_input(blocks, &block_id, "LETTER", letter_input)?;
+                set_block_input(blocks, &block_id, "STRING", text_input)?;
+                Ok(Some(block_id))
+            }
+            Expr::StringContains {
+                text, substring, ..
+            } => {
+                let block_id = self.new_block_id();
+                blocks.insert(
+                    block_id.clone(),
+                    json!({
+                        "opcode": "operator_contains",
+                        "next": Value::Null,
+                        "parent": parent_id,
+                        "inputs": {},
+                        "fields": {},
+                        "shadow": false,
+                        "topLevel": false
+                    }),
+                );
+                let text_input = self.expr_input(
+                    blocks,
+                    text,
+                    &block_id,
+                    variables_map,
+                    lists_map,
+                    param_scope,
+                    "string",
+                )?;
+                let substring_input = self.expr_input(
+                    blocks,
+                    substring,
+                    &block_id,
+                    variables_map,
+                    lists_map,
+                    param_scope,
+                    "string",
+                )?;
+                set_block_input(blocks, &block_id, "STRING1", text_input)?;
+                set_block_input(blocks, &block_id, "STRING2", substring_input)?;
+                Ok(Some(block_id))
+            }
             Expr::StringSplit { text, sep, .. } => {
                 // If a reporter named "split" is declared on this target,
                 // emit a call to the synthesized reporter procedure instead
@@ -3758,7 +5630,7 @@ impl<'a> ProjectBuilder<'a> {
                     )?;
                     // map args to signature arg ids when available
                     if let Some(s) = sig_opt.as_ref() {
-                        if s.arg_ids.len() >= 1 {
+                        if !s.arg_ids.is_empty() {
                             inputs.insert(s.arg_ids[0].clone(), text_input);
                         }
                         if s.arg_ids.len() >= 2 {
@@ -4084,6 +5956,7 @@ impl<'a> ProjectBuilder<'a> {
         match expr {
             Expr::Number { value, .. } => Some(json!([4, format_num(*value)])),
             Expr::String { value, .. } => Some(json!([10, value])),
+            Expr::Color { value, .. } => Some(json!([9, value])),
             _ => None,
         }
     }
@@ -4106,7 +5979,7 @@ impl<'a> ProjectBuilder<'a> {
         lists_map: &HashMap<String, String>,
         param_scope: &HashSet<String>,
     ) -> Result<Value> {
-        if let Expr::String { value, .. } = expr {
+        if let Expr::String { value, .. } | Expr::Color { value, .. } = expr {
             return Ok(json!([1, [9, normalize_color_hex(value)]]));
         }
         let reporter_id = self.emit_expr_reporter(
@@ -4135,6 +6008,14 @@ impl<'a> ProjectBuilder<'a> {
             .ok_or_else(|| anyhow!("Variable '{}' is not declared.", var_name))
     }
 
+    /// Cloud variables keep their prefixed name in fields, however the script spelled them.
+    fn variable_field_name(&self, var_name: &str, var_id: &str) -> String {
+        self.cloud_var_names
+            .get(var_id)
+            .cloned()
+            .unwrap_or_else(|| var_name.to_string())
+    }
+
     fn lookup_list_id(
         &self,
         lists_map: &HashMap<String, String>,
@@ -4156,105 +6037,67 @@ impl<'a> ProjectBuilder<'a> {
             };
             costumes.push(crate::ast::CostumeDecl {
                 pos: target.pos,
+                name: None,
                 path: default_path.to_string(),
+                origin_dir: None,
             });
         }
 
         let mut out = Vec::new();
         let mut used_names: HashSet<String> = HashSet::new();
         for (idx, costume) in costumes.iter().enumerate() {
-            let mut rotation_center_x = 0.0;
-            let mut rotation_center_y = 0.0;
-            let (mut data, ext, base_name) = if costume.path == "__default_stage_backdrop__.svg" {
+            let (asset_key, ext, base_name) = if costume.path == "__default_stage_backdrop__.svg" {
                 (
-                    DEFAULT_STAGE_SVG.as_bytes().to_vec(),
+                    costume.path.clone(),
                     "svg".to_string(),
                     format!("backdrop{}", idx + 1),
                 )
             } else if costume.path == "__default_sprite_costume__.svg" {
                 (
-                    DEFAULT_SPRITE_SVG.as_bytes().to_vec(),
+                    costume.path.clone(),
                     "svg".to_string(),
                     format!("costume{}", idx + 1),
                 )
             } else {
-                let mut file_path = Path::new(&costume.path).to_path_buf();
-                if !file_path.is_absolute() {
-                    let mut candidates = Vec::new();
-                    candidates.push(self.source_dir.join(&file_path));
-                    if let Some(parent) = self.source_dir.parent() {
-                        candidates.push(parent.join(&file_path));
-                    }
-                    if let Ok(cwd) = std::env::current_dir() {
-                        candidates.push(cwd.join(&file_path));
-                    }
-                    if let Some(found) = candidates.iter().find(|p| p.exists()) {
-                        file_path = found.clone();
-                    } else if let Some(first) = candidates.first() {
-                        file_path = first.clone();
-                    }
-                }
-                if !file_path.exists() || !file_path.is_file() {
-                    bail!(
-                        "Costume file not found for target '{}': '{}' resolved to '{}'.",
-                        target.name,
-                        costume.path,
-                        file_path.display()
-                    );
-                }
-                let ext = file_path
-                    .extension()
-                    .and_then(|x| x.to_str())
-                    .unwrap_or("")
-                    .to_lowercase();
+                let path = self
+                    .resolver
+                    .locate(&costume.path, costume.origin_dir.as_deref());
+                let asset_key = self.resolver.resolve(&path);
+                let ext = asset_extension(&asset_key);
                 if ext != "svg" && ext != "png" {
                     bail!(
                         "Unsupported costume format '.{}' for '{}'. Only .svg and .png are supported.",
                         ext,
-                        file_path.display()
+                        asset_key
                     );
                 }
-                let data = fs::read(&file_path)?;
-                let name = file_path
-                    .file_stem()
-                    .and_then(|s| s.to_str())
-                    .unwrap_or("costume")
-                    .to_string();
-                (data, ext, name)
+                let name = costume
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| asset_stem(&asset_key, "costume"));
+                (asset_key, ext, name)
+            };
+            let name = uniquify_asset_name(&base_name, "costume", &mut used_names);
+
+            let Some(prepared) = self.cached_costume(target, costume, &asset_key, &ext)? else {
+                eprintln!(
+                    "Skipping SVG costume '{}' for target '{}' due to non-positive viewBox dimensions.",
+                    costume.path, target.name
+                );
+                continue;
             };
-            let name = uniquify_costume_name(&base_name, &mut used_names);
-
-            if ext == "svg" {
-                match self.prepare_svg(&data, &costume.path) {
-                    Ok((prepared, cx, cy)) => {
-                        data = prepared;
-                        rotation_center_x = cx;
-                        rotation_center_y = cy;
-                    }
-                    Err(err) if is_nonpositive_viewbox_error(&err) => {
-                        eprintln!(
-                            "Skipping SVG costume '{}' for target '{}' due to non-positive viewBox dimensions.",
-                            costume.path, target.name
-                        );
-                        continue;
-                    }
-                    Err(err) => return Err(err),
-                }
-            }
 
-            let digest = format!("{:x}", md5::compute(&data));
-            let md5ext = format!("{}.{}", digest, ext);
-            self.assets.insert(md5ext.clone(), data);
             let mut entry = json!({
                 "name": name,
-                "assetId": digest,
-                "md5ext": md5ext,
+                "assetId": prepared.digest,
+                "md5ext": prepared.md5ext,
                 "dataFormat": ext,
-                "rotationCenterX": rotation_center_x,
-                "rotationCenterY": rotation_center_y
+                "rotationCenterX": prepared.rotation_center_x,
+                "rotationCenterY": prepared.rotation_center_y
             });
             if ext == "png" {
-                set_value_key(&mut entry, "bitmapResolution", json!(1))?;
+                let resolution = if self.options.hd_bitmaps { 2 } else { 1 };
+                set_value_key(&mut entry, "bitmapResolution", json!(resolution))?;
             }
             out.push(entry);
         }
@@ -4267,12 +6110,13 @@ impl<'a> ProjectBuilder<'a> {
             let (prepared, cx, cy) = self.prepare_svg(fallback_svg, "__fallback_default__.svg")?;
             let digest = format!("{:x}", md5::compute(&prepared));
             let md5ext = format!("{}.svg", digest);
-            let fallback_name = uniquify_costume_name(
+            let fallback_name = uniquify_asset_name(
                 if target.is_stage {
                     "backdrop1"
                 } else {
                     "costume1"
                 },
+                "costume",
                 &mut used_names,
             );
             self.assets.insert(md5ext.clone(), prepared);
@@ -4288,26 +6132,189 @@ impl<'a> ProjectBuilder<'a> {
         Ok(out)
     }
 
-    fn prepare_svg(&self, data: &[u8], source_name: &str) -> Result<(Vec<u8>, f64, f64)> {
-        let mut root = Element::parse(Cursor::new(data))
-            .map_err(|e| anyhow!("Invalid SVG file '{}': {}.", source_name, e))?;
-        let (min_x, min_y, width, height) = self.read_svg_bounds(&root, source_name)?;
-        if self.options.scale_svgs {
-            self.normalize_svg_root(
-                &mut root,
-                min_x,
-                min_y,
-                width,
-                height,
-                DEFAULT_SVG_TARGET_SIZE,
-            )?;
-            let centered = DEFAULT_SVG_TARGET_SIZE / 2.0;
-            let mut out = Vec::new();
-            root.write(&mut out)?;
-            return Ok((out, centered, centered));
+    /// The prepared costume for `asset_key`, preparing it on first use. The
+    /// slot is reserved under the cache lock, so a builder reaching a file
+    /// another is still preparing waits for that result instead of reading
+    /// the file again.
+    fn cached_costume(
+        &mut self,
+        target: &Target,
+        costume: &crate::ast::CostumeDecl,
+        asset_key: &str,
+        ext: &str,
+    ) -> Result<Option<PreparedCostume>> {
+        let poisoned = || anyhow!("Costume cache lock was poisoned.");
+        let slot = Arc::clone(
+            self.costume_cache
+                .lock()
+                .map_err(|_| poisoned())?
+                .entry(asset_key.to_string())
+                .or_default(),
+        );
+        let mut prepared = slot.lock().map_err(|_| poisoned())?;
+        if let Some(prepared) = prepared.as_ref() {
+            return Ok(prepared.clone());
         }
-        let mut out = Vec::new();
-        root.write(&mut out)?;
+        let fresh = self.prepare_costume(target, costume, asset_key, ext)?;
+        *prepared = Some(fresh.clone());
+        Ok(fresh)
+    }
+
+    /// Reads, normalizes, and hashes one costume file, registering it as an
+    /// asset. Returns `None` for SVGs skipped due to a non-positive viewBox.
+    fn prepare_costume(
+        &mut self,
+        target: &Target,
+        costume: &crate::ast::CostumeDecl,
+        asset_key: &str,
+        ext: &str,
+    ) -> Result<Option<PreparedCostume>> {
+        let located = self
+            .resolver
+            .locate(&costume.path, costume.origin_dir.as_deref());
+        let source_name = located.as_str();
+        let source = match source_name {
+            "__default_stage_backdrop__.svg" => {
+                AssetSource::Bytes(DEFAULT_STAGE_SVG.as_bytes().to_vec())
+            }
+            "__default_sprite_costume__.svg" => {
+                AssetSource::Bytes(DEFAULT_SPRITE_SVG.as_bytes().to_vec())
+            }
+            _ => {
+                self.costume_reads += 1;
+                // SVGs are rewritten during normalization, so only PNGs can stream.
+                self.load_asset(source_name, ext == "png").map_err(|err| {
+                    anyhow!(
+                        "Cannot load costume for target '{}': {:#}",
+                        target.name,
+                        err
+                    )
+                })?
+            }
+        };
+        let (source, rotation_center_x, rotation_center_y) = if ext == "png" {
+            let (width, height) = source
+                .png_size()?
+                .ok_or_else(|| anyhow!("Invalid PNG file '{}'.", asset_key))?;
+            // Rotation centers are in bitmap pixels, which for @2x art is the doubled grid.
+            (source, f64::from(width) / 2.0, f64::from(height) / 2.0)
+        } else {
+            let AssetSource::Bytes(data) = source else {
+                bail!("SVG costume '{}' was not loaded into memory.", asset_key);
+            };
+            match self.prepare_svg(&data, source_name) {
+                Ok((prepared, cx, cy)) => (AssetSource::Bytes(prepared), cx, cy),
+                Err(err) if is_nonpositive_viewbox_error(&err) => return Ok(None),
+                Err(err) => return Err(err),
+            }
+        };
+        let digest = source.md5_hex()?;
+        let md5ext = format!("{}.{}", digest, ext);
+        self.store_asset(md5ext.clone(), source);
+        Ok(Some(PreparedCostume {
+            digest,
+            md5ext,
+            rotation_center_x,
+            rotation_center_y,
+        }))
+    }
+
+    /// Loads an asset through the resolver. When streaming, `streamable`
+    /// file-backed assets stay on disk and are only hashed.
+    fn load_asset(&self, path: &str, streamable: bool) -> Result<AssetSource> {
+        if self.stream_assets && streamable {
+            if let Some(file_path) = self.resolver.file_path(path) {
+                return Ok(AssetSource::File(file_path));
+            }
+        }
+        Ok(AssetSource::Bytes(self.resolver.read(path)?))
+    }
+
+    fn store_asset(&mut self, md5ext: String, source: AssetSource) {
+        match source {
+            AssetSource::Bytes(bytes) => {
+                self.assets.insert(md5ext, bytes);
+            }
+            AssetSource::File(path) => {
+                self.asset_files.insert(md5ext, path);
+            }
+        }
+    }
+
+    fn build_sounds(&mut self, target: &Target) -> Result<Vec<Value>> {
+        let mut out = Vec::new();
+        let mut used_names: HashSet<String> = HashSet::new();
+        for sound in &target.sounds {
+            let path = self
+                .resolver
+                .locate(&sound.path, sound.origin_dir.as_deref());
+            let asset_key = self.resolver.resolve(&path);
+            let ext = asset_extension(&asset_key);
+            if ext != "wav" && ext != "mp3" {
+                bail!(
+                    "Unsupported sound format '.{}' for '{}'. Only .wav and .mp3 are supported.",
+                    ext,
+                    asset_key
+                );
+            }
+            let source = self.load_asset(&path, true).map_err(|err| {
+                anyhow!("Cannot load sound for target '{}': {:#}", target.name, err)
+            })?;
+            let info = if ext == "wav" {
+                source
+                    .wav_info()?
+                    .ok_or_else(|| anyhow!("Invalid WAV file '{}'.", asset_key))?
+            } else {
+                // Without a readable frame the VM still decodes the file, so
+                // fall back to its default rate rather than rejecting it.
+                source.mp3_info()?.unwrap_or(WavInfo {
+                    format: "",
+                    rate: DEFAULT_MP3_RATE,
+                    sample_count: 0,
+                })
+            };
+            let base_name = sound
+                .name
+                .clone()
+                .unwrap_or_else(|| asset_stem(&asset_key, "sound"));
+            let name = uniquify_asset_name(&base_name, "sound", &mut used_names);
+
+            let digest = source.md5_hex()?;
+            let md5ext = format!("{}.{}", digest, ext);
+            self.store_asset(md5ext.clone(), source);
+            out.push(json!({
+                "name": name,
+                "assetId": digest,
+                "dataFormat": ext,
+                "format": info.format,
+                "rate": info.rate,
+                "sampleCount": info.sample_count,
+                "md5ext": md5ext
+            }));
+        }
+        Ok(out)
+    }
+
+    fn prepare_svg(&self, data: &[u8], source_name: &str) -> Result<(Vec<u8>, f64, f64)> {
+        let mut root = Element::parse(Cursor::new(data))
+            .map_err(|e| anyhow!("Invalid SVG file '{}': {}.", source_name, e))?;
+        let (min_x, min_y, width, height) = self.read_svg_bounds(&root, source_name)?;
+        if self.options.scale_svgs {
+            self.normalize_svg_root(
+                &mut root,
+                min_x,
+                min_y,
+                width,
+                height,
+                DEFAULT_SVG_TARGET_SIZE,
+            )?;
+            let centered = DEFAULT_SVG_TARGET_SIZE / 2.0;
+            let mut out = Vec::new();
+            root.write(&mut out)?;
+            return Ok((out, centered, centered));
+        }
+        let mut out = Vec::new();
+        root.write(&mut out)?;
         Ok((out, width / 2.0, height / 2.0))
     }
 
@@ -4455,35 +6462,99 @@ impl<'a> ProjectBuilder<'a> {
     }
 }
 
+/// The message a broadcast sends when it is a literal. Number literals are
+/// message names too, so `broadcast (5)` sends "5" rather than going dynamic.
+fn literal_message(message: &Expr) -> Option<String> {
+    match message {
+        Expr::String { value, .. } => Some(value.clone()),
+        Expr::Number { value, .. } => Some(format_num(*value)),
+        _ => None,
+    }
+}
+
 fn collect_messages_from_statements(statements: &[Statement], out: &mut HashSet<String>) {
-    for stmt in statements {
-        match stmt {
-            Statement::Broadcast { message, .. } => {
-                out.insert(message.clone());
-            }
-            Statement::BroadcastAndWait { message, .. } => {
-                out.insert(message.clone());
-            }
-            Statement::Repeat { body, .. }
-            | Statement::ForEach { body, .. }
-            | Statement::While { body, .. }
-            | Statement::RepeatUntil { body, .. }
-            | Statement::Forever { body, .. } => {
-                collect_messages_from_statements(body, out);
-            }
-            Statement::If {
-                then_body,
-                else_body,
-                ..
-            } => {
-                collect_messages_from_statements(then_body, out);
-                collect_messages_from_statements(else_body, out);
-            }
-            _ => {}
+    walk_statements(statements, &mut |stmt| match stmt {
+        Statement::Broadcast { message, .. } | Statement::BroadcastAndWait { message, .. } => {
+            out.insert(
+                literal_message(message).unwrap_or_else(|| DYNAMIC_BROADCAST_SHADOW.to_string()),
+            );
         }
+        Statement::RawBlock { block, .. } => visit_raw_blocks(block, &mut |raw| {
+            let field = raw
+                .get("fields")
+                .and_then(|fields| fields.get("BROADCAST_OPTION"))
+                .and_then(|value| value.get(0));
+            let primitives = raw
+                .get("inputs")
+                .and_then(Value::as_object)
+                .into_iter()
+                .flat_map(|inputs| inputs.values())
+                .filter_map(Value::as_array)
+                .flat_map(|slots| slots.iter().skip(1))
+                .filter(|slot| slot.get(0).and_then(Value::as_i64) == Some(11))
+                .filter_map(|slot| slot.get(1));
+            for name in field.into_iter().chain(primitives) {
+                if let Some(name) = name.as_str() {
+                    out.insert(name.to_string());
+                }
+            }
+        }),
+        _ => {}
+    });
+}
+
+/// Calls `visit` on a raw block and every block nested in its inputs or `next`.
+fn visit_raw_blocks(block: &Value, visit: &mut dyn FnMut(&Value)) {
+    visit(block);
+    let nested = block
+        .get("inputs")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flat_map(|inputs| inputs.values())
+        .filter_map(Value::as_array)
+        .flat_map(|slots| slots.iter().skip(1))
+        .chain(block.get("next"))
+        .filter(|nested| nested.is_object());
+    for nested in nested {
+        visit_raw_blocks(nested, visit);
     }
 }
 
+/// Extension ids (the opcode prefix) used by raw blocks, such as `pen` or
+/// `videoSensing`; core categories and the shadow block prefixes (`math`,
+/// `text`, `colour`) need no extension entry.
+fn collect_raw_extensions(statements: &[Statement], out: &mut BTreeSet<String>) {
+    const CORE_CATEGORIES: &[&str] = &[
+        "math",
+        "text",
+        "colour",
+        "motion",
+        "looks",
+        "sound",
+        "event",
+        "control",
+        "sensing",
+        "operator",
+        "data",
+        "procedures",
+        "argument",
+    ];
+    walk_statements(statements, &mut |stmt| {
+        if let Statement::RawBlock { block, .. } = stmt {
+            visit_raw_blocks(block, &mut |raw| {
+                let prefix = raw
+                    .get("opcode")
+                    .and_then(Value::as_str)
+                    .and_then(|opcode| opcode.split_once('_'))
+                    .map(|(prefix, _)| prefix);
+                if let Some(prefix) = prefix.filter(|prefix| !CORE_CATEGORIES.contains(prefix)) {
+                    out.insert(prefix.to_string());
+                }
+            });
+        }
+    });
+}
+
 fn target_uses_pen_extension(target: &Target) -> bool {
     target
         .scripts
@@ -4496,40 +6567,78 @@ fn target_uses_pen_extension(target: &Target) -> bool {
 }
 
 fn statements_use_pen_extension(statements: &[Statement]) -> bool {
-    for stmt in statements {
-        match stmt {
+    let mut found = false;
+    walk_statements(statements, &mut |stmt| {
+        found |= matches!(
+            stmt,
             Statement::PenDown { .. }
-            | Statement::PenUp { .. }
-            | Statement::PenClear { .. }
-            | Statement::PenStamp { .. }
-            | Statement::ChangePenSizeBy { .. }
-            | Statement::SetPenSizeTo { .. }
-            | Statement::ChangePenColorParamBy { .. }
-            | Statement::SetPenColorParamTo { .. } => return true,
-            Statement::Repeat { body, .. }
-            | Statement::ForEach { body, .. }
-            | Statement::While { body, .. }
-            | Statement::RepeatUntil { body, .. }
-            | Statement::Forever { body, .. } => {
-                if statements_use_pen_extension(body) {
-                    return true;
-                }
-            }
-            Statement::If {
-                then_body,
-                else_body,
-                ..
-            } => {
-                if statements_use_pen_extension(then_body)
-                    || statements_use_pen_extension(else_body)
-                {
-                    return true;
-                }
-            }
-            _ => {}
-        }
+                | Statement::PenUp { .. }
+                | Statement::PenClear { .. }
+                | Statement::PenStamp { .. }
+                | Statement::ChangePenSizeBy { .. }
+                | Statement::SetPenSizeTo { .. }
+                | Statement::ChangePenColorParamBy { .. }
+                | Statement::SetPenColorParamTo { .. }
+                | Statement::SetPenColorTo { .. }
+        );
+    });
+    found
+}
+
+/// Whether any music block or the `tempo` reporter appears in the target,
+/// including in `when ... >` hats and reporter definitions.
+fn target_uses_music_extension(target: &Target) -> bool {
+    target.scripts.iter().any(|script| {
+        matches!(&script.event_type, EventType::WhenGreaterThan { value, .. } if expr_uses_music_extension(value))
+            || statements_use_music_extension(&script.body)
+    }) || target
+        .procedures
+        .iter()
+        .any(|procedure| statements_use_music_extension(&procedure.body))
+        || target
+            .reporters
+            .iter()
+            .any(|reporter| statements_use_music_extension(&reporter.body))
+}
+
+fn statements_use_music_extension(statements: &[Statement]) -> bool {
+    let mut found = false;
+    walk_statements(statements, &mut |stmt| {
+        found |= matches!(
+            stmt,
+            Statement::PlayNoteForBeats { .. }
+                | Statement::PlayDrumForBeats { .. }
+                | Statement::RestForBeats { .. }
+                | Statement::SetInstrumentTo { .. }
+                | Statement::SetTempoTo { .. }
+                | Statement::ChangeTempoBy { .. }
+        );
+        found |= stmt.children().0.into_iter().any(expr_uses_music_extension);
+    });
+    found
+}
+
+fn expr_uses_music_extension(expr: &Expr) -> bool {
+    let mut found = false;
+    walk_exprs(expr, &mut |expr| {
+        found |= matches!(expr, Expr::BuiltinReporter { kind, .. } if kind == "tempo");
+    });
+    found
+}
+
+/// Only the "other scripts" stop option leaves the script running, so it alone
+/// can have blocks after it. The stage shows it as "other scripts in stage".
+pub(crate) fn stop_option_allows_next(option: &str) -> bool {
+    matches!(option, "other scripts in sprite" | "other scripts in stage")
+}
+
+/// The `EFFECT` field value the VM's sound menu uses for an effect name.
+fn sound_effect_field(effect: &str) -> String {
+    match effect.trim().to_ascii_lowercase().as_str() {
+        "pan" | "pan left/right" => "PAN".to_string(),
+        "pitch" => "PITCH".to_string(),
+        _ => effect.to_string(),
     }
-    false
 }
 
 fn merge_object(dst: &mut Value, add: Value) -> Result<()> {
@@ -4545,12 +6654,21 @@ fn merge_object(dst: &mut Value, add: Value) -> Result<()> {
     Ok(())
 }
 
-fn format_num(v: f64) -> String {
+pub(crate) fn format_num(v: f64) -> String {
+    if v.abs() >= 1e15 || (v != 0.0 && v.abs() < 1e-6) {
+        // Outside the range where six decimals are enough; use the shortest exact form.
+        return v.to_string();
+    }
     if (v - v.round()).abs() < 1e-9 {
         format!("{}", v.round() as i64)
     } else {
         let s = format!("{:.6}", v);
-        s.trim_end_matches('0').trim_end_matches('.').to_string()
+        let trimmed = s.trim_end_matches('0').trim_end_matches('.');
+        if trimmed.parse::<f64>().ok() == Some(v) {
+            trimmed.to_string()
+        } else {
+            v.to_string()
+        }
     }
 }
 
@@ -4576,6 +6694,13 @@ fn is_ignored_noop_call(name: &str) -> bool {
     name.eq_ignore_ascii_case("log")
 }
 
+fn argument_reporter_opcode(kind: ParamKind) -> &'static str {
+    match kind {
+        ParamKind::StringNumber => "argument_reporter_string_number",
+        ParamKind::Boolean => "argument_reporter_boolean",
+    }
+}
+
 fn default_shadow(kind: &str) -> Value {
     if kind == "number" {
         json!([4, "0"])
@@ -4594,6 +6719,15 @@ fn normalize_touching_target_menu(raw: &str) -> String {
     }
 }
 
+fn normalize_motion_target_menu(raw: &str) -> String {
+    let lowered = raw.trim().to_ascii_lowercase();
+    match lowered.as_str() {
+        "_mouse_" | "mouse" | "mouse pointer" | "mouse-pointer" => "_mouse_".to_string(),
+        "_random_" | "random" | "random position" => "_random_".to_string(),
+        _ => raw.trim().to_string(),
+    }
+}
+
 fn normalize_color_hex(raw: &str) -> String {
     let value = raw.trim();
     if value.len() == 7
@@ -4686,15 +6820,195 @@ fn set_value_key(value: &mut Value, key: &str, entry: Value) -> Result<()> {
     Ok(())
 }
 
+struct WavInfo {
+    format: &'static str,
+    rate: u32,
+    sample_count: u32,
+}
+
+/// Parses WAV headers through `read_at(offset, count)`, so files on disk can
+/// be inspected without loading their sample data. Chunk sizes that run past
+/// the end of the address space make the file invalid.
+fn read_wav_info_with(
+    len: usize,
+    read_at: &mut dyn FnMut(usize, usize) -> Option<Vec<u8>>,
+) -> Option<WavInfo> {
+    if len < 12 {
+        return None;
+    }
+    let riff = read_at(0, 12)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return None;
+    }
+    let le_u16 =
+        |bytes: &[u8], at: usize| Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?));
+    let le_u32 =
+        |bytes: &[u8], at: usize| Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?));
+
+    let mut format_code = None;
+    let mut rate = None;
+    let mut block_align = 0u32;
+    let mut fact_samples = None;
+    let mut data_len = None;
+    let mut offset: usize = 12;
+    while offset.checked_add(8)? <= len {
+        let header = read_at(offset, 8)?;
+        let chunk_id = &header[0..4];
+        let chunk_len = le_u32(&header, 4)? as usize;
+        let body = offset + 8;
+        match chunk_id {
+            b"fmt " => {
+                let fmt = read_at(body, 14)?;
+                format_code = Some(le_u16(&fmt, 0)?);
+                rate = Some(le_u32(&fmt, 4)?);
+                block_align = u32::from(le_u16(&fmt, 12)?);
+            }
+            b"fact" => fact_samples = read_at(body, 4).and_then(|bytes| le_u32(&bytes, 0)),
+            b"data" => data_len = Some(chunk_len.min(len.saturating_sub(body)) as u32),
+            _ => {}
+        }
+        // Chunks are padded to an even length.
+        offset = body.checked_add(chunk_len)?.checked_add(chunk_len & 1)?;
+    }
+
+    let format_code = format_code?;
+    let data_len = data_len?;
+    // Format code 0x11 is IMA ADPCM, which Scratch labels "adpcm"; everything else is PCM.
+    let format = if format_code == 0x11 { "adpcm" } else { "" };
+    let sample_count = match fact_samples {
+        Some(samples) => samples,
+        None if block_align > 0 => data_len / block_align,
+        None => 0,
+    };
+    Some(WavInfo {
+        format,
+        rate: rate?,
+        sample_count,
+    })
+}
+
+/// Bitrates in kbps by header index, for MPEG-1 layers I-III and then MPEG-2/2.5
+/// layer I and layers II-III.
+const MP3_BITRATES: [[u32; 15]; 5] = [
+    [
+        0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+    ],
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+    ],
+    [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ],
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+    ],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+/// Reads the sample rate of the first MPEG audio frame and counts the samples
+/// of every frame up to the first byte that is not a frame header (such as an
+/// ID3v1 tag). A leading ID3v2 tag is skipped. `None` when no frame is found.
+fn read_mp3_info_with(
+    len: usize,
+    read_at: &mut dyn FnMut(usize, usize) -> Option<Vec<u8>>,
+) -> Option<WavInfo> {
+    let mut offset = 0usize;
+    if let Some(tag) = read_at(0, 10).filter(|tag| tag.starts_with(b"ID3")) {
+        // Tag sizes are syncsafe: 7 bits per byte, excluding the header and footer.
+        let size = tag[6..10]
+            .iter()
+            .fold(0usize, |size, byte| (size << 7) | usize::from(byte & 0x7f));
+        let footer = if tag[5] & 0x10 != 0 { 10 } else { 0 };
+        offset = size.checked_add(10 + footer)?;
+    }
+
+    let mut rate = None;
+    let mut sample_count = 0u32;
+    while offset.checked_add(4)? <= len {
+        let Some(header) = read_at(offset, 4) else {
+            break;
+        };
+        let Some((frame_rate, frame_samples, frame_len)) = mp3_frame(&header) else {
+            break;
+        };
+        if *rate.get_or_insert(frame_rate) != frame_rate {
+            break;
+        }
+        sample_count = sample_count.saturating_add(frame_samples);
+        offset = offset.checked_add(frame_len)?;
+    }
+    Some(WavInfo {
+        format: "",
+        rate: rate?,
+        sample_count,
+    })
+}
+
+/// Sample rate, samples per frame, and frame length in bytes of the MPEG audio
+/// frame starting with `header`.
+fn mp3_frame(header: &[u8]) -> Option<(u32, u32, usize)> {
+    let word = u32::from_be_bytes(header.get(..4)?.try_into().ok()?);
+    if word >> 21 != 0x7ff {
+        return None;
+    }
+    // 3 = MPEG-1, 2 = MPEG-2, 0 = MPEG-2.5; layer 3 = I, 2 = II, 1 = III.
+    let version = (word >> 19) & 3;
+    let layer = (word >> 17) & 3;
+    let bitrate_index = ((word >> 12) & 0xf) as usize;
+    let rate_index = ((word >> 10) & 3) as usize;
+    let padding = (word >> 9) & 1;
+    if version == 1 || layer == 0 || bitrate_index == 0 || bitrate_index == 15 || rate_index == 3 {
+        return None;
+    }
+    let rate = [44100, 48000, 32000][rate_index] >> [2, 0, 1, 0][version as usize];
+    let table = match (version == 3, layer) {
+        (true, 3) => 0,
+        (true, 2) => 1,
+        (true, _) => 2,
+        (false, 3) => 3,
+        (false, _) => 4,
+    };
+    let bitrate = MP3_BITRATES[table][bitrate_index] * 1000;
+    let (samples, frame_len) = match layer {
+        3 => (384, (12 * bitrate / rate + padding) * 4),
+        2 => (1152, 144 * bitrate / rate + padding),
+        _ if version == 3 => (1152, 144 * bitrate / rate + padding),
+        _ => (576, 72 * bitrate / rate + padding),
+    };
+    Some((rate, samples, frame_len as usize))
+}
+
+fn emitted_variable_name(decl: &VariableDecl) -> String {
+    if decl.is_cloud {
+        format!("{}{}", CLOUD_VARIABLE_PREFIX, decl.name)
+    } else {
+        decl.name.clone()
+    }
+}
+
+/// Reads width and height from the IHDR chunk, which must directly follow the signature.
+fn read_png_size(data: &[u8]) -> Option<(u32, u32)> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    if data.len() < 24 || &data[0..8] != SIGNATURE || &data[12..16] != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(data[16..20].try_into().ok()?);
+    let height = u32::from_be_bytes(data[20..24].try_into().ok()?);
+    if width == 0 || height == 0 {
+        return None;
+    }
+    Some((width, height))
+}
+
 fn is_nonpositive_viewbox_error(err: &anyhow::Error) -> bool {
     err.to_string()
         .contains("SVG viewBox must have positive width/height")
 }
 
-fn uniquify_costume_name(base: &str, used: &mut HashSet<String>) -> String {
+fn uniquify_asset_name(base: &str, fallback: &str, used: &mut HashSet<String>) -> String {
     let trimmed = base.trim();
     let base_name = if trimmed.is_empty() {
-        "costume"
+        fallback
     } else {
         trimmed
     };
@@ -4706,3 +7020,878 @@ fn uniquify_costume_name(base: &str, used: &mut HashSet<String>) -> String {
     }
     candidate
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_project_json(source: &str, options: CodegenOptions) -> Value {
+        let project = crate::parse_and_validate_source(source).expect("source should validate");
+        let resolver = FsAssetResolver::new(Path::new("."));
+        let mut builder = ProjectBuilder::new(&project, &resolver, options);
+        let (project_json, _, _) = builder
+            .build_with_progress(&mut None)
+            .expect("project should build");
+        project_json
+    }
+
+    /// Builds on the calling thread, so sequential ids run across targets
+    /// even when the `parallel` feature is on.
+    fn build_serial_project_json(source: &str) -> Value {
+        let project = crate::parse_and_validate_source(source).expect("source should validate");
+        let resolver = FsAssetResolver::new(Path::new("."));
+        let mut builder = ProjectBuilder::new(&project, &resolver, CodegenOptions::default());
+        #[cfg(feature = "parallel")]
+        {
+            builder.parallel = false;
+        }
+        let (project_json, _, _) = builder
+            .build_with_progress(&mut None)
+            .expect("project should build");
+        project_json
+    }
+
+    fn deterministic() -> CodegenOptions {
+        CodegenOptions {
+            deterministic_ids: true,
+            ..Default::default()
+        }
+    }
+
+    fn target_json<'v>(project_json: &'v Value, name: &str) -> &'v Value {
+        project_json["targets"]
+            .as_array()
+            .expect("targets array")
+            .iter()
+            .find(|t| t["name"] == name)
+            .expect("target should exist")
+    }
+
+    const TWO_SPRITES: &str = "stage\n  var score\n  list log\nend\n\nsprite One\n  var speed\n  when flag clicked\n    set [speed] to (1)\n    broadcast [go]\n  end\nend\n\nsprite Two\n  when I receive [go]\n    change [score] by (1)\n    add (\"hit\") to [log]\n    repeat (3)\n      move (10) steps\n    end\n  end\nend\n";
+
+    #[test]
+    fn deterministic_ids_are_byte_identical_across_compiles() {
+        let first = build_project_json(TWO_SPRITES, deterministic());
+        let second = build_project_json(TWO_SPRITES, deterministic());
+        assert_eq!(
+            serde_json::to_vec_pretty(&first).unwrap(),
+            serde_json::to_vec_pretty(&second).unwrap()
+        );
+    }
+
+    #[test]
+    fn deterministic_ids_survive_edits_to_other_sprites() {
+        let edited = TWO_SPRITES.replace(
+            "    set [speed] to (1)\n",
+            "    set [speed] to (2)\n    turn right (15)\n    say (\"hi\")\n",
+        );
+        let before = build_project_json(TWO_SPRITES, deterministic());
+        let after = build_project_json(&edited, deterministic());
+
+        assert_ne!(
+            target_json(&before, "One")["blocks"],
+            target_json(&after, "One")["blocks"]
+        );
+        for name in ["Stage", "Two"] {
+            assert_eq!(
+                target_json(&before, name)["blocks"],
+                target_json(&after, name)["blocks"],
+                "blocks of '{}' changed",
+                name
+            );
+        }
+        assert_eq!(
+            target_json(&before, "Stage")["variables"],
+            target_json(&after, "Stage")["variables"]
+        );
+        assert_eq!(
+            target_json(&before, "Stage")["broadcasts"],
+            target_json(&after, "Stage")["broadcasts"]
+        );
+    }
+
+    #[test]
+    fn broadcasts_sent_from_reporters_are_declared_on_the_stage() {
+        let project_json = build_project_json(
+            "sprite Cat\n  reporter ping (n)\n    broadcast [pinged]\n  end\nend\n",
+            CodegenOptions::default(),
+        );
+        let broadcasts = target_json(&project_json, "Stage")["broadcasts"]
+            .as_object()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(broadcasts, vec![json!("pinged")]);
+    }
+
+    #[test]
+    fn stage_click_scripts_use_the_stage_hat() {
+        let project_json = build_project_json(
+            "stage\n  when this sprite clicked\n    say (\"stage\")\n  end\nend\n\nsprite Cat\n  when this sprite clicked\n    say (\"cat\")\n  end\nend\n",
+            CodegenOptions::default(),
+        );
+        let hats = |name: &str| {
+            target_json(&project_json, name)["blocks"]
+                .as_object()
+                .unwrap()
+                .values()
+                .filter(|block| block["topLevel"] == json!(true))
+                .map(|block| block["opcode"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(hats("Stage"), ["event_whenstageclicked"]);
+        assert_eq!(hats("Cat"), ["event_whenthisspriteclicked"]);
+    }
+
+    #[test]
+    fn sequential_ids_shift_when_an_earlier_sprite_grows() {
+        let edited = TWO_SPRITES.replace(
+            "    set [speed] to (1)\n",
+            "    set [speed] to (2)\n    say (\"hi\")\n",
+        );
+        let before = build_serial_project_json(TWO_SPRITES);
+        let after = build_serial_project_json(&edited);
+        assert_ne!(
+            target_json(&before, "Two")["blocks"],
+            target_json(&after, "Two")["blocks"]
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_sequential_ids_are_numbered_per_target() {
+        let edited = TWO_SPRITES.replace(
+            "    set [speed] to (1)\n",
+            "    set [speed] to (2)\n    say (\"hi\")\n",
+        );
+        let before = build_project_json(TWO_SPRITES, CodegenOptions::default());
+        let after = build_project_json(&edited, CodegenOptions::default());
+        let two = target_json(&before, "Two");
+        assert_eq!(two["blocks"], target_json(&after, "Two")["blocks"]);
+        assert!(two["blocks"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .all(|id| id.starts_with("t2_block_")));
+    }
+
+    #[test]
+    fn deterministic_variable_ids_hash_their_names() {
+        let project_json = build_project_json(TWO_SPRITES, deterministic());
+        let stage = target_json(&project_json, "Stage");
+        assert!(stage["variables"]
+            .as_object()
+            .unwrap()
+            .contains_key(&hashed_id("gvar", "score")));
+        assert!(stage["lists"]
+            .as_object()
+            .unwrap()
+            .contains_key(&hashed_id("glist", "log")));
+        assert!(stage["broadcasts"]
+            .as_object()
+            .unwrap()
+            .contains_key(&hashed_id("broadcast", "go")));
+        let one = target_json(&project_json, "One");
+        assert!(one["variables"]
+            .as_object()
+            .unwrap()
+            .contains_key(&hashed_id("var", "one/speed")));
+    }
+
+    #[test]
+    fn compact_json_parses_identically_and_is_smaller() {
+        let mut source = String::from("sprite Big\n  var n\n  when flag clicked\n");
+        for i in 0..500 {
+            source.push_str(&format!(
+                "    if <(n) > ({})> then\n      change [n] by (1)\n    end\n",
+                i
+            ));
+        }
+        source.push_str("  end\nend\n");
+        let project = crate::parse_and_validate_source(&source).expect("source should validate");
+        let project_json = |pretty_json| {
+            let options = CodegenOptions {
+                pretty_json,
+                ..Default::default()
+            };
+            let bytes =
+                build_sb3_bytes(&project, Path::new("."), options).expect("project should build");
+            let mut zip = zip::ZipArchive::new(Cursor::new(bytes)).expect("sb3 should open");
+            let mut text = String::new();
+            std::io::Read::read_to_string(
+                &mut zip.by_name("project.json").expect("project.json"),
+                &mut text,
+            )
+            .expect("project.json should read");
+            text
+        };
+        let pretty = project_json(true);
+        let compact = project_json(false);
+        assert_eq!(
+            serde_json::from_str::<Value>(&pretty).unwrap(),
+            serde_json::from_str::<Value>(&compact).unwrap()
+        );
+        assert!(
+            compact.len() * 2 < pretty.len(),
+            "compact {} vs pretty {}",
+            compact.len(),
+            pretty.len()
+        );
+    }
+
+    #[test]
+    fn asset_compression_round_trips_asset_bytes() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 0, 16, 0, 0, 0, 16, 8, 6, 0, 0, 0, 0, 0, 0, 0]);
+        fs::write(dir.path().join("art.png"), &png).expect("png should write");
+        let source = "sprite Cat\n  costume \"art.png\"\n  when flag clicked\n    move (10) steps\n  end\nend\n";
+        let project = crate::parse_and_validate_source(source).expect("source should validate");
+        for (asset_compression, zip_level) in [
+            (AssetCompression::Auto, None),
+            (AssetCompression::Stored, Some(9)),
+            (AssetCompression::Deflate, Some(1)),
+        ] {
+            let options = CodegenOptions {
+                asset_compression,
+                zip_level,
+                ..Default::default()
+            };
+            let bytes =
+                build_sb3_bytes(&project, dir.path(), options).expect("project should build");
+            let mut zip = zip::ZipArchive::new(Cursor::new(bytes)).expect("sb3 should open");
+            let png_name = format!("{:x}.png", md5::compute(&png));
+            let mut entry = zip.by_name(&png_name).expect("png asset");
+            let expected_method = if asset_compression == AssetCompression::Deflate {
+                zip::CompressionMethod::Deflated
+            } else {
+                zip::CompressionMethod::Stored
+            };
+            assert_eq!(entry.compression(), expected_method);
+            let mut data = Vec::new();
+            std::io::Read::read_to_end(&mut entry, &mut data).expect("png should read");
+            assert_eq!(data, png);
+            drop(entry);
+            let entry = zip.by_name("project.json").expect("project.json");
+            assert_eq!(entry.compression(), zip::CompressionMethod::Deflated);
+        }
+    }
+
+    #[test]
+    fn shared_costume_files_are_read_once() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        fs::write(
+            dir.path().join("tiles.svg"),
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 32 32\"><rect width=\"32\" height=\"32\"/></svg>",
+        )
+        .expect("svg should write");
+        let mut source = String::new();
+        for i in 0..5 {
+            source.push_str(&format!(
+                "sprite Tile{}\n  costume \"tiles.svg\"\n  costume \"Again\" \"./tiles.svg\"\nend\n",
+                i
+            ));
+        }
+        let project = crate::parse_and_validate_source(&source).expect("source should validate");
+        let resolver = FsAssetResolver::new(dir.path());
+        let mut builder = ProjectBuilder::new(&project, &resolver, CodegenOptions::default());
+        let (project_json, assets, _) = builder
+            .build_with_progress(&mut None)
+            .expect("project should build");
+        assert_eq!(builder.costume_reads, 1);
+
+        let md5ext = project_json["targets"][1]["costumes"][0]["md5ext"]
+            .as_str()
+            .expect("md5ext")
+            .to_string();
+        assert_eq!(assets.keys().filter(|name| **name == md5ext).count(), 1);
+        for target in project_json["targets"].as_array().unwrap().iter().skip(1) {
+            for costume in target["costumes"].as_array().unwrap() {
+                assert_eq!(costume["md5ext"], md5ext.as_str());
+                assert_eq!(costume["rotationCenterX"], 32.0);
+            }
+        }
+    }
+
+    #[test]
+    fn mp3_sample_counts_come_from_frame_headers() {
+        // An empty ID3v2 tag, three MPEG-1 layer III frames at 128 kbps and
+        // 44.1 kHz (417 bytes each), then an ID3v1 tag.
+        let mut mp3 = b"ID3\x04\0\0\0\0\0\0".to_vec();
+        for _ in 0..3 {
+            mp3.extend_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+            mp3.resize(mp3.len() + 413, 0);
+        }
+        mp3.extend_from_slice(b"TAG");
+        let mut assets = HashMap::new();
+        assets.insert("boom.mp3".to_string(), mp3);
+        let project = crate::parse_and_validate_source("sprite Hero\n  sound \"boom.mp3\"\nend\n")
+            .expect("source should validate");
+        let bytes = build_sb3_bytes_with_assets(&project, &assets, CodegenOptions::default())
+            .expect("project should build");
+        let archive = crate::sb3::read_sb3_bytes(&bytes).expect("sb3 should read");
+        let sound = &target_json(&archive.project, "Hero")["sounds"][0];
+        assert_eq!(sound["rate"], 44100);
+        assert_eq!(sound["sampleCount"], 3 * 1152);
+
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&[1, 0, 1, 0]);
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&[0x40, 0x1f, 0, 0, 2, 0, 16, 0]);
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&u32::MAX.to_le_bytes());
+        wav.extend_from_slice(&[0; 8]);
+        let info = AssetSource::Bytes(wav)
+            .wav_info()
+            .unwrap()
+            .expect("wav should parse");
+        assert_eq!((info.rate, info.sample_count), (8000, 4));
+    }
+
+    #[test]
+    fn in_memory_assets_supply_costumes_and_sounds() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 0, 40, 0, 0, 0, 20, 8, 6, 0, 0, 0, 0, 0, 0, 0]);
+        let mut assets = HashMap::new();
+        assets.insert("art/hero.png".to_string(), png.clone());
+        assets.insert("boom.mp3".to_string(), b"ID3".to_vec());
+        let source = "sprite Hero\n  costume \"./art/hero.png\"\n  sound \"boom.mp3\"\nend\n";
+        let project = crate::parse_and_validate_source(source).expect("source should validate");
+
+        let bytes = build_sb3_bytes_with_assets(&project, &assets, CodegenOptions::default())
+            .expect("project should build");
+        let archive = crate::sb3::read_sb3_bytes(&bytes).expect("sb3 should read");
+        let hero = target_json(&archive.project, "Hero");
+        assert_eq!(hero["costumes"][0]["name"], "hero");
+        assert_eq!(hero["costumes"][0]["rotationCenterX"], 20.0);
+        assert_eq!(hero["sounds"][0]["name"], "boom");
+        let md5ext = hero["costumes"][0]["md5ext"].as_str().unwrap();
+        assert_eq!(archive.assets.get(md5ext), Some(&png));
+
+        let err = build_sb3_bytes_with_assets(&project, &HashMap::new(), CodegenOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("'./art/hero.png' is not among the provided assets"),
+            "{}",
+            err
+        );
+
+        let source = "sprite Hero\n  costume \"./art/hero.png\"\n  sound \"boom.ogg\"\nend\n";
+        let project = crate::parse_and_validate_source(source).expect("source should validate");
+        let err = build_sb3_bytes_with_assets(&project, &assets, CodegenOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("Unsupported sound format '.ogg' for 'boom.ogg'"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn streaming_writer_matches_in_memory_archive() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 1, 0, 0, 0, 0, 128, 8, 6, 0, 0, 0, 0, 0, 0, 0]);
+        png.extend((0..3 * 1024 * 1024).map(|i: u32| (i.wrapping_mul(2_654_435_761) >> 24) as u8));
+        fs::write(dir.path().join("big.png"), &png).expect("png should write");
+        let samples: u32 = 1024 * 1024;
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(36 + samples * 2).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&[1, 0, 1, 0]);
+        wav.extend_from_slice(&22050u32.to_le_bytes());
+        wav.extend_from_slice(&44100u32.to_le_bytes());
+        wav.extend_from_slice(&[2, 0, 16, 0]);
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(samples * 2).to_le_bytes());
+        wav.extend((0..samples * 2).map(|i| (i % 251) as u8));
+        fs::write(dir.path().join("loop.wav"), &wav).expect("wav should write");
+        let source = "sprite Big\n  costume \"big.png\"\n  costume \"art\" \"big.png\"\n  sound \"loop.wav\"\nend\n";
+        let project = crate::parse_and_validate_source(source).expect("source should validate");
+
+        let in_memory = build_sb3_bytes(&project, dir.path(), CodegenOptions::default())
+            .expect("project should build");
+        let output = dir.path().join("out/streamed.sb3");
+        write_sb3_streaming(
+            &project,
+            dir.path(),
+            &output,
+            CodegenOptions::default(),
+            Option::<&mut fn(usize, usize, &str)>::None,
+        )
+        .expect("project should stream");
+        let streamed = fs::read(&output).expect("output should read");
+
+        let expected = crate::sb3::read_sb3_bytes(&in_memory).expect("sb3 should read");
+        let actual = crate::sb3::read_sb3_bytes(&streamed).expect("sb3 should read");
+        assert_eq!(actual.project, expected.project);
+        assert_eq!(actual.assets, expected.assets);
+        let sound = &target_json(&actual.project, "Big")["sounds"][0];
+        assert_eq!(sound["rate"], 22050);
+        assert_eq!(sound["sampleCount"], samples);
+        assert_eq!(
+            target_json(&actual.project, "Big")["costumes"][0]["rotationCenterX"],
+            128.0
+        );
+    }
+
+    #[test]
+    fn project_json_matches_the_archive_and_lists_assets() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 0, 4, 0, 0, 0, 2, 8, 6, 0, 0, 0]);
+        fs::write(dir.path().join("hero.png"), &png).expect("png should write");
+        let source = "stage\nend\n\nsprite Hero\n  costume \"hero.png\"\nend\n";
+        let project = crate::parse_and_validate_source(source).expect("source should validate");
+
+        let (project_json, assets) =
+            super::build_project_json(&project, dir.path(), deterministic())
+                .expect("project json should build");
+        let archive =
+            build_sb3_bytes(&project, dir.path(), deterministic()).expect("project should build");
+        let archive = crate::sb3::read_sb3_bytes(&archive).expect("sb3 should read");
+        assert_eq!(project_json, archive.project);
+
+        let mut names = archive.assets.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            assets
+                .iter()
+                .map(|asset| asset.md5ext.clone())
+                .collect::<Vec<_>>(),
+            names
+        );
+        let costume = &target_json(&project_json, "Hero")["costumes"][0];
+        let hero = assets
+            .iter()
+            .find(|asset| costume["md5ext"] == asset.md5ext.as_str())
+            .expect("costume should be listed");
+        assert_eq!(
+            hero.path.as_deref(),
+            Some(dir.path().join("hero.png").as_path())
+        );
+        assert!(
+            assets.iter().any(|asset| asset.path.is_none()),
+            "stage backdrop is generated"
+        );
+
+        let missing =
+            crate::parse_and_validate_source("sprite Ghost\n  costume \"ghost.png\"\nend\n")
+                .expect("source should validate");
+        let err = super::build_project_json(&missing, dir.path(), deterministic())
+            .expect_err("missing costume should fail");
+        assert!(
+            err.to_string()
+                .contains("Cannot load costume for target 'Ghost': File not found"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn stats_count_what_the_archive_contains() {
+        let project =
+            crate::parse_and_validate_source(TWO_SPRITES).expect("source should validate");
+        let (bytes, stats) = build_sb3_bytes_with_stats(&project, &HashMap::new(), deterministic())
+            .expect("project should build");
+        let archive = crate::sb3::read_sb3_bytes(&bytes).expect("sb3 should read");
+        let blocks = archive.project["targets"]
+            .as_array()
+            .expect("targets array")
+            .iter()
+            .map(|target| target["blocks"].as_object().map_or(0, Map::len))
+            .sum::<usize>();
+
+        assert_eq!((stats.sprites, stats.scripts, stats.procedures), (2, 2, 0));
+        assert_eq!((stats.broadcasts, stats.variables, stats.lists), (1, 2, 1));
+        assert_eq!(stats.blocks, blocks);
+        assert_eq!(stats.assets, archive.assets.len());
+        assert_eq!(
+            stats.asset_bytes,
+            archive
+                .assets
+                .values()
+                .map(|data| data.len() as u64)
+                .sum::<u64>()
+        );
+        assert_eq!(stats.archive_bytes, bytes.len() as u64);
+        assert!(stats.render_table().contains("Broadcasts  1\n"));
+    }
+
+    /// Opcodes along a `next` chain starting at `first`.
+    fn chain_opcodes(blocks: &Map<String, Value>, first: Option<&str>) -> Vec<String> {
+        let mut out = Vec::new();
+        let mut current = first.map(str::to_string);
+        while let Some(id) = current {
+            out.push(blocks[&id]["opcode"].as_str().unwrap().to_string());
+            current = blocks[&id]["next"].as_str().map(str::to_string);
+        }
+        out
+    }
+
+    fn chain_last(blocks: &Map<String, Value>, first: Option<&str>) -> String {
+        let mut current = first.unwrap().to_string();
+        while let Some(next) = blocks[&current]["next"].as_str() {
+            current = next.to_string();
+        }
+        current
+    }
+
+    #[test]
+    fn remote_calls_queue_arguments_for_a_draining_handler() {
+        let source = "sprite Enemy\n  var hp\n  define hit (amount) (kind)\n    change [hp] by (amount)\n  end\nend\n\nsprite Player\n  when flag clicked\n    Enemy.hit (5) (\"fire\")\n  end\n  when this sprite clicked\n    Enemy.hit (2) (\"ice\")\n  end\nend\n";
+        let project_json = build_project_json(source, CodegenOptions::default());
+        let stage = target_json(&project_json, "Stage");
+        let names = |key: &str| {
+            let mut names = stage[key]
+                .as_object()
+                .unwrap()
+                .values()
+                .map(|v| v[0].as_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        assert_eq!(
+            names("lists"),
+            [
+                "__rpc__enemy__hit__arg1__queue",
+                "__rpc__enemy__hit__arg2__queue",
+                "__rpc__enemy__hit__calls",
+            ]
+        );
+        assert_eq!(
+            names("variables"),
+            [
+                "__rpc__enemy__hit__arg1",
+                "__rpc__enemy__hit__arg2",
+                "__rpc__enemy__hit__busy",
+            ]
+        );
+
+        let enemy = target_json(&project_json, "Enemy");
+        let blocks = enemy["blocks"].as_object().unwrap();
+        let (hat_id, _) = blocks
+            .iter()
+            .find(|(_, b)| b["opcode"] == "event_whenbroadcastreceived")
+            .expect("handler hat");
+        let first = blocks[hat_id]["next"].as_str();
+        assert_eq!(
+            chain_opcodes(blocks, first),
+            [
+                "data_setvariableto",
+                "control_repeat_until",
+                "data_setvariableto"
+            ]
+        );
+        let loop_id = blocks[first.unwrap()]["next"].as_str().unwrap();
+        let loop_body = blocks[loop_id]["inputs"]["SUBSTACK"][1].as_str();
+        assert_eq!(
+            chain_opcodes(blocks, loop_body),
+            [
+                "data_setvariableto",
+                "data_deleteoflist",
+                "data_setvariableto",
+                "data_deleteoflist",
+                "data_deleteoflist",
+                "procedures_call"
+            ]
+        );
+
+        let player = target_json(&project_json, "Player");
+        let blocks = player["blocks"].as_object().unwrap();
+        let hats = blocks
+            .values()
+            .filter(|b| b["topLevel"] == true)
+            .collect::<Vec<_>>();
+        assert_eq!(hats.len(), 2);
+        for hat in hats {
+            let first = hat["next"].as_str();
+            assert_eq!(
+                chain_opcodes(blocks, first),
+                [
+                    "data_addtolist",
+                    "data_addtolist",
+                    "data_addtolist",
+                    "control_if_else"
+                ]
+            );
+            let if_id = chain_last(blocks, first);
+            let branch =
+                |input: &str| chain_opcodes(blocks, blocks[&if_id]["inputs"][input][1].as_str());
+            assert_eq!(branch("SUBSTACK"), ["control_wait_until"]);
+            assert_eq!(branch("SUBSTACK2"), ["event_broadcastandwait"]);
+        }
+        assert!(!blocks.values().any(|b| b["opcode"] == "data_setvariableto"));
+    }
+
+    #[test]
+    fn raw_block_extensions_skip_shadows_and_include_reporters() {
+        let source = "sprite Cat\n  reporter spun (n)\n    raw {\"opcode\": \"gadget_spin\", \"inputs\": {\"N\": [1, {\"opcode\": \"math_number\", \"shadow\": true, \"fields\": {\"NUM\": [\"1\"]}}]}}\n  end\n  when flag clicked\n    raw {\"opcode\": \"looks_say\", \"inputs\": {\"MESSAGE\": [1, {\"opcode\": \"text\", \"shadow\": true, \"fields\": {\"TEXT\": [\"hi\"]}}], \"COLOR\": [1, {\"opcode\": \"colour_picker\", \"shadow\": true, \"fields\": {\"COLOUR\": [\"#ff0000\"]}}]}}\n  end\nend\n";
+        let project_json = build_project_json(source, CodegenOptions::default());
+        assert_eq!(project_json["extensions"], json!(["gadget"]));
+    }
+
+    #[test]
+    fn tempo_reporter_alone_needs_the_music_extension() {
+        for source in [
+            "sprite Cat\n  when timer > (tempo)\n    move (1)\n  end\nend\n",
+            "sprite Cat\n  reporter beat (n)\n    say (join (tempo) (n))\n  end\nend\n",
+        ] {
+            let project_json = build_project_json(source, CodegenOptions::default());
+            assert_eq!(project_json["extensions"], json!(["music"]), "{}", source);
+        }
+    }
+
+    #[test]
+    fn variables_and_parameters_shadow_word_reporters() {
+        let source = "stage\n  var Tempo\nend\n\nsprite Cat\n  var loudness\n  define username (x)\n  end\n  define greet (username)\n    say (username)\n  end\n  define aim (direction)\n    say (direction)\n  end\n  define grow (size)\n    say (size)\n  end\n  when this sprite clicked\n    say (direction)\n    say (size)\n  end\n  when flag clicked\n    set [loudness] to (1)\n    say (join (loudness) (tempo))\n    username (1)\n    ask (username)\n  end\nend\n";
+        let project_json = build_project_json(source, CodegenOptions::default());
+        let cat = target_json(&project_json, "Cat");
+        let blocks = cat["blocks"].as_object().unwrap();
+        let opcodes = |opcode: &str| {
+            blocks
+                .values()
+                .filter(|block| block["opcode"] == opcode)
+                .collect::<Vec<_>>()
+        };
+        let mut variables = opcodes("data_variable")
+            .iter()
+            .map(|block| block["fields"]["VARIABLE"][0].as_str().unwrap())
+            .collect::<Vec<_>>();
+        variables.sort();
+        assert_eq!(variables, ["Tempo", "loudness"]);
+        assert_eq!(opcodes("sensing_loudness").len(), 0);
+        assert_eq!(opcodes("music_getTempo").len(), 0);
+        assert_eq!(opcodes("sensing_username").len(), 1);
+        assert_eq!(opcodes("motion_direction").len(), 1);
+        assert_eq!(opcodes("looks_size").len(), 1);
+        for param in ["username", "direction", "size"] {
+            assert!(opcodes("argument_reporter_string_number")
+                .iter()
+                .any(|block| block["fields"]["VALUE"][0] == param));
+        }
+        assert_eq!(opcodes("procedures_call").len(), 1);
+
+        let source = "sprite Dog\n  var direction\n  var size\n  when flag clicked\n    say (direction)\n    say (size)\n  end\nend\n";
+        let project_json = build_project_json(source, CodegenOptions::default());
+        let dog = target_json(&project_json, "Dog");
+        let blocks = dog["blocks"].as_object().unwrap();
+        assert!(!blocks
+            .values()
+            .any(|b| b["opcode"] == "motion_direction" || b["opcode"] == "looks_size"));
+        for name in ["direction", "size"] {
+            assert!(blocks
+                .values()
+                .any(|b| b["opcode"] == "data_variable" && b["fields"]["VARIABLE"][0] == name));
+        }
+    }
+
+    #[test]
+    fn literal_broadcasts_use_static_message_names() {
+        let source = "sprite Cat\n  var next\n  when flag clicked\n    broadcast (5)\n    broadcast and wait (\"go\")\n    broadcast (next)\n  end\nend\n";
+        let project_json = build_project_json(source, CodegenOptions::default());
+        let cat = target_json(&project_json, "Cat");
+        let blocks = cat["blocks"].as_object().unwrap();
+        let (hat_id, _) = blocks.iter().find(|(_, b)| b["topLevel"] == true).unwrap();
+        let mut inputs = Vec::new();
+        let mut current = blocks[hat_id]["next"].as_str();
+        while let Some(id) = current {
+            inputs.push(blocks[id]["inputs"]["BROADCAST_INPUT"].clone());
+            current = blocks[id]["next"].as_str();
+        }
+        assert_eq!(inputs[0][0], 1);
+        assert_eq!(inputs[0][1][1], "5");
+        assert_eq!(inputs[1][1][1], "go");
+        assert_eq!(inputs[2][0], 3);
+        assert_eq!(inputs[2][2][1], "message1");
+
+        let stage = target_json(&project_json, "Stage");
+        let mut messages = stage["broadcasts"]
+            .as_object()
+            .unwrap()
+            .values()
+            .map(|v| v.as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        messages.sort();
+        assert_eq!(messages, ["5", "go", "message1"]);
+    }
+
+    #[test]
+    fn green_flag_remote_calls_are_not_cleared_by_another_flag_script() {
+        let source = "sprite Enemy\n  var hp\n  define hit (amount)\n    change [hp] by (amount)\n  end\nend\n\nsprite Player\n  when flag clicked\n    Enemy.hit (5)\n  end\nend\n";
+        let project_json = build_project_json(source, CodegenOptions::default());
+        // Sprites' flag scripts start before the stage's, so a stage reset script
+        // would empty the queue right after the call was made.
+        for target in project_json["targets"].as_array().unwrap() {
+            let blocks = target["blocks"].as_object().unwrap();
+            assert!(
+                !blocks
+                    .values()
+                    .any(|b| b["opcode"] == "data_deletealloflist"),
+                "{}",
+                target["name"]
+            );
+            let flag_hats = blocks
+                .values()
+                .filter(|b| b["opcode"] == "event_whenflagclicked")
+                .count();
+            let expected = usize::from(target["name"] == "Player");
+            assert_eq!(flag_hats, expected, "{}", target["name"]);
+        }
+
+        let player = target_json(&project_json, "Player");
+        let blocks = player["blocks"].as_object().unwrap();
+        let hat = blocks
+            .values()
+            .find(|b| b["opcode"] == "event_whenflagclicked")
+            .unwrap();
+        assert_eq!(
+            chain_opcodes(blocks, hat["next"].as_str()),
+            ["data_addtolist", "data_addtolist", "control_if_else"]
+        );
+    }
+
+    #[test]
+    fn pinned_layers_fill_free_slots_in_declaration_order() {
+        let source = "stage\nend\n\nsprite A\n  layer 9\nend\n\nsprite B\nend\n\nsprite C\n  layer 2\nend\n\nsprite D\nend\n";
+        let project_json = build_project_json(source, CodegenOptions::default());
+        let layers = ["Stage", "B", "C", "D", "A"].map(|name| {
+            target_json(&project_json, name)["layerOrder"]
+                .as_i64()
+                .unwrap()
+        });
+        assert_eq!(layers, [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn scripts_are_laid_out_without_overlap() {
+        let mut source = String::from("sprite Long\n  var n\n  when flag clicked\n");
+        for _ in 0..200 {
+            source.push_str("    change [n] by (1)\n");
+        }
+        source.push_str("  end\n  when this sprite clicked\n    set [n] to (0)\n  end\n");
+        source.push_str("  define tick\n    repeat (2)\n      change [n] by (1)\n    end\n  end\n");
+        source.push_str("  define tock\n    tick\n  end\nend\n");
+        let project_json = build_project_json(&source, CodegenOptions::default());
+        let blocks = target_json(&project_json, "Long")["blocks"]
+            .as_object()
+            .expect("blocks object");
+        let top_level = |opcodes: &[&str]| {
+            let mut placed = blocks
+                .values()
+                .filter(|b| {
+                    b["topLevel"] == true && opcodes.contains(&b["opcode"].as_str().unwrap())
+                })
+                .map(|b| {
+                    (
+                        b["x"].as_i64().unwrap() as i32,
+                        b["y"].as_i64().unwrap() as i32,
+                    )
+                })
+                .collect::<Vec<_>>();
+            placed.sort_by_key(|(_, y)| *y);
+            placed
+        };
+
+        let scripts = top_level(&["event_whenflagclicked", "event_whenthisspriteclicked"]);
+        assert_eq!(scripts.len(), 2);
+        assert_eq!(scripts[0].0, scripts[1].0);
+        assert!(
+            scripts[1].1 - scripts[0].1 >= 200 * STACK_BLOCK_HEIGHT,
+            "{:?}",
+            scripts
+        );
+
+        let procedures = top_level(&["procedures_definition"]);
+        assert_eq!(procedures.len(), 2);
+        assert!(procedures[0].0 < scripts[0].0);
+        assert!(procedures[1].1 - procedures[0].1 >= HAT_BLOCK_HEIGHT + 3 * STACK_BLOCK_HEIGHT);
+        // Procedures do not push event scripts down.
+        assert_eq!(scripts[0].1, procedures[0].1);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_emission_matches_serial_output() {
+        let mut source = String::from("stage\n  var score\nend\n");
+        for i in 0..12 {
+            source.push_str(&format!(
+                "sprite S{i}\n  var speed\n  when flag clicked\n    show variable [speed]\n    broadcast [tick{i}]\n    change [score] by (1)\n  end\n  when I receive [tick{i}]\n    repeat (3)\n      move (speed) steps\n    end\n  end\nend\n"
+            ));
+        }
+        let project = crate::parse_and_validate_source(&source).expect("source should validate");
+        let resolver = FsAssetResolver::new(Path::new("."));
+        let build = |parallel: bool| {
+            let mut steps = Vec::new();
+            let mut record = |step: usize, _total: usize, label: &str| {
+                if label == "Emitting targets" {
+                    steps.push(step);
+                }
+            };
+            let mut progress: Option<&mut CodegenProgressCallback<'_>> = Some(&mut record);
+            let mut builder = ProjectBuilder::new(&project, &resolver, deterministic());
+            builder.parallel = parallel;
+            let built = builder
+                .build_with_progress(&mut progress)
+                .expect("project should build");
+            (built, steps)
+        };
+
+        let (parallel, parallel_steps) = build(true);
+        let (serial, serial_steps) = build(false);
+        assert_eq!(
+            serde_json::to_vec_pretty(&parallel.0).unwrap(),
+            serde_json::to_vec_pretty(&serial.0).unwrap()
+        );
+        assert_eq!(parallel.1, serial.1);
+        assert_eq!(parallel_steps, serial_steps);
+        assert_eq!(serial_steps, (1..=13).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn unknown_calls_become_noops_only_when_the_policy_allows_them() {
+        let source = "sprite Cat\n  when flag clicked\n    SaveGame\n  end\nend\n";
+        let tokens = crate::lexer::Lexer::new(source).tokenize().unwrap();
+        let project = crate::parser::Parser::new(tokens).parse_project().unwrap();
+        let resolver = FsAssetResolver::new(Path::new("."));
+        let build = |allow_unknown: UnknownProcPolicy| {
+            let options = CodegenOptions {
+                allow_unknown,
+                ..Default::default()
+            };
+            ProjectBuilder::new(&project, &resolver, options)
+                .build_with_progress(&mut None)
+                .map(|(project_json, _, _)| project_json)
+        };
+
+        let project_json = build(UnknownProcPolicy::Names(vec!["savegame".to_string()]))
+            .expect("listed call should build");
+        let blocks = target_json(&project_json, "Cat")["blocks"]
+            .as_object()
+            .expect("blocks object");
+        assert!(blocks.values().any(|b| b["opcode"] == "control_wait"));
+
+        let err = build(UnknownProcPolicy::Names(vec!["LoadGame".to_string()])).unwrap_err();
+        assert!(
+            err.to_string().contains("Unknown procedure 'SaveGame'"),
+            "{}",
+            err
+        );
+
+        #[allow(deprecated)]
+        let options = CodegenOptions {
+            allow_unknown_procedures: true,
+            ..Default::default()
+        };
+        assert_eq!(options.unknown_proc_policy(), &UnknownProcPolicy::All);
+        ProjectBuilder::new(&project, &resolver, options)
+            .build_with_progress(&mut None)
+            .expect("the deprecated flag should still allow every call");
+    }
+}
@@ -1,27 +1,191 @@
-use crate::sb3::read_sb3_file;
+use crate::codegen::CLOUD_VARIABLE_PREFIX;
+use crate::decompile_sb2::{is_sb2_project, sb2_to_sb3, UNSUPPORTED_OPCODE as SB2_UNSUPPORTED};
+use crate::imports::STDIN_NAME;
+use crate::music::{MUSIC_DRUMS, MUSIC_INSTRUMENTS};
+use crate::rpc::{rpc_names, RPC_PREFIX};
+use crate::sb3::{read_sb3_bytes, read_sb3_file, Sb3Archive};
 use anyhow::{anyhow, Context, Result};
-use serde_json::{Map, Value};
-use std::collections::{HashMap, HashSet};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 
 type ProgressCallback<'a> = dyn FnMut(usize, usize, &str) + 'a;
 
-pub fn decompile_sb3(input: &Path, output: Option<&Path>, split_sprites: bool) -> Result<()> {
+/// A decompiled project held in memory.
+#[derive(Debug, Clone, Default)]
+pub struct DecompiledProject {
+    /// The whole project, or `main.sbtext` (stage plus imports) when split.
+    pub main: String,
+    /// `(file name, source)` for each sprite imported by `main`; empty unless split.
+    pub sprites: Vec<(String, String)>,
+    /// Costume and sound files referenced by the sources, keyed by file name.
+    pub assets: BTreeMap<String, Vec<u8>>,
+    /// Counts and everything the sources could not reproduce exactly.
+    pub report: DecompileReport,
+}
+
+/// What a decompile produced and what it could not reproduce. List entries are
+/// `"<target>: <detail>"`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DecompileReport {
+    pub scripts: usize,
+    pub procedures: usize,
+    /// Opcodes written as `raw` blocks, comments, or placeholder reporters, with
+    /// the number of blocks each.
+    pub unsupported_opcodes: BTreeMap<String, usize>,
+    /// Blocks where a cyclic `next` chain was cut.
+    pub cyclic_chains: Vec<String>,
+    /// Block ids referenced by an input or `next` but absent from the target.
+    pub missing_blocks: Vec<String>,
+    /// Blocks whose menu could not be read, so a default option was written.
+    pub unresolved_menus: Vec<String>,
+    /// Problems with the output as a whole, such as assets that were
+    /// referenced but not written. Not part of [`DecompileReport::summary`].
+    pub warnings: Vec<String>,
+}
+
+impl DecompileReport {
+    pub fn unsupported_blocks(&self) -> usize {
+        self.unsupported_opcodes.values().sum()
+    }
+
+    /// One-line summary, e.g. `decompiled 14 scripts and 2 procedures; 3 blocks
+    /// unsupported: looks_setstretchto x3`.
+    pub fn summary(&self) -> String {
+        let mut out = format!(
+            "decompiled {} {} and {} {}",
+            self.scripts,
+            plural(self.scripts, "script", "scripts"),
+            self.procedures,
+            plural(self.procedures, "procedure", "procedures")
+        );
+        let unsupported = self.unsupported_blocks();
+        if unsupported > 0 {
+            let opcodes = self
+                .unsupported_opcodes
+                .iter()
+                .map(|(opcode, count)| format!("{} x{}", opcode, count))
+                .collect::<Vec<_>>()
+                .join(", ");
+            out.push_str(&format!(
+                "; {} {} unsupported: {}",
+                unsupported,
+                plural(unsupported, "block", "blocks"),
+                opcodes
+            ));
+        }
+        for (entries, one, many) in [
+            (&self.cyclic_chains, "cyclic chain cut", "cyclic chains cut"),
+            (&self.missing_blocks, "missing block", "missing blocks"),
+            (
+                &self.unresolved_menus,
+                "unresolved menu",
+                "unresolved menus",
+            ),
+        ] {
+            if !entries.is_empty() {
+                out.push_str(&format!(
+                    "; {} {}",
+                    entries.len(),
+                    plural(entries.len(), one, many)
+                ));
+            }
+        }
+        out
+    }
+
+    /// Unwraps a menu option, recording the block when the default is used.
+    fn menu_or(&mut self, option: Option<String>, block: &Value, default: &str) -> String {
+        option.unwrap_or_else(|| {
+            let opcode = block.get("opcode").and_then(Value::as_str).unwrap_or("");
+            self.unresolved_menus.push(opcode.to_string());
+            default.to_string()
+        })
+    }
+
+    fn unsupported(&mut self, opcode: &str) {
+        *self
+            .unsupported_opcodes
+            .entry(opcode.to_string())
+            .or_default() += 1;
+    }
+
+    /// Folds a per-target report in, qualifying its entries with the target name.
+    fn merge(&mut self, target: &str, other: DecompileReport) {
+        self.scripts += other.scripts;
+        self.procedures += other.procedures;
+        for (opcode, count) in other.unsupported_opcodes {
+            *self.unsupported_opcodes.entry(opcode).or_default() += count;
+        }
+        let qualify = |entries: Vec<String>| {
+            entries
+                .into_iter()
+                .map(move |entry| format!("{}: {}", target, entry))
+        };
+        self.cyclic_chains.extend(qualify(other.cyclic_chains));
+        self.missing_blocks.extend(qualify(other.missing_blocks));
+        self.unresolved_menus
+            .extend(qualify(other.unresolved_menus));
+    }
+}
+
+/// Settings for [`decompile_sb3_with_progress`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DecompileOptions {
+    pub split_sprites: bool,
+    /// Write assets under the archive's md5 names instead of costume and sound
+    /// names, so a recompile is byte-exact.
+    pub keep_asset_hashes: bool,
+    /// Leave the broadcasts, variables, and handler scripts generated for
+    /// `Target.proc(args)` calls in the output instead of folding them back into
+    /// calls. Meant for debugging the compiler's lowering.
+    pub keep_remote_calls: bool,
+}
+
+fn plural<'a>(count: usize, one: &'a str, many: &'a str) -> &'a str {
+    if count == 1 {
+        one
+    } else {
+        many
+    }
+}
+
+pub fn decompile_sb3(
+    input: &Path,
+    output: Option<&Path>,
+    split_sprites: bool,
+) -> Result<DecompileReport> {
     decompile_sb3_with_progress(
         input,
         output,
-        split_sprites,
+        DecompileOptions {
+            split_sprites,
+            ..Default::default()
+        },
         Option::<&mut fn(usize, usize, &str)>::None,
     )
 }
 
+/// Decompiles `.sb3` (or bare `project.json`) bytes without touching the file
+/// system. Assets get friendly names, as with [`decompile_sb3`].
+pub fn decompile_sb3_bytes(bytes: &[u8], split_sprites: bool) -> Result<DecompiledProject> {
+    let options = DecompileOptions {
+        split_sprites,
+        ..Default::default()
+    };
+    decompile_archive(read_sb3_bytes(bytes)?, options, &mut None)
+}
+
+/// Decompiles `input`, writing costumes and sounds next to the output. Assets are
+/// named after their costume or sound unless `options.keep_asset_hashes` is set,
+/// in which case the archive's md5 names are kept so a recompile is byte-exact.
 pub fn decompile_sb3_with_progress<F>(
     input: &Path,
     output: Option<&Path>,
-    split_sprites: bool,
+    options: DecompileOptions,
     progress: Option<&mut F>,
-) -> Result<()>
+) -> Result<DecompileReport>
 where
     F: FnMut(usize, usize, &str),
 {
@@ -29,49 +193,172 @@ where
 
     report_progress(&mut progress, 1, 1, "Reading .sb3 archive");
     let archive = read_sb3_file(input)?;
-    let project_json = archive.project;
-    let assets = archive.assets.into_iter().collect::<HashMap<_, _>>();
-    let targets = project_json
-        .get("targets")
-        .and_then(Value::as_array)
-        .ok_or_else(|| anyhow!("Invalid project.json: missing 'targets' array."))?;
+    let output = match output {
+        Some(path) => path.to_path_buf(),
+        None if options.split_sprites => default_split_output_dir(input),
+        None => input.with_extension("sbtext"),
+    };
+    write_decompiled(
+        archive,
+        &input.display().to_string(),
+        &output,
+        options,
+        &mut progress,
+    )
+}
 
-    let mut decompiled_targets = Vec::new();
-    if targets.is_empty() {
-        report_progress(&mut progress, 1, 1, "Decompiling targets");
+/// Like [`decompile_sb3_with_progress`] for `.sb3` (or bare `project.json`)
+/// bytes, such as an archive piped to the CLI. `output` is required since there
+/// is no input path to derive it from.
+pub fn decompile_sb3_bytes_with_progress<F>(
+    bytes: &[u8],
+    output: &Path,
+    options: DecompileOptions,
+    progress: Option<&mut F>,
+) -> Result<DecompileReport>
+where
+    F: FnMut(usize, usize, &str),
+{
+    let mut progress = progress.map(|cb| cb as &mut ProgressCallback<'_>);
+
+    report_progress(&mut progress, 1, 1, "Reading .sb3 archive");
+    let archive = read_sb3_bytes(bytes)?;
+    write_decompiled(archive, STDIN_NAME, output, options, &mut progress)
+}
+
+/// Decompiles `archive` (read from `input_name`) and writes the source and
+/// assets to `output`: a directory with `split_sprites`, otherwise a file that
+/// gets a `.sbtext` extension if it has none.
+fn write_decompiled(
+    archive: Sb3Archive,
+    input_name: &str,
+    output: &Path,
+    options: DecompileOptions,
+    progress: &mut Option<&mut ProgressCallback<'_>>,
+) -> Result<DecompileReport> {
+    let missing_assets = archive.assets.is_empty() && declares_assets(&archive.project);
+    let mut project = decompile_archive(archive, options, progress)?;
+    if missing_assets {
+        project.report.warnings.push(format!(
+            "'{}' contains no asset files; costumes and sounds are referenced by md5 name but not written.",
+            input_name
+        ));
     }
-    for (index, target) in targets.iter().enumerate() {
-        decompiled_targets.push(decompile_target(target)?);
-        report_progress(
-            &mut progress,
-            index + 1,
-            targets.len().max(1),
-            "Decompiling targets",
+
+    if options.split_sprites {
+        write_split_project(&project, output, progress)?;
+    } else if output.extension().is_none() {
+        write_single_project(&project, &output.with_extension("sbtext"), progress)?;
+    } else {
+        write_single_project(&project, output, progress)?;
+    }
+
+    report_progress(progress, 1, 1, "Decompile complete");
+    Ok(project.report)
+}
+
+/// Decompiles `input` into a single source text without writing any files, for
+/// printing to stdout. Assets are not extracted, so declarations keep md5 names;
+/// `options.split_sprites` and `options.keep_asset_hashes` are ignored.
+pub fn decompile_sb3_to_string(
+    input: &Path,
+    options: DecompileOptions,
+) -> Result<(String, DecompileReport)> {
+    decompile_archive_to_string(read_sb3_file(input)?, options)
+}
+
+/// [`decompile_sb3_to_string`] for `.sb3` (or bare `project.json`) bytes.
+pub fn decompile_sb3_bytes_to_string(
+    bytes: &[u8],
+    options: DecompileOptions,
+) -> Result<(String, DecompileReport)> {
+    decompile_archive_to_string(read_sb3_bytes(bytes)?, options)
+}
+
+fn decompile_archive_to_string(
+    archive: Sb3Archive,
+    options: DecompileOptions,
+) -> Result<(String, DecompileReport)> {
+    let has_assets = declares_assets(&archive.project);
+    let options = DecompileOptions {
+        split_sprites: false,
+        keep_asset_hashes: true,
+        ..options
+    };
+    let mut project = decompile_archive(archive, options, &mut None)?;
+    if has_assets {
+        project.report.warnings.push(
+            "costumes and sounds are referenced by md5 name; asset files are not written when decompiling to stdout."
+                .to_string(),
         );
     }
+    Ok((project.main, project.report))
+}
 
-    if split_sprites {
-        let out_dir = match output {
-            Some(path) => path.to_path_buf(),
-            None => default_split_output_dir(input),
-        };
-        write_split_project(&decompiled_targets, &assets, &out_dir, &mut progress)?;
+/// Decompiles and renders a whole archive. An archive without asset files (a
+/// bare `project.json`) keeps md5 names, since there is nothing to rename.
+fn decompile_archive(
+    archive: Sb3Archive,
+    options: DecompileOptions,
+    progress: &mut Option<&mut ProgressCallback<'_>>,
+) -> Result<DecompiledProject> {
+    let archive = if is_sb2_project(&archive.project) {
+        sb2_to_sb3(&archive)?
     } else {
-        let out_file = match output {
-            Some(path) => {
-                if path.extension().is_none() {
-                    path.with_extension("sbtext")
-                } else {
-                    path.to_path_buf()
-                }
+        archive
+    };
+    let targets = archive
+        .project
+        .get("targets")
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow!("Invalid project.json: missing 'targets' array."))?;
+    let mut report = DecompileReport::default();
+    let mut decompiled_targets = decompile_targets(
+        targets,
+        archive.project.get("monitors"),
+        options,
+        &mut report,
+        progress,
+    )?;
+    if !options.keep_asset_hashes && !archive.assets.is_empty() {
+        assign_friendly_asset_files(&mut decompiled_targets);
+    }
+
+    let (main, sprites) = if options.split_sprites {
+        render_split_project(&decompiled_targets)
+    } else {
+        (render_project(&decompiled_targets), Vec::new())
+    };
+    let mut assets = BTreeMap::new();
+    for target in &decompiled_targets {
+        for asset in target.costumes.iter().chain(&target.sounds) {
+            if let Some(bytes) = archive.assets.get(&asset.md5ext) {
+                assets.insert(asset.file.clone(), bytes.clone());
             }
-            None => input.with_extension("sbtext"),
-        };
-        write_single_project(&decompiled_targets, &assets, &out_file, &mut progress)?;
+        }
     }
+    Ok(DecompiledProject {
+        main,
+        sprites,
+        assets,
+        report,
+    })
+}
 
-    report_progress(&mut progress, 1, 1, "Decompile complete");
-    Ok(())
+fn declares_assets(project_json: &Value) -> bool {
+    project_json
+        .get("targets")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .any(|target| {
+            ["costumes", "sounds"].iter().any(|key| {
+                target
+                    .get(*key)
+                    .and_then(Value::as_array)
+                    .is_some_and(|assets| !assets.is_empty())
+            })
+        })
 }
 
 fn report_progress(
@@ -91,7 +378,10 @@ struct DecompiledTarget {
     is_stage: bool,
     variables: Vec<DecompiledVariableDecl>,
     lists: Vec<DecompiledListDecl>,
-    costumes: Vec<String>,
+    costumes: Vec<DecompiledAsset>,
+    sounds: Vec<DecompiledAsset>,
+    properties: Vec<String>,
+    monitors: Vec<String>,
     procedures: Vec<DecompiledProcedure>,
     scripts: Vec<DecompiledScript>,
 }
@@ -100,6 +390,7 @@ struct DecompiledTarget {
 struct DecompiledVariableDecl {
     name: String,
     initial_value: Option<Value>,
+    is_cloud: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -112,17 +403,320 @@ struct DecompiledListDecl {
 struct DecompiledProcedure {
     name: String,
     params: Vec<String>,
+    boolean_params: Vec<bool>,
     warp: bool,
     body: Vec<String>,
+    comment: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct DecompiledAsset {
+    name: String,
+    /// The `md5ext` the bytes are stored under in the archive.
+    md5ext: String,
+    /// The file name the declaration references and the asset is written to.
+    file: String,
 }
 
 #[derive(Debug, Clone)]
 struct DecompiledScript {
     header: String,
     body: Vec<String>,
+    comment: Option<String>,
+}
+
+/// Decompiles every target with the stage first and sprites bottom to top.
+fn decompile_targets(
+    targets: &[Value],
+    monitors: Option<&Value>,
+    options: DecompileOptions,
+    report: &mut DecompileReport,
+    progress: &mut Option<&mut ProgressCallback<'_>>,
+) -> Result<Vec<DecompiledTarget>> {
+    let mut targets = targets.to_vec();
+    if !options.keep_remote_calls {
+        fold_remote_calls(&mut targets);
+    }
+    let targets = targets.as_slice();
+    let mut decompiled_targets = Vec::new();
+    if targets.is_empty() {
+        report_progress(progress, 1, 1, "Decompiling targets");
+    }
+    let mut sprite_position = 0;
+    for (index, target) in sort_targets_by_layer(targets).into_iter().enumerate() {
+        let mut decompiled = decompile_target(target, monitors, report)?;
+        if !decompiled.is_stage {
+            sprite_position += 1;
+            push_layer_property(target, sprite_position, &mut decompiled.properties);
+        }
+        decompiled_targets.push(decompiled);
+        report_progress(
+            progress,
+            index + 1,
+            targets.len().max(1),
+            "Decompiling targets",
+        );
+    }
+    Ok(decompiled_targets)
+}
+
+/// Rewrites the plumbing generated for `Target.proc(args)` back into qualified calls:
+/// each caller sequence becomes a `procedures_call` with proccode `Target.proc %s ...`,
+/// handler scripts are dropped, and so are the generated globals.
+fn fold_remote_calls(targets: &mut [Value]) {
+    let callees = remote_call_handlers(targets);
+    if callees.is_empty() {
+        return;
+    }
+    let is_generated = |name: &str| {
+        callees
+            .keys()
+            .any(|message| name == message || name.starts_with(&format!("{}__", message)))
+    };
+    for target in targets.iter_mut() {
+        if let Some(blocks) = target.get_mut("blocks").and_then(Value::as_object_mut) {
+            blocks.retain(|_, block| {
+                !(block.get("opcode").and_then(Value::as_str)
+                    == Some("event_whenbroadcastreceived")
+                    && field_first_string(block, "BROADCAST_OPTION")
+                        .is_some_and(|message| callees.contains_key(&message)))
+            });
+            let ids = blocks.keys().cloned().collect::<Vec<_>>();
+            for id in ids {
+                fold_remote_call_at(blocks, &id, &callees);
+            }
+        }
+        for key in ["variables", "lists"] {
+            if let Some(decls) = target.get_mut(key).and_then(Value::as_object_mut) {
+                decls.retain(|_, decl| {
+                    !decl
+                        .get(0)
+                        .and_then(Value::as_str)
+                        .is_some_and(is_generated)
+                });
+            }
+        }
+    }
+}
+
+/// Maps each generated remote-call message to the callee target and procedure, found
+/// from the handler script that receives it.
+fn remote_call_handlers(targets: &[Value]) -> HashMap<String, (String, String)> {
+    let mut out = HashMap::new();
+    for target in targets {
+        let (Some(target_name), Some(blocks)) = (
+            target.get("name").and_then(Value::as_str),
+            target.get("blocks").and_then(Value::as_object),
+        ) else {
+            continue;
+        };
+        for block in blocks.values() {
+            if block.get("opcode").and_then(Value::as_str) != Some("event_whenbroadcastreceived") {
+                continue;
+            }
+            let Some(message) = field_first_string(block, "BROADCAST_OPTION")
+                .filter(|message| message.starts_with(RPC_PREFIX))
+            else {
+                continue;
+            };
+            let Some(procedure) = first_procedure_call(blocks, block.get("next"))
+                .and_then(|call| procedure_call_shape(call).ok())
+                .map(|(name, _)| name)
+            else {
+                continue;
+            };
+            if rpc_names(target_name, &procedure, 0).message == message {
+                out.insert(message, (target_name.to_string(), procedure));
+            }
+        }
+    }
+    out
+}
+
+fn first_procedure_call<'a>(
+    blocks: &'a Map<String, Value>,
+    start: Option<&Value>,
+) -> Option<&'a Value> {
+    let mut current = start.and_then(Value::as_str);
+    while let Some(block) = current.and_then(|id| blocks.get(id)) {
+        if block.get("opcode").and_then(Value::as_str) == Some("procedures_call") {
+            return Some(block);
+        }
+        for input in ["SUBSTACK", "SUBSTACK2"] {
+            let nested = block_input_block_id(block, input).map(Value::String);
+            if let Some(call) = first_procedure_call(blocks, nested.as_ref()) {
+                return Some(call);
+            }
+        }
+        current = block.get("next").and_then(Value::as_str);
+    }
+    None
+}
+
+/// Folds a remote-call sequence starting at `id` into one `procedures_call` block.
+/// Recognizes the argument-queue form (`add` to each `__argN__queue`, `add` to `__calls`,
+/// then an if/else around `broadcast and wait`) and the older form that `set`s each
+/// `__argN` variable before `broadcast and wait`.
+fn fold_remote_call_at(
+    blocks: &mut Map<String, Value>,
+    id: &str,
+    callees: &HashMap<String, (String, String)>,
+) {
+    let opcode_of = |blocks: &Map<String, Value>, id: &str| {
+        blocks
+            .get(id)
+            .and_then(|b| b.get("opcode"))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    };
+    let next_of = |blocks: &Map<String, Value>, id: &str| {
+        blocks
+            .get(id)
+            .and_then(|b| b.get("next"))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    };
+    let Some(first) = blocks.get(id) else {
+        return;
+    };
+    let (message, queued) = match first.get("opcode").and_then(Value::as_str) {
+        Some("data_addtolist") => {
+            let Some(list) = field_first_string(first, "LIST") else {
+                return;
+            };
+            let Some(message) = list
+                .strip_suffix("__arg1__queue")
+                .or_else(|| list.strip_suffix("__calls"))
+            else {
+                return;
+            };
+            (message.to_string(), true)
+        }
+        Some("data_setvariableto") => {
+            let Some(message) = field_first_string(first, "VARIABLE")
+                .and_then(|name| name.strip_suffix("__arg1").map(str::to_string))
+            else {
+                return;
+            };
+            (message, false)
+        }
+        Some("event_broadcastandwait") => match broadcast_message(blocks, first) {
+            Some(message) => (message, false),
+            None => return,
+        },
+        _ => return,
+    };
+    let Some((target_name, procedure)) = callees.get(&message) else {
+        return;
+    };
+    let (arg_opcode, arg_field, arg_input) = if queued {
+        ("data_addtolist", "LIST", "ITEM")
+    } else {
+        ("data_setvariableto", "VARIABLE", "VALUE")
+    };
+    // Only fold from the start of a sequence, not from its tail.
+    let arg_prefix = format!("{}__arg", message);
+    let previous = first
+        .get("parent")
+        .and_then(Value::as_str)
+        .and_then(|parent| blocks.get(parent))
+        .filter(|parent| parent.get("next").and_then(Value::as_str) == Some(id));
+    if previous.is_some_and(|previous| {
+        previous.get("opcode").and_then(Value::as_str) == Some(arg_opcode)
+            && field_first_string(previous, arg_field)
+                .is_some_and(|name| name.starts_with(&arg_prefix))
+    }) {
+        return;
+    }
+
+    let mut args = Vec::new();
+    let mut current = Some(id.to_string());
+    while let Some(cur) = current.clone() {
+        let expected = if queued {
+            format!("{}__arg{}__queue", message, args.len() + 1)
+        } else {
+            format!("{}__arg{}", message, args.len() + 1)
+        };
+        let block = &blocks[&cur];
+        if opcode_of(blocks, &cur).as_deref() != Some(arg_opcode)
+            || field_first_string(block, arg_field).as_deref() != Some(expected.as_str())
+        {
+            break;
+        }
+        let Some(input) = block.get("inputs").and_then(|i| i.get(arg_input)).cloned() else {
+            return;
+        };
+        args.push(input);
+        current = next_of(blocks, &cur);
+    }
+    let Some(mut cur) = current else {
+        return;
+    };
+    if queued {
+        let calls_list = format!("{}__calls", message);
+        if opcode_of(blocks, &cur).as_deref() != Some("data_addtolist")
+            || field_first_string(&blocks[&cur], "LIST").as_deref() != Some(calls_list.as_str())
+        {
+            return;
+        }
+        let Some(branch) = next_of(blocks, &cur) else {
+            return;
+        };
+        if opcode_of(blocks, &branch).as_deref() != Some("control_if_else") {
+            return;
+        }
+        cur = branch;
+    } else if opcode_of(blocks, &cur).as_deref() != Some("event_broadcastandwait")
+        || broadcast_message(blocks, &blocks[&cur]).as_deref() != Some(message.as_str())
+    {
+        return;
+    }
+    let after = next_of(blocks, &cur);
+
+    let arg_ids = (1..=args.len())
+        .map(|i| format!("arg{}", i))
+        .collect::<Vec<_>>();
+    let mut proccode = format!("{}.{}", target_name, procedure);
+    for _ in &args {
+        proccode.push_str(" %s");
+    }
+    let mut inputs = Map::new();
+    for (arg_id, input) in arg_ids.iter().zip(args) {
+        if let Some(child) = input.get(1).and_then(Value::as_str) {
+            if let Some(child_block) = blocks.get_mut(child) {
+                child_block["parent"] = json!(id);
+            }
+        }
+        inputs.insert(arg_id.clone(), input);
+    }
+    if let Some(after_block) = after.as_deref().and_then(|a| blocks.get_mut(a)) {
+        after_block["parent"] = json!(id);
+    }
+    let first = &blocks[id];
+    let replacement = json!({
+        "opcode": "procedures_call",
+        "next": after,
+        "parent": first.get("parent").cloned().unwrap_or(Value::Null),
+        "inputs": inputs,
+        "fields": {},
+        "shadow": false,
+        "topLevel": first.get("topLevel").cloned().unwrap_or(json!(false)),
+        "mutation": {
+            "tagName": "mutation",
+            "children": [],
+            "proccode": proccode,
+            "argumentids": serde_json::to_string(&arg_ids).unwrap_or_default(),
+            "warp": "false"
+        }
+    });
+    blocks.insert(id.to_string(), replacement);
 }
 
-fn decompile_target(target: &Value) -> Result<DecompiledTarget> {
+fn decompile_target(
+    target: &Value,
+    monitors: Option<&Value>,
+    report: &mut DecompileReport,
+) -> Result<DecompiledTarget> {
     let name = target
         .get("name")
         .and_then(Value::as_str)
@@ -135,7 +729,14 @@ fn decompile_target(target: &Value) -> Result<DecompiledTarget> {
 
     let variables = read_variable_decls(target.get("variables"));
     let lists = read_list_decls(target.get("lists"));
-    let costumes = read_costumes(target.get("costumes"));
+    let costumes = read_asset_files(target.get("costumes"));
+    let sounds = read_asset_files(target.get("sounds"));
+    let properties = if is_stage {
+        read_stage_properties(target)
+    } else {
+        read_sprite_properties(target)
+    };
+    let monitors = read_monitors(target, monitors);
 
     let blocks_obj = target
         .get("blocks")
@@ -158,24 +759,37 @@ fn decompile_target(target: &Value) -> Result<DecompiledTarget> {
             "procedures_definition" => procedure_starts.push(id.clone()),
             "event_whenflagclicked"
             | "event_whenthisspriteclicked"
+            | "event_whenstageclicked"
             | "event_whenbroadcastreceived"
-            | "event_whenkeypressed" => script_starts.push(id.clone()),
+            | "event_whenkeypressed"
+            | "event_whenbackdropswitchesto"
+            | "event_whengreaterthan"
+            | "control_start_as_clone" => script_starts.push(id.clone()),
             _ => {}
         }
     }
 
-    procedure_starts.sort_by(|a, b| block_sort_key(&blocks, a).cmp(&block_sort_key(&blocks, b)));
-    script_starts.sort_by(|a, b| block_sort_key(&blocks, a).cmp(&block_sort_key(&blocks, b)));
+    procedure_starts.sort_by_key(|a| block_sort_key(&blocks, a));
+    script_starts.sort_by_key(|a| block_sort_key(&blocks, a));
 
+    let comments = read_block_comments(target.get("comments"));
+    let mut local = DecompileReport::default();
     let mut procedures = Vec::new();
     for id in procedure_starts {
-        procedures.push(decompile_procedure(&blocks, &id)?);
+        let mut procedure = decompile_procedure(&blocks, &mut local, &id)?;
+        procedure.comment = comments.get(&id).cloned();
+        procedures.push(procedure);
     }
 
     let mut scripts = Vec::new();
     for id in script_starts {
-        scripts.push(decompile_script(&blocks, &id)?);
+        let mut script = decompile_script(&blocks, &mut local, &id)?;
+        script.comment = comments.get(&id).cloned();
+        scripts.push(script);
     }
+    local.procedures = procedures.len();
+    local.scripts = scripts.len();
+    report.merge(&name, local);
 
     Ok(DecompiledTarget {
         name,
@@ -183,11 +797,37 @@ fn decompile_target(target: &Value) -> Result<DecompiledTarget> {
         variables,
         lists,
         costumes,
+        sounds,
+        properties,
+        monitors,
         procedures,
         scripts,
     })
 }
 
+/// Maps block ids to the text of expanded workspace comments attached to them.
+fn read_block_comments(node: Option<&Value>) -> HashMap<String, String> {
+    let mut out = HashMap::new();
+    let Some(obj) = node.and_then(Value::as_object) else {
+        return out;
+    };
+    for comment in obj.values() {
+        if comment.get("minimized").and_then(Value::as_bool) == Some(true) {
+            continue;
+        }
+        let (Some(block_id), Some(text)) = (
+            comment.get("blockId").and_then(Value::as_str),
+            comment.get("text").and_then(Value::as_str),
+        ) else {
+            continue;
+        };
+        if !text.trim().is_empty() {
+            out.insert(block_id.to_string(), text.to_string());
+        }
+    }
+    out
+}
+
 fn read_variable_decls(node: Option<&Value>) -> Vec<DecompiledVariableDecl> {
     let mut out = Vec::new();
     let Some(obj) = node.and_then(Value::as_object) else {
@@ -207,9 +847,16 @@ fn read_variable_decls(node: Option<&Value>) -> Vec<DecompiledVariableDecl> {
                 Some(v.clone())
             }
         });
+        let is_cloud = arr.get(2).and_then(Value::as_bool) == Some(true);
+        let name = if is_cloud {
+            name.strip_prefix(CLOUD_VARIABLE_PREFIX).unwrap_or(name)
+        } else {
+            name
+        };
         out.push(DecompiledVariableDecl {
             name: name.to_string(),
             initial_value,
+            is_cloud,
         });
     }
     out
@@ -243,40 +890,213 @@ fn read_list_decls(node: Option<&Value>) -> Vec<DecompiledListDecl> {
     out
 }
 
-fn read_costumes(node: Option<&Value>) -> Vec<String> {
+/// Renders sprite placement fields as declarations, skipping Scratch defaults.
+fn read_sprite_properties(target: &Value) -> Vec<String> {
     let mut out = Vec::new();
-    let Some(arr) = node.and_then(Value::as_array) else {
+    for (key, name, default) in [
+        ("x", "x", 0.0),
+        ("y", "y", 0.0),
+        ("size", "size", 100.0),
+        ("direction", "direction", 90.0),
+    ] {
+        if let Some(value) = target.get(key).and_then(Value::as_f64) {
+            if value != default {
+                out.push(format!("{} {}", name, format_decl_number(value)));
+            }
+        }
+    }
+    if target.get("visible").and_then(Value::as_bool) == Some(false) {
+        out.push("hidden".to_string());
+    }
+    if let Some(style) = target.get("rotationStyle").and_then(Value::as_str) {
+        if style != "all around" {
+            out.push(format!("rotation style [{}]", format_bracket_name(style)));
+        }
+    }
+    push_volume_property(target, &mut out);
+    push_start_costume_property(target, &mut out);
+    out
+}
+
+/// Renders stage media settings as declarations, skipping Scratch defaults.
+fn read_stage_properties(target: &Value) -> Vec<String> {
+    let mut out = Vec::new();
+    if let Some(tempo) = target.get("tempo").and_then(Value::as_f64) {
+        if tempo != 60.0 {
+            out.push(format!("tempo {}", format_decl_number(tempo)));
+        }
+    }
+    push_volume_property(target, &mut out);
+    push_start_costume_property(target, &mut out);
+    if let Some(transparency) = target.get("videoTransparency").and_then(Value::as_f64) {
+        if transparency != 50.0 {
+            out.push(format!(
+                "video transparency {}",
+                format_decl_number(transparency)
+            ));
+        }
+    }
+    if let Some(state) = target.get("videoState").and_then(Value::as_str) {
+        if state != "on" {
+            out.push(format!("video [{}]", format_bracket_name(state)));
+        }
+    }
+    out
+}
+
+/// Renders visible variable and list monitors whose id belongs to this target.
+fn read_monitors(target: &Value, monitors: Option<&Value>) -> Vec<String> {
+    let mut out = Vec::new();
+    let Some(monitors) = monitors.and_then(Value::as_array) else {
         return out;
     };
-    for costume in arr {
-        if let Some(md5ext) = costume.get("md5ext").and_then(Value::as_str) {
-            out.push(md5ext.to_string());
+    let variables = target.get("variables").and_then(Value::as_object);
+    let lists = target.get("lists").and_then(Value::as_object);
+    for monitor in monitors {
+        if monitor.get("visible").and_then(Value::as_bool) != Some(true) {
+            continue;
+        }
+        let declared = match monitor.get("opcode").and_then(Value::as_str) {
+            Some("data_variable") => variables,
+            Some("data_listcontents") => lists,
+            _ => continue,
+        };
+        let Some(name) = monitor
+            .get("id")
+            .and_then(Value::as_str)
+            .and_then(|id| declared?.get(id))
+            .and_then(|decl| decl.get(0))
+            .and_then(Value::as_str)
+        else {
+            continue;
+        };
+        let x = monitor.get("x").and_then(Value::as_f64).unwrap_or(0.0);
+        let y = monitor.get("y").and_then(Value::as_f64).unwrap_or(0.0);
+        let mut line = format!(
+            "monitor [{}] at ({}) ({})",
+            format_bracket_name(name),
+            format_decl_number(x),
+            format_decl_number(y)
+        );
+        if let Some(mode @ ("large" | "slider")) = monitor.get("mode").and_then(Value::as_str) {
+            line.push_str(&format!(" mode [{}]", mode));
         }
+        out.push(line);
     }
     out
 }
 
-fn block_sort_key(blocks: &Map<String, Value>, id: &str) -> (i64, i64, String) {
-    let block = blocks.get(id);
-    let y = block
-        .and_then(|b| b.get("y"))
-        .and_then(Value::as_i64)
-        .unwrap_or(i64::MAX);
-    let x = block
-        .and_then(|b| b.get("x"))
-        .and_then(Value::as_i64)
-        .unwrap_or(i64::MAX);
-    (y, x, id.to_string())
+/// Orders the stage first and sprites bottom to top, so declaration order
+/// reproduces the stacking of overlapping sprites.
+fn sort_targets_by_layer(targets: &[Value]) -> Vec<&Value> {
+    let mut ordered = targets.iter().collect::<Vec<_>>();
+    ordered.sort_by_key(|target| {
+        let is_stage = target.get("isStage").and_then(Value::as_bool) == Some(true);
+        let layer = target.get("layerOrder").and_then(Value::as_i64);
+        (!is_stage, layer.unwrap_or(i64::MAX))
+    });
+    ordered
 }
 
-fn decompile_procedure(
-    blocks: &Map<String, Value>,
-    definition_id: &str,
-) -> Result<DecompiledProcedure> {
-    let definition = get_block(blocks, definition_id)?;
-    let prototype_id = block_input_block_id(definition, "custom_block").ok_or_else(|| {
-        anyhow!(
-            "Procedure definition '{}' missing custom_block input.",
+/// Pins the layer only when declaration order would not already yield it.
+fn push_layer_property(target: &Value, position: i64, out: &mut Vec<String>) {
+    if let Some(layer) = target.get("layerOrder").and_then(Value::as_i64) {
+        if layer != position {
+            out.push(format!("layer {}", layer));
+        }
+    }
+}
+
+fn push_volume_property(target: &Value, out: &mut Vec<String>) {
+    if let Some(volume) = target.get("volume").and_then(Value::as_f64) {
+        if volume != 100.0 {
+            out.push(format!("volume {}", format_decl_number(volume)));
+        }
+    }
+}
+
+fn push_start_costume_property(target: &Value, out: &mut Vec<String>) {
+    let index = target
+        .get("currentCostume")
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    if index == 0 {
+        return;
+    }
+    if let Some(name) = target
+        .get("costumes")
+        .and_then(|costumes| costumes.get(index as usize))
+        .and_then(|costume| costume.get("name"))
+        .and_then(Value::as_str)
+    {
+        out.push(format!("start costume [{}]", format_bracket_name(name)));
+    }
+}
+
+fn push_doc_comment(lines: &mut Vec<String>, comment: Option<&str>) {
+    let Some(text) = comment else {
+        return;
+    };
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            lines.push("  ##".to_string());
+        } else {
+            lines.push(format!("  ## {}", line.trim_end()));
+        }
+    }
+}
+
+fn format_decl_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        value.to_string()
+    }
+}
+
+fn read_asset_files(node: Option<&Value>) -> Vec<DecompiledAsset> {
+    let mut out = Vec::new();
+    let Some(arr) = node.and_then(Value::as_array) else {
+        return out;
+    };
+    for asset in arr {
+        if let Some(md5ext) = asset.get("md5ext").and_then(Value::as_str) {
+            out.push(DecompiledAsset {
+                name: asset
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string(),
+                md5ext: md5ext.to_string(),
+                file: md5ext.to_string(),
+            });
+        }
+    }
+    out
+}
+
+fn block_sort_key(blocks: &Map<String, Value>, id: &str) -> (i64, i64, String) {
+    let block = blocks.get(id);
+    let y = block
+        .and_then(|b| b.get("y"))
+        .and_then(Value::as_i64)
+        .unwrap_or(i64::MAX);
+    let x = block
+        .and_then(|b| b.get("x"))
+        .and_then(Value::as_i64)
+        .unwrap_or(i64::MAX);
+    (y, x, id.to_string())
+}
+
+fn decompile_procedure(
+    blocks: &Map<String, Value>,
+    report: &mut DecompileReport,
+    definition_id: &str,
+) -> Result<DecompiledProcedure> {
+    let definition = get_block(blocks, definition_id)?;
+    let prototype_id = block_input_block_id(definition, "custom_block").ok_or_else(|| {
+        anyhow!(
+            "Procedure definition '{}' missing custom_block input.",
             definition_id
         )
     })?;
@@ -299,6 +1119,12 @@ fn decompile_procedure(
             Vec::new()
         };
 
+    let boolean_params = proccode
+        .split_whitespace()
+        .filter(|token| *token == "%s" || *token == "%b")
+        .map(|token| token == "%b")
+        .collect();
+
     let warp = mutation
         .get("warp")
         .and_then(Value::as_str)
@@ -306,22 +1132,30 @@ fn decompile_procedure(
         .unwrap_or(false);
 
     let body_start = definition.get("next").and_then(Value::as_str);
-    let body = decompile_chain(blocks, body_start, 4, &mut HashSet::new())?;
+    let body = decompile_chain(blocks, report, body_start, 4, &mut HashSet::new())?;
 
     Ok(DecompiledProcedure {
         name,
         params,
+        boolean_params,
         warp,
         body,
+        comment: None,
     })
 }
 
-fn decompile_script(blocks: &Map<String, Value>, hat_id: &str) -> Result<DecompiledScript> {
+fn decompile_script(
+    blocks: &Map<String, Value>,
+    report: &mut DecompileReport,
+    hat_id: &str,
+) -> Result<DecompiledScript> {
     let hat = get_block(blocks, hat_id)?;
     let opcode = hat.get("opcode").and_then(Value::as_str).unwrap_or("");
     let header = match opcode {
         "event_whenflagclicked" => "when flag clicked".to_string(),
-        "event_whenthisspriteclicked" => "when this sprite clicked".to_string(),
+        "event_whenthisspriteclicked" | "event_whenstageclicked" => {
+            "when this sprite clicked".to_string()
+        }
         "event_whenbroadcastreceived" => {
             let msg = field_first_string(hat, "BROADCAST_OPTION")
                 .unwrap_or_else(|| "message1".to_string());
@@ -331,17 +1165,40 @@ fn decompile_script(blocks: &Map<String, Value>, hat_id: &str) -> Result<Decompi
             let key = field_first_string(hat, "KEY_OPTION")
                 .or_else(|| key_option(blocks, hat))
                 .unwrap_or_else(|| "space".to_string());
-            format!("when [{}] key pressed", format_bracket_name(&key))
+            format!("when key [{}] pressed", format_bracket_name(&key))
+        }
+        "event_whenbackdropswitchesto" => {
+            let backdrop = field_first_string(hat, "BACKDROP")
+                .unwrap_or_else(|| "backdrop1".to_string());
+            format!(
+                "when backdrop switches to [{}]",
+                format_bracket_name(&backdrop)
+            )
+        }
+        "event_whengreaterthan" => {
+            let sensor = field_first_string(hat, "WHENGREATERTHANMENU")
+                .unwrap_or_else(|| "LOUDNESS".to_string());
+            let value = expr_from_input(blocks, report, hat, "VALUE")?;
+            format!("when {} > ({})", sensor.to_lowercase(), value)
+        }
+        "control_start_as_clone" => "when I start as a clone".to_string(),
+        other => {
+            report.unsupported(other);
+            format!("# unsupported event opcode: {}", other)
         }
-        other => format!("# unsupported event opcode: {}", other),
     };
     let body_start = hat.get("next").and_then(Value::as_str);
-    let body = decompile_chain(blocks, body_start, 4, &mut HashSet::new())?;
-    Ok(DecompiledScript { header, body })
+    let body = decompile_chain(blocks, report, body_start, 4, &mut HashSet::new())?;
+    Ok(DecompiledScript {
+        header,
+        body,
+        comment: None,
+    })
 }
 
 fn decompile_chain(
     blocks: &Map<String, Value>,
+    report: &mut DecompileReport,
     start: Option<&str>,
     indent: usize,
     visited: &mut HashSet<String>,
@@ -355,10 +1212,15 @@ fn decompile_chain(
                 spaces(indent),
                 id
             ));
+            report.cyclic_chains.push(id);
             break;
         }
-        let block = get_block(blocks, &id)?;
-        let mut stmt = decompile_statement(blocks, &id, block, indent, visited)?;
+        let Some(block) = blocks.get(&id) else {
+            lines.push(format!("{}# warning: missing block {}", spaces(indent), id));
+            report.missing_blocks.push(id);
+            break;
+        };
+        let mut stmt = decompile_statement(blocks, report, block, indent, visited)?;
         lines.append(&mut stmt);
         current = block
             .get("next")
@@ -370,7 +1232,7 @@ fn decompile_chain(
 
 fn decompile_statement(
     blocks: &Map<String, Value>,
-    id: &str,
+    report: &mut DecompileReport,
     block: &Value,
     indent: usize,
     visited: &mut HashSet<String>,
@@ -379,21 +1241,27 @@ fn decompile_statement(
     let pad = spaces(indent);
     let mut out = Vec::new();
     match op {
-        "event_broadcast" => {
-            let msg = broadcast_message(blocks, block).unwrap_or_else(|| "message1".to_string());
-            out.push(format!("{}broadcast [{}]", pad, format_bracket_name(&msg)));
-        }
-        "event_broadcastandwait" => {
-            let msg = broadcast_message(blocks, block).unwrap_or_else(|| "message1".to_string());
-            out.push(format!(
-                "{}broadcast and wait [{}]",
-                pad,
-                format_bracket_name(&msg)
-            ));
+        "event_broadcast" | "event_broadcastandwait" => {
+            let keyword = if op == "event_broadcast" {
+                "broadcast"
+            } else {
+                "broadcast and wait"
+            };
+            if let Some(expr) = dynamic_broadcast_expr(blocks, report, block)? {
+                out.push(format!("{}{} ({})", pad, keyword, expr));
+            } else {
+                let msg = report.menu_or(broadcast_message(blocks, block), block, "message1");
+                out.push(format!(
+                    "{}{} [{}]",
+                    pad,
+                    keyword,
+                    format_bracket_name(&msg)
+                ));
+            }
         }
         "data_setvariableto" => {
             let name = field_first_string(block, "VARIABLE").unwrap_or_else(|| "var".to_string());
-            let value = expr_from_input(blocks, block, "VALUE")?;
+            let value = expr_from_input(blocks, report, block, "VALUE")?;
             out.push(format!(
                 "{}set [{}] to ({})",
                 pad,
@@ -403,7 +1271,7 @@ fn decompile_statement(
         }
         "data_changevariableby" => {
             let name = field_first_string(block, "VARIABLE").unwrap_or_else(|| "var".to_string());
-            let value = expr_from_input(blocks, block, "VALUE")?;
+            let value = expr_from_input(blocks, report, block, "VALUE")?;
             out.push(format!(
                 "{}change [{}] by ({})",
                 pad,
@@ -427,82 +1295,108 @@ fn decompile_statement(
                 format_bracket_name(&name)
             ));
         }
+        "data_showlist" => {
+            let name = field_first_string(block, "LIST").unwrap_or_else(|| "list".to_string());
+            out.push(format!("{}show list [{}]", pad, format_bracket_name(&name)));
+        }
+        "data_hidelist" => {
+            let name = field_first_string(block, "LIST").unwrap_or_else(|| "list".to_string());
+            out.push(format!("{}hide list [{}]", pad, format_bracket_name(&name)));
+        }
         "motion_movesteps" => {
-            let steps = expr_from_input(blocks, block, "STEPS")?;
+            let steps = expr_from_input(blocks, report, block, "STEPS")?;
             out.push(format!("{}move ({}) [steps]", pad, steps));
         }
         "looks_say" => {
-            let message = expr_from_input(blocks, block, "MESSAGE")?;
+            let message = expr_from_input(blocks, report, block, "MESSAGE")?;
             out.push(format!("{}say ({})", pad, message));
         }
         "looks_sayforsecs" => {
-            let message = expr_from_input(blocks, block, "MESSAGE")?;
-            let secs = expr_from_input(blocks, block, "SECS")?;
+            let message = expr_from_input(blocks, report, block, "MESSAGE")?;
+            let secs = expr_from_input(blocks, report, block, "SECS")?;
             out.push(format!("{}say ({}) for ({}) [seconds]", pad, message, secs));
         }
         "looks_think" => {
-            let message = expr_from_input(blocks, block, "MESSAGE")?;
+            let message = expr_from_input(blocks, report, block, "MESSAGE")?;
             out.push(format!("{}think ({})", pad, message));
         }
+        "looks_thinkforsecs" => {
+            let message = expr_from_input(blocks, report, block, "MESSAGE")?;
+            let secs = expr_from_input(blocks, report, block, "SECS")?;
+            out.push(format!("{}think ({}) for ({}) [seconds]", pad, message, secs));
+        }
         "motion_turnright" => {
-            let degrees = expr_from_input(blocks, block, "DEGREES")?;
+            let degrees = expr_from_input(blocks, report, block, "DEGREES")?;
             out.push(format!("{}turn right ({})", pad, degrees));
         }
         "motion_turnleft" => {
-            let degrees = expr_from_input(blocks, block, "DEGREES")?;
+            let degrees = expr_from_input(blocks, report, block, "DEGREES")?;
             out.push(format!("{}turn left ({})", pad, degrees));
         }
         "motion_gotoxy" => {
-            let x = expr_from_input(blocks, block, "X")?;
-            let y = expr_from_input(blocks, block, "Y")?;
+            let x = expr_from_input(blocks, report, block, "X")?;
+            let y = expr_from_input(blocks, report, block, "Y")?;
             out.push(format!("{}go to x ({}) y ({})", pad, x, y));
         }
         "motion_goto" => {
-            let target = motion_target_option(blocks, block, "TO", "TO")
-                .unwrap_or_else(|| "_random_".to_string());
-            out.push(format!("{}go to ({})", pad, quote_str(&target)));
+            let target = report.menu_or(
+                motion_target_option(blocks, block, "TO", "TO"),
+                block,
+                "random position",
+            );
+            out.push(format!("{}go to [{}]", pad, format_menu_option(&target)));
         }
         "motion_glidesecstoxy" => {
-            let secs = expr_from_input(blocks, block, "SECS")?;
-            let x = expr_from_input(blocks, block, "X")?;
-            let y = expr_from_input(blocks, block, "Y")?;
+            let secs = expr_from_input(blocks, report, block, "SECS")?;
+            let x = expr_from_input(blocks, report, block, "X")?;
+            let y = expr_from_input(blocks, report, block, "Y")?;
             out.push(format!("{}glide ({}) to x ({}) y ({})", pad, secs, x, y));
         }
         "motion_glideto" => {
-            let secs = expr_from_input(blocks, block, "SECS")?;
-            let target = motion_target_option(blocks, block, "TO", "TO")
-                .unwrap_or_else(|| "_random_".to_string());
+            let secs = expr_from_input(blocks, report, block, "SECS")?;
+            let target = report.menu_or(
+                motion_target_option(blocks, block, "TO", "TO"),
+                block,
+                "random position",
+            );
             out.push(format!(
-                "{}glide ({}) to ({})",
+                "{}glide ({}) to [{}]",
                 pad,
                 secs,
-                quote_str(&target)
+                format_menu_option(&target)
             ));
         }
         "motion_changexby" => {
-            let v = expr_from_input(blocks, block, "DX")?;
+            let v = expr_from_input(blocks, report, block, "DX")?;
             out.push(format!("{}change x by ({})", pad, v));
         }
         "motion_setx" => {
-            let v = expr_from_input(blocks, block, "X")?;
+            let v = expr_from_input(blocks, report, block, "X")?;
             out.push(format!("{}set x to ({})", pad, v));
         }
         "motion_changeyby" => {
-            let v = expr_from_input(blocks, block, "DY")?;
+            let v = expr_from_input(blocks, report, block, "DY")?;
             out.push(format!("{}change y by ({})", pad, v));
         }
         "motion_sety" => {
-            let v = expr_from_input(blocks, block, "Y")?;
+            let v = expr_from_input(blocks, report, block, "Y")?;
             out.push(format!("{}set y to ({})", pad, v));
         }
         "motion_pointindirection" => {
-            let v = expr_from_input(blocks, block, "DIRECTION")?;
+            let v = expr_from_input(blocks, report, block, "DIRECTION")?;
             out.push(format!("{}point in direction ({})", pad, v));
         }
         "motion_pointtowards" => {
-            let target = motion_target_option(blocks, block, "TOWARDS", "TOWARDS")
-                .unwrap_or_else(|| "_mouse_".to_string());
-            out.push(format!("{}point towards ({})", pad, quote_str(&target)));
+            let target = report.menu_or(
+                motion_target_option(blocks, block, "TOWARDS", "TOWARDS"),
+                block,
+                "mouse-pointer",
+            );
+            out.push(format!(
+                "{}point towards [{}]",
+                pad,
+                format_menu_option(&target)
+            ));
         }
         "motion_setrotationstyle" => {
             let style =
@@ -515,11 +1409,11 @@ fn decompile_statement(
         }
         "motion_ifonedgebounce" => out.push(format!("{}if on edge bounce", pad)),
         "looks_changesizeby" => {
-            let v = expr_from_input(blocks, block, "CHANGE")?;
+            let v = expr_from_input(blocks, report, block, "CHANGE")?;
             out.push(format!("{}change size by ({})", pad, v));
         }
         "looks_setsizeto" => {
-            let v = expr_from_input(blocks, block, "SIZE")?;
+            let v = expr_from_input(blocks, report, block, "SIZE")?;
             out.push(format!("{}set size to ({})", pad, v));
         }
         "looks_show" => out.push(format!("{}show", pad)),
@@ -527,17 +1421,21 @@ fn decompile_statement(
         "looks_nextcostume" => out.push(format!("{}next costume", pad)),
         "looks_nextbackdrop" => out.push(format!("{}next backdrop", pad)),
         "looks_switchcostumeto" => {
-            let costume = expr_from_input(blocks, block, "COSTUME")?;
+            let costume = expr_from_input(blocks, report, block, "COSTUME")?;
             out.push(format!("{}switch costume to ({})", pad, costume));
         }
         "looks_switchbackdropto" => {
-            let backdrop = expr_from_input(blocks, block, "BACKDROP")?;
+            let backdrop = expr_from_input(blocks, report, block, "BACKDROP")?;
             out.push(format!("{}switch backdrop to ({})", pad, backdrop));
         }
+        "looks_switchbackdroptoandwait" => {
+            let backdrop = expr_from_input(blocks, report, block, "BACKDROP")?;
+            out.push(format!("{}switch backdrop to ({}) and wait", pad, backdrop));
+        }
         "looks_cleargraphiceffects" => out.push(format!("{}clear graphic effects", pad)),
         "looks_seteffectto" => {
             let effect = field_first_string(block, "EFFECT").unwrap_or_else(|| "ghost".to_string());
-            let value = expr_from_input(blocks, block, "VALUE")?;
+            let value = expr_from_input(blocks, report, block, "VALUE")?;
             out.push(format!(
                 "{}set graphic effect [{}] to ({})",
                 pad,
@@ -547,7 +1445,7 @@ fn decompile_statement(
         }
         "looks_changeeffectby" => {
             let effect = field_first_string(block, "EFFECT").unwrap_or_else(|| "ghost".to_string());
-            let value = expr_from_input(blocks, block, "CHANGE")?;
+            let value = expr_from_input(blocks, report, block, "CHANGE")?;
             out.push(format!(
                 "{}change graphic effect [{}] by ({})",
                 pad,
@@ -567,7 +1465,7 @@ fn decompile_statement(
         "looks_goforwardbackwardlayers" => {
             let direction = field_first_string(block, "FORWARD_BACKWARD")
                 .unwrap_or_else(|| "forward".to_string());
-            let num = expr_from_input(blocks, block, "NUM")?;
+            let num = expr_from_input(blocks, report, block, "NUM")?;
             out.push(format!(
                 "{}go [{}] ({}) layers",
                 pad,
@@ -576,24 +1474,24 @@ fn decompile_statement(
             ));
         }
         "control_wait" => {
-            let v = expr_from_input(blocks, block, "DURATION")?;
+            let v = expr_from_input(blocks, report, block, "DURATION")?;
             out.push(format!("{}wait ({})", pad, v));
         }
         "control_wait_until" => {
-            let c = expr_from_input(blocks, block, "CONDITION")?;
+            let c = expr_from_input(blocks, report, block, "CONDITION")?;
             out.push(format!("{}wait until <{}>", pad, c));
         }
         "control_repeat" => {
-            let times = expr_from_input(blocks, block, "TIMES")?;
+            let times = expr_from_input(blocks, report, block, "TIMES")?;
             out.push(format!("{}repeat ({})", pad, times));
             let sub = block_input_block_id(block, "SUBSTACK");
-            let mut body = decompile_chain(blocks, sub.as_deref(), indent + 2, visited)?;
+            let mut body = decompile_chain(blocks, report, sub.as_deref(), indent + 2, visited)?;
             out.append(&mut body);
             out.push(format!("{}end", pad));
         }
         "control_for_each" => {
             let var = field_first_string(block, "VARIABLE").unwrap_or_else(|| "i".to_string());
-            let value = expr_from_input(blocks, block, "VALUE")?;
+            let value = expr_from_input(blocks, report, block, "VALUE")?;
             out.push(format!(
                 "{}for each [{}] in ({})",
                 pad,
@@ -601,50 +1499,52 @@ fn decompile_statement(
                 value
             ));
             let sub = block_input_block_id(block, "SUBSTACK");
-            let mut body = decompile_chain(blocks, sub.as_deref(), indent + 2, visited)?;
+            let mut body = decompile_chain(blocks, report, sub.as_deref(), indent + 2, visited)?;
             out.append(&mut body);
             out.push(format!("{}end", pad));
         }
         "control_while" => {
-            let c = expr_from_input(blocks, block, "CONDITION")?;
+            let c = expr_from_input(blocks, report, block, "CONDITION")?;
             out.push(format!("{}while <{}>", pad, c));
             let sub = block_input_block_id(block, "SUBSTACK");
-            let mut body = decompile_chain(blocks, sub.as_deref(), indent + 2, visited)?;
+            let mut body = decompile_chain(blocks, report, sub.as_deref(), indent + 2, visited)?;
             out.append(&mut body);
             out.push(format!("{}end", pad));
         }
         "control_repeat_until" => {
-            let c = expr_from_input(blocks, block, "CONDITION")?;
+            let c = expr_from_input(blocks, report, block, "CONDITION")?;
             out.push(format!("{}repeat until <{}>", pad, c));
             let sub = block_input_block_id(block, "SUBSTACK");
-            let mut body = decompile_chain(blocks, sub.as_deref(), indent + 2, visited)?;
+            let mut body = decompile_chain(blocks, report, sub.as_deref(), indent + 2, visited)?;
             out.append(&mut body);
             out.push(format!("{}end", pad));
         }
         "control_forever" => {
             out.push(format!("{}forever", pad));
             let sub = block_input_block_id(block, "SUBSTACK");
-            let mut body = decompile_chain(blocks, sub.as_deref(), indent + 2, visited)?;
+            let mut body = decompile_chain(blocks, report, sub.as_deref(), indent + 2, visited)?;
             out.append(&mut body);
             out.push(format!("{}end", pad));
         }
         "control_if" => {
-            let c = expr_from_input(blocks, block, "CONDITION")?;
+            let c = expr_from_input(blocks, report, block, "CONDITION")?;
             out.push(format!("{}if <{}> then", pad, c));
             let sub = block_input_block_id(block, "SUBSTACK");
-            let mut body = decompile_chain(blocks, sub.as_deref(), indent + 2, visited)?;
+            let mut body = decompile_chain(blocks, report, sub.as_deref(), indent + 2, visited)?;
             out.append(&mut body);
             out.push(format!("{}end", pad));
         }
         "control_if_else" => {
-            let c = expr_from_input(blocks, block, "CONDITION")?;
+            let c = expr_from_input(blocks, report, block, "CONDITION")?;
             out.push(format!("{}if <{}> then", pad, c));
             let sub_then = block_input_block_id(block, "SUBSTACK");
-            let mut then_body = decompile_chain(blocks, sub_then.as_deref(), indent + 2, visited)?;
+            let mut then_body =
+                decompile_chain(blocks, report, sub_then.as_deref(), indent + 2, visited)?;
             out.append(&mut then_body);
             out.push(format!("{}else", pad));
             let sub_else = block_input_block_id(block, "SUBSTACK2");
-            let mut else_body = decompile_chain(blocks, sub_else.as_deref(), indent + 2, visited)?;
+            let mut else_body =
+                decompile_chain(blocks, report, sub_else.as_deref(), indent + 2, visited)?;
             out.append(&mut else_body);
             out.push(format!("{}end", pad));
         }
@@ -654,21 +1554,21 @@ fn decompile_statement(
             out.push(format!("{}stop ({})", pad, quote_str(&option)));
         }
         "control_create_clone_of" => {
-            let target = clone_option(blocks, block).unwrap_or_else(|| "_myself_".to_string());
+            let target = report.menu_or(clone_option(blocks, block), block, "_myself_");
             out.push(format!("{}create clone of ({})", pad, quote_str(&target)));
         }
         "control_delete_this_clone" => out.push(format!("{}delete this clone", pad)),
         "sensing_askandwait" => {
-            let q = expr_from_input(blocks, block, "QUESTION")?;
+            let q = expr_from_input(blocks, report, block, "QUESTION")?;
             out.push(format!("{}ask ({})", pad, q));
         }
         "sensing_resettimer" => out.push(format!("{}reset timer", pad)),
         "sound_play" => {
-            let sound = sound_menu_option(blocks, block).unwrap_or_else(|| "sound".to_string());
+            let sound = report.menu_or(sound_menu_option(blocks, block), block, "sound");
             out.push(format!("{}start sound ({})", pad, quote_str(&sound)));
         }
         "sound_playuntildone" => {
-            let sound = sound_menu_option(blocks, block).unwrap_or_else(|| "sound".to_string());
+            let sound = report.menu_or(sound_menu_option(blocks, block), block, "sound");
             out.push(format!(
                 "{}play sound ({}) until done",
                 pad,
@@ -677,8 +1577,9 @@ fn decompile_statement(
         }
         "sound_stopallsounds" => out.push(format!("{}stop all sounds", pad)),
         "sound_seteffectto" => {
-            let effect = field_first_string(block, "EFFECT").unwrap_or_else(|| "pitch".to_string());
-            let value = expr_from_input(blocks, block, "VALUE")?;
+            let effect = field_first_string(block, "EFFECT")
+                .map_or_else(|| "pitch".to_string(), |effect| effect.to_lowercase());
+            let value = expr_from_input(blocks, report, block, "VALUE")?;
             out.push(format!(
                 "{}set sound effect [{}] to ({})",
                 pad,
@@ -686,13 +1587,29 @@ fn decompile_statement(
                 value
             ));
         }
+        "sound_changeeffectby" => {
+            let effect = field_first_string(block, "EFFECT")
+                .map_or_else(|| "pitch".to_string(), |effect| effect.to_lowercase());
+            let value = expr_from_input(blocks, report, block, "VALUE")?;
+            out.push(format!(
+                "{}change sound effect [{}] by ({})",
+                pad,
+                format_bracket_name(&effect),
+                value
+            ));
+        }
         "sound_setvolumeto" => {
-            let value = expr_from_input(blocks, block, "VOLUME")?;
+            let value = expr_from_input(blocks, report, block, "VOLUME")?;
             out.push(format!("{}set volume to ({})", pad, value));
         }
+        "sound_changevolumeby" => {
+            let value = expr_from_input(blocks, report, block, "VOLUME")?;
+            out.push(format!("{}change volume by ({})", pad, value));
+        }
+        "sound_cleareffects" => out.push(format!("{}clear sound effects", pad)),
         "data_addtolist" => {
             let list = field_first_string(block, "LIST").unwrap_or_else(|| "list".to_string());
-            let item = expr_from_input(blocks, block, "ITEM")?;
+            let item = expr_from_input(blocks, report, block, "ITEM")?;
             out.push(format!(
                 "{}add ({}) to [{}]",
                 pad,
@@ -702,7 +1619,7 @@ fn decompile_statement(
         }
         "data_deleteoflist" => {
             let list = field_first_string(block, "LIST").unwrap_or_else(|| "list".to_string());
-            let idx = expr_from_input(blocks, block, "INDEX")?;
+            let idx = expr_from_input(blocks, report, block, "INDEX")?;
             out.push(format!(
                 "{}delete ({}) of [{}]",
                 pad,
@@ -720,8 +1637,8 @@ fn decompile_statement(
         }
         "data_insertatlist" => {
             let list = field_first_string(block, "LIST").unwrap_or_else(|| "list".to_string());
-            let item = expr_from_input(blocks, block, "ITEM")?;
-            let idx = expr_from_input(blocks, block, "INDEX")?;
+            let item = expr_from_input(blocks, report, block, "ITEM")?;
+            let idx = expr_from_input(blocks, report, block, "INDEX")?;
             out.push(format!(
                 "{}insert ({}) at ({}) of [{}]",
                 pad,
@@ -732,8 +1649,8 @@ fn decompile_statement(
         }
         "data_replaceitemoflist" => {
             let list = field_first_string(block, "LIST").unwrap_or_else(|| "list".to_string());
-            let item = expr_from_input(blocks, block, "ITEM")?;
-            let idx = expr_from_input(blocks, block, "INDEX")?;
+            let item = expr_from_input(blocks, report, block, "ITEM")?;
+            let idx = expr_from_input(blocks, report, block, "INDEX")?;
             out.push(format!(
                 "{}replace item ({}) of [{}] with ({})",
                 pad,
@@ -746,7 +1663,7 @@ fn decompile_statement(
             let (name, arg_order) = procedure_call_shape(block)?;
             let mut line = format!("{}{}", pad, format_call_name(&name));
             for arg_id in arg_order {
-                let arg_expr = expr_from_input(blocks, block, &arg_id)?;
+                let arg_expr = expr_from_input(blocks, report, block, &arg_id)?;
                 line.push_str(&format!(" ({})", arg_expr));
             }
             out.push(line);
@@ -756,46 +1673,190 @@ fn decompile_statement(
         "pen_clear" => out.push(format!("{}erase all", pad)),
         "pen_stamp" => out.push(format!("{}stamp", pad)),
         "pen_changePenSizeBy" => {
-            let v = expr_from_input(blocks, block, "SIZE")?;
+            let v = expr_from_input(blocks, report, block, "SIZE")?;
             out.push(format!("{}change pen size by ({})", pad, v));
         }
         "pen_setPenSizeTo" => {
-            let v = expr_from_input(blocks, block, "SIZE")?;
+            let v = expr_from_input(blocks, report, block, "SIZE")?;
             out.push(format!("{}set pen size to ({})", pad, v));
         }
         "pen_changePenColorParamBy" => {
-            let param = pen_color_param(blocks, block).unwrap_or_else(|| "color".to_string());
-            let v = expr_from_input(blocks, block, "VALUE")?;
+            let param = report.menu_or(pen_color_param(blocks, block), block, "color");
+            let v = expr_from_input(blocks, report, block, "VALUE")?;
             out.push(format!("{}change pen {} by ({})", pad, param, v));
         }
         "pen_setPenColorParamTo" => {
-            let param = pen_color_param(blocks, block).unwrap_or_else(|| "color".to_string());
-            let v = expr_from_input(blocks, block, "VALUE")?;
+            let param = report.menu_or(pen_color_param(blocks, block), block, "color");
+            let v = expr_from_input(blocks, report, block, "VALUE")?;
             out.push(format!("{}set pen {} to ({})", pad, param, v));
         }
         "pen_setPenColorToColor" => {
-            let v = expr_from_input(blocks, block, "COLOR")?;
-            out.push(format!("{}set pen color to ({})", pad, v));
+            let v = expr_from_input(blocks, report, block, "COLOR")?;
+            if v.starts_with('#') {
+                out.push(format!("{}set pen color to ({})", pad, v));
+            } else {
+                // Without a color literal, `set pen color to (expr)` is the
+                // numeric color parameter.
+                out.push(format!("{}set pen color to color ({})", pad, v));
+            }
+        }
+        "music_playNoteForBeats" => {
+            let note = expr_from_input(blocks, report, block, "NOTE")?;
+            let beats = expr_from_input(blocks, report, block, "BEATS")?;
+            out.push(format!("{}play note ({}) for ({}) beats", pad, note, beats));
+        }
+        "music_playDrumForBeats" => {
+            let drum = report.menu_or(
+                music_menu_option(blocks, block, "DRUM", &MUSIC_DRUMS),
+                block,
+                MUSIC_DRUMS[0],
+            );
+            let beats = expr_from_input(blocks, report, block, "BEATS")?;
+            out.push(format!(
+                "{}play drum [{}] for ({}) beats",
+                pad,
+                format_bracket_name(&drum),
+                beats
+            ));
+        }
+        "music_restForBeats" => {
+            let beats = expr_from_input(blocks, report, block, "BEATS")?;
+            out.push(format!("{}rest for ({}) beats", pad, beats));
+        }
+        "music_setInstrument" => {
+            let instrument = report.menu_or(
+                music_menu_option(blocks, block, "INSTRUMENT", &MUSIC_INSTRUMENTS),
+                block,
+                MUSIC_INSTRUMENTS[0],
+            );
+            out.push(format!(
+                "{}set instrument to [{}]",
+                pad,
+                format_bracket_name(&instrument)
+            ));
+        }
+        "music_setTempo" => {
+            let tempo = expr_from_input(blocks, report, block, "TEMPO")?;
+            out.push(format!("{}set tempo to ({})", pad, tempo));
+        }
+        "music_changeTempo" => {
+            let tempo = expr_from_input(blocks, report, block, "TEMPO")?;
+            out.push(format!("{}change tempo by ({})", pad, tempo));
+        }
+        SB2_UNSUPPORTED => {
+            let sb2_opcode = field_first_string(block, "OPCODE").unwrap_or_default();
+            report.unsupported(&sb2_opcode);
+            out.push(format!("{}# unsupported sb2 block: {}", pad, sb2_opcode));
+        }
+        _ => {
+            report.unsupported(op);
+            // The chain walker decompiles whatever follows, so only nested
+            // stacks keep their `next` links inside the raw JSON.
+            let mut raw = raw_block_json(blocks, block);
+            raw.remove("next");
+            out.push(format!("{}raw {}", pad, Value::Object(raw)));
         }
-        _ => out.push(format!(
-            "{}# unsupported opcode: {} (block {})",
-            pad, op, id
-        )),
     }
     Ok(out)
 }
 
-fn expr_from_input(blocks: &Map<String, Value>, block: &Value, input_name: &str) -> Result<String> {
+/// Serializes an unmodeled block for a `raw { ... }` statement: block ids are
+/// replaced by nested objects and variable, list and broadcast references keep
+/// only their names, so the compiler can allocate fresh ids on the way back in.
+fn raw_block_json(blocks: &Map<String, Value>, block: &Value) -> Map<String, Value> {
+    let mut raw = Map::new();
+    let Some(obj) = block.as_object() else {
+        return raw;
+    };
+    for (key, value) in obj {
+        match key.as_str() {
+            "parent" | "topLevel" | "x" | "y" | "comment" => {}
+            "shadow" if value == &Value::Bool(false) => {}
+            "next" => {
+                if let Some(next) = value.as_str().and_then(|id| blocks.get(id)) {
+                    raw.insert(key.clone(), Value::Object(raw_block_json(blocks, next)));
+                }
+            }
+            "inputs" => {
+                let inputs = value
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .map(|(name, slots)| (name.clone(), raw_input_json(blocks, slots)))
+                    .collect();
+                raw.insert(key.clone(), Value::Object(inputs));
+            }
+            "fields" => {
+                let fields = value
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .map(|(name, field)| {
+                        let field = match (name.as_str(), field.get(0)) {
+                            ("VARIABLE" | "LIST" | "BROADCAST_OPTION", Some(value)) => {
+                                json!([value])
+                            }
+                            _ => field.clone(),
+                        };
+                        (name.clone(), field)
+                    })
+                    .collect();
+                raw.insert(key.clone(), Value::Object(fields));
+            }
+            _ => {
+                raw.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    raw
+}
+
+fn raw_input_json(blocks: &Map<String, Value>, slots: &Value) -> Value {
+    let Some(slots) = slots.as_array() else {
+        return slots.clone();
+    };
+    let mut out = Vec::with_capacity(slots.len());
+    for (index, slot) in slots.iter().enumerate() {
+        if index == 0 {
+            out.push(slot.clone());
+            continue;
+        }
+        let value = match slot {
+            Value::String(id) => match blocks.get(id) {
+                Some(nested) => Value::Object(raw_block_json(blocks, nested)),
+                None => Value::Null,
+            },
+            Value::Array(primitive) => match primitive.first().and_then(Value::as_i64) {
+                Some(11..=13) if primitive.len() > 2 => json!([primitive[0], primitive[1]]),
+                _ => slot.clone(),
+            },
+            _ => slot.clone(),
+        };
+        out.push(value);
+    }
+    Value::Array(out)
+}
+
+fn expr_from_input(
+    blocks: &Map<String, Value>,
+    report: &mut DecompileReport,
+    block: &Value,
+    input_name: &str,
+) -> Result<String> {
     let inputs = block.get("inputs").and_then(Value::as_object);
     let Some(input_val) = inputs.and_then(|m| m.get(input_name)) else {
         return Ok("0".to_string());
     };
-    input_to_expr(blocks, input_val)
+    input_to_expr(blocks, report, input_val)
 }
 
-fn input_to_expr(blocks: &Map<String, Value>, input_val: &Value) -> Result<String> {
+fn input_to_expr(
+    blocks: &Map<String, Value>,
+    report: &mut DecompileReport,
+    input_val: &Value,
+) -> Result<String> {
     if let Some(block_id) = input_val.as_str() {
-        return reporter_expr(blocks, block_id);
+        return reporter_expr(blocks, report, block_id);
     }
     let Some(arr) = input_val.as_array() else {
         return Ok("0".to_string());
@@ -805,12 +1866,12 @@ fn input_to_expr(blocks: &Map<String, Value>, input_val: &Value) -> Result<Strin
     }
     let mode = arr[0].as_i64().unwrap_or_default();
     match mode {
-        1 | 2 | 3 => {
-            if let Some(expr) = payload_to_expr(blocks, &arr[1])? {
+        1..=3 => {
+            if let Some(expr) = payload_to_expr(blocks, report, &arr[1])? {
                 return Ok(expr);
             }
             if arr.len() > 2 {
-                if let Some(expr) = payload_to_expr(blocks, &arr[2])? {
+                if let Some(expr) = payload_to_expr(blocks, report, &arr[2])? {
                     return Ok(expr);
                 }
             }
@@ -820,9 +1881,13 @@ fn input_to_expr(blocks: &Map<String, Value>, input_val: &Value) -> Result<Strin
     }
 }
 
-fn payload_to_expr(blocks: &Map<String, Value>, payload: &Value) -> Result<Option<String>> {
+fn payload_to_expr(
+    blocks: &Map<String, Value>,
+    report: &mut DecompileReport,
+    payload: &Value,
+) -> Result<Option<String>> {
     if let Some(block_id) = payload.as_str() {
-        return reporter_expr(blocks, block_id).map(Some);
+        return reporter_expr(blocks, report, block_id).map(Some);
     }
     let Some(arr) = payload.as_array() else {
         return Ok(None);
@@ -836,25 +1901,46 @@ fn payload_to_expr(blocks: &Map<String, Value>, payload: &Value) -> Result<Optio
     Ok(None)
 }
 
-fn reporter_expr(blocks: &Map<String, Value>, block_id: &str) -> Result<String> {
-    let block = get_block(blocks, block_id)?;
+fn reporter_expr(
+    blocks: &Map<String, Value>,
+    report: &mut DecompileReport,
+    block_id: &str,
+) -> Result<String> {
+    let Some(block) = blocks.get(block_id) else {
+        report.missing_blocks.push(block_id.to_string());
+        return Ok("0".to_string());
+    };
     let op = block.get("opcode").and_then(Value::as_str).unwrap_or("");
     let expr = match op {
         "data_variable" => format_var_ref(
             field_first_string(block, "VARIABLE").unwrap_or_else(|| "var".to_string()),
         ),
-        "argument_reporter_string_number" => {
+        "argument_reporter_string_number" | "argument_reporter_boolean" => {
             format_var_ref(field_first_string(block, "VALUE").unwrap_or_default())
         }
         "sensing_answer" => "answer".to_string(),
         "sensing_mousex" => "mouse x".to_string(),
         "sensing_mousey" => "mouse y".to_string(),
+        "sensing_mousedown" => "mouse down?".to_string(),
         "sensing_timer" => "timer".to_string(),
-        "operator_round" => format!("round ({})", expr_from_input(blocks, block, "NUM")?),
+        "sensing_loudness" => "loudness".to_string(),
+        "motion_xposition" => "x position".to_string(),
+        "motion_yposition" => "y position".to_string(),
+        "motion_direction" => "direction".to_string(),
+        "looks_size" => "size".to_string(),
+        "sensing_username" => "username".to_string(),
+        "sensing_dayssince2000" => "days since 2000".to_string(),
+        "music_getTempo" => "tempo".to_string(),
+        "note" => field_first_string(block, "NOTE").unwrap_or_else(|| "60".to_string()),
+        "operator_round" => format!("round ({})", expr_from_input(blocks, report, block, "NUM")?),
         "operator_mathop" => {
             let op_name =
                 field_first_string(block, "OPERATOR").unwrap_or_else(|| "floor".to_string());
-            format!("{} ({})", op_name, expr_from_input(blocks, block, "NUM")?)
+            format!(
+                "{} ({})",
+                op_name,
+                expr_from_input(blocks, report, block, "NUM")?
+            )
         }
         "sensing_of" => {
             let prop = field_first_string(block, "PROPERTY").unwrap_or_else(|| "var".to_string());
@@ -867,40 +1953,69 @@ fn reporter_expr(blocks: &Map<String, Value>, block_id: &str) -> Result<String>
         }
         "operator_random" => format!(
             "pick random ({}) to ({})",
-            expr_from_input(blocks, block, "FROM")?,
-            expr_from_input(blocks, block, "TO")?
+            expr_from_input(blocks, report, block, "FROM")?,
+            expr_from_input(blocks, report, block, "TO")?
         ),
         "data_itemoflist" => {
             let list = field_first_string(block, "LIST").unwrap_or_else(|| "list".to_string());
-            let idx = expr_from_input(blocks, block, "INDEX")?;
+            let idx = expr_from_input(blocks, report, block, "INDEX")?;
             format!("item ({}) of [{}]", idx, format_bracket_name(&list))
         }
         "data_lengthoflist" => {
             let list = field_first_string(block, "LIST").unwrap_or_else(|| "list".to_string());
             format!("length of [{}]", format_bracket_name(&list))
         }
+        "operator_length" => format!(
+            "length of ({})",
+            expr_from_input(blocks, report, block, "STRING")?
+        ),
         "data_listcontents" => {
             let list = field_first_string(block, "LIST").unwrap_or_else(|| "list".to_string());
             format!("contents of [{}]", format_bracket_name(&list))
         }
+        "data_itemnumoflist" => {
+            let list = field_first_string(block, "LIST").unwrap_or_else(|| "list".to_string());
+            let item = expr_from_input(blocks, report, block, "ITEM")?;
+            format!(
+                "item number of ({}) in [{}]",
+                item,
+                format_bracket_name(&list)
+            )
+        }
         "data_listcontainsitem" => {
             let list = field_first_string(block, "LIST").unwrap_or_else(|| "list".to_string());
-            let item = expr_from_input(blocks, block, "ITEM")?;
+            let item = expr_from_input(blocks, report, block, "ITEM")?;
             format!("[{}] contains ({})", format_bracket_name(&list), item)
         }
         "sensing_keypressed" => {
-            let key = key_option(blocks, block).unwrap_or_else(|| "space".to_string());
+            let key = report.menu_or(key_option(blocks, block), block, "space");
             format!("key ({}) pressed?", quote_str(&key))
         }
         "sensing_touchingobject" => {
-            let target = touching_object_option(blocks, block)
-                .unwrap_or_else(|| "mouse-pointer".to_string());
-            format!("touching ({})", quote_str(&target))
+            let target = report.menu_or(
+                touching_object_option(blocks, block),
+                block,
+                "mouse-pointer",
+            );
+            format!("touching [{}]?", format_menu_option(&target))
         }
         "sensing_touchingcolor" => {
-            let color = expr_from_input(blocks, block, "COLOR")?;
-            format!("touching color ({})", color)
+            let color = expr_from_input(blocks, report, block, "COLOR")?;
+            format!("touching color ({})?", color)
         }
+        "sensing_coloristouchingcolor" => format!(
+            "color ({}) is touching ({})?",
+            expr_from_input(blocks, report, block, "COLOR")?,
+            expr_from_input(blocks, report, block, "COLOR2")?
+        ),
+        "looks_costumenumbername" => format!(
+            "costume {}",
+            field_first_string(block, "NUMBER_NAME").unwrap_or_else(|| "number".to_string())
+        ),
+        "looks_backdropnumbername" => format!(
+            "backdrop {}",
+            field_first_string(block, "NUMBER_NAME").unwrap_or_else(|| "number".to_string())
+        ),
         "looks_costume" => {
             let name =
                 field_first_string(block, "COSTUME").unwrap_or_else(|| "costume1".to_string());
@@ -911,24 +2026,51 @@ fn reporter_expr(blocks: &Map<String, Value>, block_id: &str) -> Result<String>
                 field_first_string(block, "BACKDROP").unwrap_or_else(|| "backdrop1".to_string());
             quote_str(&name)
         }
-        "operator_not" => format!("not ({})", expr_from_input(blocks, block, "OPERAND")?),
-        "operator_add" => binary_expr(blocks, block, "+", "NUM1", "NUM2")?,
-        "operator_subtract" => binary_expr(blocks, block, "-", "NUM1", "NUM2")?,
-        "operator_multiply" => binary_expr(blocks, block, "*", "NUM1", "NUM2")?,
-        "operator_divide" => binary_expr(blocks, block, "/", "NUM1", "NUM2")?,
-        "operator_mod" => binary_expr(blocks, block, "%", "NUM1", "NUM2")?,
-        "operator_lt" => binary_expr(blocks, block, "<", "OPERAND1", "OPERAND2")?,
-        "operator_gt" => binary_expr(blocks, block, ">", "OPERAND1", "OPERAND2")?,
-        "operator_equals" => binary_expr(blocks, block, "=", "OPERAND1", "OPERAND2")?,
-        "operator_and" => binary_expr(blocks, block, "and", "OPERAND1", "OPERAND2")?,
-        "operator_or" => binary_expr(blocks, block, "or", "OPERAND1", "OPERAND2")?,
-        _ => "0".to_string(),
+        "operator_join" => format!(
+            "join ({}) ({})",
+            expr_from_input(blocks, report, block, "STRING1")?,
+            expr_from_input(blocks, report, block, "STRING2")?
+        ),
+        "operator_letter_of" => format!(
+            "letter ({}) of ({})",
+            expr_from_input(blocks, report, block, "LETTER")?,
+            expr_from_input(blocks, report, block, "STRING")?
+        ),
+        "operator_contains" => format!(
+            "({}) contains ({})",
+            expr_from_input(blocks, report, block, "STRING1")?,
+            expr_from_input(blocks, report, block, "STRING2")?
+        ),
+        "operator_not" => format!(
+            "not ({})",
+            expr_from_input(blocks, report, block, "OPERAND")?
+        ),
+        "operator_add" => binary_expr(blocks, report, block, "+", "NUM1", "NUM2")?,
+        "operator_subtract" => binary_expr(blocks, report, block, "-", "NUM1", "NUM2")?,
+        "operator_multiply" => binary_expr(blocks, report, block, "*", "NUM1", "NUM2")?,
+        "operator_divide" => binary_expr(blocks, report, block, "/", "NUM1", "NUM2")?,
+        "operator_mod" => binary_expr(blocks, report, block, "%", "NUM1", "NUM2")?,
+        "operator_lt" => binary_expr(blocks, report, block, "<", "OPERAND1", "OPERAND2")?,
+        "operator_gt" => binary_expr(blocks, report, block, ">", "OPERAND1", "OPERAND2")?,
+        "operator_equals" => binary_expr(blocks, report, block, "=", "OPERAND1", "OPERAND2")?,
+        "operator_and" => binary_expr(blocks, report, block, "and", "OPERAND1", "OPERAND2")?,
+        "operator_or" => binary_expr(blocks, report, block, "or", "OPERAND1", "OPERAND2")?,
+        SB2_UNSUPPORTED => {
+            let sb2_opcode = field_first_string(block, "OPCODE").unwrap_or_default();
+            report.unsupported(&sb2_opcode);
+            format!("/* unsupported sb2 reporter {} */ 0", sb2_opcode)
+        }
+        _ => {
+            report.unsupported(op);
+            format!("/* unsupported reporter {} */ 0", op)
+        }
     };
     Ok(expr)
 }
 
 fn binary_expr(
     blocks: &Map<String, Value>,
+    report: &mut DecompileReport,
     block: &Value,
     op: &str,
     left: &str,
@@ -936,9 +2078,9 @@ fn binary_expr(
 ) -> Result<String> {
     Ok(format!(
         "(({}) {} ({}))",
-        expr_from_input(blocks, block, left)?,
+        expr_from_input(blocks, report, block, left)?,
         op,
-        expr_from_input(blocks, block, right)?
+        expr_from_input(blocks, report, block, right)?
     ))
 }
 
@@ -968,7 +2110,12 @@ fn motion_target_option(
 ) -> Option<String> {
     let menu_id = block_input_block_id(block, input_name)?;
     let menu_block = blocks.get(&menu_id)?;
-    field_first_string(menu_block, field_name)
+    let value = field_first_string(menu_block, field_name)?;
+    Some(match value.as_str() {
+        "_mouse_" => "mouse-pointer".to_string(),
+        "_random_" => "random position".to_string(),
+        _ => value,
+    })
 }
 
 fn sound_menu_option(blocks: &Map<String, Value>, block: &Value) -> Option<String> {
@@ -983,6 +2130,25 @@ fn clone_option(blocks: &Map<String, Value>, block: &Value) -> Option<String> {
     field_first_string(menu_block, "CLONE_OPTION")
 }
 
+/// Reads a music drum/instrument menu and maps its stored number back to a name.
+fn music_menu_option(
+    blocks: &Map<String, Value>,
+    block: &Value,
+    menu_name: &str,
+    table: &[&str],
+) -> Option<String> {
+    let menu_id = block_input_block_id(block, menu_name)?;
+    let menu_block = blocks.get(&menu_id)?;
+    let raw = field_first_string(menu_block, menu_name)?;
+    let name = raw
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| table.get(number.checked_sub(1)?))
+        .map(|name| name.to_string());
+    Some(name.unwrap_or(raw))
+}
+
 fn pen_color_param(blocks: &Map<String, Value>, block: &Value) -> Option<String> {
     let menu_id = block_input_block_id(block, "COLOR_PARAM")?;
     let menu_block = blocks.get(&menu_id)?;
@@ -1010,7 +2176,7 @@ fn procedure_call_shape(block: &Value) -> Result<(String, Vec<String>)> {
 fn proccode_name(proccode: &str) -> String {
     let mut parts = Vec::new();
     for token in proccode.split_whitespace() {
-        if token == "%s" {
+        if token == "%s" || token == "%b" {
             break;
         }
         parts.push(token);
@@ -1022,6 +2188,27 @@ fn proccode_name(proccode: &str) -> String {
     }
 }
 
+/// Returns the reporter expression when a broadcast's message is computed
+/// rather than picked from the menu.
+fn dynamic_broadcast_expr(
+    blocks: &Map<String, Value>,
+    report: &mut DecompileReport,
+    block: &Value,
+) -> Result<Option<String>> {
+    let Some(input_id) = block_input_block_id(block, "BROADCAST_INPUT") else {
+        return Ok(None);
+    };
+    let is_reporter = blocks
+        .get(&input_id)
+        .and_then(|b| b.get("shadow"))
+        .and_then(Value::as_bool)
+        == Some(false);
+    if !is_reporter {
+        return Ok(None);
+    }
+    reporter_expr(blocks, report, &input_id).map(Some)
+}
+
 fn broadcast_message(blocks: &Map<String, Value>, block: &Value) -> Option<String> {
     if let Some(menu_id) = block_input_block_id(block, "BROADCAST_INPUT") {
         if let Some(menu_block) = blocks.get(&menu_id) {
@@ -1098,8 +2285,16 @@ fn literal_to_expr_with_code(code: i64, lit: &[Value]) -> String {
         return "0".to_string();
     }
     match code {
-        4 | 5 | 6 | 7 | 8 => lit[1].as_str().unwrap_or("0").to_string(),
-        9 | 10 | 11 => quote_str(lit[1].as_str().unwrap_or("")),
+        4..=8 => lit[1].as_str().unwrap_or("0").to_string(),
+        9 => {
+            let color = lit[1].as_str().unwrap_or("");
+            if is_hex_color(color) {
+                color.to_ascii_lowercase()
+            } else {
+                quote_str(color)
+            }
+        }
+        10 | 11 => quote_str(lit[1].as_str().unwrap_or("")),
         12 => {
             let name = lit[1].as_str().unwrap_or("var");
             format_var_ref(name.to_string())
@@ -1118,6 +2313,10 @@ fn literal_to_expr_with_code(code: i64, lit: &[Value]) -> String {
     }
 }
 
+fn is_hex_color(value: &str) -> bool {
+    value.len() == 7 && value.starts_with('#') && value[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
 fn format_var_ref(name: String) -> String {
     if is_simple_identifier_or_qualified(&name) {
         name
@@ -1134,6 +2333,13 @@ fn format_call_name(name: &str) -> String {
     }
 }
 
+fn format_menu_option(name: &str) -> String {
+    match name {
+        "mouse-pointer" | "random position" | "edge" | "myself" => name.to_string(),
+        _ => format_bracket_name(name),
+    }
+}
+
 fn format_bracket_name(name: &str) -> String {
     if is_simple_identifier_or_qualified(name) {
         name.to_string()
@@ -1206,6 +2412,7 @@ fn is_reserved_keyword(name: &str) -> bool {
             | "left"
             | "length"
             | "list"
+            | "loudness"
             | "mouse"
             | "move"
             | "next"
@@ -1234,6 +2441,7 @@ fn is_reserved_keyword(name: &str) -> bool {
             | "steps"
             | "stop"
             | "switch"
+            | "tempo"
             | "then"
             | "think"
             | "this"
@@ -1242,6 +2450,7 @@ fn is_reserved_keyword(name: &str) -> bool {
             | "touching"
             | "turn"
             | "until"
+            | "username"
             | "var"
             | "wait"
             | "when"
@@ -1252,8 +2461,22 @@ fn is_reserved_keyword(name: &str) -> bool {
     )
 }
 
+/// Quotes a string using the escapes the lexer understands, so output always re-lexes.
 fn quote_str(s: &str) -> String {
-    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            _ => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
 }
 
 fn spaces(n: usize) -> String {
@@ -1279,7 +2502,8 @@ fn render_target(target: &DecompiledTarget) -> String {
     }
 
     for var in &target.variables {
-        let mut line = format!("  var {}", format_decl_name(&var.name));
+        let keyword = if var.is_cloud { "cloud var" } else { "var" };
+        let mut line = format!("  {} {}", keyword, format_decl_name(&var.name));
         if let Some(value) = &var.initial_value {
             line.push_str(" = ");
             line.push_str(&format_initializer_value(value));
@@ -1300,45 +2524,65 @@ fn render_target(target: &DecompiledTarget) -> String {
         }
         lines.push(line);
     }
+    for property in &target.properties {
+        lines.push(format!("  {}", property));
+    }
+    for monitor in &target.monitors {
+        lines.push(format!("  {}", monitor));
+    }
     for costume in &target.costumes {
-        lines.push(format!("  costume {}", quote_str(costume)));
+        let stem = Path::new(&costume.file)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default();
+        if costume.name.is_empty() || costume.name == stem {
+            lines.push(format!("  costume {}", quote_str(&costume.file)));
+        } else {
+            lines.push(format!(
+                "  costume {} {}",
+                quote_str(&costume.name),
+                quote_str(&costume.file)
+            ));
+        }
+    }
+    for sound in &target.sounds {
+        let stem = Path::new(&sound.file)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default();
+        if sound.name.is_empty() || sound.name == stem {
+            lines.push(format!("  sound {}", quote_str(&sound.file)));
+        } else {
+            lines.push(format!(
+                "  sound {} {}",
+                quote_str(&sound.name),
+                quote_str(&sound.file)
+            ));
+        }
     }
 
-    if (!target.variables.is_empty() || !target.lists.is_empty() || !target.costumes.is_empty())
+    if (!target.variables.is_empty()
+        || !target.lists.is_empty()
+        || !target.properties.is_empty()
+        || !target.monitors.is_empty()
+        || !target.costumes.is_empty()
+        || !target.sounds.is_empty())
         && (!target.procedures.is_empty() || !target.scripts.is_empty())
     {
         lines.push(String::new());
     }
 
     for (idx, proc_def) in target.procedures.iter().enumerate() {
-        let mut header = format!(
-            "  define {}{}",
-            if proc_def.warp { "!" } else { "" },
-            format_decl_name(&proc_def.name)
-        );
-        for param in &proc_def.params {
-            header.push_str(&format!(" ({})", format_decl_name(param)));
-        }
-        lines.push(header);
-        if proc_def.body.is_empty() {
-            lines.push("    # empty".to_string());
-        } else {
-            lines.extend(proc_def.body.clone());
-        }
-        lines.push("  end".to_string());
+        push_doc_comment(&mut lines, proc_def.comment.as_deref());
+        push_procedure(&mut lines, proc_def);
         if idx + 1 < target.procedures.len() || !target.scripts.is_empty() {
             lines.push(String::new());
         }
     }
 
     for (idx, script) in target.scripts.iter().enumerate() {
-        lines.push(format!("  {}", script.header));
-        if script.body.is_empty() {
-            lines.push("    # empty".to_string());
-        } else {
-            lines.extend(script.body.clone());
-        }
-        lines.push("  end".to_string());
+        push_doc_comment(&mut lines, script.comment.as_deref());
+        push_script(&mut lines, script);
         if idx + 1 < target.scripts.len() {
             lines.push(String::new());
         }
@@ -1349,6 +2593,79 @@ fn render_target(target: &DecompiledTarget) -> String {
     lines.join("\n")
 }
 
+fn push_procedure(lines: &mut Vec<String>, proc_def: &DecompiledProcedure) {
+    let mut header = format!(
+        "  define {}{}",
+        if proc_def.warp { "!" } else { "" },
+        format_decl_name(&proc_def.name)
+    );
+    for (idx, param) in proc_def.params.iter().enumerate() {
+        if proc_def.boolean_params.get(idx).copied().unwrap_or(false) {
+            header.push_str(&format!(" <{}>", format_decl_name(param)));
+        } else {
+            header.push_str(&format!(" ({})", format_decl_name(param)));
+        }
+    }
+    lines.push(header);
+    if proc_def.body.is_empty() {
+        lines.push("    # empty".to_string());
+    } else {
+        lines.extend(proc_def.body.iter().cloned());
+    }
+    lines.push("  end".to_string());
+}
+
+fn push_script(lines: &mut Vec<String>, script: &DecompiledScript) {
+    lines.push(format!("  {}", script.header));
+    if script.body.is_empty() {
+        lines.push("    # empty".to_string());
+    } else {
+        lines.extend(script.body.iter().cloned());
+    }
+    lines.push("  end".to_string());
+}
+
+/// Renders the top-level script starting at `top_id` the way it appears in a
+/// decompiled target, indented by two spaces. Loose stacks without a hat are
+/// rendered as bare statements, and loose variable or list reporters (stored
+/// as `[tag, name, id, x, y]`) as the reporter alone.
+pub(crate) fn render_top_level_script(
+    blocks: &Map<String, Value>,
+    top_id: &str,
+) -> Result<Vec<String>> {
+    let mut report = DecompileReport::default();
+    let mut lines = Vec::new();
+    let top = get_block(blocks, top_id)?;
+    if let Some(primitive) = top.as_array() {
+        let code = primitive
+            .first()
+            .and_then(Value::as_i64)
+            .unwrap_or_default();
+        return Ok(vec![format!(
+            "  {}",
+            literal_to_expr_with_code(code, primitive)
+        )]);
+    }
+    match top.get("opcode").and_then(Value::as_str) {
+        Some("procedures_definition") => push_procedure(
+            &mut lines,
+            &decompile_procedure(blocks, &mut report, top_id)?,
+        ),
+        Some(
+            "event_whenflagclicked"
+            | "event_whenthisspriteclicked"
+            | "event_whenstageclicked"
+            | "event_whenbroadcastreceived"
+            | "event_whenkeypressed"
+            | "event_whenbackdropswitchesto"
+            | "event_whengreaterthan"
+            | "control_start_as_clone",
+        ) => push_script(&mut lines, &decompile_script(blocks, &mut report, top_id)?),
+        _ => lines = decompile_chain(blocks, &mut report, Some(top_id), 2, &mut HashSet::new())?,
+    }
+    Ok(lines)
+}
+
 fn format_decl_name(name: &str) -> String {
     if is_simple_identifier(name) {
         name.to_string()
@@ -1360,7 +2677,10 @@ fn format_decl_name(name: &str) -> String {
 fn format_initializer_value(value: &Value) -> String {
     match value {
         Value::String(s) => quote_str(s),
-        Value::Number(n) => n.to_string(),
+        Value::Number(n) => n
+            .as_f64()
+            .map(format_decl_number)
+            .unwrap_or_else(|| n.to_string()),
         Value::Bool(b) => {
             if *b {
                 quote_str("true")
@@ -1374,123 +2694,161 @@ fn format_initializer_value(value: &Value) -> String {
 }
 
 fn write_single_project(
-    targets: &[DecompiledTarget],
-    assets: &HashMap<String, Vec<u8>>,
+    project: &DecompiledProject,
     out_file: &Path,
     progress: &mut Option<&mut ProgressCallback<'_>>,
 ) -> Result<()> {
     report_progress(progress, 1, 1, "Writing SBText output");
-    let mut ordered = targets.to_vec();
-    ordered.sort_by_key(|t| if t.is_stage { 0 } else { 1 });
-    let mut text = String::new();
-    for target in &ordered {
-        text.push_str(&render_target(target));
-        text.push('\n');
-    }
-
     if let Some(parent) = out_file.parent() {
         fs::create_dir_all(parent)?;
-        write_assets_for_targets(&ordered, assets, parent, progress, "Writing assets")?;
+        write_assets(&project.assets, parent, progress, "Writing assets")?;
     }
-    fs::write(out_file, text.as_bytes())
+    fs::write(out_file, project.main.as_bytes())
         .with_context(|| format!("Failed to write '{}'.", out_file.display()))?;
     Ok(())
 }
 
-fn write_split_project(
-    targets: &[DecompiledTarget],
-    assets: &HashMap<String, Vec<u8>>,
-    out_dir: &Path,
-    progress: &mut Option<&mut ProgressCallback<'_>>,
-) -> Result<()> {
-    fs::create_dir_all(out_dir)?;
-    let mut stage = None;
-    let mut sprites = Vec::new();
-    for target in targets {
-        if target.is_stage && stage.is_none() {
-            stage = Some(target.clone());
-        } else if !target.is_stage {
-            sprites.push(target.clone());
-        }
+/// Renders every target into one source file, stage first.
+fn render_project(targets: &[DecompiledTarget]) -> String {
+    let mut ordered = targets.iter().collect::<Vec<_>>();
+    ordered.sort_by_key(|t| if t.is_stage { 0 } else { 1 });
+    let mut text = String::new();
+    for target in ordered {
+        text.push_str(&render_target(target));
+        text.push('\n');
     }
+    text
+}
 
+/// Renders `main.sbtext` (imports plus the stage) and one file per sprite.
+fn render_split_project(targets: &[DecompiledTarget]) -> (String, Vec<(String, String)>) {
+    let stage = targets.iter().find(|target| target.is_stage);
     let mut used_files = HashSet::new();
-    let mut imports = Vec::new();
-    let split_file_total = sprites.len() + 1;
-    for (index, sprite) in sprites.iter().enumerate() {
-        let file_name = unique_sprite_filename(&sprite.name, &mut used_files);
-        imports.push((sprite.name.clone(), file_name.clone()));
-        let sprite_path = out_dir.join(&file_name);
-        fs::write(&sprite_path, render_target(sprite).as_bytes())
-            .with_context(|| format!("Failed to write '{}'.", sprite_path.display()))?;
-        report_progress(
-            progress,
-            index + 1,
-            split_file_total.max(1),
-            "Writing split SBText output",
-        );
-    }
-
+    let mut sprites = Vec::new();
     let mut main_text = String::new();
-    for (sprite_name, file_name) in &imports {
+    for sprite in targets.iter().filter(|target| !target.is_stage) {
+        let file_name = unique_sprite_filename(&sprite.name, &mut used_files);
         main_text.push_str(&format!(
             "import [{}] from {}\n",
-            sprite_name,
-            quote_str(file_name)
+            sprite.name,
+            quote_str(&file_name)
         ));
+        sprites.push((file_name, render_target(sprite)));
     }
-    if !imports.is_empty() {
+    if !sprites.is_empty() {
         main_text.push('\n');
     }
     if let Some(stage_target) = stage {
-        main_text.push_str(&render_target(&stage_target));
+        main_text.push_str(&render_target(stage_target));
     } else {
         main_text.push_str("stage\nend\n");
     }
+    (main_text, sprites)
+}
 
-    let main_path = out_dir.join("main.sbtext");
-    fs::write(&main_path, main_text.as_bytes())
-        .with_context(|| format!("Failed to write '{}'.", main_path.display()))?;
-    report_progress(
-        progress,
-        split_file_total.max(1),
-        split_file_total.max(1),
-        "Writing split SBText output",
+fn write_split_project(
+    project: &DecompiledProject,
+    out_dir: &Path,
+    progress: &mut Option<&mut ProgressCallback<'_>>,
+) -> Result<()> {
+    fs::create_dir_all(out_dir)?;
+    let split_file_total = project.sprites.len() + 1;
+    for (index, (file_name, text)) in project.sprites.iter().enumerate() {
+        let sprite_path = out_dir.join(file_name);
+        fs::write(&sprite_path, text.as_bytes())
+            .with_context(|| format!("Failed to write '{}'.", sprite_path.display()))?;
+        report_progress(
+            progress,
+            index + 1,
+            split_file_total,
+            "Writing split SBText output",
+        );
+    }
+
+    let main_path = out_dir.join("main.sbtext");
+    fs::write(&main_path, project.main.as_bytes())
+        .with_context(|| format!("Failed to write '{}'.", main_path.display()))?;
+    report_progress(
+        progress,
+        split_file_total,
+        split_file_total,
+        "Writing split SBText output",
     );
 
-    write_assets_for_targets(targets, assets, out_dir, progress, "Writing split assets")?;
+    write_assets(&project.assets, out_dir, progress, "Writing split assets")?;
     Ok(())
 }
 
-fn write_assets_for_targets(
-    targets: &[DecompiledTarget],
-    assets: &HashMap<String, Vec<u8>>,
+fn write_assets(
+    assets: &BTreeMap<String, Vec<u8>>,
     out_dir: &Path,
     progress: &mut Option<&mut ProgressCallback<'_>>,
     progress_label: &str,
 ) -> Result<()> {
-    let mut needed = HashSet::new();
-    for target in targets {
-        for costume in &target.costumes {
-            needed.insert(costume.clone());
+    for (index, (file, bytes)) in assets.iter().enumerate() {
+        let path = out_dir.join(file);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
         }
+        fs::write(path, bytes)?;
+        report_progress(progress, index + 1, assets.len(), progress_label);
     }
-    let mut needed = needed.into_iter().collect::<Vec<_>>();
-    needed.sort_unstable();
-    if needed.is_empty() {
-        return Ok(());
-    }
-    for (index, asset_name) in needed.iter().enumerate() {
-        if let Some(bytes) = assets.get(asset_name) {
-            let path = out_dir.join(asset_name);
-            if let Some(parent) = path.parent() {
-                fs::create_dir_all(parent)?;
+    Ok(())
+}
+
+/// Renames every costume and sound file to `<asset name>.<ext>`. Names are unique
+/// case-insensitively across the project because all assets share one directory;
+/// the same name with the same bytes reuses one file.
+fn assign_friendly_asset_files(targets: &mut [DecompiledTarget]) {
+    let mut used = HashSet::new();
+    let mut assigned = HashMap::<(String, String), String>::new();
+    for target in targets.iter_mut() {
+        for (asset, fallback) in target
+            .costumes
+            .iter_mut()
+            .map(|asset| (asset, "costume"))
+            .chain(target.sounds.iter_mut().map(|asset| (asset, "sound")))
+        {
+            let key = (asset.name.clone(), asset.md5ext.clone());
+            if let Some(file) = assigned.get(&key) {
+                asset.file = file.clone();
+                continue;
+            }
+            let extension = Path::new(&asset.md5ext)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .map(|ext| format!(".{}", ext))
+                .unwrap_or_default();
+            let mut base = sanitize_asset_stem(&asset.name);
+            if base.is_empty() {
+                base = fallback.to_string();
+            }
+            let mut candidate = format!("{}{}", base, extension);
+            let mut index = 2usize;
+            while !used.insert(candidate.to_lowercase()) {
+                candidate = format!("{}_{}{}", base, index, extension);
+                index += 1;
             }
-            fs::write(path, bytes)?;
+            asset.file = candidate.clone();
+            assigned.insert(key, candidate);
         }
-        report_progress(progress, index + 1, needed.len(), progress_label);
     }
-    Ok(())
+}
+
+/// Keeps spaces and punctuation so the recompiled sound name (taken from the file
+/// stem) matches the original, replacing only characters that are unsafe in paths.
+fn sanitize_asset_stem(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_control() || matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect::<String>()
+        .trim_matches(|c: char| c == '.' || c.is_whitespace())
+        .to_string()
 }
 
 fn unique_sprite_filename(name: &str, used: &mut HashSet<String>) -> String {
@@ -1531,3 +2889,1921 @@ fn default_split_output_dir(input: &Path) -> PathBuf {
         .unwrap_or_else(|| Path::new("."))
         .join(format!("{}_sbtext", stem))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sb3::read_sb3_bytes;
+    use serde_json::json;
+
+    fn compile_archive(source: &str, source_dir: &Path) -> crate::sb3::Sb3Archive {
+        let bytes = crate::compile_source_to_sb3_bytes(source, source_dir, false)
+            .expect("source should compile");
+        read_sb3_bytes(&bytes).expect("sb3 should read")
+    }
+
+    fn tiny_wav() -> Vec<u8> {
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&44u32.to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&22050u32.to_le_bytes());
+        wav.extend_from_slice(&44100u32.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&8u32.to_le_bytes());
+        wav.extend_from_slice(&[0; 8]);
+        wav
+    }
+
+    fn compile_project(source: &str) -> Value {
+        compile_archive(source, Path::new(".")).project
+    }
+
+    fn decompile_project(project: &Value) -> String {
+        let targets = project
+            .get("targets")
+            .and_then(Value::as_array)
+            .expect("targets array");
+        let mut text = String::new();
+        for decompiled in decompile_targets(
+            targets,
+            project.get("monitors"),
+            DecompileOptions::default(),
+            &mut DecompileReport::default(),
+            &mut None,
+        )
+        .expect("targets should decompile")
+        {
+            text.push_str(&render_target(&decompiled));
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Compiles, decompiles, recompiles, and decompiles again, asserting the
+    /// decompiled text is stable. Returns the decompiled text.
+    fn roundtrip(source: &str) -> String {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let archive = compile_archive(source, dir.path());
+        for (name, bytes) in &archive.assets {
+            fs::write(dir.path().join(name), bytes).expect("asset should write");
+        }
+        let first = decompile_project(&archive.project);
+        let second = decompile_project(&compile_archive(&first, dir.path()).project);
+        // Default costumes are regenerated on each compile, so their asset names differ.
+        let strip_costumes = |text: &str| {
+            text.lines()
+                .filter(|line| !line.trim_start().starts_with("costume "))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        assert_eq!(
+            strip_costumes(&first),
+            strip_costumes(&second),
+            "decompiled output should be stable"
+        );
+        first
+    }
+
+    fn opcodes(project: &Value) -> Vec<String> {
+        let mut out = Vec::new();
+        for target in project["targets"].as_array().expect("targets array") {
+            if let Some(blocks) = target["blocks"].as_object() {
+                for block in blocks.values() {
+                    if let Some(opcode) = block.get("opcode").and_then(Value::as_str) {
+                        out.push(opcode.to_string());
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn think_for_seconds_roundtrips() {
+        let source = "sprite Cat\n  when flag clicked\n    think (\"Hmm...\") for (2) seconds\n  end\nend\n";
+        let project = compile_project(source);
+        assert!(opcodes(&project).contains(&"looks_thinkforsecs".to_string()));
+        let text = roundtrip(source);
+        assert!(text.contains("think (\"Hmm...\") for (2) [seconds]"), "{}", text);
+    }
+
+    #[test]
+    fn key_pressed_hat_roundtrips() {
+        let source = "sprite Cat\n  when key [space] pressed\n    move (10)\n  end\n\n  when key [\"up arrow\"] pressed\n    move (5)\n  end\nend\n";
+        let project = compile_project(source);
+        assert!(opcodes(&project).contains(&"event_whenkeypressed".to_string()));
+        let text = roundtrip(source);
+        assert!(text.contains("when key [space] pressed"), "{}", text);
+        assert!(text.contains("when key [\"up arrow\"] pressed"), "{}", text);
+    }
+
+    #[test]
+    fn start_as_clone_hat_roundtrips() {
+        let source = "sprite Cat\n  when flag clicked\n    create clone of (\"_myself_\")\n  end\n\n  when I start as a clone\n    move (10)\n    delete this clone\n  end\nend\n";
+        let project = compile_project(source);
+        let ops = opcodes(&project);
+        assert!(ops.contains(&"control_start_as_clone".to_string()));
+        assert!(ops.contains(&"control_create_clone_of".to_string()));
+        let text = roundtrip(source);
+        assert!(text.contains("when I start as a clone"), "{}", text);
+    }
+
+    #[test]
+    fn backdrop_switch_hat_roundtrips() {
+        let source = "stage\n  when backdrop switches to [backdrop1]\n    broadcast [intro]\n  end\nend\n\nsprite Cat\n  when I receive [intro]\n    say (\"Welcome\")\n  end\nend\n";
+        let project = compile_project(source);
+        assert!(opcodes(&project).contains(&"event_whenbackdropswitchesto".to_string()));
+        let text = roundtrip(source);
+        assert!(text.contains("when backdrop switches to [backdrop1]"), "{}", text);
+    }
+
+    #[test]
+    fn greater_than_hat_roundtrips() {
+        let source = "sprite Cat\n  when loudness > (30)\n    say (\"Loud!\")\n  end\n\n  when timer > (5)\n    say (\"Time!\")\n  end\nend\n";
+        let project = compile_project(source);
+        let hats = project["targets"][1]["blocks"]
+            .as_object()
+            .unwrap()
+            .values()
+            .filter(|b| b["opcode"] == "event_whengreaterthan")
+            .map(|b| b["fields"]["WHENGREATERTHANMENU"][0].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(hats.len(), 2);
+        assert!(hats.contains(&"LOUDNESS".to_string()));
+        assert!(hats.contains(&"TIMER".to_string()));
+        let text = roundtrip(source);
+        assert!(text.contains("when loudness > (30)"), "{}", text);
+        assert!(text.contains("when timer > (5)"), "{}", text);
+    }
+
+    #[test]
+    fn join_roundtrips_including_nested() {
+        let source = "sprite Cat\n  var who\n  when flag clicked\n    say (join (join (\"a\") (who)) (\"!\"))\n    say (join (\"b\") with (who))\n  end\nend\n";
+        let project = compile_project(source);
+        let joins = opcodes(&project)
+            .into_iter()
+            .filter(|op| op == "operator_join")
+            .count();
+        assert_eq!(joins, 3);
+        let text = roundtrip(source);
+        assert!(
+            text.contains("say (join (join (\"a\") (who)) (\"!\"))"),
+            "{}",
+            text
+        );
+        assert!(text.contains("say (join (\"b\") (who))"), "{}", text);
+    }
+
+    #[test]
+    fn letter_of_and_string_contains_roundtrip() {
+        let source = "sprite Cat\n  var word\n  list seen\n  when flag clicked\n    say (letter (1) of (word))\n    if <(word) contains (letter (length of (word)) of (\"xyz\"))?> then\n      say (item number of (word) in [seen])\n    end\n  end\nend\n";
+        let ops = opcodes(&compile_project(source));
+        assert_eq!(
+            ops.iter().filter(|op| *op == "operator_letter_of").count(),
+            2
+        );
+        assert!(ops.contains(&"operator_contains".to_string()));
+        assert!(ops.contains(&"data_itemnumoflist".to_string()));
+        let text = roundtrip(source);
+        assert!(text.contains("say (letter (1) of (word))"), "{}", text);
+        assert!(
+            text.contains("if <(word) contains (letter (length of (word)) of (\"xyz\"))> then"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("say (item number of (word) in [seen])"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn unknown_reporters_are_marked_in_the_output() {
+        let project = json!({
+            "targets": [{
+                "isStage": false,
+                "name": "Cat",
+                "variables": {},
+                "lists": {},
+                "blocks": {
+                    "hat": {"opcode": "event_whenflagclicked", "next": "say", "parent": null,
+                        "inputs": {}, "fields": {}, "shadow": false, "topLevel": true, "x": 0, "y": 0},
+                    "say": {"opcode": "looks_say", "next": null, "parent": "hat",
+                        "inputs": {"MESSAGE": [3, "odd", [10, ""]]}, "fields": {}, "shadow": false, "topLevel": false},
+                    "odd": {"opcode": "sensing_foo", "next": null, "parent": "say",
+                        "inputs": {}, "fields": {}, "shadow": false, "topLevel": false}
+                },
+                "costumes": [],
+                "sounds": []
+            }]
+        });
+        let text = decompile_project(&project);
+        assert!(
+            text.contains("say (/* unsupported reporter sensing_foo */ 0)"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn unknown_blocks_roundtrip_as_raw_statements() {
+        let project = json!({
+            "targets": [{
+                "isStage": false,
+                "name": "Cat",
+                "variables": {"v1": ["score", 0]},
+                "lists": {},
+                "blocks": {
+                    "hat": {"opcode": "event_whenflagclicked", "next": "ext", "parent": null,
+                        "inputs": {}, "fields": {}, "shadow": false, "topLevel": true, "x": 0, "y": 0},
+                    "ext": {"opcode": "gadget_spin", "next": "say", "parent": "hat",
+                        "inputs": {
+                            "SPEED": [3, "rep", [4, "10"]],
+                            "AMOUNT": [3, [12, "score", "v1"], [4, "0"]],
+                            "SUBSTACK": [2, "inner"]
+                        },
+                        "fields": {"MODE": ["fast", null]}, "shadow": false, "topLevel": false},
+                    "rep": {"opcode": "gadget_speed", "next": null, "parent": "ext",
+                        "inputs": {}, "fields": {}, "shadow": false, "topLevel": false},
+                    "inner": {"opcode": "data_changevariableby", "next": null, "parent": "ext",
+                        "inputs": {"VALUE": [1, [4, "1"]]},
+                        "fields": {"VARIABLE": ["score", "v1"]}, "shadow": false, "topLevel": false},
+                    "say": {"opcode": "looks_say", "next": null, "parent": "ext",
+                        "inputs": {"MESSAGE": [1, [10, "after"]]}, "fields": {}, "shadow": false, "topLevel": false}
+                },
+                "costumes": [],
+                "sounds": []
+            }]
+        });
+        let text = decompile_project(&project);
+        assert!(text.contains("    raw {\"fields\""), "{}", text);
+        assert!(!text.contains("\"v1\""), "{}", text);
+        assert!(text.contains("    say (\"after\")"), "{}", text);
+
+        let recompiled = compile_project(&text);
+        assert!(recompiled["extensions"]
+            .as_array()
+            .expect("extensions array")
+            .contains(&json!("gadget")));
+        let target = &recompiled["targets"][1];
+        let var_id = target["variables"]
+            .as_object()
+            .and_then(|vars| vars.iter().find(|(_, var)| var[0] == "score"))
+            .map(|(id, _)| id.clone())
+            .expect("score variable");
+        let blocks = target["blocks"].as_object().expect("blocks");
+        let find = |opcode: &str| {
+            blocks
+                .iter()
+                .find(|(_, block)| block["opcode"] == opcode)
+                .unwrap_or_else(|| panic!("missing {}", opcode))
+        };
+        let (hat_id, _) = find("event_whenflagclicked");
+        let (ext_id, ext) = find("gadget_spin");
+        let (rep_id, rep) = find("gadget_speed");
+        let (inner_id, inner) = find("data_changevariableby");
+        let (say_id, say) = find("looks_say");
+        assert_eq!(ext["parent"], json!(hat_id));
+        assert_eq!(ext["next"], json!(say_id));
+        assert_eq!(say["parent"], json!(ext_id));
+        assert_eq!(ext["fields"]["MODE"], json!(["fast", null]));
+        assert_eq!(ext["inputs"]["SPEED"], json!([3, rep_id, [4, "10"]]));
+        assert_eq!(
+            ext["inputs"]["AMOUNT"],
+            json!([3, [12, "score", var_id], [4, "0"]])
+        );
+        assert_eq!(ext["inputs"]["SUBSTACK"], json!([2, inner_id]));
+        assert_eq!(rep["parent"], json!(ext_id));
+        assert_eq!(inner["parent"], json!(ext_id));
+        assert_eq!(inner["fields"]["VARIABLE"], json!(["score", var_id]));
+        let raw_line = text
+            .lines()
+            .find(|line| line.trim_start().starts_with("raw "))
+            .expect("raw line");
+        assert!(decompile_project(&recompiled).contains(raw_line));
+    }
+
+    #[test]
+    fn report_counts_scripts_and_records_what_was_lost() {
+        let block = |opcode: &str, next: Option<&str>, inputs: Value| {
+            json!({"opcode": opcode, "next": next, "parent": null, "inputs": inputs,
+                "fields": {}, "shadow": false, "topLevel": false})
+        };
+        let mut hat = block("event_whenflagclicked", Some("stretch1"), json!({}));
+        hat["topLevel"] = json!(true);
+        let mut loop_hat = block("event_whenflagclicked", Some("a"), json!({}));
+        loop_hat["topLevel"] = json!(true);
+        let project = json!({
+            "targets": [{
+                "isStage": false,
+                "name": "Cat",
+                "variables": {},
+                "lists": {},
+                "blocks": {
+                    "hat": hat,
+                    "stretch1": block("looks_setstretchto", Some("stretch2"), json!({})),
+                    "stretch2": block("looks_setstretchto", Some("goto"), json!({})),
+                    "goto": block("motion_goto", Some("say"), json!({})),
+                    "say": block("looks_say", Some("ghost"), json!({"MESSAGE": [3, "odd", [10, ""]]})),
+                    "odd": block("sensing_foo", None, json!({})),
+                    "loop_hat": loop_hat,
+                    "a": block("looks_show", Some("b"), json!({})),
+                    "b": block("looks_hide", Some("a"), json!({}))
+                },
+                "costumes": [],
+                "sounds": []
+            }]
+        });
+        let mut report = DecompileReport::default();
+        let targets = project["targets"].as_array().expect("targets array");
+        let decompiled = decompile_targets(
+            targets,
+            None,
+            DecompileOptions::default(),
+            &mut report,
+            &mut None,
+        )
+        .expect("targets should decompile");
+        let text = render_target(&decompiled[0]);
+        assert!(text.contains("# warning: missing block ghost"), "{}", text);
+
+        assert_eq!(report.scripts, 2);
+        assert_eq!(report.procedures, 0);
+        assert_eq!(
+            report.unsupported_opcodes,
+            BTreeMap::from([
+                ("looks_setstretchto".to_string(), 2),
+                ("sensing_foo".to_string(), 1)
+            ])
+        );
+        assert_eq!(report.cyclic_chains, ["Cat: a"]);
+        assert_eq!(report.missing_blocks, ["Cat: ghost"]);
+        assert_eq!(report.unresolved_menus, ["Cat: motion_goto"]);
+        assert_eq!(
+            report.summary(),
+            "decompiled 2 scripts and 0 procedures; 3 blocks unsupported: \
+             looks_setstretchto x2, sensing_foo x1; 1 cyclic chain cut; 1 missing block; \
+             1 unresolved menu"
+        );
+    }
+
+    #[test]
+    fn string_and_list_length_roundtrip() {
+        let source = "sprite Cat\n  var who\n  list items\n  when flag clicked\n    say (length of (\"hello\"))\n    say (length of (who))\n    say (length of [items])\n  end\nend\n";
+        let project = compile_project(source);
+        let ops = opcodes(&project);
+        assert_eq!(ops.iter().filter(|op| *op == "operator_length").count(), 2);
+        assert_eq!(
+            ops.iter().filter(|op| *op == "data_lengthoflist").count(),
+            1
+        );
+        let text = roundtrip(source);
+        assert!(text.contains("say (length of (\"hello\"))"), "{}", text);
+        assert!(text.contains("say (length of (who))"), "{}", text);
+        assert!(text.contains("say (length of [items])"), "{}", text);
+    }
+
+    #[test]
+    fn touching_object_roundtrips() {
+        let source = "sprite Cat\n  when flag clicked\n    if <touching [mouse-pointer]?> then\n      say (\"mouse\")\n    end\n    if <touching [edge]?> then\n      say (\"edge\")\n    end\n    wait until <touching [Enemy]?>\n  end\nend\n\nsprite Enemy\nend\n";
+        let project = compile_project(source);
+        let menus = project["targets"][1]["blocks"]
+            .as_object()
+            .unwrap()
+            .values()
+            .filter(|b| b["opcode"] == "sensing_touchingobjectmenu")
+            .map(|b| {
+                b["fields"]["TOUCHINGOBJECTMENU"][0]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(menus.len(), 3);
+        for expected in ["_mouse_", "_edge_", "Enemy"] {
+            assert!(menus.contains(&expected.to_string()), "{:?}", menus);
+        }
+        let text = roundtrip(source);
+        assert!(text.contains("touching [mouse-pointer]?"), "{}", text);
+        assert!(text.contains("touching [edge]?"), "{}", text);
+        assert!(text.contains("touching [Enemy]?"), "{}", text);
+    }
+
+    #[test]
+    fn color_sensing_roundtrips_with_hex_literals() {
+        let source = "sprite Cat\n  when flag clicked\n    wait until <touching color (#FF0000)?>\n    if <color (#00ff00) is touching (#0000ff)?> then\n      say (\"hit\")\n    end\n  end\nend\n";
+        let project = compile_project(source);
+        let blocks = project["targets"][1]["blocks"].as_object().unwrap();
+        let touching = blocks
+            .values()
+            .find(|b| b["opcode"] == "sensing_touchingcolor")
+            .unwrap();
+        assert_eq!(touching["inputs"]["COLOR"], json!([1, [9, "#ff0000"]]));
+        let color_touching = blocks
+            .values()
+            .find(|b| b["opcode"] == "sensing_coloristouchingcolor")
+            .unwrap();
+        assert_eq!(
+            color_touching["inputs"]["COLOR2"],
+            json!([1, [9, "#0000ff"]])
+        );
+        let text = roundtrip(source);
+        assert!(text.contains("touching color (#ff0000)?"), "{}", text);
+        assert!(
+            text.contains("color (#00ff00) is touching (#0000ff)?"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn mouse_down_roundtrips_in_conditions() {
+        let source = "sprite Cat\n  when flag clicked\n    wait until <mouse down?>\n    if <mouse down?> then\n      say (\"down\")\n    end\n  end\nend\n";
+        let project = compile_project(source);
+        let count = opcodes(&project)
+            .iter()
+            .filter(|op| *op == "sensing_mousedown")
+            .count();
+        assert_eq!(count, 2);
+        let text = roundtrip(source);
+        assert!(text.contains("wait until <mouse down?>"), "{}", text);
+        assert!(text.contains("if <mouse down?> then"), "{}", text);
+    }
+
+    #[test]
+    fn loudness_reporter_roundtrips() {
+        let source = "sprite Cat\n  when flag clicked\n    if <(loudness) > (30)> then\n      say (loudness)\n    end\n  end\n\n  when loudness > (10)\n    say (\"hi\")\n  end\nend\n";
+        let project = compile_project(source);
+        let count = opcodes(&project)
+            .iter()
+            .filter(|op| *op == "sensing_loudness")
+            .count();
+        assert_eq!(count, 2);
+        let text = roundtrip(source);
+        assert!(text.contains("if <((loudness) > (30))> then"), "{}", text);
+        assert!(text.contains("say (loudness)"), "{}", text);
+        assert!(text.contains("when loudness > (10)"), "{}", text);
+    }
+
+    #[test]
+    fn username_and_days_since_2000_roundtrip() {
+        let source = "sprite Cat\n  var days\n  when flag clicked\n    say (join (username) (days since 2000))\n    set [days] to (days)\n  end\nend\n";
+        let project = compile_project(source);
+        let ops = opcodes(&project);
+        assert!(ops.contains(&"sensing_username".to_string()));
+        assert!(ops.contains(&"sensing_dayssince2000".to_string()));
+        let text = roundtrip(source);
+        assert!(
+            text.contains("say (join (username) (days since 2000))"),
+            "{}",
+            text
+        );
+        assert!(text.contains("set [days] to (days)"), "{}", text);
+    }
+
+    #[test]
+    fn motion_reporters_roundtrip() {
+        let source = "sprite Cat\n  define report (value)\n    say (value)\n  end\n\n  when flag clicked\n    set x to ((x position) + (10))\n    set y to ((y position) - (5))\n    report (direction)\n    report (size)\n  end\nend\n";
+        let project = compile_project(source);
+        let ops = opcodes(&project);
+        for opcode in [
+            "motion_xposition",
+            "motion_yposition",
+            "motion_direction",
+            "looks_size",
+        ] {
+            assert!(ops.contains(&opcode.to_string()), "{} missing", opcode);
+        }
+        let text = roundtrip(source);
+        assert!(
+            text.contains("set x to (((x position) + (10)))"),
+            "{}",
+            text
+        );
+        assert!(text.contains("set y to (((y position) - (5)))"), "{}", text);
+        assert!(text.contains("report (direction)"), "{}", text);
+        assert!(text.contains("report (size)"), "{}", text);
+    }
+
+    #[test]
+    fn costume_and_backdrop_number_name_roundtrip() {
+        let source = "sprite Cat\n  when flag clicked\n    say (costume number)\n    say (costume name)\n    say (backdrop number)\n    say (backdrop name)\n  end\nend\n";
+        let project = compile_project(source);
+        let blocks = project["targets"][1]["blocks"].as_object().unwrap();
+        let fields = blocks
+            .values()
+            .filter(|b| {
+                b["opcode"] == "looks_costumenumbername"
+                    || b["opcode"] == "looks_backdropnumbername"
+            })
+            .map(|b| {
+                format!(
+                    "{}:{}",
+                    b["opcode"].as_str().unwrap(),
+                    b["fields"]["NUMBER_NAME"][0].as_str().unwrap()
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(fields.len(), 4);
+        assert!(fields.contains(&"looks_costumenumbername:name".to_string()));
+        assert!(fields.contains(&"looks_backdropnumbername:number".to_string()));
+        let text = roundtrip(source);
+        for expected in [
+            "say (costume number)",
+            "say (costume name)",
+            "say (backdrop number)",
+            "say (backdrop name)",
+        ] {
+            assert!(text.contains(expected), "{}", text);
+        }
+    }
+
+    #[test]
+    fn show_and_hide_list_roundtrip() {
+        let source = "sprite Cat\n  list scores\n  when flag clicked\n    show list [scores]\n    hide list [scores]\n  end\nend\n";
+        let project = compile_project(source);
+        let blocks = project["targets"][1]["blocks"].as_object().unwrap();
+        let show = blocks
+            .values()
+            .find(|b| b["opcode"] == "data_showlist")
+            .unwrap();
+        assert_eq!(show["fields"]["LIST"][0], "scores");
+        assert!(opcodes(&project).contains(&"data_hidelist".to_string()));
+        let text = roundtrip(source);
+        assert!(text.contains("show list [scores]"), "{}", text);
+        assert!(text.contains("hide list [scores]"), "{}", text);
+    }
+
+    #[test]
+    fn item_number_of_list_roundtrips() {
+        let source = "sprite Cat\n  list seen\n  when flag clicked\n    if <(item number of (\"apple\") in [seen]) > (0)> then\n      say (item number of (\"apple\") in [seen])\n    end\n  end\nend\n";
+        let project = compile_project(source);
+        let count = opcodes(&project)
+            .iter()
+            .filter(|op| *op == "data_itemnumoflist")
+            .count();
+        assert_eq!(count, 2);
+        let text = roundtrip(source);
+        assert!(
+            text.contains("say (item number of (\"apple\") in [seen])"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("if <((item number of (\"apple\") in [seen]) > (0))> then"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn sound_declarations_embed_and_decompile() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let wav = tiny_wav();
+        fs::write(dir.path().join("meow.wav"), &wav).expect("wav should write");
+
+        let source = "sprite Cat\n  sound \"meow.wav\"\n  when flag clicked\n    start sound [meow]\n  end\nend\n";
+        let archive = compile_archive(source, dir.path());
+        let sound = &archive.project["targets"][1]["sounds"][0];
+        assert_eq!(sound["name"], "meow");
+        assert_eq!(sound["dataFormat"], "wav");
+        assert_eq!(sound["rate"], 22050);
+        assert_eq!(sound["sampleCount"], 4);
+        let md5ext = sound["md5ext"].as_str().expect("md5ext");
+        assert!(archive.assets.iter().any(|(name, _)| name == md5ext));
+
+        let text = decompile_project(&archive.project);
+        assert!(text.contains(&format!("sound \"meow\" \"{}\"", md5ext)));
+        assert!(text.contains("start sound (\"meow\")"));
+    }
+
+    /// Each target's blocks as sorted `opcode fields` lines, ignoring ids and layout.
+    fn block_shapes(project: &Value) -> Vec<Vec<String>> {
+        project["targets"]
+            .as_array()
+            .expect("targets array")
+            .iter()
+            .map(|target| {
+                let mut shapes = target["blocks"]
+                    .as_object()
+                    .map(|blocks| {
+                        blocks
+                            .values()
+                            .map(|block| {
+                                let fields = block["fields"]
+                                    .as_object()
+                                    .map(|fields| {
+                                        fields
+                                            .iter()
+                                            .map(|(key, value)| format!("{}={}", key, value[0]))
+                                            .collect::<Vec<_>>()
+                                            .join(",")
+                                    })
+                                    .unwrap_or_default();
+                                format!("{} {}", block["opcode"], fields)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_default();
+                shapes.sort();
+                shapes
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sound_clone_and_data_blocks_roundtrip_structurally() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        fs::write(dir.path().join("meow.wav"), tiny_wav()).expect("wav should write");
+        let source = "stage\n  var score\n  list names\n  when flag clicked\n    show variable [score]\n    hide variable [score]\n    show list [names]\n    hide list [names]\n  end\nend\n\nsprite Cat\n  sound \"meow.wav\"\n  when flag clicked\n    start sound [meow]\n    play sound [meow] until done\n    stop all sounds\n    set sound effect [pitch] to (20)\n    change sound effect [pan] by (5)\n    clear sound effects\n    set volume to (50)\n    change volume by (10)\n    create clone of (\"_myself_\")\n    create clone of (\"Dog\")\n    say (Dog.hp)\n    say ([Dog.x position])\n  end\n  when I start as a clone\n    delete this clone\n  end\nend\n\nsprite Dog\n  var hp\nend\n";
+        let archive = compile_archive(source, dir.path());
+        for (name, bytes) in &archive.assets {
+            fs::write(dir.path().join(name), bytes).expect("asset should write");
+        }
+        let text = decompile_project(&archive.project);
+        assert!(!text.contains("unsupported opcode"), "{}", text);
+        let recompiled = compile_archive(&text, dir.path());
+        assert_eq!(
+            block_shapes(&archive.project),
+            block_shapes(&recompiled.project),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn change_volume_and_clear_sound_effects_roundtrip() {
+        let source = "sprite Cat\n  when flag clicked\n    change volume by (10)\n    clear sound effects\n  end\nend\n";
+        let ops = opcodes(&compile_project(source));
+        assert!(ops.contains(&"sound_changevolumeby".to_string()));
+        assert!(ops.contains(&"sound_cleareffects".to_string()));
+        let text = roundtrip(source);
+        assert!(text.contains("change volume by (10)"), "{}", text);
+        assert!(text.contains("clear sound effects"), "{}", text);
+    }
+
+    #[test]
+    fn change_sound_effect_roundtrip() {
+        let source = "sprite Cat\n  when flag clicked\n    change sound pitch by (10)\n    change sound pan by (-5)\n    set sound effect [Pan] to (3)\n  end\nend\n";
+        let project = compile_project(source);
+        let blocks = project["targets"][1]["blocks"].as_object().unwrap();
+        let effects = |opcode: &str| {
+            let mut effects = blocks
+                .values()
+                .filter(|b| b["opcode"] == opcode)
+                .map(|b| b["fields"]["EFFECT"][0].as_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+            effects.sort();
+            effects
+        };
+        assert_eq!(effects("sound_changeeffectby"), ["PAN", "PITCH"]);
+        assert_eq!(effects("sound_seteffectto"), ["PAN"]);
+        let text = roundtrip(source);
+        assert!(
+            text.contains("change sound effect [pitch] by (10)"),
+            "{}",
+            text
+        );
+        assert!(text.contains("set sound effect [pan] to (3)"), "{}", text);
+    }
+
+    #[test]
+    fn music_blocks_roundtrip() {
+        let source = "sprite Cat\n  when flag clicked\n    set instrument to [electric guitar]\n    set tempo to (90)\n    change tempo by (10)\n    play note (60) for (0.5) beats\n    play note (tempo) for (1) beats\n    play drum [snare] for (0.25) beats\n    rest for (1) beats\n    say (tempo)\n  end\nend\n";
+        let project = compile_project(source);
+        assert_eq!(project["extensions"], json!(["music"]));
+        let blocks = project["targets"][1]["blocks"].as_object().unwrap();
+        let drum_menu = blocks
+            .values()
+            .find(|b| b["opcode"] == "music_menu_DRUM")
+            .unwrap();
+        assert_eq!(drum_menu["fields"]["DRUM"][0], "1");
+        let instrument_menu = blocks
+            .values()
+            .find(|b| b["opcode"] == "music_menu_INSTRUMENT")
+            .unwrap();
+        assert_eq!(instrument_menu["fields"]["INSTRUMENT"][0], "5");
+        assert!(opcodes(&project).contains(&"music_getTempo".to_string()));
+        let mut note_inputs = blocks
+            .values()
+            .filter(|b| b["opcode"] == "music_playNoteForBeats")
+            .map(|b| &b["inputs"]["NOTE"])
+            .collect::<Vec<_>>();
+        note_inputs.sort_by_key(|input| input[0].as_u64());
+        assert_eq!(note_inputs.len(), 2);
+        assert_eq!(note_inputs[0][0], 1);
+        assert_eq!(note_inputs[1][0], 3);
+        assert_eq!(
+            blocks[note_inputs[1][1].as_str().unwrap()]["opcode"],
+            "music_getTempo"
+        );
+        let note_shadows = note_inputs
+            .iter()
+            .map(|input| {
+                let shadow = &blocks[input.as_array().unwrap().last().unwrap().as_str().unwrap()];
+                assert_eq!(shadow["opcode"], "note");
+                assert_eq!(shadow["shadow"], true);
+                shadow["fields"]["NOTE"][0].clone()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(note_shadows, [json!("60"), json!("60")]);
+
+        let text = roundtrip(source);
+        for line in [
+            "set instrument to [\"electric guitar\"]",
+            "set tempo to (90)",
+            "change tempo by (10)",
+            "play note (60) for (0.5) beats",
+            "play note (tempo) for (1) beats",
+            "play drum [\"snare drum\"] for (0.25) beats",
+            "rest for (1) beats",
+            "say (tempo)",
+        ] {
+            assert!(text.contains(line), "missing {:?} in {}", line, text);
+        }
+    }
+
+    #[test]
+    fn pen_color_hex_roundtrip() {
+        let source = "sprite Cat\n  when flag clicked\n    set pen color to (#00ff00)\n    set pen color to (50)\n  end\nend\n";
+        let project = compile_project(source);
+        assert_eq!(project["extensions"], json!(["pen"]));
+        let blocks = project["targets"][1]["blocks"].as_object().unwrap();
+        let block = blocks
+            .values()
+            .find(|b| b["opcode"] == "pen_setPenColorToColor")
+            .unwrap();
+        assert_eq!(block["inputs"]["COLOR"], json!([1, [9, "#00ff00"]]));
+        assert!(opcodes(&project).contains(&"pen_setPenColorParamTo".to_string()));
+        let text = roundtrip(source);
+        assert!(text.contains("set pen color to (#00ff00)"), "{}", text);
+    }
+
+    #[test]
+    fn computed_pen_colors_decompile_as_expressions() {
+        let project = json!({
+            "targets": [{
+                "isStage": false, "name": "Cat", "variables": {"v1": ["tint", 0]},
+                "lists": {}, "broadcasts": {},
+                "blocks": {
+                    "hat": {"opcode": "event_whenflagclicked", "next": "pen", "parent": null,
+                        "inputs": {}, "fields": {}, "shadow": false, "topLevel": true, "x": 0, "y": 0},
+                    "pen": {"opcode": "pen_setPenColorToColor", "next": null, "parent": "hat",
+                        "inputs": {"COLOR": [3, [12, "tint", "v1"], [9, "#ff0000"]]},
+                        "fields": {}, "shadow": false, "topLevel": false}
+                },
+                "costumes": [],
+                "sounds": []
+            }]
+        });
+        let text = decompile_project(&project);
+        assert!(
+            text.contains("    set pen color to color (tint)\n"),
+            "{}",
+            text
+        );
+        let recompiled = compile_project(&text);
+        let blocks = recompiled["targets"][1]["blocks"].as_object().unwrap();
+        let pen = blocks
+            .values()
+            .find(|b| b["opcode"] == "pen_setPenColorToColor")
+            .expect("pen color block");
+        assert_eq!(pen["inputs"]["COLOR"][0], 2);
+        let reporter = &blocks[pen["inputs"]["COLOR"][1].as_str().unwrap()];
+        assert_eq!(reporter["opcode"], "data_variable");
+        assert_eq!(reporter["fields"]["VARIABLE"][0], "tint");
+        assert_eq!(recompiled["extensions"], json!(["pen"]));
+    }
+
+    #[test]
+    fn go_to_target_roundtrip() {
+        let source = "sprite Cat\n  when flag clicked\n    go to [mouse-pointer]\n    go to [random position]\n    go to [Dog]\n    go to [front] layer\n  end\nend\n\nsprite Dog\nend\n";
+        let project = compile_project(source);
+        let blocks = project["targets"][1]["blocks"].as_object().unwrap();
+        let menus = blocks
+            .values()
+            .filter(|b| b["opcode"] == "motion_goto_menu")
+            .map(|b| b["fields"]["TO"][0].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(menus.len(), 3);
+        for expected in ["_mouse_", "_random_", "Dog"] {
+            assert!(menus.contains(&expected.to_string()), "{:?}", menus);
+        }
+        assert!(opcodes(&project).contains(&"looks_gotofrontback".to_string()));
+        let text = roundtrip(source);
+        assert!(text.contains("go to [mouse-pointer]"), "{}", text);
+        assert!(text.contains("go to [random position]"), "{}", text);
+        assert!(text.contains("go to [Dog]"), "{}", text);
+    }
+
+    #[test]
+    fn motion_and_looks_menus_fixture_roundtrips() {
+        let fixture =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/adv/test_motion_looks_menus.sbtext");
+        let source = fs::read_to_string(fixture).expect("fixture should read");
+        let text = roundtrip(&source);
+        assert!(!text.contains("unsupported opcode"), "{}", text);
+        for line in [
+            "go to [random position]",
+            "go to [Target]",
+            "glide (1) to [mouse-pointer]",
+            "glide (0.5) to x (20) y (((0) - (20)))",
+            "point towards [Target]",
+            "set rotation style [\"left-right\"]",
+            "switch costume to (\"costume1\")",
+            "switch backdrop to (\"backdrop1\")",
+            "set graphic effect [ghost] to (50)",
+            "change graphic effect [color] by (25)",
+            "clear graphic effects",
+            "go to [front] layer",
+            "go [backward] (2) layers",
+        ] {
+            assert!(
+                text.contains(&format!("    {}\n", line)),
+                "{}\n{}",
+                line,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn glide_roundtrip() {
+        let source = "sprite Cat\n  when flag clicked\n    glide (1) to x (10) y (20)\n    glide (0.5) to [random position]\n    glide (2) to [mouse-pointer]\n    glide (1) to [Dog]\n  end\nend\n\nsprite Dog\nend\n";
+        let project = compile_project(source);
+        let blocks = project["targets"][1]["blocks"].as_object().unwrap();
+        let menus = blocks
+            .values()
+            .filter(|b| b["opcode"] == "motion_glideto_menu")
+            .map(|b| b["fields"]["TO"][0].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(menus.len(), 3);
+        for expected in ["_random_", "_mouse_", "Dog"] {
+            assert!(menus.contains(&expected.to_string()), "{:?}", menus);
+        }
+        assert!(opcodes(&project).contains(&"motion_glidesecstoxy".to_string()));
+        let text = roundtrip(source);
+        for line in [
+            "glide (1) to x (10) y (20)",
+            "glide (0.5) to [random position]",
+            "glide (2) to [mouse-pointer]",
+            "glide (1) to [Dog]",
+        ] {
+            assert!(text.contains(line), "missing {:?} in {}", line, text);
+        }
+    }
+
+    #[test]
+    fn point_towards_roundtrip() {
+        let source = "sprite Cat\n  when flag clicked\n    point towards [mouse-pointer]\n    point towards [Dog]\n  end\nend\n\nsprite Dog\nend\n";
+        let project = compile_project(source);
+        let blocks = project["targets"][1]["blocks"].as_object().unwrap();
+        let menus = blocks
+            .values()
+            .filter(|b| b["opcode"] == "motion_pointtowards_menu")
+            .map(|b| b["fields"]["TOWARDS"][0].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(menus.len(), 2);
+        for expected in ["_mouse_", "Dog"] {
+            assert!(menus.contains(&expected.to_string()), "{:?}", menus);
+        }
+        let text = roundtrip(source);
+        assert!(text.contains("point towards [mouse-pointer]"), "{}", text);
+        assert!(text.contains("point towards [Dog]"), "{}", text);
+    }
+
+    #[test]
+    fn while_and_for_each_roundtrip() {
+        let source = "sprite Cat\n  var n\n  var total\n  when flag clicked\n    for each [n] in (10)\n      change [total] by (n)\n      while <(total) > (100)>\n        change [total] by (-1)\n      end\n    end\n    while <(total) > (0)>\n      change [total] by (1)\n    end\n  end\nend\n";
+        let ops = opcodes(&compile_project(source));
+        assert!(ops.contains(&"control_for_each".to_string()));
+        assert!(ops.contains(&"control_while".to_string()));
+        let text = roundtrip(source);
+        assert!(!text.contains("unsupported opcode"), "{}", text);
+        assert!(
+            text.contains(
+                "    for each [n] in (10)\n      change [total] by (n)\n      while <((total) > (100))>\n        change [total] by (((0) - (1)))\n      end\n    end\n"
+            ),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("    while <((total) > (0))>\n      change [total] by (1)\n    end\n"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn switch_backdrop_and_wait_roundtrip() {
+        let source = "stage\n  when flag clicked\n    switch backdrop to (\"intro\") and wait\n    switch backdrop to (\"level\")\n  end\nend\n";
+        let ops = opcodes(&compile_project(source));
+        assert!(ops.contains(&"looks_switchbackdroptoandwait".to_string()));
+        assert!(ops.contains(&"looks_switchbackdropto".to_string()));
+        let text = roundtrip(source);
+        assert!(
+            text.contains("switch backdrop to (\"intro\") and wait"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn sprite_properties_roundtrip() {
+        let source = "sprite Cat\n  x -120\n  y 80.5\n  size 50\n  direction 180\n  hidden\n  rotation style [left-right]\nend\n";
+        let project = compile_project(source);
+        let cat = &project["targets"][1];
+        assert_eq!(cat["x"], json!(-120.0));
+        assert_eq!(cat["y"], json!(80.5));
+        assert_eq!(cat["size"], json!(50.0));
+        assert_eq!(cat["direction"], json!(180.0));
+        assert_eq!(cat["visible"], json!(false));
+        assert_eq!(cat["rotationStyle"], "left-right");
+        let text = roundtrip(source);
+        for line in [
+            "  x -120",
+            "  y 80.5",
+            "  size 50",
+            "  direction 180",
+            "  hidden",
+            "  rotation style [\"left-right\"]",
+        ] {
+            assert!(text.contains(line), "missing {:?} in {}", line, text);
+        }
+    }
+
+    #[test]
+    fn sb3_fixture_renders_sprite_placement() {
+        let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/placement.sb3");
+        let (text, report) = decompile_sb3_to_string(&input, DecompileOptions::default())
+            .expect("placement.sb3 should decompile");
+        assert_eq!(
+            text,
+            concat!(
+                "stage\n",
+                "  costume \"backdrop1\" \"b75eeb440b34ebdfa62d262b774bff31.svg\"\n",
+                "end\n",
+                "\n",
+                "sprite Dog\n",
+                "  costume \"sit\" \"fa78767ad109d104c021260be31ce946.svg\"\n",
+                "end\n",
+                "\n",
+                "sprite Cat\n",
+                "  x -120\n",
+                "  y 80.5\n",
+                "  size 50\n",
+                "  direction -90\n",
+                "  hidden\n",
+                "  rotation style [\"left-right\"]\n",
+                "  volume 40\n",
+                "  start costume [walk]\n",
+                "  costume \"idle\" \"2a8a9a06e73271478a427222c5fe13ee.svg\"\n",
+                "  costume \"walk\" \"4a15232079a0ba598191695b042bf956.svg\"\n",
+                "end\n",
+                "\n",
+            )
+        );
+        assert_eq!(report.summary(), "decompiled 0 scripts and 0 procedures");
+    }
+
+    #[test]
+    fn stage_properties_roundtrip() {
+        let source = "stage\n  tempo 90\n  volume 80\n  video transparency 0\n  video [off]\nend\n\nsprite Cat\n  volume 40\nend\n";
+        let project = compile_project(source);
+        let stage = &project["targets"][0];
+        assert_eq!(stage["tempo"], json!(90.0));
+        assert_eq!(stage["volume"], json!(80.0));
+        assert_eq!(stage["videoTransparency"], json!(0.0));
+        assert_eq!(stage["videoState"], "off");
+        assert_eq!(project["targets"][1]["volume"], json!(40.0));
+        let text = roundtrip(source);
+        for line in [
+            "stage\n  tempo 90\n  volume 80\n  video transparency 0\n  video [off]\n",
+            "  volume 40\n",
+        ] {
+            assert!(text.contains(line), "missing {:?} in {}", line, text);
+        }
+
+        let defaults = roundtrip("stage\nend\n");
+        assert!(!defaults.contains("tempo"), "{}", defaults);
+        assert!(!defaults.contains("video"), "{}", defaults);
+    }
+
+    #[test]
+    fn remote_calls_roundtrip_as_qualified_calls() {
+        let source = "sprite Enemy\n  var hp\n  define hit (amount) (kind)\n    change [hp] by (amount)\n  end\n  define respawn\n    set [hp] to (10)\n  end\nend\n\nsprite Player\n  when flag clicked\n    Enemy.hit (5) (\"fire\")\n    Enemy.respawn\n    say (\"done\")\n  end\nend\n";
+        let text = roundtrip(source);
+        assert!(!text.contains(RPC_PREFIX), "{}", text);
+        for line in [
+            "    Enemy.hit (5) (\"fire\")\n",
+            "    Enemy.respawn\n    say (\"done\")\n",
+        ] {
+            assert!(text.contains(line), "missing {:?} in {}", line, text);
+        }
+        assert_eq!(text.matches("when ").count(), 1, "{}", text);
+    }
+
+    #[test]
+    fn keep_remote_calls_leaves_the_lowering_in_place() {
+        let source = "sprite Enemy\n  define hit (amount)\n    say (amount)\n  end\nend\n\nsprite Player\n  when flag clicked\n    Enemy.hit (5)\n  end\nend\n";
+        let project = compile_project(source);
+        let targets = project["targets"].as_array().expect("targets array");
+        let options = DecompileOptions {
+            keep_remote_calls: true,
+            ..Default::default()
+        };
+        let mut report = DecompileReport::default();
+        let text = decompile_targets(targets, None, options, &mut report, &mut None)
+            .expect("targets should decompile")
+            .iter()
+            .map(render_target)
+            .collect::<String>();
+        assert!(!text.contains("Enemy.hit"), "{}", text);
+        assert!(
+            text.contains("when I receive [__rpc__enemy__hit]"),
+            "{}",
+            text
+        );
+        assert!(text.contains("__rpc__enemy__hit__arg1__queue"), "{}", text);
+        assert_eq!(report.scripts, 2);
+    }
+
+    #[test]
+    fn legacy_remote_call_globals_fold_into_calls() {
+        let project = json!({
+            "targets": [
+                {
+                    "isStage": true, "name": "Stage", "blocks": {},
+                    "variables": {"g1": ["__rpc__enemy__hit__arg1", 0]},
+                    "lists": {}, "broadcasts": {"b1": "__rpc__enemy__hit"}
+                },
+                {
+                    "isStage": false, "name": "Enemy", "variables": {}, "lists": {},
+                    "blocks": {
+                        "hat": {"opcode": "event_whenbroadcastreceived", "next": "call",
+                                "parent": null, "inputs": {}, "topLevel": true, "x": 0, "y": 0,
+                                "fields": {"BROADCAST_OPTION": ["__rpc__enemy__hit", "b1"]}},
+                        "call": {"opcode": "procedures_call", "next": null, "parent": "hat",
+                                 "inputs": {"a": [3, [12, "__rpc__enemy__hit__arg1", "g1"], [10, ""]]},
+                                 "fields": {}, "topLevel": false,
+                                 "mutation": {"proccode": "hit %s", "argumentids": "[\"a\"]"}}
+                    }
+                },
+                {
+                    "isStage": false, "name": "Player", "variables": {}, "lists": {},
+                    "blocks": {
+                        "flag": {"opcode": "event_whenflagclicked", "next": "set", "parent": null,
+                                 "inputs": {}, "fields": {}, "topLevel": true, "x": 0, "y": 0},
+                        "set": {"opcode": "data_setvariableto", "next": "send", "parent": "flag",
+                                "inputs": {"VALUE": [1, [4, "3"]]},
+                                "fields": {"VARIABLE": ["__rpc__enemy__hit__arg1", "g1"]},
+                                "topLevel": false},
+                        "send": {"opcode": "event_broadcastandwait", "next": null, "parent": "set",
+                                 "inputs": {"BROADCAST_INPUT": [1, [11, "__rpc__enemy__hit", "b1"]]},
+                                 "fields": {}, "topLevel": false}
+                    }
+                }
+            ]
+        });
+        let text = decompile_project(&project);
+        assert!(
+            text.contains("  when flag clicked\n    Enemy.hit (3)\n"),
+            "{}",
+            text
+        );
+        assert!(!text.contains(RPC_PREFIX), "{}", text);
+    }
+
+    #[test]
+    fn layer_order_roundtrips() {
+        let source = "sprite Sky\nend\n\nsprite Hero\n  layer 1\nend\n\nsprite Cloud\nend\n";
+        let project = compile_project(source);
+        let layer_of = |name: &str| {
+            project["targets"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|t| t["name"] == name)
+                .map(|t| t["layerOrder"].clone())
+                .unwrap()
+        };
+        assert_eq!(layer_of("Hero"), json!(1));
+        assert_eq!(layer_of("Sky"), json!(2));
+        assert_eq!(layer_of("Cloud"), json!(3));
+
+        // Declaration order follows the stacking, so no pins are needed.
+        let text = roundtrip(source);
+        let hero = text.find("sprite Hero").unwrap();
+        let sky = text.find("sprite Sky").unwrap();
+        let cloud = text.find("sprite Cloud").unwrap();
+        assert!(hero < sky && sky < cloud, "{}", text);
+        assert!(!text.contains("layer "), "{}", text);
+
+        let mut gapped = project.clone();
+        gapped["targets"][2]["layerOrder"] = json!(5);
+        let text = decompile_project(&gapped);
+        assert!(text.contains("  layer 5\n"), "{}", text);
+    }
+
+    #[test]
+    fn variable_initial_values_roundtrip() {
+        let source = "stage\n  var level = 3\nend\n\nsprite Cat\n  var score = 10\n  var name = \"Alice\"\n  var plain\nend\n";
+        let project = compile_project(source);
+        let initial_values = |target: &Value| {
+            target["variables"]
+                .as_object()
+                .unwrap()
+                .values()
+                .map(|v| (v[0].as_str().unwrap().to_string(), v[1].clone()))
+                .collect::<HashMap<_, _>>()
+        };
+        let stage = initial_values(&project["targets"][0]);
+        assert_eq!(stage["level"], json!(3.0));
+        let cat = initial_values(&project["targets"][1]);
+        assert_eq!(cat["score"], json!(10.0));
+        assert_eq!(cat["name"], json!("Alice"));
+        assert_eq!(cat["plain"], json!(0));
+
+        let text = roundtrip(source);
+        for line in [
+            "  var level = 3\n",
+            "  var score = 10\n",
+            "  var name = \"Alice\"\n",
+            "  var plain\n",
+        ] {
+            assert!(text.contains(line), "missing {:?} in {}", line, text);
+        }
+    }
+
+    #[test]
+    fn list_initial_items_roundtrip() {
+        let many = (1..=200)
+            .map(|n| format!("\"item {}\"", n))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let source = format!(
+            "sprite Cat\n  list fruits = [\"apple\", \"passion fruit\", 3]\n  list many = [{}]\n  list empty\nend\n",
+            many
+        );
+        let project = compile_project(&source);
+        let lists = project["targets"][1]["lists"]
+            .as_object()
+            .unwrap()
+            .values()
+            .map(|v| (v[0].as_str().unwrap().to_string(), v[1].clone()))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(lists["fruits"], json!(["apple", "passion fruit", 3.0]));
+        assert_eq!(lists["many"].as_array().unwrap().len(), 200);
+        assert_eq!(lists["empty"], json!([]));
+
+        let text = roundtrip(&source);
+        assert!(
+            text.contains("  list fruits = [\"apple\", \"passion fruit\", 3]\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains(&format!("  list many = [{}]\n", many)));
+        assert!(text.contains("  list empty\n"));
+    }
+
+    #[test]
+    fn boolean_procedure_params_roundtrip() {
+        let source = "sprite Cat\n  var d\n  define check (limit) <strict>\n    if <strict> then\n      say (limit)\n    end\n  end\n\n  when flag clicked\n    check (10) ((d) < (5))\n  end\nend\n";
+        let project = compile_project(source);
+        let blocks = project["targets"][1]["blocks"].as_object().unwrap();
+        let prototype = blocks
+            .values()
+            .find(|b| b["opcode"] == "procedures_prototype")
+            .unwrap();
+        assert_eq!(prototype["mutation"]["proccode"], "check %s %b");
+        assert_eq!(
+            prototype["mutation"]["argumentdefaults"],
+            "[\"\",\"false\"]"
+        );
+        let ops = opcodes(&project);
+        assert!(ops.contains(&"argument_reporter_boolean".to_string()));
+        assert!(ops.contains(&"argument_reporter_string_number".to_string()));
+        let call = blocks
+            .values()
+            .find(|b| b["opcode"] == "procedures_call")
+            .unwrap();
+        let arg_ids: Vec<String> =
+            serde_json::from_str(call["mutation"]["argumentids"].as_str().unwrap()).unwrap();
+        let bool_arg = call["inputs"][&arg_ids[1]][1].as_str().unwrap();
+        assert_eq!(blocks[bool_arg]["opcode"], "operator_lt");
+
+        let text = roundtrip(source);
+        assert!(
+            text.contains("  define check (limit) <strict>\n    if <strict> then\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains("    check (10) (((d) < (5)))\n"), "{}", text);
+    }
+
+    #[test]
+    fn warp_procedures_roundtrip() {
+        let source = "sprite Cat\n  define !fast (n)\n    say (n)\n  end\n\n  define slow (n) run without screen refresh\n    say (n)\n  end\n\n  define plain\n    say (\"hi\")\n  end\nend\n";
+        let warp_by_proccode = |project: &Value| {
+            project["targets"][1]["blocks"]
+                .as_object()
+                .unwrap()
+                .values()
+                .filter(|b| b["opcode"] == "procedures_prototype")
+                .map(|b| {
+                    (
+                        b["mutation"]["proccode"].as_str().unwrap().to_string(),
+                        b["mutation"]["warp"].as_str().unwrap().to_string(),
+                    )
+                })
+                .collect::<HashMap<_, _>>()
+        };
+        let warp = warp_by_proccode(&compile_project(source));
+        assert_eq!(warp["fast %s"], "true");
+        assert_eq!(warp["slow %s"], "true");
+        assert_eq!(warp["plain"], "false");
+
+        let text = roundtrip(source);
+        assert!(text.contains("  define !fast (n)\n"), "{}", text);
+        assert!(text.contains("  define !slow (n)\n"), "{}", text);
+        assert!(text.contains("  define plain\n"), "{}", text);
+        let without_costumes = text
+            .lines()
+            .filter(|line| !line.trim_start().starts_with("costume "))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let warp = warp_by_proccode(&compile_project(&without_costumes));
+        assert_eq!(warp["fast %s"], "true");
+        assert_eq!(warp["slow %s"], "true");
+        assert_eq!(warp["plain"], "false");
+    }
+
+    #[test]
+    fn dynamic_broadcast_roundtrip() {
+        let source = "sprite Cat\n  var n\n  when flag clicked\n    broadcast (join (\"level\") (n))\n    broadcast and wait (n)\n    broadcast [ready]\n  end\nend\n";
+        let project = compile_project(source);
+        let blocks = project["targets"][1]["blocks"].as_object().unwrap();
+        let broadcast = blocks
+            .values()
+            .find(|b| b["opcode"] == "event_broadcast" && b["inputs"]["BROADCAST_INPUT"][0] == 3)
+            .expect("dynamic broadcast block");
+        let reporter = broadcast["inputs"]["BROADCAST_INPUT"][1].as_str().unwrap();
+        assert_eq!(blocks[reporter]["opcode"], "operator_join");
+        assert_eq!(broadcast["inputs"]["BROADCAST_INPUT"][2][0], 11);
+        let stage_broadcasts = project["targets"][0]["broadcasts"].as_object().unwrap();
+        assert!(stage_broadcasts.values().any(|name| name == "message1"));
+
+        let text = roundtrip(source);
+        for line in [
+            "    broadcast (join (\"level\") (n))\n",
+            "    broadcast and wait (n)\n",
+            "    broadcast [ready]\n",
+        ] {
+            assert!(text.contains(line), "missing {:?} in {}", line, text);
+        }
+    }
+
+    #[test]
+    fn doc_comments_roundtrip() {
+        let source = "sprite Cat\n  ## Moves the cat.\n  ##\n  ## Called every frame.\n  define step\n    ## plain comment inside a body\n    move (10) steps\n  end\n\n  ## Entry point\n  when flag clicked\n    step\n  end\n\n  when this sprite clicked\n    say (\"hi\")\n  end\nend\n";
+        let project = compile_project(source);
+        let target = &project["targets"][1];
+        let comments = target["comments"].as_object().unwrap();
+        assert_eq!(comments.len(), 2);
+        let blocks = target["blocks"].as_object().unwrap();
+        for comment in comments.values() {
+            let block_id = comment["blockId"].as_str().unwrap();
+            let opcode = blocks[block_id]["opcode"].as_str().unwrap();
+            let expected = match opcode {
+                "procedures_definition" => "Moves the cat.\n\nCalled every frame.",
+                "event_whenflagclicked" => "Entry point",
+                other => panic!("comment attached to {}", other),
+            };
+            assert_eq!(comment["text"], expected);
+            assert_eq!(comment["minimized"], false);
+            assert!(comment["height"].as_u64().unwrap() > 0);
+            assert!(blocks[block_id]["comment"].is_string());
+        }
+
+        let text = roundtrip(source);
+        assert!(
+            text.contains("  ## Moves the cat.\n  ##\n  ## Called every frame.\n  define step\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("  ## Entry point\n  when flag clicked\n"),
+            "{}",
+            text
+        );
+        assert!(!text.contains("plain comment"), "{}", text);
+        assert!(!text.contains("##\n  when this sprite clicked"), "{}", text);
+    }
+
+    #[test]
+    fn string_escapes_roundtrip() {
+        let source = "sprite Cat\n  var \"He said \\\"hi\\\"\\\\path\"\n  when flag clicked\n    set [\"He said \\\"hi\\\"\\\\path\"] to (\"line one\\nline\\ttwo\")\n    say ([\"He said \\\"hi\\\"\\\\path\"])\n  end\nend\n";
+        let project = compile_project(source);
+        let variables = project["targets"][1]["variables"].as_object().unwrap();
+        assert!(variables.values().any(|v| v[0] == "He said \"hi\"\\path"));
+        let blocks = project["targets"][1]["blocks"].as_object().unwrap();
+        let set = blocks
+            .values()
+            .find(|b| b["opcode"] == "data_setvariableto")
+            .unwrap();
+        assert_eq!(set["inputs"]["VALUE"][1][1], "line one\nline\ttwo");
+
+        let text = roundtrip(source);
+        assert!(
+            text.contains("  var \"He said \\\"hi\\\"\\\\path\"\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains("\"line one\\nline\\ttwo\""), "{}", text);
+    }
+
+    #[test]
+    fn exponent_number_literals_keep_precision() {
+        let source = "sprite Cat\n  when flag clicked\n    say (1e20)\n    say (2.5e-3)\n    say (1.5e-7)\n    say (.5)\n  end\nend\n";
+        let project = compile_project(source);
+        let blocks = project["targets"][1]["blocks"].as_object().unwrap();
+        let values = blocks
+            .values()
+            .filter(|b| b["opcode"] == "looks_say")
+            .map(|b| b["inputs"]["MESSAGE"][1][1].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        for value in &values {
+            let parsed = value.parse::<f64>().unwrap();
+            assert!(
+                [1e20, 2.5e-3, 1.5e-7, 0.5].contains(&parsed),
+                "{} lost precision",
+                value
+            );
+        }
+        assert_eq!(values.len(), 4);
+    }
+
+    #[test]
+    fn in_memory_decompile_renders_single_and_split_projects() {
+        let source = "stage\nend\n\nsprite Cat\n  costume \"Idle\" \"assets/player_idle.svg\"\n  when flag clicked\n    say (\"hi\")\n  end\nend\n\nsprite Dog\n  when flag clicked\n    move (5)\n  end\nend\n";
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+        let bytes = crate::sb3::build_sb3_bytes(&compile_archive(source, &fixtures))
+            .expect("sb3 should build");
+
+        let single = decompile_sb3_bytes(&bytes, false).expect("sb3 should decompile");
+        assert!(single.sprites.is_empty());
+        assert!(single.main.starts_with("stage\n"), "{}", single.main);
+        assert!(
+            single.main.contains("sprite Cat\n  costume \"Idle.svg\"\n"),
+            "{}",
+            single.main
+        );
+        assert!(single.main.contains("sprite Dog\n"), "{}", single.main);
+        assert!(single.assets.contains_key("Idle.svg"));
+        assert!(single.assets.contains_key("backdrop1.svg"));
+        assert_eq!(
+            single.report.summary(),
+            "decompiled 2 scripts and 0 procedures"
+        );
+
+        let split = decompile_sb3_bytes(&bytes, true).expect("sb3 should decompile");
+        assert!(
+            split.main.starts_with(
+                "import [Cat] from \"Cat.sbtext\"\nimport [Dog] from \"Dog.sbtext\"\n\nstage\n"
+            ),
+            "{}",
+            split.main
+        );
+        let files = split
+            .sprites
+            .iter()
+            .map(|(file, _)| file.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(files, ["Cat.sbtext", "Dog.sbtext"]);
+        assert!(split.sprites[0].1.starts_with("sprite Cat\n"));
+        assert!(split.sprites[1].1.contains("    move (5)"));
+        assert_eq!(split.assets, single.assets);
+    }
+
+    #[test]
+    fn sb2_projects_decompile_through_the_sb3_path() {
+        let svg =
+            br#"<svg xmlns="http://www.w3.org/2000/svg" width="2" height="2"></svg>"#.to_vec();
+        let project = json!({
+            "objName": "Stage",
+            "variables": [{"name": "score", "value": 3, "isPersistent": false}],
+            "lists": [{"listName": "log", "contents": ["a"], "visible": true, "x": 5, "y": 6}],
+            "scripts": [
+                [0, 0, [
+                    ["whenClicked"],
+                    ["stopScripts", "other scripts in stage"]
+                ]]
+            ],
+            "costumes": [{"costumeName": "backdrop1", "baseLayerID": 1,
+                "baseLayerMD5": "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa.svg", "rotationCenterX": 240, "rotationCenterY": 180}],
+            "sounds": [],
+            "currentCostumeIndex": 0,
+            "tempoBPM": 90,
+            "children": [
+                {
+                    "objName": "Cat",
+                    "variables": [],
+                    "lists": [],
+                    "scripts": [
+                        [10, 10, [
+                            ["whenGreenFlag"],
+                            ["setVar:to:", "score", 0],
+                            ["doRepeat", 10, [
+                                ["changeVar:by:", "score", 1],
+                                ["forward:", ["*", ["readVariable", "score"], 2]]
+                            ]],
+                            ["doIfElse", [">", ["readVariable", "score"], 5],
+                                [["say:duration:elapsed:from:", "big", 2]],
+                                [["lookLike:", "Idle"]]],
+                            ["append:toList:", ["concatenate:with:", "n", ["readVariable", "score"]], "log"],
+                            ["call", "jump %n high", 4],
+                            ["fancyNewBlock:", 1],
+                            ["say:", ["mysteryReporter"]],
+                            ["setPenHueTo:", 100],
+                            ["changePenShadeBy:", ["readVariable", "score"]],
+                            ["broadcast:", "go"]
+                        ]],
+                        [10, 200, [
+                            ["procDef", "jump %n high", ["height"], [1], true],
+                            ["changeYposBy:", ["getParam", "height", "r"]]
+                        ]],
+                        [10, 300, [
+                            ["whenIReceive", "go"],
+                            ["stopScripts", "other scripts in sprite"]
+                        ]]
+                    ],
+                    "costumes": [{"costumeName": "Idle", "baseLayerID": 0,
+                        "baseLayerMD5": "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb.svg", "rotationCenterX": 1, "rotationCenterY": 1}],
+                    "sounds": [],
+                    "currentCostumeIndex": 0,
+                    "scratchX": 20,
+                    "scratchY": -10,
+                    "scale": 0.5,
+                    "direction": 90,
+                    "rotationStyle": "leftRight",
+                    "visible": true
+                },
+                {"target": "Stage", "cmd": "getVar:", "param": "score", "mode": 2, "visible": true, "x": 1, "y": 2}
+            ]
+        });
+        let mut files = std::collections::BTreeMap::new();
+        files.insert("0.svg".to_string(), svg.clone());
+        files.insert("1.svg".to_string(), svg);
+        let bytes =
+            crate::sb3::build_sb3_bytes(&Sb3Archive::new(project, files)).expect("sb2 should zip");
+
+        let decompiled = decompile_sb3_bytes(&bytes, false).expect("sb2 should decompile");
+        let text = &decompiled.main;
+        for expected in [
+            "stage\n  var score = 3\n  list log = [\"a\"]\n",
+            "  tempo 90\n",
+            "  monitor [score] at (1) (2) mode [large]\n",
+            "  monitor [log] at (5) (6)\n",
+            "sprite Cat\n",
+            "  costume \"Idle.svg\"\n",
+            "  x 20\n  y -10\n  size 50\n",
+            "  rotation style [\"left-right\"]\n",
+            "  define !jump (height)\n    change y by (height)\n  end\n",
+            "    set [score] to (0)\n    repeat (10)\n      change [score] by (1)\n      move (((score) * (2))) [steps]\n    end\n",
+            "    if <((score) > (5))> then\n      say (\"big\") for (2) [seconds]\n    else\n      switch costume to (\"Idle\")\n    end\n",
+            "    add (join (\"n\") (score)) to [log]\n",
+            "    jump (4)\n",
+            "    # unsupported sb2 block: fancyNewBlock:\n",
+            "    say (/* unsupported sb2 reporter mysteryReporter */ 0)\n",
+            "    set pen color to (50)\n    change pen brightness by (((score) * (2)))\n",
+            "    broadcast [\"go\"]\n",
+            "  when this sprite clicked\n    stop (\"other scripts in stage\")\n",
+            "  when I receive [\"go\"]\n    stop (\"other scripts in sprite\")\n",
+        ] {
+            assert!(text.contains(expected), "missing {:?} in\n{}", expected, text);
+        }
+        assert!(decompiled.assets.contains_key("Idle.svg"));
+        assert!(decompiled.assets.contains_key("backdrop1.svg"));
+
+        let dir = tempfile::tempdir().expect("temp dir");
+        for (name, bytes) in &decompiled.assets {
+            fs::write(dir.path().join(name), bytes).expect("asset should write");
+        }
+        let recompiled = compile_archive(text, dir.path());
+        let recompiled_opcodes = opcodes(&recompiled.project);
+        assert!(recompiled_opcodes.contains(&"procedures_call".to_string()));
+        assert!(recompiled_opcodes.contains(&"event_whenstageclicked".to_string()));
+    }
+
+    #[test]
+    fn bare_project_json_decompiles_without_assets() {
+        let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/project.json");
+        let dir = tempfile::tempdir().expect("temp dir");
+        let out_file = dir.path().join("game.sbtext");
+        decompile_sb3(&input, Some(&out_file), false).expect("project.json should decompile");
+
+        let text = fs::read_to_string(&out_file).expect("output should read");
+        assert!(
+            text.contains("  costume \"Idle\" \"87f6d86ed9b1c4e5c1809e2449b5cb96.svg\"\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains(
+                "    repeat (3)\n      change [score] by (1)\n      say (score)\n    end\n"
+            ),
+            "{}",
+            text
+        );
+        let written = fs::read_dir(dir.path())
+            .expect("output dir")
+            .map(|entry| entry.expect("dir entry").file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(written, ["game.sbtext"]);
+
+        let err = decompile_sb3(&out_file, None, false).unwrap_err();
+        assert!(
+            err.to_string().contains("is not a valid zip/.sb3 file"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn decompile_to_string_matches_file_output() {
+        let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/project.json");
+        let dir = tempfile::tempdir().expect("temp dir");
+        let out_file = dir.path().join("game.sbtext");
+        let file_report =
+            decompile_sb3(&input, Some(&out_file), false).expect("project.json should decompile");
+
+        let (text, report) = decompile_sb3_to_string(&input, DecompileOptions::default())
+            .expect("project.json should decompile");
+        assert_eq!(
+            text,
+            fs::read_to_string(&out_file).expect("output should read")
+        );
+        assert!(text.starts_with("stage\n"), "{}", text);
+        let without_warnings = |report: &DecompileReport| DecompileReport {
+            warnings: Vec::new(),
+            ..report.clone()
+        };
+        assert_eq!(without_warnings(&report), without_warnings(&file_report));
+        assert_eq!(report.summary(), "decompiled 1 script and 0 procedures");
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].contains("not written when decompiling to stdout"));
+        assert_eq!(file_report.warnings.len(), 1);
+        assert!(
+            file_report.warnings[0].contains("contains no asset files"),
+            "{:?}",
+            file_report.warnings
+        );
+    }
+
+    #[test]
+    fn decompiled_assets_use_friendly_unique_names() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10" viewBox="0 0 10 10"><rect width="10" height="10"/></svg>"#;
+        fs::write(dir.path().join("hero_walk.svg"), svg).expect("svg should write");
+        fs::write(
+            dir.path().join("enemy_walk.svg"),
+            svg.replace("10\"/>", "5\"/>"),
+        )
+        .expect("svg should write");
+        fs::write(dir.path().join("Big meow.wav"), tiny_wav()).expect("wav should write");
+        let mut other_wav = tiny_wav();
+        *other_wav.last_mut().unwrap() ^= 0xff;
+        fs::write(dir.path().join("enemy_meow.wav"), other_wav).expect("wav should write");
+
+        let source = "sprite Hero\n  costume \"Walking\" \"hero_walk.svg\"\n  sound \"Big meow.wav\"\nend\n\nsprite Enemy\n  costume \"Walking\" \"enemy_walk.svg\"\n  sound \"Big meow\" \"enemy_meow.wav\"\nend\n";
+        let bytes = crate::compile_source_to_sb3_bytes(source, dir.path(), false)
+            .expect("source should compile");
+        let input = dir.path().join("game.sb3");
+        fs::write(&input, &bytes).expect("sb3 should write");
+        let original = read_sb3_bytes(&bytes).expect("sb3 should read");
+
+        let friendly = dir.path().join("friendly");
+        decompile_sb3(&input, Some(&friendly.join("game.sbtext")), false).expect("decompile");
+        let text = fs::read_to_string(friendly.join("game.sbtext")).expect("sbtext");
+        assert!(text.contains("  costume \"Walking.svg\"\n"), "{}", text);
+        assert!(text.contains("  sound \"Big meow.wav\"\n"), "{}", text);
+        assert!(
+            text.contains("  costume \"Walking\" \"Walking_2.svg\"\n"),
+            "{}",
+            text
+        );
+        assert!(friendly.join("Walking.svg").exists());
+        assert!(
+            text.contains("  sound \"Big meow\" \"Big meow_2.wav\"\n"),
+            "{}",
+            text
+        );
+        assert!(friendly.join("Walking_2.svg").exists());
+        assert!(friendly.join("Big meow.wav").exists());
+        assert!(friendly.join("Big meow_2.wav").exists());
+
+        let recompiled = compile_archive(&text, &friendly);
+        for (index, kind) in [
+            (1, "costumes"),
+            (1, "sounds"),
+            (2, "costumes"),
+            (2, "sounds"),
+        ] {
+            let before = &original.project["targets"][index][kind][0];
+            let after = &recompiled.project["targets"][index][kind][0];
+            assert_eq!(before["name"], after["name"]);
+            assert_eq!(before["assetId"], after["assetId"]);
+        }
+
+        let hashed = dir.path().join("hashed");
+        decompile_sb3_with_progress(
+            &input,
+            Some(&hashed.join("game.sbtext")),
+            DecompileOptions {
+                keep_asset_hashes: true,
+                ..Default::default()
+            },
+            Option::<&mut fn(usize, usize, &str)>::None,
+        )
+        .expect("decompile");
+        let md5ext = original.project["targets"][1]["sounds"][0]["md5ext"]
+            .as_str()
+            .unwrap();
+        let text = fs::read_to_string(hashed.join("game.sbtext")).expect("sbtext");
+        assert!(
+            text.contains(&format!("  sound \"Big meow\" \"{}\"\n", md5ext)),
+            "{}",
+            text
+        );
+        assert!(hashed.join(md5ext).exists());
+    }
+
+    #[test]
+    fn start_costume_roundtrip() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10" viewBox="0 0 10 10"><rect width="10" height="10"/></svg>"#;
+        fs::write(dir.path().join("walk.svg"), svg).expect("svg should write");
+        fs::write(dir.path().join("idle.svg"), svg.replace("10\"/>", "5\"/>"))
+            .expect("svg should write");
+
+        let source = "sprite Hero\n  costume \"walk.svg\"\n  costume \"Idle Pose\" \"idle.svg\"\n  x 40\n  size 50\n  start costume [idle pose]\nend\n";
+        let archive = compile_archive(source, dir.path());
+        assert_eq!(archive.project["targets"][1]["currentCostume"], 1);
+
+        let text = decompile_project(&archive.project);
+        assert!(
+            text.contains("  x 40\n  size 50\n  start costume [\"Idle Pose\"]\n"),
+            "{}",
+            text
+        );
+        for (name, bytes) in &archive.assets {
+            fs::write(dir.path().join(name), bytes).expect("asset should write");
+        }
+        let recompiled = compile_archive(&text, dir.path());
+        assert_eq!(recompiled.project["targets"][1]["currentCostume"], 1);
+    }
+
+    #[test]
+    fn named_costume_roundtrip() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10" viewBox="0 0 10 10"><rect width="10" height="10"/></svg>"#;
+        fs::write(dir.path().join("hero_walk_01.svg"), svg).expect("svg should write");
+        fs::write(
+            dir.path().join("hero_idle.svg"),
+            svg.replace("10\"/>", "5\"/>"),
+        )
+        .expect("svg should write");
+
+        let source = "sprite Hero\n  costume \"Walking\" \"hero_walk_01.svg\"\n  costume \"hero_idle.svg\"\n  when flag clicked\n    switch costume to (\"Walking\")\n  end\nend\n";
+        let archive = compile_archive(source, dir.path());
+        let costumes = archive.project["targets"][1]["costumes"]
+            .as_array()
+            .unwrap();
+        assert_eq!(costumes[0]["name"], "Walking");
+        assert_eq!(costumes[1]["name"], "hero_idle");
+
+        let text = decompile_project(&archive.project);
+        let walking = costumes[0]["md5ext"].as_str().unwrap();
+        assert!(
+            text.contains(&format!("  costume \"Walking\" \"{}\"\n", walking)),
+            "{}",
+            text
+        );
+        assert!(text.contains("  costume \"hero_idle\" \""), "{}", text);
+
+        for (name, bytes) in &archive.assets {
+            fs::write(dir.path().join(name), bytes).expect("asset should write");
+        }
+        let recompiled = compile_archive(&text, dir.path());
+        let names = recompiled.project["targets"][1]["costumes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c["name"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Walking", "hero_idle"]);
+    }
+
+    fn png_header(width: u32, height: u32) -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[8, 6, 0, 0, 0, 0, 0, 0, 0]);
+        data
+    }
+
+    #[test]
+    fn png_costumes_are_centered() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        fs::write(dir.path().join("player.png"), png_header(48, 31)).expect("png should write");
+        let source = "sprite Player\n  costume \"player.png\"\nend\n";
+
+        let archive = compile_archive(source, dir.path());
+        let costume = &archive.project["targets"][1]["costumes"][0];
+        assert_eq!(costume["rotationCenterX"], 24.0);
+        assert_eq!(costume["rotationCenterY"], 15.5);
+        assert_eq!(costume["bitmapResolution"], 1);
+
+        let project = crate::parse_and_validate_source(source).expect("source should parse");
+        let bytes = crate::codegen::build_sb3_bytes(
+            &project,
+            dir.path(),
+            crate::codegen::CodegenOptions {
+                hd_bitmaps: true,
+                ..Default::default()
+            },
+        )
+        .expect("source should compile");
+        let archive = read_sb3_bytes(&bytes).expect("sb3 should read");
+        let costume = &archive.project["targets"][1]["costumes"][0];
+        assert_eq!(costume["rotationCenterX"], 24.0);
+        assert_eq!(costume["bitmapResolution"], 2);
+    }
+
+    #[test]
+    fn truncated_png_costume_is_an_error() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        fs::write(dir.path().join("broken.png"), &png_header(48, 31)[..20])
+            .expect("png should write");
+        let source = "sprite Player\n  costume \"broken.png\"\nend\n";
+
+        let err = crate::compile_source_to_sb3_bytes(source, dir.path(), false)
+            .expect_err("truncated PNG should fail");
+        let message = err.to_string();
+        assert!(message.starts_with("Invalid PNG file '"), "{}", message);
+        assert!(message.contains("broken.png"), "{}", message);
+    }
+
+    #[test]
+    fn variable_monitors_roundtrip() {
+        let source = "stage\n  var score = 3\n  var level\n  monitor [score] at (10) (-5) mode [large]\nend\n\nsprite Player\n  var health\n  monitor [health]\n  monitor [Level] at (5) (40) mode [slider]\nend\n";
+        let project = compile_project(source);
+        let monitors = project["monitors"].as_array().unwrap();
+        assert_eq!(monitors.len(), 3);
+        let stage_vars = project["targets"][0]["variables"].as_object().unwrap();
+        assert!(stage_vars.contains_key(monitors[0]["id"].as_str().unwrap()));
+        assert_eq!(monitors[0]["opcode"], "data_variable");
+        assert_eq!(monitors[0]["mode"], "large");
+        assert_eq!(monitors[0]["value"], 3.0);
+        assert_eq!(monitors[0]["spriteName"], Value::Null);
+        assert_eq!(monitors[0]["x"], 10.0);
+        assert_eq!(monitors[0]["y"], -5.0);
+        assert_eq!(monitors[1]["spriteName"], "Player");
+        assert_eq!(monitors[1]["params"]["VARIABLE"], "health");
+        assert!(stage_vars.contains_key(monitors[2]["id"].as_str().unwrap()));
+        assert_eq!(monitors[2]["params"]["VARIABLE"], "level");
+        assert_eq!(monitors[2]["spriteName"], Value::Null);
+
+        let text = roundtrip(source);
+        assert!(
+            text.contains("  monitor [score] at (10) (-5) mode [large]\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains("  monitor [health] at (0) (0)\n"), "{}", text);
+        assert!(
+            text.contains("  monitor [level] at (5) (40) mode [slider]\n"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn list_monitors_roundtrip() {
+        let source = "stage\n  list names = [ada, grace]\n  monitor [names] at (20) (30)\nend\n\nsprite Player\n  var items\n  list items\n  monitor [items]\nend\n";
+        let project = compile_project(source);
+        let monitors = project["monitors"].as_array().unwrap();
+        assert_eq!(monitors.len(), 2);
+        assert_eq!(monitors[0]["opcode"], "data_listcontents");
+        assert_eq!(monitors[0]["mode"], "list");
+        assert_eq!(monitors[0]["params"]["LIST"], "names");
+        assert_eq!(monitors[0]["value"], json!(["ada", "grace"]));
+        assert_eq!(monitors[0]["spriteName"], Value::Null);
+        let stage_lists = project["targets"][0]["lists"].as_object().unwrap();
+        assert!(stage_lists.contains_key(monitors[0]["id"].as_str().unwrap()));
+        assert_eq!(monitors[1]["opcode"], "data_variable");
+        assert_eq!(monitors[1]["spriteName"], "Player");
+
+        let text = roundtrip(source);
+        assert!(
+            text.contains("  monitor [names] at (20) (30)\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("  list names = [\"ada\", \"grace\"]\n"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn cloud_variables_roundtrip() {
+        let source = "stage\n  cloud var highscore = 5\n  var level\nend\n\nsprite Player\n  when flag clicked\n    set [highscore] to (1)\n    change [\"☁ highscore\"] by (2)\n  end\nend\n";
+        let project = compile_project(source);
+        let stage_vars = project["targets"][0]["variables"].as_object().unwrap();
+        let (id, cloud) = stage_vars
+            .iter()
+            .find(|(_, v)| v[0] == "☁ highscore")
+            .expect("cloud variable should be emitted");
+        assert_eq!(cloud, &json!(["☁ highscore", 5.0, true]));
+        assert!(stage_vars.values().any(|v| v == &json!(["level", 0])));
+
+        let blocks = project["targets"][1]["blocks"].as_object().unwrap();
+        let fields = blocks
+            .values()
+            .filter_map(|b| b["fields"].get("VARIABLE"))
+            .collect::<Vec<_>>();
+        assert_eq!(fields.len(), 2);
+        for field in fields {
+            assert_eq!(field, &json!(["☁ highscore", id]));
+        }
+
+        let text = roundtrip(source);
+        assert!(text.contains("  cloud var highscore = 5\n"), "{}", text);
+        assert!(text.contains("  var level\n"), "{}", text);
+    }
+
+    #[test]
+    fn stop_other_scripts_keeps_chaining() {
+        let source = "sprite Cat\n  when flag clicked\n    stop (\"other scripts in sprite\")\n    say (\"alone\")\n    stop (\"this script\")\n  end\nend\n";
+        let project = compile_project(source);
+        let blocks = project["targets"][1]["blocks"].as_object().unwrap();
+        let stops = blocks
+            .values()
+            .filter(|b| b["opcode"] == "control_stop")
+            .map(|b| {
+                (
+                    b["fields"]["STOP_OPTION"][0].as_str().unwrap(),
+                    b["mutation"]["hasnext"].as_str().unwrap(),
+                    b["next"].is_null(),
+                )
+            })
+            .collect::<HashSet<_>>();
+        assert_eq!(
+            stops,
+            HashSet::from([
+                ("other scripts in sprite", "true", false),
+                ("this script", "false", true),
+            ])
+        );
+
+        let text = roundtrip(source);
+        assert!(
+            text.contains("    stop (\"other scripts in sprite\")\n    say (\"alone\")\n"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn if_without_else_stays_control_if() {
+        let source = "sprite Cat\n  var n\n  when flag clicked\n    if <(n) > (1)> then\n      say (\"big\")\n    end\n    if <(n) < (1)> then\n      say (\"small\")\n    else\n      say (\"one\")\n    end\n  end\nend\n";
+        let ifs = |project: &Value| {
+            let mut ops = opcodes(project)
+                .into_iter()
+                .filter(|op| op.starts_with("control_if"))
+                .collect::<Vec<_>>();
+            ops.sort();
+            ops
+        };
+        let dir = tempfile::tempdir().expect("temp dir");
+        let archive = compile_archive(source, dir.path());
+        assert_eq!(ifs(&archive.project), ["control_if", "control_if_else"]);
+        for (name, bytes) in &archive.assets {
+            fs::write(dir.path().join(name), bytes).expect("asset should write");
+        }
+
+        let text = decompile_project(&archive.project);
+        assert!(text.contains("      say (\"big\")\n    end\n"), "{}", text);
+        let recompiled = compile_archive(&text, dir.path());
+        assert_eq!(ifs(&recompiled.project), ["control_if", "control_if_else"]);
+    }
+}
@@ -0,0 +1,1188 @@
+//! Scratch 2 (`.sb2`) front-end for the decompiler. The sb2 project shape (a stage
+//! object with `children`, scripts as nested arrays) is rewritten into an sb3
+//! `project.json` with a block map, so `decompile` renders both formats the same way.
+
+use crate::sb3::Sb3Archive;
+use anyhow::{anyhow, Result};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+
+/// Opcode given to sb2 blocks with no sb3 equivalent; the original sb2 opcode is
+/// kept in its `OPCODE` field so the decompiler can name it in a comment.
+pub(crate) const UNSUPPORTED_OPCODE: &str = "sb2_unsupported";
+
+/// True for an sb2 `project.json`: a stage object rather than a `targets` array.
+pub fn is_sb2_project(project: &Value) -> bool {
+    project.get("targets").is_none() && project.get("objName").is_some()
+}
+
+/// Converts an sb2 archive into the equivalent sb3 project. Costume and sound files,
+/// stored in sb2 as `<baseLayerID>.<ext>` / `<soundID>.<ext>`, are re-keyed by md5 name.
+pub fn sb2_to_sb3(archive: &Sb3Archive) -> Result<Sb3Archive> {
+    let stage = archive
+        .project
+        .as_object()
+        .ok_or_else(|| anyhow!("Invalid sb2 project.json: expected a stage object."))?;
+    let children = stage
+        .get("children")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let sprites = children
+        .iter()
+        .filter(|child| child.get("objName").is_some())
+        .collect::<Vec<_>>();
+
+    let mut converter = Converter::default();
+    converter.declare_data("Stage", &archive.project);
+    for sprite in &sprites {
+        converter.declare_data(object_name(sprite), sprite);
+    }
+
+    let mut assets = BTreeMap::new();
+    let mut targets =
+        vec![converter.convert_target(&archive.project, None, &archive.assets, &mut assets)?];
+    for (index, sprite) in sprites.iter().enumerate() {
+        targets.push(converter.convert_target(
+            sprite,
+            Some(index as i64 + 1),
+            &archive.assets,
+            &mut assets,
+        )?);
+    }
+    // Broadcasts are only known once every script has been converted.
+    targets[0]["broadcasts"] = converter
+        .broadcast_ids
+        .iter()
+        .map(|(name, id)| (id.clone(), json!(name)))
+        .collect::<Map<_, _>>()
+        .into();
+    let monitors = converter.convert_watchers(&archive.project, children, &sprites);
+
+    let project = json!({
+        "targets": targets,
+        "monitors": monitors,
+        "extensions": [],
+        "meta": {"semver": "3.0.0", "vm": "0.2.0", "agent": "sb2 import"}
+    });
+    Ok(Sb3Archive::new(project, assets))
+}
+
+fn object_name(object: &Value) -> &str {
+    object
+        .get("objName")
+        .and_then(Value::as_str)
+        .unwrap_or("Sprite")
+}
+
+/// How one sb2 block argument becomes an sb3 input or field.
+#[derive(Clone, Copy)]
+enum Arg {
+    /// Number input; non-numeric literals fall back to text.
+    Num(&'static str),
+    Text(&'static str),
+    Bool(&'static str),
+    /// Integer color literal, stored as `#rrggbb`.
+    Color(&'static str),
+    Stack(&'static str),
+    Field(&'static str),
+    /// Input backed by a menu shadow block: (input, menu opcode, menu field).
+    Menu(&'static str, &'static str, &'static str),
+    Variable,
+    List,
+    /// Field with a fixed value that consumes no sb2 argument.
+    Fixed(&'static str, &'static str),
+    /// Pen color parameter menu with a fixed value; consumes no sb2 argument.
+    PenParam(&'static str),
+    /// Number input multiplied by a factor, for sb2 values on another scale.
+    Scaled(&'static str, f64),
+}
+
+use Arg::*;
+
+/// sb2 opcode, sb3 opcode, and the argument mapping in sb2 argument order.
+const SPECS: &[(&str, &str, &[Arg])] = &[
+    // Events
+    ("whenGreenFlag", "event_whenflagclicked", &[]),
+    (
+        "whenKeyPressed",
+        "event_whenkeypressed",
+        &[Field("KEY_OPTION")],
+    ),
+    ("whenClicked", "event_whenthisspriteclicked", &[]),
+    (
+        "whenSceneStarts",
+        "event_whenbackdropswitchesto",
+        &[Field("BACKDROP")],
+    ),
+    (
+        "whenIReceive",
+        "event_whenbroadcastreceived",
+        &[Field("BROADCAST_OPTION")],
+    ),
+    (
+        "whenSensorGreaterThan",
+        "event_whengreaterthan",
+        &[Field("WHENGREATERTHANMENU"), Num("VALUE")],
+    ),
+    ("whenCloned", "control_start_as_clone", &[]),
+    (
+        "broadcast:",
+        "event_broadcast",
+        &[Menu(
+            "BROADCAST_INPUT",
+            "event_broadcast_menu",
+            "BROADCAST_OPTION",
+        )],
+    ),
+    (
+        "doBroadcastAndWait",
+        "event_broadcastandwait",
+        &[Menu(
+            "BROADCAST_INPUT",
+            "event_broadcast_menu",
+            "BROADCAST_OPTION",
+        )],
+    ),
+    // Motion
+    ("forward:", "motion_movesteps", &[Num("STEPS")]),
+    ("turnRight:", "motion_turnright", &[Num("DEGREES")]),
+    ("turnLeft:", "motion_turnleft", &[Num("DEGREES")]),
+    ("heading:", "motion_pointindirection", &[Num("DIRECTION")]),
+    (
+        "pointTowards:",
+        "motion_pointtowards",
+        &[Menu("TOWARDS", "motion_pointtowards_menu", "TOWARDS")],
+    ),
+    ("gotoX:y:", "motion_gotoxy", &[Num("X"), Num("Y")]),
+    (
+        "gotoSpriteOrMouse:",
+        "motion_goto",
+        &[Menu("TO", "motion_goto_menu", "TO")],
+    ),
+    (
+        "glideSecs:toX:y:elapsed:from:",
+        "motion_glidesecstoxy",
+        &[Num("SECS"), Num("X"), Num("Y")],
+    ),
+    ("changeXposBy:", "motion_changexby", &[Num("DX")]),
+    ("xpos:", "motion_setx", &[Num("X")]),
+    ("changeYposBy:", "motion_changeyby", &[Num("DY")]),
+    ("ypos:", "motion_sety", &[Num("Y")]),
+    ("bounceOffEdge", "motion_ifonedgebounce", &[]),
+    (
+        "setRotationStyle",
+        "motion_setrotationstyle",
+        &[Field("STYLE")],
+    ),
+    ("xpos", "motion_xposition", &[]),
+    ("ypos", "motion_yposition", &[]),
+    ("heading", "motion_direction", &[]),
+    // Looks
+    (
+        "say:duration:elapsed:from:",
+        "looks_sayforsecs",
+        &[Text("MESSAGE"), Num("SECS")],
+    ),
+    ("say:", "looks_say", &[Text("MESSAGE")]),
+    (
+        "think:duration:elapsed:from:",
+        "looks_thinkforsecs",
+        &[Text("MESSAGE"), Num("SECS")],
+    ),
+    ("think:", "looks_think", &[Text("MESSAGE")]),
+    ("show", "looks_show", &[]),
+    ("hide", "looks_hide", &[]),
+    (
+        "lookLike:",
+        "looks_switchcostumeto",
+        &[Menu("COSTUME", "looks_costume", "COSTUME")],
+    ),
+    ("nextCostume", "looks_nextcostume", &[]),
+    (
+        "startScene",
+        "looks_switchbackdropto",
+        &[Menu("BACKDROP", "looks_backdrops", "BACKDROP")],
+    ),
+    (
+        "startSceneAndWait",
+        "looks_switchbackdroptoandwait",
+        &[Menu("BACKDROP", "looks_backdrops", "BACKDROP")],
+    ),
+    ("nextScene", "looks_nextbackdrop", &[]),
+    (
+        "changeGraphicEffect:by:",
+        "looks_changeeffectby",
+        &[Field("EFFECT"), Num("CHANGE")],
+    ),
+    (
+        "setGraphicEffect:to:",
+        "looks_seteffectto",
+        &[Field("EFFECT"), Num("VALUE")],
+    ),
+    ("filterReset", "looks_cleargraphiceffects", &[]),
+    ("changeSizeBy:", "looks_changesizeby", &[Num("CHANGE")]),
+    ("setSizeTo:", "looks_setsizeto", &[Num("SIZE")]),
+    (
+        "comeToFront",
+        "looks_gotofrontback",
+        &[Fixed("FRONT_BACK", "front")],
+    ),
+    (
+        "goBackByLayers:",
+        "looks_goforwardbackwardlayers",
+        &[Num("NUM"), Fixed("FORWARD_BACKWARD", "backward")],
+    ),
+    (
+        "costumeIndex",
+        "looks_costumenumbername",
+        &[Fixed("NUMBER_NAME", "number")],
+    ),
+    (
+        "sceneName",
+        "looks_backdropnumbername",
+        &[Fixed("NUMBER_NAME", "name")],
+    ),
+    (
+        "backgroundIndex",
+        "looks_backdropnumbername",
+        &[Fixed("NUMBER_NAME", "number")],
+    ),
+    ("scale", "looks_size", &[]),
+    // Sound and music
+    (
+        "playSound:",
+        "sound_play",
+        &[Menu("SOUND_MENU", "sound_sounds_menu", "SOUND_MENU")],
+    ),
+    (
+        "doPlaySoundAndWait",
+        "sound_playuntildone",
+        &[Menu("SOUND_MENU", "sound_sounds_menu", "SOUND_MENU")],
+    ),
+    ("stopAllSounds", "sound_stopallsounds", &[]),
+    ("changeVolumeBy:", "sound_changevolumeby", &[Num("VOLUME")]),
+    ("setVolumeTo:", "sound_setvolumeto", &[Num("VOLUME")]),
+    ("volume", "sound_volume", &[]),
+    (
+        "playDrum",
+        "music_playDrumForBeats",
+        &[Menu("DRUM", "music_menu_DRUM", "DRUM"), Num("BEATS")],
+    ),
+    ("rest:elapsed:from:", "music_restForBeats", &[Num("BEATS")]),
+    (
+        "noteOn:duration:elapsed:from:",
+        "music_playNoteForBeats",
+        &[Num("NOTE"), Num("BEATS")],
+    ),
+    (
+        "instrument:",
+        "music_setInstrument",
+        &[Menu("INSTRUMENT", "music_menu_INSTRUMENT", "INSTRUMENT")],
+    ),
+    ("changeTempoBy:", "music_changeTempo", &[Num("TEMPO")]),
+    ("setTempoTo:", "music_setTempo", &[Num("TEMPO")]),
+    ("tempo", "music_getTempo", &[]),
+    // Pen
+    ("clearPenTrails", "pen_clear", &[]),
+    ("stampCostume", "pen_stamp", &[]),
+    ("putPenDown", "pen_penDown", &[]),
+    ("putPenUp", "pen_penUp", &[]),
+    ("penColor:", "pen_setPenColorToColor", &[Color("COLOR")]),
+    // sb2 hue runs from 0 to 200 where sb3 color runs from 0 to 100. Shade 50
+    // is the plain color and lower shades darken it, like brightness at half
+    // the scale; shades above 50, which lighten, have no exact match.
+    (
+        "changePenHueBy:",
+        "pen_changePenColorParamBy",
+        &[PenParam("color"), Scaled("VALUE", 0.5)],
+    ),
+    (
+        "setPenHueTo:",
+        "pen_setPenColorParamTo",
+        &[PenParam("color"), Scaled("VALUE", 0.5)],
+    ),
+    (
+        "changePenShadeBy:",
+        "pen_changePenColorParamBy",
+        &[PenParam("brightness"), Scaled("VALUE", 2.0)],
+    ),
+    (
+        "setPenShadeTo:",
+        "pen_setPenColorParamTo",
+        &[PenParam("brightness"), Scaled("VALUE", 2.0)],
+    ),
+    ("changePenSizeBy:", "pen_changePenSizeBy", &[Num("SIZE")]),
+    ("penSize:", "pen_setPenSizeTo", &[Num("SIZE")]),
+    // Control
+    ("wait:elapsed:from:", "control_wait", &[Num("DURATION")]),
+    (
+        "doRepeat",
+        "control_repeat",
+        &[Num("TIMES"), Stack("SUBSTACK")],
+    ),
+    ("doForever", "control_forever", &[Stack("SUBSTACK")]),
+    (
+        "doIf",
+        "control_if",
+        &[Bool("CONDITION"), Stack("SUBSTACK")],
+    ),
+    (
+        "doIfElse",
+        "control_if_else",
+        &[Bool("CONDITION"), Stack("SUBSTACK"), Stack("SUBSTACK2")],
+    ),
+    ("doWaitUntil", "control_wait_until", &[Bool("CONDITION")]),
+    (
+        "doUntil",
+        "control_repeat_until",
+        &[Bool("CONDITION"), Stack("SUBSTACK")],
+    ),
+    (
+        "doWhile",
+        "control_while",
+        &[Bool("CONDITION"), Stack("SUBSTACK")],
+    ),
+    (
+        "doForLoop",
+        "control_for_each",
+        &[Variable, Num("VALUE"), Stack("SUBSTACK")],
+    ),
+    ("stopScripts", "control_stop", &[Field("STOP_OPTION")]),
+    (
+        "createCloneOf",
+        "control_create_clone_of",
+        &[Menu(
+            "CLONE_OPTION",
+            "control_create_clone_of_menu",
+            "CLONE_OPTION",
+        )],
+    ),
+    ("deleteClone", "control_delete_this_clone", &[]),
+    // Sensing
+    (
+        "touching:",
+        "sensing_touchingobject",
+        &[Menu(
+            "TOUCHINGOBJECTMENU",
+            "sensing_touchingobjectmenu",
+            "TOUCHINGOBJECTMENU",
+        )],
+    ),
+    ("touchingColor:", "sensing_touchingcolor", &[Color("COLOR")]),
+    (
+        "color:sees:",
+        "sensing_coloristouchingcolor",
+        &[Color("COLOR"), Color("COLOR2")],
+    ),
+    (
+        "distanceTo:",
+        "sensing_distanceto",
+        &[Menu(
+            "DISTANCETOMENU",
+            "sensing_distancetomenu",
+            "DISTANCETOMENU",
+        )],
+    ),
+    ("doAsk", "sensing_askandwait", &[Text("QUESTION")]),
+    ("answer", "sensing_answer", &[]),
+    (
+        "keyPressed:",
+        "sensing_keypressed",
+        &[Menu("KEY_OPTION", "sensing_keyoptions", "KEY_OPTION")],
+    ),
+    ("mousePressed", "sensing_mousedown", &[]),
+    ("mouseX", "sensing_mousex", &[]),
+    ("mouseY", "sensing_mousey", &[]),
+    ("soundLevel", "sensing_loudness", &[]),
+    ("timer", "sensing_timer", &[]),
+    ("timerReset", "sensing_resettimer", &[]),
+    (
+        "getAttribute:of:",
+        "sensing_of",
+        &[
+            Field("PROPERTY"),
+            Menu("OBJECT", "sensing_of_object_menu", "OBJECT"),
+        ],
+    ),
+    ("timeAndDate", "sensing_current", &[Field("CURRENTMENU")]),
+    ("timestamp", "sensing_dayssince2000", &[]),
+    ("getUserName", "sensing_username", &[]),
+    // Operators
+    ("+", "operator_add", &[Num("NUM1"), Num("NUM2")]),
+    ("-", "operator_subtract", &[Num("NUM1"), Num("NUM2")]),
+    ("*", "operator_multiply", &[Num("NUM1"), Num("NUM2")]),
+    ("/", "operator_divide", &[Num("NUM1"), Num("NUM2")]),
+    ("%", "operator_mod", &[Num("NUM1"), Num("NUM2")]),
+    (
+        "randomFrom:to:",
+        "operator_random",
+        &[Num("FROM"), Num("TO")],
+    ),
+    ("<", "operator_lt", &[Text("OPERAND1"), Text("OPERAND2")]),
+    (
+        "=",
+        "operator_equals",
+        &[Text("OPERAND1"), Text("OPERAND2")],
+    ),
+    (">", "operator_gt", &[Text("OPERAND1"), Text("OPERAND2")]),
+    ("&", "operator_and", &[Bool("OPERAND1"), Bool("OPERAND2")]),
+    ("|", "operator_or", &[Bool("OPERAND1"), Bool("OPERAND2")]),
+    ("not", "operator_not", &[Bool("OPERAND")]),
+    (
+        "concatenate:with:",
+        "operator_join",
+        &[Text("STRING1"), Text("STRING2")],
+    ),
+    (
+        "letter:of:",
+        "operator_letter_of",
+        &[Num("LETTER"), Text("STRING")],
+    ),
+    ("stringLength:", "operator_length", &[Text("STRING")]),
+    ("rounded", "operator_round", &[Num("NUM")]),
+    (
+        "computeFunction:of:",
+        "operator_mathop",
+        &[Field("OPERATOR"), Num("NUM")],
+    ),
+    // Data
+    (
+        "setVar:to:",
+        "data_setvariableto",
+        &[Variable, Text("VALUE")],
+    ),
+    (
+        "changeVar:by:",
+        "data_changevariableby",
+        &[Variable, Num("VALUE")],
+    ),
+    ("showVariable:", "data_showvariable", &[Variable]),
+    ("hideVariable:", "data_hidevariable", &[Variable]),
+    ("append:toList:", "data_addtolist", &[Text("ITEM"), List]),
+    (
+        "deleteLine:ofList:",
+        "data_deleteoflist",
+        &[Num("INDEX"), List],
+    ),
+    (
+        "insert:at:ofList:",
+        "data_insertatlist",
+        &[Text("ITEM"), Num("INDEX"), List],
+    ),
+    (
+        "setLine:ofList:to:",
+        "data_replaceitemoflist",
+        &[Num("INDEX"), List, Text("ITEM")],
+    ),
+    ("getLine:ofList:", "data_itemoflist", &[Num("INDEX"), List]),
+    ("lineCountOfList:", "data_lengthoflist", &[List]),
+    (
+        "list:contains:",
+        "data_listcontainsitem",
+        &[List, Text("ITEM")],
+    ),
+    ("showList:", "data_showlist", &[List]),
+    ("hideList:", "data_hidelist", &[List]),
+];
+
+#[derive(Default)]
+struct Converter {
+    next_id: usize,
+    /// (target name, variable name) to id; stage variables are under `Stage`.
+    variable_ids: HashMap<(String, String), String>,
+    list_ids: HashMap<(String, String), String>,
+    broadcast_ids: BTreeMap<String, String>,
+}
+
+/// Per-target state while converting scripts.
+struct TargetBlocks<'a> {
+    name: &'a str,
+    is_stage: bool,
+    blocks: Map<String, Value>,
+}
+
+impl Converter {
+    fn new_id(&mut self, kind: &str) -> String {
+        self.next_id += 1;
+        format!("sb2-{}-{}", kind, self.next_id)
+    }
+
+    fn declare_data(&mut self, target: &str, object: &Value) {
+        for variable in array(object, "variables") {
+            if let Some(name) = variable.get("name").and_then(Value::as_str) {
+                let id = self.new_id("var");
+                self.variable_ids
+                    .insert((target.to_string(), name.to_string()), id);
+            }
+        }
+        for list in array(object, "lists") {
+            if let Some(name) = list.get("listName").and_then(Value::as_str) {
+                let id = self.new_id("list");
+                self.list_ids
+                    .insert((target.to_string(), name.to_string()), id);
+            }
+        }
+    }
+
+    fn variable_id(&self, target: &str, name: &str) -> String {
+        self.variable_ids
+            .get(&(target.to_string(), name.to_string()))
+            .or_else(|| {
+                self.variable_ids
+                    .get(&("Stage".to_string(), name.to_string()))
+            })
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    fn list_id(&self, target: &str, name: &str) -> String {
+        self.list_ids
+            .get(&(target.to_string(), name.to_string()))
+            .or_else(|| self.list_ids.get(&("Stage".to_string(), name.to_string())))
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    fn broadcast_id(&mut self, name: &str) -> String {
+        if let Some(id) = self.broadcast_ids.get(name) {
+            return id.clone();
+        }
+        let id = self.new_id("broadcast");
+        self.broadcast_ids.insert(name.to_string(), id.clone());
+        id
+    }
+
+    /// Builds the sb3 target for the stage (`layer` is `None`) or a sprite.
+    fn convert_target(
+        &mut self,
+        object: &Value,
+        layer: Option<i64>,
+        files: &BTreeMap<String, Vec<u8>>,
+        assets: &mut BTreeMap<String, Vec<u8>>,
+    ) -> Result<Value> {
+        let is_stage = layer.is_none();
+        let name = if is_stage {
+            "Stage"
+        } else {
+            object_name(object)
+        };
+
+        let mut variables = Map::new();
+        for variable in array(object, "variables") {
+            let Some(var_name) = variable.get("name").and_then(Value::as_str) else {
+                continue;
+            };
+            let value = variable.get("value").cloned().unwrap_or(json!(0));
+            let mut decl = vec![json!(var_name), value];
+            if variable.get("isPersistent").and_then(Value::as_bool) == Some(true) {
+                decl.push(json!(true));
+            }
+            variables.insert(self.variable_id(name, var_name), Value::Array(decl));
+        }
+        let mut lists = Map::new();
+        for list in array(object, "lists") {
+            let Some(list_name) = list.get("listName").and_then(Value::as_str) else {
+                continue;
+            };
+            let items = list.get("contents").cloned().unwrap_or(json!([]));
+            lists.insert(self.list_id(name, list_name), json!([list_name, items]));
+        }
+
+        let mut costumes = Vec::new();
+        for costume in array(object, "costumes") {
+            let md5ext = costume
+                .get("baseLayerMD5")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            let layer_id = costume.get("baseLayerID").and_then(Value::as_i64);
+            costumes.push(self.convert_asset(
+                costume.get("costumeName"),
+                md5ext,
+                layer_id,
+                files,
+                assets,
+                json!({
+                    "bitmapResolution": costume.get("bitmapResolution").cloned().unwrap_or(json!(1)),
+                    "rotationCenterX": costume.get("rotationCenterX").cloned().unwrap_or(json!(0)),
+                    "rotationCenterY": costume.get("rotationCenterY").cloned().unwrap_or(json!(0)),
+                }),
+            ));
+        }
+        let mut sounds = Vec::new();
+        for sound in array(object, "sounds") {
+            let md5ext = sound.get("md5").and_then(Value::as_str).unwrap_or_default();
+            let sound_id = sound.get("soundID").and_then(Value::as_i64);
+            sounds.push(self.convert_asset(
+                sound.get("soundName"),
+                md5ext,
+                sound_id,
+                files,
+                assets,
+                json!({
+                    "rate": sound.get("rate").cloned().unwrap_or(json!(22050)),
+                    "sampleCount": sound.get("sampleCount").cloned().unwrap_or(json!(0)),
+                }),
+            ));
+        }
+
+        let mut target = TargetBlocks {
+            name,
+            is_stage,
+            blocks: Map::new(),
+        };
+        for script in array(object, "scripts") {
+            let Some(parts) = script.as_array() else {
+                continue;
+            };
+            let (Some(x), Some(y), Some(stack)) = (
+                parts.first().and_then(Value::as_f64),
+                parts.get(1).and_then(Value::as_f64),
+                parts.get(2).and_then(Value::as_array),
+            ) else {
+                continue;
+            };
+            if let Some(first) = self.convert_stack(&mut target, stack, None) {
+                if let Some(block) = target.blocks.get_mut(&first) {
+                    block["topLevel"] = json!(true);
+                    block["x"] = json!(x.round() as i64);
+                    block["y"] = json!(y.round() as i64);
+                }
+            }
+        }
+
+        let mut out = json!({
+            "isStage": is_stage,
+            "name": name,
+            "variables": variables,
+            "lists": lists,
+            "broadcasts": {},
+            "blocks": target.blocks,
+            "comments": {},
+            "currentCostume": object.get("currentCostumeIndex").cloned().unwrap_or(json!(0)),
+            "costumes": costumes,
+            "sounds": sounds,
+            "volume": object.get("volume").cloned().unwrap_or(json!(100)),
+            "layerOrder": layer.unwrap_or(0),
+        });
+        if is_stage {
+            out["tempo"] = object.get("tempoBPM").cloned().unwrap_or(json!(60));
+            if let Some(alpha) = object.get("videoAlpha").and_then(Value::as_f64) {
+                out["videoTransparency"] = json!(((1.0 - alpha) * 100.0).round());
+            }
+        } else {
+            let rotation_style = match object.get("rotationStyle").and_then(Value::as_str) {
+                Some("leftRight") => "left-right",
+                Some("none") => "don't rotate",
+                _ => "all around",
+            };
+            let scale = object.get("scale").and_then(Value::as_f64).unwrap_or(1.0);
+            out["x"] = object.get("scratchX").cloned().unwrap_or(json!(0));
+            out["y"] = object.get("scratchY").cloned().unwrap_or(json!(0));
+            out["size"] = json!(scale * 100.0);
+            out["direction"] = object.get("direction").cloned().unwrap_or(json!(90));
+            out["visible"] = object.get("visible").cloned().unwrap_or(json!(true));
+            out["draggable"] = object.get("isDraggable").cloned().unwrap_or(json!(false));
+            out["rotationStyle"] = json!(rotation_style);
+        }
+        Ok(out)
+    }
+
+    fn convert_asset(
+        &mut self,
+        name: Option<&Value>,
+        md5ext: &str,
+        file_id: Option<i64>,
+        files: &BTreeMap<String, Vec<u8>>,
+        assets: &mut BTreeMap<String, Vec<u8>>,
+        extra: Value,
+    ) -> Value {
+        let (asset_id, ext) = md5ext.rsplit_once('.').unwrap_or((md5ext, ""));
+        let bytes = file_id.and_then(|id| files.get(&format!("{}.{}", id, ext)));
+        if let Some(bytes) = bytes {
+            assets.insert(md5ext.to_string(), bytes.clone());
+        }
+        let mut asset = json!({
+            "name": name.cloned().unwrap_or(json!("")),
+            "assetId": asset_id,
+            "md5ext": md5ext,
+            "dataFormat": ext,
+        });
+        if let (Some(asset), Some(extra)) = (asset.as_object_mut(), extra.as_object()) {
+            asset.extend(extra.clone());
+        }
+        asset
+    }
+
+    /// Converts a list of sb2 blocks into a linked chain and returns its first id.
+    fn convert_stack(
+        &mut self,
+        target: &mut TargetBlocks<'_>,
+        stack: &[Value],
+        parent: Option<&str>,
+    ) -> Option<String> {
+        let mut first = None;
+        let mut previous: Option<String> = None;
+        for block in stack {
+            let Some(parts) = block.as_array() else {
+                continue;
+            };
+            let parent = previous.as_deref().or(parent);
+            let id = self.convert_block(target, parts, parent);
+            if let Some(previous) = &previous {
+                target.blocks[previous]["next"] = json!(id);
+            }
+            first.get_or_insert_with(|| id.clone());
+            previous = Some(id);
+        }
+        first
+    }
+
+    fn convert_block(
+        &mut self,
+        target: &mut TargetBlocks<'_>,
+        parts: &[Value],
+        parent: Option<&str>,
+    ) -> String {
+        let id = self.new_id("block");
+        let opcode = parts.first().and_then(Value::as_str).unwrap_or_default();
+        let args = parts.get(1..).unwrap_or_default();
+        let mut block = json!({
+            "opcode": opcode,
+            "next": null,
+            "parent": parent,
+            "inputs": {},
+            "fields": {},
+            "shadow": false,
+            "topLevel": false,
+        });
+        // Inserted before the inputs are built so nested blocks can link back to it.
+        target.blocks.insert(id.clone(), block.clone());
+
+        match opcode {
+            "procDef" => self.convert_procedure_definition(target, &id, args, &mut block),
+            "call" => self.convert_procedure_call(target, &id, args, &mut block),
+            "getParam" => {
+                let name = args.first().and_then(Value::as_str).unwrap_or_default();
+                block["opcode"] = json!(if args.get(1).and_then(Value::as_str) == Some("b") {
+                    "argument_reporter_boolean"
+                } else {
+                    "argument_reporter_string_number"
+                });
+                block["fields"]["VALUE"] = json!([name, null]);
+            }
+            "readVariable" => {
+                let name = args.first().and_then(Value::as_str).unwrap_or_default();
+                block["opcode"] = json!("data_variable");
+                block["fields"]["VARIABLE"] = json!([name, self.variable_id(target.name, name)]);
+            }
+            "contentsOfList:" => {
+                let name = args.first().and_then(Value::as_str).unwrap_or_default();
+                block["opcode"] = json!("data_listcontents");
+                block["fields"]["LIST"] = json!([name, self.list_id(target.name, name)]);
+            }
+            // sb2 uses one hat for both; sb3 gives the stage its own.
+            "whenClicked" if target.is_stage => {
+                block["opcode"] = json!("event_whenstageclicked");
+            }
+            "deleteLine:ofList:" if args.first().and_then(Value::as_str) == Some("all") => {
+                let name = args.get(1).and_then(Value::as_str).unwrap_or_default();
+                block["opcode"] = json!("data_deletealloflist");
+                block["fields"]["LIST"] = json!([name, self.list_id(target.name, name)]);
+            }
+            _ => match SPECS.iter().find(|(sb2, _, _)| *sb2 == opcode) {
+                Some((_, sb3, spec)) => {
+                    block["opcode"] = json!(sb3);
+                    self.convert_args(target, &id, spec, args, &mut block);
+                }
+                None => {
+                    block["opcode"] = json!(UNSUPPORTED_OPCODE);
+                    block["fields"]["OPCODE"] = json!([opcode, null]);
+                }
+            },
+        }
+        target.blocks.insert(id.clone(), block);
+        id
+    }
+
+    fn convert_args(
+        &mut self,
+        target: &mut TargetBlocks<'_>,
+        id: &str,
+        spec: &[Arg],
+        args: &[Value],
+        block: &mut Value,
+    ) {
+        let mut args = args.iter();
+        for arg in spec {
+            if let Fixed(field, value) = arg {
+                block["fields"][*field] = json!([value, null]);
+                continue;
+            }
+            if let PenParam(param) = arg {
+                let menu_id =
+                    self.menu_shadow(target, id, "pen_menu_colorParam", "colorParam", param, None);
+                block["inputs"]["COLOR_PARAM"] = json!([1, menu_id]);
+                continue;
+            }
+            let value = args.next().unwrap_or(&Value::Null);
+            match *arg {
+                Num(input) | Text(input) | Color(input) => {
+                    let slot = self.value_input(target, id, *arg, value);
+                    block["inputs"][input] = slot;
+                }
+                Bool(input) => {
+                    if let Some(parts) = value.as_array() {
+                        let reporter = self.convert_block(target, parts, Some(id));
+                        block["inputs"][input] = json!([2, reporter]);
+                    }
+                }
+                Stack(input) => {
+                    let stack = value.as_array().map(Vec::as_slice).unwrap_or_default();
+                    if let Some(first) = self.convert_stack(target, stack, Some(id)) {
+                        block["inputs"][input] = json!([2, first]);
+                    }
+                }
+                Field(field) => {
+                    let text = field_value(block["opcode"].as_str(), scalar_text(value));
+                    let field_id = if field == "BROADCAST_OPTION" {
+                        json!(self.broadcast_id(&text))
+                    } else {
+                        Value::Null
+                    };
+                    block["fields"][field] = json!([text, field_id]);
+                }
+                Scaled(input, factor) => {
+                    let number = is_number(value)
+                        .then(|| scalar_text(value).trim().parse::<f64>().ok())
+                        .flatten();
+                    block["inputs"][input] = match number {
+                        Some(number) => json!([1, [4, scalar_text(&json!(number * factor))]]),
+                        None => self.scaled_input(target, id, factor, value),
+                    };
+                }
+                Menu(input, menu_opcode, field) => {
+                    let text = if value.is_array() {
+                        String::new()
+                    } else {
+                        scalar_text(value)
+                    };
+                    let field_id = if field == "BROADCAST_OPTION" && !text.is_empty() {
+                        Some(self.broadcast_id(&text))
+                    } else {
+                        None
+                    };
+                    let menu_id = self.menu_shadow(target, id, menu_opcode, field, &text, field_id);
+                    block["inputs"][input] = match value.as_array() {
+                        Some(parts) => {
+                            json!([3, self.convert_block(target, parts, Some(id)), menu_id])
+                        }
+                        None => json!([1, menu_id]),
+                    };
+                }
+                Variable => {
+                    let name = scalar_text(value);
+                    block["fields"]["VARIABLE"] =
+                        json!([name, self.variable_id(target.name, &name)]);
+                }
+                List => {
+                    let name = scalar_text(value);
+                    block["fields"]["LIST"] = json!([name, self.list_id(target.name, &name)]);
+                }
+                Fixed(..) | PenParam(_) => {}
+            }
+        }
+    }
+
+    /// Adds the shadow block behind a menu input and returns its id.
+    fn menu_shadow(
+        &mut self,
+        target: &mut TargetBlocks<'_>,
+        parent: &str,
+        menu_opcode: &str,
+        field: &str,
+        text: &str,
+        field_id: Option<String>,
+    ) -> String {
+        let menu_id = self.new_id("block");
+        target.blocks.insert(
+            menu_id.clone(),
+            json!({
+                "opcode": menu_opcode,
+                "next": null,
+                "parent": parent,
+                "inputs": {},
+                "fields": {field: [text, field_id]},
+                "shadow": true,
+                "topLevel": false,
+            }),
+        );
+        menu_id
+    }
+
+    /// A reporter (or text) argument times `factor`, as an `operator_multiply`
+    /// block in the input.
+    fn scaled_input(
+        &mut self,
+        target: &mut TargetBlocks<'_>,
+        parent: &str,
+        factor: f64,
+        value: &Value,
+    ) -> Value {
+        let multiply_id = self.new_id("block");
+        let mut multiply = json!({
+            "opcode": "operator_multiply",
+            "next": null,
+            "parent": parent,
+            "inputs": {"NUM2": [1, [4, scalar_text(&json!(factor))]]},
+            "fields": {},
+            "shadow": false,
+            "topLevel": false,
+        });
+        target.blocks.insert(multiply_id.clone(), multiply.clone());
+        multiply["inputs"]["NUM1"] = self.value_input(target, &multiply_id, Num("NUM1"), value);
+        target.blocks.insert(multiply_id.clone(), multiply);
+        json!([3, multiply_id, [4, ""]])
+    }
+
+    /// Encodes a literal or reporter argument as an sb3 input slot.
+    fn value_input(
+        &mut self,
+        target: &mut TargetBlocks<'_>,
+        id: &str,
+        arg: Arg,
+        value: &Value,
+    ) -> Value {
+        let shadow = match arg {
+            Num(_) => json!([4, ""]),
+            Color(_) => json!([9, "#000000"]),
+            _ => json!([10, ""]),
+        };
+        let Some(parts) = value.as_array() else {
+            let literal = match arg {
+                Color(_) => json!([9, color_text(value)]),
+                Num(_) if is_number(value) => json!([4, scalar_text(value)]),
+                Text(_) if value.is_number() => json!([4, scalar_text(value)]),
+                _ => json!([10, scalar_text(value)]),
+            };
+            return json!([1, literal]);
+        };
+        let name = parts.get(1).and_then(Value::as_str).unwrap_or_default();
+        match parts.first().and_then(Value::as_str) {
+            Some("readVariable") => {
+                json!([3, [12, name, self.variable_id(target.name, name)], shadow])
+            }
+            Some("contentsOfList:") => {
+                json!([3, [13, name, self.list_id(target.name, name)], shadow])
+            }
+            _ => json!([3, self.convert_block(target, parts, Some(id)), shadow]),
+        }
+    }
+
+    /// `["procDef", spec, names, defaults, warp]` becomes a definition plus prototype.
+    fn convert_procedure_definition(
+        &mut self,
+        target: &mut TargetBlocks<'_>,
+        id: &str,
+        args: &[Value],
+        block: &mut Value,
+    ) {
+        let spec = args.first().and_then(Value::as_str).unwrap_or_default();
+        let names = args.get(1).cloned().unwrap_or(json!([]));
+        let defaults = args.get(2).cloned().unwrap_or(json!([]));
+        let warp = args.get(3).and_then(Value::as_bool).unwrap_or(false);
+        let argument_ids = (0..spec_arguments(spec).len())
+            .map(|index| format!("arg{}", index))
+            .collect::<Vec<_>>();
+        let prototype_id = self.new_id("block");
+        target.blocks.insert(
+            prototype_id.clone(),
+            json!({
+                "opcode": "procedures_prototype",
+                "next": null,
+                "parent": id,
+                "inputs": {},
+                "fields": {},
+                "shadow": true,
+                "topLevel": false,
+                "mutation": {
+                    "tagName": "mutation",
+                    "children": [],
+                    "proccode": sb3_proccode(spec),
+                    "argumentids": json!(argument_ids).to_string(),
+                    "argumentnames": names.to_string(),
+                    "argumentdefaults": defaults.to_string(),
+                    "warp": warp.to_string(),
+                },
+            }),
+        );
+        block["opcode"] = json!("procedures_definition");
+        block["inputs"]["custom_block"] = json!([1, prototype_id]);
+    }
+
+    /// `["call", spec, args...]` becomes a `procedures_call` with one input per argument.
+    fn convert_procedure_call(
+        &mut self,
+        target: &mut TargetBlocks<'_>,
+        id: &str,
+        args: &[Value],
+        block: &mut Value,
+    ) {
+        let spec = args.first().and_then(Value::as_str).unwrap_or_default();
+        let kinds = spec_arguments(spec);
+        let mut argument_ids = Vec::new();
+        for (index, kind) in kinds.iter().enumerate() {
+            let input = format!("arg{}", index);
+            let value = args.get(index + 1).unwrap_or(&Value::Null);
+            let slot = if *kind == 'b' {
+                match value.as_array() {
+                    Some(parts) => json!([2, self.convert_block(target, parts, Some(id))]),
+                    None => Value::Null,
+                }
+            } else {
+                let arg = if *kind == 'n' { Num("") } else { Text("") };
+                self.value_input(target, id, arg, value)
+            };
+            if !slot.is_null() {
+                block["inputs"][&input] = slot;
+            }
+            argument_ids.push(input);
+        }
+        block["opcode"] = json!("procedures_call");
+        block["mutation"] = json!({
+            "tagName": "mutation",
+            "children": [],
+            "proccode": sb3_proccode(spec),
+            "argumentids": json!(argument_ids).to_string(),
+            "warp": "false",
+        });
+    }
+
+    /// Visible variable watchers and list displays become sb3 monitors.
+    fn convert_watchers(
+        &self,
+        stage: &Value,
+        children: &[Value],
+        sprites: &[&Value],
+    ) -> Vec<Value> {
+        let mut monitors = Vec::new();
+        for watcher in children {
+            if watcher.get("cmd").and_then(Value::as_str) != Some("getVar:")
+                || watcher.get("visible").and_then(Value::as_bool) != Some(true)
+            {
+                continue;
+            }
+            let owner = watcher
+                .get("target")
+                .and_then(Value::as_str)
+                .unwrap_or("Stage");
+            let name = watcher
+                .get("param")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            let mode = match watcher.get("mode").and_then(Value::as_i64) {
+                Some(2) => "large",
+                Some(3) => "slider",
+                _ => "default",
+            };
+            monitors.push(json!({
+                "id": self.variable_id(owner, name),
+                "mode": mode,
+                "opcode": "data_variable",
+                "params": {"VARIABLE": name},
+                "spriteName": if owner == "Stage" { Value::Null } else { json!(owner) },
+                "visible": true,
+                "x": watcher.get("x").cloned().unwrap_or(json!(0)),
+                "y": watcher.get("y").cloned().unwrap_or(json!(0)),
+            }));
+        }
+        let owners = std::iter::once(("Stage", stage))
+            .chain(sprites.iter().map(|sprite| (object_name(sprite), *sprite)));
+        for (owner, object) in owners {
+            for list in array(object, "lists") {
+                if list.get("visible").and_then(Value::as_bool) != Some(true) {
+                    continue;
+                }
+                let name = list
+                    .get("listName")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                monitors.push(json!({
+                    "id": self.list_id(owner, name),
+                    "mode": "list",
+                    "opcode": "data_listcontents",
+                    "params": {"LIST": name},
+                    "spriteName": if owner == "Stage" { Value::Null } else { json!(owner) },
+                    "visible": true,
+                    "x": list.get("x").cloned().unwrap_or(json!(0)),
+                    "y": list.get("y").cloned().unwrap_or(json!(0)),
+                }));
+            }
+        }
+        monitors
+    }
+}
+
+fn array<'a>(object: &'a Value, key: &str) -> &'a [Value] {
+    object
+        .get(key)
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+/// Rewrites field values whose sb2 spelling differs from sb3.
+fn field_value(opcode: Option<&str>, value: String) -> String {
+    match opcode {
+        Some("event_whengreaterthan" | "sensing_current") => value.to_uppercase().replace(' ', ""),
+        _ => value,
+    }
+}
+
+/// The `%n`, `%s`, and `%b` argument slots of a custom block spec, in order.
+fn spec_arguments(spec: &str) -> Vec<char> {
+    spec.split_whitespace()
+        .filter_map(|token| match token {
+            "%n" => Some('n'),
+            "%s" => Some('s'),
+            "%b" => Some('b'),
+            _ => None,
+        })
+        .collect()
+}
+
+/// sb3 proccodes have no number slots; `%n` arguments become `%s`.
+fn sb3_proccode(spec: &str) -> String {
+    spec.split(' ')
+        .map(|token| if token == "%n" { "%s" } else { token })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn is_number(value: &Value) -> bool {
+    match value {
+        Value::Number(_) => true,
+        Value::String(text) => !text.trim().is_empty() && text.trim().parse::<f64>().is_ok(),
+        _ => false,
+    }
+}
+
+fn scalar_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Number(number) => match number.as_f64() {
+            Some(float) if float.fract() == 0.0 && float.abs() < 1e15 => {
+                format!("{}", float as i64)
+            }
+            _ => number.to_string(),
+        },
+        Value::Bool(flag) => flag.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// sb2 stores colors as signed ARGB integers.
+fn color_text(value: &Value) -> String {
+    let rgb = value.as_i64().unwrap_or_default() & 0xff_ffff;
+    format!("#{:06x}", rgb)
+}
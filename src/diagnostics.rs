@@ -0,0 +1,296 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How serious a printed diagnostic is; decides its prefix and color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    /// `error` or `warning`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Severity::Error => "Error",
+            Severity::Warning => "Warning",
+        }
+    }
+
+    /// Bold red or bold yellow.
+    fn color(self) -> &'static str {
+        match self {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+        }
+    }
+}
+
+/// The compiler stage a [`Diagnostic`] comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Resolving imports and includes, or reading an `.sbtc` bundle.
+    Import,
+    Lex,
+    Parse,
+    Semantic,
+    /// Building the project, including loading costumes and sounds.
+    Codegen,
+    /// Reading the input file.
+    Io,
+}
+
+impl Phase {
+    /// `import`, `lex`, `parse`, `semantic`, `codegen`, or `io`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Phase::Import => "import",
+            Phase::Lex => "lex",
+            Phase::Parse => "parse",
+            Phase::Semantic => "semantic",
+            Phase::Codegen => "codegen",
+            Phase::Io => "io",
+        }
+    }
+}
+
+/// A second location that explains a [`Diagnostic`], such as the first of two
+/// duplicate declarations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Note {
+    pub message: String,
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// One problem found while compiling. Positions are already mapped from the
+/// merged source back to the file they were written in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub phase: Phase,
+    /// The message on its own, without any position.
+    pub message: String,
+    /// The original source file; `None` for source compiled from a string and
+    /// for problems not tied to a file.
+    pub file: Option<PathBuf>,
+    /// 1-based line in `file` (or in the source string); `None` when the
+    /// problem has no position.
+    pub line: Option<usize>,
+    /// 1-based column, in characters.
+    pub column: Option<usize>,
+    pub notes: Vec<Note>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, phase: Phase, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            phase,
+            message: message.into(),
+            file: None,
+            line: None,
+            column: None,
+            notes: Vec::new(),
+        }
+    }
+
+    /// An error without a position, carrying `error` and its causes.
+    pub fn from_error(phase: Phase, error: &anyhow::Error) -> Self {
+        Self::new(Severity::Error, phase, format!("{:#}", error))
+    }
+
+    pub fn at(mut self, file: Option<PathBuf>, line: usize, column: usize) -> Self {
+        self.file = file;
+        self.line = Some(line);
+        self.column = Some(column);
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+/// The one-line form the string-based APIs return, for example
+/// `Parse error: Expected 'end'. (file 'main.sbtext', line 3, column 1)`.
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let position = self.line.zip(self.column);
+        match self.phase {
+            Phase::Lex | Phase::Parse => {
+                let kind = if self.phase == Phase::Lex {
+                    "Lex"
+                } else {
+                    "Parse"
+                };
+                write!(f, "{} error: {}", kind, self.message)?;
+                match (&self.file, position) {
+                    (Some(file), Some((line, column))) => write!(
+                        f,
+                        " (file '{}', line {}, column {})",
+                        display_path(file),
+                        line,
+                        column
+                    ),
+                    (None, Some((line, column))) => {
+                        write!(f, " (line {}, column {})", line, column)
+                    }
+                    _ => Ok(()),
+                }
+            }
+            _ => {
+                write!(f, "{}", self.message)?;
+                let Some((line, column)) = position else {
+                    return Ok(());
+                };
+                let Some(file) = &self.file else {
+                    return write!(f, " (line {}, column {})", line, column);
+                };
+                write!(
+                    f,
+                    " (file '{}', mapped line {}, column {})",
+                    display_path(file),
+                    line,
+                    column
+                )?;
+                for note in &self.notes {
+                    if let Some(file) = &note.file {
+                        write!(
+                            f,
+                            " (see file '{}', mapped line {}, column {})",
+                            display_path(file),
+                            note.line,
+                            note.column
+                        )?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// `path` for messages, without the `\\?\` prefix Windows adds to
+/// canonical paths.
+pub(crate) fn display_path(path: &Path) -> String {
+    let raw = path.display().to_string();
+    match raw.strip_prefix(r"\\?\") {
+        Some(stripped) => stripped.to_string(),
+        None => raw,
+    }
+}
+
+/// `Error:` or `Warning:`, wrapped in ANSI color codes when `color` is set.
+pub fn prefix(severity: Severity, color: bool) -> String {
+    if color {
+        format!("{}{}:\x1b[0m", severity.color(), severity.label())
+    } else {
+        format!("{}:", severity.label())
+    }
+}
+
+/// Whether diagnostics on stderr should be colored: stderr is a terminal and
+/// `NO_COLOR` is unset or empty (see <https://no-color.org>).
+#[cfg(not(target_arch = "wasm32"))]
+pub fn stderr_color() -> bool {
+    use std::io::IsTerminal;
+    std::io::stderr().is_terminal()
+        && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+/// Renders line `line` of `file` with the position underlined, reading the
+/// file from disk. Falls back to `fallback` (the same line taken from the
+/// merged source) when the file cannot be read, as for stdin or a `.sbtc`
+/// bundle built elsewhere.
+pub fn file_snippet(
+    file: &Path,
+    line: usize,
+    column: usize,
+    fallback: Option<&str>,
+) -> Option<String> {
+    let from_disk = fs::read_to_string(file).ok().and_then(|source| {
+        source
+            .lines()
+            .nth(line.checked_sub(1)?)
+            .map(ToString::to_string)
+    });
+    let text = from_disk.or_else(|| fallback.map(ToString::to_string))?;
+    Some(snippet(line, &text, column))
+}
+
+/// A source line under a line-number gutter with `^~~~` under the word that
+/// starts at `column` (1-based, in characters):
+///
+/// ```text
+///  3 |     say (scroe)
+///    |          ^~~~~
+/// ```
+pub fn snippet(line: usize, text: &str, column: usize) -> String {
+    let text = text.trim_end();
+    let number = line.to_string();
+    let gutter = " ".repeat(number.len());
+    // Keep tabs in the padding so the caret lines up however tabs are shown.
+    let padding = text
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+    let word = text
+        .chars()
+        .skip(column.saturating_sub(1))
+        .take_while(|ch| ch.is_alphanumeric() || *ch == '_')
+        .count();
+    format!(
+        " {} | {}\n {} | {}^{}",
+        number,
+        text,
+        gutter,
+        padding,
+        "~".repeat(word.saturating_sub(1))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn underlines_the_word_at_the_column() {
+        assert_eq!(
+            snippet(12, "    say (scroe)", 10),
+            " 12 |     say (scroe)\n    |          ^~~~~"
+        );
+    }
+
+    #[test]
+    fn punctuation_gets_a_single_caret_and_tabs_are_kept() {
+        assert_eq!(snippet(3, "\tsay (\n", 6), " 3 | \tsay (\n   | \t    ^");
+        assert_eq!(snippet(1, "x", 5), " 1 | x\n   |  ^");
+    }
+
+    #[test]
+    fn prefixes_are_colored_only_on_request() {
+        assert_eq!(prefix(Severity::Warning, false), "Warning:");
+        assert_eq!(prefix(Severity::Error, true), "\x1b[1;31mError:\x1b[0m");
+    }
+
+    #[test]
+    fn unreadable_files_fall_back_to_the_merged_line() {
+        let missing = Path::new("/nonexistent/<stdin>");
+        assert_eq!(
+            file_snippet(missing, 2, 1, Some("end")).as_deref(),
+            Some(" 2 | end\n   | ^~~")
+        );
+        assert_eq!(file_snippet(missing, 2, 1, None), None);
+    }
+}
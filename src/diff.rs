@@ -0,0 +1,340 @@
+use crate::decompile::render_top_level_script;
+use crate::sb3::normalize::{
+    normalize_project, NormalizedAsset, NormalizedScript, NormalizedTarget,
+};
+use crate::sb3::read_sb3_file;
+use anyhow::Result;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+/// Semantic differences between two projects, ignoring block ids, script
+/// positions, and the order of targets and scripts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProjectDiff {
+    pub added_targets: Vec<String>,
+    pub removed_targets: Vec<String>,
+    /// Targets present in both projects, with one rendered line per change.
+    pub changed_targets: Vec<(String, Vec<String>)>,
+}
+
+impl ProjectDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_targets.is_empty()
+            && self.removed_targets.is_empty()
+            && self.changed_targets.is_empty()
+    }
+
+    pub fn render(&self) -> String {
+        let mut lines = Vec::new();
+        for name in &self.removed_targets {
+            lines.push(format!("- target {}", name));
+        }
+        for name in &self.added_targets {
+            lines.push(format!("+ target {}", name));
+        }
+        for (name, changes) in &self.changed_targets {
+            lines.push(format!("~ target {}", name));
+            lines.extend(changes.iter().map(|change| format!("  {}", change)));
+        }
+        lines.join("\n")
+    }
+}
+
+pub fn diff_sb3_files(old: &Path, new: &Path) -> Result<ProjectDiff> {
+    let old = read_sb3_file(old)?;
+    let new = read_sb3_file(new)?;
+    diff_projects(&old.project, &new.project)
+}
+
+pub fn diff_projects(old: &Value, new: &Value) -> Result<ProjectDiff> {
+    let old_targets = normalize_project(old).targets;
+    let new_targets = normalize_project(new).targets;
+    let mut diff = ProjectDiff {
+        removed_targets: keys_only_in(&old_targets, &new_targets),
+        added_targets: keys_only_in(&new_targets, &old_targets),
+        ..Default::default()
+    };
+    for (name, old_target) in &old_targets {
+        let Some(new_target) = new_targets.get(name) else {
+            continue;
+        };
+        let blocks = (target_blocks(old, name), target_blocks(new, name));
+        let changes = diff_target(old_target, new_target, blocks)?;
+        if !changes.is_empty() {
+            diff.changed_targets.push((name.clone(), changes));
+        }
+    }
+    Ok(diff)
+}
+
+fn keys_only_in<V>(left: &BTreeMap<String, V>, right: &BTreeMap<String, V>) -> Vec<String> {
+    left.keys()
+        .filter(|key| !right.contains_key(*key))
+        .cloned()
+        .collect()
+}
+
+fn target_blocks(project: &Value, name: &str) -> Map<String, Value> {
+    project
+        .get("targets")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .find(|target| target.get("name").and_then(Value::as_str) == Some(name))
+        .and_then(|target| target.get("blocks"))
+        .and_then(Value::as_object)
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn diff_target(
+    old: &NormalizedTarget,
+    new: &NormalizedTarget,
+    (old_blocks, new_blocks): (Map<String, Value>, Map<String, Value>),
+) -> Result<Vec<String>> {
+    let mut changes = Vec::new();
+    diff_names(&mut changes, "var", &old.variables, &new.variables);
+    diff_names(&mut changes, "list", &old.lists, &new.lists);
+    diff_assets(&mut changes, "costume", &old.costumes, &new.costumes);
+    diff_asset_order(&mut changes, "costume", &old.costumes, &new.costumes);
+    diff_assets(&mut changes, "sound", &old.sounds, &new.sounds);
+    diff_asset_order(&mut changes, "sound", &old.sounds, &new.sounds);
+
+    let old_scripts = scripts_by_key(&old.scripts);
+    let new_scripts = scripts_by_key(&new.scripts);
+    let keys = old_scripts
+        .keys()
+        .chain(new_scripts.keys())
+        .collect::<BTreeSet<_>>();
+    for key in keys {
+        let mut removed = old_scripts.get(key).cloned().unwrap_or_default();
+        let mut added = new_scripts.get(key).cloned().unwrap_or_default();
+        removed.retain(
+            |script| match added.iter().position(|other| other.shape == script.shape) {
+                Some(index) => {
+                    added.remove(index);
+                    false
+                }
+                None => true,
+            },
+        );
+        // Whatever is left over on both sides under one key was edited.
+        let paired = removed.len().min(added.len());
+        for (old_script, new_script) in removed.iter().zip(&added) {
+            let before = render_top_level_script(&old_blocks, &old_script.top_id)?;
+            let after = render_top_level_script(&new_blocks, &new_script.top_id)?;
+            changes.push("~ script".to_string());
+            let lines = line_diff(&before, &after);
+            if lines.iter().all(|line| line.starts_with(' ')) {
+                changes
+                    .push("    (differs only in details the decompiler does not show)".to_string());
+            }
+            changes.extend(lines.into_iter().map(|line| format!("  {}", line)));
+        }
+        for script in &removed[paired..] {
+            changes.push("- script".to_string());
+            for line in render_top_level_script(&old_blocks, &script.top_id)? {
+                changes.push(format!("  - {}", line));
+            }
+        }
+        for script in &added[paired..] {
+            changes.push("+ script".to_string());
+            for line in render_top_level_script(&new_blocks, &script.top_id)? {
+                changes.push(format!("  + {}", line));
+            }
+        }
+    }
+    Ok(changes)
+}
+
+fn scripts_by_key(scripts: &[NormalizedScript]) -> BTreeMap<&str, Vec<&NormalizedScript>> {
+    let mut by_key: BTreeMap<&str, Vec<&NormalizedScript>> = BTreeMap::new();
+    for script in scripts {
+        by_key.entry(&script.key).or_default().push(script);
+    }
+    by_key
+}
+
+fn diff_names(
+    changes: &mut Vec<String>,
+    kind: &str,
+    old: &BTreeSet<String>,
+    new: &BTreeSet<String>,
+) {
+    for name in old.difference(new) {
+        changes.push(format!("- {} {}", kind, name));
+    }
+    for name in new.difference(old) {
+        changes.push(format!("+ {} {}", kind, name));
+    }
+}
+
+/// Assets are matched by name; a name whose md5 changed had its content edited.
+fn diff_assets(
+    changes: &mut Vec<String>,
+    kind: &str,
+    old: &[NormalizedAsset],
+    new: &[NormalizedAsset],
+) {
+    let find = |assets: &'_ [NormalizedAsset], name: &str| {
+        assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .map(|asset| asset.md5ext.clone())
+    };
+    for asset in old {
+        match find(new, &asset.name) {
+            None => changes.push(format!("- {} {:?} ({})", kind, asset.name, asset.md5ext)),
+            Some(md5ext) if md5ext != asset.md5ext => changes.push(format!(
+                "~ {} {:?} content {} -> {}",
+                kind, asset.name, asset.md5ext, md5ext
+            )),
+            Some(_) => {}
+        }
+    }
+    for asset in new {
+        if find(old, &asset.name).is_none() {
+            changes.push(format!("+ {} {:?} ({})", kind, asset.name, asset.md5ext));
+        }
+    }
+}
+
+/// Costumes and sounds are also picked by number, so moving one changes the
+/// project. Only names in both lists are compared; additions and removals are
+/// reported by [`diff_assets`].
+fn diff_asset_order(
+    changes: &mut Vec<String>,
+    kind: &str,
+    old: &[NormalizedAsset],
+    new: &[NormalizedAsset],
+) {
+    fn kept_in<'a>(assets: &'a [NormalizedAsset], other: &[NormalizedAsset]) -> Vec<&'a str> {
+        assets
+            .iter()
+            .filter(|asset| other.iter().any(|o| o.name == asset.name))
+            .map(|asset| asset.name.as_str())
+            .collect()
+    }
+    let (old_order, new_order) = (kept_in(old, new), kept_in(new, old));
+    if old_order != new_order {
+        changes.push(format!(
+            "~ {} order {:?} -> {:?}",
+            kind, old_order, new_order
+        ));
+    }
+}
+
+/// A line diff by longest common subsequence: unchanged lines start with two
+/// spaces, removed ones with `- `, added ones with `+ `.
+fn line_diff(old: &[String], new: &[String]) -> Vec<String> {
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(format!("  {}", old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(format!("- {}", old[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+ {}", new[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::CodegenOptions;
+    use serde_json::json;
+
+    fn project(source: &str) -> Value {
+        let bytes = crate::compile_source_to_sb3_bytes_with_options(
+            source,
+            Path::new("."),
+            CodegenOptions::default(),
+        )
+        .expect("source should compile");
+        crate::sb3::read_sb3_bytes(&bytes)
+            .expect("archive should read")
+            .project
+    }
+
+    #[test]
+    fn reordered_scripts_are_identical_and_edits_are_reported() {
+        let base = "stage\nend\n\nsprite Cat\n  var speed\n  when flag clicked\n    move (10) steps\n  end\n  when I receive [go]\n    turn right (15)\n  end\nend\n";
+        let reordered = "stage\nend\n\nsprite Cat\n  var speed\n  when I receive [go]\n    turn right (15)\n  end\n  when flag clicked\n    move (10) steps\n  end\nend\n";
+        let edited = "stage\nend\n\nsprite Cat\n  list log\n  when flag clicked\n    move (20) steps\n  end\n  when I receive [go]\n    turn right (15)\n  end\n  when this sprite clicked\n    show\n  end\nend\n\nsprite Dog\nend\n";
+
+        let same = diff_projects(&project(base), &project(reordered)).expect("diff");
+        assert!(same.is_empty(), "{}", same.render());
+
+        let diff = diff_projects(&project(base), &project(edited)).expect("diff");
+        assert_eq!(diff.added_targets, vec!["Dog".to_string()]);
+        let rendered = diff.render();
+        for expected in [
+            "~ target Cat",
+            "  - var speed",
+            "  + list log",
+            "  ~ script",
+            "    -     move (10) [steps]",
+            "    +     move (20) [steps]",
+            "  + script",
+            "    +   when this sprite clicked",
+        ] {
+            assert!(
+                rendered.lines().any(|line| line == expected),
+                "missing {:?} in\n{}",
+                expected,
+                rendered
+            );
+        }
+        assert!(!rendered.contains("turn right"), "{}", rendered);
+    }
+
+    #[test]
+    fn loose_reporters_and_costume_order_are_compared() {
+        let target = |costumes: &[&str], blocks: Value| {
+            json!({"targets": [{
+                "name": "Cat",
+                "isStage": false,
+                "costumes": costumes
+                    .iter()
+                    .map(|name| json!({"name": name, "md5ext": format!("{}.svg", name)}))
+                    .collect::<Vec<_>>(),
+                "blocks": blocks,
+            }]})
+        };
+        let old = target(&["a", "b"], json!({}));
+        let new = target(&["b", "a"], json!({"loose": [12, "score", "v1", 10, 20]}));
+
+        let diff = diff_projects(&old, &new).expect("diff");
+        let rendered = diff.render();
+        for expected in [
+            "  ~ costume order [\"a\", \"b\"] -> [\"b\", \"a\"]",
+            "  + script",
+            "    +   score",
+        ] {
+            assert!(
+                rendered.lines().any(|line| line == expected),
+                "missing {:?} in\n{}",
+                expected,
+                rendered
+            );
+        }
+    }
+}
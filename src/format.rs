@@ -0,0 +1,313 @@
+use crate::ast::{Project, Statement};
+use crate::lexer::{Lexer, TokenType};
+use crate::parser::Parser;
+use anyhow::{anyhow, Result};
+use std::collections::{HashMap, HashSet};
+
+/// Spaces per nesting level.
+const INDENT: usize = 2;
+
+/// Re-indents SBText source: targets at column 0, their declarations and
+/// scripts one level in, and each nested body one level further, with `else`
+/// and `end` lined up with the block they belong to. Trailing whitespace and
+/// runs of blank lines are dropped.
+///
+/// Only whitespace between lines changes, never the tokens, so formatting
+/// cannot change what a file compiles to. Lines that continue a statement
+/// started on an earlier line are left as they are. The file must parse on
+/// its own; `import` and `include` lines are kept without being followed.
+pub fn format_source(source: &str) -> Result<String> {
+    let source = source.trim_start_matches('\u{feff}');
+    let lines = source.lines().collect::<Vec<_>>();
+    // The parser does not know imports and includes; blank them out so line
+    // numbers still match.
+    let parse_text = lines
+        .iter()
+        .map(|line| if directive(line).is_some() { "" } else { line })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let tokens = Lexer::new(&parse_text).tokenize().map_err(|e| {
+        anyhow!(
+            "Lex error: {} (line {}, column {})",
+            e.message,
+            e.pos.line,
+            e.pos.column
+        )
+    })?;
+    let mut first_tokens: HashMap<usize, (TokenType, String)> = HashMap::new();
+    for token in tokens
+        .iter()
+        .filter(|token| token.typ != TokenType::Newline)
+    {
+        first_tokens
+            .entry(token.pos.line)
+            .or_insert_with(|| (token.typ.clone(), token.value.clone()));
+    }
+    // Lines and keywords of the tokens that are not layout, in order.
+    let code = tokens
+        .iter()
+        .filter(|token| {
+            !matches!(
+                token.typ,
+                TokenType::Newline | TokenType::DocComment | TokenType::Eof
+            )
+        })
+        .map(|token| {
+            let keyword = token.typ == TokenType::Keyword;
+            (token.pos.line, keyword.then(|| token.value.clone()))
+        })
+        .collect::<Vec<_>>();
+    let project = Parser::new(tokens).parse_project().map_err(|e| {
+        anyhow!(
+            "Parse error: {} (line {}, column {})",
+            e.message,
+            e.pos.line,
+            e.pos.column
+        )
+    })?;
+    let else_lines = first_tokens
+        .iter()
+        .filter(|(_, (typ, value))| *typ == TokenType::Keyword && value == "else")
+        .map(|(line, _)| *line)
+        .collect::<HashSet<_>>();
+    let starts = line_starts(&project, &else_lines);
+
+    let mut out: Vec<String> = Vec::new();
+    // Blocks still waiting for their `end`: depth, and whether the `end` is
+    // optional (event scripts).
+    let mut open: Vec<(usize, bool)> = Vec::new();
+    // Comment and blank lines take the indentation of the code after them.
+    let mut pending: Vec<&str> = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let number = index + 1;
+        let text = line.trim();
+        let first = first_tokens.get(&number);
+        let is_comment =
+            text.starts_with('#') && first.is_none_or(|(typ, _)| *typ == TokenType::DocComment);
+        if text.is_empty() || is_comment {
+            pending.push(text);
+            continue;
+        }
+
+        let keyword = first
+            .filter(|(typ, _)| *typ == TokenType::Keyword)
+            .map(|(_, value)| value.as_str());
+        let (depth, comment_depth) = if let Some(depth) = directive(line) {
+            (Some(depth), depth)
+        } else if let Some(&(depth, block)) = starts.get(&number) {
+            // An `else if` line stays inside the `if` it continues.
+            if block != Block::ElseIf {
+                while open.last().is_some_and(|&(top, _)| top >= depth) {
+                    open.pop();
+                }
+            }
+            match block {
+                Block::None => {}
+                Block::Closed | Block::ElseIf => open.push((depth, false)),
+                Block::Event => open.push((depth, true)),
+            }
+            let comment_depth = if block == Block::ElseIf {
+                depth + 1
+            } else {
+                depth
+            };
+            (Some(depth), comment_depth)
+        } else if keyword == Some("end") {
+            // Like the parser: an `end` right before the next target or the
+            // end of the file closes the target, not an event script in it.
+            let next = code.partition_point(|(line, _)| *line <= number);
+            let closes_target = match code.get(next) {
+                None => true,
+                Some((_, keyword)) => matches!(keyword.as_deref(), Some("sprite" | "stage")),
+            };
+            if closes_target && open.last().is_some_and(|&(_, optional)| optional) {
+                open.pop();
+            }
+            let depth = open.pop().map_or(0, |(depth, _)| depth);
+            (Some(depth), depth + 1)
+        } else if keyword == Some("else") {
+            let depth = open.last().map_or(0, |&(depth, _)| depth);
+            (Some(depth), depth + 1)
+        } else {
+            // The rest of a statement that started on an earlier line.
+            (None, open.last().map_or(0, |&(depth, _)| depth + 1))
+        };
+        flush_pending(&mut out, &mut pending, comment_depth);
+        match depth {
+            Some(depth) => out.push(format!("{}{}", " ".repeat(depth * INDENT), text)),
+            None => out.push(line.trim_end().to_string()),
+        }
+    }
+    flush_pending(&mut out, &mut pending, 0);
+
+    while out.last().is_some_and(String::is_empty) {
+        out.pop();
+    }
+    let mut formatted = out.join("\n");
+    formatted.push('\n');
+    Ok(formatted)
+}
+
+/// The depth of an `import` (top level) or `include` (inside a target) line.
+fn directive(line: &str) -> Option<usize> {
+    let text = line.trim_start();
+    if text.starts_with("import ") || text.starts_with("import[") {
+        Some(0)
+    } else if text.starts_with("include ") {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+/// Writes buffered comment and blank lines, keeping at most one blank line in
+/// a row and none at the start of the file.
+fn flush_pending(out: &mut Vec<String>, pending: &mut Vec<&str>, depth: usize) {
+    for text in pending.drain(..) {
+        if text.is_empty() {
+            if out.last().is_some_and(|last| !last.is_empty()) {
+                out.push(String::new());
+            }
+        } else {
+            out.push(format!("{}{}", " ".repeat(depth * INDENT), text));
+        }
+    }
+}
+
+/// What kind of block a line opens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Block {
+    None,
+    /// Closed by a required `end`.
+    Closed,
+    /// An event script, whose `end` may be left out.
+    Event,
+    /// The `if` of an `else if` line, lined up with the `if` it continues and
+    /// closed by its own `end`.
+    ElseIf,
+}
+
+/// Maps the first line of every target, declaration, script, and statement to
+/// its nesting depth and the block it opens. `else_lines` are the lines that
+/// start with `else`.
+fn line_starts(project: &Project, else_lines: &HashSet<usize>) -> HashMap<usize, (usize, Block)> {
+    let mut starts = HashMap::new();
+    let mut mark = |line: usize, depth: usize, block: Block| {
+        starts.entry(line).or_insert((depth, block));
+    };
+    for target in &project.targets {
+        mark(target.pos.line, 0, Block::Closed);
+        let declarations = target
+            .variables
+            .iter()
+            .map(|decl| decl.pos)
+            .chain(target.lists.iter().map(|decl| decl.pos))
+            .chain(target.costumes.iter().map(|decl| decl.pos))
+            .chain(target.sounds.iter().map(|decl| decl.pos))
+            .chain(target.properties.iter().map(|decl| decl.pos))
+            .chain(target.monitors.iter().map(|decl| decl.pos));
+        for pos in declarations {
+            mark(pos.line, 1, Block::None);
+        }
+        let bodies = target
+            .procedures
+            .iter()
+            .map(|procedure| (procedure.pos, &procedure.body, Block::Closed))
+            .chain(
+                target
+                    .reporters
+                    .iter()
+                    .map(|reporter| (reporter.pos, &reporter.body, Block::Closed)),
+            )
+            .chain(
+                target
+                    .scripts
+                    .iter()
+                    .map(|script| (script.pos, &script.body, Block::Event)),
+            );
+        for (pos, body, block) in bodies {
+            mark(pos.line, 1, block);
+            mark_statements(body, 2, else_lines, &mut mark);
+        }
+    }
+    starts
+}
+
+fn mark_statements(
+    statements: &[Statement],
+    depth: usize,
+    else_lines: &HashSet<usize>,
+    mark: &mut impl FnMut(usize, usize, Block),
+) {
+    for statement in statements {
+        let line = statement.pos().line;
+        let (_, mut bodies) = statement.children();
+        if bodies.is_empty() {
+            mark(line, depth, Block::None);
+            continue;
+        }
+        mark(line, depth, Block::Closed);
+        // An `if` that is the whole `else` branch and shares the `else` line
+        // continues the chain at this depth; its branches replace the `else`.
+        let mut chain = statement;
+        while let Statement::If { else_body, .. } = chain {
+            let [nested @ Statement::If { pos, .. }] = else_body.as_slice() else {
+                break;
+            };
+            if !else_lines.contains(&pos.line) {
+                break;
+            }
+            mark(pos.line, depth, Block::ElseIf);
+            bodies.pop();
+            bodies.extend(nested.children().1);
+            chain = nested;
+        }
+        for body in bodies {
+            mark_statements(body, depth + 1, else_lines, mark);
+        }
+    }
+}
+
+/// Whether `source` is already formatted.
+pub fn is_formatted(source: &str) -> Result<bool> {
+    Ok(format_source(source)? == source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reindents_nested_blocks_and_lines_up_else_and_end() {
+        let source = "\u{feff}\n\nsprite Cat\n      var speed = 1\n# jump when clicked\nwhen flag clicked\nif <(speed) > (0)> then\n        say (\"hi\")   \n   else\nrepeat (3)\nmove (speed) steps\nend\n end\n\n\n\n    define hop (n)\n change y by (n)\nend\nend\n";
+        let expected = "sprite Cat\n  var speed = 1\n  # jump when clicked\n  when flag clicked\n    if <(speed) > (0)> then\n      say (\"hi\")\n    else\n      repeat (3)\n        move (speed) steps\n      end\n    end\n\n  define hop (n)\n    change y by (n)\n  end\nend\n";
+        let formatted = format_source(source).expect("source should format");
+        assert_eq!(formatted, expected);
+        assert!(is_formatted(&formatted).expect("formatted source should parse"));
+    }
+
+    #[test]
+    fn an_end_before_the_next_target_closes_the_target() {
+        let source = "import [Dog] from \"dog.sbtext\"\nstage\n when flag clicked\n  say (\"a\")\n    end\nsprite Cat\nwhen flag clicked\n say (\"b\")\nend\n";
+        let expected = "import [Dog] from \"dog.sbtext\"\nstage\n  when flag clicked\n    say (\"a\")\nend\nsprite Cat\n  when flag clicked\n    say (\"b\")\nend\n";
+        assert_eq!(
+            format_source(source).expect("source should format"),
+            expected
+        );
+    }
+
+    #[test]
+    fn else_if_chains_line_up_with_the_first_if() {
+        let source = "sprite Cat\nwhen flag clicked\nif <(1) = (1)> then\nsay (\"one\")\nelse if <(1) = (2)> then\nsay (\"two\")\n# otherwise\nelse if <(1) = (3)> then\nsay (\"three\")\nelse\nif <(1) = (4)> then\nsay (\"four\")\nend\nend\nend\nend\nmove (1)\nend\nend\n";
+        let expected = "sprite Cat\n  when flag clicked\n    if <(1) = (1)> then\n      say (\"one\")\n    else if <(1) = (2)> then\n      say (\"two\")\n      # otherwise\n    else if <(1) = (3)> then\n      say (\"three\")\n    else\n      if <(1) = (4)> then\n        say (\"four\")\n      end\n    end\n    end\n    end\n    move (1)\n  end\nend\n";
+        let formatted = format_source(source).expect("source should format");
+        assert_eq!(formatted, expected);
+        assert!(is_formatted(&formatted).expect("formatted source should parse"));
+    }
+
+    #[test]
+    fn unparsable_files_are_rejected() {
+        let err = format_source("sprite Cat\n  when flag clicked\n    say (\nend\n").unwrap_err();
+        assert!(err.to_string().starts_with("Parse error:"), "{}", err);
+    }
+}
@@ -1,5 +1,7 @@
+use crate::ast::{Position, Project};
 use anyhow::{bail, Result};
 use regex::Regex;
+use serde_json::{json, Value};
 use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -17,19 +19,66 @@ pub struct MappedPosition {
     pub column: usize,
 }
 
+/// A run of merged lines copied from consecutive lines of one source file.
+/// Line numbers are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceSegment {
+    pub merged_start_line: usize,
+    pub file: PathBuf,
+    pub original_start_line: usize,
+    pub length: usize,
+}
+
+/// An import or include problem found on a line of a source file. The
+/// message already names the file and line; they are kept apart for
+/// diagnostics.
+#[derive(Debug)]
+pub(crate) struct ImportError {
+    pub message: String,
+    pub file: PathBuf,
+    pub line: usize,
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+fn import_error(file: &Path, line: usize, message: String) -> anyhow::Error {
+    ImportError {
+        message,
+        file: file.to_path_buf(),
+        line,
+    }
+    .into()
+}
+
 #[derive(Debug, Clone)]
 pub struct MergedSource {
     pub source: String,
     pub line_origins: Vec<SourceLineOrigin>,
     entry_file: PathBuf,
+    files: Vec<PathBuf>,
+    import_dirs: Vec<PathBuf>,
 }
 
 impl MergedSource {
+    /// The file list is the entry file followed by every other file named in
+    /// `line_origins`, in order of first appearance.
     pub fn new(source: String, line_origins: Vec<SourceLineOrigin>, entry_file: PathBuf) -> Self {
+        let files = unique_paths(
+            std::iter::once(entry_file.clone())
+                .chain(line_origins.iter().map(|origin| origin.file.clone())),
+        );
         Self {
             source,
             line_origins,
             entry_file,
+            files,
+            import_dirs: Vec::new(),
         }
     }
 
@@ -37,6 +86,94 @@ impl MergedSource {
         &self.entry_file
     }
 
+    /// Records, on each costume and sound declared outside the entry file, the
+    /// directory of the file that declared it, so its path resolves next to
+    /// that file first.
+    pub fn set_asset_origins(&self, project: &mut Project) {
+        let origin_dir = |pos: Position| {
+            let mapped = self.map_position(pos.line, pos.column);
+            if mapped.file == self.entry_file {
+                return None;
+            }
+            mapped.file.parent().map(Path::to_path_buf)
+        };
+        for target in &mut project.targets {
+            for costume in &mut target.costumes {
+                costume.origin_dir = origin_dir(costume.pos);
+            }
+            for sound in &mut target.sounds {
+                sound.origin_dir = origin_dir(sound.pos);
+            }
+        }
+    }
+
+    /// Every source file that took part in the merge: the entry file, then each
+    /// imported or included file in resolution order. Includes files that
+    /// contribute no lines.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// The directories scanned by `import *` lines. Adding, removing, or
+    /// renaming a file in one of them can change what the merge pulls in.
+    pub fn import_dirs(&self) -> &[PathBuf] {
+        &self.import_dirs
+    }
+
+    /// The line origins collapsed into segments, in merged-line order.
+    pub fn segments(&self) -> Vec<SourceSegment> {
+        let mut segments: Vec<SourceSegment> = Vec::new();
+        for (index, origin) in self.line_origins.iter().enumerate() {
+            if let Some(last) = segments.last_mut() {
+                if last.file == origin.file && last.original_start_line + last.length == origin.line
+                {
+                    last.length += 1;
+                    continue;
+                }
+            }
+            segments.push(SourceSegment {
+                merged_start_line: index + 1,
+                file: origin.file.clone(),
+                original_start_line: origin.line,
+                length: 1,
+            });
+        }
+        segments
+    }
+
+    /// The source map written by `--emit-source-map`:
+    ///
+    /// ```json
+    /// {
+    ///   "entry_file": "/abs/main.sbtext",
+    ///   "segments": [
+    ///     { "merged_start_line": 1, "file": "/abs/sprites/cat.sbtext",
+    ///       "original_start_line": 1, "length": 12 }
+    ///   ]
+    /// }
+    /// ```
+    ///
+    /// Merged line `m` in a segment maps to original line
+    /// `original_start_line + (m - merged_start_line)`; columns are unchanged.
+    pub fn source_map_json(&self) -> Value {
+        let segments = self
+            .segments()
+            .into_iter()
+            .map(|segment| {
+                json!({
+                    "merged_start_line": segment.merged_start_line,
+                    "file": segment.file.to_string_lossy(),
+                    "original_start_line": segment.original_start_line,
+                    "length": segment.length,
+                })
+            })
+            .collect::<Vec<_>>();
+        json!({
+            "entry_file": self.entry_file.to_string_lossy(),
+            "segments": segments,
+        })
+    }
+
     pub fn map_position(&self, merged_line: usize, merged_column: usize) -> MappedPosition {
         if self.line_origins.is_empty() {
             return MappedPosition {
@@ -67,14 +204,27 @@ impl MergedSource {
 
 #[derive(Debug, Clone)]
 struct ImportSpec {
-    sprite_name: String,
+    /// `None` for `import * from "..."`, which takes every matching file.
+    sprite_name: Option<String>,
+    /// The `as` name the imported sprite is renamed to.
+    rename: Option<String>,
     relative_path: String,
     line: usize,
 }
 
+/// An `include "lib.sbtext"` line inside a target body.
+#[derive(Debug, Clone)]
+struct IncludeSpec {
+    relative_path: String,
+    line: usize,
+    /// Index into `body_lines` where the library's lines are spliced in.
+    at: usize,
+}
+
 #[derive(Debug, Clone, Default)]
 struct ParsedFile {
     imports: Vec<ImportSpec>,
+    includes: Vec<IncludeSpec>,
     body_lines: Vec<(String, usize)>,
     local_sprites: Vec<String>,
     has_stage: bool,
@@ -84,40 +234,129 @@ struct ParsedFile {
 struct ResolvedFile {
     merged_lines: Vec<String>,
     merged_line_origins: Vec<SourceLineOrigin>,
+    /// Index in `merged_lines` where this file's own body starts, after the
+    /// files it imports. The body includes the lines of its libraries.
+    body_start: usize,
     local_sprites: Vec<String>,
     local_has_stage: bool,
     merged_sprites: Vec<String>,
+    /// This file, then the files it imports and includes, in resolution order.
+    files: Vec<PathBuf>,
+    /// Directories scanned by `import *` in this file or the files it imports.
+    import_dirs: Vec<PathBuf>,
+}
+
+/// Where import paths are looked up after the importing file's own directory.
+#[derive(Debug, Clone)]
+struct ImportContext {
+    include_dirs: Vec<PathBuf>,
+    sprite_header_re: Regex,
+}
+
+impl ImportContext {
+    fn new(include_dirs: &[PathBuf]) -> Result<Self> {
+        Ok(Self {
+            include_dirs: include_dirs.to_vec(),
+            sprite_header_re: Regex::new(
+                r#"^(?P<head>\s*sprite\s+)(?:"[^"]+"|[A-Za-z_][A-Za-z0-9_]*)(?P<tail>.*)$"#,
+            )?,
+        })
+    }
 }
 
+/// File name reported for an entry source that was not read from a file.
+pub const STDIN_NAME: &str = "<stdin>";
+
 #[allow(dead_code)]
 pub fn resolve_merged_source(entry: &Path) -> Result<String> {
-    Ok(resolve_merged_source_with_map(entry)?.source)
+    Ok(resolve_merged_source_with_map(entry, &[])?.source)
 }
 
-pub fn resolve_merged_source_with_map(entry: &Path) -> Result<MergedSource> {
+/// Resolves `entry` and its imports into one source. Each import path is tried
+/// relative to the importing file first, then under each of `include_dirs` in
+/// order.
+pub fn resolve_merged_source_with_map(
+    entry: &Path,
+    include_dirs: &[PathBuf],
+) -> Result<MergedSource> {
     let canonical_entry = entry
         .canonicalize()
         .map_err(|_| anyhow::anyhow!("Input file not found: '{}'.", entry.display()))?;
+    let ctx = ImportContext::new(include_dirs)?;
     let mut cache: HashMap<PathBuf, ResolvedFile> = HashMap::new();
     let mut stack: Vec<PathBuf> = Vec::new();
-    let resolved = resolve_file(&canonical_entry, &mut stack, &mut cache)?;
+    let resolved = resolve_file(&canonical_entry, &ctx, &mut stack, &mut cache)?;
     ensure_unique_sprite_names(&resolved.merged_sprites)?;
-    let source = if resolved.merged_lines.is_empty() {
-        String::new()
-    } else {
-        let mut out = resolved.merged_lines.join("\n");
-        out.push('\n');
-        out
-    };
-    Ok(MergedSource::new(
-        source,
+    let mut merged = MergedSource::new(
+        join_merged_lines(&resolved.merged_lines),
         resolved.merged_line_origins,
         canonical_entry,
-    ))
+    );
+    merged.files = unique_paths(resolved.files);
+    merged.import_dirs = unique_paths(resolved.import_dirs);
+    Ok(merged)
+}
+
+/// Like [`resolve_merged_source_with_map`] for an entry given as text, such as
+/// source piped to the CLI. Its imports, includes, and assets resolve as if it
+/// were a file in `source_dir`, and its lines are attributed to [`STDIN_NAME`].
+/// `files()` lists only the imported and included files.
+pub fn resolve_merged_source_from_text(
+    source: &str,
+    source_dir: &Path,
+    include_dirs: &[PathBuf],
+) -> Result<MergedSource> {
+    let source_dir = source_dir
+        .canonicalize()
+        .map_err(|_| anyhow::anyhow!("Source directory not found: '{}'.", source_dir.display()))?;
+    let entry = source_dir.join(STDIN_NAME);
+    let ctx = ImportContext::new(include_dirs)?;
+    let mut cache: HashMap<PathBuf, ResolvedFile> = HashMap::new();
+    let mut stack: Vec<PathBuf> = Vec::new();
+    let resolved = resolve_source(source, &entry, &ctx, &mut stack, &mut cache)?;
+    ensure_unique_sprite_names(&resolved.merged_sprites)?;
+    let line_origins = resolved
+        .merged_line_origins
+        .into_iter()
+        .map(|origin| SourceLineOrigin {
+            file: if origin.file == entry {
+                PathBuf::from(STDIN_NAME)
+            } else {
+                origin.file
+            },
+            line: origin.line,
+        })
+        .collect();
+    let mut merged = MergedSource::new(
+        join_merged_lines(&resolved.merged_lines),
+        line_origins,
+        PathBuf::from(STDIN_NAME),
+    );
+    merged.files = unique_paths(resolved.files.into_iter().filter(|file| *file != entry));
+    merged.import_dirs = unique_paths(resolved.import_dirs);
+    Ok(merged)
+}
+
+fn join_merged_lines(lines: &[String]) -> String {
+    if lines.is_empty() {
+        return String::new();
+    }
+    let mut out = lines.join("\n");
+    out.push('\n');
+    out
+}
+
+fn unique_paths(paths: impl IntoIterator<Item = PathBuf>) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    paths
+        .into_iter()
+        .filter(|path| seen.insert(path.clone()))
+        .collect()
 }
 
 fn resolve_file(
     path: &Path,
+    ctx: &ImportContext,
     stack: &mut Vec<PathBuf>,
     cache: &mut HashMap<PathBuf, ResolvedFile>,
 ) -> Result<ResolvedFile> {
@@ -131,90 +370,448 @@ fn resolve_file(
         return Ok(cached.clone());
     }
 
-    if let Some(idx) = stack.iter().position(|p| p == &current) {
-        let mut cycle = stack[idx..].to_vec();
-        cycle.push(current.clone());
-        let rendered = cycle
-            .iter()
-            .map(|p| p.display().to_string())
-            .collect::<Vec<_>>()
-            .join(" -> ");
-        bail!("Circular import detected: {}", rendered);
-    }
+    ensure_not_circular(stack, &current, "import")?;
 
     let source = fs::read_to_string(&current)?;
-    let parsed = parse_file(&source, &current)?;
+    let resolved = resolve_source(&source, &current, ctx, stack, cache)?;
+    cache.insert(path.to_path_buf(), resolved.clone());
+    Ok(resolved)
+}
+
+/// Resolves the imports and includes of `source`, the text of `current`.
+fn resolve_source(
+    source: &str,
+    current: &Path,
+    ctx: &ImportContext,
+    stack: &mut Vec<PathBuf>,
+    cache: &mut HashMap<PathBuf, ResolvedFile>,
+) -> Result<ResolvedFile> {
+    let current = current.to_path_buf();
+    let parsed = parse_file(source, &current, false)?;
 
     stack.push(current.clone());
+    let mut files = vec![current.clone()];
+    let mut import_dirs: Vec<PathBuf> = Vec::new();
     let mut merged_lines: Vec<String> = Vec::new();
     let mut merged_line_origins: Vec<SourceLineOrigin> = Vec::new();
     let mut merged_sprites: Vec<String> = Vec::new();
+    // Lowercased sprite name -> the file that defines it, for clash messages.
+    let mut imported_from: HashMap<String, PathBuf> = HashMap::new();
 
+    let search_dirs = search_dirs(&current, ctx);
     for spec in &parsed.imports {
-        let imported_path = current
-            .parent()
-            .unwrap_or_else(|| Path::new("."))
-            .join(&spec.relative_path)
-            .canonicalize()
-            .map_err(|_| {
-                anyhow::anyhow!(
-                    "Imported file does not exist: '{}' (from '{}', line {}).",
-                    spec.relative_path,
-                    current.display(),
-                    spec.line
+        let imported_paths = if spec.sprite_name.is_some() {
+            let imported_path = find_file(&spec.relative_path, &search_dirs).map_err(|tried| {
+                import_error(
+                    &current,
+                    spec.line,
+                    format!(
+                        "Imported file does not exist: '{}' (from '{}', line {}). Tried: {}.",
+                        spec.relative_path,
+                        current.display(),
+                        spec.line,
+                        render_paths(&tried)
+                    ),
                 )
             })?;
+            vec![imported_path]
+        } else {
+            let (dir, paths) = expand_wildcard_import(spec, &search_dirs, &current)?;
+            import_dirs.push(dir);
+            paths
+        };
 
-        let resolved_child = resolve_file(&imported_path, stack, cache)?;
-        validate_import_target(
-            spec,
-            &current,
-            &imported_path,
-            &resolved_child.local_sprites,
-            resolved_child.local_has_stage,
-        )?;
+        for imported_path in imported_paths {
+            let mut resolved_child = resolve_file(&imported_path, ctx, stack, cache)?;
+            validate_import_target(
+                spec,
+                &current,
+                &imported_path,
+                &resolved_child.local_sprites,
+                resolved_child.local_has_stage,
+            )?;
+            if let Some(rename) = &spec.rename {
+                rename_imported_sprite(&mut resolved_child, &imported_path, rename, ctx);
+            }
+            let sprite = &resolved_child.local_sprites[0];
+            if let Some(previous) =
+                imported_from.insert(sprite.to_lowercase(), imported_path.clone())
+            {
+                if previous == imported_path {
+                    return Err(import_error(
+                        &current,
+                        spec.line,
+                        format!(
+                            "File '{}' is imported more than once (from '{}', line {}).",
+                            imported_path.display(),
+                            current.display(),
+                            spec.line
+                        ),
+                    ));
+                }
+                bail!(
+                    "Sprite '{}' is defined by both '{}' and '{}' (imported from '{}').",
+                    sprite,
+                    previous.display(),
+                    imported_path.display(),
+                    current.display()
+                );
+            }
 
-        merged_lines.extend(resolved_child.merged_lines.clone());
-        merged_line_origins.extend(resolved_child.merged_line_origins.clone());
-        merged_sprites.extend(resolved_child.merged_sprites.clone());
+            merged_lines.extend(resolved_child.merged_lines.clone());
+            merged_line_origins.extend(resolved_child.merged_line_origins.clone());
+            merged_sprites.extend(resolved_child.merged_sprites.clone());
+            files.extend(resolved_child.files.clone());
+            import_dirs.extend(resolved_child.import_dirs.clone());
+        }
     }
-    stack.pop();
-
-    for (line_text, line_no) in parsed.body_lines {
+    let body_start = merged_lines.len();
+    for (line_text, origin) in expand_body(&parsed, &current, ctx, stack, &mut files)? {
         merged_lines.push(line_text);
-        merged_line_origins.push(SourceLineOrigin {
-            file: current.clone(),
-            line: line_no,
-        });
+        merged_line_origins.push(origin);
     }
+    stack.pop();
 
     merged_sprites.extend(parsed.local_sprites.clone());
 
     let resolved = ResolvedFile {
         merged_lines,
         merged_line_origins,
+        body_start,
         local_sprites: parsed.local_sprites,
         local_has_stage: parsed.has_stage,
         merged_sprites,
+        files,
+        import_dirs,
     };
-    cache.insert(path.to_path_buf(), resolved.clone());
     cache.insert(current, resolved.clone());
     Ok(resolved)
 }
 
-fn parse_file(source: &str, source_path: &Path) -> Result<ParsedFile> {
+/// The body lines of `file` with every `include` replaced by the lines of the
+/// library it names. Library paths are appended to `files`.
+fn expand_body(
+    parsed: &ParsedFile,
+    file: &Path,
+    ctx: &ImportContext,
+    stack: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> Result<Vec<(String, SourceLineOrigin)>> {
+    let mut includes = parsed.includes.iter().peekable();
+    let mut out = Vec::new();
+    for (index, (line_text, line_no)) in parsed.body_lines.iter().enumerate() {
+        while let Some(include) = includes.next_if(|include| include.at == index) {
+            out.extend(resolve_library(include, file, ctx, stack, files)?);
+        }
+        let origin = SourceLineOrigin {
+            file: file.to_path_buf(),
+            line: *line_no,
+        };
+        out.push((line_text.clone(), origin));
+    }
+    for include in includes {
+        out.extend(resolve_library(include, file, ctx, stack, files)?);
+    }
+    Ok(out)
+}
+
+/// Reads the library named by an `include` in `includer`. Libraries hold target
+/// members (normally only `define`s) and may include other libraries.
+fn resolve_library(
+    spec: &IncludeSpec,
+    includer: &Path,
+    ctx: &ImportContext,
+    stack: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> Result<Vec<(String, SourceLineOrigin)>> {
+    let path = find_file(&spec.relative_path, &search_dirs(includer, ctx)).map_err(|tried| {
+        import_error(
+            includer,
+            spec.line,
+            format!(
+                "Included file does not exist: '{}' (from '{}', line {}). Tried: {}.",
+                spec.relative_path,
+                includer.display(),
+                spec.line,
+                render_paths(&tried)
+            ),
+        )
+    })?;
+    ensure_not_circular(stack, &path, "include")?;
+    let source = fs::read_to_string(&path)?;
+    let parsed = parse_file(&source, &path, true)?;
+    if !parsed.imports.is_empty() || !parsed.local_sprites.is_empty() || parsed.has_stage {
+        return Err(import_error(
+            includer,
+            spec.line,
+            format!(
+                "Included file '{}' must not contain imports, sprites, or a stage (included from '{}', line {}).",
+                path.display(),
+                includer.display(),
+                spec.line
+            ),
+        ));
+    }
+    files.push(path.clone());
+    stack.push(path.clone());
+    let lines = expand_body(&parsed, &path, ctx, stack, files)?;
+    stack.pop();
+    Ok(lines)
+}
+
+fn ensure_not_circular(stack: &[PathBuf], current: &Path, kind: &str) -> Result<()> {
+    if let Some(idx) = stack.iter().position(|p| p == current) {
+        let mut cycle = stack[idx..].to_vec();
+        cycle.push(current.to_path_buf());
+        let rendered = cycle
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        bail!("Circular {} detected: {}", kind, rendered);
+    }
+    Ok(())
+}
+
+/// The importing file's directory followed by the include directories.
+fn search_dirs(file: &Path, ctx: &ImportContext) -> Vec<PathBuf> {
+    let file_dir = file.parent().unwrap_or_else(|| Path::new("."));
+    std::iter::once(file_dir.to_path_buf())
+        .chain(ctx.include_dirs.iter().cloned())
+        .collect()
+}
+
+/// Finds `relative` under the first of `search_dirs` that has it, or returns
+/// every candidate tried.
+fn find_file(relative: &str, search_dirs: &[PathBuf]) -> Result<PathBuf, Vec<PathBuf>> {
+    let tried = search_dirs
+        .iter()
+        .map(|dir| dir.join(relative))
+        .collect::<Vec<_>>();
+    tried
+        .iter()
+        .find(|path| path.is_file())
+        .and_then(|path| path.canonicalize().ok())
+        .ok_or(tried)
+}
+
+/// Lists the files an `import *` pulls in, sorted by path. A path ending in `/`
+/// (or naming a directory) takes every `.sbtext` file in that directory; otherwise
+/// the last path component is a pattern where `*` matches any run of characters
+/// and `?` matches one. The directory is taken from the first of `search_dirs`
+/// where it exists, and is returned alongside the files. The importing file
+/// itself is never included.
+fn expand_wildcard_import(
+    spec: &ImportSpec,
+    search_dirs: &[PathBuf],
+    source_path: &Path,
+) -> Result<(PathBuf, Vec<PathBuf>)> {
+    let relative = Path::new(&spec.relative_path);
+    let (relative_dir, pattern) = if spec.relative_path.ends_with('/')
+        || search_dirs.iter().any(|dir| dir.join(relative).is_dir())
+    {
+        (relative, "*.sbtext".to_string())
+    } else {
+        let pattern = relative
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        (relative.parent().unwrap_or(Path::new("")), pattern)
+    };
+    if relative_dir.to_string_lossy().contains(['*', '?']) {
+        return Err(import_error(
+            source_path,
+            spec.line,
+            format!(
+                "Wildcards are only supported in the file name of an import path: '{}' (from '{}', line {}).",
+                spec.relative_path,
+                source_path.display(),
+                spec.line
+            ),
+        ));
+    }
+    let tried = search_dirs
+        .iter()
+        .map(|dir| dir.join(relative_dir))
+        .collect::<Vec<_>>();
+    let Some(dir) = tried.iter().find(|dir| dir.is_dir()) else {
+        return Err(import_error(
+            source_path,
+            spec.line,
+            format!(
+                "Imported directory does not exist: '{}' (from '{}', line {}). Tried: {}.",
+                spec.relative_path,
+                source_path.display(),
+                spec.line,
+                render_paths(&tried)
+            ),
+        ));
+    };
+    let entries = fs::read_dir(dir)?;
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let matches = path
+            .file_name()
+            .is_some_and(|name| wildcard_match(&pattern, &name.to_string_lossy()));
+        if matches && path.is_file() {
+            let path = path.canonicalize()?;
+            if path != source_path {
+                paths.push(path);
+            }
+        }
+    }
+    if paths.is_empty() {
+        return Err(import_error(
+            source_path,
+            spec.line,
+            format!(
+                "Import '{}' matched no files (from '{}', line {}).",
+                spec.relative_path,
+                source_path.display(),
+                spec.line
+            ),
+        ));
+    }
+    paths.sort();
+    Ok((dir.canonicalize()?, paths))
+}
+
+/// Renames the one sprite `file` defines to `new_name`. Its header and the
+/// `Old.member` references in `file` are rewritten; lines from the files it
+/// imports are left alone.
+fn rename_imported_sprite(
+    resolved: &mut ResolvedFile,
+    file: &Path,
+    new_name: &str,
+    ctx: &ImportContext,
+) {
+    let old_name = resolved.local_sprites[0].clone();
+    // Only the sprite's own body is rewritten, with the lines of the libraries
+    // it includes; the sprites it imports come first and are left alone.
+    let body = resolved.body_start..;
+    for (line, origin) in resolved.merged_lines[body.clone()]
+        .iter_mut()
+        .zip(&resolved.merged_line_origins[body])
+    {
+        if origin.file != file {
+            *line = rename_qualified_references(line, &old_name, new_name);
+        } else if let Some(caps) = ctx.sprite_header_re.captures(line) {
+            *line = format!("{}{}{}", &caps["head"], new_name, &caps["tail"]);
+        } else {
+            *line = rename_qualified_references(line, &old_name, new_name);
+        }
+    }
+    if let Some(sprite) = resolved
+        .merged_sprites
+        .iter_mut()
+        .rev()
+        .find(|sprite| **sprite == old_name)
+    {
+        *sprite = new_name.to_string();
+    }
+    resolved.local_sprites = vec![new_name.to_string()];
+}
+
+/// Replaces `old.` at the start of an identifier with `new.`, outside string
+/// literals and comments.
+fn rename_qualified_references(line: &str, old: &str, new: &str) -> String {
+    let prefix = format!("{}.", old);
+    let mut out = String::with_capacity(line.len());
+    let mut in_string = false;
+    let mut rest = line;
+    let mut previous: Option<char> = None;
+    while let Some(ch) = rest.chars().next() {
+        if in_string {
+            if ch == '\\' {
+                let escaped = rest.chars().take(2).collect::<String>();
+                out.push_str(&escaped);
+                rest = &rest[escaped.len()..];
+                continue;
+            }
+            in_string = ch != '"';
+        } else if ch == '"' {
+            in_string = true;
+        } else if ch == '#' {
+            out.push_str(rest);
+            break;
+        } else if rest.starts_with(&prefix)
+            && !previous.is_some_and(|p| p.is_alphanumeric() || p == '_' || p == '.')
+            && rest[prefix.len()..]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_alphabetic() || c == '_')
+        {
+            out.push_str(new);
+            out.push('.');
+            rest = &rest[prefix.len()..];
+            previous = Some('.');
+            continue;
+        }
+        out.push(ch);
+        rest = &rest[ch.len_utf8()..];
+        previous = Some(ch);
+    }
+    out
+}
+
+fn render_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| format!("'{}'", path.display()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the name index it is currently absorbing up to.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, absorbed)) => {
+                    p = star + 1;
+                    n = absorbed + 1;
+                    backtrack = Some((star, n));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Splits a file into imports, includes, and body lines. Outside a `library`,
+/// `include` is only valid directly in a `sprite` or `stage` body; in a library
+/// it is only valid outside every block.
+fn parse_file(source: &str, source_path: &Path, library: bool) -> Result<ParsedFile> {
     let import_re = Regex::new(
-        r#"^\s*import\s+\[(?P<name>[^\]\r\n]+)\]\s+from\s+"(?P<path>[^"\r\n]+)"\s*(?:#.*)?$"#,
+        r#"^\s*import\s+(?:\[(?P<name>[^\]\r\n]+)\]|\*)\s+from\s+"(?P<path>[^"\r\n]+)"(?:\s+as\s+(?P<rename>[A-Za-z_][A-Za-z0-9_]*))?\s*(?:#.*)?$"#,
     )?;
     let sprite_re =
         Regex::new(r#"^\s*sprite\s+(?P<name>"[^"]+"|[A-Za-z_][A-Za-z0-9_]*)\s*(?:#.*)?$"#)?;
     let stage_re = Regex::new(r#"^\s*stage(?:\s+("[^"]+"|[A-Za-z_][A-Za-z0-9_]*))?\s*(?:#.*)?$"#)?;
+    let include_re = Regex::new(r#"^\s*include\s+"(?P<path>[^"\r\n]+)"\s*(?:#.*)?$"#)?;
 
     let mut imports = Vec::new();
+    let mut includes = Vec::new();
     let mut body_lines: Vec<(String, usize)> = Vec::new();
     let mut saw_non_import_code = false;
     let mut local_sprites: Vec<String> = Vec::new();
     let mut has_stage = false;
+    // Open blocks at the current line, counting the target itself.
+    let mut depth = 0usize;
 
     for (idx, raw_line) in source.lines().enumerate() {
         let line_no = idx + 1;
@@ -225,14 +822,33 @@ fn parse_file(source: &str, source_path: &Path) -> Result<ParsedFile> {
         };
         if let Some(caps) = import_re.captures(line) {
             if saw_non_import_code {
-                bail!(
-                    "Imports are only allowed at the top level. Invalid import in '{}' at line {}.",
-                    source_path.display(),
-                    line_no
-                );
+                return Err(import_error(
+                    source_path,
+                    line_no,
+                    format!(
+                        "Imports are only allowed at the top level. Invalid import in '{}' at line {}.",
+                        source_path.display(),
+                        line_no
+                    ),
+                ));
+            }
+            let rename = caps.name("rename").map(|name| name.as_str().to_string());
+            if rename.is_some() && caps.name("name").is_none() {
+                return Err(import_error(
+                    source_path,
+                    line_no,
+                    format!(
+                        "'as' can only rename a single named import. Invalid import in '{}' at line {}.",
+                        source_path.display(),
+                        line_no
+                    ),
+                ));
             }
             imports.push(ImportSpec {
-                sprite_name: caps["name"].trim().to_string(),
+                sprite_name: caps
+                    .name("name")
+                    .map(|name| name.as_str().trim().to_string()),
+                rename,
                 relative_path: caps["path"].trim().to_string(),
                 line: line_no,
             });
@@ -242,11 +858,45 @@ fn parse_file(source: &str, source_path: &Path) -> Result<ParsedFile> {
         if !is_blank_or_comment(line) {
             saw_non_import_code = true;
         }
+        if let Some(caps) = include_re.captures(line) {
+            if !library && depth == 0 {
+                return Err(import_error(
+                    source_path,
+                    line_no,
+                    format!(
+                        "'include' is only allowed inside a sprite or stage body. Invalid include in '{}' at line {}.",
+                        source_path.display(),
+                        line_no
+                    ),
+                ));
+            }
+            if depth > usize::from(!library) {
+                return Err(import_error(
+                    source_path,
+                    line_no,
+                    format!(
+                        "'include' is not allowed inside a script, procedure, or other block. Invalid include in '{}' at line {}.",
+                        source_path.display(),
+                        line_no
+                    ),
+                ));
+            }
+            includes.push(IncludeSpec {
+                relative_path: caps["path"].trim().to_string(),
+                line: line_no,
+                at: body_lines.len(),
+            });
+            continue;
+        }
         if let Some(caps) = sprite_re.captures(line) {
             let raw_name = caps["name"].trim();
             local_sprites.push(unquote(raw_name));
+            depth = 1;
         } else if stage_re.is_match(line) {
             has_stage = true;
+            depth = 1;
+        } else {
+            depth = nested_depth(line, depth);
         }
 
         body_lines.push((raw_line.to_string(), line_no));
@@ -254,12 +904,29 @@ fn parse_file(source: &str, source_path: &Path) -> Result<ParsedFile> {
 
     Ok(ParsedFile {
         imports,
+        includes,
         body_lines,
         local_sprites,
         has_stage,
     })
 }
 
+/// The block depth after `line`: hats, definitions, and C blocks open a block
+/// and `end` closes one. `else` (and `else if`) continues the open `if`.
+fn nested_depth(line: &str, depth: usize) -> usize {
+    let word = line
+        .trim_start()
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    match word.as_str() {
+        "end" => depth.saturating_sub(1),
+        "when" | "define" | "reporter" | "if" | "repeat" | "forever" | "while" | "for" => depth + 1,
+        _ => depth,
+    }
+}
+
 fn validate_import_target(
     spec: &ImportSpec,
     source_path: &Path,
@@ -268,38 +935,54 @@ fn validate_import_target(
     local_has_stage: bool,
 ) -> Result<()> {
     if local_sprites.is_empty() {
-        bail!(
-            "Imported file '{}' defines zero sprites; expected exactly one (imported from '{}', line {}).",
-            imported_path.display(),
-            source_path.display(),
-            spec.line
-        );
+        return Err(import_error(
+            source_path,
+            spec.line,
+            format!(
+                "Imported file '{}' defines zero sprites; expected exactly one (imported from '{}', line {}).",
+                imported_path.display(),
+                source_path.display(),
+                spec.line
+            ),
+        ));
     }
     if local_sprites.len() > 1 {
-        bail!(
-            "Imported file '{}' defines more than one sprite; expected exactly one (imported from '{}', line {}).",
-            imported_path.display(),
-            source_path.display(),
-            spec.line
-        );
+        return Err(import_error(
+            source_path,
+            spec.line,
+            format!(
+                "Imported file '{}' defines more than one sprite; expected exactly one (imported from '{}', line {}).",
+                imported_path.display(),
+                source_path.display(),
+                spec.line
+            ),
+        ));
     }
     let actual = &local_sprites[0];
-    if actual != &spec.sprite_name {
-        bail!(
-            "Imported sprite name mismatch in '{}', line {}: expected '{}', file defines '{}'.",
-            source_path.display(),
+    if let Some(expected) = spec.sprite_name.as_ref().filter(|name| *name != actual) {
+        return Err(import_error(
+            source_path,
             spec.line,
-            spec.sprite_name,
-            actual
-        );
+            format!(
+                "Imported sprite name mismatch in '{}', line {}: expected '{}', file defines '{}'.",
+                source_path.display(),
+                spec.line,
+                expected,
+                actual
+            ),
+        ));
     }
     if local_has_stage {
-        bail!(
-            "Imported file '{}' must not define a stage (imported from '{}', line {}).",
-            imported_path.display(),
-            source_path.display(),
-            spec.line
-        );
+        return Err(import_error(
+            source_path,
+            spec.line,
+            format!(
+                "Imported file '{}' must not define a stage (imported from '{}', line {}).",
+                imported_path.display(),
+                source_path.display(),
+                spec.line
+            ),
+        ));
     }
     Ok(())
 }
@@ -327,3 +1010,571 @@ fn unquote(name: &str) -> String {
         name.to_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, text: &str) -> PathBuf {
+        let path = dir.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("dir should create");
+        }
+        fs::write(&path, text).expect("file should write");
+        path
+    }
+
+    fn sprite(name: &str) -> String {
+        format!(
+            "sprite {}\n  when flag clicked\n    say (\"hi\")\n  end\nend\n",
+            name
+        )
+    }
+
+    #[test]
+    fn directory_imports_merge_sorted_with_source_map() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let main = write(
+            dir.path(),
+            "main.sbtext",
+            "import * from \"sprites/\"\n\nstage\nend\n",
+        );
+        write(dir.path(), "sprites/b.sbtext", &sprite("Zed"));
+        write(dir.path(), "sprites/a.sbtext", &sprite("Amy"));
+        write(dir.path(), "sprites/notes.txt", "not source");
+
+        let merged = resolve_merged_source_with_map(&main, &[]).expect("imports should resolve");
+        let amy = merged.source.find("sprite Amy").expect("Amy merged");
+        let zed = merged.source.find("sprite Zed").expect("Zed merged");
+        assert!(amy < zed, "{}", merged.source);
+        assert!(!merged.source.contains("not source"));
+
+        let zed_line = merged.source[..zed].lines().count() + 1;
+        let origin = merged.map_position(zed_line, 1);
+        assert_eq!(
+            origin.file,
+            dir.path().join("sprites/b.sbtext").canonicalize().unwrap()
+        );
+        assert_eq!(origin.line, 1);
+        assert_eq!(
+            merged.import_dirs(),
+            [dir.path().join("sprites").canonicalize().unwrap()]
+        );
+    }
+
+    #[test]
+    fn glob_imports_match_file_names_only() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let main = write(
+            dir.path(),
+            "main.sbtext",
+            "import * from \"sprites/enemy_*.sbtext\"\nimport [Player] from \"sprites/player.sbtext\"\n",
+        );
+        write(dir.path(), "sprites/enemy_bat.sbtext", &sprite("Bat"));
+        write(dir.path(), "sprites/enemy_rat.sbtext", &sprite("Rat"));
+        write(dir.path(), "sprites/player.sbtext", &sprite("Player"));
+
+        let merged = resolve_merged_source_with_map(&main, &[]).expect("imports should resolve");
+        let sprites = merged
+            .source
+            .lines()
+            .filter(|line| line.starts_with("sprite "))
+            .collect::<Vec<_>>();
+        assert_eq!(sprites, ["sprite Bat", "sprite Rat", "sprite Player"]);
+
+        assert!(wildcard_match("*.sbtext", "a.sbtext"));
+        assert!(wildcard_match("e?emy_*_*.sbtext", "enemy_big_bat.sbtext"));
+        assert!(!wildcard_match("enemy_*.sbtext", "enemy_bat.sbtext.bak"));
+    }
+
+    #[test]
+    fn conflicting_globbed_sprites_name_both_files() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let main = write(dir.path(), "main.sbtext", "import * from \"sprites\"\n");
+        write(dir.path(), "sprites/one.sbtext", &sprite("Cat"));
+        write(dir.path(), "sprites/two.sbtext", &sprite("cat"));
+
+        let err = resolve_merged_source_with_map(&main, &[])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Sprite 'cat' is defined by both"), "{}", err);
+        assert!(
+            err.contains("one.sbtext") && err.contains("two.sbtext"),
+            "{}",
+            err
+        );
+
+        let empty = write(
+            dir.path(),
+            "empty.sbtext",
+            "import * from \"sprites/*.txt\"\n",
+        );
+        let err = resolve_merged_source_with_map(&empty, &[])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("matched no files"), "{}", err);
+    }
+
+    #[test]
+    fn include_dirs_are_searched_after_the_importing_directory() {
+        let project = tempfile::tempdir().expect("temp dir");
+        let library = tempfile::tempdir().expect("temp dir");
+        let main = write(
+            project.path(),
+            "main.sbtext",
+            "import [Hero] from \"hero.sbtext\"\nimport [Coin] from \"coin.sbtext\"\n",
+        );
+        write(project.path(), "hero.sbtext", &sprite("Hero"));
+        write(library.path(), "hero.sbtext", &sprite("Villain"));
+        write(
+            library.path(),
+            "coin.sbtext",
+            "sprite Coin\n  costume \"Spin\" \"art/coin.svg\"\n  sound \"missing.wav\"\nend\n",
+        );
+        write(library.path(), "art/coin.svg", "<svg/>");
+
+        let merged = resolve_merged_source_with_map(&main, &[library.path().to_path_buf()])
+            .expect("imports should resolve");
+        assert!(merged.source.contains("sprite Hero"), "{}", merged.source);
+        assert!(!merged.source.contains("Villain"), "{}", merged.source);
+        assert!(
+            merged
+                .source
+                .contains("  costume \"Spin\" \"art/coin.svg\"\n"),
+            "{}",
+            merged.source
+        );
+        let project = crate::parse_and_validate_project(&merged).expect("project should check");
+        let coin = project
+            .targets
+            .iter()
+            .find(|target| target.name == "Coin")
+            .expect("Coin target");
+        let library_dir = library.path().canonicalize().unwrap();
+        assert_eq!(
+            coin.costumes[0].origin_dir.as_deref(),
+            Some(library_dir.as_path())
+        );
+        assert_eq!(
+            coin.sounds[0].origin_dir.as_deref(),
+            Some(library_dir.as_path())
+        );
+
+        let err = resolve_merged_source_with_map(&main, &[])
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("Imported file does not exist: 'coin.sbtext'"),
+            "{}",
+            err
+        );
+        assert!(err.contains("Tried: '"), "{}", err);
+
+        let elsewhere = tempfile::tempdir().expect("temp dir");
+        assert!(resolve_merged_source_with_map(
+            &main,
+            &[elsewhere.path().to_path_buf(), library.path().to_path_buf()],
+        )
+        .is_ok());
+        let err = resolve_merged_source_with_map(&main, &[elsewhere.path().to_path_buf()])
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains(&elsewhere.path().join("coin.sbtext").display().to_string()),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn included_libraries_become_local_procedures_in_each_target() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let library = "## Squares a number.\ndefine square (n)\n  say ((n) * (n))\nend\n";
+        write(dir.path(), "lib/math.sbtext", library);
+        let main = write(
+            dir.path(),
+            "main.sbtext",
+            "sprite Cat\n  include \"lib/math.sbtext\"\n  when flag clicked\n    square (3)\n  end\nend\n\nsprite Dog\n  when flag clicked\n    square (4)\n  end\n  include \"lib/math.sbtext\" # shared\nend\n",
+        );
+
+        let merged = resolve_merged_source_with_map(&main, &[]).expect("includes should resolve");
+        assert_eq!(merged.source.matches("define square (n)").count(), 2);
+        assert!(!merged.source.contains("include"), "{}", merged.source);
+        let project = crate::parse_and_validate_project(&merged).expect("project should compile");
+        for target in &project.targets {
+            assert_eq!(target.procedures.len(), 1, "{}", target.name);
+            let pos = target.procedures[0].pos;
+            let origin = merged.map_position(pos.line, pos.column);
+            assert_eq!(
+                origin.file,
+                dir.path().join("lib/math.sbtext").canonicalize().unwrap()
+            );
+            assert_eq!(origin.line, 2);
+        }
+
+        let clash = write(
+            dir.path(),
+            "clash.sbtext",
+            "sprite Cat\n  define square (n)\n    say (n)\n  end\n  include \"lib/math.sbtext\"\nend\n",
+        );
+        let merged = resolve_merged_source_with_map(&clash, &[]).expect("includes should resolve");
+        let err = crate::parse_and_validate_project(&merged)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Procedure 'square'"), "{}", err);
+        assert!(err.contains("is already defined"), "{}", err);
+    }
+
+    #[test]
+    fn includes_must_sit_in_a_target_and_hold_no_targets() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        write(dir.path(), "lib/sprite.sbtext", &sprite("Oops"));
+        let top_level = write(dir.path(), "top.sbtext", "include \"lib/sprite.sbtext\"\n");
+        let err = resolve_merged_source_with_map(&top_level, &[])
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("only allowed inside a sprite or stage body"),
+            "{}",
+            err
+        );
+
+        write(
+            dir.path(),
+            "lib/moves.sbtext",
+            "define hop\n  change y by (10)\nend\n",
+        );
+        let between = write(
+            dir.path(),
+            "between.sbtext",
+            "sprite Cat\nend\ninclude \"lib/moves.sbtext\"\nsprite Dog\nend\n",
+        );
+        let err = resolve_merged_source_with_map(&between, &[]).unwrap_err();
+        let import = err.downcast_ref::<ImportError>().expect("import error");
+        assert_eq!(import.line, 3);
+        assert!(
+            err.to_string()
+                .contains("only allowed inside a sprite or stage body"),
+            "{}",
+            err
+        );
+
+        let in_script = write(
+            dir.path(),
+            "in_script.sbtext",
+            "sprite Cat\n  when flag clicked\n    if <(1) = (1)> then\n      say (1)\n    else\n      include \"lib/moves.sbtext\"\n    end\n  end\nend\n",
+        );
+        let err = resolve_merged_source_with_map(&in_script, &[]).unwrap_err();
+        let import = err.downcast_ref::<ImportError>().expect("import error");
+        assert_eq!(import.line, 6);
+        assert!(
+            err.to_string().contains("not allowed inside a script"),
+            "{}",
+            err
+        );
+
+        let after_script = write(
+            dir.path(),
+            "after_script.sbtext",
+            "sprite Cat\n  when flag clicked\n    repeat (2)\n      say (1)\n    end\n  end\n  include \"lib/moves.sbtext\"\nend\n",
+        );
+        resolve_merged_source_with_map(&after_script, &[]).expect("include between scripts");
+
+        let main = write(
+            dir.path(),
+            "main.sbtext",
+            "sprite Cat\n  include \"lib/sprite.sbtext\"\nend\n",
+        );
+        let err = resolve_merged_source_with_map(&main, &[])
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("must not contain imports, sprites, or a stage"),
+            "{}",
+            err
+        );
+
+        write(dir.path(), "lib/a.sbtext", "include \"b.sbtext\"\n");
+        write(dir.path(), "lib/b.sbtext", "include \"a.sbtext\"\n");
+        let looped = write(
+            dir.path(),
+            "loop.sbtext",
+            "sprite Cat\n  include \"lib/a.sbtext\"\nend\n",
+        );
+        let err = resolve_merged_source_with_map(&looped, &[])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Circular include detected"), "{}", err);
+    }
+
+    #[test]
+    fn assets_resolve_next_to_the_declaring_sprite_file() {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/nested_assets");
+        let bytes = crate::compile_entry_to_sb3_bytes(&fixture.join("main.sbtext"), false)
+            .expect("fixture should compile");
+        let archive = crate::sb3::read_sb3_bytes(&bytes).expect("sb3 should read");
+        // Each body.svg has its own fill; a root-level body.svg is a decoy.
+        for (sprite, fill) in [("Hero", "#3a7bd5"), ("Villain", "#c0392b")] {
+            let target = archive.project["targets"]
+                .as_array()
+                .expect("targets array")
+                .iter()
+                .find(|target| target["name"] == sprite)
+                .expect("sprite target");
+            let md5ext = target["costumes"][0]["md5ext"]
+                .as_str()
+                .expect("costume md5ext");
+            let svg = String::from_utf8_lossy(&archive.assets[md5ext]).into_owned();
+            assert!(svg.contains(fill), "{}: {}", sprite, svg);
+        }
+    }
+
+    #[test]
+    fn source_map_json_agrees_with_map_position() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        write(
+            dir.path(),
+            "lib/util.sbtext",
+            "define ping\n  say (\"ping\")\nend\n",
+        );
+        write(
+            dir.path(),
+            "cat.sbtext",
+            "sprite Cat\n  include \"lib/util.sbtext\"\n  when flag clicked\n    ping\n  end\nend\n",
+        );
+        let main = write(
+            dir.path(),
+            "main.sbtext",
+            "import [Cat] from \"cat.sbtext\"\n\nstage\nend\n",
+        );
+        let merged = resolve_merged_source_with_map(&main, &[]).expect("imports should resolve");
+        let segments = merged.segments();
+        let cat = dir.path().join("cat.sbtext").canonicalize().unwrap();
+        assert_eq!(
+            segments[0],
+            SourceSegment {
+                merged_start_line: 1,
+                file: cat.clone(),
+                original_start_line: 1,
+                length: 1,
+            }
+        );
+        assert_eq!(segments.len(), 4, "{:?}", segments);
+
+        let map = merged.source_map_json();
+        let lookup = |line: usize| {
+            map["segments"]
+                .as_array()
+                .expect("segments array")
+                .iter()
+                .find_map(|segment| {
+                    let start = segment["merged_start_line"].as_u64()? as usize;
+                    let length = segment["length"].as_u64()? as usize;
+                    (start..start + length).contains(&line).then(|| {
+                        let original = segment["original_start_line"].as_u64()? as usize;
+                        Some((
+                            PathBuf::from(segment["file"].as_str()?),
+                            original + line - start,
+                        ))
+                    })?
+                })
+                .expect("line is covered")
+        };
+        for line in 1..=merged.line_origins.len() {
+            let mapped = merged.map_position(line, 3);
+            assert_eq!(lookup(line), (mapped.file, mapped.line), "line {}", line);
+        }
+    }
+
+    #[test]
+    fn import_as_renames_references_in_included_libraries() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        write(
+            dir.path(),
+            "player.sbtext",
+            "sprite Player\n  var hp = 3\n  include \"moves.sbtext\"\nend\n",
+        );
+        write(
+            dir.path(),
+            "moves.sbtext",
+            "define heal\n  change [hp] by (1)\n  say (Player.hp)\nend\n",
+        );
+        let main = write(
+            dir.path(),
+            "main.sbtext",
+            "import [Player] from \"player.sbtext\" as Hero\n\nstage\nend\n",
+        );
+
+        let merged = resolve_merged_source_with_map(&main, &[]).expect("imports should resolve");
+        assert!(
+            merged.source.contains("  say (Hero.hp)\n"),
+            "{}",
+            merged.source
+        );
+        assert!(!merged.source.contains("Player"), "{}", merged.source);
+        crate::compile_entry_to_sb3_bytes(&main, false).expect("project should compile");
+    }
+
+    #[test]
+    fn import_as_renames_the_sprite_and_its_qualified_references() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let player = |greeting: &str| {
+            format!(
+                "sprite Player\n  var hp = 3\n  define hit (amount)\n    change [hp] by (amount)\n    say (\"{} Player.hp\") # Player.hp\n  end\n  when flag clicked\n    Player.hit (1)\n    say (Player.hp)\n  end\nend\n",
+                greeting
+            )
+        };
+        write(dir.path(), "vendor_a/player.sbtext", &player("a"));
+        write(dir.path(), "vendor_b/player.sbtext", &player("b"));
+        let main = write(
+            dir.path(),
+            "main.sbtext",
+            "import [Player] from \"vendor_a/player.sbtext\"\nimport [Player] from \"vendor_b/player.sbtext\" as Rival # renamed\n\nstage\n  var seen\n  when flag clicked\n    Rival.hit (5)\n    set [seen] to (Rival.hp)\n  end\nend\n",
+        );
+
+        let merged = resolve_merged_source_with_map(&main, &[]).expect("imports should resolve");
+        assert!(
+            merged.source.contains("sprite Rival\n"),
+            "{}",
+            merged.source
+        );
+        assert!(
+            merged
+                .source
+                .contains("    Rival.hit (1)\n    say (Rival.hp)\n"),
+            "{}",
+            merged.source
+        );
+        assert!(
+            merged
+                .source
+                .contains("say (\"b Player.hp\") # Player.hp\n"),
+            "{}",
+            merged.source
+        );
+        assert_eq!(merged.source.matches("sprite Player\n").count(), 1);
+
+        let bytes =
+            crate::compile_entry_to_sb3_bytes(&main, false).expect("project should compile");
+        let archive = crate::sb3::read_sb3_bytes(&bytes).expect("sb3 should read");
+        let stage = &archive.project["targets"][0];
+        assert!(
+            stage["broadcasts"]
+                .as_object()
+                .expect("broadcasts")
+                .values()
+                .any(|name| name == "__rpc__rival__hit"),
+            "{}",
+            stage["broadcasts"]
+        );
+
+        let clash = write(
+            dir.path(),
+            "clash.sbtext",
+            "import [Player] from \"vendor_a/player.sbtext\" as Rival\nimport [Player] from \"vendor_b/player.sbtext\" as rival\n",
+        );
+        let err = resolve_merged_source_with_map(&clash, &[])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Sprite 'rival' is defined by both"), "{}", err);
+
+        let glob = write(
+            dir.path(),
+            "glob.sbtext",
+            "import * from \"vendor_a/\" as Rival\n",
+        );
+        let err = resolve_merged_source_with_map(&glob, &[])
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("'as' can only rename a single named import"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn files_lists_every_merged_file_in_resolution_order() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        write(dir.path(), "lib/empty.sbtext", "");
+        write(dir.path(), "lib/util.sbtext", "include \"empty.sbtext\"\n");
+        write(
+            dir.path(),
+            "cat.sbtext",
+            "sprite Cat\n  include \"lib/util.sbtext\"\nend\n",
+        );
+        write(
+            dir.path(),
+            "dog.sbtext",
+            "sprite Dog\n  include \"lib/util.sbtext\"\nend\n",
+        );
+        let main = write(
+            dir.path(),
+            "main.sbtext",
+            "import [Dog] from \"dog.sbtext\"\nimport [Cat] from \"cat.sbtext\"\n",
+        );
+
+        let merged = resolve_merged_source_with_map(&main, &[]).expect("imports should resolve");
+        let root = dir.path().canonicalize().unwrap();
+        let expected = [
+            "main.sbtext",
+            "dog.sbtext",
+            "lib/util.sbtext",
+            "lib/empty.sbtext",
+            "cat.sbtext",
+        ]
+        .map(|name| root.join(name));
+        assert_eq!(merged.files(), expected);
+
+        let rebuilt = MergedSource::new(
+            merged.source.clone(),
+            merged.line_origins.clone(),
+            merged.entry_file().to_path_buf(),
+        );
+        // Without the resolver, only files that contributed lines are known.
+        assert_eq!(
+            rebuilt.files(),
+            [
+                expected[0].clone(),
+                expected[1].clone(),
+                expected[4].clone()
+            ]
+        );
+    }
+
+    #[test]
+    fn text_entry_resolves_imports_against_the_source_dir() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        write(dir.path(), "sprites/cat.sbtext", &sprite("Cat"));
+        let source = "import [Cat] from \"sprites/cat.sbtext\"\n\nstage\nend\n";
+
+        let merged = resolve_merged_source_from_text(source, dir.path(), &[])
+            .expect("imports should resolve");
+        let cat = dir
+            .path()
+            .canonicalize()
+            .unwrap()
+            .join("sprites/cat.sbtext");
+        assert_eq!(merged.entry_file(), Path::new(STDIN_NAME));
+        assert_eq!(merged.files(), std::slice::from_ref(&cat));
+        assert_eq!(merged.line_origins[0].file, cat);
+        let stage_line = merged
+            .source
+            .lines()
+            .position(|line| line == "stage")
+            .unwrap();
+        let position = merged.map_position(stage_line + 1, 1);
+        assert_eq!(
+            (position.file, position.line),
+            (PathBuf::from(STDIN_NAME), 3)
+        );
+
+        let err = resolve_merged_source_from_text(source, &dir.path().join("sprites"), &[])
+            .expect_err("import is relative to the source dir");
+        assert!(
+            err.to_string().contains("Imported file does not exist"),
+            "{}",
+            err
+        );
+    }
+}
@@ -9,6 +9,7 @@ pub enum TokenType {
     Ident,
     Number,
     String,
+    Color,
     Op,
     LParen,
     RParen,
@@ -16,6 +17,9 @@ pub enum TokenType {
     RBracket,
     Comma,
     Newline,
+    DocComment,
+    /// A balanced `{ ... }` JSON object, the payload of a `raw` statement.
+    RawJson,
     Eof,
 }
 
@@ -101,6 +105,20 @@ impl<'a> Lexer<'a> {
                 continue;
             }
             if ch == '#' {
+                // Colors only appear as wrapped inputs, so elsewhere `#facade`
+                // stays a comment.
+                if tokens.last().is_some_and(|t| t.typ == TokenType::LParen)
+                    && self.starts_color_literal()
+                {
+                    tokens.push(self.read_color());
+                    continue;
+                }
+                if self.peek_next() == '#'
+                    && tokens.last().is_none_or(|t| t.typ == TokenType::Newline)
+                {
+                    tokens.push(self.read_doc_comment());
+                    continue;
+                }
                 if self.starts_comment() {
                     self.skip_comment();
                     continue;
@@ -114,16 +132,30 @@ impl<'a> Lexer<'a> {
                 });
                 continue;
             }
+            if ch == '/' && self.peek_next() == '*' {
+                if self.skip_block_comment()? {
+                    tokens.push(Token {
+                        typ: TokenType::Newline,
+                        value: "\n".to_string(),
+                        pos: self.pos(),
+                    });
+                }
+                continue;
+            }
             if ch == '"' {
                 tokens.push(self.read_string()?);
                 continue;
             }
+            if ch == '{' {
+                tokens.push(self.read_raw_json()?);
+                continue;
+            }
             if ch.is_ascii_digit() {
-                tokens.push(self.read_number());
+                tokens.push(self.read_number()?);
                 continue;
             }
             if ch == '.' && self.peek_next().is_ascii_digit() {
-                tokens.push(self.read_number_starting_with_dot());
+                tokens.push(self.read_number_starting_with_dot()?);
                 continue;
             }
             if ch.is_ascii_alphabetic() || ch == '_' {
@@ -172,7 +204,7 @@ impl<'a> Lexer<'a> {
                         pos,
                     });
                 }
-                '+' | '-' | '*' | '/' | '%' => {
+                '+' | '-' | '*' | '/' | '%' | '?' => {
                     self.advance();
                     tokens.push(Token {
                         typ: TokenType::Op,
@@ -196,7 +228,7 @@ impl<'a> Lexer<'a> {
             value: String::new(),
             pos: self.pos(),
         });
-        if let Some(cb) = progress.as_deref_mut() {
+        if let Some(cb) = progress {
             for pct in (last_percent + 1)..=100 {
                 cb(pct);
             }
@@ -244,9 +276,7 @@ impl<'a> Lexer<'a> {
         text.push(self.advance());
         while !self.at_end() {
             let ch = self.peek();
-            if ch.is_ascii_alphanumeric() || ch == '_' || ch == '?' {
-                text.push(self.advance());
-            } else if ch == '.' {
+            if ch.is_ascii_alphanumeric() || ch == '_' || ch == '?' || ch == '.' {
                 text.push(self.advance());
             } else {
                 break;
@@ -268,7 +298,7 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn read_number(&mut self) -> Token {
+    fn read_number(&mut self) -> Result<Token, LexerError> {
         let pos = self.pos();
         let mut text = String::new();
         text.push(self.advance());
@@ -291,11 +321,11 @@ impl<'a> Lexer<'a> {
                         break;
                     }
                 }
-                return Token {
+                return Ok(Token {
                     typ: TokenType::Number,
                     value: text,
                     pos,
-                };
+                });
             }
         }
 
@@ -313,25 +343,67 @@ impl<'a> Lexer<'a> {
             }
             break;
         }
-        Token {
+        self.read_exponent(&mut text, pos)?;
+        Ok(Token {
             typ: TokenType::Number,
             value: text,
             pos,
-        }
+        })
     }
 
-    fn read_number_starting_with_dot(&mut self) -> Token {
+    fn read_number_starting_with_dot(&mut self) -> Result<Token, LexerError> {
         let pos = self.pos();
         let mut text = String::from(".");
         self.advance();
         while !self.at_end() && self.peek().is_ascii_digit() {
             text.push(self.advance());
         }
-        Token {
+        self.read_exponent(&mut text, pos)?;
+        Ok(Token {
             typ: TokenType::Number,
             value: text,
             pos,
+        })
+    }
+
+    /// Appends an `e`/`E` exponent to a number. An `e` followed by letters is left for the
+    /// identifier lexer, but `1e` or `1e+` on their own are malformed numbers.
+    fn read_exponent(&mut self, text: &mut String, pos: Position) -> Result<(), LexerError> {
+        if !matches!(self.peek(), 'e' | 'E') {
+            return Ok(());
+        }
+        let after_e = self.peek_next();
+        let has_sign = matches!(after_e, '+' | '-');
+        let first_digit = if has_sign {
+            self.chars.get(self.index + 2).copied().unwrap_or('\0')
+        } else {
+            after_e
+        };
+        if !first_digit.is_ascii_digit() {
+            if !has_sign && (after_e.is_ascii_alphabetic() || after_e == '_') {
+                return Ok(());
+            }
+            let mut malformed = text.clone();
+            malformed.push(self.peek());
+            if has_sign {
+                malformed.push(after_e);
+            }
+            return Err(LexerError {
+                message: format!(
+                    "Malformed number '{}': expected digits after the exponent",
+                    malformed
+                ),
+                pos,
+            });
+        }
+        text.push(self.advance());
+        if has_sign {
+            text.push(self.advance());
+        }
+        while !self.at_end() && self.peek().is_ascii_digit() {
+            text.push(self.advance());
         }
+        Ok(())
     }
 
     fn read_string(&mut self) -> Result<Token, LexerError> {
@@ -351,6 +423,7 @@ impl<'a> Lexer<'a> {
                 if self.at_end() {
                     break;
                 }
+                let escape_pos = Position::new(self.line, self.column - 1);
                 let esc = self.advance();
                 let mapped = match esc {
                     '"' => '"',
@@ -358,7 +431,15 @@ impl<'a> Lexer<'a> {
                     'n' => '\n',
                     'r' => '\r',
                     't' => '\t',
-                    _ => esc,
+                    _ => {
+                        return Err(LexerError {
+                            message: format!(
+                                "Unknown escape sequence '\\{}' in string literal",
+                                esc
+                            ),
+                            pos: escape_pos,
+                        });
+                    }
                 };
                 out.push(mapped);
                 continue;
@@ -377,12 +458,133 @@ impl<'a> Lexer<'a> {
         })
     }
 
+    /// Reads up to the matching `}`, skipping braces inside JSON strings. The JSON
+    /// itself is checked by the parser.
+    fn read_raw_json(&mut self) -> Result<Token, LexerError> {
+        let pos = self.pos();
+        let mut value = String::new();
+        let mut depth = 0usize;
+        let mut in_string = false;
+        while !self.at_end() {
+            let ch = self.advance();
+            value.push(ch);
+            if in_string {
+                if ch == '\\' && !self.at_end() {
+                    value.push(self.advance());
+                } else if ch == '"' {
+                    in_string = false;
+                }
+                continue;
+            }
+            match ch {
+                '"' => in_string = true,
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(Token {
+                            typ: TokenType::RawJson,
+                            value,
+                            pos,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(LexerError {
+            message: "Unterminated raw block JSON".to_string(),
+            pos,
+        })
+    }
+
+    /// `##` at the start of a line is a doc comment kept for the next script or procedure.
+    fn read_doc_comment(&mut self) -> Token {
+        let pos = self.pos();
+        self.advance();
+        self.advance();
+        if self.peek() == ' ' {
+            self.advance();
+        }
+        let mut value = String::new();
+        while !self.at_end() && self.peek() != '\n' {
+            value.push(self.advance());
+        }
+        Token {
+            typ: TokenType::DocComment,
+            value: value.trim_end().to_string(),
+            pos,
+        }
+    }
+
+    /// Skips a `/* ... */` comment and reports whether it spanned a line break, so the
+    /// caller can keep the statement separator that the comment swallowed.
+    fn skip_block_comment(&mut self) -> Result<bool, LexerError> {
+        let start = self.pos();
+        self.advance();
+        self.advance();
+        let mut spans_lines = false;
+        while !self.at_end() {
+            let ch = self.peek();
+            if ch == '*' && self.peek_next() == '/' {
+                self.advance();
+                self.advance();
+                return Ok(spans_lines);
+            }
+            if ch == '/' && self.peek_next() == '*' {
+                return Err(LexerError {
+                    message: "Nested block comments are not supported".to_string(),
+                    pos: self.pos(),
+                });
+            }
+            if ch == '\n' {
+                spans_lines = true;
+            }
+            self.advance();
+        }
+        Err(LexerError {
+            message: "Unterminated block comment".to_string(),
+            pos: start,
+        })
+    }
+
     fn skip_comment(&mut self) {
         while !self.at_end() && self.peek() != '\n' {
             self.advance();
         }
     }
 
+    /// `#rrggbb` directly followed by a non-identifier character is a color literal.
+    fn starts_color_literal(&self) -> bool {
+        let hex_end = self.index + 7;
+        if hex_end > self.chars.len() {
+            return false;
+        }
+        if !self.chars[self.index + 1..hex_end]
+            .iter()
+            .all(|c| c.is_ascii_hexdigit())
+        {
+            return false;
+        }
+        match self.chars.get(hex_end) {
+            Some(c) => !(c.is_ascii_alphanumeric() || *c == '_'),
+            None => true,
+        }
+    }
+
+    fn read_color(&mut self) -> Token {
+        let pos = self.pos();
+        let mut value = String::new();
+        for _ in 0..7 {
+            value.push(self.advance());
+        }
+        Token {
+            typ: TokenType::Color,
+            value: value.to_ascii_lowercase(),
+            pos,
+        }
+    }
+
     fn starts_comment(&self) -> bool {
         let next = self.peek_next();
         !matches!(next, ')' | ']' | '>' | ',' | '=')
@@ -559,3 +761,116 @@ fn is_ignorable_format_char(ch: char) -> bool {
             | '\u{2060}' // word joiner
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(source: &str) -> Result<Vec<Token>, LexerError> {
+        Lexer::new(source).tokenize()
+    }
+
+    #[test]
+    fn block_comments_keep_positions() {
+        let tokens = lex("say /* one\ntwo */ (1)\nmove (2 /* inline */ ) steps").unwrap();
+        let values = tokens
+            .iter()
+            .filter(|t| t.typ != TokenType::Eof)
+            .map(|t| t.value.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            ["say", "\n", "(", "1", ")", "\n", "move", "(", "2", ")", "steps"]
+        );
+        let paren = &tokens[2];
+        assert_eq!((paren.pos.line, paren.pos.column), (2, 8));
+        let steps = tokens.iter().find(|t| t.value == "steps").unwrap();
+        assert_eq!((steps.pos.line, steps.pos.column), (3, 24));
+    }
+
+    #[test]
+    fn colors_are_lexed_only_inside_parentheses() {
+        let tokens = lex("#facade of the house\nsay (#FACADE)\nmove (1) #decade").unwrap();
+        let values = tokens
+            .iter()
+            .filter(|t| t.typ != TokenType::Eof)
+            .map(|t| (t.typ.clone(), t.value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(values[0], (TokenType::Newline, "\n"));
+        assert_eq!(values[3], (TokenType::Color, "#facade"));
+        assert!(!values.iter().any(|(_, value)| *value == "#decade"));
+    }
+
+    #[test]
+    fn raw_json_is_one_token() {
+        let tokens =
+            lex("raw {\"opcode\": \"x_y\", \"fields\": {\"T\": [\"}\\\"\"]}}\nmove").unwrap();
+        assert_eq!(tokens[1].typ, TokenType::RawJson);
+        assert_eq!(
+            tokens[1].value,
+            "{\"opcode\": \"x_y\", \"fields\": {\"T\": [\"}\\\"\"]}}"
+        );
+        assert_eq!(tokens[3].value, "move");
+
+        let err = lex("raw {\"opcode\": \"x_y\"").unwrap_err();
+        assert_eq!(err.message, "Unterminated raw block JSON");
+        assert_eq!((err.pos.line, err.pos.column), (1, 5));
+    }
+
+    #[test]
+    fn block_comment_errors_report_positions() {
+        let err = lex("say (1)\n  /* never closed\nmove").unwrap_err();
+        assert_eq!(err.message, "Unterminated block comment");
+        assert_eq!((err.pos.line, err.pos.column), (2, 3));
+
+        let err = lex("/* outer /* inner */ */").unwrap_err();
+        assert_eq!(err.message, "Nested block comments are not supported");
+        assert_eq!((err.pos.line, err.pos.column), (1, 10));
+    }
+
+    #[test]
+    fn string_escapes() {
+        let tokens = lex(r#"say ("He said \"hi\"\\path\n\tok")"#).unwrap();
+        assert_eq!(tokens[2].typ, TokenType::String);
+        assert_eq!(tokens[2].value, "He said \"hi\"\\path\n\tok");
+
+        let err = lex(r#"say ("bad \q escape")"#).unwrap_err();
+        assert_eq!(
+            err.message,
+            "Unknown escape sequence '\\q' in string literal"
+        );
+        assert_eq!((err.pos.line, err.pos.column), (1, 11));
+    }
+
+    #[test]
+    fn number_exponents_and_leading_dots() {
+        let tokens = lex("1e6 2.5e-3 .5 .5E+2 3eggs").unwrap();
+        let values = tokens
+            .iter()
+            .filter(|t| t.typ != TokenType::Eof)
+            .map(|t| (t.typ.clone(), t.value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            [
+                (TokenType::Number, "1e6"),
+                (TokenType::Number, "2.5e-3"),
+                (TokenType::Number, ".5"),
+                (TokenType::Number, ".5E+2"),
+                (TokenType::Number, "3"),
+                (TokenType::Ident, "eggs"),
+            ]
+        );
+
+        for (source, malformed) in [("1e", "1e"), ("say (1e+)", "1e+"), ("2.5E-x", "2.5E-")] {
+            let err = lex(source).unwrap_err();
+            assert_eq!(
+                err.message,
+                format!(
+                    "Malformed number '{}': expected digits after the exponent",
+                    malformed
+                )
+            );
+        }
+    }
+}
@@ -1,9 +1,16 @@
+pub mod assets;
 pub mod ast;
+pub mod build;
 pub mod codegen;
+pub mod diagnostics;
+pub mod diff;
+pub mod format;
 pub mod imports;
 pub mod lexer;
+mod music;
 pub mod obfuscator;
 pub mod parser;
+mod rpc;
 pub mod sb3;
 pub mod sbtc;
 pub mod semantic;
@@ -14,121 +21,441 @@ pub mod cli;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod python_backend;
 
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(all(feature = "watch", not(target_arch = "wasm32")))]
+pub mod watch;
+
 pub mod decompile;
+pub mod decompile_sb2;
 
 use anyhow::Result;
 #[cfg(not(target_arch = "wasm32"))]
-use cli::{Command, CompileArgs, InspectArgs, ObfuscateArgs};
+use cli::{Command, CompileArgs, DecompileArgs, DiffArgs, FmtArgs, InspectArgs, ObfuscateArgs};
 use codegen::CodegenOptions;
-use imports::{resolve_merged_source_with_map, MergedSource};
+use diagnostics::{Diagnostic, Note, Phase, Severity};
+use imports::{resolve_merged_source_with_map, ImportError, MergedSource};
 use lexer::{Lexer, TokenType};
 use parser::Parser as SbParser;
-use semantic::{
-    analyze as semantic_analyze, analyze_with_options as semantic_analyze_with_options,
-    SemanticOptions, SemanticReport,
-};
+use semantic::{analyze_all as semantic_analyze_all, SemanticOptions, SemanticReport};
+use std::collections::HashMap;
 #[cfg(not(target_arch = "wasm32"))]
-use std::io::{self, IsTerminal, Write};
+use std::io::{self, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
 
 #[cfg(all(target_arch = "wasm32", feature = "wasm-bindings"))]
 pub mod wasm;
 
+/// What went wrong, as far as a caller deciding how to react is concerned. The
+/// CLI exits with [`ErrorKind::exit_code`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Missing, conflicting, or unsupported arguments.
+    Usage,
+    /// The input could not be read as a program: lex, parse, and import errors,
+    /// or a malformed `.sbtc`/`.sb3`.
+    Syntax,
+    /// The program parsed but failed semantic checks.
+    Semantic,
+    /// Building the output failed, e.g. a missing or invalid asset.
+    Codegen,
+    /// Reading an input or writing an output file failed.
+    Io,
+    /// `diff` found semantic differences between two projects, or `fmt --check`
+    /// found unformatted files.
+    Differs,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Usage => 1,
+            ErrorKind::Syntax => 2,
+            ErrorKind::Semantic => 3,
+            ErrorKind::Codegen => 4,
+            ErrorKind::Io => 5,
+            ErrorKind::Differs => 6,
+        }
+    }
+
+    /// The kind `error` was tagged with. Untagged errors count as I/O errors
+    /// when an `io::Error` caused them and as codegen errors otherwise.
+    pub fn of(error: &anyhow::Error) -> ErrorKind {
+        if let Some(tagged) = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<KindError>())
+        {
+            return tagged.kind;
+        }
+        if error.chain().any(|cause| cause.is::<std::io::Error>()) {
+            ErrorKind::Io
+        } else {
+            ErrorKind::Codegen
+        }
+    }
+}
+
+/// An error tagged with its [`ErrorKind`]. Displays as the wrapped error, so
+/// tagging never changes a message.
+#[derive(Debug)]
+struct KindError {
+    kind: ErrorKind,
+    error: anyhow::Error,
+}
+
+impl std::fmt::Display for KindError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for KindError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+trait WithErrorKind<T> {
+    /// Tags the error with `kind`, unless it is already tagged or was caused by
+    /// an `io::Error` (which is always [`ErrorKind::Io`]).
+    fn kind(self, kind: ErrorKind) -> Result<T>;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<T, E: Into<anyhow::Error>> WithErrorKind<T> for std::result::Result<T, E> {
+    fn kind(self, kind: ErrorKind) -> Result<T> {
+        self.map_err(|error| {
+            let error = error.into();
+            if error.chain().any(|cause| cause.is::<KindError>()) {
+                return error;
+            }
+            let kind = if error.chain().any(|cause| cause.is::<std::io::Error>()) {
+                ErrorKind::Io
+            } else {
+                kind
+            };
+            KindError { kind, error }.into()
+        })
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 pub fn run_cli(args: &cli::Args) -> Result<()> {
-    if let Some(command) = &args.command {
-        return match command {
-            Command::Obfuscate(command_args) => run_obfuscate_cli(command_args),
-            Command::Inspect(command_args) => run_inspect_cli(command_args),
+    let command = args.command().kind(ErrorKind::Usage)?;
+    if args.is_legacy() {
+        let replacement = match command {
+            Command::Decompile(_) => "sbtext-rs decompile INPUT [OUTPUT]",
+            _ => "sbtext-rs compile INPUT [OUTPUT]",
         };
+        eprintln!(
+            "{} 'sbtext-rs INPUT [OUTPUT]' without a subcommand is deprecated and will be removed in the next release; use '{}'.",
+            diagnostics::prefix(Severity::Warning, diagnostics::stderr_color()),
+            replacement
+        );
     }
 
-    run_compile_cli(&args.compile)
+    let settings = ProgressSettings {
+        mode: if args.quiet {
+            ProgressMode::Quiet
+        } else if args.no_progress {
+            ProgressMode::Lines
+        } else {
+            ProgressMode::Bar
+        },
+        timings: args.timings,
+    };
+    match command {
+        Command::Compile(command_args) => run_compile_cli(&command_args, settings),
+        Command::Check(command_args) => run_compile_cli(&command_args.into(), settings),
+        Command::Decompile(command_args) => run_decompile_cli(&command_args, settings),
+        Command::Fmt(command_args) => run_fmt_cli(&command_args),
+        Command::Obfuscate(command_args) => run_obfuscate_cli(&command_args),
+        Command::Inspect(command_args) => run_inspect_cli(&command_args),
+        Command::Diff(command_args) => run_diff_cli(&command_args),
+    }
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-fn run_compile_cli(args: &CompileArgs) -> Result<()> {
-    let input_arg = args.input.as_ref().ok_or_else(|| {
-        anyhow::anyhow!(
-            "Missing INPUT. Use 'sbtext-rs <INPUT> [OUTPUT]' for compile/decompile, or 'sbtext-rs inspect <INPUT.sb3>' / 'sbtext-rs obfuscate <INPUT.sb3> -o <OUTPUT.sb3>'."
-        )
-    })?;
-    if args.decompile {
-        if args.python_backend {
-            anyhow::bail!("--python-backend cannot be used with --decompile.");
-        }
-        if args.sprite_name.is_some() {
-            anyhow::bail!("--sprite-name cannot be used with --decompile.");
-        }
-        if args.emit_merged.is_some() {
-            anyhow::bail!("--emit-merged cannot be used with --decompile.");
+fn run_decompile_cli(args: &DecompileArgs, settings: ProgressSettings) -> Result<()> {
+    let input_arg = args.input.as_path();
+    let output_to_stdout = args.output.as_deref() == Some(Path::new("-"));
+    if output_to_stdout && args.split_sprites {
+        return Err(anyhow::anyhow!(
+            "--split-sprites cannot write to stdout ('-')."
+        ))
+        .kind(ErrorKind::Usage);
+    }
+    if is_stdin_arg(input_arg) && args.output.is_none() {
+        return Err(anyhow::anyhow!(
+            "Decompiling from stdin ('-') requires OUTPUT."
+        ))
+        .kind(ErrorKind::Usage);
+    }
+    let options = decompile::DecompileOptions {
+        split_sprites: args.split_sprites,
+        keep_asset_hashes: args.keep_asset_hashes,
+        keep_remote_calls: args.keep_remote_calls,
+    };
+    if output_to_stdout {
+        let (text, report) = if is_stdin_arg(input_arg) {
+            decompile::decompile_sb3_bytes_to_string(&read_stdin_bytes()?, options)
+                .kind(ErrorKind::Syntax)?
+        } else {
+            let input = canonicalize_file(input_arg).kind(ErrorKind::Io)?;
+            decompile::decompile_sb3_to_string(&input, options).kind(ErrorKind::Syntax)?
+        };
+        io::stdout().write_all(text.as_bytes())?;
+        print_decompile_warnings(&report);
+        if settings.mode != ProgressMode::Quiet {
+            eprintln!("{}", report.summary());
         }
-        if args.emit_sbtc.is_some() {
-            anyhow::bail!("--emit-sbtc cannot be used with --decompile.");
+        return Ok(());
+    }
+    let mut progress = CliProgress::new("Decompile", settings);
+    progress.emit("Resolving input path", 1, 1);
+    let result = {
+        let mut decomp_stage_cb = |step: usize, total: usize, label: &str| {
+            progress.emit(label, step, total);
+        };
+        match args.output.as_deref() {
+            Some(output) if is_stdin_arg(input_arg) => {
+                decompile::decompile_sb3_bytes_with_progress(
+                    &read_stdin_bytes()?,
+                    output,
+                    options,
+                    Some(&mut decomp_stage_cb),
+                )
+            }
+            output => decompile::decompile_sb3_with_progress(
+                &canonicalize_file(input_arg).kind(ErrorKind::Io)?,
+                output,
+                options,
+                Some(&mut decomp_stage_cb),
+            ),
         }
-        if args.compile_sbtc {
-            anyhow::bail!("--compile-sbtc cannot be used with --decompile.");
+    };
+    progress.finish();
+    let report = result.kind(ErrorKind::Syntax)?;
+    print_decompile_warnings(&report);
+    if settings.mode != ProgressMode::Quiet {
+        eprintln!("{}", report.summary());
+    }
+    progress.print_timings();
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn print_decompile_warnings(report: &decompile::DecompileReport) {
+    for warning in &report.warnings {
+        eprintln!(
+            "{} {}",
+            diagnostics::prefix(Severity::Warning, diagnostics::stderr_color()),
+            warning
+        );
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn run_compile_cli(args: &CompileArgs, settings: ProgressSettings) -> Result<()> {
+    let input_arg = check_compile_args(args).kind(ErrorKind::Usage)?;
+    let input = if is_stdin_arg(input_arg) {
+        input_arg.to_path_buf()
+    } else {
+        canonicalize_file(input_arg).kind(ErrorKind::Io)?
+    };
+    let default_output = args.output.is_none() && !args.no_output;
+    let args = &with_output_path(args, input_arg, &input).kind(ErrorKind::Usage)?;
+    if args.watch {
+        return run_watch_cli(args, &input);
+    }
+
+    let mut progress = CliProgress::new("Compile", settings);
+    progress.emit("Resolving input path", 1, 1);
+    let stats = compile_input(args, &input, &mut progress, &mut Vec::new())?;
+    progress.emit("Compile complete", 1, 1);
+    progress.finish();
+    if let Some(output) = args
+        .output
+        .as_ref()
+        .filter(|_| default_output && settings.mode != ProgressMode::Quiet)
+    {
+        eprintln!("Wrote '{}'", output.display());
+    }
+    if let Some(stats) = stats.filter(|_| args.stats) {
+        print!("{}", stats.render_table());
+    }
+    progress.print_timings();
+    Ok(())
+}
+
+/// `args` with OUTPUT filled in when it was left out: `<input stem>.sb3` beside
+/// the input, unless `--no-output` is set. Warns about an OUTPUT that is
+/// neither `.sb3` nor `.sprite3`.
+#[cfg(not(target_arch = "wasm32"))]
+fn with_output_path(args: &CompileArgs, input_arg: &Path, input: &Path) -> Result<CompileArgs> {
+    let output = match &args.output {
+        Some(output) => {
+            let is_sb3 = output
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| e.eq_ignore_ascii_case("sb3"));
+            if !is_sb3 && !is_sprite3_path(output) {
+                eprintln!(
+                    "{} OUTPUT '{}' does not end in .sb3 or .sprite3; it is still written as an .sb3 archive.",
+                    diagnostics::prefix(Severity::Warning, diagnostics::stderr_color()),
+                    output.display()
+                );
+            }
+            Some(output.clone())
         }
-        if args.allow_unknown_procedures {
-            anyhow::bail!("--allow-unknown-procedures cannot be used with --decompile.");
+        None if args.no_output => None,
+        None => {
+            let output = input_arg.with_extension("sb3");
+            if output.canonicalize().ok().as_deref() == Some(input) {
+                anyhow::bail!("INPUT already ends in .sb3; pass an OUTPUT path or --no-output.");
+            }
+            Some(output)
         }
-        let mut progress = CliProgress::new("Decompile");
-        progress.emit("Resolving input path", 1, 1);
-        let input = canonicalize_file(input_arg)?;
-        let result = {
-            let mut decomp_stage_cb = |step: usize, total: usize, label: &str| {
-                progress.emit(label, step, total);
-            };
-            decompile::decompile_sb3_with_progress(
-                &input,
-                args.output.as_deref(),
-                args.split_sprites,
-                Some(&mut decomp_stage_cb),
-            )
-        };
-        progress.finish();
-        return result;
-    }
+    };
+    Ok(CompileArgs {
+        output,
+        ..args.clone()
+    })
+}
 
-    if args.split_sprites {
-        anyhow::bail!("--split-sprites requires --decompile.");
+/// Rejects flag combinations that depend on the INPUT and OUTPUT paths, which
+/// clap cannot express, before any file is read. Returns INPUT.
+#[cfg(not(target_arch = "wasm32"))]
+fn check_compile_args(args: &CompileArgs) -> Result<&Path> {
+    let input_arg = args.input.as_path();
+    if args.watch && !cfg!(feature = "watch") {
+        anyhow::bail!(
+            "--watch requires a build with the 'watch' feature (cargo build --features watch)."
+        );
+    }
+    if args.emit_ast.is_some() && !cfg!(feature = "serde") {
+        anyhow::bail!(
+            "--emit-ast requires a build with the 'serde' feature (cargo build --features serde)."
+        );
     }
     let output_is_sprite3 = args.output.as_deref().map(is_sprite3_path).unwrap_or(false);
     if args.sprite_name.is_some() && !output_is_sprite3 {
         anyhow::bail!("--sprite-name is only supported when OUTPUT is .sprite3.");
     }
-    if args.python_backend && args.allow_unknown_procedures {
-        anyhow::bail!(
-            "--allow-unknown-procedures is only supported by the native Rust backend (remove --python-backend)."
-        );
-    }
     if args.python_backend && output_is_sprite3 {
         anyhow::bail!("--python-backend is not supported with .sprite3 output.");
     }
+    if args.stats && output_is_sprite3 {
+        anyhow::bail!("--stats requires an .sb3 OUTPUT.");
+    }
 
-    let mut progress = CliProgress::new("Compile");
-    progress.emit("Resolving input path", 1, 1);
-    let input = canonicalize_file(input_arg)?;
-    let input_is_sbtc = args.compile_sbtc || is_sbtc_path(&input);
-
-    if args.python_backend && input_is_sbtc {
+    if is_stdin_arg(input_arg) {
+        if args.output.is_none() && !args.no_output {
+            anyhow::bail!("Compiling from stdin ('-') requires OUTPUT or --no-output.");
+        }
+        if args.compile_sbtc {
+            anyhow::bail!("--compile-sbtc cannot read from stdin ('-').");
+        }
+        if args.python_backend {
+            anyhow::bail!("--python-backend cannot read from stdin ('-').");
+        }
+        if args.watch {
+            anyhow::bail!("--watch cannot read from stdin ('-').");
+        }
+    } else if args.source_dir.is_some() {
+        anyhow::bail!("--source-dir only applies when INPUT is '-' (stdin).");
+    }
+    if args.python_backend && is_sbtc_path(input_arg) {
         anyhow::bail!("--python-backend is not supported with .sbtc input.");
     }
+    Ok(input_arg)
+}
+
+#[cfg(all(feature = "watch", not(target_arch = "wasm32")))]
+fn run_watch_cli(args: &CompileArgs, input: &Path) -> Result<()> {
+    let summary = match &args.output {
+        Some(output) => format!(
+            "Compiled '{}' -> '{}'",
+            diagnostics::display_path(input),
+            output.display()
+        ),
+        None => format!("Checked '{}'", diagnostics::display_path(input)),
+    };
+    watch::watch(input, |deps| {
+        let mut progress = CliProgress::new(
+            "Compile",
+            ProgressSettings {
+                mode: ProgressMode::Quiet,
+                timings: false,
+            },
+        );
+        compile_input(args, input, &mut progress, deps)?;
+        Ok(summary.clone())
+    })
+}
+
+#[cfg(all(not(feature = "watch"), not(target_arch = "wasm32")))]
+fn run_watch_cli(_args: &CompileArgs, _input: &Path) -> Result<()> {
+    unreachable!("check_compile_args rejects --watch without the 'watch' feature")
+}
+
+/// Compiles `input` as `args` describe. `deps` receives the source and asset
+/// files the build reads as soon as they are known, so a failed build still
+/// reports what to watch. Returns the stats of a written `.sb3`.
+#[cfg(not(target_arch = "wasm32"))]
+fn compile_input(
+    args: &CompileArgs,
+    input: &Path,
+    progress: &mut CliProgress,
+    deps: &mut Vec<PathBuf>,
+) -> Result<Option<codegen::CodegenStats>> {
+    let input = input.to_path_buf();
+    let from_stdin = is_stdin_arg(&input);
+    let input_is_sbtc = !from_stdin && (args.compile_sbtc || is_sbtc_path(&input));
+    let output_is_sprite3 = args.output.as_deref().map(is_sprite3_path).unwrap_or(false);
+    if !from_stdin {
+        deps.push(input.clone());
+    }
 
-    let (merged, compile_source_dir) = if input_is_sbtc {
+    let (merged, compile_source_dir) = if from_stdin {
+        progress.emit("Reading source from stdin", 1, 1);
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source)?;
+        let source_dir = match &args.source_dir {
+            Some(dir) => dir.clone(),
+            None => std::env::current_dir()?,
+        };
+        let source_dir = source_dir
+            .canonicalize()
+            .map_err(|_| anyhow::anyhow!("Source directory not found: '{}'.", source_dir.display()))
+            .kind(ErrorKind::Io)?;
+        let merged = imports::resolve_merged_source_from_text(
+            &source,
+            &source_dir,
+            &import_search_dirs(&args.include_dirs),
+        )
+        .kind(ErrorKind::Syntax)?;
+        (merged, source_dir)
+    } else if input_is_sbtc {
         progress.emit("Reading .sbtc bundle", 1, 1);
-        let (merged, source_dir_from_bundle) = sbtc::read_sbtc_file(&input)?;
+        let (merged, source_dir_from_bundle) =
+            sbtc::read_sbtc_file(&input).kind(ErrorKind::Syntax)?;
         let source_dir =
             source_dir_from_bundle.unwrap_or_else(|| default_source_dir_for_input(&input));
         (merged, source_dir)
     } else {
         progress.emit("Resolving imports", 1, 1);
         (
-            resolve_merged_source_with_map(&input)?,
+            resolve_merged_source_with_map(&input, &import_search_dirs(&args.include_dirs))
+                .kind(ErrorKind::Syntax)?,
             default_source_dir_for_input(&input),
         )
     };
+    if !input_is_sbtc {
+        *deps = merged.files().to_vec();
+        deps.extend(merged.import_dirs().iter().cloned());
+    }
 
     let (project, semantic_report) = {
         let mut analyze_progress_cb = |step: usize, total: usize, label: &str| {
@@ -137,18 +464,37 @@ fn run_compile_cli(args: &CompileArgs) -> Result<()> {
         parse_and_validate_project_with_options_with_progress(
             &merged,
             SemanticOptions {
-                allow_unknown_procedures: args.allow_unknown_procedures,
+                allow_unknown: args.unknown_proc_policy(),
+                allow_sprite_blocks_on_stage: args.allow_sprite_blocks_on_stage,
+                ..SemanticOptions::default()
             },
+            true,
             Some(&mut analyze_progress_cb),
         )?
     };
+    deps.extend(asset_dependencies(&project, &compile_source_dir));
     if args.allow_unknown_procedures {
         progress.finish();
         eprintln!(
-            "Warning: --allow-unknown-procedures is enabled. Unknown procedure calls will compile as no-op wait(0) blocks."
+            "{} --allow-unknown-procedures is enabled. Unknown procedure calls will compile as no-op wait(0) blocks.",
+            diagnostics::prefix(Severity::Warning, diagnostics::stderr_color())
         );
+    }
+    if !semantic_report.warnings.is_empty() {
+        progress.finish();
         for warning in semantic_report.warnings {
-            eprintln!("Warning: {}", warning.message);
+            let diagnostic = semantic_diagnostic(
+                Severity::Warning,
+                &warning.message,
+                warning.pos,
+                warning.related,
+                Some(&merged),
+            );
+            eprintln!(
+                "{} {}",
+                diagnostics::prefix(Severity::Warning, diagnostics::stderr_color()),
+                render_diagnostic(&diagnostic, Some(&merged), true)
+            );
         }
     }
 
@@ -156,29 +502,59 @@ fn run_compile_cli(args: &CompileArgs) -> Result<()> {
         progress.emit("Writing merged source", 1, 1);
         std::fs::write(emit_path, merged.source.as_bytes())?;
     }
+    if let Some(emit_path) = &args.emit_source_map {
+        progress.emit("Writing source map", 1, 1);
+        std::fs::write(
+            emit_path,
+            serde_json::to_string_pretty(&merged.source_map_json())?,
+        )?;
+    }
     if let Some(emit_path) = &args.emit_sbtc {
         progress.emit("Writing .sbtc bundle", 1, 1);
         sbtc::write_sbtc_file(&merged, &compile_source_dir, emit_path)?;
     }
+    #[cfg(feature = "serde")]
+    if let Some(emit_path) = &args.emit_ast {
+        progress.emit("Writing AST", 1, 1);
+        std::fs::write(
+            emit_path,
+            serde_json::to_string_pretty(&ast::project_to_json(&project)?)?,
+        )?;
+    }
+
+    let options = CodegenOptions {
+        scale_svgs: !args.no_svg_scale,
+        allow_unknown: args.unknown_proc_policy(),
+        hd_bitmaps: args.hd_bitmaps,
+        deterministic_ids: args.deterministic_ids,
+        pretty_json: !args.compact_json,
+        zip_level: args.zip_level,
+        ..CodegenOptions::default()
+    };
+    if let Some(emit_path) = &args.emit_project_json {
+        progress.emit("Emitting project.json", 1, 1);
+        let (project_json, _) =
+            codegen::build_project_json(&project, &compile_source_dir, options.clone())
+                .kind(ErrorKind::Codegen)?;
+        std::fs::write(emit_path, serde_json::to_string_pretty(&project_json)?)?;
+    }
 
     let sprite3_target_name = if output_is_sprite3 {
-        Some(select_sprite_target_name_for_export(
-            &project,
-            args.sprite_name.as_deref(),
-        )?)
+        Some(
+            select_sprite_target_name_for_export(&project, args.sprite_name.as_deref())
+                .kind(ErrorKind::Usage)?,
+        )
     } else {
         None
     };
 
+    let mut stats = None;
     if let Some(output) = &args.output {
         if args.python_backend {
             progress.emit("Building .sb3 (Python backend)", 1, 1);
-            python_backend::compile_with_python(&input, &merged.source, output, args.no_svg_scale)?;
+            python_backend::compile_with_python(&input, &merged.source, output, args.no_svg_scale)
+                .kind(ErrorKind::Codegen)?;
         } else {
-            let options = CodegenOptions {
-                scale_svgs: !args.no_svg_scale,
-                allow_unknown_procedures: args.allow_unknown_procedures,
-            };
             let result = if output_is_sprite3 {
                 let sprite_name = sprite3_target_name.as_deref().ok_or_else(|| {
                     anyhow::anyhow!("Missing selected sprite name for .sprite3 export.")
@@ -195,41 +571,100 @@ fn run_compile_cli(args: &CompileArgs) -> Result<()> {
                     options,
                     Some(&mut codegen_progress_cb),
                 )
+                .map(|()| None)
             } else {
                 let mut codegen_progress_cb = |step: usize, total: usize, label: &str| {
                     progress.emit(label, step, total);
                 };
-                codegen::write_sb3_with_progress(
+                codegen::write_sb3_streaming(
                     &project,
                     &compile_source_dir,
                     output,
                     options,
                     Some(&mut codegen_progress_cb),
                 )
+                .map(Some)
             };
-            result?;
+            stats = result.kind(ErrorKind::Codegen)?;
+        }
+        if let Some(depfile) = &args.emit_depfile {
+            progress.emit("Writing depfile", 1, 1);
+            std::fs::write(depfile, render_depfile(output, deps))?;
         }
     }
-
-    progress.emit("Compile complete", 1, 1);
-    progress.finish();
-    Ok(())
+    Ok(stats)
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 fn run_inspect_cli(args: &InspectArgs) -> Result<()> {
-    let input = canonicalize_file(&args.input)?;
-    let report = obfuscator::inspect_sb3_file(&input)?;
+    let input = canonicalize_file(&args.input).kind(ErrorKind::Io)?;
+    let report = obfuscator::inspect_sb3_file(&input).kind(ErrorKind::Syntax)?;
     println!(
         "{}",
-        obfuscator::inspect::render_inspect_report(&pretty_path(&input), &report)
+        obfuscator::inspect::render_inspect_report(&diagnostics::display_path(&input), &report)
     );
     Ok(())
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+fn run_fmt_cli(args: &FmtArgs) -> Result<()> {
+    let mut unformatted = Vec::new();
+    for input in &args.inputs {
+        let (name, source) = if is_stdin_arg(input) {
+            let source = String::from_utf8(read_stdin_bytes()?)
+                .map_err(|_| anyhow::anyhow!("Source read from stdin is not UTF-8."))
+                .kind(ErrorKind::Syntax)?;
+            (imports::STDIN_NAME.to_string(), source)
+        } else {
+            let path = canonicalize_file(input).kind(ErrorKind::Io)?;
+            (
+                diagnostics::display_path(&path),
+                std::fs::read_to_string(&path)?,
+            )
+        };
+        let formatted = format::format_source(&source)
+            .map_err(|err| anyhow::anyhow!("Cannot format '{}': {}", name, err))
+            .kind(ErrorKind::Syntax)?;
+        if args.check {
+            if formatted != source {
+                unformatted.push(name);
+            }
+        } else if is_stdin_arg(input) {
+            io::stdout().write_all(formatted.as_bytes())?;
+        } else if formatted != source {
+            std::fs::write(input, formatted)?;
+            eprintln!("Formatted '{}'", name);
+        }
+    }
+    if unformatted.is_empty() {
+        return Ok(());
+    }
+    for name in &unformatted {
+        println!("{}", name);
+    }
+    Err(anyhow::anyhow!(
+        "{} file(s) are not formatted.",
+        unformatted.len()
+    ))
+    .kind(ErrorKind::Differs)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn run_diff_cli(args: &DiffArgs) -> Result<()> {
+    let old = canonicalize_file(&args.old).kind(ErrorKind::Io)?;
+    let new = canonicalize_file(&args.new).kind(ErrorKind::Io)?;
+    let diff = diff::diff_sb3_files(&old, &new).kind(ErrorKind::Syntax)?;
+    if diff.is_empty() {
+        println!("No semantic differences.");
+        return Ok(());
+    }
+    println!("{}", diff.render());
+    Err(anyhow::anyhow!("Projects differ.")).kind(ErrorKind::Differs)
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 fn run_obfuscate_cli(args: &ObfuscateArgs) -> Result<()> {
-    let input = canonicalize_file(&args.input)?;
+    let input = canonicalize_file(&args.input).kind(ErrorKind::Io)?;
     let protect_vars = args
         .protect
         .as_deref()
@@ -247,10 +682,11 @@ fn run_obfuscate_cli(args: &ObfuscateArgs) -> Result<()> {
         preset: args.preset,
         seed: args.seed,
     };
-    let result = obfuscator::obfuscate_sb3_file(&input, &args.output, config)?;
+    let result =
+        obfuscator::obfuscate_sb3_file(&input, &args.output, config).kind(ErrorKind::Codegen)?;
 
     println!("SBText-RS Obfuscator");
-    println!("Input: {}", pretty_path(&input));
+    println!("Input: {}", diagnostics::display_path(&input));
     println!("Output: {}", args.output.display());
     println!("Seed: {}", result.seed);
     println!();
@@ -270,64 +706,218 @@ fn run_obfuscate_cli(args: &ObfuscateArgs) -> Result<()> {
     Ok(())
 }
 
+/// Imports are only looked up next to the importing file; see
+/// [`compile_entry_to_sb3_bytes_with_includes`] to search more directories.
 pub fn compile_entry_to_sb3_bytes(input: &Path, scale_svgs: bool) -> Result<Vec<u8>> {
+    compile_entry_to_sb3_bytes_with_includes(
+        input,
+        &[],
+        CodegenOptions {
+            scale_svgs,
+            ..CodegenOptions::default()
+        },
+    )
+}
+
+/// Like [`compile_entry_to_sb3_bytes`], searching `include_dirs` in order for
+/// imports not found next to the importing file. The environment is not read;
+/// pass [`sbtext_path_dirs`] to honor `SBTEXT_PATH` like the CLI does.
+pub fn compile_entry_to_sb3_bytes_with_includes(
+    input: &Path,
+    include_dirs: &[PathBuf],
+    options: CodegenOptions,
+) -> Result<Vec<u8>> {
     let input = canonicalize_file(input)?;
-    let (merged, source_dir) = if is_sbtc_path(&input) {
-        let (merged, source_dir_from_bundle) = sbtc::read_sbtc_file(&input)?;
+    let (merged, source_dir) = read_entry(&input, include_dirs)?;
+    let project = parse_and_validate_project(&merged)?;
+    codegen::build_sb3_bytes(&project, &source_dir, options)
+}
+
+/// The merged source of an entry file or `.sbtc` bundle, and the directory
+/// its assets are resolved against.
+fn read_entry(input: &Path, include_dirs: &[PathBuf]) -> Result<(MergedSource, PathBuf)> {
+    if is_sbtc_path(input) {
+        let (merged, source_dir_from_bundle) = sbtc::read_sbtc_file(input)?;
         let source_dir =
-            source_dir_from_bundle.unwrap_or_else(|| default_source_dir_for_input(&input));
-        (merged, source_dir)
+            source_dir_from_bundle.unwrap_or_else(|| default_source_dir_for_input(input));
+        Ok((merged, source_dir))
     } else {
-        (
-            resolve_merged_source_with_map(&input)?,
-            default_source_dir_for_input(&input),
-        )
+        Ok((
+            resolve_merged_source_with_map(input, include_dirs)?,
+            default_source_dir_for_input(input),
+        ))
+    }
+}
+
+/// Like [`compile_entry_to_sb3_bytes`], but problems come back as
+/// [`Diagnostic`]s mapped to the files they were written in instead of one
+/// message. The archive is returned when there are no errors; warnings are
+/// reported either way.
+pub fn compile_entry_with_diagnostics(
+    input: &Path,
+    options: CodegenOptions,
+    semantic_options: SemanticOptions,
+) -> (Option<Vec<u8>>, Vec<Diagnostic>) {
+    let input = match canonicalize_file(input) {
+        Ok(input) => input,
+        Err(err) => return (None, vec![Diagnostic::from_error(Phase::Io, &err)]),
     };
-    let project = parse_and_validate_project(&merged)?;
-    codegen::build_sb3_bytes(
-        &project,
-        &source_dir,
-        CodegenOptions {
-            scale_svgs,
-            allow_unknown_procedures: false,
-        },
+    let (merged, source_dir) = match read_entry(&input, &[]) {
+        Ok(read) => read,
+        Err(err) => return (None, vec![import_diagnostic(&err)]),
+    };
+    build_with_diagnostics(
+        parse_and_validate_project_with_diagnostics(&merged, semantic_options),
+        |project| codegen::build_sb3_bytes(project, &source_dir, options),
+    )
+}
+
+/// Like [`compile_sbtc_bytes_to_sb3_bytes_with_options`], returning
+/// [`Diagnostic`]s instead of failing with a message.
+pub fn compile_sbtc_bytes_with_diagnostics(
+    sbtc_bytes: &[u8],
+    fallback_source_dir: &Path,
+    options: CodegenOptions,
+    semantic_options: SemanticOptions,
+) -> (Option<Vec<u8>>, Vec<Diagnostic>) {
+    let (merged, source_dir_from_bundle) = match sbtc::read_sbtc_bytes(sbtc_bytes) {
+        Ok(read) => read,
+        Err(err) => return (None, vec![import_diagnostic(&err)]),
+    };
+    let source_dir = source_dir_from_bundle.unwrap_or_else(|| fallback_source_dir.to_path_buf());
+    build_with_diagnostics(
+        parse_and_validate_project_with_diagnostics(&merged, semantic_options),
+        |project| codegen::build_sb3_bytes(project, &source_dir, options),
     )
 }
 
+/// Like [`compile_source_to_sb3_bytes_with_options`], returning
+/// [`Diagnostic`]s (without a file) instead of failing with a message.
+pub fn compile_source_with_diagnostics(
+    source: &str,
+    source_dir: &Path,
+    options: CodegenOptions,
+    semantic_options: SemanticOptions,
+) -> (Option<Vec<u8>>, Vec<Diagnostic>) {
+    build_with_diagnostics(
+        parse_and_validate_source_with_diagnostics(source, semantic_options),
+        |project| codegen::build_sb3_bytes(project, source_dir, options),
+    )
+}
+
+/// Like [`compile_source_to_sb3_bytes_with_assets`], returning
+/// [`Diagnostic`]s (without a file) instead of failing with a message.
+pub fn compile_source_with_assets_with_diagnostics(
+    source: &str,
+    assets: &HashMap<String, Vec<u8>>,
+    options: CodegenOptions,
+    semantic_options: SemanticOptions,
+) -> (Option<Vec<u8>>, Vec<Diagnostic>) {
+    build_with_diagnostics(
+        parse_and_validate_source_with_diagnostics(source, semantic_options),
+        |project| codegen::build_sb3_bytes_with_assets(project, assets, options),
+    )
+}
+
+/// A diagnostic for a failure to read the entry or a bundle. Import errors
+/// keep the file and line they were found on, and a failed read is an I/O
+/// error rather than an import error.
+fn import_diagnostic(error: &anyhow::Error) -> Diagnostic {
+    if error.chain().any(|cause| cause.is::<std::io::Error>()) {
+        return Diagnostic::from_error(Phase::Io, error);
+    }
+    let import = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<ImportError>());
+    match import {
+        Some(import) => Diagnostic::new(Severity::Error, Phase::Import, import.message.clone()).at(
+            Some(import.file.clone()),
+            import.line,
+            1,
+        ),
+        None => Diagnostic::from_error(Phase::Import, error),
+    }
+}
+
+/// Runs `build` on an analyzed project, adding a codegen diagnostic when it
+/// fails.
+fn build_with_diagnostics<T>(
+    (project, mut diagnostics): (Option<ast::Project>, Vec<Diagnostic>),
+    build: impl FnOnce(&ast::Project) -> Result<T>,
+) -> (Option<T>, Vec<Diagnostic>) {
+    let Some(project) = project else {
+        return (None, diagnostics);
+    };
+    match build(&project) {
+        Ok(built) => (Some(built), diagnostics),
+        Err(err) => {
+            diagnostics.push(Diagnostic::from_error(Phase::Codegen, &err));
+            (None, diagnostics)
+        }
+    }
+}
+
 pub fn compile_sbtc_bytes_to_sb3_bytes(
     sbtc_bytes: &[u8],
     fallback_source_dir: &Path,
     scale_svgs: bool,
 ) -> Result<Vec<u8>> {
-    let (merged, source_dir_from_bundle) = sbtc::read_sbtc_bytes(sbtc_bytes)?;
-    let source_dir = source_dir_from_bundle.unwrap_or_else(|| fallback_source_dir.to_path_buf());
-    let project = parse_and_validate_project(&merged)?;
-    codegen::build_sb3_bytes(
-        &project,
-        &source_dir,
+    compile_sbtc_bytes_to_sb3_bytes_with_options(
+        sbtc_bytes,
+        fallback_source_dir,
         CodegenOptions {
             scale_svgs,
-            allow_unknown_procedures: false,
+            ..CodegenOptions::default()
         },
     )
 }
 
+pub fn compile_sbtc_bytes_to_sb3_bytes_with_options(
+    sbtc_bytes: &[u8],
+    fallback_source_dir: &Path,
+    options: CodegenOptions,
+) -> Result<Vec<u8>> {
+    let (merged, source_dir_from_bundle) = sbtc::read_sbtc_bytes(sbtc_bytes)?;
+    let source_dir = source_dir_from_bundle.unwrap_or_else(|| fallback_source_dir.to_path_buf());
+    let project = parse_and_validate_project(&merged)?;
+    codegen::build_sb3_bytes(&project, &source_dir, options)
+}
+
 pub fn compile_source_to_sb3_bytes(
     source: &str,
     source_dir: &Path,
     scale_svgs: bool,
 ) -> Result<Vec<u8>> {
-    let project = parse_and_validate_source(source)?;
-    codegen::build_sb3_bytes(
-        &project,
+    compile_source_to_sb3_bytes_with_options(
+        source,
         source_dir,
         CodegenOptions {
             scale_svgs,
-            allow_unknown_procedures: false,
+            ..CodegenOptions::default()
         },
     )
 }
 
+pub fn compile_source_to_sb3_bytes_with_options(
+    source: &str,
+    source_dir: &Path,
+    options: CodegenOptions,
+) -> Result<Vec<u8>> {
+    let project = parse_and_validate_source(source)?;
+    codegen::build_sb3_bytes(&project, source_dir, options)
+}
+
+/// Like [`compile_source_to_sb3_bytes_with_options`], but costumes and sounds
+/// come from `assets` (file name to bytes) instead of the filesystem.
+pub fn compile_source_to_sb3_bytes_with_assets(
+    source: &str,
+    assets: &HashMap<String, Vec<u8>>,
+    options: CodegenOptions,
+) -> Result<Vec<u8>> {
+    let project = parse_and_validate_source(source)?;
+    codegen::build_sb3_bytes_with_assets(&project, assets, options)
+}
+
 pub fn parse_and_validate_project(merged: &MergedSource) -> Result<ast::Project> {
     let (project, _) = parse_and_validate_project_with_options(merged, SemanticOptions::default())?;
     Ok(project)
@@ -340,71 +930,223 @@ pub fn parse_and_validate_project_with_options(
     parse_and_validate_project_with_options_with_progress(
         merged,
         semantic_options,
+        false,
         Option::<&mut fn(usize, usize, &str)>::None,
     )
 }
 
+/// Like [`parse_and_validate_project_with_options`], but every problem comes
+/// back as a [`Diagnostic`] mapped to its original file, warnings included.
+/// The project is returned when there are no errors.
+pub fn parse_and_validate_project_with_diagnostics(
+    merged: &MergedSource,
+    semantic_options: SemanticOptions,
+) -> (Option<ast::Project>, Vec<Diagnostic>) {
+    let (analyzed, diagnostics) = analyze_source(
+        &merged.source,
+        Some(merged),
+        semantic_options,
+        &mut Option::<&mut fn(usize, usize, &str)>::None,
+    );
+    (analyzed.map(|(project, _)| project), diagnostics)
+}
+
+/// Like [`parse_and_validate_source`], returning [`Diagnostic`]s (without a
+/// file) instead of failing with a message.
+pub fn parse_and_validate_source_with_diagnostics(
+    source: &str,
+    semantic_options: SemanticOptions,
+) -> (Option<ast::Project>, Vec<Diagnostic>) {
+    let (analyzed, diagnostics) = analyze_source(
+        source,
+        None,
+        semantic_options,
+        &mut Option::<&mut fn(usize, usize, &str)>::None,
+    );
+    (analyzed.map(|(project, _)| project), diagnostics)
+}
+
+/// With `snippets`, each error message is followed by the offending source
+/// line, underlined (see [`diagnostics::snippet`]).
 fn parse_and_validate_project_with_options_with_progress<F>(
     merged: &MergedSource,
     semantic_options: SemanticOptions,
+    snippets: bool,
     mut progress: Option<&mut F>,
 ) -> Result<(ast::Project, SemanticReport)>
 where
     F: FnMut(usize, usize, &str),
 {
-    let mut lexer = Lexer::new(&merged.source);
-    let mut lex_progress_cb = |percent: usize| {
-        report_analysis_progress(&mut progress, percent, 100, &format!("Lexing {}%", percent));
-    };
-    let tokens = lexer
-        .tokenize_with_progress(Some(&mut lex_progress_cb))
-        .map_err(|e| {
-            anyhow::anyhow!(format_source_error(
-                "Lex error",
-                &e.message,
-                e.pos.line,
-                e.pos.column,
-                merged,
-            ))
-        })?;
-    emit_parsing_progress_from_tokens(&tokens, &mut progress);
-    let mut parser = SbParser::new(tokens);
-    let project = parser.parse_project().map_err(|e| {
-        anyhow::anyhow!(format_source_error(
-            "Parse error",
-            &e.message,
-            e.pos.line,
-            e.pos.column,
-            merged,
-        ))
-    })?;
-    emit_semantic_progress_from_project(&project, &mut progress);
-    let semantic_report = semantic_analyze_with_options(&project, semantic_options)
-        .map_err(|e| anyhow::anyhow!(format_semantic_error(&e.message, merged)))?;
-    Ok((project, semantic_report))
+    let (analyzed, diagnostics) = analyze_source(
+        &merged.source,
+        Some(merged),
+        semantic_options,
+        &mut progress,
+    );
+    analyzed.ok_or_else(|| diagnostics_error(&diagnostics, Some(merged), snippets))
 }
 
 pub fn parse_and_validate_source(source: &str) -> Result<ast::Project> {
+    let (project, diagnostics) =
+        parse_and_validate_source_with_diagnostics(source, SemanticOptions::default());
+    project.ok_or_else(|| {
+        // Only the first error, as this function reported before semantic
+        // errors were collected.
+        let first_error = diagnostics.iter().find(|d| d.is_error()).cloned();
+        diagnostics_error(first_error.as_slice(), None, false)
+    })
+}
+
+/// Lexes, parses, and checks `source`, the merged source of `merged` when
+/// there is one. Returns the project and semantic report when there are no
+/// errors, and every diagnostic (errors and warnings) either way.
+fn analyze_source<F>(
+    source: &str,
+    merged: Option<&MergedSource>,
+    semantic_options: SemanticOptions,
+    progress: &mut Option<&mut F>,
+) -> (Option<(ast::Project, SemanticReport)>, Vec<Diagnostic>)
+where
+    F: FnMut(usize, usize, &str),
+{
     let mut lexer = Lexer::new(source);
-    let tokens = lexer.tokenize().map_err(|e| {
-        anyhow::anyhow!(
-            "Lex error: {} (line {}, column {})",
-            e.message,
-            e.pos.line,
-            e.pos.column
-        )
-    })?;
+    let mut lex_progress_cb = |percent: usize| {
+        report_analysis_progress(progress, percent, 100, &format!("Lexing {}%", percent));
+    };
+    let tokens = match lexer.tokenize_with_progress(Some(&mut lex_progress_cb)) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            let diagnostic = Diagnostic::new(Severity::Error, Phase::Lex, e.message);
+            return (None, vec![locate(diagnostic, merged, e.pos)]);
+        }
+    };
+    emit_parsing_progress_from_tokens(&tokens, progress);
     let mut parser = SbParser::new(tokens);
-    let project = parser.parse_project().map_err(|e| {
-        anyhow::anyhow!(
-            "Parse error: {} (line {}, column {})",
-            e.message,
-            e.pos.line,
-            e.pos.column
+    let mut project = match parser.parse_project() {
+        Ok(project) => project,
+        Err(e) => {
+            let diagnostic = Diagnostic::new(Severity::Error, Phase::Parse, e.message);
+            return (None, vec![locate(diagnostic, merged, e.pos)]);
+        }
+    };
+    if let Some(merged) = merged {
+        merged.set_asset_origins(&mut project);
+    }
+    emit_semantic_progress_from_project(&project, progress);
+    let semantic_report = semantic_analyze_all(&project, semantic_options);
+    let errors = semantic_report
+        .errors
+        .iter()
+        .map(|e| semantic_diagnostic(Severity::Error, &e.message, e.pos, e.related, merged));
+    let warnings = semantic_report
+        .warnings
+        .iter()
+        .map(|w| semantic_diagnostic(Severity::Warning, &w.message, w.pos, w.related, merged));
+    let diagnostics = errors.chain(warnings).collect::<Vec<_>>();
+    if semantic_report.errors.is_empty() {
+        (Some((project, semantic_report)), diagnostics)
+    } else {
+        (None, diagnostics)
+    }
+}
+
+/// Places `diagnostic` at merged position `pos`, mapped to its original file.
+/// Without `merged` the position is kept as is, with no file.
+fn locate(diagnostic: Diagnostic, merged: Option<&MergedSource>, pos: ast::Position) -> Diagnostic {
+    let (file, line, column) = original_position(merged, pos);
+    diagnostic.at(file, line, column)
+}
+
+fn original_position(
+    merged: Option<&MergedSource>,
+    pos: ast::Position,
+) -> (Option<PathBuf>, usize, usize) {
+    match merged {
+        Some(merged) => {
+            let mapped = merged.map_position(pos.line, pos.column);
+            (Some(mapped.file), mapped.line, mapped.column)
+        }
+        None => (None, pos.line, pos.column),
+    }
+}
+
+fn semantic_diagnostic(
+    severity: Severity,
+    message: &str,
+    pos: Option<ast::Position>,
+    related: Option<ast::Position>,
+    merged: Option<&MergedSource>,
+) -> Diagnostic {
+    let mut diagnostic = Diagnostic::new(severity, Phase::Semantic, strip_positions(message));
+    if let Some(pos) = pos {
+        diagnostic = locate(diagnostic, merged, pos);
+    }
+    if let Some(related) = related {
+        let (file, line, column) = original_position(merged, related);
+        diagnostic.notes.push(Note {
+            message: "related location".to_string(),
+            file,
+            line,
+            column,
+        });
+    }
+    diagnostic
+}
+
+/// `message` without the merged-source positions semantic messages spell out,
+/// such as `at line 3, column 5` or `(line 3, column 5)`; diagnostics carry
+/// the mapped position separately.
+fn strip_positions(message: &str) -> String {
+    static POSITIONS: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    let positions = POSITIONS.get_or_init(|| {
+        regex::Regex::new(
+            r" \(calls at line \d+, column \d+(?:; line \d+, column \d+)*\)| \(line \d+, column \d+\)| at line \d+, column \d+",
         )
-    })?;
-    semantic_analyze(&project)?;
-    Ok(project)
+        .expect("position pattern is valid")
+    });
+    positions.replace_all(message, "").into_owned()
+}
+
+/// The errors among `diagnostics`, one per line, tagged with the kind of the
+/// phase they come from.
+fn diagnostics_error(
+    diagnostics: &[Diagnostic],
+    merged: Option<&MergedSource>,
+    snippets: bool,
+) -> anyhow::Error {
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.is_error())
+        .collect::<Vec<_>>();
+    let kind = match errors.first().map(|d| d.phase) {
+        Some(Phase::Semantic) => ErrorKind::Semantic,
+        Some(Phase::Codegen) => ErrorKind::Codegen,
+        Some(Phase::Io) => ErrorKind::Io,
+        _ => ErrorKind::Syntax,
+    };
+    let messages = errors
+        .iter()
+        .map(|d| render_diagnostic(d, merged, snippets))
+        .collect::<Vec<_>>();
+    KindError {
+        kind,
+        error: anyhow::anyhow!(messages.join("\n")),
+    }
+    .into()
+}
+
+/// `diagnostic` as one line, followed with `snippets` by its source line,
+/// underlined.
+fn render_diagnostic(
+    diagnostic: &Diagnostic,
+    merged: Option<&MergedSource>,
+    snippets: bool,
+) -> String {
+    let mut formatted = diagnostic.to_string();
+    if let (true, Some(merged)) = (snippets, merged) {
+        push_snippet(&mut formatted, merged, diagnostic);
+    }
+    formatted
 }
 
 pub fn canonicalize_file(path: &Path) -> Result<PathBuf> {
@@ -417,72 +1159,113 @@ pub fn canonicalize_file(path: &Path) -> Result<PathBuf> {
     Ok(path.canonicalize()?)
 }
 
-fn format_source_error(
-    kind: &str,
-    message: &str,
-    line: usize,
-    column: usize,
-    merged: &MergedSource,
-) -> String {
-    let mapped = merged.map_position(line, column);
-    format!(
-        "{}: {} (file '{}', line {}, column {})",
-        kind,
-        message,
-        pretty_path(&mapped.file),
-        mapped.line,
-        mapped.column
-    )
+/// Appends the source line `diagnostic` points at on new lines, read from the
+/// original file or, failing that, from the merged source.
+fn push_snippet(formatted: &mut String, merged: &MergedSource, diagnostic: &Diagnostic) {
+    let (Some(file), Some(line), Some(column)) =
+        (&diagnostic.file, diagnostic.line, diagnostic.column)
+    else {
+        return;
+    };
+    let merged_line = if merged.line_origins.is_empty() {
+        Some(line)
+    } else {
+        merged
+            .line_origins
+            .iter()
+            .position(|origin| origin.line == line && origin.file == *file)
+            .map(|index| index + 1)
+    };
+    let fallback = merged_line
+        .and_then(|line| line.checked_sub(1))
+        .and_then(|index| merged.source.lines().nth(index));
+    if let Some(snippet) = diagnostics::file_snippet(file, line, column, fallback) {
+        formatted.push('\n');
+        formatted.push_str(&snippet);
+    }
 }
 
-fn format_semantic_error(message: &str, merged: &MergedSource) -> String {
-    if let Some((line, column)) = extract_line_column(message) {
-        let mapped = merged.map_position(line, column);
-        return format!(
-            "{} (file '{}', mapped line {}, column {})",
-            message,
-            pretty_path(&mapped.file),
-            mapped.line,
-            mapped.column
-        );
-    }
-    message.to_string()
+/// Import directories after the importing file's own: `include_dirs` (from `-I`)
+/// followed by the entries of the `SBTEXT_PATH` environment variable.
+#[cfg(not(target_arch = "wasm32"))]
+fn import_search_dirs(include_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut dirs = include_dirs.to_vec();
+    dirs.extend(sbtext_path_dirs());
+    dirs
 }
 
-fn extract_line_column(message: &str) -> Option<(usize, usize)> {
-    let line_marker = "line ";
-    let col_marker = ", column ";
-    let line_start = message.find(line_marker)? + line_marker.len();
-    let line_tail = &message[line_start..];
-    let line_digits = line_tail
-        .chars()
-        .take_while(|c| c.is_ascii_digit())
-        .collect::<String>();
-    if line_digits.is_empty() {
-        return None;
-    }
-    let line = line_digits.parse::<usize>().ok()?;
-    let after_line = &line_tail[line_digits.len()..];
-    let col_start = after_line.find(col_marker)? + col_marker.len();
-    let col_tail = &after_line[col_start..];
-    let col_digits = col_tail
-        .chars()
-        .take_while(|c| c.is_ascii_digit())
-        .collect::<String>();
-    if col_digits.is_empty() {
-        return None;
+/// The directories listed in the `SBTEXT_PATH` environment variable, which the
+/// CLI searches for imports after its `-I` directories. Library entry points
+/// only search them when given explicitly.
+pub fn sbtext_path_dirs() -> Vec<PathBuf> {
+    std::env::var_os("SBTEXT_PATH")
+        .map(|paths| {
+            std::env::split_paths(&paths)
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Costume and sound files on disk that `project` reads, in declaration order.
+#[cfg(not(target_arch = "wasm32"))]
+fn asset_dependencies(project: &ast::Project, source_dir: &Path) -> Vec<PathBuf> {
+    use assets::AssetResolver;
+    let resolver = assets::FsAssetResolver::new(source_dir);
+    let mut deps: Vec<PathBuf> = Vec::new();
+    for target in &project.targets {
+        let paths = target
+            .costumes
+            .iter()
+            .map(|costume| (&costume.path, &costume.origin_dir))
+            .chain(
+                target
+                    .sounds
+                    .iter()
+                    .map(|sound| (&sound.path, &sound.origin_dir)),
+            );
+        for (path, origin_dir) in paths {
+            if let Some(file) = resolver.file_path(&resolver.locate(path, origin_dir.as_deref())) {
+                let file = file.canonicalize().unwrap_or(file);
+                if !deps.contains(&file) {
+                    deps.push(file);
+                }
+            }
+        }
     }
-    let column = col_digits.parse::<usize>().ok()?;
-    Some((line, column))
+    deps
 }
 
-fn pretty_path(path: &Path) -> String {
-    let raw = path.display().to_string();
-    if let Some(stripped) = raw.strip_prefix(r"\\?\") {
-        stripped.to_string()
-    } else {
-        raw
+/// A Make/Ninja depfile rule: `output: dep dep ...`, with spaces, `#`, and `$`
+/// escaped.
+#[cfg(not(target_arch = "wasm32"))]
+fn render_depfile(output: &Path, deps: &[PathBuf]) -> String {
+    let escape = |path: &Path| {
+        path.to_string_lossy()
+            .replace('$', "$$")
+            .replace('#', "\\#")
+            .replace(' ', "\\ ")
+    };
+    let mut out = format!("{}:", escape(output));
+    for dep in deps {
+        out.push_str(" \\\n  ");
+        out.push_str(&escape(dep));
     }
+    out.push('\n');
+    out
+}
+
+/// `-` as INPUT reads the source (or archive, when decompiling) from stdin.
+#[cfg(not(target_arch = "wasm32"))]
+fn is_stdin_arg(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_stdin_bytes() -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    io::stdin().read_to_end(&mut bytes)?;
+    Ok(bytes)
 }
 
 fn default_source_dir_for_input(input: &Path) -> PathBuf {
@@ -684,38 +1467,18 @@ fn count_semantic_statement_checks(project: &ast::Project) -> usize {
     let mut total = 0usize;
     for target in &project.targets {
         for procedure in &target.procedures {
-            total += count_statement_checks_recursive(&procedure.body);
+            total += count_statement_checks(&procedure.body);
         }
         for script in &target.scripts {
-            total += count_statement_checks_recursive(&script.body);
+            total += count_statement_checks(&script.body);
         }
     }
     total
 }
 
-fn count_statement_checks_recursive(statements: &[ast::Statement]) -> usize {
+fn count_statement_checks(statements: &[ast::Statement]) -> usize {
     let mut total = 0usize;
-    for statement in statements {
-        total += 1;
-        match statement {
-            ast::Statement::Repeat { body, .. }
-            | ast::Statement::ForEach { body, .. }
-            | ast::Statement::While { body, .. }
-            | ast::Statement::RepeatUntil { body, .. }
-            | ast::Statement::Forever { body, .. } => {
-                total += count_statement_checks_recursive(body);
-            }
-            ast::Statement::If {
-                then_body,
-                else_body,
-                ..
-            } => {
-                total += count_statement_checks_recursive(then_body);
-                total += count_statement_checks_recursive(else_body);
-            }
-            _ => {}
-        }
-    }
+    ast::walk_statements(statements, &mut |_| total += 1);
     total
 }
 
@@ -728,7 +1491,7 @@ fn walk_semantic_statement_checks<F>(
 ) where
     F: FnMut(usize, usize, &str),
 {
-    for statement in statements {
+    ast::walk_statements(statements, &mut |_| {
         *done += 1;
         report_phase_percent_with_counts(
             progress,
@@ -738,25 +1501,7 @@ fn walk_semantic_statement_checks<F>(
             "checks",
             last_percent,
         );
-        match statement {
-            ast::Statement::Repeat { body, .. }
-            | ast::Statement::ForEach { body, .. }
-            | ast::Statement::While { body, .. }
-            | ast::Statement::RepeatUntil { body, .. }
-            | ast::Statement::Forever { body, .. } => {
-                walk_semantic_statement_checks(body, done, total, progress, last_percent);
-            }
-            ast::Statement::If {
-                then_body,
-                else_body,
-                ..
-            } => {
-                walk_semantic_statement_checks(then_body, done, total, progress, last_percent);
-                walk_semantic_statement_checks(else_body, done, total, progress, last_percent);
-            }
-            _ => {}
-        }
-    }
+    });
 }
 
 fn report_phase_percent_with_counts<F>(
@@ -784,26 +1529,67 @@ fn report_phase_percent_with_counts<F>(
     );
 }
 
+/// How [`CliProgress`] reports steps.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProgressMode {
+    /// A bar redrawn in place on a terminal, one line per step otherwise.
+    Bar,
+    /// One line when each phase starts (`--no-progress`).
+    Lines,
+    /// Nothing (`--quiet`, and `--watch`, which reports one line per build).
+    Quiet,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy)]
+struct ProgressSettings {
+    mode: ProgressMode,
+    /// Record per-phase wall-clock time for [`CliProgress::print_timings`].
+    timings: bool,
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 struct CliProgress {
     prefix: &'static str,
+    mode: ProgressMode,
     is_tty: bool,
     rendered_line_len: usize,
     has_rendered: bool,
+    /// The phase of the last step and when it started.
+    phase: Option<(String, Instant)>,
+    /// Time per phase in order of first appearance; `None` without `--timings`.
+    timings: Option<Vec<(String, Duration)>>,
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 impl CliProgress {
-    fn new(prefix: &'static str) -> Self {
+    fn new(prefix: &'static str, settings: ProgressSettings) -> Self {
         Self {
             prefix,
+            mode: settings.mode,
             is_tty: io::stderr().is_terminal(),
             rendered_line_len: 0,
             has_rendered: false,
+            phase: None,
+            timings: settings.timings.then(Vec::new),
         }
     }
 
     fn emit(&mut self, label: &str, step: usize, total: usize) {
+        let phase = progress_phase(label);
+        if self.phase.as_ref().map(|(name, _)| name.as_str()) != phase {
+            self.end_phase();
+            if let Some(phase) = phase {
+                if self.mode == ProgressMode::Lines {
+                    eprintln!("[{}] {}...", self.prefix, phase);
+                }
+                self.phase = Some((phase.to_string(), Instant::now()));
+            }
+        }
+        if self.mode != ProgressMode::Bar {
+            return;
+        }
         let total = total.max(1);
         let step = step.clamp(1, total);
         let bar = render_progress_bar(step, total, 14);
@@ -829,6 +1615,75 @@ impl CliProgress {
             self.rendered_line_len = 0;
         }
     }
+
+    fn end_phase(&mut self) {
+        let Some((name, started)) = self.phase.take() else {
+            return;
+        };
+        let Some(timings) = &mut self.timings else {
+            return;
+        };
+        let elapsed = started.elapsed();
+        match timings.iter_mut().find(|(phase, _)| *phase == name) {
+            Some((_, total)) => *total += elapsed,
+            None => timings.push((name, elapsed)),
+        }
+    }
+
+    /// Prints the `--timings` table; a no-op without `--timings`.
+    fn print_timings(&mut self) {
+        self.end_phase();
+        let Some(timings) = self.timings.take() else {
+            return;
+        };
+        self.finish();
+        let width = timings
+            .iter()
+            .map(|(phase, _)| phase.len())
+            .chain(["Total".len()])
+            .max()
+            .unwrap_or(0);
+        eprintln!("[{}] Timings:", self.prefix);
+        for (phase, elapsed) in &timings {
+            eprintln!("  {:<width$}  {:>9.2} ms", phase, millis(*elapsed));
+        }
+        let total = timings.iter().map(|(_, elapsed)| *elapsed).sum();
+        eprintln!("  {:<width$}  {:>9.2} ms", "Total", millis(total));
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Groups progress labels into the phases shown by `--no-progress` and
+/// `--timings`. Labels without a group are their own phase; the final
+/// "... complete" label belongs to none.
+#[cfg(not(target_arch = "wasm32"))]
+fn progress_phase(label: &str) -> Option<&str> {
+    const PHASES: [(&str, &str); 11] = [
+        ("Resolving imports", "Resolving imports"),
+        ("Reading .sbtc bundle", "Resolving imports"),
+        ("Reading source from stdin", "Resolving imports"),
+        ("Lexing", "Lexing"),
+        ("Parsing", "Parsing"),
+        ("Semantic checks", "Semantic checks"),
+        ("Building .s", "Codegen"),
+        ("Emitting targets", "Codegen"),
+        ("Selecting sprite target", "Codegen"),
+        ("Writing project.json", "Packaging assets"),
+        ("Writing sprite.json", "Packaging assets"),
+    ];
+    if label.ends_with(" complete") {
+        return None;
+    }
+    Some(
+        PHASES
+            .iter()
+            .find(|(prefix, _)| label.starts_with(prefix))
+            .map_or(label, |(_, phase)| phase),
+    )
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -850,3 +1705,348 @@ fn render_progress_bar(step: usize, total: usize, width: usize) -> String {
     s.push(']');
     s
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use semantic::UnknownProcPolicy;
+
+    fn run(args: &[&str]) -> Result<()> {
+        let args = cli::Args::try_parse_from(["sbtext-rs", "--quiet"].iter().chain(args).copied())
+            .expect("arguments should parse");
+        run_cli(&args)
+    }
+
+    fn exit_code(args: &[&str]) -> i32 {
+        match run(args) {
+            Ok(()) => 0,
+            Err(err) => ErrorKind::of(&err).exit_code(),
+        }
+    }
+
+    fn fixture(name: &str) -> String {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/exit_codes")
+            .join(name)
+            .display()
+            .to_string()
+    }
+
+    #[test]
+    fn each_failure_category_has_its_own_exit_code() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let output = dir.path().join("out.sb3").display().to_string();
+        let out_dir = dir.path().display().to_string();
+
+        assert_eq!(exit_code(&["compile", &fixture("ok.sbtext"), &output]), 0);
+        assert_eq!(
+            exit_code(&["compile", &fixture("ok.sbtext"), "-", "--source-dir", "."]),
+            1
+        );
+        assert_eq!(exit_code(&["check", &fixture("syntax_error.sbtext")]), 2);
+        assert_eq!(exit_code(&["check", &fixture("semantic_error.sbtext")]), 3);
+        assert_eq!(
+            exit_code(&["compile", &fixture("missing_asset.sbtext"), &output]),
+            4
+        );
+        assert_eq!(exit_code(&["compile", &fixture("ok.sbtext"), &out_dir]), 5);
+        assert_eq!(exit_code(&["check", &fixture("does_not_exist.sbtext")]), 5);
+        assert_eq!(exit_code(&["fmt", "--check", &fixture("ok.sbtext")]), 0);
+    }
+
+    #[test]
+    fn compile_defaults_the_output_to_the_input_name() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let input = dir.path().join("game.sbtext");
+        std::fs::copy(fixture("ok.sbtext"), &input).expect("copy fixture");
+        let input = input.display().to_string();
+        let default_output = dir.path().join("game.sb3");
+
+        run(&["compile", &input, "--no-output"]).expect("dry run");
+        assert!(!default_output.exists());
+
+        run(&["compile", &input]).expect("compile with the default output");
+        assert!(default_output.is_file());
+
+        let explicit = dir.path().join("explicit.sb3");
+        run(&["compile", &input, &explicit.display().to_string()])
+            .expect("compile with an explicit output");
+        assert!(explicit.is_file());
+
+        let sb3 = default_output.display().to_string();
+        let err = run(&["compile", &sb3]).unwrap_err();
+        assert_eq!(ErrorKind::of(&err), ErrorKind::Usage);
+        assert_eq!(exit_code(&["compile", "-"]), 1);
+    }
+
+    #[test]
+    fn legacy_flat_flags_translate_to_subcommands() {
+        let parse = |args: &[&str]| {
+            cli::Args::try_parse_from(["sbtext-rs"].iter().chain(args).copied())
+                .expect("arguments should parse")
+                .command()
+        };
+        let Ok(Command::Decompile(decompile)) =
+            parse(&["game.sb3", "out", "--decompile", "--split-sprites"])
+        else {
+            panic!("--decompile should select decompile");
+        };
+        assert!(decompile.split_sprites);
+        assert_eq!(decompile.output.as_deref(), Some(Path::new("out")));
+        let Ok(Command::Compile(compile)) =
+            parse(&["game.sbtext", "-I", "lib", "--stats", "o.sb3"])
+        else {
+            panic!("flat flags should select compile");
+        };
+        assert_eq!(compile.include_dirs, vec![PathBuf::from("lib")]);
+        assert!(compile.stats);
+
+        for (args, message) in [
+            (
+                &["game.sbtext", "--split-sprites"][..],
+                "--split-sprites requires --decompile.",
+            ),
+            (
+                &["game.sb3", "--decompile", "--stats"][..],
+                "--stats cannot be used with --decompile.",
+            ),
+            (&["--quiet"][..], "Missing INPUT."),
+        ] {
+            let err = parse(args).expect_err("combination should be rejected");
+            assert!(err.to_string().starts_with(message), "{}", err);
+        }
+
+        let dir = tempfile::tempdir().expect("temp dir");
+        let output = dir.path().join("out.sb3").display().to_string();
+        assert_eq!(exit_code(&[&fixture("ok.sbtext"), &output]), 0);
+        assert_eq!(exit_code(&[&fixture("semantic_error.sbtext")]), 3);
+        assert_eq!(exit_code(&[&output, "--decompile", "-"]), 0);
+    }
+
+    #[test]
+    fn depfiles_list_every_input_with_make_escaping() {
+        let rendered = render_depfile(
+            Path::new("out dir/game#1.sb3"),
+            &[
+                PathBuf::from("src/a b.sbtext"),
+                PathBuf::from("art/$hero.svg"),
+            ],
+        );
+        assert_eq!(
+            rendered,
+            "out\\ dir/game\\#1.sb3: \\\n  src/a\\ b.sbtext \\\n  art/$$hero.svg\n"
+        );
+
+        let dir = tempfile::tempdir().expect("temp dir");
+        let src = dir.path().join("my src");
+        std::fs::create_dir(&src).expect("create dir");
+        std::fs::write(
+            src.join("main #1.sbtext"),
+            "import [Lib] from \"lib.sbtext\"\nstage\nend\n",
+        )
+        .expect("write main");
+        std::fs::write(src.join("lib.sbtext"), "sprite Lib\nend\n").expect("write lib");
+        let input = src.join("main #1.sbtext");
+        let output = dir.path().join("out.sb3");
+        let depfile = dir.path().join("out.d");
+        run(&[
+            "compile",
+            &input.display().to_string(),
+            &output.display().to_string(),
+            "--emit-depfile",
+            &depfile.display().to_string(),
+        ])
+        .expect("compile with depfile");
+        let written = std::fs::read_to_string(&depfile).expect("depfile written");
+        let src = src.canonicalize().expect("canonical dir");
+        let expected = render_depfile(
+            &output,
+            &[src.join("main #1.sbtext"), src.join("lib.sbtext")],
+        );
+        assert_eq!(written, expected);
+        assert!(
+            written.contains("my\\ src/main\\ \\#1.sbtext"),
+            "{}",
+            written
+        );
+    }
+
+    #[test]
+    fn tagging_an_error_keeps_its_message() {
+        let err = run(&["check", &fixture("semantic_error.sbtext")]).expect_err("should fail");
+        let message = err.to_string();
+        assert!(
+            message.starts_with("Unknown procedure 'undefined_procedure'"),
+            "{}",
+            message
+        );
+        assert_eq!(format!("{:#}", err), message);
+    }
+
+    #[test]
+    fn diagnostics_keep_positions_in_the_original_files() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let write = |name: &str, source: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, source).expect("write source");
+            path
+        };
+        let lib = write(
+            "lib.sbtext",
+            "sprite Lib\n  var a\n  var A\n  when flag clicked\n    say (scroe)\n  end\nend\n",
+        );
+        let main = write(
+            "main.sbtext",
+            "import [Lib] from \"lib.sbtext\"\nstage\n  when flag clicked\n    broadcast [nobody]\n  end\nend\n",
+        );
+
+        let (bytes, diagnostics) = compile_entry_with_diagnostics(
+            &main,
+            CodegenOptions::default(),
+            SemanticOptions::default(),
+        );
+        assert!(bytes.is_none());
+        let lib = lib.canonicalize().expect("canonical path");
+        let summary = diagnostics
+            .iter()
+            .map(|d| (d.severity, d.phase, d.file.clone(), d.line, d.column))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary[..2],
+            [
+                (
+                    Severity::Error,
+                    Phase::Semantic,
+                    Some(lib.clone()),
+                    Some(3),
+                    Some(3)
+                ),
+                (
+                    Severity::Error,
+                    Phase::Semantic,
+                    Some(lib.clone()),
+                    Some(5),
+                    Some(10)
+                ),
+            ]
+        );
+        assert_eq!(diagnostics[0].notes[0].line, 2);
+        for diagnostic in &diagnostics[..2] {
+            assert!(
+                !diagnostic.message.contains("line") && !diagnostic.message.contains("column"),
+                "{}",
+                diagnostic.message
+            );
+        }
+        assert!(diagnostics.len() > 2, "{:?}", diagnostics);
+        assert!(diagnostics[2..]
+            .iter()
+            .all(|d| d.severity == Severity::Warning && d.phase == Phase::Semantic));
+
+        let merged = resolve_merged_source_with_map(&main, &[]).expect("imports should resolve");
+        let err = parse_and_validate_project(&merged).unwrap_err().to_string();
+        let rendered = diagnostics[..2]
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(err, rendered.join("\n"));
+
+        let (_, diagnostics) = compile_source_with_diagnostics(
+            "sprite Cat\n  when flag clicked\n    say (\"hi\"\n  end\nend\n",
+            Path::new("."),
+            CodegenOptions::default(),
+            SemanticOptions::default(),
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            (
+                diagnostics[0].phase,
+                diagnostics[0].file.as_ref(),
+                diagnostics[0].line
+            ),
+            (Phase::Parse, None, Some(3))
+        );
+
+        let (bytes, diagnostics) = compile_entry_with_diagnostics(
+            Path::new(&fixture("missing_asset.sbtext")),
+            CodegenOptions::default(),
+            SemanticOptions::default(),
+        );
+        assert!(bytes.is_none());
+        assert_eq!(diagnostics.last().map(|d| d.phase), Some(Phase::Codegen));
+
+        let broken = write(
+            "broken.sbtext",
+            "stage
+end
+import [Lib] from \"lib.sbtext\"\n",
+        );
+        let (_, diagnostics) = compile_entry_with_diagnostics(
+            &broken,
+            CodegenOptions::default(),
+            SemanticOptions::default(),
+        );
+        let broken = broken.canonicalize().expect("canonical path");
+        assert_eq!(
+            (
+                diagnostics[0].phase,
+                diagnostics[0].file.as_ref(),
+                diagnostics[0].line
+            ),
+            (Phase::Import, Some(&broken), Some(3))
+        );
+
+        let source = "sprite Cat\n  when flag clicked\n    SaveGame\n  end\nend\n";
+        let (bytes, diagnostics) = compile_source_with_diagnostics(
+            source,
+            Path::new("."),
+            CodegenOptions {
+                allow_unknown: UnknownProcPolicy::All,
+                ..CodegenOptions::default()
+            },
+            SemanticOptions {
+                allow_unknown: UnknownProcPolicy::All,
+                ..SemanticOptions::default()
+            },
+        );
+        assert!(bytes.is_some(), "{:?}", diagnostics);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn library_entry_points_search_only_the_include_dirs_they_are_given() {
+        let project = tempfile::tempdir().expect("temp dir");
+        let library = tempfile::tempdir().expect("temp dir");
+        let main = project.path().join("main.sbtext");
+        std::fs::write(&main, "import [Coin] from \"coin.sbtext\"\n").expect("write main");
+        std::fs::write(library.path().join("coin.sbtext"), "sprite Coin\nend\n")
+            .expect("write library");
+
+        let err = compile_entry_to_sb3_bytes(&main, false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Imported file does not exist"), "{}", err);
+        compile_entry_to_sb3_bytes_with_includes(
+            &main,
+            &[library.path().to_path_buf()],
+            CodegenOptions::default(),
+        )
+        .expect("include dir should be searched");
+    }
+
+    #[test]
+    fn cli_errors_underline_the_source_line() {
+        let err = run(&["check", &fixture("syntax_error.sbtext")]).expect_err("should fail");
+        let message = err.to_string();
+        assert!(
+            message.ends_with("line 3, column 10)\n 3 |     say (\n   |          ^"),
+            "{}",
+            message
+        );
+
+        let source = std::fs::read_to_string(fixture("semantic_error.sbtext")).expect("fixture");
+        let err = parse_and_validate_source(&source).expect_err("should fail");
+        assert!(!err.to_string().contains('^'), "{}", err);
+    }
+}
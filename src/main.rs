@@ -1,14 +1,39 @@
 #[cfg(not(target_arch = "wasm32"))]
-use anyhow::Result;
-#[cfg(not(target_arch = "wasm32"))]
 use clap::Parser;
 #[cfg(not(target_arch = "wasm32"))]
-use sbtext_rs_core::cli::Args;
+use sbtext_rs_core::{
+    cli::Args,
+    diagnostics::{self, Severity},
+    ErrorKind,
+};
 
 #[cfg(not(target_arch = "wasm32"))]
-fn main() -> Result<()> {
-    let args = Args::parse();
-    sbtext_rs_core::run_cli(&args)
+fn main() {
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        Err(err) => {
+            let _ = err.print();
+            // `--help` and `--version` also arrive here, on stdout.
+            let code = if err.use_stderr() {
+                ErrorKind::Usage.exit_code()
+            } else {
+                0
+            };
+            std::process::exit(code);
+        }
+    };
+    if let Err(err) = sbtext_rs_core::run_cli(&args) {
+        let kind = ErrorKind::of(&err);
+        // The differences are already on stdout; this is an outcome, not a failure.
+        if kind != ErrorKind::Differs {
+            eprintln!(
+                "{} {:?}",
+                diagnostics::prefix(Severity::Error, diagnostics::stderr_color()),
+                err
+            );
+        }
+        std::process::exit(kind.exit_code());
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
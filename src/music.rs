@@ -0,0 +1,64 @@
+//! Menu tables of the music extension, shared by the compiler, the checker,
+//! and the decompiler.
+
+/// Drum menu entries of the music extension, in Scratch's 1-based menu order.
+pub(crate) const MUSIC_DRUMS: [&str; 18] = [
+    "snare drum",
+    "bass drum",
+    "side stick",
+    "crash cymbal",
+    "open hi-hat",
+    "closed hi-hat",
+    "tambourine",
+    "hand clap",
+    "claves",
+    "wood block",
+    "cowbell",
+    "triangle",
+    "bongo",
+    "conga",
+    "cabasa",
+    "guiro",
+    "vibraslap",
+    "cuica",
+];
+
+/// Instrument menu entries of the music extension, in Scratch's 1-based menu order.
+pub(crate) const MUSIC_INSTRUMENTS: [&str; 21] = [
+    "piano",
+    "electric piano",
+    "organ",
+    "guitar",
+    "electric guitar",
+    "bass",
+    "pizzicato",
+    "cello",
+    "trombone",
+    "clarinet",
+    "saxophone",
+    "flute",
+    "wooden flute",
+    "bassoon",
+    "choir",
+    "vibraphone",
+    "music box",
+    "steel drum",
+    "marimba",
+    "synth lead",
+    "synth pad",
+];
+
+/// Resolves a music drum/instrument menu name, or its 1-based number, to the
+/// menu number Scratch stores. Drum names may omit a trailing "drum".
+pub(crate) fn music_menu_number(table: &[&str], name: &str) -> Option<usize> {
+    let lowered = name.trim().to_ascii_lowercase();
+    if let Ok(number) = lowered.parse::<usize>() {
+        return (1..=table.len()).contains(&number).then_some(number);
+    }
+    table
+        .iter()
+        .position(|entry| {
+            *entry == lowered || entry.strip_suffix(" drum") == Some(lowered.as_str())
+        })
+        .map(|index| index + 1)
+}
@@ -1,9 +1,11 @@
+use crate::ast::{walk_exprs_mut, walk_statements_mut};
 use crate::ast::{
-    CostumeDecl, EventScript, EventType, Expr, InitialValue, ListDecl, Position, Procedure,
-    Project, Statement, Target, VariableDecl, ReporterDecl,
+    CostumeDecl, EventScript, EventType, Expr, InitialValue, ListDecl, MonitorDecl, ParamKind,
+    Position, Procedure, Project, PropertyDecl, ReporterDecl, SoundDecl, Statement, Target,
+    TargetProperty, VariableDecl,
 };
 use crate::lexer::{Token, TokenType};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 
@@ -25,6 +27,10 @@ impl Display for ParseError {
 
 impl Error for ParseError {}
 
+/// Reporters named by a single word that variables and parameters may also
+/// use. A variable or parameter in scope wins over the reporter.
+const CONTEXTUAL_REPORTERS: &[&str] = &["direction", "loudness", "size", "tempo", "username"];
+
 pub struct Parser {
     tokens: Vec<Token>,
     index: usize,
@@ -36,7 +42,7 @@ impl Parser {
     }
 
     pub fn parse_project(&mut self) -> Result<Project, ParseError> {
-        self.skip_newlines();
+        self.skip_doc_comments();
         let start = self.current().pos;
         let mut targets = Vec::new();
         while !self.at_end() {
@@ -48,7 +54,7 @@ impl Parser {
             } else {
                 return self.error_here("Expected 'sprite' or 'stage'.");
             }
-            self.skip_newlines();
+            self.skip_doc_comments();
         }
         if targets.is_empty() {
             return Err(ParseError {
@@ -56,6 +62,7 @@ impl Parser {
                 pos: start,
             });
         }
+        shadow_contextual_reporters(&mut targets);
         Ok(Project {
             pos: start,
             targets,
@@ -90,12 +97,16 @@ impl Parser {
             variables: Vec::new(),
             lists: Vec::new(),
             costumes: Vec::new(),
+            sounds: Vec::new(),
+            properties: Vec::new(),
+            monitors: Vec::new(),
             procedures: Vec::new(),
             scripts: Vec::new(),
             reporters: Vec::new(),
         };
         loop {
             self.skip_newlines();
+            let comment = self.take_doc_comment();
             if self.at_end() {
                 return self.error_here(format!(
                     "Unterminated target block for '{}'. Expected 'end'.",
@@ -105,8 +116,16 @@ impl Parser {
             if self.match_keyword("end") {
                 break;
             }
+            let cloud_pos = if self.current_word().as_deref() == Some("cloud")
+                && self.peek().typ == TokenType::Keyword
+                && self.peek().value == "var"
+            {
+                Some(self.advance().pos)
+            } else {
+                None
+            };
             if self.match_keyword("var") {
-                let prev = self.previous().pos;
+                let prev = cloud_pos.unwrap_or(self.previous().pos);
                 let var_name = self.parse_decl_name_token()?;
                 let initial_value = if self.match_operator("=") {
                     if self.check_type(TokenType::Newline) || self.check_type(TokenType::Eof) {
@@ -121,6 +140,7 @@ impl Parser {
                     pos: prev,
                     name: var_name,
                     initial_value,
+                    is_cloud: cloud_pos.is_some(),
                 });
                 continue;
             }
@@ -141,17 +161,50 @@ impl Parser {
             }
             if self.match_keyword("costume") {
                 let prev = self.previous().pos;
-                let path_token =
+                let first =
                     self.consume_type(TokenType::String, "Expected costume path string.")?;
+                let (name, path) = if self.check_type(TokenType::String) {
+                    (Some(first.value), self.advance().value)
+                } else {
+                    (None, first.value)
+                };
                 target.costumes.push(CostumeDecl {
                     pos: prev,
-                    path: path_token.value,
+                    name,
+                    path,
+                    origin_dir: None,
                 });
                 continue;
             }
+            if self.match_keyword("sound") {
+                let prev = self.previous().pos;
+                let first = self.consume_type(TokenType::String, "Expected sound path string.")?;
+                let (name, path) = if self.check_type(TokenType::String) {
+                    (Some(first.value), self.advance().value)
+                } else {
+                    (None, first.value)
+                };
+                target.sounds.push(SoundDecl {
+                    pos: prev,
+                    name,
+                    path,
+                    origin_dir: None,
+                });
+                continue;
+            }
+            if let Some(property) = self.parse_target_property()? {
+                target.properties.push(property);
+                continue;
+            }
+            if self.current_word().as_deref() == Some("monitor") {
+                target.monitors.push(self.parse_monitor_decl()?);
+                continue;
+            }
             if self.match_keyword("define") {
                 let prev = self.previous().pos;
-                target.procedures.push(self.parse_procedure(prev)?);
+                let mut procedure = self.parse_procedure(prev)?;
+                procedure.comment = comment;
+                target.procedures.push(procedure);
                 continue;
             }
             if self.match_keyword("reporter") {
@@ -161,16 +214,153 @@ impl Parser {
             }
             if self.match_keyword("when") {
                 let prev = self.previous().pos;
-                target.scripts.push(self.parse_event_script(prev)?);
+                let mut script = self.parse_event_script(prev)?;
+                script.comment = comment;
+                target.scripts.push(script);
                 continue;
             }
+            if self.current_word().as_deref() == Some("include") {
+                return self.error_here(
+                    "'include' is resolved when compiling from a file and is not supported here.",
+                );
+            }
             return self.error_here(
-                "Expected 'var', 'list', 'costume', 'define', 'when', or 'end' inside target.",
+                "Expected 'var', 'list', 'costume', 'sound', 'monitor', 'define', 'when', or 'end' inside target.",
             );
         }
         Ok(target)
     }
 
+    fn parse_target_property(&mut self) -> Result<Option<PropertyDecl>, ParseError> {
+        let pos = self.current().pos;
+        let property = match self.current_word().as_deref() {
+            Some("x") => {
+                self.advance();
+                TargetProperty::X(self.parse_property_number("x")?)
+            }
+            Some("y") => {
+                self.advance();
+                TargetProperty::Y(self.parse_property_number("y")?)
+            }
+            Some("size") => {
+                self.advance();
+                TargetProperty::Size(self.parse_property_number("size")?)
+            }
+            Some("direction") => {
+                self.advance();
+                TargetProperty::Direction(self.parse_property_number("direction")?)
+            }
+            Some("hidden") => {
+                self.advance();
+                TargetProperty::Visible(false)
+            }
+            Some("visible") => {
+                self.advance();
+                TargetProperty::Visible(true)
+            }
+            Some("rotation") => {
+                self.advance();
+                self.consume_keyword("style", "Expected 'style' after 'rotation'.")?;
+                let style = self.parse_bracket_menu_text()?;
+                if style.is_empty() {
+                    return self.error_here("Rotation style cannot be empty.");
+                }
+                TargetProperty::RotationStyle(style)
+            }
+            Some("volume") => {
+                self.advance();
+                TargetProperty::Volume(self.parse_property_number("volume")?)
+            }
+            Some("layer") => {
+                self.advance();
+                TargetProperty::Layer(self.parse_property_number("layer")?)
+            }
+            Some("start") => {
+                self.advance();
+                self.consume_keyword("costume", "Expected 'costume' after 'start'.")?;
+                let name = self.parse_bracket_menu_text()?;
+                if name.is_empty() {
+                    return self.error_here("Start costume cannot be empty.");
+                }
+                TargetProperty::StartCostume(name)
+            }
+            Some("tempo") => {
+                self.advance();
+                TargetProperty::Tempo(self.parse_property_number("tempo")?)
+            }
+            Some("video") => {
+                self.advance();
+                if self.current_word().as_deref() == Some("transparency") {
+                    self.advance();
+                    TargetProperty::VideoTransparency(
+                        self.parse_property_number("video transparency")?,
+                    )
+                } else {
+                    let state = self.parse_bracket_menu_text()?;
+                    if state.is_empty() {
+                        return self.error_here("Video state cannot be empty.");
+                    }
+                    TargetProperty::VideoState(state)
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(PropertyDecl { pos, property }))
+    }
+
+    fn parse_monitor_decl(&mut self) -> Result<MonitorDecl, ParseError> {
+        let pos = self.advance().pos;
+        let var_name = self.parse_variable_field_name()?;
+        let (mut x, mut y) = (0.0, 0.0);
+        if self.current_word().as_deref() == Some("at") {
+            self.advance();
+            x = self.parse_monitor_coordinate("x")?;
+            y = self.parse_monitor_coordinate("y")?;
+        }
+        let mut mode = "default".to_string();
+        if self.current_word().as_deref() == Some("mode") {
+            self.advance();
+            mode = self.parse_bracket_menu_text()?;
+            if mode.is_empty() {
+                return self.error_here("Monitor mode cannot be empty.");
+            }
+        }
+        Ok(MonitorDecl {
+            pos,
+            var_name,
+            x,
+            y,
+            mode,
+        })
+    }
+
+    fn parse_monitor_coordinate(&mut self, axis: &str) -> Result<f64, ParseError> {
+        self.consume_type(
+            TokenType::LParen,
+            &format!("Expected '(' before monitor {} position.", axis),
+        )?;
+        let value = self.parse_property_number(&format!("monitor {}", axis))?;
+        self.consume_type(
+            TokenType::RParen,
+            &format!("Expected ')' after monitor {} position.", axis),
+        )?;
+        Ok(value)
+    }
+
+    fn parse_property_number(&mut self, property: &str) -> Result<f64, ParseError> {
+        let context = format!("{} property", property);
+        match self.parse_initializer_value(&context)? {
+            InitialValue::Number(value) => Ok(value),
+            InitialValue::String(_) => {
+                let pos = self.previous().pos;
+                Err(ParseError {
+                    message: format!("Expected number in {}.", context),
+                    pos,
+                })
+            }
+        }
+    }
+
     fn parse_procedure(&mut self, pos: Position) -> Result<Procedure, ParseError> {
         let mut run_without_screen_refresh = false;
         if self.check_type(TokenType::Op) && self.current().value == "!" {
@@ -179,14 +369,32 @@ impl Parser {
         }
         let name = self.parse_decl_name_token()?;
         let mut params = Vec::new();
-        while self.check_type(TokenType::LParen) {
-            self.consume_type(TokenType::LParen, "Expected '('.")?;
-            if self.check_type(TokenType::RParen) {
-                return self.error_here("Empty parameter declaration is not allowed.");
+        let mut param_kinds = Vec::new();
+        loop {
+            if self.check_type(TokenType::LParen) {
+                self.advance();
+                if self.check_type(TokenType::RParen) {
+                    return self.error_here("Empty parameter declaration is not allowed.");
+                }
+                let param = self.parse_decl_name_token()?;
+                self.consume_type(TokenType::RParen, "Expected ')' after parameter name.")?;
+                params.push(param);
+                param_kinds.push(ParamKind::StringNumber);
+            } else if self.check_type(TokenType::Op) && self.current().value == "<" {
+                self.advance();
+                if self.check_type(TokenType::Op) && self.current().value == ">" {
+                    return self.error_here("Empty parameter declaration is not allowed.");
+                }
+                let param = self.parse_decl_name_token()?;
+                if !(self.check_type(TokenType::Op) && self.current().value == ">") {
+                    return self.error_here("Expected '>' after boolean parameter name.");
+                }
+                self.advance();
+                params.push(param);
+                param_kinds.push(ParamKind::Boolean);
+            } else {
+                break;
             }
-            let param = self.parse_decl_name_token()?;
-            self.consume_type(TokenType::RParen, "Expected ')' after parameter name.")?;
-            params.push(param);
         }
         run_without_screen_refresh =
             run_without_screen_refresh || self.try_parse_run_without_screen_refresh();
@@ -197,8 +405,10 @@ impl Parser {
             pos,
             name,
             params,
+            param_kinds,
             run_without_screen_refresh,
             body,
+            comment: None,
         })
     }
 
@@ -278,12 +488,50 @@ impl Parser {
             )?;
             EventType::WhenThisSpriteClicked
         } else if self.match_keyword("i") {
-            self.consume_keyword("receive", "Expected 'receive' after 'when I'.")?;
-            let msg = self.parse_bracket_text()?;
-            if msg.is_empty() {
-                return self.error_here("Broadcast message cannot be empty.");
+            if self.match_keyword("start") {
+                self.consume_word("as", "Expected 'as' in 'when I start as a clone'.")?;
+                self.consume_word("a", "Expected 'a' in 'when I start as a clone'.")?;
+                self.consume_keyword("clone", "Expected 'clone' in 'when I start as a clone'.")?;
+                EventType::WhenStartAsClone
+            } else {
+                self.consume_keyword("receive", "Expected 'receive' after 'when I'.")?;
+                let msg = self.parse_bracket_text()?;
+                if msg.is_empty() {
+                    return self.error_here("Broadcast message cannot be empty.");
+                }
+                EventType::WhenIReceive(msg)
+            }
+        } else if self.match_keyword("backdrop") {
+            self.consume_word(
+                "switches",
+                "Expected 'switches' in 'when backdrop switches to [name]'.",
+            )?;
+            self.consume_keyword("to", "Expected 'to' in 'when backdrop switches to [name]'.")?;
+            let backdrop = self.parse_bracket_text()?;
+            if backdrop.is_empty() {
+                return self.error_here("Backdrop name cannot be empty in backdrop switch event.");
+            }
+            EventType::WhenBackdropSwitches(backdrop)
+        } else if matches!(self.current_word().as_deref(), Some("loudness") | Some("timer")) {
+            let sensor = self.advance().value.to_lowercase();
+            if !(self.check_type(TokenType::Op) && self.current().value == ">") {
+                return self.error_here(format!("Expected '>' in 'when {} > (value)'.", sensor));
+            }
+            self.advance();
+            let value = self.parse_wrapped_expression()?;
+            EventType::WhenGreaterThan { sensor, value }
+        } else if self.match_keyword("key") {
+            let key_name = self.parse_bracket_text()?;
+            if key_name.is_empty() {
+                return self.error_here("Key name cannot be empty in key press event.");
             }
-            EventType::WhenIReceive(msg)
+            let word = self.current_word();
+            if word.as_deref() == Some("pressed") || word.as_deref() == Some("pressed?") {
+                self.advance();
+            } else {
+                return self.error_here("Expected 'pressed' in 'when key [key] pressed'.");
+            }
+            EventType::WhenKeyPressed(key_name)
         } else if self.check_type(TokenType::LBracket) {
             let key_name = self.parse_bracket_text()?;
             if key_name.is_empty() {
@@ -301,8 +549,10 @@ impl Parser {
             return self.error_here("Unknown event header after 'when'.");
         };
         self.skip_newlines();
-        let body = self
-            .parse_statement_block(&["when", "define", "var", "list", "costume", "end"], false)?;
+        let body = self.parse_statement_block(
+            &["when", "define", "var", "list", "costume", "sound", "end"],
+            false,
+        )?;
         if self.check_keyword("end") && self.looks_like_event_end() {
             self.advance();
         }
@@ -310,6 +560,7 @@ impl Parser {
             pos,
             event_type,
             body,
+            comment: None,
         })
     }
 
@@ -322,6 +573,19 @@ impl Parser {
         let mut statements = Vec::new();
         loop {
             self.skip_newlines();
+            if self.check_type(TokenType::DocComment) {
+                // Doc comments belong to the next script or procedure; anywhere else they are
+                // plain comments.
+                let next = self.token_after_doc_comments();
+                if next.typ == TokenType::Keyword
+                    && matches!(next.value.as_str(), "when" | "define" | "reporter")
+                    && end_set.contains(next.value.as_str())
+                {
+                    break;
+                }
+                self.skip_doc_comments();
+                continue;
+            }
             if self.at_end() {
                 break;
             }
@@ -443,6 +707,14 @@ impl Parser {
         if self.check_keyword("replace") {
             return self.parse_replace_list_stmt();
         }
+        if self.current_word().as_deref() == Some("rest")
+            && self.word_at_offset(1).as_deref() == Some("for")
+        {
+            return self.parse_rest_stmt();
+        }
+        if self.current_word().as_deref() == Some("raw") && self.peek().typ == TokenType::RawJson {
+            return self.parse_raw_stmt();
+        }
         if self.check_type(TokenType::Ident)
             || self.check_type(TokenType::String)
             || self.check_type(TokenType::Number)
@@ -452,6 +724,16 @@ impl Parser {
         self.error_here("Unknown statement.")
     }
 
+    fn parse_raw_stmt(&mut self) -> Result<Statement, ParseError> {
+        let start = self.advance().pos;
+        let token = self.advance();
+        let block = serde_json::from_str(&token.value).map_err(|err| ParseError {
+            message: format!("Invalid JSON in raw block: {}.", err),
+            pos: token.pos,
+        })?;
+        Ok(Statement::RawBlock { pos: start, block })
+    }
+
     fn parse_broadcast_stmt(&mut self) -> Result<Statement, ParseError> {
         let start = self
             .consume_keyword("broadcast", "Expected 'broadcast'.")?
@@ -462,10 +744,16 @@ impl Parser {
         } else {
             false
         };
-        let message = self.parse_bracket_text()?;
-        if message.is_empty() {
-            return self.error_here("Broadcast message cannot be empty.");
-        }
+        let message = if self.check_type(TokenType::LParen) {
+            self.parse_wrapped_expression()?
+        } else {
+            let pos = self.current().pos;
+            let value = self.parse_bracket_text()?;
+            if value.is_empty() {
+                return self.error_here("Broadcast message cannot be empty.");
+            }
+            Expr::String { pos, value }
+        };
         if wait {
             return Ok(Statement::BroadcastAndWait {
                 pos: start,
@@ -497,7 +785,7 @@ impl Parser {
         }
         if self.match_keyword("rotation") {
             self.consume_keyword("style", "Expected 'style' in 'set rotation style ...'.")?;
-            let style = self.parse_bracket_text()?;
+            let style = self.parse_bracket_menu_text()?;
             if style.is_empty() {
                 return self.error_here("Rotation style cannot be empty.");
             }
@@ -531,6 +819,30 @@ impl Parser {
                 value,
             });
         }
+        if self.current_word().as_deref() == Some("tempo") {
+            self.advance();
+            self.consume_keyword("to", "Expected 'to' in 'set tempo to ...'.")?;
+            let value = self.parse_wrapped_expression()?;
+            return Ok(Statement::SetTempoTo { pos: start, value });
+        }
+        if self.current_word().as_deref() == Some("instrument")
+            && self.word_at_offset(1).as_deref() == Some("to")
+            && self
+                .tokens
+                .get(self.index + 2)
+                .map(|t| t.typ == TokenType::LBracket)
+                .unwrap_or(false)
+        {
+            self.index += 2;
+            let instrument = self.parse_bracket_menu_text()?;
+            if instrument.is_empty() {
+                return self.error_here("Instrument name cannot be empty.");
+            }
+            return Ok(Statement::SetInstrumentTo {
+                pos: start,
+                instrument,
+            });
+        }
         if self.match_keyword("volume") {
             self.consume_keyword("to", "Expected 'to' in 'set volume to ...'.")?;
             let value = self.parse_wrapped_expression()?;
@@ -586,6 +898,37 @@ impl Parser {
         if self.match_keyword("pen") {
             return self.parse_change_pen_stmt(start);
         }
+        if self.match_keyword("sound") {
+            let effect = if self.match_keyword("effect") {
+                self.parse_bracket_text()?
+            } else if matches!(self.current_word().as_deref(), Some("pitch") | Some("pan")) {
+                self.advance().value.to_lowercase()
+            } else {
+                return self
+                    .error_here("Expected 'pitch', 'pan', or 'effect' after 'change sound'.");
+            };
+            if effect.is_empty() {
+                return self.error_here("Sound effect name cannot be empty.");
+            }
+            self.consume_keyword("by", "Expected 'by' in 'change sound ... by ...'.")?;
+            let value = self.parse_wrapped_expression()?;
+            return Ok(Statement::ChangeSoundEffectBy {
+                pos: start,
+                effect,
+                value,
+            });
+        }
+        if self.current_word().as_deref() == Some("tempo") {
+            self.advance();
+            self.consume_keyword("by", "Expected 'by' in 'change tempo by'.")?;
+            let value = self.parse_wrapped_expression()?;
+            return Ok(Statement::ChangeTempoBy { pos: start, value });
+        }
+        if self.match_keyword("volume") {
+            self.consume_keyword("by", "Expected 'by' in 'change volume by'.")?;
+            let value = self.parse_wrapped_expression()?;
+            return Ok(Statement::ChangeVolumeBy { pos: start, value });
+        }
         let var_name = self.parse_variable_field_name()?;
         self.consume_keyword("by", "Expected 'by' in change statement.")?;
         let delta = self.parse_wrapped_expression()?;
@@ -635,6 +978,21 @@ impl Parser {
     fn parse_think_stmt(&mut self) -> Result<Statement, ParseError> {
         let start = self.consume_keyword("think", "Expected 'think'.")?.pos;
         let message = self.parse_wrapped_expression()?;
+        if self.match_keyword("for") {
+            let duration = self.parse_wrapped_expression()?;
+            if !self.match_keyword("seconds") && self.check_type(TokenType::LBracket) {
+                let unit = self.parse_bracket_text()?;
+                if !unit.eq_ignore_ascii_case("seconds") {
+                    return self
+                        .error_here("Expected 'seconds' or '[seconds]' after think duration.");
+                }
+            }
+            return Ok(Statement::ThinkForSeconds {
+                pos: start,
+                message,
+                duration,
+            });
+        }
         Ok(Statement::Think {
             pos: start,
             message,
@@ -656,7 +1014,7 @@ impl Parser {
                 y,
             });
         }
-        let target = self.parse_wrapped_expression()?;
+        let target = self.parse_motion_target_expr()?;
         Ok(Statement::GlideToTarget {
             pos: start,
             duration,
@@ -664,6 +1022,20 @@ impl Parser {
         })
     }
 
+    /// Parses a motion menu target: either `[mouse-pointer]`-style bracket text
+    /// or a wrapped expression.
+    fn parse_motion_target_expr(&mut self) -> Result<Expr, ParseError> {
+        if !self.check_type(TokenType::LBracket) {
+            return self.parse_wrapped_expression();
+        }
+        let pos = self.current().pos;
+        let value = self.parse_bracket_menu_text()?;
+        if value.is_empty() {
+            return self.error_here("Motion target cannot be empty.");
+        }
+        Ok(Expr::String { pos, value })
+    }
+
     fn parse_repeat_stmt(&mut self) -> Result<Statement, ParseError> {
         let start = self.consume_keyword("repeat", "Expected 'repeat'.")?.pos;
         if self.match_keyword("until") {
@@ -767,9 +1139,19 @@ impl Parser {
                 return Ok(Statement::GoToXY { pos: start, x, y });
             }
             if self.check_type(TokenType::LBracket) {
-                let layer = self.parse_bracket_text()?;
-                self.consume_keyword("layer", "Expected 'layer' in 'go to [front/back] layer'.")?;
-                return Ok(Statement::GoToLayer { pos: start, layer });
+                let pos = self.current().pos;
+                let name = self.parse_bracket_menu_text()?;
+                if self.match_keyword("layer") {
+                    return Ok(Statement::GoToLayer {
+                        pos: start,
+                        layer: name,
+                    });
+                }
+                if name.is_empty() {
+                    return self.error_here("Go to target cannot be empty.");
+                }
+                let target = Expr::String { pos, value: name };
+                return Ok(Statement::GoToTarget { pos: start, target });
             }
             let target = self.parse_wrapped_expression()?;
             return Ok(Statement::GoToTarget { pos: start, target });
@@ -803,7 +1185,7 @@ impl Parser {
             });
         }
         if self.match_keyword("towards") {
-            let target = self.parse_wrapped_expression()?;
+            let target = self.parse_motion_target_expr()?;
             return Ok(Statement::PointTowards { pos: start, target });
         }
         self.error_here("Expected 'in direction' or 'towards' after 'point'.")
@@ -818,6 +1200,13 @@ impl Parser {
                 var_name,
             });
         }
+        if self.match_keyword("list") {
+            let list_name = self.parse_list_field_name()?;
+            return Ok(Statement::ShowList {
+                pos: start,
+                list_name,
+            });
+        }
         Ok(Statement::Show { pos: start })
     }
 
@@ -830,6 +1219,13 @@ impl Parser {
                 var_name,
             });
         }
+        if self.match_keyword("list") {
+            let list_name = self.parse_list_field_name()?;
+            return Ok(Statement::HideList {
+                pos: start,
+                list_name,
+            });
+        }
         Ok(Statement::Hide { pos: start })
     }
 
@@ -857,6 +1253,16 @@ impl Parser {
         if self.match_keyword("backdrop") {
             self.consume_keyword("to", "Expected 'to' in 'switch backdrop to'.")?;
             let backdrop = self.parse_wrapped_expression()?;
+            if self.match_keyword("and") {
+                self.consume_keyword(
+                    "wait",
+                    "Expected 'wait' in 'switch backdrop to ... and wait'.",
+                )?;
+                return Ok(Statement::SwitchBackdropToAndWait {
+                    pos: start,
+                    backdrop,
+                });
+            }
             return Ok(Statement::SwitchBackdropTo {
                 pos: start,
                 backdrop,
@@ -911,7 +1317,15 @@ impl Parser {
             self.consume_keyword("sounds", "Expected 'sounds' in 'stop all sounds'.")?;
             return Ok(Statement::StopAllSounds { pos: start });
         }
-        let option = self.parse_wrapped_expression()?;
+        let option = if self.check_type(TokenType::LBracket) {
+            let pos = self.current().pos;
+            Expr::String {
+                pos,
+                value: self.parse_bracket_text()?,
+            }
+        } else {
+            self.parse_wrapped_expression()?
+        };
         Ok(Statement::Stop { pos: start, option })
     }
 
@@ -929,21 +1343,75 @@ impl Parser {
         if !self.match_keyword("sound") {
             return self.parse_keyword_call_stmt(start, "start");
         }
-        let sound = self.parse_wrapped_expression()?;
+        let sound = self.parse_sound_menu_expr()?;
         Ok(Statement::StartSound { pos: start, sound })
     }
 
     fn parse_play_stmt(&mut self) -> Result<Statement, ParseError> {
         let start = self.consume_keyword("play", "Expected 'play'.")?.pos;
+        if self.current_word().as_deref() == Some("note") {
+            self.advance();
+            let note = self.parse_wrapped_expression()?;
+            let beats = self.parse_for_beats("play note")?;
+            return Ok(Statement::PlayNoteForBeats {
+                pos: start,
+                note,
+                beats,
+            });
+        }
+        if self.current_word().as_deref() == Some("drum") {
+            self.advance();
+            let drum = self.parse_bracket_menu_text()?;
+            if drum.is_empty() {
+                return self.error_here("Drum name cannot be empty.");
+            }
+            let beats = self.parse_for_beats("play drum")?;
+            return Ok(Statement::PlayDrumForBeats {
+                pos: start,
+                drum,
+                beats,
+            });
+        }
         if !self.match_keyword("sound") {
             return self.parse_keyword_call_stmt(start, "play");
         }
-        let sound = self.parse_wrapped_expression()?;
+        let sound = self.parse_sound_menu_expr()?;
         self.consume_keyword("until", "Expected 'until' in 'play sound ... until done'.")?;
         self.consume_keyword("done", "Expected 'done' in 'play sound ... until done'.")?;
         Ok(Statement::PlaySoundUntilDone { pos: start, sound })
     }
 
+    fn parse_rest_stmt(&mut self) -> Result<Statement, ParseError> {
+        let start = self.consume_word("rest", "Expected 'rest'.")?.pos;
+        let beats = self.parse_for_beats("rest")?;
+        Ok(Statement::RestForBeats { pos: start, beats })
+    }
+
+    fn parse_for_beats(&mut self, context: &str) -> Result<Expr, ParseError> {
+        self.consume_keyword(
+            "for",
+            &format!("Expected 'for' in '{} ... for (beats) beats'.", context),
+        )?;
+        let beats = self.parse_wrapped_expression()?;
+        self.consume_word(
+            "beats",
+            &format!("Expected 'beats' in '{} ... for (beats) beats'.", context),
+        )?;
+        Ok(beats)
+    }
+
+    fn parse_sound_menu_expr(&mut self) -> Result<Expr, ParseError> {
+        if !self.check_type(TokenType::LBracket) {
+            return self.parse_wrapped_expression();
+        }
+        let pos = self.current().pos;
+        let value = self.parse_bracket_menu_text()?;
+        if value.is_empty() {
+            return self.error_here("Sound name cannot be empty.");
+        }
+        Ok(Expr::String { pos, value })
+    }
+
     fn parse_reset_stmt(&mut self) -> Result<Statement, ParseError> {
         let start = self.consume_keyword("reset", "Expected 'reset'.")?.pos;
         if self.match_keyword("timer") {
@@ -987,6 +1455,10 @@ impl Parser {
             self.consume_keyword("effects", "Expected 'effects' in 'clear graphic effects'.")?;
             return Ok(Statement::ClearGraphicEffects { pos: start });
         }
+        if self.match_keyword("sound") {
+            self.consume_keyword("effects", "Expected 'effects' in 'clear sound effects'.")?;
+            return Ok(Statement::ClearSoundEffects { pos: start });
+        }
         self.parse_keyword_call_stmt(start, "clear")
     }
 
@@ -1064,7 +1536,15 @@ impl Parser {
         }
         if is_pen_color_param(param.as_str()) {
             self.consume_keyword("to", "Expected 'to' in 'set pen <param> to'.")?;
+            // `set pen color to color (expr)` sets the pen to a computed color.
+            let picked = param == "color" && self.match_keyword("color");
             let value = self.parse_wrapped_expression()?;
+            if picked || (param == "color" && matches!(value, Expr::Color { .. })) {
+                return Ok(Statement::SetPenColorTo {
+                    pos: start,
+                    color: value,
+                });
+            }
             return Ok(Statement::SetPenColorParamTo {
                 pos: start,
                 param,
@@ -1347,6 +1827,9 @@ impl Parser {
         if self.check_keyword("pick") {
             return self.parse_pick_random_expr();
         }
+        if self.check_keyword("item") && self.word_at_offset(1).as_deref() == Some("number") {
+            return self.parse_item_number_of_list_expr();
+        }
         if self.check_keyword("item") && self.peek().typ == TokenType::LParen {
             return self.parse_item_of_list_expr();
         }
@@ -1371,8 +1854,12 @@ impl Parser {
         if self.check_keyword("key") && self.peek().typ == TokenType::LParen {
             return self.parse_key_pressed_expr();
         }
+        if self.check_keyword("color") && self.peek().typ == TokenType::LParen {
+            return self.parse_color_touching_color_expr();
+        }
         if self.check_keyword("touching")
             && (self.peek().typ == TokenType::LParen
+                || self.peek().typ == TokenType::LBracket
                 || self
                     .word_at_offset(1)
                     .as_deref()
@@ -1390,6 +1877,10 @@ impl Parser {
         if self.check_keyword("join") && self.peek().typ == TokenType::LParen {
             return self.parse_join_expr();
         }
+        if self.current_word().as_deref() == Some("letter") && self.peek().typ == TokenType::LParen
+        {
+            return self.parse_letter_of_expr();
+        }
         if (token.typ == TokenType::Ident || token.typ == TokenType::Keyword)
             && is_math_func_name(&token.value)
             && self.peek().typ == TokenType::LParen
@@ -1423,7 +1914,16 @@ impl Parser {
                     kind: "mouse_y".to_string(),
                 });
             }
-            return self.error_here("Expected 'x' or 'y' after 'mouse'.");
+            let word = self.current_word();
+            if word.as_deref() == Some("down") || word.as_deref() == Some("down?") {
+                self.advance();
+                self.match_question_mark();
+                return Ok(Expr::BuiltinReporter {
+                    pos: start,
+                    kind: "mouse_down".to_string(),
+                });
+            }
+            return self.error_here("Expected 'x', 'y', or 'down?' after 'mouse'.");
         }
         if self.check_keyword("timer") {
             let start = self.consume_keyword("timer", "Expected 'timer'.")?.pos;
@@ -1432,6 +1932,49 @@ impl Parser {
                 kind: "timer".to_string(),
             });
         }
+        if (self.check_keyword("x") || self.check_keyword("y"))
+            && self.word_at_offset(1).as_deref() == Some("position")
+        {
+            let token = self.advance();
+            self.advance();
+            return Ok(Expr::BuiltinReporter {
+                pos: token.pos,
+                kind: format!("{}_position", token.value),
+            });
+        }
+        if (self.check_keyword("costume") || self.check_keyword("backdrop"))
+            && matches!(
+                self.word_at_offset(1).as_deref(),
+                Some("number") | Some("name")
+            )
+        {
+            let token = self.advance();
+            let number_name = self.advance().value.to_lowercase();
+            return Ok(Expr::LooksNumberName {
+                pos: token.pos,
+                backdrop: token.value == "backdrop",
+                number_name,
+            });
+        }
+        if let Some(kind) = self.contextual_reporter() {
+            let start = self.advance().pos;
+            return Ok(Expr::BuiltinReporter { pos: start, kind });
+        }
+        if self.current_word().as_deref() == Some("days")
+            && self.word_at_offset(1).as_deref() == Some("since")
+            && self
+                .tokens
+                .get(self.index + 2)
+                .map(|t| t.typ == TokenType::Number && t.value == "2000")
+                .unwrap_or(false)
+        {
+            let start = self.current().pos;
+            self.index += 3;
+            return Ok(Expr::BuiltinReporter {
+                pos: start,
+                kind: "days_since_2000".to_string(),
+            });
+        }
         if token.typ == TokenType::Number {
             self.advance();
             let value = parse_number_literal(&token.value).unwrap_or(0.0);
@@ -1447,6 +1990,13 @@ impl Parser {
                 value: token.value,
             });
         }
+        if token.typ == TokenType::Color {
+            self.advance();
+            return Ok(Expr::Color {
+                pos: token.pos,
+                value: token.value,
+            });
+        }
         if token.typ == TokenType::Ident {
             if self.peek().typ == TokenType::LParen {
                 return Err(ParseError {
@@ -1491,6 +2041,15 @@ impl Parser {
             }
             let expr = self.parse_expression(&[TokenType::RParen], 1)?;
             self.consume_type(TokenType::RParen, "Expected ')' after grouped expression.")?;
+            if self.match_keyword("contains") {
+                let substring = self.parse_wrapped_expression()?;
+                self.match_question_mark();
+                return Ok(Expr::StringContains {
+                    pos: token.pos,
+                    text: Box::new(expr),
+                    substring: Box::new(substring),
+                });
+            }
             return Ok(expr);
         }
         if token.typ == TokenType::LBracket {
@@ -1536,6 +2095,23 @@ impl Parser {
         })
     }
 
+    fn parse_item_number_of_list_expr(&mut self) -> Result<Expr, ParseError> {
+        let start = self.consume_keyword("item", "Expected 'item'.")?.pos;
+        self.consume_word(
+            "number",
+            "Expected 'number' in 'item number of (...) in [list]'.",
+        )?;
+        self.consume_keyword("of", "Expected 'of' in 'item number of (...) in [list]'.")?;
+        let item = self.parse_wrapped_expression()?;
+        self.consume_keyword("in", "Expected 'in' in 'item number of (...) in [list]'.")?;
+        let list_name = self.parse_list_field_name()?;
+        Ok(Expr::ItemNumOfList {
+            pos: start,
+            list_name,
+            item: Box::new(item),
+        })
+    }
+
     fn parse_length_expr(&mut self) -> Result<Expr, ParseError> {
         let start = self.consume_keyword("length", "Expected 'length'.")?.pos;
         self.consume_keyword("of", "Expected 'of' in 'length of ...'.")?;
@@ -1546,7 +2122,14 @@ impl Parser {
                 list_name,
             });
         }
-        self.error_here("Expected list reference after 'length of'.")
+        if self.check_type(TokenType::LParen) {
+            let text = self.parse_wrapped_expression()?;
+            return Ok(Expr::StringLength {
+                pos: start,
+                text: Box::new(text),
+            });
+        }
+        self.error_here("Expected list reference or '(expr)' after 'length of'.")
     }
 
     fn parse_contents_expr(&mut self) -> Result<Expr, ParseError> {
@@ -1585,6 +2168,7 @@ impl Parser {
             .pos;
         if self.match_keyword("color") {
             let color = self.parse_wrapped_expression()?;
+            self.match_question_mark();
             return Ok(Expr::TouchingColor {
                 pos: start,
                 color: Box::new(color),
@@ -1594,17 +2178,44 @@ impl Parser {
         if self.match_keyword("sprite") || self.match_keyword("object") {
             // consume optional target qualifier keyword
         }
-        let target = self.parse_wrapped_expression()?;
+        let target = if self.check_type(TokenType::LBracket) {
+            let pos = self.current().pos;
+            let value = self.parse_bracket_menu_text()?;
+            if value.is_empty() {
+                return self.error_here("Touching target cannot be empty.");
+            }
+            Expr::String { pos, value }
+        } else {
+            self.parse_wrapped_expression()?
+        };
+        self.match_question_mark();
         Ok(Expr::TouchingObject {
             pos: start,
             target: Box::new(target),
         })
     }
 
+    fn parse_color_touching_color_expr(&mut self) -> Result<Expr, ParseError> {
+        let start = self.consume_keyword("color", "Expected 'color'.")?.pos;
+        let color = self.parse_wrapped_expression()?;
+        self.consume_word("is", "Expected 'is' in 'color (...) is touching (...)?'.")?;
+        self.consume_keyword(
+            "touching",
+            "Expected 'touching' in 'color (...) is touching (...)?'.",
+        )?;
+        let color2 = self.parse_wrapped_expression()?;
+        self.match_question_mark();
+        Ok(Expr::ColorTouchingColor {
+            pos: start,
+            color: Box::new(color),
+            color2: Box::new(color2),
+        })
+    }
+
     fn parse_join_expr(&mut self) -> Result<Expr, ParseError> {
         let start = self.consume_keyword("join", "Expected 'join'.")?.pos;
         let text1 = self.parse_wrapped_expression()?;
-        self.consume_keyword("with", "Expected 'with' in 'join (...) with (...)'.")?;
+        self.match_keyword("with");
         let text2 = self.parse_wrapped_expression()?;
         Ok(Expr::StringJoin {
             pos: start,
@@ -1613,6 +2224,18 @@ impl Parser {
         })
     }
 
+    fn parse_letter_of_expr(&mut self) -> Result<Expr, ParseError> {
+        let start = self.advance().pos;
+        let index = self.parse_wrapped_expression()?;
+        self.consume_keyword("of", "Expected 'of' in 'letter (...) of (...)'.")?;
+        let text = self.parse_wrapped_expression()?;
+        Ok(Expr::LetterOf {
+            pos: start,
+            index: Box::new(index),
+            text: Box::new(text),
+        })
+    }
+
     fn parse_split_expr(&mut self) -> Result<Expr, ParseError> {
         let start = self.consume_keyword("split", "Expected 'split'.")?.pos;
         let text = self.parse_wrapped_expression()?;
@@ -1694,6 +2317,23 @@ impl Parser {
             .to_string())
     }
 
+    /// Reads bracket text for a menu option, keeping hyphenated names like
+    /// `mouse-pointer` intact.
+    fn parse_bracket_menu_text(&mut self) -> Result<String, ParseError> {
+        let contents = self.parse_bracket_tokens()?;
+        let mut text = String::new();
+        let mut glue_next = true;
+        for token in &contents {
+            let is_dash = token.typ == TokenType::Op && token.value == "-";
+            if !glue_next && !is_dash {
+                text.push(' ');
+            }
+            text.push_str(&token.value);
+            glue_next = is_dash;
+        }
+        Ok(text.trim().to_string())
+    }
+
     fn parse_bracket_tokens(&mut self) -> Result<Vec<Token>, ParseError> {
         self.consume_type(TokenType::LBracket, "Expected '['.")?;
         let mut tokens = Vec::new();
@@ -1932,6 +2572,29 @@ impl Parser {
         }
     }
 
+    /// The reporter named by the current word, if it is one of
+    /// [`CONTEXTUAL_REPORTERS`] and not a `name #` variable reference.
+    fn contextual_reporter(&self) -> Option<String> {
+        let word = self.current_word()?;
+        let var_suffix = self.peek().typ == TokenType::Op && self.peek().value == "#";
+        (CONTEXTUAL_REPORTERS.contains(&word.as_str()) && !var_suffix).then_some(word)
+    }
+
+    fn consume_word(&mut self, word: &str, message: &str) -> Result<Token, ParseError> {
+        if self.current_word().as_deref() == Some(word) {
+            return Ok(self.advance());
+        }
+        self.error_here(message)
+    }
+
+    fn match_question_mark(&mut self) -> bool {
+        if self.check_type(TokenType::Op) && self.current().value == "?" {
+            self.advance();
+            return true;
+        }
+        false
+    }
+
     fn check_keyword(&self, keyword: &str) -> bool {
         let token = self.current();
         token.typ == TokenType::Keyword && token.value == keyword
@@ -1939,7 +2602,12 @@ impl Parser {
 
     fn looks_like_event_end(&self) -> bool {
         let mut idx = self.index + 1;
-        while idx < self.tokens.len() && self.tokens[idx].typ == TokenType::Newline {
+        while idx < self.tokens.len()
+            && matches!(
+                self.tokens[idx].typ,
+                TokenType::Newline | TokenType::DocComment
+            )
+        {
             idx += 1;
         }
         if idx >= self.tokens.len() {
@@ -2007,6 +2675,39 @@ impl Parser {
         }
     }
 
+    fn skip_doc_comments(&mut self) {
+        while self.check_type(TokenType::Newline) || self.check_type(TokenType::DocComment) {
+            self.advance();
+        }
+    }
+
+    /// Collects consecutive `##` lines into one comment text.
+    fn take_doc_comment(&mut self) -> Option<String> {
+        let mut lines = Vec::new();
+        while self.check_type(TokenType::DocComment) {
+            lines.push(self.advance().value);
+            self.skip_newlines();
+        }
+        if lines.is_empty() {
+            None
+        } else {
+            Some(lines.join("\n"))
+        }
+    }
+
+    fn token_after_doc_comments(&self) -> &Token {
+        let mut idx = self.index;
+        while idx + 1 < self.tokens.len()
+            && matches!(
+                self.tokens[idx].typ,
+                TokenType::Newline | TokenType::DocComment
+            )
+        {
+            idx += 1;
+        }
+        &self.tokens[idx]
+    }
+
     fn at_end(&self) -> bool {
         self.current().typ == TokenType::Eof
     }
@@ -2077,6 +2778,75 @@ fn is_math_func_name(name: &str) -> bool {
     )
 }
 
+/// Turns each contextual reporter back into a variable read where a variable
+/// (the target's own or the stage's) or parameter of that name is in scope.
+fn shadow_contextual_reporters(targets: &mut [Target]) {
+    let names_of = |variables: &[VariableDecl]| {
+        variables
+            .iter()
+            .map(|decl| (decl.name.to_lowercase(), decl.name.clone()))
+            .collect::<HashMap<_, _>>()
+    };
+    let globals = targets
+        .iter()
+        .find(|target| target.is_stage)
+        .map(|stage| names_of(&stage.variables))
+        .unwrap_or_default();
+    for target in targets {
+        let mut names = globals.clone();
+        names.extend(names_of(&target.variables));
+        for script in &mut target.scripts {
+            if let EventType::WhenGreaterThan { value, .. } = &mut script.event_type {
+                shadow_in_expr(value, &names);
+            }
+            shadow_in_body(&mut script.body, &names);
+        }
+        let bodies = target
+            .procedures
+            .iter_mut()
+            .map(|procedure| (&procedure.params, &mut procedure.body))
+            .chain(
+                target
+                    .reporters
+                    .iter_mut()
+                    .map(|reporter| (&reporter.params, &mut reporter.body)),
+            );
+        for (params, body) in bodies {
+            let mut scope = names.clone();
+            scope.extend(
+                params
+                    .iter()
+                    .map(|param| (param.to_lowercase(), param.clone())),
+            );
+            shadow_in_body(body, &scope);
+        }
+    }
+}
+
+fn shadow_in_body(body: &mut [Statement], names: &HashMap<String, String>) {
+    walk_statements_mut(body, &mut |statement| {
+        for expr in statement.children_mut().0 {
+            shadow_in_expr(expr, names);
+        }
+    });
+}
+
+fn shadow_in_expr(expr: &mut Expr, names: &HashMap<String, String>) {
+    walk_exprs_mut(expr, &mut |expr| {
+        if let Expr::BuiltinReporter { pos, kind } = expr {
+            if let Some(name) = names
+                .get(kind.as_str())
+                .filter(|_| CONTEXTUAL_REPORTERS.contains(&kind.as_str()))
+            {
+                *expr = Expr::Var {
+                    pos: *pos,
+                    name: name.clone(),
+                };
+            }
+        }
+    });
+}
+
 fn append_procedure_name_part(name: &mut String, part: &str) {
     if name.is_empty() {
         name.push_str(part);
@@ -0,0 +1,36 @@
+//! Names of the broadcasts, variables, and lists generated for remote procedure
+//! calls, shared by the compiler, the checker, and the decompiler.
+
+/// Prefix of every broadcast, variable, and list generated for remote calls.
+pub(crate) const RPC_PREFIX: &str = "__rpc__";
+
+/// Names the compiler generates for remote calls to `target.procedure`.
+pub(crate) struct RpcNames {
+    pub message: String,
+    pub arg_vars: Vec<String>,
+    pub arg_queues: Vec<String>,
+    pub calls_list: String,
+    pub busy_var: String,
+}
+
+pub(crate) fn rpc_names(target: &str, procedure: &str, arg_count: usize) -> RpcNames {
+    let message = format!(
+        "{}{}__{}",
+        RPC_PREFIX,
+        target.to_lowercase(),
+        procedure.to_lowercase()
+    );
+    let arg_vars = (1..=arg_count)
+        .map(|i| format!("{}__arg{}", message, i))
+        .collect::<Vec<_>>();
+    RpcNames {
+        arg_queues: arg_vars
+            .iter()
+            .map(|name| format!("{}__queue", name))
+            .collect(),
+        calls_list: format!("{}__calls", message),
+        busy_var: format!("{}__busy", message),
+        arg_vars,
+        message,
+    }
+}
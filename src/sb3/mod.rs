@@ -1,8 +1,12 @@
 pub mod archive;
 pub mod model;
+pub mod normalize;
 pub mod read;
+pub mod validate;
 pub mod write;
 
 pub use model::Sb3Archive;
+pub use normalize::{normalize_project, NormalizedProject};
 pub use read::{read_sb3_bytes, read_sb3_file};
+pub use validate::{validate_project_json, ValidationIssue};
 pub use write::{build_sb3_bytes, write_sb3_file};
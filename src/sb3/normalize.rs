@@ -0,0 +1,354 @@
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// A `project.json` reduced to what changes how the project behaves: no block,
+/// variable, or broadcast ids, no script positions, and targets and scripts in
+/// a stable order, so two builds of the same program compare equal.
+#[derive(Debug, Clone, Default)]
+pub struct NormalizedProject {
+    pub targets: BTreeMap<String, NormalizedTarget>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NormalizedTarget {
+    pub is_stage: bool,
+    pub variables: BTreeSet<String>,
+    pub lists: BTreeSet<String>,
+    pub costumes: Vec<NormalizedAsset>,
+    pub sounds: Vec<NormalizedAsset>,
+    /// Top-level scripts, sorted by key and then by shape.
+    pub scripts: Vec<NormalizedScript>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NormalizedAsset {
+    pub name: String,
+    pub md5ext: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct NormalizedScript {
+    /// What the script is "for": the hat block without its body, or the
+    /// proccode of a procedure definition. Scripts sharing a key are treated
+    /// as versions of one another.
+    pub key: String,
+    /// The whole script as compact JSON, with ids and positions removed and
+    /// procedure arguments numbered by position.
+    pub shape: String,
+    /// Id of the top block in the original project, to look the script up again.
+    pub top_id: String,
+}
+
+pub fn normalize_project(project: &Value) -> NormalizedProject {
+    let mut normalized = NormalizedProject::default();
+    let targets = project.get("targets").and_then(Value::as_array);
+    for target in targets.into_iter().flatten() {
+        let name = target
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or("<unnamed>");
+        normalized
+            .targets
+            .insert(name.to_string(), normalize_target(target));
+    }
+    normalized
+}
+
+pub fn normalize_target(target: &Value) -> NormalizedTarget {
+    let no_blocks = Map::new();
+    let blocks = target
+        .get("blocks")
+        .and_then(Value::as_object)
+        .unwrap_or(&no_blocks);
+    let mut scripts = blocks
+        .iter()
+        .filter(|(_, block)| is_top_level(block))
+        .map(|(id, _)| normalize_script(blocks, id))
+        .collect::<Vec<_>>();
+    scripts.sort_by(|a, b| (&a.key, &a.shape).cmp(&(&b.key, &b.shape)));
+
+    NormalizedTarget {
+        is_stage: target.get("isStage").and_then(Value::as_bool) == Some(true),
+        variables: declared_names(target.get("variables")),
+        lists: declared_names(target.get("lists")),
+        costumes: assets(target.get("costumes")),
+        sounds: assets(target.get("sounds")),
+        scripts,
+    }
+}
+
+fn is_top_level(block: &Value) -> bool {
+    match block {
+        // Loose variable/list reporters are stored as `[tag, name, id, x, y]`.
+        Value::Array(primitive) => primitive.len() >= 5,
+        _ => block.get("topLevel").and_then(Value::as_bool) == Some(true),
+    }
+}
+
+fn normalize_script(blocks: &Map<String, Value>, top_id: &str) -> NormalizedScript {
+    let mut seen = HashSet::new();
+    let shape = Value::Array(chain_shape(blocks, top_id, &mut seen));
+    let top = blocks.get(top_id);
+    let key = match top
+        .and_then(|block| block.get("opcode"))
+        .and_then(Value::as_str)
+    {
+        Some("procedures_definition") => format!("define {}", definition_proccode(blocks, top)),
+        _ => {
+            let mut hat = shape.get(0).cloned().unwrap_or(Value::Null);
+            // Keep the hat's fields but not its inputs, so editing the value
+            // in `when loudness > (10)` reads as a change, not a replacement.
+            if let Some(hat) = hat.as_object_mut() {
+                hat.remove("inputs");
+            }
+            hat.to_string()
+        }
+    };
+    NormalizedScript {
+        key,
+        shape: shape.to_string(),
+        top_id: top_id.to_string(),
+    }
+}
+
+fn definition_proccode(blocks: &Map<String, Value>, definition: Option<&Value>) -> String {
+    definition
+        .and_then(|block| block.pointer("/inputs/custom_block/1"))
+        .and_then(Value::as_str)
+        .and_then(|prototype| blocks.get(prototype))
+        .and_then(|prototype| prototype.pointer("/mutation/proccode"))
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// The blocks from `start` along `next` links. A cycle ends the chain with a
+/// `"cycle"` marker instead of looping.
+fn chain_shape(blocks: &Map<String, Value>, start: &str, seen: &mut HashSet<String>) -> Vec<Value> {
+    let mut shapes = Vec::new();
+    let mut current = Some(start.to_string());
+    while let Some(id) = current {
+        if !seen.insert(id.clone()) {
+            shapes.push(Value::String("cycle".to_string()));
+            break;
+        }
+        shapes.push(block_shape(blocks, &id, seen));
+        current = blocks
+            .get(&id)
+            .and_then(|block| block.get("next"))
+            .and_then(Value::as_str)
+            .map(ToString::to_string);
+    }
+    shapes
+}
+
+fn block_shape(blocks: &Map<String, Value>, id: &str, seen: &mut HashSet<String>) -> Value {
+    let block = match blocks.get(id) {
+        Some(Value::Array(primitive)) => return primitive_shape(primitive),
+        Some(block) => block,
+        None => return Value::String("missing".to_string()),
+    };
+    let mut shape = Map::new();
+    if let Some(opcode) = block.get("opcode") {
+        shape.insert("opcode".to_string(), opcode.clone());
+    }
+
+    // Procedure calls and prototypes key their inputs by argument id; number
+    // them by position instead.
+    let argument_ids = block
+        .pointer("/mutation/argumentids")
+        .and_then(Value::as_str)
+        .and_then(|raw| serde_json::from_str::<Vec<String>>(raw).ok())
+        .unwrap_or_default();
+    let mut inputs = BTreeMap::new();
+    for (name, input) in block
+        .get("inputs")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+    {
+        let name = match argument_ids.iter().position(|arg| arg == name) {
+            Some(index) => format!("arg{}", index),
+            None => name.clone(),
+        };
+        // An obscured shadow (the third slot) is never evaluated, so only the
+        // visible value counts.
+        let value = match input.get(1) {
+            Some(Value::String(child)) => Value::Array(chain_shape(blocks, child, seen)),
+            Some(Value::Array(primitive)) => primitive_shape(primitive),
+            _ => Value::Null,
+        };
+        inputs.insert(name, value);
+    }
+    if !inputs.is_empty() {
+        shape.insert(
+            "inputs".to_string(),
+            Value::Object(inputs.into_iter().collect()),
+        );
+    }
+
+    let fields = block
+        .get("fields")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+        .map(|(name, field)| (name.clone(), field.get(0).cloned().unwrap_or(Value::Null)))
+        .collect::<BTreeMap<_, _>>();
+    if !fields.is_empty() {
+        shape.insert(
+            "fields".to_string(),
+            Value::Object(fields.into_iter().collect()),
+        );
+    }
+
+    if let Some(mutation) = block.get("mutation").and_then(Value::as_object) {
+        let mutation = mutation
+            .iter()
+            .filter(|(key, _)| key.as_str() != "argumentids")
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect::<BTreeMap<_, _>>();
+        shape.insert(
+            "mutation".to_string(),
+            Value::Object(mutation.into_iter().collect()),
+        );
+    }
+    Value::Object(shape)
+}
+
+/// `[tag, value, id?, x?, y?]` becomes `[tag, value]`.
+fn primitive_shape(primitive: &[Value]) -> Value {
+    Value::Array(primitive.iter().take(2).cloned().collect())
+}
+
+fn declared_names(node: Option<&Value>) -> BTreeSet<String> {
+    node.and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+        .filter_map(|(_, decl)| decl.get(0).and_then(Value::as_str))
+        .map(ToString::to_string)
+        .collect()
+}
+
+fn assets(node: Option<&Value>) -> Vec<NormalizedAsset> {
+    node.and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|asset| {
+            let text = |key: &str| {
+                asset
+                    .get(key)
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            let md5ext = match asset.get("md5ext").and_then(Value::as_str) {
+                Some(md5ext) => md5ext.to_string(),
+                None => format!("{}.{}", text("assetId"), text("dataFormat")),
+            };
+            NormalizedAsset {
+                name: text("name"),
+                md5ext,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn shapes(target: &NormalizedTarget) -> Vec<&str> {
+        target.scripts.iter().map(|s| s.shape.as_str()).collect()
+    }
+
+    fn sprite(blocks: Value) -> Value {
+        json!({
+            "isStage": false,
+            "name": "Cat",
+            "variables": {"v1": ["speed", 0]},
+            "lists": {"l1": ["log", []]},
+            "costumes": [{"name": "a", "assetId": "abc", "md5ext": "abc.svg", "dataFormat": "svg"}],
+            "blocks": blocks
+        })
+    }
+
+    #[test]
+    fn ids_and_positions_do_not_matter() {
+        let first = sprite(json!({
+            "h": {"opcode": "event_whenflagclicked", "next": "s", "parent": null,
+                  "inputs": {}, "fields": {}, "topLevel": true, "x": 0, "y": 0},
+            "s": {"opcode": "data_setvariableto", "next": null, "parent": "h",
+                  "inputs": {"VALUE": [3, [12, "speed", "v1"], [10, "0"]]},
+                  "fields": {"VARIABLE": ["speed", "v1"]}, "topLevel": false},
+            "k": {"opcode": "event_whenkeypressed", "next": null, "parent": null,
+                  "inputs": {}, "fields": {"KEY_OPTION": ["space", null]},
+                  "topLevel": true, "x": 300, "y": 0}
+        }));
+        let second = sprite(json!({
+            "zz": {"opcode": "event_whenkeypressed", "next": null, "parent": null,
+                   "inputs": {}, "fields": {"KEY_OPTION": ["space", null]},
+                   "topLevel": true, "x": -40, "y": 900},
+            "q1": {"opcode": "data_setvariableto", "next": null, "parent": "q0",
+                   "inputs": {"VALUE": [3, [12, "speed", "other"], [10, "5"]]},
+                   "fields": {"VARIABLE": ["speed", "other"]}, "topLevel": false},
+            "q0": {"opcode": "event_whenflagclicked", "next": "q1", "parent": null,
+                   "inputs": {}, "fields": {}, "topLevel": true, "x": 12, "y": 7}
+        }));
+        let (first, second) = (normalize_target(&first), normalize_target(&second));
+        assert_eq!(shapes(&first), shapes(&second));
+        assert!(
+            !shapes(&first).join("").contains("v1"),
+            "{:?}",
+            shapes(&first)
+        );
+        assert_eq!(first.variables, BTreeSet::from(["speed".to_string()]));
+        assert_eq!(first.costumes[0].md5ext, "abc.svg");
+    }
+
+    #[test]
+    fn procedure_arguments_are_numbered_by_position() {
+        let define = |arg_id: &str| {
+            sprite(json!({
+                "d": {"opcode": "procedures_definition", "next": null, "parent": null,
+                      "inputs": {"custom_block": [1, "p"]}, "fields": {},
+                      "topLevel": true, "x": 0, "y": 0},
+                "p": {"opcode": "procedures_prototype", "next": null, "parent": "d",
+                      "inputs": {arg_id: [1, "r"]}, "fields": {}, "shadow": true,
+                      "topLevel": false,
+                      "mutation": {"proccode": "hop %s",
+                                   "argumentids": format!("[\"{}\"]", arg_id),
+                                   "argumentnames": "[\"n\"]",
+                                   "argumentdefaults": "[\"\"]", "warp": "false"}},
+                "r": {"opcode": "argument_reporter_string_number", "next": null,
+                      "parent": "p", "inputs": {}, "fields": {"VALUE": ["n", null]},
+                      "shadow": true, "topLevel": false}
+            }))
+        };
+        let (first, second) = (
+            normalize_target(&define("a1")),
+            normalize_target(&define("xyz")),
+        );
+        assert_eq!(shapes(&first), shapes(&second));
+        assert_eq!(first.scripts[0].key, "define hop %s");
+        assert!(first.scripts[0].shape.contains("\"arg0\""));
+    }
+
+    #[test]
+    fn body_changes_keep_the_key_but_not_the_shape() {
+        let steps = |count: &str| {
+            normalize_target(&sprite(json!({
+                "h": {"opcode": "event_whenbroadcastreceived", "next": "m", "parent": null,
+                      "inputs": {}, "fields": {"BROADCAST_OPTION": ["go", "b1"]},
+                      "topLevel": true, "x": 0, "y": 0},
+                "m": {"opcode": "motion_movesteps", "next": null, "parent": "h",
+                      "inputs": {"STEPS": [1, [4, count]]}, "fields": {},
+                      "topLevel": false}
+            })))
+        };
+        let (ten, twenty) = (steps("10"), steps("20"));
+        assert_eq!(ten.scripts[0].key, twenty.scripts[0].key);
+        assert_ne!(ten.scripts[0].shape, twenty.scripts[0].shape);
+        assert!(ten.scripts[0].key.contains("\"go\""));
+    }
+}
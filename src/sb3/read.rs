@@ -1,6 +1,8 @@
 use super::archive::read_archive_from_zip;
 use super::model::Sb3Archive;
 use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::Cursor;
 use std::path::Path;
@@ -15,9 +17,18 @@ pub fn read_sb3_bytes(bytes: &[u8]) -> Result<Sb3Archive> {
     read_sb3_bytes_with_label(bytes, "memory")
 }
 
+/// Reads an `.sb3` zip, or a bare `project.json` (as extracted by TurboWarp or
+/// served by the Scratch API), which yields an archive without assets.
 fn read_sb3_bytes_with_label(bytes: &[u8], label: &str) -> Result<Sb3Archive> {
-    let mut zip = ZipArchive::new(Cursor::new(bytes))
-        .with_context(|| format!("'{}' is not a valid zip/.sb3 file.", label))?;
+    let mut zip = match ZipArchive::new(Cursor::new(bytes)) {
+        Ok(zip) => zip,
+        Err(err) => {
+            return match serde_json::from_slice::<Value>(bytes) {
+                Ok(project) if project.is_object() => Ok(Sb3Archive::new(project, BTreeMap::new())),
+                _ => Err(err).with_context(|| format!("'{}' is not a valid zip/.sb3 file.", label)),
+            };
+        }
+    };
     let (project, assets) = read_archive_from_zip(&mut zip, label)?;
     Ok(Sb3Archive::new(project, assets))
 }
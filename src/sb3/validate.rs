@@ -0,0 +1,379 @@
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::fmt;
+
+/// A structural problem in a `project.json` that would make Scratch refuse to
+/// load the project or silently drop blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub target: String,
+    pub block: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.block {
+            Some(block) => write!(f, "{} / {}: {}", self.target, block, self.message),
+            None => write!(f, "{}: {}", self.target, self.message),
+        }
+    }
+}
+
+/// Input primitive tags for variable, list, and broadcast references.
+const BROADCAST_PRIMITIVE: u64 = 11;
+const VARIABLE_PRIMITIVE: u64 = 12;
+const LIST_PRIMITIVE: u64 = 13;
+
+/// Checks block links, variable/list/broadcast references, and procedure
+/// prototypes in every target. Returns all issues found, in target order.
+pub fn validate_project_json(project: &Value) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let Some(targets) = project.get("targets").and_then(Value::as_array) else {
+        issues.push(ValidationIssue {
+            target: "project".to_string(),
+            block: None,
+            message: "missing 'targets' array".to_string(),
+        });
+        return issues;
+    };
+    let stage = targets
+        .iter()
+        .find(|t| t.get("isStage").and_then(Value::as_bool) == Some(true));
+    let stage_keys = |key: &str| object_keys(stage.and_then(|s| s.get(key)));
+    let stage_variables = stage_keys("variables");
+    let stage_lists = stage_keys("lists");
+    let broadcasts = stage_keys("broadcasts");
+    let no_blocks = Map::new();
+
+    for target in targets {
+        let name = target
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or("<unnamed>");
+        let mut checker = TargetChecker {
+            target: name,
+            blocks: target
+                .get("blocks")
+                .and_then(Value::as_object)
+                .unwrap_or(&no_blocks),
+            variables: object_keys(target.get("variables")),
+            lists: object_keys(target.get("lists")),
+            stage_variables: &stage_variables,
+            stage_lists: &stage_lists,
+            broadcasts: &broadcasts,
+            issues: &mut issues,
+        };
+        checker.check();
+    }
+    issues
+}
+
+fn object_keys(value: Option<&Value>) -> HashSet<&str> {
+    value
+        .and_then(Value::as_object)
+        .map(|map| map.keys().map(String::as_str).collect())
+        .unwrap_or_default()
+}
+
+struct TargetChecker<'a, 'p> {
+    target: &'p str,
+    blocks: &'p Map<String, Value>,
+    variables: HashSet<&'p str>,
+    lists: HashSet<&'p str>,
+    stage_variables: &'a HashSet<&'p str>,
+    stage_lists: &'a HashSet<&'p str>,
+    broadcasts: &'a HashSet<&'p str>,
+    issues: &'a mut Vec<ValidationIssue>,
+}
+
+impl TargetChecker<'_, '_> {
+    fn check(&mut self) {
+        let prototypes = self
+            .blocks
+            .values()
+            .filter(|b| opcode(b) == Some("procedures_prototype"))
+            .filter_map(proccode)
+            .collect::<HashSet<_>>();
+        for (id, block) in self.blocks {
+            match block {
+                // Top-level variable/list reporters are stored as bare primitives.
+                Value::Array(primitive) => self.check_primitive(id, primitive),
+                Value::Object(_) => self.check_block(id, block, &prototypes),
+                _ => self.push(
+                    id,
+                    "block is neither an object nor a primitive array".to_string(),
+                ),
+            }
+        }
+    }
+
+    fn check_block(&mut self, id: &str, block: &Value, prototypes: &HashSet<&str>) {
+        for key in ["parent", "next"] {
+            if let Some(link) = block.get(key).and_then(Value::as_str) {
+                if !self.blocks.contains_key(link) {
+                    self.push(id, format!("'{}' references missing block '{}'", key, link));
+                }
+            }
+        }
+        if block.get("topLevel").and_then(Value::as_bool) == Some(true) {
+            if !block.get("x").is_some_and(Value::is_number)
+                || !block.get("y").is_some_and(Value::is_number)
+            {
+                self.push(id, "top-level block has no x/y position".to_string());
+            }
+            if block.get("shadow").and_then(Value::as_bool) == Some(true) {
+                self.push(id, "shadow block is top-level".to_string());
+            }
+        }
+        if let Some(inputs) = block.get("inputs").and_then(Value::as_object) {
+            for (input_name, input) in inputs {
+                self.check_input(id, input_name, input);
+            }
+        }
+        if let Some(fields) = block.get("fields").and_then(Value::as_object) {
+            for (field_name, field) in fields {
+                let field_id = field.get(1).and_then(Value::as_str);
+                let label = field.get(0).and_then(Value::as_str).unwrap_or("");
+                match (field_name.as_str(), field_id) {
+                    ("VARIABLE", Some(var_id)) => self.check_variable(id, label, var_id),
+                    ("LIST", Some(list_id)) => self.check_list(id, label, list_id),
+                    ("BROADCAST_OPTION", Some(broadcast_id)) => {
+                        self.check_broadcast(id, label, broadcast_id)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if opcode(block) == Some("procedures_call") {
+            if let Some(code) = proccode(block) {
+                if !prototypes.contains(code) {
+                    self.push(id, format!("call to '{}' has no matching prototype", code));
+                }
+            }
+        }
+    }
+
+    /// Inputs are `[shadow_type, value, shadow?]`: type 1 holds a shadow,
+    /// 2 a block without shadow, 3 a block obscuring a shadow.
+    fn check_input(&mut self, id: &str, input_name: &str, input: &Value) {
+        let Some(parts) = input.as_array() else {
+            self.push(id, format!("input '{}' is not an array", input_name));
+            return;
+        };
+        let shadow_type = parts.first().and_then(Value::as_u64);
+        for (slot, part) in parts.iter().enumerate().skip(1) {
+            match part {
+                Value::String(child) => {
+                    let Some(child_block) = self.blocks.get(child) else {
+                        self.push(
+                            id,
+                            format!(
+                                "input '{}' references missing block '{}'",
+                                input_name, child
+                            ),
+                        );
+                        continue;
+                    };
+                    let is_shadow =
+                        child_block.get("shadow").and_then(Value::as_bool) == Some(true);
+                    let expect_shadow = shadow_type == Some(1) || slot == 2;
+                    if child_block.is_object() && is_shadow != expect_shadow {
+                        self.push(
+                            id,
+                            format!(
+                                "input '{}' block '{}' should {}be a shadow",
+                                input_name,
+                                child,
+                                if expect_shadow { "" } else { "not " }
+                            ),
+                        );
+                    }
+                }
+                Value::Array(primitive) => self.check_primitive(id, primitive),
+                _ => {}
+            }
+        }
+    }
+
+    fn check_primitive(&mut self, id: &str, primitive: &[Value]) {
+        let label = primitive.get(1).and_then(Value::as_str).unwrap_or("");
+        let Some(ref_id) = primitive.get(2).and_then(Value::as_str) else {
+            return;
+        };
+        match primitive.first().and_then(Value::as_u64) {
+            Some(BROADCAST_PRIMITIVE) => self.check_broadcast(id, label, ref_id),
+            Some(VARIABLE_PRIMITIVE) => self.check_variable(id, label, ref_id),
+            Some(LIST_PRIMITIVE) => self.check_list(id, label, ref_id),
+            _ => {}
+        }
+    }
+
+    fn check_variable(&mut self, id: &str, name: &str, var_id: &str) {
+        if !self.variables.contains(var_id) && !self.stage_variables.contains(var_id) {
+            self.push(
+                id,
+                format!("variable '{}' has unknown id '{}'", name, var_id),
+            );
+        }
+    }
+
+    fn check_list(&mut self, id: &str, name: &str, list_id: &str) {
+        if !self.lists.contains(list_id) && !self.stage_lists.contains(list_id) {
+            self.push(id, format!("list '{}' has unknown id '{}'", name, list_id));
+        }
+    }
+
+    fn check_broadcast(&mut self, id: &str, name: &str, broadcast_id: &str) {
+        if !self.broadcasts.contains(broadcast_id) {
+            self.push(
+                id,
+                format!(
+                    "broadcast '{}' id '{}' is not declared on the stage",
+                    name, broadcast_id
+                ),
+            );
+        }
+    }
+
+    fn push(&mut self, block: &str, message: String) {
+        self.issues.push(ValidationIssue {
+            target: self.target.to_string(),
+            block: Some(block.to_string()),
+            message,
+        });
+    }
+}
+
+fn opcode(block: &Value) -> Option<&str> {
+    block.get("opcode").and_then(Value::as_str)
+}
+
+fn proccode(block: &Value) -> Option<&str> {
+    block
+        .get("mutation")
+        .and_then(|m| m.get("proccode"))
+        .and_then(Value::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn project(sprite_blocks: Value) -> Value {
+        json!({
+            "targets": [
+                {
+                    "isStage": true,
+                    "name": "Stage",
+                    "variables": {"gv": ["score", 0]},
+                    "lists": {},
+                    "broadcasts": {"b1": "go"},
+                    "blocks": {}
+                },
+                {
+                    "isStage": false,
+                    "name": "Cat",
+                    "variables": {"v1": ["speed", 0]},
+                    "lists": {"l1": ["log", []]},
+                    "blocks": sprite_blocks
+                }
+            ]
+        })
+    }
+
+    fn messages(project: &Value) -> Vec<String> {
+        validate_project_json(project)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn compiled_projects_are_valid() {
+        let source = "stage\n  var score\nend\n\nsprite Cat\n  var speed\n  list log\n  define hop (n)\n    change y by (n)\n  end\n  when flag clicked\n    broadcast [go]\n    hop (speed)\n    add (score) to [log]\n  end\n  when I receive [go]\n    set [speed] to (item (1) of [log])\n  end\nend\n";
+        let options = crate::codegen::CodegenOptions {
+            validate_output: false,
+            ..Default::default()
+        };
+        let bytes = crate::compile_source_to_sb3_bytes_with_options(
+            source,
+            std::path::Path::new("."),
+            options,
+        )
+        .expect("source should compile");
+        let archive = crate::sb3::read_sb3_bytes(&bytes).expect("archive should read");
+        assert_eq!(messages(&archive.project), Vec::<String>::new());
+    }
+
+    #[test]
+    fn dangling_links_are_reported() {
+        let blocks = json!({
+            "hat": {"opcode": "event_whenflagclicked", "next": "gone", "parent": null,
+                    "inputs": {}, "fields": {}, "topLevel": true},
+            "move": {"opcode": "motion_movesteps", "next": null, "parent": "hat",
+                     "inputs": {"STEPS": [3, "missing", [4, "10"]]}, "fields": {},
+                     "topLevel": false}
+        });
+        let found = messages(&project(blocks));
+        assert_eq!(
+            found,
+            vec![
+                "Cat / hat: 'next' references missing block 'gone'",
+                "Cat / hat: top-level block has no x/y position",
+                "Cat / move: input 'STEPS' references missing block 'missing'",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn shadow_flags_must_match_input_slots() {
+        let blocks = json!({
+            "say": {"opcode": "looks_say", "next": null, "parent": null, "x": 0, "y": 0,
+                    "inputs": {"MESSAGE": [1, "answer"]}, "fields": {}, "topLevel": true},
+            "answer": {"opcode": "sensing_answer", "next": null, "parent": "say",
+                       "inputs": {}, "fields": {}, "shadow": false, "topLevel": false}
+        });
+        let found = messages(&project(blocks));
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert!(found[0].contains("should be a shadow"), "{:?}", found);
+    }
+
+    #[test]
+    fn unknown_references_are_reported() {
+        let blocks = json!({
+            "set": {"opcode": "data_setvariableto", "next": "add", "parent": null,
+                    "x": 0, "y": 0, "topLevel": true,
+                    "inputs": {"VALUE": [3, [12, "score", "gv"], [10, ""]]},
+                    "fields": {"VARIABLE": ["hp", "nope"]}},
+            "add": {"opcode": "data_addtolist", "next": "send", "parent": "set",
+                    "inputs": {"ITEM": [3, [12, "speed", "v1"], [10, ""]]},
+                    "fields": {"LIST": ["todo", "l2"]}, "topLevel": false},
+            "send": {"opcode": "event_broadcast", "next": "call", "parent": "add",
+                     "inputs": {"BROADCAST_INPUT": [1, [11, "stop", "b2"]]},
+                     "fields": {}, "topLevel": false},
+            "call": {"opcode": "procedures_call", "next": null, "parent": "send",
+                     "inputs": {}, "fields": {}, "topLevel": false,
+                     "mutation": {"proccode": "jump %s"}}
+        });
+        let found = messages(&project(blocks)).join("\n");
+        for expected in [
+            "variable 'hp' has unknown id 'nope'",
+            "list 'todo' has unknown id 'l2'",
+            "broadcast 'stop' id 'b2' is not declared on the stage",
+            "call to 'jump %s' has no matching prototype",
+        ] {
+            assert!(
+                found.contains(expected),
+                "missing {:?} in\n{}",
+                expected,
+                found
+            );
+        }
+        assert_eq!(found.lines().count(), 4, "{}", found);
+    }
+}
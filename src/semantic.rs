@@ -1,26 +1,131 @@
-use crate::ast::{EventScript, Expr, Project, Statement, Target};
-use std::collections::{HashMap, HashSet};
+use crate::ast::{
+    walk_statements, EventScript, EventType, Expr, Position, Procedure, Project, Statement, Target,
+    TargetProperty,
+};
+use crate::codegen::{format_num, stop_option_allows_next, CLOUD_VARIABLE_PREFIX};
+use crate::music::{music_menu_number, MUSIC_DRUMS, MUSIC_INSTRUMENTS};
+use crate::rpc::{rpc_names, RPC_PREFIX};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+const MAX_CLOUD_VARIABLES: usize = 10;
+const ROTATION_STYLES: [&str; 3] = ["all around", "left-right", "don't rotate"];
+const GRAPHIC_EFFECTS: [&str; 7] = [
+    "color",
+    "fisheye",
+    "whirl",
+    "pixelate",
+    "mosaic",
+    "brightness",
+    "ghost",
+];
 
 #[derive(Debug, Clone)]
 pub struct SemanticError {
     pub message: String,
+    pub pos: Option<Position>,
+    /// The offending identifier (variable, procedure, option, ...) when there is one.
+    pub name: Option<String>,
+    /// A second location involved in the problem, such as the first of two duplicate
+    /// declarations.
+    pub related: Option<Position>,
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+/// Options for the semantic checks. Not `Copy`, since
+/// [`UnknownProcPolicy::Names`] holds a list of names; clone it to reuse it.
+#[derive(Debug, Clone, Default)]
 pub struct SemanticOptions {
+    /// Which calls to undeclared procedures compile as no-op `wait 0` blocks.
+    pub allow_unknown: UnknownProcPolicy,
+    /// `true` allows every unknown call, whatever `allow_unknown` says.
+    #[deprecated(note = "use `allow_unknown: UnknownProcPolicy::All` instead")]
     pub allow_unknown_procedures: bool,
+    /// Report sprite-only blocks in the stage as warnings instead of errors.
+    pub allow_sprite_blocks_on_stage: bool,
+}
+
+/// Which calls to procedures that are declared nowhere are let through, with a
+/// warning, and compiled as no-op `wait 0` blocks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum UnknownProcPolicy {
+    /// Every unknown call. Near misses of a declared procedure get a "did you
+    /// mean" hint in their warning.
+    All,
+    /// None: every unknown call is an error.
+    #[default]
+    None,
+    /// Only calls to these names (`proc` or `Target.proc`), compared
+    /// case-insensitively.
+    Names(Vec<String>),
+}
+
+impl SemanticOptions {
+    /// The policy in effect, taking the deprecated `allow_unknown_procedures`
+    /// flag into account.
+    #[allow(deprecated)]
+    pub fn unknown_proc_policy(&self) -> &UnknownProcPolicy {
+        UnknownProcPolicy::or_all(&self.allow_unknown, self.allow_unknown_procedures)
+    }
+}
+
+impl UnknownProcPolicy {
+    /// `policy`, or [`UnknownProcPolicy::All`] when the old boolean option
+    /// `allow_all` is set.
+    pub(crate) fn or_all(policy: &Self, allow_all: bool) -> &Self {
+        const ALL: &UnknownProcPolicy = &UnknownProcPolicy::All;
+        if allow_all {
+            ALL
+        } else {
+            policy
+        }
+    }
+
+    /// Whether an unknown call to `name` is let through.
+    pub fn allows(&self, name: &str) -> bool {
+        match self {
+            UnknownProcPolicy::All => true,
+            UnknownProcPolicy::None => false,
+            UnknownProcPolicy::Names(names) => names
+                .iter()
+                .any(|allowed| allowed.to_lowercase() == name.to_lowercase()),
+        }
+    }
+
+    /// Why an allowed call was let through, for its warning.
+    fn reason(&self) -> &'static str {
+        match self {
+            UnknownProcPolicy::All => "allow_unknown_procedures is enabled",
+            _ => "it is on the allowed unknown procedure list",
+        }
+    }
+}
+
+/// `true` allows every unknown call, like the old boolean option.
+impl From<bool> for UnknownProcPolicy {
+    fn from(allow_all: bool) -> Self {
+        if allow_all {
+            UnknownProcPolicy::All
+        } else {
+            UnknownProcPolicy::None
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct SemanticWarning {
     pub message: String,
+    pub pos: Option<Position>,
+    pub name: Option<String>,
+    pub related: Option<Position>,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct SemanticReport {
     pub warnings: Vec<SemanticWarning>,
+    pub errors: Vec<SemanticError>,
 }
 
 impl Display for SemanticError {
@@ -33,15 +138,8 @@ impl Error for SemanticError {}
 
 #[derive(Debug, Clone)]
 struct ProcedureInfo {
-    line: usize,
-    params: Vec<String>,
-}
-
-#[derive(Debug, Clone)]
-struct ReporterInfo {
-    line: usize,
+    pos: Position,
     params: Vec<String>,
-    return_name: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -50,6 +148,12 @@ struct TargetInfo {
     variables: HashSet<String>,
     lists: HashSet<String>,
     procedures: HashMap<String, usize>,
+    is_stage: bool,
+    costumes: HashSet<String>,
+    /// Declared variable, list, and procedure names as written, for "did you mean" hints.
+    variable_names: Vec<String>,
+    list_names: Vec<String>,
+    procedure_names: Vec<String>,
 }
 
 pub fn analyze(project: &Project) -> Result<(), SemanticError> {
@@ -60,23 +164,45 @@ pub fn analyze_with_options(
     project: &Project,
     options: SemanticOptions,
 ) -> Result<SemanticReport, SemanticError> {
+    let mut report = analyze_all(project, options);
+    if report.errors.is_empty() {
+        Ok(report)
+    } else {
+        Err(report.errors.remove(0))
+    }
+}
+
+/// Runs every check instead of stopping at the first problem. Errors are ordered by
+/// target, then by source position.
+pub fn analyze_all(project: &Project, options: SemanticOptions) -> SemanticReport {
+    let mut report = SemanticReport::default();
     if project.targets.is_empty() {
-        return Err(SemanticError {
+        report.errors.push(SemanticError {
             message: "Project must define at least one target.".to_string(),
+            pos: None,
+            name: None,
+            related: None,
         });
+        return report;
     }
     let stage_count = project.targets.iter().filter(|t| t.is_stage).count();
     if stage_count > 1 {
-        return Err(SemanticError {
+        report.errors.push(SemanticError {
             message: "Project can only define one stage.".to_string(),
+            pos: None,
+            name: None,
+            related: None,
         });
     }
     let mut names = HashSet::new();
     for target in &project.targets {
         let lowered = target.name.to_lowercase();
         if !names.insert(lowered) {
-            return Err(SemanticError {
+            report.errors.push(SemanticError {
                 message: format!("Duplicate target name '{}'.", target.name),
+                pos: Some(target.pos),
+                name: Some(target.name.clone()),
+                related: None,
             });
         }
     }
@@ -86,6 +212,9 @@ pub fn analyze_with_options(
         let mut vars = HashSet::new();
         for decl in &target.variables {
             vars.insert(decl.name.to_lowercase());
+            if decl.is_cloud {
+                vars.insert(cloud_variable_name(&decl.name).to_lowercase());
+            }
         }
         let mut lists = HashSet::new();
         for decl in &target.lists {
@@ -102,29 +231,50 @@ pub fn analyze_with_options(
                 variables: vars,
                 lists,
                 procedures: procs,
+                is_stage: target.is_stage,
+                costumes: costume_names(target),
+                variable_names: target.variables.iter().map(|d| d.name.clone()).collect(),
+                list_names: target.lists.iter().map(|d| d.name.clone()).collect(),
+                procedure_names: target.procedures.iter().map(|p| p.name.clone()).collect(),
             },
         );
     }
 
-    let mut warnings = Vec::new();
+    let mut cloud_count = 0;
+    let mut monitored = HashSet::new();
+    let mut layers = HashMap::new();
     for target in &project.targets {
-        analyze_target(target, &target_infos, options, &mut warnings)?;
+        let first_error = report.errors.len();
+        analyze_cloud_variables(target, &mut cloud_count, &mut report);
+        analyze_layer_declarations(target, &mut layers, &mut report);
+        analyze_target_monitors(target, &target_infos, &mut monitored, &mut report);
+        analyze_target(target, &target_infos, &options, &mut report);
+        report.errors[first_error..].sort_by_key(|err| err.pos.map(|pos| (pos.line, pos.column)));
     }
-    Ok(SemanticReport { warnings })
+    analyze_recursion(project, &mut report);
+    analyze_broadcasts(project, &mut report);
+    analyze_rpc_name_collisions(project, &mut report);
+    report
 }
 
 fn analyze_target(
     target: &Target,
     target_infos: &HashMap<String, TargetInfo>,
-    options: SemanticOptions,
-    warnings: &mut Vec<SemanticWarning>,
-) -> Result<(), SemanticError> {
+    options: &SemanticOptions,
+    report: &mut SemanticReport,
+) {
+    analyze_target_properties(target, report);
+    analyze_duplicate_declarations(target, report);
+
     let mut variables: HashMap<String, usize> = HashMap::new();
     for decl in &target.variables {
         let lowered = decl.name.to_lowercase();
         if variables.contains_key(&lowered) {
             continue;
         }
+        if decl.is_cloud {
+            variables.insert(cloud_variable_name(&lowered), decl.pos.line);
+        }
         variables.insert(lowered, decl.pos.line);
     }
 
@@ -141,28 +291,41 @@ fn analyze_target(
     for procedure in &target.procedures {
         let lowered = procedure.name.to_lowercase();
         if let Some(prev) = procedures.get(&lowered) {
-            return Err(SemanticError {
+            report.errors.push(SemanticError {
                 message: format!(
-                    "Procedure '{}' is already defined at line {} in target '{}'.",
-                    procedure.name, prev.line, target.name
+                    "Procedure '{}' at line {}, column {} is already defined at line {}, column {} in target '{}'.",
+                    procedure.name,
+                    procedure.pos.line,
+                    procedure.pos.column,
+                    prev.pos.line,
+                    prev.pos.column,
+                    target.name
                 ),
+                pos: Some(procedure.pos),
+                name: Some(procedure.name.clone()),
+                related: Some(prev.pos),
             });
+            continue;
         }
         let mut param_names = HashSet::new();
         for p in &procedure.params {
             if !param_names.insert(p.to_lowercase()) {
-                return Err(SemanticError {
+                report.errors.push(SemanticError {
                     message: format!(
                         "Procedure '{}' has duplicate parameter names at line {}, column {}.",
                         procedure.name, procedure.pos.line, procedure.pos.column
                     ),
+                    pos: Some(procedure.pos),
+                    name: Some(procedure.name.clone()),
+                    related: None,
                 });
+                break;
             }
         }
         procedures.insert(
             lowered,
             ProcedureInfo {
-                line: procedure.pos.line,
+                pos: procedure.pos,
                 params: procedure.params.clone(),
             },
         );
@@ -184,12 +347,12 @@ fn analyze_target(
             &param_scope,
             &format!("procedure '{}'", procedure.name),
             options,
-            warnings,
-        )?;
+            report,
+        );
     }
 
     for script in &target.scripts {
-        analyze_event_script(
+        if let Err(err) = analyze_event_script(
             target,
             script,
             &variables,
@@ -197,49 +360,62 @@ fn analyze_target(
             &procedures,
             target_infos,
             options,
-            warnings,
-        )?;
+            report,
+        ) {
+            report.errors.push(err);
+        }
     }
 
     // Analyze reporter declarations
-    let mut reporters: HashMap<String, ReporterInfo> = HashMap::new();
+    // Lowercased reporter name -> where it was first declared.
+    let mut reporters: HashMap<String, Position> = HashMap::new();
     for reporter in &target.reporters {
         let lowered = reporter.name.to_lowercase();
         if let Some(prev) = reporters.get(&lowered) {
-            return Err(SemanticError {
+            report.errors.push(SemanticError {
                 message: format!(
-                    "Reporter '{}' is already defined at line {} in target '{}'.",
-                    reporter.name, prev.line, target.name
+                    "Reporter '{}' at line {}, column {} is already defined at line {}, column {} in target '{}'.",
+                    reporter.name,
+                    reporter.pos.line,
+                    reporter.pos.column,
+                    prev.line,
+                    prev.column,
+                    target.name
                 ),
+                pos: Some(reporter.pos),
+                name: Some(reporter.name.clone()),
+                related: Some(*prev),
             });
+            continue;
         }
         if procedures.contains_key(&lowered) {
-            return Err(SemanticError {
+            report.errors.push(SemanticError {
                 message: format!(
                     "Reporter '{}' conflicts with a procedure name in target '{}'.",
                     reporter.name, target.name
                 ),
+                pos: Some(reporter.pos),
+                name: Some(reporter.name.clone()),
+                related: None,
             });
+            continue;
         }
         let mut param_names = HashSet::new();
         for p in &reporter.params {
             if !param_names.insert(p.to_lowercase()) {
-                return Err(SemanticError {
+                report.errors.push(SemanticError {
                     message: format!(
                         "Reporter '{}' has duplicate parameter names at line {}, column {}.",
                         reporter.name, reporter.pos.line, reporter.pos.column
                     ),
+                    pos: Some(reporter.pos),
+                    name: Some(reporter.name.clone()),
+                    related: None,
                 });
+                break;
             }
         }
-        reporters.insert(
-            lowered,
-            ReporterInfo {
-                line: reporter.pos.line,
-                params: reporter.params.clone(),
-                return_name: reporter.return_name.clone(),
-            },
-        );
+        reporters.insert(lowered, reporter.pos);
     }
 
     // Validate reporter bodies
@@ -266,25 +442,386 @@ fn analyze_target(
             &param_scope,
             &format!("reporter '{}'", reporter.name),
             options,
-            warnings,
-        )?;
+            report,
+        );
 
         if let Some(rn) = &reporter.return_name {
             let rn_lower = rn.to_lowercase();
             if !reporter_assigns_return(&reporter.body, &rn_lower) {
-                return Err(SemanticError {
+                report.errors.push(SemanticError {
                     message: format!(
                         "Reporter '{}' must assign its return variable '{}' at line {}, column {} in target '{}'.",
                         reporter.name, rn, reporter.pos.line, reporter.pos.column, target.name
                     ),
+                    pos: Some(reporter.pos),
+                    name: Some(reporter.name.clone()),
+                    related: None,
                 });
             }
         }
     }
+}
 
-    Ok(())
+/// Scratch only syncs stage variables to the cloud, and at most ten of them.
+/// `count` tracks the cloud variables seen so far across the whole project.
+fn analyze_cloud_variables(target: &Target, count: &mut usize, report: &mut SemanticReport) {
+    for decl in target.variables.iter().filter(|decl| decl.is_cloud) {
+        let pos = decl.pos;
+        if !target.is_stage {
+            report.errors.push(SemanticError {
+                message: format!(
+                    "Cloud variable '{}' at line {}, column {} must be declared on the stage, not in sprite '{}'.",
+                    decl.name, pos.line, pos.column, target.name
+                ),
+                pos: Some(pos),
+                name: Some(decl.name.clone()),
+                related: None,
+            });
+            continue;
+        }
+        *count += 1;
+        if *count > MAX_CLOUD_VARIABLES {
+            report.errors.push(SemanticError {
+                message: format!(
+                    "Cloud variable '{}' at line {}, column {} exceeds the limit of {} cloud variables per project.",
+                    decl.name, pos.line, pos.column, MAX_CLOUD_VARIABLES
+                ),
+                pos: Some(pos),
+                name: Some(decl.name.clone()),
+                related: None,
+            });
+        }
+    }
+}
+
+/// Names are compared case-insensitively, matching how blocks look them up at runtime.
+/// Costumes only clash when both are explicitly named; derived names are uniquified.
+fn analyze_duplicate_declarations(target: &Target, report: &mut SemanticReport) {
+    let variables = target
+        .variables
+        .iter()
+        .map(|decl| (decl.name.as_str(), decl.pos));
+    let lists = target
+        .lists
+        .iter()
+        .map(|decl| (decl.name.as_str(), decl.pos));
+    let costumes = target
+        .costumes
+        .iter()
+        .filter_map(|decl| decl.name.as_deref().map(|name| (name, decl.pos)));
+    let first_variables = report_duplicates(target, "Variable", variables, report);
+    let first_lists = report_duplicates(target, "List", lists, report);
+    report_duplicates(target, "Costume", costumes, report);
+
+    for decl in &target.lists {
+        let Some(var_pos) = first_variables.get(&decl.name.to_lowercase()) else {
+            continue;
+        };
+        if first_lists.get(&decl.name.to_lowercase()) != Some(&decl.pos) {
+            continue;
+        }
+        report.warnings.push(SemanticWarning {
+            message: format!(
+                "List '{}' at line {}, column {} has the same name as variable declared at line {}, column {} in target '{}'.",
+                decl.name, decl.pos.line, decl.pos.column, var_pos.line, var_pos.column, target.name
+            ),
+            pos: Some(decl.pos),
+            name: Some(decl.name.clone()),
+            related: Some(*var_pos),
+        });
+    }
+}
+
+/// Reports every repeat of a name and returns the first position of each name.
+fn report_duplicates<'a>(
+    target: &Target,
+    kind: &str,
+    decls: impl Iterator<Item = (&'a str, Position)>,
+    report: &mut SemanticReport,
+) -> HashMap<String, Position> {
+    let mut first: HashMap<String, Position> = HashMap::new();
+    for (name, pos) in decls {
+        if let Some(prev) = first.get(&name.to_lowercase()) {
+            report.errors.push(SemanticError {
+                message: format!(
+                    "{} '{}' at line {}, column {} is already declared at line {}, column {} in target '{}'.",
+                    kind, name, pos.line, pos.column, prev.line, prev.column, target.name
+                ),
+                pos: Some(pos),
+                name: Some(name.to_string()),
+                related: Some(*prev),
+            });
+            continue;
+        }
+        first.insert(name.to_lowercase(), pos);
+    }
+    first
+}
+
+fn cloud_variable_name(name: &str) -> String {
+    format!("{}{}", CLOUD_VARIABLE_PREFIX, name)
+}
+
+fn analyze_target_properties(target: &Target, report: &mut SemanticReport) {
+    for decl in &target.properties {
+        let pos = decl.pos;
+        let stage_only = matches!(
+            decl.property,
+            TargetProperty::Tempo(_)
+                | TargetProperty::VideoTransparency(_)
+                | TargetProperty::VideoState(_)
+        );
+        let sprite_only = !stage_only
+            && !matches!(
+                decl.property,
+                TargetProperty::Volume(_) | TargetProperty::StartCostume(_)
+            );
+        if target.is_stage && sprite_only {
+            report.errors.push(SemanticError {
+                message: format!(
+                    "Sprite property declarations are not allowed on the stage at line {}, column {}.",
+                    pos.line, pos.column
+                ),
+                pos: Some(pos),
+                name: None,
+                related: None,
+            });
+            continue;
+        }
+        if !target.is_stage && stage_only {
+            report.errors.push(SemanticError {
+                message: format!(
+                    "Stage property declarations are not allowed in sprite '{}' at line {}, column {}.",
+                    target.name, pos.line, pos.column
+                ),
+                pos: Some(pos),
+                name: Some(target.name.clone()),
+                related: None,
+            });
+            continue;
+        }
+        match &decl.property {
+            TargetProperty::Direction(direction) if !(-180.0..=180.0).contains(direction) => {
+                report.errors.push(SemanticError {
+                    message: format!(
+                        "Direction {} at line {}, column {} in target '{}' must be between -180 and 180.",
+                        format_num(*direction), pos.line, pos.column, target.name
+                    ),
+                    pos: Some(pos),
+                    name: None,
+                    related: None,
+                });
+            }
+            TargetProperty::RotationStyle(style) if !is_rotation_style_name(style) => {
+                report.errors.push(SemanticError {
+                    message: format!(
+                        "Unknown rotation style '{}' at line {}, column {} in target '{}'. Expected 'all around', 'left-right', or 'don't rotate'.",
+                        style, pos.line, pos.column, target.name
+                    ),
+                    pos: Some(pos),
+                    name: Some(style.to_string()),
+                    related: None,
+                });
+            }
+            TargetProperty::Volume(value) | TargetProperty::VideoTransparency(value)
+                if !(0.0..=100.0).contains(value) =>
+            {
+                let name = if matches!(decl.property, TargetProperty::Volume(_)) {
+                    "Volume"
+                } else {
+                    "Video transparency"
+                };
+                report.errors.push(SemanticError {
+                    message: format!(
+                        "{} {} at line {}, column {} in target '{}' must be between 0 and 100.",
+                        name,
+                        format_num(*value),
+                        pos.line,
+                        pos.column,
+                        target.name
+                    ),
+                    pos: Some(pos),
+                    name: None,
+                    related: None,
+                });
+            }
+            TargetProperty::Layer(layer) if *layer < 1.0 || layer.fract() != 0.0 => {
+                report.errors.push(SemanticError {
+                    message: format!(
+                        "Layer {} at line {}, column {} in target '{}' must be a whole number of at least 1.",
+                        format_num(*layer), pos.line, pos.column, target.name
+                    ),
+                    pos: Some(pos),
+                    name: None,
+                    related: None,
+                });
+            }
+            TargetProperty::StartCostume(name)
+                if !costume_names(target).contains(&name.to_lowercase()) =>
+            {
+                report.errors.push(SemanticError {
+                    message: format!(
+                        "Start costume '{}' at line {}, column {} in target '{}' is not one of its costumes.",
+                        name, pos.line, pos.column, target.name
+                    ),
+                    pos: Some(pos),
+                    name: Some(name.to_string()),
+                    related: None,
+                });
+            }
+            TargetProperty::VideoState(state) if !is_video_state_name(state) => {
+                report.errors.push(SemanticError {
+                    message: format!(
+                        "Unknown video state '{}' at line {}, column {} in target '{}'. Expected 'on', 'off', or 'on-flipped'.",
+                        state, pos.line, pos.column, target.name
+                    ),
+                    pos: Some(pos),
+                    name: Some(state.to_string()),
+                    related: None,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `layers` maps each pinned layer to the sprite that claimed it first, since two
+/// sprites cannot share a stacking position.
+fn analyze_layer_declarations(
+    target: &Target,
+    layers: &mut HashMap<i64, (String, Position)>,
+    report: &mut SemanticReport,
+) {
+    if target.is_stage {
+        return;
+    }
+    let mut declared = target
+        .properties
+        .iter()
+        .filter_map(|decl| match decl.property {
+            TargetProperty::Layer(layer) => Some((layer, decl.pos)),
+            _ => None,
+        });
+    let Some((layer, pos)) = declared.next() else {
+        return;
+    };
+    if let Some((_, extra)) = declared.next() {
+        report.errors.push(SemanticError {
+            message: format!(
+                "Sprite '{}' declares 'layer' more than once (line {}, column {}).",
+                target.name, extra.line, extra.column
+            ),
+            pos: Some(extra),
+            name: Some(target.name.clone()),
+            related: Some(pos),
+        });
+    }
+    if layer < 1.0 || layer.fract() != 0.0 {
+        return;
+    }
+    match layers.get(&(layer as i64)) {
+        Some((owner, prev)) => report.errors.push(SemanticError {
+            message: format!(
+                "Layer {} at line {}, column {} in sprite '{}' is already taken by sprite '{}' at line {}, column {}.",
+                format_num(layer), pos.line, pos.column, target.name, owner, prev.line, prev.column
+            ),
+            pos: Some(pos),
+            name: Some(target.name.clone()),
+            related: Some(*prev),
+        }),
+        None => {
+            layers.insert(layer as i64, (target.name.clone(), pos));
+        }
+    }
+}
+
+/// `monitored` collects the variables and lists already given a monitor, keyed by
+/// owning target, since Scratch allows a single monitor per variable or list across
+/// the whole project. A name resolves to a variable first, then to a list.
+fn analyze_target_monitors(
+    target: &Target,
+    target_infos: &HashMap<String, TargetInfo>,
+    monitored: &mut HashSet<String>,
+    report: &mut SemanticReport,
+) {
+    for decl in &target.monitors {
+        let pos = decl.pos;
+        let lowered = decl.var_name.to_lowercase();
+        let lowered = lowered
+            .strip_prefix(CLOUD_VARIABLE_PREFIX)
+            .map(str::to_string)
+            .unwrap_or(lowered);
+        let variable_here = target
+            .variables
+            .iter()
+            .any(|v| v.name.to_lowercase() == lowered);
+        let variable_on_stage = target_infos
+            .values()
+            .any(|info| info.is_stage && info.variables.contains(&lowered));
+        let list_here = target
+            .lists
+            .iter()
+            .any(|l| l.name.to_lowercase() == lowered);
+        let list_on_stage = target_infos
+            .values()
+            .any(|info| info.is_stage && info.lists.contains(&lowered));
+        let (kind, declared_here) = if variable_here || variable_on_stage {
+            ("variable", variable_here)
+        } else if list_here || list_on_stage {
+            ("list", list_here)
+        } else {
+            report.errors.push(SemanticError {
+                message: format!(
+                    "Monitor at line {}, column {} in target '{}' refers to unknown variable or list '{}'.",
+                    pos.line, pos.column, target.name, decl.var_name
+                ),
+                pos: Some(pos),
+                name: None,
+                related: None,
+            });
+            continue;
+        };
+        let owner = if declared_here && !target.is_stage {
+            target.name.to_lowercase()
+        } else {
+            String::new()
+        };
+        if !monitored.insert(format!("{}\n{}\n{}", kind, owner, lowered)) {
+            report.errors.push(SemanticError {
+                message: format!(
+                    "Duplicate monitor for {} '{}' at line {}, column {} in target '{}'.",
+                    kind, decl.var_name, pos.line, pos.column, target.name
+                ),
+                pos: Some(pos),
+                name: Some(decl.var_name.clone()),
+                related: None,
+            });
+        }
+        if kind == "list" && decl.mode != "default" {
+            report.errors.push(SemanticError {
+                message: format!(
+                    "Monitor mode '{}' at line {}, column {} in target '{}' is not supported for list '{}'. List monitors only use the default mode.",
+                    decl.mode, pos.line, pos.column, target.name, decl.var_name
+                ),
+                pos: Some(pos),
+                name: Some(decl.mode.clone()),
+                related: None,
+            });
+        } else if !is_monitor_mode_name(&decl.mode) {
+            report.errors.push(SemanticError {
+                message: format!(
+                    "Unknown monitor mode '{}' at line {}, column {} in target '{}'. Expected 'default', 'large', or 'slider'.",
+                    decl.mode, pos.line, pos.column, target.name
+                ),
+                pos: Some(pos),
+                name: Some(decl.mode.clone()),
+                related: None,
+            });
+        }
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn analyze_event_script(
     target: &Target,
     script: &EventScript,
@@ -292,9 +829,51 @@ fn analyze_event_script(
     lists: &HashMap<String, usize>,
     procedures: &HashMap<String, ProcedureInfo>,
     target_infos: &HashMap<String, TargetInfo>,
-    options: SemanticOptions,
-    warnings: &mut Vec<SemanticWarning>,
+    options: &SemanticOptions,
+    report: &mut SemanticReport,
 ) -> Result<(), SemanticError> {
+    if let EventType::WhenKeyPressed(key_name) = &script.event_type {
+        if !is_scratch_key_name(key_name) {
+            return Err(SemanticError {
+                message: format!(
+                    "Unknown key '{}' in key press event at line {}, column {} in target '{}'.",
+                    key_name, script.pos.line, script.pos.column, target.name
+                ),
+                pos: Some(script.pos),
+                name: Some(key_name.to_string()),
+                related: None,
+            });
+        }
+    }
+    if let EventType::WhenBackdropSwitches(backdrop) = &script.event_type {
+        let known = target_infos
+            .values()
+            .filter(|info| info.is_stage)
+            .any(|info| info.costumes.contains(&backdrop.to_lowercase()));
+        if !known {
+            report.warnings.push(SemanticWarning {
+                message: format!(
+                    "Backdrop '{}' in backdrop switch event at line {}, column {} in target '{}' does not match any stage costume.",
+                    backdrop, script.pos.line, script.pos.column, target.name
+                ),
+                pos: Some(script.pos),
+                name: Some(backdrop.to_string()),
+                related: None,
+            });
+        }
+    }
+    if let EventType::WhenGreaterThan { value, .. } = &script.event_type {
+        analyze_expr(
+            target,
+            value,
+            variables,
+            lists,
+            target_infos,
+            &HashSet::new(),
+            options,
+            report,
+        )?;
+    }
     analyze_statements(
         target,
         &script.body,
@@ -305,10 +884,12 @@ fn analyze_event_script(
         &HashSet::new(),
         "event script",
         options,
-        warnings,
-    )
+        report,
+    );
+    Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn analyze_statements(
     target: &Target,
     statements: &[Statement],
@@ -318,490 +899,1123 @@ fn analyze_statements(
     target_infos: &HashMap<String, TargetInfo>,
     param_scope: &HashSet<String>,
     scope_name: &str,
-    options: SemanticOptions,
-    warnings: &mut Vec<SemanticWarning>,
+    options: &SemanticOptions,
+    report: &mut SemanticReport,
+) {
+    analyze_control_flow(target, statements, report);
+    for index in 0..statements.len() {
+        if let Err(err) = analyze_statement(
+            target,
+            statements,
+            index,
+            variables,
+            lists,
+            procedures,
+            target_infos,
+            param_scope,
+            scope_name,
+            options,
+            report,
+        ) {
+            report.errors.push(err);
+        }
+    }
+}
+
+/// Warns about blocks after a `forever` (which never finishes) and about `forever` or
+/// `repeat` loops with an empty body, which usually means the body was pasted elsewhere.
+/// Blocks after a terminal `stop` are already rejected by the statement checks.
+fn analyze_control_flow(target: &Target, statements: &[Statement], report: &mut SemanticReport) {
+    for (index, stmt) in statements.iter().enumerate() {
+        let (kind, body, pos) = match stmt {
+            Statement::Forever { body, pos } => ("forever", body, pos),
+            Statement::Repeat { body, pos, .. } => ("repeat", body, pos),
+            _ => continue,
+        };
+        if body.is_empty() {
+            report.warnings.push(SemanticWarning {
+                message: format!(
+                    "Empty '{}' loop at line {}, column {} in target '{}'.",
+                    kind, pos.line, pos.column, target.name
+                ),
+                pos: Some(*pos),
+                name: None,
+                related: None,
+            });
+        }
+        if kind != "forever" {
+            continue;
+        }
+        if let Some(next) = statements.get(index + 1) {
+            let next_pos = next.pos();
+            report.warnings.push(SemanticWarning {
+                message: format!(
+                    "Unreachable code at line {}, column {} in target '{}': it follows the 'forever' loop at line {}, column {}.",
+                    next_pos.line, next_pos.column, target.name, pos.line, pos.column
+                ),
+                pos: Some(next_pos),
+                name: None,
+                related: Some(*pos),
+            });
+        }
+    }
+}
+
+/// Checks `statements[index]`. Nested bodies record their own errors, so a bad block
+/// only stops analysis of the statement that contains it. A bad loop or `if` header is
+/// recorded without skipping the body under it.
+#[allow(clippy::too_many_arguments)]
+fn analyze_statement(
+    target: &Target,
+    statements: &[Statement],
+    index: usize,
+    variables: &HashMap<String, usize>,
+    lists: &HashMap<String, usize>,
+    procedures: &HashMap<String, ProcedureInfo>,
+    target_infos: &HashMap<String, TargetInfo>,
+    param_scope: &HashSet<String>,
+    scope_name: &str,
+    options: &SemanticOptions,
+    report: &mut SemanticReport,
 ) -> Result<(), SemanticError> {
-    for stmt in statements {
-        match stmt {
-            Statement::Broadcast { message, pos } => {
-                if message.is_empty() {
-                    return Err(SemanticError {
-                        message: format!(
-                            "Broadcast message cannot be empty at line {}, column {} in target '{}'.",
-                            pos.line, pos.column, target.name
-                        ),
-                    });
-                }
-            }
-            Statement::BroadcastAndWait { message, pos } => {
-                if message.is_empty() {
-                    return Err(SemanticError {
-                        message: format!(
-                            "Broadcast message cannot be empty at line {}, column {} in target '{}'.",
-                            pos.line, pos.column, target.name
-                        ),
-                    });
-                }
+    let stmt = &statements[index];
+    if target.is_stage {
+        if let Some(block) = sprite_only_statement(stmt) {
+            stage_block_violation(target, block, stmt.pos(), options, report)?;
+        }
+    }
+    analyze_menu_fields(target, stmt)?;
+    match stmt {
+        Statement::Broadcast { message, pos } | Statement::BroadcastAndWait { message, pos } => {
+            if matches!(message, Expr::String { value, .. } if value.is_empty()) {
+                return Err(SemanticError {
+                    message: format!(
+                        "Broadcast message cannot be empty at line {}, column {} in target '{}'.",
+                        pos.line, pos.column, target.name
+                    ),
+                    pos: Some(*pos),
+                    name: None,
+                    related: None,
+                });
             }
-            Statement::SetVar {
+            analyze_expr(
+                target,
+                message,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?
+        }
+        Statement::SetVar {
+            var_name,
+            value,
+            pos,
+        } => {
+            ensure_variable_exists(
+                target,
                 var_name,
+                variables,
+                target_infos,
+                param_scope,
+                pos.line,
+                pos.column,
+            )?;
+            analyze_expr(
+                target,
                 value,
-                pos,
-            } => {
-                ensure_variable_exists(
-                    target,
-                    var_name,
-                    variables,
-                    target_infos,
-                    param_scope,
-                    pos.line,
-                    pos.column,
-                )?;
-                analyze_expr(target, value, variables, lists, target_infos, param_scope)?;
-            }
-            Statement::ChangeVar {
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+        }
+        Statement::ChangeVar {
+            var_name,
+            delta,
+            pos,
+        } => {
+            ensure_variable_exists(
+                target,
                 var_name,
+                variables,
+                target_infos,
+                param_scope,
+                pos.line,
+                pos.column,
+            )?;
+            analyze_expr(
+                target,
                 delta,
-                pos,
-            } => {
-                ensure_variable_exists(
-                    target,
-                    var_name,
-                    variables,
-                    target_infos,
-                    param_scope,
-                    pos.line,
-                    pos.column,
-                )?;
-                analyze_expr(target, delta, variables, lists, target_infos, param_scope)?;
-            }
-            Statement::Move { steps, .. } => {
-                analyze_expr(target, steps, variables, lists, target_infos, param_scope)?
-            }
-            Statement::Say { message, .. } => {
-                analyze_expr(target, message, variables, lists, target_infos, param_scope)?
-            }
-            Statement::SayForSeconds {
-                message, duration, ..
-            } => {
-                analyze_expr(target, message, variables, lists, target_infos, param_scope)?;
-                analyze_expr(
-                    target,
-                    duration,
-                    variables,
-                    lists,
-                    target_infos,
-                    param_scope,
-                )?;
-            }
-            Statement::Think { message, .. } => {
-                analyze_expr(target, message, variables, lists, target_infos, param_scope)?
-            }
-            Statement::Wait { duration, .. } => analyze_expr(
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+        }
+        Statement::Move { steps, .. } => analyze_expr(
+            target,
+            steps,
+            variables,
+            lists,
+            target_infos,
+            param_scope,
+            options,
+            report,
+        )?,
+        Statement::Say { message, .. } => analyze_expr(
+            target,
+            message,
+            variables,
+            lists,
+            target_infos,
+            param_scope,
+            options,
+            report,
+        )?,
+        Statement::SayForSeconds {
+            message, duration, ..
+        }
+        | Statement::ThinkForSeconds {
+            message, duration, ..
+        } => {
+            analyze_expr(
+                target,
+                message,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+            analyze_expr(
                 target,
                 duration,
                 variables,
                 lists,
                 target_infos,
                 param_scope,
-            )?,
-            Statement::WaitUntil { condition, .. } => analyze_expr(
+                options,
+                report,
+            )?;
+        }
+        Statement::Think { message, .. } => analyze_expr(
+            target,
+            message,
+            variables,
+            lists,
+            target_infos,
+            param_scope,
+            options,
+            report,
+        )?,
+        Statement::Wait { duration, .. } => analyze_expr(
+            target,
+            duration,
+            variables,
+            lists,
+            target_infos,
+            param_scope,
+            options,
+            report,
+        )?,
+        Statement::WaitUntil { condition, .. } => analyze_expr(
+            target,
+            condition,
+            variables,
+            lists,
+            target_infos,
+            param_scope,
+            options,
+            report,
+        )?,
+        Statement::Repeat { times, body, .. } => {
+            if let Err(err) = analyze_expr(
                 target,
-                condition,
+                times,
                 variables,
                 lists,
                 target_infos,
                 param_scope,
-            )?,
-            Statement::Repeat { times, body, .. } => {
-                analyze_expr(target, times, variables, lists, target_infos, param_scope)?;
-                analyze_statements(
-                    target,
-                    body,
-                    variables,
-                    lists,
-                    procedures,
-                    target_infos,
-                    param_scope,
-                    scope_name,
-                    options,
-                    warnings,
-                )?;
+                options,
+                report,
+            ) {
+                report.errors.push(err);
             }
-            Statement::ForEach {
-                var_name,
-                value,
+            analyze_statements(
+                target,
                 body,
-                pos,
-            } => {
-                ensure_variable_exists(
-                    target,
-                    var_name,
-                    variables,
-                    target_infos,
-                    param_scope,
-                    pos.line,
-                    pos.column,
-                )?;
-                analyze_expr(target, value, variables, lists, target_infos, param_scope)?;
-                analyze_statements(
-                    target,
-                    body,
-                    variables,
-                    lists,
-                    procedures,
-                    target_infos,
-                    param_scope,
-                    scope_name,
-                    options,
-                    warnings,
-                )?;
+                variables,
+                lists,
+                procedures,
+                target_infos,
+                param_scope,
+                scope_name,
+                options,
+                report,
+            );
+        }
+        Statement::ForEach {
+            var_name,
+            value,
+            body,
+            pos,
+        } => {
+            if let Err(err) = ensure_variable_exists(
+                target,
+                var_name,
+                variables,
+                target_infos,
+                param_scope,
+                pos.line,
+                pos.column,
+            ) {
+                report.errors.push(err);
             }
-            Statement::While {
-                condition, body, ..
-            } => {
-                analyze_expr(
-                    target,
-                    condition,
-                    variables,
-                    lists,
-                    target_infos,
-                    param_scope,
-                )?;
-                analyze_statements(
-                    target,
-                    body,
-                    variables,
-                    lists,
-                    procedures,
-                    target_infos,
-                    param_scope,
-                    scope_name,
-                    options,
-                    warnings,
-                )?;
+            if let Err(err) = analyze_expr(
+                target,
+                value,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            ) {
+                report.errors.push(err);
             }
-            Statement::RepeatUntil {
-                condition, body, ..
-            } => {
-                analyze_expr(
-                    target,
-                    condition,
-                    variables,
-                    lists,
-                    target_infos,
-                    param_scope,
-                )?;
-                analyze_statements(
-                    target,
-                    body,
-                    variables,
-                    lists,
-                    procedures,
-                    target_infos,
-                    param_scope,
-                    scope_name,
-                    options,
-                    warnings,
-                )?;
+            analyze_statements(
+                target,
+                body,
+                variables,
+                lists,
+                procedures,
+                target_infos,
+                param_scope,
+                scope_name,
+                options,
+                report,
+            );
+        }
+        Statement::While {
+            condition, body, ..
+        } => {
+            if let Err(err) = analyze_expr(
+                target,
+                condition,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            ) {
+                report.errors.push(err);
             }
-            Statement::Forever { body, .. } => {
-                analyze_statements(
-                    target,
-                    body,
-                    variables,
-                    lists,
-                    procedures,
-                    target_infos,
-                    param_scope,
-                    scope_name,
-                    options,
-                    warnings,
-                )?;
+            analyze_statements(
+                target,
+                body,
+                variables,
+                lists,
+                procedures,
+                target_infos,
+                param_scope,
+                scope_name,
+                options,
+                report,
+            );
+        }
+        Statement::RepeatUntil {
+            condition, body, ..
+        } => {
+            if let Err(err) = analyze_expr(
+                target,
+                condition,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            ) {
+                report.errors.push(err);
             }
-            Statement::If {
+            analyze_statements(
+                target,
+                body,
+                variables,
+                lists,
+                procedures,
+                target_infos,
+                param_scope,
+                scope_name,
+                options,
+                report,
+            );
+        }
+        Statement::Forever { body, .. } => {
+            analyze_statements(
+                target,
+                body,
+                variables,
+                lists,
+                procedures,
+                target_infos,
+                param_scope,
+                scope_name,
+                options,
+                report,
+            );
+        }
+        Statement::If {
+            condition,
+            then_body,
+            else_body,
+            ..
+        } => {
+            if let Err(err) = analyze_expr(
+                target,
                 condition,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            ) {
+                report.errors.push(err);
+            }
+            analyze_statements(
+                target,
                 then_body,
+                variables,
+                lists,
+                procedures,
+                target_infos,
+                param_scope,
+                scope_name,
+                options,
+                report,
+            );
+            analyze_statements(
+                target,
                 else_body,
-                ..
-            } => {
-                analyze_expr(
-                    target,
-                    condition,
-                    variables,
-                    lists,
-                    target_infos,
-                    param_scope,
-                )?;
-                analyze_statements(
-                    target,
-                    then_body,
-                    variables,
-                    lists,
-                    procedures,
-                    target_infos,
-                    param_scope,
-                    scope_name,
-                    options,
-                    warnings,
-                )?;
-                analyze_statements(
-                    target,
-                    else_body,
-                    variables,
-                    lists,
-                    procedures,
-                    target_infos,
-                    param_scope,
-                    scope_name,
-                    options,
-                    warnings,
-                )?;
-            }
-            Statement::ProcedureCall { name, args, pos } => {
-                if let Some(proc_info) = procedures.get(&name.to_lowercase()) {
-                    if args.len() != proc_info.params.len() {
-                        return Err(SemanticError {
+                variables,
+                lists,
+                procedures,
+                target_infos,
+                param_scope,
+                scope_name,
+                options,
+                report,
+            );
+        }
+        Statement::ProcedureCall { name, args, pos } => {
+            if let Some(proc_info) = procedures.get(&name.to_lowercase()) {
+                if args.len() != proc_info.params.len() {
+                    return Err(SemanticError {
+                        message: format!(
+                            "Procedure '{}' expects {} argument(s), got {} at line {}, column {} in {}.",
+                            name,
+                            proc_info.params.len(),
+                            args.len(),
+                            pos.line,
+                            pos.column,
+                            scope_name
+                        ),
+                        pos: Some(*pos),
+                        name: Some(name.to_string()),
+                        related: None,
+                    });
+                }
+            } else if let Some((remote_target_name, remote_proc_name)) = split_qualified(name) {
+                let Some(remote_target) = target_infos.get(&remote_target_name.to_lowercase())
+                else {
+                    if options.unknown_proc_policy().allows(name) {
+                        report.warnings.push(SemanticWarning {
                             message: format!(
-                                "Procedure '{}' expects {} argument(s), got {} at line {}, column {} in {}.",
-                                name,
-                                proc_info.params.len(),
-                                args.len(),
-                                pos.line,
-                                pos.column,
-                                scope_name
+                                "Allowed unknown procedure call '{}' at line {}, column {} in target '{}' because {}.",
+                                name, pos.line, pos.column, target.name, options.unknown_proc_policy().reason()
                             ),
+                            pos: Some(*pos),
+                            name: Some(name.to_string()),
+                            related: None,
                         });
-                    }
-                } else if let Some((remote_target_name, remote_proc_name)) = split_qualified(name) {
-                    let Some(remote_target) = target_infos.get(&remote_target_name.to_lowercase())
-                    else {
-                        if options.allow_unknown_procedures {
-                            warnings.push(SemanticWarning {
-                                message: format!(
-                                    "Allowed unknown procedure call '{}' at line {}, column {} in target '{}' because allow_unknown_procedures is enabled.",
-                                    name, pos.line, pos.column, target.name
-                                ),
-                            });
-                        } else {
-                            return Err(SemanticError {
-                                message: format!(
-                                    "Unknown target '{}' in procedure call '{}' at line {}, column {} in target '{}'.",
-                                    remote_target_name, name, pos.line, pos.column, target.name
-                                ),
-                            });
-                        }
-                        for arg in args {
-                            analyze_expr(target, arg, variables, lists, target_infos, param_scope)?;
-                        }
-                        continue;
-                    };
-                    let Some(expected_args) = remote_target
-                        .procedures
-                        .get(&remote_proc_name.to_lowercase())
-                    else {
-                        if options.allow_unknown_procedures {
-                            warnings.push(SemanticWarning {
-                                message: format!(
-                                    "Allowed unknown procedure call '{}' at line {}, column {} in target '{}' because allow_unknown_procedures is enabled.",
-                                    name, pos.line, pos.column, target.name
-                                ),
-                            });
-                        } else {
-                            return Err(SemanticError {
-                                message: format!(
-                                    "Unknown procedure '{}' on target '{}' at line {}, column {} in target '{}'.",
-                                    remote_proc_name, remote_target.name, pos.line, pos.column, target.name
-                                ),
-                            });
-                        }
-                        for arg in args {
-                            analyze_expr(target, arg, variables, lists, target_infos, param_scope)?;
-                        }
-                        continue;
-                    };
-                    if args.len() != *expected_args {
+                    } else {
                         return Err(SemanticError {
                             message: format!(
-                                "Procedure '{}' on target '{}' expects {} argument(s), got {} at line {}, column {} in {}.",
-                                remote_proc_name,
-                                remote_target.name,
-                                expected_args,
-                                args.len(),
-                                pos.line,
-                                pos.column,
-                                scope_name
+                                "Unknown target '{}' in procedure call '{}' at line {}, column {} in target '{}'.",
+                                remote_target_name, name, pos.line, pos.column, target.name
                             ),
+                            pos: Some(*pos),
+                            name: Some(remote_target_name.to_string()),
+                            related: None,
                         });
                     }
-                } else {
-                    if is_ignored_noop_call(name) {
-                        for arg in args {
-                            analyze_expr(target, arg, variables, lists, target_infos, param_scope)?;
-                        }
-                        continue;
+                    for arg in args {
+                        analyze_expr(
+                            target,
+                            arg,
+                            variables,
+                            lists,
+                            target_infos,
+                            param_scope,
+                            options,
+                            report,
+                        )?;
                     }
-                    if options.allow_unknown_procedures {
-                        warnings.push(SemanticWarning {
+                    return Ok(());
+                };
+                let Some(expected_args) = remote_target
+                    .procedures
+                    .get(&remote_proc_name.to_lowercase())
+                else {
+                    let hint = did_you_mean(
+                        remote_proc_name,
+                        remote_target.procedure_names.iter().map(String::as_str),
+                    );
+                    if options.unknown_proc_policy().allows(name) {
+                        report.warnings.push(SemanticWarning {
                             message: format!(
-                                "Allowed unknown procedure call '{}' at line {}, column {} in target '{}' because allow_unknown_procedures is enabled.",
-                                name, pos.line, pos.column, target.name
+                                "Allowed unknown procedure call '{}' at line {}, column {} in target '{}' because {}.{}",
+                                name, pos.line, pos.column, target.name, options.unknown_proc_policy().reason(), hint
                             ),
+                            pos: Some(*pos),
+                            name: Some(name.to_string()),
+                            related: None,
                         });
                     } else {
                         return Err(SemanticError {
                             message: format!(
-                                "Unknown procedure '{}' at line {}, column {} in target '{}'.",
-                                name, pos.line, pos.column, target.name
+                                "Unknown procedure '{}' on target '{}' at line {}, column {} in target '{}'.{}",
+                                remote_proc_name, remote_target.name, pos.line, pos.column, target.name, hint
                             ),
+                            pos: Some(*pos),
+                            name: Some(remote_proc_name.to_string()),
+                            related: None,
                         });
                     }
+                    for arg in args {
+                        analyze_expr(
+                            target,
+                            arg,
+                            variables,
+                            lists,
+                            target_infos,
+                            param_scope,
+                            options,
+                            report,
+                        )?;
+                    }
+                    return Ok(());
+                };
+                if args.len() != *expected_args {
+                    return Err(SemanticError {
+                        message: format!(
+                            "Procedure '{}' on target '{}' expects {} argument(s), got {} at line {}, column {} in {}.",
+                            remote_proc_name,
+                            remote_target.name,
+                            expected_args,
+                            args.len(),
+                            pos.line,
+                            pos.column,
+                            scope_name
+                        ),
+                        pos: Some(*pos),
+                        name: Some(remote_proc_name.to_string()),
+                        related: None,
+                    });
+                }
+            } else {
+                if is_ignored_noop_call(name) {
+                    for arg in args {
+                        analyze_expr(
+                            target,
+                            arg,
+                            variables,
+                            lists,
+                            target_infos,
+                            param_scope,
+                            options,
+                            report,
+                        )?;
+                    }
+                    return Ok(());
                 }
-                for arg in args {
-                    analyze_expr(target, arg, variables, lists, target_infos, param_scope)?;
+                // An allowed call that is a near miss of a declared procedure keeps the
+                // hint in its warning, since it is most likely a typo.
+                let hint = did_you_mean(name, target.procedures.iter().map(|p| p.name.as_str()));
+                if options.unknown_proc_policy().allows(name) {
+                    report.warnings.push(SemanticWarning {
+                        message: format!(
+                            "Allowed unknown procedure call '{}' at line {}, column {} in target '{}' because {}.{}",
+                            name, pos.line, pos.column, target.name, options.unknown_proc_policy().reason(), hint
+                        ),
+                        pos: Some(*pos),
+                        name: Some(name.to_string()),
+                        related: None,
+                    });
+                } else {
+                    return Err(SemanticError {
+                        message: format!(
+                            "Unknown procedure '{}' at line {}, column {} in target '{}'.{}",
+                            name, pos.line, pos.column, target.name, hint
+                        ),
+                        pos: Some(*pos),
+                        name: Some(name.to_string()),
+                        related: None,
+                    });
                 }
             }
-            Statement::TurnRight { degrees, .. } => {
-                analyze_expr(target, degrees, variables, lists, target_infos, param_scope)?
-            }
-            Statement::TurnLeft { degrees, .. } => {
-                analyze_expr(target, degrees, variables, lists, target_infos, param_scope)?
-            }
-            Statement::GoToXY { x, y, .. } => {
-                analyze_expr(target, x, variables, lists, target_infos, param_scope)?;
-                analyze_expr(target, y, variables, lists, target_infos, param_scope)?;
-            }
-            Statement::GoToTarget { target: value, .. }
-            | Statement::GlideToTarget { target: value, .. }
-            | Statement::PointTowards { target: value, .. }
-            | Statement::CreateCloneOf { target: value, .. } => {
-                analyze_expr(target, value, variables, lists, target_infos, param_scope)?
-            }
-            Statement::GlideToXY { duration, x, y, .. } => {
+            for arg in args {
                 analyze_expr(
                     target,
-                    duration,
+                    arg,
                     variables,
                     lists,
                     target_infos,
                     param_scope,
+                    options,
+                    report,
                 )?;
-                analyze_expr(target, x, variables, lists, target_infos, param_scope)?;
-                analyze_expr(target, y, variables, lists, target_infos, param_scope)?;
-            }
-            Statement::ChangeXBy { value, .. }
-            | Statement::SetX { value, .. }
-            | Statement::ChangeYBy { value, .. }
-            | Statement::SetY { value, .. }
-            | Statement::ChangeSizeBy { value, .. }
-            | Statement::SetSizeTo { value, .. }
-            | Statement::SetGraphicEffectTo { value, .. }
-            | Statement::ChangeGraphicEffectBy { value, .. }
-            | Statement::GoLayers { layers: value, .. }
-            | Statement::ChangePenSizeBy { value, .. }
-            | Statement::SetPenSizeTo { value, .. }
-            | Statement::ChangePenColorParamBy { value, .. }
-            | Statement::SetPenColorParamTo { value, .. }
-            | Statement::SwitchCostumeTo { costume: value, .. }
-            | Statement::SwitchBackdropTo {
-                backdrop: value, ..
             }
-            | Statement::SetSoundEffectTo { value, .. }
-            | Statement::SetVolumeTo { value, .. }
-            | Statement::StartSound { sound: value, .. }
-            | Statement::PlaySoundUntilDone { sound: value, .. } => {
-                analyze_expr(target, value, variables, lists, target_infos, param_scope)?
+        }
+        Statement::TurnRight { degrees, .. } => analyze_expr(
+            target,
+            degrees,
+            variables,
+            lists,
+            target_infos,
+            param_scope,
+            options,
+            report,
+        )?,
+        Statement::TurnLeft { degrees, .. } => analyze_expr(
+            target,
+            degrees,
+            variables,
+            lists,
+            target_infos,
+            param_scope,
+            options,
+            report,
+        )?,
+        Statement::GoToXY { x, y, .. } => {
+            analyze_expr(
+                target,
+                x,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+            analyze_expr(
+                target,
+                y,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+        }
+        Statement::GoToTarget { target: value, .. }
+        | Statement::GlideToTarget { target: value, .. }
+        | Statement::CreateCloneOf { target: value, .. } => analyze_expr(
+            target,
+            value,
+            variables,
+            lists,
+            target_infos,
+            param_scope,
+            options,
+            report,
+        )?,
+        Statement::PointTowards { target: value, pos } => {
+            if let Expr::String { value: name, .. } = value {
+                let lowered = name.trim().to_ascii_lowercase();
+                let is_mouse = matches!(
+                    lowered.as_str(),
+                    "_mouse_" | "mouse" | "mouse pointer" | "mouse-pointer"
+                );
+                if !is_mouse && !target_infos.contains_key(&lowered) {
+                    report.warnings.push(SemanticWarning {
+                        message: format!(
+                            "Pointing towards unknown sprite '{}' at line {}, column {} in target '{}'.",
+                            name, pos.line, pos.column, target.name
+                        ),
+                        pos: Some(*pos),
+                        name: Some(name.to_string()),
+                        related: None,
+                    });
+                }
             }
-            Statement::PointInDirection { direction, .. } => analyze_expr(
+            analyze_expr(
                 target,
-                direction,
+                value,
                 variables,
                 lists,
                 target_infos,
                 param_scope,
-            )?,
-            Statement::IfOnEdgeBounce { .. }
-            | Statement::SetRotationStyle { .. }
-            | Statement::PenDown { .. }
-            | Statement::PenUp { .. }
-            | Statement::PenClear { .. }
-            | Statement::PenStamp { .. }
-            | Statement::ClearGraphicEffects { .. }
-            | Statement::GoToLayer { .. }
-            | Statement::Show { .. }
-            | Statement::Hide { .. }
-            | Statement::NextCostume { .. }
-            | Statement::NextBackdrop { .. }
-            | Statement::StopAllSounds { .. }
-            | Statement::DeleteThisClone { .. }
-            | Statement::ResetTimer { .. } => {}
-            Statement::Stop { option, .. } => {
-                analyze_expr(target, option, variables, lists, target_infos, param_scope)?
+                options,
+                report,
+            )?
+        }
+        Statement::GlideToXY { duration, x, y, .. } => {
+            analyze_expr(
+                target,
+                duration,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+            analyze_expr(
+                target,
+                x,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+            analyze_expr(
+                target,
+                y,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+        }
+        Statement::ChangeXBy { value, .. }
+        | Statement::SetX { value, .. }
+        | Statement::ChangeYBy { value, .. }
+        | Statement::SetY { value, .. }
+        | Statement::ChangeSizeBy { value, .. }
+        | Statement::SetSizeTo { value, .. }
+        | Statement::SetGraphicEffectTo { value, .. }
+        | Statement::ChangeGraphicEffectBy { value, .. }
+        | Statement::GoLayers { layers: value, .. }
+        | Statement::ChangePenSizeBy { value, .. }
+        | Statement::SetPenSizeTo { value, .. }
+        | Statement::ChangePenColorParamBy { value, .. }
+        | Statement::SetPenColorParamTo { value, .. }
+        | Statement::SwitchCostumeTo { costume: value, .. }
+        | Statement::SwitchBackdropTo {
+            backdrop: value, ..
+        }
+        | Statement::SwitchBackdropToAndWait {
+            backdrop: value, ..
+        }
+        | Statement::SetVolumeTo { value, .. }
+        | Statement::ChangeVolumeBy { value, .. }
+        | Statement::RestForBeats { beats: value, .. }
+        | Statement::SetTempoTo { value, .. }
+        | Statement::ChangeTempoBy { value, .. } => analyze_expr(
+            target,
+            value,
+            variables,
+            lists,
+            target_infos,
+            param_scope,
+            options,
+            report,
+        )?,
+        Statement::SetPenColorTo { color, .. } => analyze_color_input(
+            target,
+            color,
+            variables,
+            lists,
+            target_infos,
+            param_scope,
+            options,
+            report,
+        )?,
+        Statement::SetSoundEffectTo { effect, value, pos }
+        | Statement::ChangeSoundEffectBy { effect, value, pos } => {
+            if !is_sound_effect_name(effect) {
+                return Err(SemanticError {
+                    message: format!(
+                        "Unknown sound effect '{}' at line {}, column {} in target '{}'. Expected 'pitch' or 'pan'.",
+                        effect, pos.line, pos.column, target.name
+                    ),
+                    pos: Some(*pos),
+                    name: Some(effect.to_string()),
+                    related: None,
+                });
             }
-            Statement::Ask { question, .. } => analyze_expr(
+            analyze_expr(
                 target,
-                question,
+                value,
                 variables,
                 lists,
                 target_infos,
                 param_scope,
-            )?,
-            Statement::ShowVariable { var_name, pos }
-            | Statement::HideVariable { var_name, pos } => {
-                ensure_variable_exists(
+                options,
+                report,
+            )?
+        }
+        Statement::PlayNoteForBeats { note, beats, .. } => {
+            for value in [note, beats] {
+                analyze_expr(
                     target,
-                    var_name,
+                    value,
                     variables,
+                    lists,
                     target_infos,
                     param_scope,
-                    pos.line,
-                    pos.column,
+                    options,
+                    report,
                 )?;
             }
-            Statement::AddToList {
-                list_name,
-                item,
-                pos,
-            } => {
-                ensure_list_exists(target, list_name, lists, target_infos, pos.line, pos.column)?;
-                analyze_expr(target, item, variables, lists, target_infos, param_scope)?;
+        }
+        Statement::PlayDrumForBeats { drum, beats, pos } => {
+            if music_menu_number(&MUSIC_DRUMS, drum).is_none() {
+                return Err(SemanticError {
+                    message: format!(
+                        "Unknown drum '{}' at line {}, column {} in target '{}'.",
+                        drum, pos.line, pos.column, target.name
+                    ),
+                    pos: Some(*pos),
+                    name: Some(drum.to_string()),
+                    related: None,
+                });
             }
-            Statement::DeleteOfList {
-                list_name,
-                index,
-                pos,
-            } => {
-                ensure_list_exists(target, list_name, lists, target_infos, pos.line, pos.column)?;
-                analyze_expr(target, index, variables, lists, target_infos, param_scope)?;
+            analyze_expr(
+                target,
+                beats,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?
+        }
+        Statement::SetInstrumentTo { instrument, pos } => {
+            if music_menu_number(&MUSIC_INSTRUMENTS, instrument).is_none() {
+                return Err(SemanticError {
+                    message: format!(
+                        "Unknown instrument '{}' at line {}, column {} in target '{}'.",
+                        instrument, pos.line, pos.column, target.name
+                    ),
+                    pos: Some(*pos),
+                    name: Some(instrument.to_string()),
+                    related: None,
+                });
             }
-            Statement::DeleteAllOfList { list_name, pos } => {
-                ensure_list_exists(target, list_name, lists, target_infos, pos.line, pos.column)?;
+        }
+        Statement::StartSound { sound, pos } | Statement::PlaySoundUntilDone { sound, pos } => {
+            if let Expr::String { value: name, .. } = sound {
+                if !sound_names(target).contains(&name.to_lowercase()) {
+                    report.warnings.push(SemanticWarning {
+                        message: format!(
+                            "Sound '{}' at line {}, column {} in target '{}' is not declared.",
+                            name, pos.line, pos.column, target.name
+                        ),
+                        pos: Some(*pos),
+                        name: Some(name.to_string()),
+                        related: None,
+                    });
+                }
             }
-            Statement::InsertAtList {
-                list_name,
+            analyze_expr(
+                target,
+                sound,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?
+        }
+        Statement::PointInDirection { direction, .. } => analyze_expr(
+            target,
+            direction,
+            variables,
+            lists,
+            target_infos,
+            param_scope,
+            options,
+            report,
+        )?,
+        Statement::IfOnEdgeBounce { .. }
+        | Statement::SetRotationStyle { .. }
+        | Statement::PenDown { .. }
+        | Statement::PenUp { .. }
+        | Statement::PenClear { .. }
+        | Statement::PenStamp { .. }
+        | Statement::ClearGraphicEffects { .. }
+        | Statement::GoToLayer { .. }
+        | Statement::Show { .. }
+        | Statement::Hide { .. }
+        | Statement::NextCostume { .. }
+        | Statement::NextBackdrop { .. }
+        | Statement::StopAllSounds { .. }
+        | Statement::ClearSoundEffects { .. }
+        | Statement::DeleteThisClone { .. }
+        | Statement::ResetTimer { .. } => {}
+        Statement::Stop { option, pos } => {
+            let Expr::String { value, .. } = option else {
+                return Err(SemanticError {
+                    message: format!(
+                        "Stop option at line {}, column {} in target '{}' must be a literal 'all', 'this script', or 'other scripts in sprite'.",
+                        pos.line, pos.column, target.name
+                    ),
+                    pos: Some(*pos),
+                    name: None,
+                    related: None,
+                });
+            };
+            if !is_stop_option_name(value) {
+                return Err(SemanticError {
+                    message: format!(
+                        "Unknown stop option '{}' at line {}, column {} in target '{}'. Expected 'all', 'this script', or 'other scripts in sprite'.",
+                        value, pos.line, pos.column, target.name
+                    ),
+                    pos: Some(*pos),
+                    name: Some(value.to_string()),
+                    related: None,
+                });
+            }
+            if !stop_option_allows_next(value) && index + 1 < statements.len() {
+                return Err(SemanticError {
+                    message: format!(
+                        "Statements after 'stop [{}]' at line {}, column {} in target '{}' can never run. Only 'stop [other scripts in sprite]' can be followed by more blocks.",
+                        value, pos.line, pos.column, target.name
+                    ),
+                    pos: Some(*pos),
+                    name: None,
+                    related: None,
+                });
+            }
+        }
+        Statement::Ask { question, .. } => analyze_expr(
+            target,
+            question,
+            variables,
+            lists,
+            target_infos,
+            param_scope,
+            options,
+            report,
+        )?,
+        Statement::ShowVariable { var_name, pos } | Statement::HideVariable { var_name, pos } => {
+            ensure_variable_exists(
+                target,
+                var_name,
+                variables,
+                target_infos,
+                param_scope,
+                pos.line,
+                pos.column,
+            )?;
+        }
+        Statement::ShowList { list_name, pos } | Statement::HideList { list_name, pos } => {
+            ensure_list_exists(target, list_name, lists, target_infos, pos.line, pos.column)?;
+        }
+        Statement::AddToList {
+            list_name,
+            item,
+            pos,
+        } => {
+            ensure_list_exists(target, list_name, lists, target_infos, pos.line, pos.column)?;
+            analyze_expr(
+                target,
                 item,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+        }
+        Statement::DeleteOfList {
+            list_name,
+            index,
+            pos,
+        } => {
+            ensure_list_exists(target, list_name, lists, target_infos, pos.line, pos.column)?;
+            analyze_expr(
+                target,
                 index,
-                pos,
-            } => {
-                ensure_list_exists(target, list_name, lists, target_infos, pos.line, pos.column)?;
-                analyze_expr(target, item, variables, lists, target_infos, param_scope)?;
-                analyze_expr(target, index, variables, lists, target_infos, param_scope)?;
-            }
-            Statement::ReplaceItemOfList {
-                list_name,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+        }
+        Statement::DeleteAllOfList { list_name, pos } => {
+            ensure_list_exists(target, list_name, lists, target_infos, pos.line, pos.column)?;
+        }
+        Statement::InsertAtList {
+            list_name,
+            item,
+            index,
+            pos,
+        } => {
+            ensure_list_exists(target, list_name, lists, target_infos, pos.line, pos.column)?;
+            analyze_expr(
+                target,
+                item,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+            analyze_expr(
+                target,
+                index,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+        }
+        Statement::ReplaceItemOfList {
+            list_name,
+            index,
+            item,
+            pos,
+        } => {
+            ensure_list_exists(target, list_name, lists, target_infos, pos.line, pos.column)?;
+            analyze_expr(
+                target,
                 index,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+            analyze_expr(
+                target,
                 item,
-                pos,
-            } => {
-                ensure_list_exists(target, list_name, lists, target_infos, pos.line, pos.column)?;
-                analyze_expr(target, index, variables, lists, target_infos, param_scope)?;
-                analyze_expr(target, item, variables, lists, target_infos, param_scope)?;
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+        }
+        Statement::RawBlock { block, pos } => {
+            analyze_raw_block(target, block, *pos, variables, lists, target_infos)?;
+        }
+    }
+    Ok(())
+}
+
+/// Every block object in a `raw` statement needs an opcode, and the variables and
+/// lists it names must exist so codegen can point them at the fresh ids.
+fn analyze_raw_block(
+    target: &Target,
+    block: &serde_json::Value,
+    pos: Position,
+    variables: &HashMap<String, usize>,
+    lists: &HashMap<String, usize>,
+    target_infos: &HashMap<String, TargetInfo>,
+) -> Result<(), SemanticError> {
+    let opcode = block
+        .get("opcode")
+        .and_then(serde_json::Value::as_str)
+        .unwrap_or_default();
+    if !block.is_object() || opcode.is_empty() {
+        return Err(SemanticError {
+            message: format!(
+                "Raw block at line {}, column {} in target '{}' must be a JSON object with an 'opcode' field.",
+                pos.line, pos.column, target.name
+            ),
+            pos: Some(pos),
+            name: None,
+            related: None,
+        });
+    }
+    let no_params = HashSet::new();
+    if let Some(fields) = block.get("fields").and_then(serde_json::Value::as_object) {
+        for (key, value) in fields {
+            let Some(name) = value.get(0).and_then(serde_json::Value::as_str) else {
+                continue;
+            };
+            match key.as_str() {
+                "VARIABLE" => ensure_variable_exists(
+                    target,
+                    name,
+                    variables,
+                    target_infos,
+                    &no_params,
+                    pos.line,
+                    pos.column,
+                )?,
+                "LIST" => {
+                    ensure_list_exists(target, name, lists, target_infos, pos.line, pos.column)?
+                }
+                _ => {}
+            }
+        }
+    }
+    let inputs = block
+        .get("inputs")
+        .and_then(serde_json::Value::as_object)
+        .into_iter()
+        .flat_map(|inputs| inputs.values())
+        .filter_map(serde_json::Value::as_array);
+    for input in inputs {
+        for slot in input.iter().skip(1) {
+            if slot.is_object() {
+                analyze_raw_block(target, slot, pos, variables, lists, target_infos)?;
+                continue;
+            }
+            let name = slot.get(1).and_then(serde_json::Value::as_str);
+            match (slot.get(0).and_then(serde_json::Value::as_i64), name) {
+                (Some(12), Some(name)) => ensure_variable_exists(
+                    target,
+                    name,
+                    variables,
+                    target_infos,
+                    &no_params,
+                    pos.line,
+                    pos.column,
+                )?,
+                (Some(13), Some(name)) => {
+                    ensure_list_exists(target, name, lists, target_infos, pos.line, pos.column)?
+                }
+                _ => {}
             }
         }
     }
+    if let Some(next) = block.get("next").filter(|next| next.is_object()) {
+        analyze_raw_block(target, next, pos, variables, lists, target_infos)?;
+    }
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn analyze_expr(
     target: &Target,
     expr: &Expr,
@@ -809,7 +2023,14 @@ fn analyze_expr(
     lists: &HashMap<String, usize>,
     target_infos: &HashMap<String, TargetInfo>,
     param_scope: &HashSet<String>,
+    options: &SemanticOptions,
+    report: &mut SemanticReport,
 ) -> Result<(), SemanticError> {
+    if target.is_stage {
+        if let Some(block) = sprite_only_reporter(expr) {
+            stage_block_violation(target, block, expr.pos(), options, report)?;
+        }
+    }
     match expr {
         Expr::Var { name, pos } => {
             let lowered = name.to_lowercase();
@@ -827,6 +2048,9 @@ fn analyze_expr(
                             "Unknown target '{}' in variable reference '{}' at line {}, column {} in target '{}'.",
                             remote_target_name, name, pos.line, pos.column, target.name
                         ),
+                        pos: Some(*pos),
+                        name: Some(remote_target_name.to_string()),
+                        related: None,
                     });
                 };
                 if is_sensing_property_name(remote_var_name) {
@@ -841,30 +2065,95 @@ fn analyze_expr(
                             "Unknown variable '{}' on target '{}' at line {}, column {} in target '{}'.",
                             remote_var_name, remote_target.name, pos.line, pos.column, target.name
                         ),
+                        pos: Some(*pos),
+                        name: Some(remote_var_name.to_string()),
+                        related: None,
                     });
                 }
                 return Ok(());
             }
+            let candidates = param_scope.iter().map(String::as_str).chain(visible_names(
+                target,
+                target_infos,
+                true,
+            ));
             Err(SemanticError {
                 message: format!(
-                    "Unknown variable '{}' at line {}, column {} in target '{}'.",
-                    name, pos.line, pos.column, target.name
+                    "Unknown variable '{}' at line {}, column {} in target '{}'.{}",
+                    name,
+                    pos.line,
+                    pos.column,
+                    target.name,
+                    did_you_mean(name, candidates)
                 ),
+                pos: Some(*pos),
+                name: Some(name.to_string()),
+                related: None,
             })
         }
-        Expr::Unary { operand, .. } => {
-            analyze_expr(target, operand, variables, lists, target_infos, param_scope)
-        }
-        Expr::MathFunc { value, .. } => {
-            analyze_expr(target, value, variables, lists, target_infos, param_scope)
-        }
+        Expr::Unary { operand, .. } => analyze_expr(
+            target,
+            operand,
+            variables,
+            lists,
+            target_infos,
+            param_scope,
+            options,
+            report,
+        ),
+        Expr::MathFunc { value, .. } => analyze_expr(
+            target,
+            value,
+            variables,
+            lists,
+            target_infos,
+            param_scope,
+            options,
+            report,
+        ),
         Expr::Binary { left, right, .. } => {
-            analyze_expr(target, left, variables, lists, target_infos, param_scope)?;
-            analyze_expr(target, right, variables, lists, target_infos, param_scope)
+            analyze_expr(
+                target,
+                left,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+            analyze_expr(
+                target,
+                right,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )
         }
         Expr::PickRandom { start, end, .. } => {
-            analyze_expr(target, start, variables, lists, target_infos, param_scope)?;
-            analyze_expr(target, end, variables, lists, target_infos, param_scope)
+            analyze_expr(
+                target,
+                start,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+            analyze_expr(
+                target,
+                end,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )
         }
         Expr::ListItem {
             list_name,
@@ -872,7 +2161,16 @@ fn analyze_expr(
             pos,
         } => {
             ensure_list_exists(target, list_name, lists, target_infos, pos.line, pos.column)?;
-            analyze_expr(target, index, variables, lists, target_infos, param_scope)
+            analyze_expr(
+                target,
+                index,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )
         }
         Expr::ListLength { list_name, pos } => {
             ensure_list_exists(target, list_name, lists, target_infos, pos.line, pos.column)
@@ -884,34 +2182,853 @@ fn analyze_expr(
             list_name,
             item,
             pos,
+        }
+        | Expr::ItemNumOfList {
+            list_name,
+            item,
+            pos,
         } => {
             ensure_list_exists(target, list_name, lists, target_infos, pos.line, pos.column)?;
-            analyze_expr(target, item, variables, lists, target_infos, param_scope)
-        }
-        Expr::KeyPressed { key, .. } => {
-            analyze_expr(target, key, variables, lists, target_infos, param_scope)
+            analyze_expr(
+                target,
+                item,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )
         }
-        Expr::TouchingObject { target: value, .. } => {
-            analyze_expr(target, value, variables, lists, target_infos, param_scope)
+        Expr::KeyPressed { key, .. } => analyze_expr(
+            target,
+            key,
+            variables,
+            lists,
+            target_infos,
+            param_scope,
+            options,
+            report,
+        ),
+        Expr::TouchingObject { target: value, pos } => {
+            if let Expr::String { value: name, .. } = value.as_ref() {
+                if !is_builtin_touching_option(name)
+                    && !target_infos.contains_key(&name.to_lowercase())
+                {
+                    report.warnings.push(SemanticWarning {
+                        message: format!(
+                            "Touching unknown sprite '{}' at line {}, column {} in target '{}'.",
+                            name, pos.line, pos.column, target.name
+                        ),
+                        pos: Some(*pos),
+                        name: Some(name.to_string()),
+                        related: None,
+                    });
+                }
+            }
+            analyze_expr(
+                target,
+                value,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )
         }
-        Expr::TouchingColor { color, .. } => {
-            analyze_expr(target, color, variables, lists, target_infos, param_scope)
+        Expr::TouchingColor { color, .. } => analyze_color_input(
+            target,
+            color,
+            variables,
+            lists,
+            target_infos,
+            param_scope,
+            options,
+            report,
+        ),
+        Expr::ColorTouchingColor { color, color2, .. } => {
+            analyze_color_input(
+                target,
+                color,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+            analyze_color_input(
+                target,
+                color2,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )
         }
         Expr::StringJoin { text1, text2, .. } => {
-            analyze_expr(target, text1, variables, lists, target_infos, param_scope)?;
-            analyze_expr(target, text2, variables, lists, target_infos, param_scope)
+            analyze_expr(
+                target,
+                text1,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+            analyze_expr(
+                target,
+                text2,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )
+        }
+        Expr::StringLength { text, .. } => analyze_expr(
+            target,
+            text,
+            variables,
+            lists,
+            target_infos,
+            param_scope,
+            options,
+            report,
+        ),
+        Expr::LetterOf {
+            index: first,
+            text: second,
+            ..
+        }
+        | Expr::StringContains {
+            text: first,
+            substring: second,
+            ..
+        } => {
+            analyze_expr(
+                target,
+                first,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+            analyze_expr(
+                target,
+                second,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )
         }
         Expr::StringSplit { text, sep, .. } => {
-            analyze_expr(target, text, variables, lists, target_infos, param_scope)?;
-            analyze_expr(target, sep, variables, lists, target_infos, param_scope)
+            analyze_expr(
+                target,
+                text,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+            analyze_expr(
+                target,
+                sep,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )
+        }
+        Expr::Substring {
+            text, start, end, ..
+        } => {
+            analyze_expr(
+                target,
+                text,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+            analyze_expr(
+                target,
+                start,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )?;
+            analyze_expr(
+                target,
+                end,
+                variables,
+                lists,
+                target_infos,
+                param_scope,
+                options,
+                report,
+            )
+        }
+        Expr::Color { value, pos } => Err(SemanticError {
+            message: format!(
+                "Color literal '{}' at line {}, column {} in target '{}' can only be used as the color of 'set pen color to', 'touching color', or 'color is touching'.",
+                value, pos.line, pos.column, target.name
+            ),
+            pos: Some(*pos),
+            name: None,
+            related: None,
+        }),
+        Expr::BuiltinReporter { .. }
+        | Expr::LooksNumberName { .. }
+        | Expr::Number { .. }
+        | Expr::String { .. } => Ok(()),
+    }
+}
+
+/// Checks the input of a color slot, the one place a color literal is allowed.
+#[allow(clippy::too_many_arguments)]
+fn analyze_color_input(
+    target: &Target,
+    expr: &Expr,
+    variables: &HashMap<String, usize>,
+    lists: &HashMap<String, usize>,
+    target_infos: &HashMap<String, TargetInfo>,
+    param_scope: &HashSet<String>,
+    options: &SemanticOptions,
+    report: &mut SemanticReport,
+) -> Result<(), SemanticError> {
+    if matches!(expr, Expr::Color { .. }) {
+        return Ok(());
+    }
+    analyze_expr(
+        target,
+        expr,
+        variables,
+        lists,
+        target_infos,
+        param_scope,
+        options,
+        report,
+    )
+}
+
+struct CallNode<'a> {
+    target: &'a Target,
+    procedure: &'a Procedure,
+    /// Callee node index, the position of the call statement, and whether the
+    /// call is a qualified `Target.proc` remote call.
+    calls: Vec<(usize, Position, bool)>,
+}
+
+/// Builds the procedure call graph across all targets (qualified remote calls included)
+/// and reports every recursive cycle. Recursion made only of run-without-screen-refresh
+/// procedures never yields to the VM, so it freezes the project; other recursion only
+/// gets a note. Remote calls wait for their handler to finish, so a cycle of remote calls
+/// deadlocks and is an error; a cycle mixing remote and local calls gets a warning.
+fn analyze_recursion(project: &Project, report: &mut SemanticReport) {
+    let mut nodes = Vec::new();
+    let mut index = HashMap::new();
+    for target in &project.targets {
+        for procedure in &target.procedures {
+            let key = format!(
+                "{}\n{}",
+                target.name.to_lowercase(),
+                procedure.name.to_lowercase()
+            );
+            index.entry(key).or_insert_with(|| {
+                nodes.push(CallNode {
+                    target,
+                    procedure,
+                    calls: Vec::new(),
+                });
+                nodes.len() - 1
+            });
+        }
+    }
+    for node in &mut nodes {
+        let mut calls = Vec::new();
+        collect_procedure_calls(&node.procedure.body, &mut calls);
+        let local_target = node.target.name.to_lowercase();
+        for (name, pos) in calls {
+            let local = index.get(&format!("{}\n{}", local_target, name.to_lowercase()));
+            let remote = split_qualified(name).and_then(|(target_name, proc_name)| {
+                index.get(&format!(
+                    "{}\n{}",
+                    target_name.to_lowercase(),
+                    proc_name.to_lowercase()
+                ))
+            });
+            if let Some(&callee) = local {
+                node.calls.push((callee, pos, false));
+            } else if let Some(&callee) = remote {
+                node.calls.push((callee, pos, true));
+            }
+        }
+    }
+
+    for component in strongly_connected_components(&nodes) {
+        let start = component[0];
+        let recursive =
+            component.len() > 1 || nodes[start].calls.iter().any(|(c, _, _)| *c == start);
+        if !recursive {
+            continue;
+        }
+        if report_remote_cycle(&nodes, &component, report) {
+            continue;
+        }
+        let cycle = shortest_cycle(&nodes, start, &component, false);
+        let single_target = cycle
+            .iter()
+            .all(|(node, _)| std::ptr::eq(nodes[*node].target, nodes[start].target));
+        let label = |node: usize| {
+            if single_target {
+                nodes[node].procedure.name.clone()
+            } else {
+                format!("{}.{}", nodes[node].target.name, nodes[node].procedure.name)
+            }
+        };
+        let mut path = cycle
+            .iter()
+            .map(|(node, _)| label(*node))
+            .collect::<Vec<_>>();
+        path.push(label(start));
+        let calls = cycle
+            .iter()
+            .map(|(_, pos)| format!("line {}, column {}", pos.line, pos.column))
+            .collect::<Vec<_>>();
+        let first = &nodes[start];
+        let message = if component
+            .iter()
+            .all(|node| nodes[*node].procedure.run_without_screen_refresh)
+        {
+            format!(
+                "Run-without-screen-refresh procedures recurse in target '{}': {} (calls at {}). This freezes the Scratch VM until the recursion ends.",
+                first.target.name,
+                path.join(" -> "),
+                calls.join("; ")
+            )
+        } else {
+            format!(
+                "Note: procedures recurse in target '{}': {} (calls at {}). Make sure the recursion terminates.",
+                first.target.name,
+                path.join(" -> "),
+                calls.join("; ")
+            )
+        };
+        report.warnings.push(SemanticWarning {
+            message,
+            pos: Some(cycle[0].1),
+            name: Some(first.procedure.name.clone()),
+            related: Some(first.procedure.pos),
+        });
+    }
+}
+
+/// Reports a recursive component that contains remote calls: an error naming a cycle made
+/// only of remote calls if there is one, otherwise a warning naming the shortest cycle
+/// through a remote call. Returns false when the component has no remote calls.
+fn report_remote_cycle(
+    nodes: &[CallNode],
+    component: &[usize],
+    report: &mut SemanticReport,
+) -> bool {
+    let is_remote_call = |(caller, pos): &(usize, Position)| {
+        nodes[*caller]
+            .calls
+            .iter()
+            .any(|&(_, call_pos, remote)| remote && call_pos == *pos)
+    };
+    let remote_cycle = component
+        .iter()
+        .map(|&start| shortest_cycle(nodes, start, component, true))
+        .find(|cycle| !cycle.is_empty());
+    let all_remote = remote_cycle.is_some();
+    let Some(cycle) = remote_cycle.or_else(|| {
+        component
+            .iter()
+            .map(|&start| shortest_cycle(nodes, start, component, false))
+            .filter(|cycle| cycle.iter().any(is_remote_call))
+            .min_by_key(Vec::len)
+    }) else {
+        return false;
+    };
+
+    let label = |node: usize| format!("{}.{}", nodes[node].target.name, nodes[node].procedure.name);
+    let mut path = cycle
+        .iter()
+        .map(|(node, _)| label(*node))
+        .collect::<Vec<_>>();
+    path.push(label(cycle[0].0));
+    let calls = cycle
+        .iter()
+        .map(|(_, pos)| format!("line {}, column {}", pos.line, pos.column))
+        .collect::<Vec<_>>();
+    let first = &nodes[cycle[0].0];
+    let pos = cycle
+        .iter()
+        .find(|call| is_remote_call(call))
+        .map(|(_, pos)| *pos);
+    let related = cycle.last().map(|(_, pos)| *pos);
+    if all_remote {
+        report.errors.push(SemanticError {
+            message: format!(
+                "Remote procedure calls form a cycle: {} (calls at {}). Each remote call waits for the previous one to finish, so the Scratch VM deadlocks.",
+                path.join(" -> "),
+                calls.join("; ")
+            ),
+            pos,
+            name: Some(first.procedure.name.clone()),
+            related,
+        });
+    } else {
+        report.warnings.push(SemanticWarning {
+            message: format!(
+                "Remote procedure calls form a cycle through local calls: {} (calls at {}). A remote call waits for its handler to finish, so the Scratch VM deadlocks if this cycle runs.",
+                path.join(" -> "),
+                calls.join("; ")
+            ),
+            pos,
+            name: Some(first.procedure.name.clone()),
+            related,
+        });
+    }
+    true
+}
+
+/// Rejects user variables, lists, and broadcasts named like the globals and messages the
+/// compiler generates for a `Target.proc` call, since the generated plumbing would share
+/// (and clobber) them.
+fn analyze_rpc_name_collisions(project: &Project, report: &mut SemanticReport) {
+    let mut procedures = HashMap::new();
+    for target in &project.targets {
+        for procedure in &target.procedures {
+            procedures.insert(
+                format!(
+                    "{}\n{}",
+                    target.name.to_lowercase(),
+                    procedure.name.to_lowercase()
+                ),
+                (target, procedure),
+            );
+        }
+    }
+    let mut generated: HashMap<String, (&str, String)> = HashMap::new();
+    for target in &project.targets {
+        let mut calls = Vec::new();
+        for body in target
+            .scripts
+            .iter()
+            .map(|s| &s.body)
+            .chain(target.procedures.iter().map(|p| &p.body))
+            .chain(target.reporters.iter().map(|r| &r.body))
+        {
+            collect_procedure_calls(body, &mut calls);
+        }
+        for (name, _) in calls {
+            let Some((target_name, proc_name)) = split_qualified(name) else {
+                continue;
+            };
+            let key = format!(
+                "{}\n{}",
+                target_name.to_lowercase(),
+                proc_name.to_lowercase()
+            );
+            let Some((callee_target, callee)) = procedures.get(&key) else {
+                continue;
+            };
+            let label = format!("{}.{}", callee_target.name, callee.name);
+            let names = rpc_names(target_name, proc_name, callee.params.len());
+            let mut reserve = |kind: &'static str, name: &String| {
+                generated
+                    .entry(name.to_lowercase())
+                    .or_insert_with(|| (kind, label.clone()));
+            };
+            reserve("broadcast", &names.message);
+            reserve("variable", &names.busy_var);
+            names.arg_vars.iter().for_each(|n| reserve("variable", n));
+            reserve("list", &names.calls_list);
+            names.arg_queues.iter().for_each(|n| reserve("list", n));
+        }
+    }
+    if generated.is_empty() {
+        return;
+    }
+
+    let mut check = |kind: &str, name: &str, pos: Position, target: &Target| {
+        let Some((generated_kind, label)) = generated.get(&name.to_lowercase()) else {
+            return;
+        };
+        if *generated_kind != kind {
+            return;
+        }
+        let title = match kind {
+            "variable" => "Variable",
+            "list" => "List",
+            _ => "Broadcast",
+        };
+        report.errors.push(SemanticError {
+            message: format!(
+                "{} '{}' at line {}, column {} in target '{}' has the name the compiler generates for remote call '{}'. Rename it.",
+                title, name, pos.line, pos.column, target.name, label
+            ),
+            pos: Some(pos),
+            name: Some(name.to_string()),
+            related: None,
+        });
+    };
+    for target in &project.targets {
+        for var in &target.variables {
+            check("variable", &var.name, var.pos, target);
+        }
+        for list in &target.lists {
+            check("list", &list.name, list.pos, target);
+        }
+        let mut broadcasts = Vec::new();
+        let mut dynamic = false;
+        for script in &target.scripts {
+            if let EventType::WhenIReceive(message) = &script.event_type {
+                broadcasts.push((Cow::Borrowed(message.as_str()), script.pos));
+            }
+            collect_broadcasts(&script.body, &mut broadcasts, &mut dynamic);
+        }
+        for procedure in &target.procedures {
+            collect_broadcasts(&procedure.body, &mut broadcasts, &mut dynamic);
+        }
+        for reporter in &target.reporters {
+            collect_broadcasts(&reporter.body, &mut broadcasts, &mut dynamic);
+        }
+        for (message, pos) in broadcasts {
+            check("broadcast", &message, pos, target);
+        }
+    }
+}
+
+/// Cross-checks broadcast literals against `when I receive` hats across the whole project.
+/// Receivers are only reported when every broadcast is a literal, since a computed message
+/// could reach any of them. The compiler's own `__rpc__` messages are skipped.
+fn analyze_broadcasts(project: &Project, report: &mut SemanticReport) {
+    let mut broadcasts: Vec<(&Target, Cow<str>, Position)> = Vec::new();
+    let mut receivers: Vec<(&Target, &str, Position)> = Vec::new();
+    let mut dynamic = false;
+    for target in &project.targets {
+        let mut found = Vec::new();
+        for procedure in &target.procedures {
+            collect_broadcasts(&procedure.body, &mut found, &mut dynamic);
+        }
+        for reporter in &target.reporters {
+            collect_broadcasts(&reporter.body, &mut found, &mut dynamic);
+        }
+        for script in &target.scripts {
+            if let EventType::WhenIReceive(message) = &script.event_type {
+                receivers.push((target, message, script.pos));
+            }
+            collect_broadcasts(&script.body, &mut found, &mut dynamic);
+        }
+        broadcasts.extend(
+            found
+                .into_iter()
+                .map(|(message, pos)| (target, message, pos)),
+        );
+    }
+    broadcasts.retain(|(_, message, _)| !message.starts_with(RPC_PREFIX));
+    receivers.retain(|(_, message, _)| !message.starts_with(RPC_PREFIX));
+
+    let normalize = |message: &str| message.trim().to_lowercase();
+    let mut reported = HashSet::new();
+    for (target, message, pos) in &broadcasts {
+        if receivers.iter().any(|(_, r, _)| r == message) || !reported.insert(message.as_ref()) {
+            continue;
+        }
+        let near = receivers
+            .iter()
+            .find(|(_, r, _)| normalize(r) == normalize(message));
+        let (text, related) = match near {
+            Some((receiver_target, receiver, receiver_pos)) => (
+                format!(
+                    "Broadcast '{}' at line {}, column {} in target '{}' differs only by case or spacing from receiver '{}' at line {}, column {} in target '{}'.",
+                    message, pos.line, pos.column, target.name, receiver, receiver_pos.line, receiver_pos.column, receiver_target.name
+                ),
+                Some(*receiver_pos),
+            ),
+            None => (
+                format!(
+                    "Broadcast '{}' at line {}, column {} in target '{}' has no 'when I receive' script.",
+                    message, pos.line, pos.column, target.name
+                ),
+                None,
+            ),
+        };
+        report.warnings.push(SemanticWarning {
+            message: text,
+            pos: Some(*pos),
+            name: Some(message.to_string()),
+            related,
+        });
+    }
+
+    if dynamic {
+        return;
+    }
+    let mut reported = HashSet::new();
+    for (target, message, pos) in &receivers {
+        let heard = broadcasts
+            .iter()
+            .any(|(_, b, _)| normalize(b) == normalize(message));
+        if heard || !reported.insert(*message) {
+            continue;
+        }
+        report.warnings.push(SemanticWarning {
+            message: format!(
+                "Receiver '{}' at line {}, column {} in target '{}' is never broadcast.",
+                message, pos.line, pos.column, target.name
+            ),
+            pos: Some(*pos),
+            name: Some(message.to_string()),
+            related: None,
+        });
+    }
+}
+
+/// Collects literal broadcast messages; `dynamic` is set when a message is computed.
+/// A number literal broadcasts its text, as the compiler emits it as a static message.
+fn collect_broadcasts<'a>(
+    statements: &'a [Statement],
+    out: &mut Vec<(Cow<'a, str>, Position)>,
+    dynamic: &mut bool,
+) {
+    walk_statements(statements, &mut |stmt| match stmt {
+        Statement::Broadcast { message, pos } | Statement::BroadcastAndWait { message, pos } => {
+            match message {
+                Expr::String { value, .. } => out.push((Cow::Borrowed(value), *pos)),
+                Expr::Number { value, .. } => out.push((Cow::Owned(format_num(*value)), *pos)),
+                _ => *dynamic = true,
+            }
+        }
+        _ => {}
+    });
+}
+
+fn collect_procedure_calls<'a>(statements: &'a [Statement], out: &mut Vec<(&'a str, Position)>) {
+    walk_statements(statements, &mut |stmt| {
+        if let Statement::ProcedureCall { name, pos, .. } = stmt {
+            out.push((name, *pos));
+        }
+    });
+}
+
+/// Tarjan's algorithm. Components come back with their node indices sorted, ordered by
+/// their smallest node so diagnostics follow declaration order.
+fn strongly_connected_components(nodes: &[CallNode]) -> Vec<Vec<usize>> {
+    struct State {
+        next_index: usize,
+        indices: Vec<Option<usize>>,
+        low_links: Vec<usize>,
+        stack: Vec<usize>,
+        on_stack: Vec<bool>,
+        components: Vec<Vec<usize>>,
+    }
+
+    fn visit(nodes: &[CallNode], node: usize, state: &mut State) {
+        state.indices[node] = Some(state.next_index);
+        state.low_links[node] = state.next_index;
+        state.next_index += 1;
+        state.stack.push(node);
+        state.on_stack[node] = true;
+        for &(callee, _, _) in &nodes[node].calls {
+            match state.indices[callee] {
+                None => {
+                    visit(nodes, callee, state);
+                    state.low_links[node] = state.low_links[node].min(state.low_links[callee]);
+                }
+                Some(callee_index) if state.on_stack[callee] => {
+                    state.low_links[node] = state.low_links[node].min(callee_index);
+                }
+                Some(_) => {}
+            }
+        }
+        if Some(state.low_links[node]) == state.indices[node] {
+            let mut component = Vec::new();
+            while let Some(member) = state.stack.pop() {
+                state.on_stack[member] = false;
+                component.push(member);
+                if member == node {
+                    break;
+                }
+            }
+            component.sort_unstable();
+            state.components.push(component);
+        }
+    }
+
+    let mut state = State {
+        next_index: 0,
+        indices: vec![None; nodes.len()],
+        low_links: vec![0; nodes.len()],
+        stack: Vec::new(),
+        on_stack: vec![false; nodes.len()],
+        components: Vec::new(),
+    };
+    for node in 0..nodes.len() {
+        if state.indices[node].is_none() {
+            visit(nodes, node, &mut state);
+        }
+    }
+    state.components.sort_by_key(|component| component[0]);
+    state.components
+}
+
+/// Breadth-first search for the shortest cycle through `start` within `component`.
+/// Each entry is a caller together with the position of its call to the next entry.
+/// With `remote_only`, only qualified remote calls are followed.
+fn shortest_cycle(
+    nodes: &[CallNode],
+    start: usize,
+    component: &[usize],
+    remote_only: bool,
+) -> Vec<(usize, Position)> {
+    let mut parent: HashMap<usize, (usize, Position)> = HashMap::new();
+    let mut queue = VecDeque::from([start]);
+    while let Some(node) = queue.pop_front() {
+        for &(callee, pos, remote) in &nodes[node].calls {
+            if remote_only && !remote {
+                continue;
+            }
+            if callee == start {
+                let mut cycle = vec![(node, pos)];
+                let mut current = node;
+                while current != start {
+                    let (caller, call_pos) = parent[&current];
+                    cycle.push((caller, call_pos));
+                    current = caller;
+                }
+                cycle.reverse();
+                return cycle;
+            }
+            if component.binary_search(&callee).is_ok() && !parent.contains_key(&callee) {
+                parent.insert(callee, (node, pos));
+                queue.push_back(callee);
+            }
+        }
+    }
+    Vec::new()
+}
+
+/// Scratch ignores these blocks when they run on the stage, so a stage script using them
+/// silently does nothing.
+fn sprite_only_statement(stmt: &Statement) -> Option<&'static str> {
+    let block = match stmt {
+        Statement::Move { .. } => "move",
+        Statement::TurnRight { .. } => "turn right",
+        Statement::TurnLeft { .. } => "turn left",
+        Statement::GoToXY { .. } => "go to x: y:",
+        Statement::GoToTarget { .. } => "go to",
+        Statement::GlideToXY { .. } => "glide to x: y:",
+        Statement::GlideToTarget { .. } => "glide to",
+        Statement::ChangeXBy { .. } => "change x by",
+        Statement::SetX { .. } => "set x to",
+        Statement::ChangeYBy { .. } => "change y by",
+        Statement::SetY { .. } => "set y to",
+        Statement::PointInDirection { .. } => "point in direction",
+        Statement::PointTowards { .. } => "point towards",
+        Statement::SetRotationStyle { .. } => "set rotation style",
+        Statement::IfOnEdgeBounce { .. } => "if on edge, bounce",
+        Statement::ChangeSizeBy { .. } => "change size by",
+        Statement::SetSizeTo { .. } => "set size to",
+        Statement::GoToLayer { .. } => "go to layer",
+        Statement::GoLayers { .. } => "go layers",
+        Statement::Show { .. } => "show",
+        Statement::Hide { .. } => "hide",
+        Statement::Say { .. } | Statement::SayForSeconds { .. } => "say",
+        Statement::Think { .. } | Statement::ThinkForSeconds { .. } => "think",
+        Statement::NextCostume { .. } => "next costume",
+        Statement::SwitchCostumeTo { .. } => "switch costume to",
+        Statement::PenDown { .. } => "pen down",
+        Statement::PenUp { .. } => "pen up",
+        Statement::PenStamp { .. } => "stamp",
+        Statement::ChangePenSizeBy { .. } => "change pen size by",
+        Statement::SetPenSizeTo { .. } => "set pen size to",
+        Statement::ChangePenColorParamBy { .. } => "change pen color by",
+        Statement::SetPenColorParamTo { .. } | Statement::SetPenColorTo { .. } => {
+            "set pen color to"
         }
-        Expr::Substring { text, start, end, .. } => {
-            analyze_expr(target, text, variables, lists, target_infos, param_scope)?;
-            analyze_expr(target, start, variables, lists, target_infos, param_scope)?;
-            analyze_expr(target, end, variables, lists, target_infos, param_scope)
+        Statement::DeleteThisClone { .. } => "delete this clone",
+        Statement::CreateCloneOf {
+            target: Expr::String { value, .. },
+            ..
+        } if matches!(value.to_lowercase().as_str(), "_myself_" | "myself") => {
+            "create clone of myself"
         }
-        Expr::BuiltinReporter { .. } | Expr::Number { .. } | Expr::String { .. } => Ok(()),
+        _ => return None,
+    };
+    Some(block)
+}
+
+fn sprite_only_reporter(expr: &Expr) -> Option<&'static str> {
+    let block = match expr {
+        Expr::BuiltinReporter { kind, .. } => match kind.as_str() {
+            "x_position" => "x position",
+            "y_position" => "y position",
+            "direction" => "direction",
+            "size" => "size",
+            _ => return None,
+        },
+        Expr::LooksNumberName {
+            backdrop: false, ..
+        } => "costume number/name",
+        Expr::TouchingObject { .. } => "touching",
+        Expr::TouchingColor { .. } => "touching color",
+        Expr::ColorTouchingColor { .. } => "color is touching color",
+        _ => return None,
+    };
+    Some(block)
+}
+
+fn stage_block_violation(
+    target: &Target,
+    block: &str,
+    pos: Position,
+    options: &SemanticOptions,
+    report: &mut SemanticReport,
+) -> Result<(), SemanticError> {
+    let message = format!(
+        "Sprite-only block '{}' at line {}, column {} cannot be used in the stage '{}'.",
+        block, pos.line, pos.column, target.name
+    );
+    // A stage speech bubble is harmless: the block just does nothing.
+    if options.allow_sprite_blocks_on_stage || matches!(block, "say" | "think") {
+        report.warnings.push(SemanticWarning {
+            message,
+            pos: Some(pos),
+            name: Some(block.to_string()),
+            related: None,
+        });
+        return Ok(());
     }
+    Err(SemanticError {
+        message,
+        pos: Some(pos),
+        name: Some(block.to_string()),
+        related: None,
+    })
 }
 
 fn split_qualified(name: &str) -> Option<(&str, &str)> {
@@ -941,16 +3058,27 @@ fn ensure_variable_exists(
                 "Variable field '{}' refers to a procedure parameter at line {}, column {}; Scratch variable blocks must target declared variables.",
                 name, line, column
             ),
+            pos: Some(Position::new(line, column)),
+            name: Some(name.to_string()),
+            related: None,
         });
     }
     if variables.contains_key(&lowered) || variable_exists_anywhere(target_infos, &lowered) {
         return Ok(());
     }
+    let candidates = visible_names(target, target_infos, false);
     Err(SemanticError {
         message: format!(
-            "Unknown variable '{}' at line {}, column {} in target '{}'.",
-            name, line, column, target.name
+            "Unknown variable '{}' at line {}, column {} in target '{}'.{}",
+            name,
+            line,
+            column,
+            target.name,
+            did_you_mean(name, candidates)
         ),
+        pos: Some(Position::new(line, column)),
+        name: Some(name.to_string()),
+        related: None,
     })
 }
 
@@ -971,6 +3099,9 @@ fn ensure_list_exists(
             "Unknown list '{}' at line {}, column {} in target '{}'.",
             name, line, column, target.name
         ),
+        pos: Some(Position::new(line, column)),
+        name: Some(name.to_string()),
+        related: None,
     })
 }
 
@@ -989,10 +3120,214 @@ fn list_exists_anywhere(target_infos: &HashMap<String, TargetInfo>, lowered_name
         .any(|target| target.lists.contains(lowered_name))
 }
 
+/// Variable names, and list names when `with_lists` is set, that `target` can reach as
+/// written: its own and the stage's. Other sprites' locals are not suggested.
+fn visible_names<'a>(
+    target: &Target,
+    target_infos: &'a HashMap<String, TargetInfo>,
+    with_lists: bool,
+) -> impl Iterator<Item = &'a str> {
+    let own = target.name.to_lowercase();
+    target_infos
+        .iter()
+        .filter(move |(key, info)| **key == own || info.is_stage)
+        .flat_map(move |(_, info)| {
+            let lists = if with_lists {
+                &info.list_names[..]
+            } else {
+                &[]
+            };
+            info.variable_names.iter().chain(lists)
+        })
+        .map(String::as_str)
+}
+
+/// Returns a " Did you mean '...'?" suffix naming the closest candidate, or an empty
+/// string when nothing is close enough to be a plausible typo. Longer names
+/// tolerate more edits: one per three characters, and at least one.
+fn did_you_mean<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> String {
+    let lowered = name.to_lowercase();
+    let max_distance = (lowered.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| {
+            (
+                edit_distance(&lowered, &candidate.to_lowercase()),
+                candidate,
+            )
+        })
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| format!(" Did you mean '{}'?", candidate))
+        .unwrap_or_default()
+}
+
+/// Levenshtein distance that also counts swapping two adjacent characters as one edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let mut rows = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
 fn is_ignored_noop_call(name: &str) -> bool {
     name.eq_ignore_ascii_case("log")
 }
 
+fn costume_names(target: &Target) -> HashSet<String> {
+    if target.costumes.is_empty() {
+        let default_name = if target.is_stage {
+            "backdrop1"
+        } else {
+            "costume1"
+        };
+        return HashSet::from([default_name.to_string()]);
+    }
+    target
+        .costumes
+        .iter()
+        .filter_map(|costume| match &costume.name {
+            Some(name) => Some(name.to_lowercase()),
+            None => Path::new(&costume.path)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(str::to_lowercase),
+        })
+        .collect()
+}
+
+fn sound_names(target: &Target) -> HashSet<String> {
+    target
+        .sounds
+        .iter()
+        .filter_map(|sound| match &sound.name {
+            Some(name) => Some(name.to_lowercase()),
+            None => Path::new(&sound.path)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(str::to_lowercase),
+        })
+        .collect()
+}
+
+fn is_builtin_touching_option(name: &str) -> bool {
+    matches!(
+        name.trim().to_ascii_lowercase().as_str(),
+        "_mouse_" | "mouse" | "mouse pointer" | "mouse-pointer" | "_edge_" | "edge"
+    )
+}
+
+fn is_monitor_mode_name(mode: &str) -> bool {
+    matches!(mode, "default" | "large" | "slider")
+}
+
+fn is_video_state_name(state: &str) -> bool {
+    matches!(state, "on" | "off" | "on-flipped")
+}
+
+/// Free-text menu fields are copied straight into the block, so a typo produces a block
+/// the editor cannot display.
+fn analyze_menu_fields(target: &Target, stmt: &Statement) -> Result<(), SemanticError> {
+    let (kind, value, pos, valid): (&str, &str, &Position, &[&str]) = match stmt {
+        Statement::SetRotationStyle { style, pos } => {
+            ("rotation style", style, pos, &ROTATION_STYLES)
+        }
+        Statement::SetGraphicEffectTo { effect, pos, .. }
+        | Statement::ChangeGraphicEffectBy { effect, pos, .. } => {
+            // The VM lowercases effect names before using them.
+            let lowered = effect.to_lowercase();
+            if GRAPHIC_EFFECTS.contains(&lowered.as_str()) {
+                return Ok(());
+            }
+            ("graphic effect", effect, pos, &GRAPHIC_EFFECTS)
+        }
+        Statement::GoToLayer { layer, pos } => ("layer", layer, pos, &["front", "back"]),
+        Statement::GoLayers { direction, pos, .. } => {
+            ("layer direction", direction, pos, &["forward", "backward"])
+        }
+        _ => return Ok(()),
+    };
+    if valid.contains(&value) {
+        return Ok(());
+    }
+    Err(SemanticError {
+        message: format!(
+            "Unknown {} '{}' at line {}, column {} in target '{}'. Expected {}.",
+            kind,
+            value,
+            pos.line,
+            pos.column,
+            target.name,
+            quoted_alternatives(valid)
+        ),
+        pos: Some(*pos),
+        name: Some(value.to_string()),
+        related: None,
+    })
+}
+
+/// Formats `["a", "b", "c"]` as `'a', 'b', or 'c'`.
+fn quoted_alternatives(values: &[&str]) -> String {
+    let quoted = values
+        .iter()
+        .map(|value| format!("'{}'", value))
+        .collect::<Vec<_>>();
+    match quoted.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, [only])) => format!("{} or {}", only, last),
+        Some((last, rest)) => format!("{}, or {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
+
+fn is_stop_option_name(option: &str) -> bool {
+    matches!(option, "all" | "this script") || stop_option_allows_next(option)
+}
+
+fn is_rotation_style_name(name: &str) -> bool {
+    ROTATION_STYLES.contains(&name)
+}
+
+fn is_sound_effect_name(name: &str) -> bool {
+    matches!(
+        name.trim().to_ascii_lowercase().as_str(),
+        "pitch" | "pan" | "pan left/right"
+    )
+}
+
+fn is_scratch_key_name(name: &str) -> bool {
+    let lowered = name.trim().to_ascii_lowercase();
+    if matches!(
+        lowered.as_str(),
+        "space" | "up arrow" | "down arrow" | "left arrow" | "right arrow" | "any"
+    ) {
+        return true;
+    }
+    let mut chars = lowered.chars();
+    matches!(
+        (chars.next(), chars.next()),
+        (Some(ch), None) if ch.is_ascii_lowercase() || ch.is_ascii_digit()
+    )
+}
+
 fn is_sensing_property_name(name: &str) -> bool {
     matches!(
         name.trim().to_ascii_lowercase().as_str(),
@@ -1009,40 +3344,791 @@ fn is_sensing_property_name(name: &str) -> bool {
 }
 
 fn reporter_assigns_return(statements: &[Statement], return_name: &str) -> bool {
-    for stmt in statements {
-        match stmt {
-            Statement::SetVar { var_name, .. } if var_name.eq_ignore_ascii_case(return_name) => {
-                return true;
-            }
+    let mut assigns = false;
+    walk_statements(statements, &mut |stmt| {
+        assigns |= match stmt {
+            Statement::SetVar { var_name, .. } => var_name.eq_ignore_ascii_case(return_name),
             Statement::AddToList { list_name, .. }
             | Statement::DeleteAllOfList { list_name, .. }
             | Statement::InsertAtList { list_name, .. }
             | Statement::ReplaceItemOfList { list_name, .. }
-            | Statement::DeleteOfList { list_name, .. } if list_name.eq_ignore_ascii_case(return_name) => {
-                return true;
-            }
-            Statement::Repeat { body, .. }
-            | Statement::RepeatUntil { body, .. }
-            | Statement::Forever { body, .. }
-            | Statement::ForEach { body, .. }
-            | Statement::While { body, .. } => {
-                if reporter_assigns_return(body, return_name) {
-                    return true;
-                }
+            | Statement::DeleteOfList { list_name, .. } => {
+                list_name.eq_ignore_ascii_case(return_name)
             }
-            Statement::If {
-                then_body,
-                else_body,
-                ..
-            } => {
-                if reporter_assigns_return(then_body, return_name)
-                    || reporter_assigns_return(else_body, return_name)
-                {
-                    return true;
-                }
-            }
-            _ => {}
+            _ => false,
+        };
+    });
+    assigns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn analyze_source(source: &str) -> Result<SemanticReport, String> {
+        let tokens = Lexer::new(source).tokenize().map_err(|e| e.message)?;
+        let project = Parser::new(tokens).parse_project().map_err(|e| e.message)?;
+        analyze_with_options(&project, SemanticOptions::default()).map_err(|e| e.message)
+    }
+
+    fn check(source: &str) -> Result<(), String> {
+        analyze_source(source).map(|_| ())
+    }
+
+    #[test]
+    fn key_press_event_accepts_scratch_keys() {
+        for key in ["space", "\"up arrow\"", "any", "a", "Z", "7"] {
+            let source = format!(
+                "sprite Cat\n  when key [{}] pressed\n    move (1)\n  end\nend\n",
+                key
+            );
+            assert!(check(&source).is_ok(), "key {} should be accepted", key);
         }
     }
-    false
+
+    #[test]
+    fn key_press_event_rejects_unknown_keys() {
+        let source = "sprite Cat\n  when key [enter] pressed\n    move (1)\n  end\nend\n";
+        let err = check(source).unwrap_err();
+        assert!(err.contains("Unknown key 'enter'"), "{}", err);
+        assert!(err.contains("line 2, column 3"), "{}", err);
+    }
+
+    #[test]
+    fn backdrop_switch_event_warns_on_unknown_backdrop() {
+        let source = "stage\n  when backdrop switches to [intro]\n    wait (1)\n  end\nend\n";
+        let report = analyze_source(source).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].message.contains("Backdrop 'intro'"));
+
+        let source = "stage\n  when backdrop switches to [backdrop1]\n    wait (1)\n  end\nend\n";
+        assert!(analyze_source(source).unwrap().warnings.is_empty());
+
+        let source = "stage\n  costume \"Intro\" \"art/title_01.svg\"\n  when backdrop switches to [intro]\n    wait (1)\n  end\nend\n";
+        assert!(analyze_source(source).unwrap().warnings.is_empty());
+    }
+
+    #[test]
+    fn touching_unknown_sprite_warns() {
+        let source = "sprite Cat\n  when flag clicked\n    wait until <touching [Ghost]?>\n    wait until <touching [edge]?>\n    wait until <touching [Cat]?>\n  end\nend\n";
+        let report = analyze_source(source).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0]
+            .message
+            .contains("unknown sprite 'Ghost'"));
+    }
+
+    #[test]
+    fn show_list_requires_declared_list() {
+        let source = "sprite Cat\n  list scores\n  when flag clicked\n    show list [scores]\n    hide list [missing]\n  end\nend\n";
+        let err = check(source).unwrap_err();
+        assert!(err.contains("Unknown list 'missing'"), "{}", err);
+    }
+
+    #[test]
+    fn playing_undeclared_sound_warns() {
+        let source = "sprite Cat\n  sound \"sounds/meow.wav\"\n  when flag clicked\n    start sound [meow]\n    play sound (\"bark\") until done\n  end\nend\n";
+        let report = analyze_source(source).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].message.contains("Sound 'bark'"));
+    }
+
+    #[test]
+    fn sound_effect_names_are_validated() {
+        let source = "sprite Cat\n  when flag clicked\n    change sound pitch by (10)\n    change sound effect [PAN] by (5)\n    set sound effect [pitch] to (0)\n  end\nend\n";
+        assert!(check(source).is_ok());
+
+        let source =
+            "sprite Cat\n  when flag clicked\n    change sound effect [echo] by (10)\n  end\nend\n";
+        let err = check(source).unwrap_err();
+        assert!(err.contains("Unknown sound effect 'echo'"), "{}", err);
+    }
+
+    #[test]
+    fn music_menu_names_are_validated() {
+        let source = "sprite Cat\n  when flag clicked\n    play drum [bass] for (1) beats\n    set instrument to [3]\n  end\nend\n";
+        assert!(check(source).is_ok());
+
+        let source =
+            "sprite Cat\n  when flag clicked\n    play drum [gong] for (1) beats\n  end\nend\n";
+        let err = check(source).unwrap_err();
+        assert!(err.contains("Unknown drum 'gong'"), "{}", err);
+
+        let source = "sprite Cat\n  when flag clicked\n    set instrument to [kazoo]\n  end\nend\n";
+        let err = check(source).unwrap_err();
+        assert!(err.contains("Unknown instrument 'kazoo'"), "{}", err);
+    }
+
+    #[test]
+    fn point_towards_unknown_sprite_warns() {
+        let source = "sprite Cat\n  when flag clicked\n    point towards [mouse-pointer]\n    point towards [Dog]\n    point towards [Ghost]\n  end\nend\n\nsprite Dog\nend\n";
+        let report = analyze_source(source).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0]
+            .message
+            .contains("unknown sprite 'Ghost'"));
+    }
+
+    #[test]
+    fn for_each_requires_declared_variable() {
+        let source = "sprite Cat\n  var i\n  when flag clicked\n    for each [i] in (3)\n      move (i)\n    end\n  end\nend\n";
+        assert!(check(source).is_ok());
+
+        let source = "sprite Cat\n  when flag clicked\n    for each [k] in (3)\n      move (1)\n    end\n  end\nend\n";
+        let err = check(source).unwrap_err();
+        assert!(err.contains("'k'"), "{}", err);
+    }
+
+    #[test]
+    fn sprite_properties_are_validated() {
+        let source = "sprite Cat\n  direction -90\n  rotation style [\"don't rotate\"]\nend\n";
+        assert!(check(source).is_ok());
+
+        let source = "sprite Cat\n  direction 270\nend\n";
+        let err = check(source).unwrap_err();
+        assert!(err.contains("between -180 and 180"), "{}", err);
+
+        let source = "sprite Cat\n  rotation style [sideways]\nend\n";
+        let err = check(source).unwrap_err();
+        assert!(err.contains("Unknown rotation style 'sideways'"), "{}", err);
+
+        let source = "stage\n  x 10\nend\n";
+        let err = check(source).unwrap_err();
+        assert!(err.contains("not allowed on the stage"), "{}", err);
+
+        let source = "stage\n  start costume [backdrop1]\nend\nsprite Cat\n  start costume [Costume1]\nend\n";
+        assert!(check(source).is_ok());
+
+        let err = check("sprite Cat\n  start costume [walk]\nend\n").unwrap_err();
+        assert!(
+            err.contains("Start costume 'walk' at line 2, column 3 in target 'Cat' is not one of its costumes"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn layer_declarations_are_validated() {
+        let source = "sprite Cat\n  layer 2\nend\n\nsprite Dog\n  layer 1\nend\n";
+        assert!(check(source).is_ok());
+
+        let err = check("sprite Cat\n  layer 0\nend\n").unwrap_err();
+        assert!(err.contains("whole number of at least 1"), "{}", err);
+
+        let err = check("sprite Cat\n  layer 1.5\nend\n").unwrap_err();
+        assert!(err.contains("Layer 1.5"), "{}", err);
+
+        let err = check("sprite Cat\n  layer 2\nend\n\nsprite Dog\n  layer 2\nend\n").unwrap_err();
+        assert!(err.contains("already taken by sprite 'Cat'"), "{}", err);
+
+        let err = check("sprite Cat\n  layer 1\n  layer 2\nend\n").unwrap_err();
+        assert!(err.contains("more than once"), "{}", err);
+
+        let err = check("stage\n  layer 1\nend\n").unwrap_err();
+        assert!(err.contains("not allowed on the stage"), "{}", err);
+    }
+
+    #[test]
+    fn stage_properties_are_validated() {
+        let source =
+            "stage\n  tempo 90\n  video transparency 0\n  video [on-flipped]\n  volume 80\nend\n";
+        assert!(check(source).is_ok());
+
+        let err = check("stage\n  volume 120\nend\n").unwrap_err();
+        assert!(err.contains("Volume 120"), "{}", err);
+        assert!(err.contains("between 0 and 100"), "{}", err);
+
+        let err = check("stage\n  video transparency 101\nend\n").unwrap_err();
+        assert!(err.contains("Video transparency 101"), "{}", err);
+
+        let err = check("stage\n  video [sideways]\nend\n").unwrap_err();
+        assert!(err.contains("Unknown video state 'sideways'"), "{}", err);
+
+        let err = check("sprite Cat\n  tempo 90\nend\n").unwrap_err();
+        assert!(err.contains("not allowed in sprite 'Cat'"), "{}", err);
+    }
+
+    #[test]
+    fn variable_initializer_rejects_expressions() {
+        let err = check("sprite Cat\n  var score = (1 + 2)\nend\n").unwrap_err();
+        assert!(err.contains("Expected string/number literal"), "{}", err);
+    }
+
+    #[test]
+    fn monitors_are_validated() {
+        let source = "stage\n  var score\nend\nsprite Cat\n  var lives\n  monitor [lives] mode [slider]\n  monitor [score]\nend\n";
+        assert!(check(source).is_ok());
+
+        let err = check("sprite Cat\n  monitor [lives]\nend\n").unwrap_err();
+        assert!(err.contains("unknown variable or list 'lives'"), "{}", err);
+
+        let err = check("sprite Cat\n  var lives\n  monitor [lives]\n  monitor [Lives]\nend\n")
+            .unwrap_err();
+        assert!(err.contains("Duplicate monitor"), "{}", err);
+
+        let err = check(
+            "stage\n  var score\n  monitor [score]\nend\nsprite Cat\n  monitor [score]\nend\n",
+        )
+        .unwrap_err();
+        assert!(
+            err.contains("Duplicate monitor for variable 'score'"),
+            "{}",
+            err
+        );
+
+        let err =
+            check("sprite Cat\n  var lives\n  monitor [lives] mode [huge]\nend\n").unwrap_err();
+        assert!(err.contains("Unknown monitor mode 'huge'"), "{}", err);
+
+        let source = "stage\n  list names\nend\nsprite Cat\n  var items\n  list items\n  monitor [items]\n  monitor [names]\nend\n";
+        assert!(check(source).is_ok());
+
+        let err =
+            check("sprite Cat\n  list items\n  monitor [items] mode [large]\nend\n").unwrap_err();
+        assert!(err.contains("not supported for list 'items'"), "{}", err);
+
+        let err = check("sprite Cat\n  list items\n  monitor [items]\n  monitor [Items]\nend\n")
+            .unwrap_err();
+        assert!(
+            err.contains("Duplicate monitor for list 'Items'"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn cloud_variables_are_validated() {
+        let source = "stage\n  cloud var highscore\n  monitor [\"☁ highscore\"]\nend\nsprite Cat\n  when flag clicked\n    set [\"☁ highscore\"] to (1)\n    say (highscore)\n  end\nend\n";
+        assert!(check(source).is_ok());
+
+        let err = check("sprite Cat\n  cloud var highscore\nend\n").unwrap_err();
+        assert!(err.contains("must be declared on the stage"), "{}", err);
+
+        let many = (1..=11)
+            .map(|i| format!("  cloud var c{}\n", i))
+            .collect::<String>();
+        let err = check(&format!("stage\n{}end\n", many)).unwrap_err();
+        assert!(err.contains("Cloud variable 'c11'"), "{}", err);
+        assert!(err.contains("limit of 10"), "{}", err);
+    }
+
+    #[test]
+    fn stop_options_are_validated() {
+        let source = "sprite Cat\n  when flag clicked\n    stop [other scripts in sprite]\n    say (\"still running\")\n    stop [this script]\n  end\nend\n";
+        assert!(check(source).is_ok());
+
+        let err = check("sprite Cat\n  when flag clicked\n    stop [everything]\n  end\nend\n")
+            .unwrap_err();
+        assert!(err.contains("Unknown stop option 'everything'"), "{}", err);
+
+        let err = check(
+            "sprite Cat\n  when flag clicked\n    stop [all]\n    say (\"never\")\n  end\nend\n",
+        )
+        .unwrap_err();
+        assert!(err.contains("Statements after 'stop [all]'"), "{}", err);
+    }
+
+    #[test]
+    fn errors_and_warnings_carry_structured_positions() {
+        let source = "sprite Cat\n  when flag clicked\n    say (missing)\n  end\nend\n";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let project = Parser::new(tokens).parse_project().unwrap();
+        let err = analyze_with_options(&project, SemanticOptions::default()).unwrap_err();
+        let pos = err.pos.expect("error should carry a position");
+        assert_eq!(pos.line, 3);
+        assert_eq!(err.name.as_deref(), Some("missing"));
+
+        let source = "sprite Cat\n  when flag clicked\n    point towards [Ghost]\n  end\nend\n";
+        let report = analyze_source(source).unwrap();
+        let warning = &report.warnings[0];
+        assert_eq!(warning.pos.map(|p| p.line), Some(3));
+        assert_eq!(warning.name.as_deref(), Some("Ghost"));
+    }
+
+    #[test]
+    fn collects_every_error_in_source_order() {
+        let source = "sprite Cat\n  when flag clicked\n    say (missing)\n    stop [everything]\n  end\n  when key [enter] pressed\n    move (1)\n  end\nend\nsprite Dog\n  direction 270\nend\n";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let project = Parser::new(tokens).parse_project().unwrap();
+        let report = analyze_all(&project, SemanticOptions::default());
+        let lines = report
+            .errors
+            .iter()
+            .map(|e| e.pos.map(|p| p.line))
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec![Some(3), Some(4), Some(6), Some(11)]);
+        assert!(report.errors[3].message.contains("Direction 270"));
+    }
+
+    #[test]
+    fn unknown_names_suggest_close_matches() {
+        let source = "sprite Cat\n  var score\n  when flag clicked\n    say (scroe)\n  end\nend\n";
+        let err = check(source).unwrap_err();
+        assert!(err.contains("Did you mean 'score'?"), "{}", err);
+
+        let source =
+            "sprite Cat\n  var Score\n  when flag clicked\n    set [SCROE] to (1)\n  end\nend\n";
+        let err = check(source).unwrap_err();
+        assert!(err.contains("Did you mean 'Score'?"), "{}", err);
+
+        let source = "sprite Cat\n  define jump (height)\n    change y by (hieght)\n  end\nend\n";
+        let err = check(source).unwrap_err();
+        assert!(err.contains("Did you mean 'height'?"), "{}", err);
+
+        let source = "sprite Cat\n  var score\n  when flag clicked\n    say (lives)\n  end\nend\n";
+        let err = check(source).unwrap_err();
+        assert!(!err.contains("Did you mean"), "{}", err);
+
+        let source = "stage\n  var Level\nend\n\nsprite Dog\n  var bark\nend\n\nsprite Cat\n  define jumps\n    move (1)\n  end\n  when flag clicked\n    say (levle)\n    say (jump)\n    set [brak] to (1)\n  end\nend\n";
+        let err = check(source).unwrap_err();
+        assert!(err.contains("Did you mean 'Level'?"), "{}", err);
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let project = Parser::new(tokens).parse_project().unwrap();
+        let errors = analyze_all(&project, SemanticOptions::default()).errors;
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert!(errors[1].message.starts_with("Unknown variable 'jump'"));
+        assert!(
+            !errors[1].message.contains("Did you mean"),
+            "{}",
+            errors[1].message
+        );
+        assert!(errors[2].message.starts_with("Unknown variable 'brak'"));
+        assert!(
+            !errors[2].message.contains("Did you mean"),
+            "{}",
+            errors[2].message
+        );
+    }
+
+    #[test]
+    fn suggestion_distance_scales_with_name_length() {
+        let candidates = ["score", "playerhealth"];
+        assert_eq!(did_you_mean("scroe", candidates), " Did you mean 'score'?");
+        assert_eq!(did_you_mean("sxorx", candidates), "");
+        assert_eq!(
+            did_you_mean("plyrhaelth", candidates),
+            " Did you mean 'playerhealth'?"
+        );
+        assert_eq!(did_you_mean("plyrhlth", candidates), "");
+    }
+
+    #[test]
+    fn unknown_procedures_suggest_close_matches() {
+        let source = "sprite Cat\n  define Jump\n    move (1)\n  end\n  when flag clicked\n    ujmp\n  end\nend\n";
+        let err = check(source).unwrap_err();
+        assert!(err.contains("Did you mean 'Jump'?"), "{}", err);
+
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let project = Parser::new(tokens).parse_project().unwrap();
+        let options = SemanticOptions {
+            allow_unknown: UnknownProcPolicy::All,
+            ..SemanticOptions::default()
+        };
+        let report = analyze_with_options(&project, options).unwrap();
+        assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+        let warning = &report.warnings[0].message;
+        assert!(warning.starts_with("Allowed unknown procedure call 'ujmp'"));
+        assert!(warning.ends_with("Did you mean 'Jump'?"), "{}", warning);
+    }
+
+    #[test]
+    fn deprecated_allow_unknown_procedures_flag_allows_every_call() {
+        let source = "sprite Cat\n  when flag clicked\n    SaveGame\n  end\nend\n";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let project = Parser::new(tokens).parse_project().unwrap();
+        #[allow(deprecated)]
+        let options = SemanticOptions {
+            allow_unknown_procedures: true,
+            ..SemanticOptions::default()
+        };
+        let report = analyze_with_options(&project, options).unwrap();
+        assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+        assert!(report.warnings[0]
+            .message
+            .starts_with("Allowed unknown procedure call 'SaveGame'"));
+    }
+
+    #[test]
+    fn only_listed_unknown_procedures_are_allowed() {
+        let source = "sprite Cat\n  define Jump\n    move (1)\n  end\n  when flag clicked\n    SaveGame\n    ujmp\n  end\nend\n";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let project = Parser::new(tokens).parse_project().unwrap();
+        let with_names = |names: &[&str]| SemanticOptions {
+            allow_unknown: UnknownProcPolicy::Names(names.iter().map(|n| n.to_string()).collect()),
+            ..SemanticOptions::default()
+        };
+
+        let err = analyze_with_options(&project, with_names(&["savegame"])).unwrap_err();
+        assert!(
+            err.message.starts_with("Unknown procedure 'ujmp'"),
+            "{}",
+            err
+        );
+
+        let report = analyze_with_options(&project, with_names(&["savegame", "UJMP"])).unwrap();
+        let allowed = report
+            .warnings
+            .iter()
+            .filter_map(|w| w.name.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(allowed, ["SaveGame", "ujmp"]);
+        assert!(report.warnings[0]
+            .message
+            .ends_with("because it is on the allowed unknown procedure list."));
+
+        assert!(UnknownProcPolicy::from(true).allows("anything"));
+        assert!(!UnknownProcPolicy::from(false).allows("anything"));
+    }
+
+    #[test]
+    fn duplicate_declarations_report_both_positions() {
+        let source = "sprite Cat\n  var score\n  var Score\nend\n";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let project = Parser::new(tokens).parse_project().unwrap();
+        let err = analyze_with_options(&project, SemanticOptions::default()).unwrap_err();
+        assert!(
+            err.message
+                .contains("at line 3, column 3 is already declared at line 2, column 3"),
+            "{}",
+            err
+        );
+        assert_eq!(err.related.map(|p| p.line), Some(2));
+
+        let err = check("sprite Cat\n  list items\n  list ITEMS\nend\n").unwrap_err();
+        assert!(err.contains("List 'ITEMS'"), "{}", err);
+
+        let err =
+            check("sprite Cat\n  costume \"Walk\" \"a.svg\"\n  costume \"walk\" \"b.svg\"\nend\n")
+                .unwrap_err();
+        assert!(err.contains("Costume 'walk'"), "{}", err);
+
+        let err = check(
+            "sprite Cat\n  define jump\n    move (1)\n  end\n  define Jump\n    move (2)\n  end\nend\n",
+        )
+        .unwrap_err();
+        assert!(
+            err.contains("already defined at line 2, column 3"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn variable_and_list_with_same_name_warns() {
+        let report = analyze_source("sprite Cat\n  var items\n  list items\nend\n").unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0]
+            .message
+            .contains("same name as variable declared at line 2, column 3"));
+    }
+
+    #[test]
+    fn warp_recursion_warns_with_cycle() {
+        let source = "sprite Cat\n  define !spin\n    turn right (1)\n    spin\n  end\nend\n";
+        let report = analyze_source(source).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        let message = &report.warnings[0].message;
+        assert!(
+            message.starts_with("Run-without-screen-refresh"),
+            "{}",
+            message
+        );
+        assert!(message.contains("spin -> spin"), "{}", message);
+        assert!(message.contains("line 4, column 5"), "{}", message);
+
+        let source = "sprite Cat\n  define !ping\n    pong\n  end\n  define !pong\n    if <(1) = (1)> then\n      ping\n    end\n  end\nend\n";
+        let report = analyze_source(source).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        let message = &report.warnings[0].message;
+        assert!(message.contains("ping -> pong -> ping"), "{}", message);
+        assert!(
+            message.contains("line 3, column 5; line 7, column 7"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn color_literals_only_fill_color_inputs() {
+        let ok = "sprite Cat\n  when flag clicked\n    set pen color to (#00ff00)\n    wait until <touching color (#FF0000)?>\n    wait until <color (#000000) is touching (#ffffff)?>\n  end\nend\n";
+        assert!(check(ok).is_ok(), "{:?}", check(ok));
+
+        let err =
+            check("sprite Cat\n  when flag clicked\n    say (#facade)\n  end\nend\n").unwrap_err();
+        assert!(
+            err.starts_with("Color literal '#facade' at line 3, column 10"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn bad_block_headers_still_check_their_bodies() {
+        let source = "sprite Cat\n  when flag clicked\n    repeat (ghost)\n      move (phantom)\n    end\n    if <(wraith) = (1)> then\n      say (spectre)\n    else\n      think (shade)\n    end\n    for each [nobody] in (10)\n      move (poltergeist)\n    end\n  end\nend\n";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let project = Parser::new(tokens).parse_project().unwrap();
+        let report = analyze_all(&project, SemanticOptions::default());
+        let lines = report
+            .errors
+            .iter()
+            .map(|err| err.pos.unwrap().line)
+            .collect::<Vec<_>>();
+        assert_eq!(lines, [3, 4, 6, 7, 9, 11, 12], "{:?}", report.errors);
+    }
+
+    #[test]
+    fn remote_call_cycles_are_reported() {
+        let source = "sprite A\n  define pong\n    B.ping\n  end\nend\n\nsprite B\n  define ping\n    A.pong\n  end\nend\n";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let project = Parser::new(tokens).parse_project().unwrap();
+        let report = analyze_all(&project, SemanticOptions::default());
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        let error = &report.errors[0];
+        assert!(
+            error
+                .message
+                .contains("Remote procedure calls form a cycle: A.pong -> B.ping -> A.pong"),
+            "{}",
+            error.message
+        );
+        assert!(
+            error.message.contains("line 3, column 5; line 9, column 5"),
+            "{}",
+            error.message
+        );
+        assert_eq!(error.pos, Some(Position::new(3, 5)));
+        assert_eq!(error.related, Some(Position::new(9, 5)));
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+
+        let source = "sprite A\n  define pong\n    B.ping\n  end\nend\n\nsprite B\n  define ping\n    relay\n  end\n  define relay\n    A.pong\n  end\nend\n";
+        let report = analyze_source(source).unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(report.warnings.len(), 1);
+        let warning = &report.warnings[0];
+        assert!(
+            warning
+                .message
+                .contains("cycle through local calls: A.pong -> B.ping -> B.relay -> A.pong"),
+            "{}",
+            warning.message
+        );
+        assert_eq!(warning.pos, Some(Position::new(3, 5)));
+    }
+
+    #[test]
+    fn generated_remote_call_names_are_reserved() {
+        let source = "stage\n  var __rpc__enemy__hit__arg1\n  list __rpc__enemy__hit__calls\nend\n\nsprite Enemy\n  define hit (amount)\n    move (amount)\n  end\n  when I receive [__rpc__enemy__hit]\n    move (1)\n  end\nend\n\nsprite Player\n  when flag clicked\n    Enemy.hit (5)\n  end\nend\n";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let project = Parser::new(tokens).parse_project().unwrap();
+        let report = analyze_all(&project, SemanticOptions::default());
+        let messages = report
+            .errors
+            .iter()
+            .map(|e| e.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(messages.len(), 3, "{:?}", messages);
+        assert!(messages[0].starts_with("Variable '__rpc__enemy__hit__arg1' at line 2, column 3"));
+        assert!(messages[1].starts_with("List '__rpc__enemy__hit__calls' at line 3, column 3"));
+        assert!(messages[2].starts_with("Broadcast '__rpc__enemy__hit'"));
+        assert!(
+            messages[2].contains("remote call 'Enemy.hit'"),
+            "{}",
+            messages[2]
+        );
+
+        let source = "stage\n  var __rpc__enemy__hit__arg1\nend\n";
+        assert!(check(source).is_ok());
+    }
+
+    #[test]
+    fn non_warp_recursion_is_only_a_note() {
+        let source = "sprite Cat\n  define countdown (n)\n    if <(n) > (0)> then\n      countdown ((n) - (1))\n    end\n  end\nend\n";
+        let report = analyze_source(source).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].message.starts_with("Note:"));
+
+        let source =
+            "sprite Cat\n  define !a\n    b\n  end\n  define !b\n    move (1)\n  end\nend\n";
+        assert!(analyze_source(source).unwrap().warnings.is_empty());
+    }
+
+    #[test]
+    fn unreachable_code_after_forever_warns() {
+        let source = "sprite Cat\n  when flag clicked\n    forever\n      move (1)\n    end\n    say (\"never\")\n    move (2)\n  end\nend\n";
+        let report = analyze_source(source).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0]
+            .message
+            .contains("Unreachable code at line 6, column 5"));
+
+        let source = "sprite Cat\n  when flag clicked\n    repeat (3)\n      forever\n        move (1)\n      end\n    end\n    move (2)\n  end\nend\n";
+        assert!(analyze_source(source).unwrap().warnings.is_empty());
+    }
+
+    #[test]
+    fn empty_loops_warn() {
+        let source = "sprite Cat\n  when flag clicked\n    repeat (3)\n    end\n    forever\n    end\n  end\nend\n";
+        let report = analyze_source(source).unwrap();
+        let messages = report
+            .warnings
+            .iter()
+            .map(|w| w.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(messages.len(), 2, "{:?}", messages);
+        assert!(messages[0].starts_with("Empty 'repeat' loop at line 3"));
+        assert!(messages[1].starts_with("Empty 'forever' loop at line 5"));
+    }
+
+    #[test]
+    fn sprite_only_blocks_are_rejected_in_stage() {
+        let err = check("stage\n  when flag clicked\n    move (10)\n  end\nend\n").unwrap_err();
+        assert!(
+            err.contains("Sprite-only block 'move' at line 3, column 5"),
+            "{}",
+            err
+        );
+
+        let err = check("stage\n  when flag clicked\n    pen down\n  end\nend\n").unwrap_err();
+        assert!(err.contains("'pen down'"), "{}", err);
+
+        let err =
+            check("stage\n  when flag clicked\n    wait until <touching [edge]?>\n  end\nend\n")
+                .unwrap_err();
+        assert!(err.contains("'touching'"), "{}", err);
+
+        let err = check("stage\n  when flag clicked\n    wait (size)\n  end\nend\n").unwrap_err();
+        assert!(err.contains("'size'"), "{}", err);
+
+        let source = "stage\n  var score\n  when flag clicked\n    next backdrop\n    broadcast [go]\n    set [score] to (x position)\n  end\nend\n";
+        let err = check(source).unwrap_err();
+        assert!(err.contains("'x position'"), "{}", err);
+
+        let source = "stage\n  var score\n  when flag clicked\n    next backdrop\n    broadcast [go]\n    set [score] to (1)\n    erase all\n    start sound [pop]\n  end\nend\n";
+        let report = analyze_source(source).unwrap();
+        assert!(report
+            .warnings
+            .iter()
+            .all(|w| !w.message.contains("Sprite-only")));
+
+        let source =
+            "stage\n  when flag clicked\n    say (\"hi\")\n    think (\"hm\")\n  end\nend\n";
+        let report = analyze_source(source).unwrap();
+        assert_eq!(report.warnings.len(), 2, "{:?}", report.warnings);
+        assert!(report.warnings[0].message.contains("'say' at line 3"));
+        assert!(report.warnings[1].message.contains("'think' at line 4"));
+    }
+
+    #[test]
+    fn sprite_only_blocks_in_stage_can_be_warnings() {
+        let source = "stage\n  when flag clicked\n    show\n  end\nend\n";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let project = Parser::new(tokens).parse_project().unwrap();
+        let options = SemanticOptions {
+            allow_sprite_blocks_on_stage: true,
+            ..SemanticOptions::default()
+        };
+        let report = analyze_with_options(&project, options).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].message.contains("'show'"));
+    }
+
+    #[test]
+    fn unmatched_broadcasts_and_receivers_warn() {
+        let source = "sprite Cat\n  when flag clicked\n    broadcast (\"Start\")\n    broadcast [lost]\n    broadcast and wait [ready]\n  end\n  when I receive [start ]\n    move (1)\n  end\n  when I receive [ready]\n    move (1)\n  end\n  when I receive [orphan]\n    move (1)\n  end\nend\n";
+        let report = analyze_source(source).unwrap();
+        let messages = report
+            .warnings
+            .iter()
+            .map(|w| w.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(messages.len(), 3, "{:?}", messages);
+        assert!(messages[0].contains("'Start' at line 3, column 5"));
+        assert!(messages[0].contains("differs only by case or spacing from receiver 'start'"));
+        assert!(messages[1].contains("'lost' at line 4, column 5 in target 'Cat' has no"));
+        assert!(messages[2].contains("Receiver 'orphan' at line 13, column 3"));
+    }
+
+    #[test]
+    fn computed_broadcasts_silence_receiver_warnings() {
+        let source = "sprite Cat\n  var next\n  when flag clicked\n    broadcast (next)\n  end\n  when I receive [orphan]\n    move (1)\n  end\nend\n";
+        assert!(analyze_source(source).unwrap().warnings.is_empty());
+    }
+
+    #[test]
+    fn number_broadcasts_match_receivers_by_text() {
+        let source = "sprite Cat\n  when flag clicked\n    broadcast (5)\n  end\n  when I receive [5]\n    move (1)\n  end\n  when I receive [orphan]\n    move (1)\n  end\nend\n";
+        let report = analyze_source(source).unwrap();
+        assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+        assert!(report.warnings[0].message.contains("Receiver 'orphan'"));
+
+        let source = "sprite Cat\n  when flag clicked\n    broadcast (2.0000000001)\n  end\n  when I receive [2]\n    move (1)\n  end\nend\n";
+        let report = analyze_source(source).unwrap();
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+    }
+
+    #[test]
+    fn raw_blocks_need_an_opcode_and_known_references() {
+        let ok = "sprite Cat\n  var score\n  when flag clicked\n    raw {\"opcode\": \"gadget_spin\", \"inputs\": {\"N\": [3, [12, \"score\"], [4, \"0\"]]}}\n  end\nend\n";
+        assert!(check(ok).is_ok(), "{:?}", check(ok));
+
+        let err = check("sprite Cat\n  when flag clicked\n    raw {\"inputs\": {}}\n  end\nend\n")
+            .unwrap_err();
+        assert!(
+            err.contains("must be a JSON object with an 'opcode' field"),
+            "{}",
+            err
+        );
+
+        let err = check(
+            "sprite Cat\n  when flag clicked\n    raw {\"opcode\": \"gadget_spin\", \"next\": {\"opcode\": \"data_setvariableto\", \"fields\": {\"VARIABLE\": [\"ghost\"]}}}\n  end\nend\n",
+        )
+        .unwrap_err();
+        assert!(err.contains("ghost"), "{}", err);
+
+        let err = check("sprite Cat\n  when flag clicked\n    raw {\"opcode\": }\n  end\nend\n")
+            .unwrap_err();
+        assert!(err.contains("Invalid JSON in raw block"), "{}", err);
+    }
+
+    #[test]
+    fn menu_field_values_are_validated() {
+        let source = "sprite Cat\n  when flag clicked\n    set rotation style [left-right]\n    set graphic effect [GHOST] to (50)\n    change graphic effect [color] by (5)\n    go to [front] layer\n    go [backward] (1) layers\n  end\nend\n";
+        assert!(check(source).is_ok(), "{:?}", check(source));
+
+        let err = check(
+            "sprite Cat\n  when flag clicked\n    set rotation style [sideways]\n  end\nend\n",
+        )
+        .unwrap_err();
+        assert!(
+            err.contains("Unknown rotation style 'sideways' at line 3, column 5"),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains("Expected 'all around', 'left-right', or 'don't rotate'."),
+            "{}",
+            err
+        );
+
+        let err = check(
+            "sprite Cat\n  when flag clicked\n    set graphic effect [colr] to (1)\n  end\nend\n",
+        )
+        .unwrap_err();
+        assert!(err.contains("Unknown graphic effect 'colr'"), "{}", err);
+        assert!(err.contains("'brightness', or 'ghost'"), "{}", err);
+
+        let err = check("sprite Cat\n  when flag clicked\n    go to [frnt] layer\n  end\nend\n")
+            .unwrap_err();
+        assert!(
+            err.contains("Unknown layer 'frnt'") && err.contains("Expected 'front' or 'back'."),
+            "{}",
+            err
+        );
+
+        let err = check("sprite Cat\n  when flag clicked\n    go [up] (1) layers\n  end\nend\n")
+            .unwrap_err();
+        assert!(err.contains("Unknown layer direction 'up'"), "{}", err);
+    }
 }
@@ -1,8 +1,13 @@
+use crate::diagnostics::{Diagnostic, Phase, Severity};
+use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
 
+// Compile errors are thrown as an array of diagnostic objects (see
+// `diagnostic_object`), not as a string.
+
 #[wasm_bindgen]
 pub fn compile_source_to_sb3(source: &str) -> Result<Vec<u8>, JsValue> {
-    compile_source_to_sb3_with_options(source, ".", true)
+    compile_source_to_sb3_with_options(source, ".", true, None)
 }
 
 #[wasm_bindgen]
@@ -10,14 +15,134 @@ pub fn compile_source_to_sb3_with_options(
     source: &str,
     source_dir: &str,
     scale_svgs: bool,
+    pretty_json: Option<bool>,
 ) -> Result<Vec<u8>, JsValue> {
-    crate::compile_source_to_sb3_bytes(source, std::path::Path::new(source_dir), scale_svgs)
-        .map_err(|e| JsValue::from_str(&e.to_string()))
+    built_or_diagnostics(crate::compile_source_with_diagnostics(
+        source,
+        std::path::Path::new(source_dir),
+        codegen_options(scale_svgs, pretty_json),
+        Default::default(),
+    ))
+}
+
+/// Compiles `source` with costumes and sounds taken from `assets`, a JS object
+/// mapping file names (as written in source) to `Uint8Array` bytes.
+#[wasm_bindgen]
+pub fn compile_source_to_sb3_with_assets(
+    source: &str,
+    assets: &js_sys::Object,
+    scale_svgs: bool,
+    pretty_json: Option<bool>,
+) -> Result<Vec<u8>, JsValue> {
+    built_or_diagnostics(crate::compile_source_with_assets_with_diagnostics(
+        source,
+        &asset_map(assets)?,
+        codegen_options(scale_svgs, pretty_json),
+        Default::default(),
+    ))
+}
+
+/// Like [`compile_source_to_sb3_with_assets`], but never throws for problems
+/// in the source: returns `{ sb3, diagnostics }`, where `sb3` is the archive as
+/// a `Uint8Array` (or `null` when there are errors) and `diagnostics` lists
+/// every error and warning.
+#[wasm_bindgen]
+pub fn compile_source_to_sb3_with_diagnostics(
+    source: &str,
+    assets: &js_sys::Object,
+    scale_svgs: bool,
+    pretty_json: Option<bool>,
+) -> Result<js_sys::Object, JsValue> {
+    let (bytes, diagnostics) = crate::compile_source_with_assets_with_diagnostics(
+        source,
+        &asset_map(assets)?,
+        codegen_options(scale_svgs, pretty_json),
+        Default::default(),
+    );
+    let sb3 = match bytes {
+        Some(bytes) => js_sys::Uint8Array::from(bytes.as_slice()).into(),
+        None => JsValue::NULL,
+    };
+    let diagnostics = diagnostics_array(&diagnostics)?;
+    let out = js_sys::Object::new();
+    js_sys::Reflect::set(&out, &"sb3".into(), &sb3)?;
+    js_sys::Reflect::set(&out, &"diagnostics".into(), &diagnostics)?;
+    Ok(out)
+}
+
+/// Like [`compile_source_to_sb3_with_assets`], returning `{ sb3, stats }`:
+/// `sb3` is the archive as a `Uint8Array` and `stats` is `{ sprites, scripts,
+/// procedures, blocks, broadcasts, variables, lists, assets, assetBytes,
+/// archiveBytes }`, the numbers the CLI prints for `--stats`.
+#[wasm_bindgen]
+pub fn compile_source_to_sb3_with_stats(
+    source: &str,
+    assets: &js_sys::Object,
+    scale_svgs: bool,
+    pretty_json: Option<bool>,
+) -> Result<js_sys::Object, JsValue> {
+    let files = asset_map(assets)?;
+    let (bytes, stats) = built_or_diagnostics(crate::build_with_diagnostics(
+        crate::parse_and_validate_source_with_diagnostics(source, Default::default()),
+        |project| {
+            crate::codegen::build_sb3_bytes_with_stats(
+                project,
+                &files,
+                codegen_options(scale_svgs, pretty_json),
+            )
+        },
+    ))?;
+    let counts = [
+        ("sprites", stats.sprites as f64),
+        ("scripts", stats.scripts as f64),
+        ("procedures", stats.procedures as f64),
+        ("blocks", stats.blocks as f64),
+        ("broadcasts", stats.broadcasts as f64),
+        ("variables", stats.variables as f64),
+        ("lists", stats.lists as f64),
+        ("assets", stats.assets as f64),
+        ("assetBytes", stats.asset_bytes as f64),
+        ("archiveBytes", stats.archive_bytes as f64),
+    ];
+    let stats_object = js_sys::Object::new();
+    for (key, value) in counts {
+        js_sys::Reflect::set(&stats_object, &key.into(), &value.into())?;
+    }
+    let out = js_sys::Object::new();
+    let sb3 = js_sys::Uint8Array::from(bytes.as_slice());
+    js_sys::Reflect::set(&out, &"sb3".into(), &sb3)?;
+    js_sys::Reflect::set(&out, &"stats".into(), &stats_object)?;
+    Ok(out)
+}
+
+/// Reads a JS object mapping asset file names to `Uint8Array` bytes.
+fn asset_map(assets: &js_sys::Object) -> Result<HashMap<String, Vec<u8>>, JsValue> {
+    let mut files = HashMap::new();
+    for entry in js_sys::Object::entries(assets).iter() {
+        let pair = js_sys::Array::from(&entry);
+        let name = pair
+            .get(0)
+            .as_string()
+            .ok_or_else(|| asset_error("Asset names must be strings.".to_string()))?;
+        let bytes = pair
+            .get(1)
+            .dyn_into::<js_sys::Uint8Array>()
+            .map_err(|_| asset_error(format!("Asset '{}' must be a Uint8Array.", name)))?;
+        files.insert(name, bytes.to_vec());
+    }
+    Ok(files)
+}
+
+/// An invalid `assets` argument, thrown as a single-diagnostic array like
+/// compile errors.
+fn asset_error(message: String) -> JsValue {
+    let diagnostic = Diagnostic::new(Severity::Error, Phase::Io, message);
+    diagnostics_array(&[diagnostic]).map_or_else(|err| err, Into::into)
 }
 
 #[wasm_bindgen]
 pub fn compile_sbtc_to_sb3(sbtc_bytes: &[u8]) -> Result<Vec<u8>, JsValue> {
-    compile_sbtc_to_sb3_with_options(sbtc_bytes, ".", true)
+    compile_sbtc_to_sb3_with_options(sbtc_bytes, ".", true, None)
 }
 
 #[wasm_bindgen]
@@ -25,11 +150,143 @@ pub fn compile_sbtc_to_sb3_with_options(
     sbtc_bytes: &[u8],
     fallback_source_dir: &str,
     scale_svgs: bool,
+    pretty_json: Option<bool>,
 ) -> Result<Vec<u8>, JsValue> {
-    crate::compile_sbtc_bytes_to_sb3_bytes(
+    built_or_diagnostics(crate::compile_sbtc_bytes_with_diagnostics(
         sbtc_bytes,
         std::path::Path::new(fallback_source_dir),
+        codegen_options(scale_svgs, pretty_json),
+        Default::default(),
+    ))
+}
+
+/// Decompiles `.sb3` (or bare `project.json`) bytes into a JS object
+/// `{ main, sprites, assets, report }`: `main` is the source text, `sprites`
+/// maps sprite file names to source (empty unless `split_sprites`), `assets`
+/// maps file names to `Uint8Array` bytes, and `report` is
+/// `{ summary, scripts, procedures, unsupportedOpcodes, cyclicChains,
+/// missingBlocks, unresolvedMenus, warnings }`.
+#[wasm_bindgen]
+pub fn decompile_sb3_to_source(
+    sb3_bytes: &[u8],
+    split_sprites: bool,
+) -> Result<js_sys::Object, JsValue> {
+    let project = crate::decompile::decompile_sb3_bytes(sb3_bytes, split_sprites)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let sprites = js_sys::Object::new();
+    for (file_name, source) in &project.sprites {
+        js_sys::Reflect::set(&sprites, &file_name.into(), &source.into())?;
+    }
+    let assets = js_sys::Object::new();
+    for (file_name, bytes) in &project.assets {
+        let bytes = js_sys::Uint8Array::from(bytes.as_slice());
+        js_sys::Reflect::set(&assets, &file_name.into(), &bytes)?;
+    }
+    let out = js_sys::Object::new();
+    js_sys::Reflect::set(&out, &"main".into(), &project.main.into())?;
+    js_sys::Reflect::set(&out, &"sprites".into(), &sprites)?;
+    js_sys::Reflect::set(&out, &"assets".into(), &assets)?;
+    let report = report_object(&project.report)?;
+    js_sys::Reflect::set(&out, &"report".into(), &report)?;
+    Ok(out)
+}
+
+fn report_object(report: &crate::decompile::DecompileReport) -> Result<js_sys::Object, JsValue> {
+    let unsupported = js_sys::Object::new();
+    for (opcode, count) in &report.unsupported_opcodes {
+        js_sys::Reflect::set(&unsupported, &opcode.into(), &(*count as u32).into())?;
+    }
+    let strings = |entries: &[String]| {
+        entries
+            .iter()
+            .map(|entry| JsValue::from_str(entry))
+            .collect::<js_sys::Array>()
+    };
+    let out = js_sys::Object::new();
+    js_sys::Reflect::set(&out, &"summary".into(), &report.summary().into())?;
+    js_sys::Reflect::set(&out, &"scripts".into(), &(report.scripts as u32).into())?;
+    js_sys::Reflect::set(
+        &out,
+        &"procedures".into(),
+        &(report.procedures as u32).into(),
+    )?;
+    js_sys::Reflect::set(&out, &"unsupportedOpcodes".into(), &unsupported)?;
+    js_sys::Reflect::set(
+        &out,
+        &"cyclicChains".into(),
+        &strings(&report.cyclic_chains),
+    )?;
+    js_sys::Reflect::set(
+        &out,
+        &"missingBlocks".into(),
+        &strings(&report.missing_blocks),
+    )?;
+    js_sys::Reflect::set(
+        &out,
+        &"unresolvedMenus".into(),
+        &strings(&report.unresolved_menus),
+    )?;
+    js_sys::Reflect::set(&out, &"warnings".into(), &strings(&report.warnings))?;
+    Ok(out)
+}
+
+/// The build result, or the diagnostics as a JS array to throw.
+fn built_or_diagnostics<T>(
+    (built, diagnostics): (Option<T>, Vec<Diagnostic>),
+) -> Result<T, JsValue> {
+    match built {
+        Some(built) => Ok(built),
+        None => Err(diagnostics_array(&diagnostics)?.into()),
+    }
+}
+
+fn diagnostics_array(diagnostics: &[Diagnostic]) -> Result<js_sys::Array, JsValue> {
+    diagnostics.iter().map(diagnostic_object).collect()
+}
+
+/// `{ severity, phase, message, file, line, column, notes }`, where `severity`
+/// is `"error"` or `"warning"`, `file`, `line`, and `column` may be `null`, and
+/// `notes` is an array of `{ message, file, line, column }`.
+fn diagnostic_object(diagnostic: &Diagnostic) -> Result<JsValue, JsValue> {
+    let file = |file: &Option<std::path::PathBuf>| match file {
+        Some(file) => JsValue::from_str(&file.display().to_string()),
+        None => JsValue::NULL,
+    };
+    let number = |value: Option<usize>| match value {
+        Some(value) => JsValue::from(value as u32),
+        None => JsValue::NULL,
+    };
+    let notes = js_sys::Array::new();
+    for note in &diagnostic.notes {
+        let object = js_sys::Object::new();
+        js_sys::Reflect::set(&object, &"message".into(), &note.message.as_str().into())?;
+        js_sys::Reflect::set(&object, &"file".into(), &file(&note.file))?;
+        js_sys::Reflect::set(&object, &"line".into(), &number(Some(note.line)))?;
+        js_sys::Reflect::set(&object, &"column".into(), &number(Some(note.column)))?;
+        notes.push(&object);
+    }
+    let out = js_sys::Object::new();
+    js_sys::Reflect::set(
+        &out,
+        &"severity".into(),
+        &diagnostic.severity.as_str().into(),
+    )?;
+    js_sys::Reflect::set(&out, &"phase".into(), &diagnostic.phase.as_str().into())?;
+    js_sys::Reflect::set(&out, &"message".into(), &diagnostic.message.as_str().into())?;
+    js_sys::Reflect::set(&out, &"file".into(), &file(&diagnostic.file))?;
+    js_sys::Reflect::set(&out, &"line".into(), &number(diagnostic.line))?;
+    js_sys::Reflect::set(&out, &"column".into(), &number(diagnostic.column))?;
+    js_sys::Reflect::set(&out, &"notes".into(), &notes)?;
+    Ok(out.into())
+}
+
+/// `pretty_json` is optional in every export so callers written before it was
+/// added keep working; leaving it out keeps the indented default.
+fn codegen_options(scale_svgs: bool, pretty_json: Option<bool>) -> crate::codegen::CodegenOptions {
+    let defaults = crate::codegen::CodegenOptions::default();
+    crate::codegen::CodegenOptions {
         scale_svgs,
-    )
-    .map_err(|e| JsValue::from_str(&e.to_string()))
+        pretty_json: pretty_json.unwrap_or(defaults.pretty_json),
+        ..defaults
+    }
 }
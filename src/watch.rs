@@ -0,0 +1,126 @@
+use anyhow::{anyhow, Result};
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Quiet period after the last change before rebuilding, so an editor that
+/// saves with several writes and a rename triggers a single build.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Runs `build` now and again whenever one of the files it read changes.
+///
+/// `build` pushes the files it depends on into the given list (also when it
+/// fails part way) and returns a one-line summary on success. A directory in
+/// the list means its contents matter: adding, removing, or renaming a file in
+/// it triggers a rebuild. A failed build
+/// keeps the last known file set, so fixing the error triggers the next build.
+/// Only returns if the file watcher itself fails.
+pub fn watch<F>(entry: &Path, mut build: F) -> Result<()>
+where
+    F: FnMut(&mut Vec<PathBuf>) -> Result<String>,
+{
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = tx.send(event);
+    })?;
+    let mut files: BTreeSet<PathBuf> = BTreeSet::new();
+    let mut watched_dirs: BTreeSet<PathBuf> = BTreeSet::new();
+
+    loop {
+        let mut deps = Vec::new();
+        let result = build(&mut deps);
+        if result.is_ok() || deps.len() > 1 || files.is_empty() {
+            files = deps.into_iter().collect();
+            files.insert(entry.to_path_buf());
+        }
+        match result {
+            Ok(summary) => eprintln!(
+                "[{}] {} (watching {} files)",
+                timestamp(),
+                summary,
+                files.len()
+            ),
+            Err(err) => eprintln!("[{}] Error: {:#}", timestamp(), err),
+        }
+
+        // Watch directories rather than files: editors often save by replacing
+        // the file, which drops a watch placed on the old inode.
+        let dirs: BTreeSet<PathBuf> = files
+            .iter()
+            .filter_map(|file| {
+                if file.is_dir() {
+                    Some(file.clone())
+                } else {
+                    file.parent().map(Path::to_path_buf)
+                }
+            })
+            .collect();
+        for dir in dirs.difference(&watched_dirs) {
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+        for dir in watched_dirs.difference(&dirs) {
+            let _ = watcher.unwatch(dir);
+        }
+        watched_dirs = dirs;
+
+        wait_for_change(&rx, &files)?;
+    }
+}
+
+fn wait_for_change(rx: &Receiver<notify::Result<Event>>, files: &BTreeSet<PathBuf>) -> Result<()> {
+    let closed = || anyhow!("File watcher stopped unexpectedly.");
+    loop {
+        match rx.recv().map_err(|_| closed())? {
+            Ok(event) if touches(&event, files) => break,
+            Ok(_) => {}
+            Err(err) => eprintln!("[{}] Watch error: {}", timestamp(), err),
+        }
+    }
+    while rx.recv_timeout(DEBOUNCE).is_ok() {}
+    Ok(())
+}
+
+fn touches(event: &Event, files: &BTreeSet<PathBuf>) -> bool {
+    if matches!(event.kind, EventKind::Access(_)) {
+        return false;
+    }
+    let lists_dir = matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_))
+    );
+    event.paths.iter().any(|path| {
+        let normalized = normalize(path);
+        files.contains(path)
+            || files.contains(&normalized)
+            || (lists_dir && normalized.parent().is_some_and(|dir| files.contains(dir)))
+    })
+}
+
+/// Canonicalizes through the parent directory so removed or renamed files
+/// still compare equal to the canonical paths the build reported.
+fn normalize(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => dir
+            .canonicalize()
+            .map(|dir| dir.join(name))
+            .unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Wall-clock `HH:MM:SS` (UTC) for the per-build status line.
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60
+    )
+}